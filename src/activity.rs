@@ -0,0 +1,367 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use derive_more::Display;
+use log::*;
+use rayon::prelude::*;
+use thiserror::Error;
+
+use crate::group::MovieGroup;
+use crate::telemetry::{self, GpsFix};
+
+/// Metadata key of the activity tagged into the merged movies.
+pub const ACTIVITY_TAG: &str = "gopro_merge_activity";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid region {0}, expected <name>=<latitude>,<longitude>,<latitude>,<longitude> with the corners of its bounding box, e.g. \"bike park=46.01,7.74,46.03,7.77\"")]
+    InvalidRegion(String),
+
+    #[error("Invalid activity mode {0}, one of \"folder\" | \"tag\"")]
+    InvalidActivityMode(String),
+
+    #[error(transparent)]
+    IO(#[from] io::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Tells which activity, e.g. a trail or a bike park, the GPS fixes of a merged movie belong to.
+pub trait Classifier: fmt::Debug + Send + Sync {
+    /// Name of the activity of the `fixes`, if they belong to any.
+    fn classify(&self, fixes: &[GpsFix]) -> Option<String>;
+}
+
+/// A named bounding box of coordinates, in degrees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    pub name: String,
+    pub south: f64,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+}
+
+impl Region {
+    pub fn contains(&self, fix: &GpsFix) -> bool {
+        (self.south..=self.north).contains(&fix.latitude)
+            && (self.west..=self.east).contains(&fix.longitude)
+    }
+}
+
+impl FromStr for Region {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidRegion(s.into());
+        let (name, corners) = s.rsplit_once('=').ok_or_else(invalid)?;
+        let name = name.trim();
+        // the name becomes the folder of the merged movies
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(invalid());
+        }
+
+        let corners = corners
+            .split(',')
+            .map(|corner| corner.trim().parse::<f64>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>>>()?;
+        let (latitudes, longitudes) = match corners.as_slice() {
+            [lat1, lon1, lat2, lon2] => ([*lat1, *lat2], [*lon1, *lon2]),
+            _ => return Err(invalid()),
+        };
+        if latitudes.iter().any(|lat| !(-90.0..=90.0).contains(lat))
+            || longitudes.iter().any(|lon| !(-180.0..=180.0).contains(lon))
+        {
+            return Err(invalid());
+        }
+
+        Ok(Region {
+            name: name.into(),
+            south: latitudes[0].min(latitudes[1]),
+            west: longitudes[0].min(longitudes[1]),
+            north: latitudes[0].max(latitudes[1]),
+            east: longitudes[0].max(longitudes[1]),
+        })
+    }
+}
+
+/// Reads a region per line, in the format of `--region`, skipping empty lines and `#` comments.
+pub fn read_regions(path: &Path) -> Result<Vec<Region>> {
+    fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(Region::from_str)
+        .collect()
+}
+
+/// Classifies movies into the region most of their GPS fixes are in, the first one defined
+/// winning ties.
+#[derive(Debug, Clone, Default)]
+pub struct RegionClassifier {
+    pub regions: Vec<Region>,
+}
+
+impl Classifier for RegionClassifier {
+    fn classify(&self, fixes: &[GpsFix]) -> Option<String> {
+        let mut best: Option<(&Region, usize)> = None;
+        for region in &self.regions {
+            let inside = fixes.iter().filter(|fix| region.contains(fix)).count();
+            if inside > best.map_or(0, |(_, most)| most) {
+                best = Some((region, inside));
+            }
+        }
+
+        best.map(|(region, _)| region.name.clone())
+    }
+}
+
+/// Where the activity of a merged movie ends up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum ActivityMode {
+    /// A subfolder of the output directory named after the activity.
+    #[display(fmt = "folder")]
+    Folder,
+    /// An [ACTIVITY_TAG] metadata tag of the merged movie.
+    #[display(fmt = "tag")]
+    Tag,
+}
+
+impl Default for ActivityMode {
+    fn default() -> Self {
+        ActivityMode::Folder
+    }
+}
+
+impl FromStr for ActivityMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "folder" => ActivityMode::Folder,
+            "tag" => ActivityMode::Tag,
+            _ => return Err(Error::InvalidActivityMode(s.into())),
+        })
+    }
+}
+
+/// The activities of the groups of a run, keyed by group name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Activities {
+    pub mode: ActivityMode,
+    pub groups: BTreeMap<String, String>,
+}
+
+impl Activities {
+    /// Classifies the `groups` found in `input` by the GPS fixes of their chapters, the chapters
+    /// whose telemetry can't be read are only warned about.
+    pub fn classify(
+        groups: &[MovieGroup],
+        input: &Path,
+        classifier: &dyn Classifier,
+        mode: ActivityMode,
+    ) -> Self {
+        let groups = groups
+            .par_iter()
+            .filter_map(|group| {
+                let fixes = group
+                    .chapters
+                    .iter()
+                    .map(|chapter| group.chapter_path(chapter, input))
+                    .flat_map(|path| {
+                        telemetry::read_gps(&path).unwrap_or_else(|err| {
+                            warn!("reading the GPS of {}: {}", path.display(), err);
+                            vec![]
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let activity = classifier.classify(&fixes);
+                debug!(
+                    "classified {} with {} GPS fixes as {:?}",
+                    group,
+                    fixes.len(),
+                    activity
+                );
+                activity.map(|activity| (group.name(), activity))
+            })
+            .collect();
+
+        Activities { mode, groups }
+    }
+
+    pub fn of(&self, group: &MovieGroup) -> Option<&str> {
+        self.groups.get(&group.name()).map(String::as_str)
+    }
+
+    /// The directory in `output` the merged movie of the `group` goes to.
+    pub fn output_dir(&self, group: &MovieGroup, output: &Path) -> PathBuf {
+        match (self.mode, self.of(group)) {
+            (ActivityMode::Folder, Some(activity)) => output.join(activity),
+            _ => output.to_path_buf(),
+        }
+    }
+
+    /// The metadata tag of the `group`, rendered as `key=value`.
+    pub fn tag(&self, group: &MovieGroup) -> Option<String> {
+        match (self.mode, self.of(group)) {
+            (ActivityMode::Tag, Some(activity)) => Some(format!("{}={}", ACTIVITY_TAG, activity)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    use crate::group::group_file_names;
+
+    fn fix(latitude: f64, longitude: f64) -> GpsFix {
+        GpsFix {
+            latitude,
+            longitude,
+            altitude: 0.0,
+            time: None,
+        }
+    }
+
+    #[test]
+    fn test_region_from_str() {
+        assert_eq!(
+            Region {
+                name: "bike park".into(),
+                south: 46.01,
+                west: 7.74,
+                north: 46.03,
+                east: 7.77,
+            },
+            "bike park=46.03,7.77,46.01,7.74".parse().unwrap()
+        );
+        assert_eq!(
+            "home trails",
+            " home trails = -33.9, 18.4, -34.0, 18.5"
+                .parse::<Region>()
+                .unwrap()
+                .name
+        );
+
+        for invalid in &[
+            "",
+            "bike park",
+            "=46.01,7.74,46.03,7.77",
+            "../up=46.01,7.74,46.03,7.77",
+            "a/b=46.01,7.74,46.03,7.77",
+            "bike park=46.01,7.74,46.03",
+            "bike park=46.01,7.74,46.03,7.77,1",
+            "bike park=north,7.74,46.03,7.77",
+            "bike park=91,7.74,46.03,7.77",
+            "bike park=46.01,181,46.03,7.77",
+        ] {
+            assert!(invalid.parse::<Region>().is_err(), "{} was parsed", invalid);
+        }
+    }
+
+    #[test]
+    fn test_read_regions() {
+        let path = env::temp_dir().join("goprotest_read_regions.txt");
+        fs::write(
+            &path,
+            "# trails around home\nhome trails=47.3,8.4,47.4,8.6\n\n  bike park=46.01,7.74,46.03,7.77\n",
+        )
+        .unwrap();
+        let regions = read_regions(&path).unwrap();
+        assert_eq!(
+            vec!["home trails", "bike park"],
+            regions
+                .iter()
+                .map(|region| region.name.as_str())
+                .collect::<Vec<_>>()
+        );
+
+        fs::write(&path, "home trails=47.3,8.4\n").unwrap();
+        assert!(matches!(read_regions(&path), Err(Error::InvalidRegion(..))));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_region_classifier() {
+        let classifier = RegionClassifier {
+            regions: vec![
+                "home trails=47.3,8.4,47.4,8.6".parse().unwrap(),
+                "bike park=46.01,7.74,46.03,7.77".parse().unwrap(),
+                "valley=46.0,7.7,46.1,7.8".parse().unwrap(),
+            ],
+        };
+
+        assert_eq!(None, classifier.classify(&[]));
+        assert_eq!(None, classifier.classify(&[fix(0.0, 0.0)]));
+        assert_eq!(
+            Some("home trails".to_string()),
+            classifier.classify(&[fix(47.35, 8.5), fix(0.0, 0.0)])
+        );
+        // the bike park lies in the valley, the first region defined wins ties
+        assert_eq!(
+            Some("bike park".to_string()),
+            classifier.classify(&[fix(46.02, 7.75), fix(46.02, 7.76)])
+        );
+        assert_eq!(
+            Some("valley".to_string()),
+            classifier.classify(&[fix(46.02, 7.75), fix(46.05, 7.71), fix(46.06, 7.72)])
+        );
+    }
+
+    #[test]
+    fn test_activity_mode_from_str() {
+        assert_eq!(ActivityMode::Folder, "folder".parse().unwrap());
+        assert_eq!(ActivityMode::Tag, "tag".parse().unwrap());
+        assert!("rename".parse::<ActivityMode>().is_err());
+    }
+
+    #[test]
+    fn test_activities() {
+        let groups = group_file_names(vec!["GH010084.mp4", "GH010085.mp4"]);
+        let output = Path::new("/movies");
+        let mut activities = Activities {
+            mode: ActivityMode::Folder,
+            groups: vec![("GH000084.mp4".to_string(), "bike park".to_string())]
+                .into_iter()
+                .collect(),
+        };
+
+        assert_eq!(Some("bike park"), activities.of(&groups[0]));
+        assert_eq!(None, activities.of(&groups[1]));
+        assert_eq!(
+            output.join("bike park"),
+            activities.output_dir(&groups[0], output)
+        );
+        assert_eq!(output, activities.output_dir(&groups[1], output));
+        assert_eq!(None, activities.tag(&groups[0]));
+
+        activities.mode = ActivityMode::Tag;
+        assert_eq!(output, activities.output_dir(&groups[0], output));
+        assert_eq!(
+            Some("gopro_merge_activity=bike park".to_string()),
+            activities.tag(&groups[0])
+        );
+        assert_eq!(None, activities.tag(&groups[1]));
+    }
+
+    #[test]
+    fn test_classify_without_telemetry() {
+        let input = std::fs::canonicalize("./tests").unwrap();
+        let groups = crate::group::group_movies(&input).unwrap();
+        let classifier = RegionClassifier {
+            regions: vec!["everywhere=-90,-180,90,180".parse().unwrap()],
+        };
+
+        let activities = Activities::classify(&groups, &input, &classifier, ActivityMode::Tag);
+        assert_eq!(ActivityMode::Tag, activities.mode);
+        assert!(activities.groups.is_empty());
+    }
+}
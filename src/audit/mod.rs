@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use derive_more::Display;
+use log::*;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid audit hash {0}, supported hashes are \"sha256\"")]
+    InvalidHash(String),
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Display)]
+pub enum AuditHash {
+    #[display(fmt = "sha256")]
+    Sha256,
+}
+
+impl FromStr for AuditHash {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sha256" => Ok(AuditHash::Sha256),
+            _ => Err(Error::InvalidHash(s.into())),
+        }
+    }
+}
+
+/// What a source chapter looked like when it was scanned, the audit trail of a merged movie.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceRecord {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Hex digest of the contents, only computed when asked for as it reads the whole file.
+    pub hash: Option<String>,
+}
+
+/// Records the size of the source chapter at `path` and its `hash` if any, reporting
+/// the hashed bytes and the total size to `progress` for every percent hashed.
+pub fn record(
+    path: &Path,
+    hash: Option<AuditHash>,
+    mut progress: impl FnMut(u64, u64),
+) -> io::Result<SourceRecord> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+
+    let hash = match hash {
+        Some(AuditHash::Sha256) => {
+            debug!("hashing {} with {}", path.display(), AuditHash::Sha256);
+            let mut hasher = Sha256::new();
+            let mut buf = vec![0; 1 << 16];
+            let (mut hashed, mut reported) = (0u64, None);
+            loop {
+                let read = file.read(&mut buf)?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buf[..read]);
+                hashed += read as u64;
+
+                let percent = hashed * 100 / size.max(1);
+                if reported != Some(percent) {
+                    reported = Some(percent);
+                    progress(hashed, size);
+                }
+            }
+            Some(format!("{:x}", hasher.finalize()))
+        }
+        None => None,
+    };
+
+    Ok(SourceRecord {
+        path: path.to_path_buf(),
+        size,
+        hash,
+    })
+}
+
+/// The ordered source chapters as embedded into the merged movie, a line per chapter with its
+/// file name and hash if any.
+pub fn provenance(records: &[SourceRecord]) -> String {
+    records
+        .iter()
+        .map(|record| {
+            let name = record
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            match &record.hash {
+                Some(hash) => format!("{} {}\n", name, hash),
+                None => format!("{}\n", name),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_audit_hash_from_str() {
+        assert_eq!(AuditHash::Sha256, "sha256".parse().unwrap());
+        assert_eq!(AuditHash::Sha256, "SHA256".parse().unwrap());
+        assert!("md5".parse::<AuditHash>().is_err());
+    }
+
+    #[test]
+    fn test_provenance() {
+        let records = [
+            SourceRecord {
+                path: "/movies/GH010084.mp4".into(),
+                size: 1,
+                hash: Some("abc".into()),
+            },
+            SourceRecord {
+                path: "/movies/DCIM/GH020084.mp4".into(),
+                size: 2,
+                hash: None,
+            },
+        ];
+
+        assert_eq!("GH010084.mp4 abc\nGH020084.mp4\n", provenance(&records));
+    }
+
+    #[test]
+    fn test_record() {
+        let path = env::temp_dir().join("goprotest_audit_record.mp4");
+        fs::write(&path, vec![b'a'; 200_000]).unwrap();
+
+        let record_only_size = record(&path, None, |_, _| panic!("nothing is hashed")).unwrap();
+        assert_eq!(200_000, record_only_size.size);
+        assert_eq!(None, record_only_size.hash);
+
+        let mut reported = vec![];
+        let record = record(&path, Some(AuditHash::Sha256), |hashed, size| {
+            reported.push((hashed, size))
+        })
+        .unwrap();
+        assert_eq!(
+            Some("2287d207f24a941ff3b56c04c8a25ad56b63e3023207b3bb5b4ac0c9869d74be"),
+            record.hash.as_deref()
+        );
+        assert_eq!(Some(&(200_000, 200_000)), reported.last());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use crate::group::MovieGroup;
+use crate::proxy::VideoStream;
+use crate::timestamp::ChapterTimestamp;
+
+/// What probing a single source chapter yields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbedChapter {
+    pub duration: Duration,
+    /// Indices of the GPMF telemetry streams.
+    pub telemetry: Vec<usize>,
+    pub timestamp: ChapterTimestamp,
+    pub video: Option<VideoStream>,
+}
+
+/// Position of a single source chapter inside the merged movie.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChapterBoundary {
+    pub name: String,
+    pub start: Duration,
+    pub duration: Duration,
+    pub timestamp: ChapterTimestamp,
+}
+
+/// Lays out the group chapters one after another, `probed` being in the order of `group.chapters`.
+pub fn chapter_boundaries(group: &MovieGroup, probed: &[ProbedChapter]) -> Vec<ChapterBoundary> {
+    group
+        .chapters
+        .iter()
+        .zip(probed)
+        .scan(Duration::default(), |start, (chapter, probed)| {
+            let boundary = ChapterBoundary {
+                name: group.chapter_file_name(chapter),
+                start: *start,
+                duration: probed.duration,
+                timestamp: probed.timestamp,
+            };
+            *start = start.saturating_add(probed.duration);
+            Some(boundary)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::encoding::Encoding;
+    use crate::identifier::Identifier;
+    use crate::movie::Fingerprint;
+    use crate::timestamp::TimestampSource;
+
+    #[test]
+    fn test_chapter_boundaries() {
+        let group = MovieGroup {
+            fingerprint: Fingerprint {
+                encoding: Encoding::Avc,
+                file: Identifier::try_from("0084").unwrap(),
+                extension: "mp4".into(),
+                scheme: Default::default(),
+            },
+            chapters: vec![
+                Identifier::try_from("01").unwrap(),
+                Identifier::try_from("02").unwrap(),
+                Identifier::try_from("03").unwrap(),
+            ],
+            directories: Default::default(),
+            file_names: Default::default(),
+        };
+
+        let timestamp = ChapterTimestamp {
+            time: None,
+            source: TimestampSource::Unknown,
+        };
+        let probed = [
+            Duration::from_secs(10),
+            Duration::from_secs(5),
+            Duration::from_millis(1500),
+        ]
+        .into_iter()
+        .map(|duration| ProbedChapter {
+            duration,
+            telemetry: vec![],
+            timestamp,
+            video: None,
+        })
+        .collect::<Vec<_>>();
+        let boundaries = chapter_boundaries(&group, &probed);
+
+        assert_eq!(
+            vec![
+                ChapterBoundary {
+                    name: "GH010084.mp4".into(),
+                    start: Duration::default(),
+                    duration: Duration::from_secs(10),
+                    timestamp,
+                },
+                ChapterBoundary {
+                    name: "GH020084.mp4".into(),
+                    start: Duration::from_secs(10),
+                    duration: Duration::from_secs(5),
+                    timestamp,
+                },
+                ChapterBoundary {
+                    name: "GH030084.mp4".into(),
+                    start: Duration::from_secs(15),
+                    duration: Duration::from_millis(1500),
+                    timestamp,
+                },
+            ],
+            boundaries
+        );
+    }
+}
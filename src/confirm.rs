@@ -0,0 +1,195 @@
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use derive_more::Display;
+use log::*;
+use thiserror::Error;
+
+use crate::group::{MovieGroup, MovieGroups};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Refusing to {0} without confirmation, pass --assume-yes to allow it in non-interactive runs")]
+    Refused(DestructiveAction),
+
+    #[error(transparent)]
+    IO(#[from] io::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Everything the tool can do that loses data, each of them goes through [ConfirmPolicy].
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+pub enum DestructiveAction {
+    #[display(fmt = "overwrite {}", "_0.display()")]
+    Overwrite(PathBuf),
+}
+
+/// How destructive actions get confirmed, shared by every feature that needs to do one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmPolicy {
+    /// Everything is confirmed upfront with `--assume-yes`.
+    AssumeYes,
+    /// Ask on the terminal.
+    Prompt,
+    /// Nobody to ask, so nothing destructive is done.
+    Refuse,
+}
+
+impl ConfirmPolicy {
+    pub fn new(assume_yes: bool, interactive: bool) -> Self {
+        match (assume_yes, interactive) {
+            (true, _) => ConfirmPolicy::AssumeYes,
+            (false, true) => ConfirmPolicy::Prompt,
+            (false, false) => ConfirmPolicy::Refuse,
+        }
+    }
+
+    /// Prompts only when both stdin and stderr are terminals, stdout might carry the json events.
+    pub fn detect(assume_yes: bool) -> Self {
+        Self::new(
+            assume_yes,
+            atty::is(atty::Stream::Stdin) && atty::is(atty::Stream::Stderr),
+        )
+    }
+
+    /// Whether the `action` may be done, an error if it can't be confirmed at all.
+    pub fn confirm(&self, action: &DestructiveAction) -> Result<bool> {
+        self.confirm_with(action, io::stdin().lock(), io::stderr())
+    }
+
+    fn confirm_with(
+        &self,
+        action: &DestructiveAction,
+        mut input: impl BufRead,
+        mut output: impl Write,
+    ) -> Result<bool> {
+        match self {
+            ConfirmPolicy::AssumeYes => Ok(true),
+            ConfirmPolicy::Refuse => Err(Error::Refused(action.clone())),
+            ConfirmPolicy::Prompt => {
+                write!(output, "{}? [y/N] ", capitalize(&action.to_string()))?;
+                output.flush()?;
+
+                let mut answer = String::new();
+                input.read_line(&mut answer)?;
+                Ok(matches!(
+                    answer.trim().to_ascii_lowercase().as_str(),
+                    "y" | "yes"
+                ))
+            }
+        }
+    }
+}
+
+/// Confirms overwriting the already existing outputs, leaving out the movies it was declined for.
+/// The `output` of a group is where its merged movie goes.
+pub fn confirm_overwrites(
+    policy: &ConfirmPolicy,
+    movies: MovieGroups,
+    output: impl Fn(&MovieGroup) -> PathBuf,
+) -> Result<MovieGroups> {
+    movies.into_iter().try_fold(vec![], |mut confirmed, group| {
+        let path = output(&group);
+        let allowed = !path.exists() || policy.confirm(&DestructiveAction::Overwrite(path))?;
+        if allowed {
+            confirmed.push(group);
+        } else {
+            warn!("skipping {}, overwriting its output was declined", group);
+        }
+
+        Ok(confirmed)
+    })
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    use crate::group::group_movies;
+
+    #[test]
+    fn test_confirm_policy_new() {
+        assert_eq!(ConfirmPolicy::AssumeYes, ConfirmPolicy::new(true, false));
+        assert_eq!(ConfirmPolicy::AssumeYes, ConfirmPolicy::new(true, true));
+        assert_eq!(ConfirmPolicy::Prompt, ConfirmPolicy::new(false, true));
+        assert_eq!(ConfirmPolicy::Refuse, ConfirmPolicy::new(false, false));
+    }
+
+    #[test]
+    fn test_confirm_with() {
+        let action = DestructiveAction::Overwrite("/movies/GH000084.mp4".into());
+        let confirm = |policy: ConfirmPolicy, answer: &str| {
+            let mut prompt = vec![];
+            let result = policy.confirm_with(&action, answer.as_bytes(), &mut prompt);
+            (result, String::from_utf8(prompt).unwrap())
+        };
+
+        let (result, prompt) = confirm(ConfirmPolicy::Prompt, "y\n");
+        assert!(result.unwrap());
+        assert_eq!("Overwrite /movies/GH000084.mp4? [y/N] ", prompt);
+
+        assert!(confirm(ConfirmPolicy::Prompt, "Yes\n").0.unwrap());
+        assert!(!confirm(ConfirmPolicy::Prompt, "n\n").0.unwrap());
+        assert!(!confirm(ConfirmPolicy::Prompt, "").0.unwrap());
+
+        let (result, prompt) = confirm(ConfirmPolicy::AssumeYes, "");
+        assert!(result.unwrap());
+        assert!(prompt.is_empty());
+
+        assert!(matches!(
+            confirm(ConfirmPolicy::Refuse, "y\n").0,
+            Err(Error::Refused(..))
+        ));
+    }
+
+    #[test]
+    fn test_confirm_overwrites() {
+        let input = env::temp_dir().join("goprotest_confirm_overwrites");
+        let _ = fs::remove_dir_all(&input);
+        fs::create_dir_all(&input).unwrap();
+        fs::write(input.join("GH010084.mp4"), b"\0\0\0\x20ftypisom").unwrap();
+        fs::write(input.join("GH010085.mp4"), b"\0\0\0\x20ftypisom").unwrap();
+        fs::write(input.join("GH000085.mp4"), b"merged before").unwrap();
+        let movies = group_movies(&input).unwrap();
+        assert_eq!(2, movies.len());
+        let output = |dir: &Path| {
+            let dir = dir.to_path_buf();
+            move |group: &MovieGroup| dir.join(group.name())
+        };
+
+        assert_eq!(
+            2,
+            confirm_overwrites(&ConfirmPolicy::AssumeYes, movies.clone(), output(&input))
+                .unwrap()
+                .len()
+        );
+        assert!(matches!(
+            confirm_overwrites(&ConfirmPolicy::Refuse, movies.clone(), output(&input)),
+            Err(Error::Refused(DestructiveAction::Overwrite(path))) if path == input.join("GH000085.mp4")
+        ));
+
+        let empty = env::temp_dir().join("goprotest_confirm_overwrites_empty");
+        fs::create_dir_all(&empty).unwrap();
+        assert_eq!(
+            2,
+            confirm_overwrites(&ConfirmPolicy::Refuse, movies, output(&empty))
+                .unwrap()
+                .len()
+        );
+
+        fs::remove_dir_all(&input).unwrap();
+    }
+}
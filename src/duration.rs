@@ -0,0 +1,164 @@
+use std::fmt;
+use std::time::Duration;
+
+const SECS_PER_MINUTE: u64 = 60;
+const SECS_PER_HOUR: u64 = 60 * SECS_PER_MINUTE;
+const SECS_PER_DAY: u64 = 24 * SECS_PER_HOUR;
+
+/// Units of [HumanDuration], largest first, with their compact form.
+const HUMAN_UNITS: &[(u64, &str, &str)] = &[
+    (365 * SECS_PER_DAY, "year", "y"),
+    (7 * SECS_PER_DAY, "week", "w"),
+    (SECS_PER_DAY, "day", "d"),
+    (SECS_PER_HOUR, "hour", "h"),
+    (SECS_PER_MINUTE, "minute", "m"),
+    (1, "second", "s"),
+];
+
+/// Sum of the `durations`, saturating at [Duration::MAX] where summing with `Sum` panics, e.g. on
+/// a corrupt chapter probed with a nonsensical duration.
+pub fn saturating_sum(durations: impl IntoIterator<Item = Duration>) -> Duration {
+    durations
+        .into_iter()
+        .fold(Duration::ZERO, Duration::saturating_add)
+}
+
+/// Like [saturating_sum], `None` if any of the `durations` is.
+pub fn checked_sum(durations: impl IntoIterator<Item = Option<Duration>>) -> Option<Duration> {
+    durations
+        .into_iter()
+        .try_fold(Duration::ZERO, |sum, duration| {
+            Some(sum.saturating_add(duration?))
+        })
+}
+
+/// A duration from its clock components, saturating when they're too large to represent.
+pub fn from_clock(hours: u64, minutes: u64, seconds: u64, micros: u64) -> Duration {
+    let secs = hours
+        .saturating_mul(SECS_PER_HOUR)
+        .saturating_add(minutes.saturating_mul(SECS_PER_MINUTE))
+        .saturating_add(seconds);
+
+    Duration::from_secs(secs).saturating_add(Duration::from_micros(micros))
+}
+
+/// Formats a duration as a clock, e.g. `02:13:44`, prefixed by the days once it lasts that long,
+/// e.g. `1d 02:13:44`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormattedDuration(pub Duration);
+
+impl fmt::Display for FormattedDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.as_secs();
+        let clock = secs % SECS_PER_DAY;
+        if secs >= SECS_PER_DAY {
+            write!(f, "{}d ", secs / SECS_PER_DAY)?;
+        }
+
+        write!(
+            f,
+            "{:02}:{:02}:{:02}",
+            clock / SECS_PER_HOUR,
+            clock % SECS_PER_HOUR / SECS_PER_MINUTE,
+            clock % SECS_PER_MINUTE
+        )
+    }
+}
+
+/// Formats a duration in its largest whole unit, e.g. `3 minutes` or `1 hour`, compact with the
+/// alternate flag, e.g. `3m`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HumanDuration(pub Duration);
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.as_secs();
+        let (count, name, short) = HUMAN_UNITS
+            .iter()
+            .map(|(unit, name, short)| (secs / unit, *name, *short))
+            .find(|(count, ..)| *count > 0)
+            .unwrap_or((0, "second", "s"));
+
+        match (f.alternate(), count) {
+            (true, _) => write!(f, "{}{}", count, short),
+            (false, 1) => write!(f, "{} {}", count, name),
+            (false, _) => write!(f, "{} {}s", count, name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(SECS_PER_DAY);
+
+    #[test]
+    fn test_saturating_sum() {
+        assert_eq!(Duration::ZERO, saturating_sum(vec![]));
+        // a week of loop recording in 10 minute chapters
+        assert_eq!(
+            7 * DAY,
+            saturating_sum(vec![Duration::from_secs(10 * 60); 7 * 24 * 6])
+        );
+        assert_eq!(
+            Duration::MAX,
+            saturating_sum(vec![Duration::MAX, Duration::from_nanos(1), Duration::MAX])
+        );
+
+        assert_eq!(
+            Some(Duration::MAX),
+            checked_sum(vec![Some(Duration::MAX), Some(DAY)])
+        );
+        assert_eq!(None, checked_sum(vec![Some(Duration::MAX), None]));
+        assert_eq!(Some(Duration::ZERO), checked_sum(vec![]));
+    }
+
+    #[test]
+    fn test_from_clock() {
+        assert_eq!(
+            Duration::from_secs(26 * SECS_PER_HOUR + 13 * 60 + 44) + Duration::from_micros(100),
+            from_clock(26, 13, 44, 100)
+        );
+        assert_eq!(Duration::from_secs(u64::MAX), from_clock(u64::MAX, 0, 0, 0));
+        assert_eq!(Duration::MAX, from_clock(0, u64::MAX, u64::MAX, u64::MAX));
+    }
+
+    #[test]
+    fn test_human_duration() {
+        assert_eq!("0 seconds", HumanDuration(Duration::ZERO).to_string());
+        assert_eq!(
+            "1 second",
+            HumanDuration(Duration::from_millis(1_900)).to_string()
+        );
+        assert_eq!(
+            "2 minutes",
+            HumanDuration(Duration::from_secs(179)).to_string()
+        );
+        assert_eq!("1 week", HumanDuration(13 * DAY).to_string());
+        assert_eq!("2h", format!("{:#}", HumanDuration(2 * DAY / 24)));
+    }
+
+    #[test]
+    fn test_formatted_duration() {
+        assert_eq!("00:00:00", FormattedDuration(Duration::ZERO).to_string());
+        assert_eq!(
+            "00:06:49",
+            FormattedDuration(Duration::from_millis(409_900)).to_string()
+        );
+        assert_eq!(
+            "23:59:59",
+            FormattedDuration(DAY - Duration::from_secs(1)).to_string()
+        );
+        assert_eq!("1d 00:00:00", FormattedDuration(DAY).to_string());
+        assert_eq!(
+            "1d 02:13:44",
+            FormattedDuration(DAY + Duration::from_secs(2 * SECS_PER_HOUR + 13 * 60 + 44))
+                .to_string()
+        );
+        assert_eq!(
+            "213503982334601d 07:00:15",
+            FormattedDuration(Duration::MAX).to_string()
+        );
+    }
+}
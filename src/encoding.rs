@@ -3,7 +3,7 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("Invalid encoding for file {0}. Supported encodings are AVC(GH), HEVC(GX): https://community.gopro.com/t5/en/GoPro-Camera-File-Naming-Convention/ta-p/390220#")]
+    #[error("Invalid encoding for file {0}. Supported encodings are AVC(GH), HEVC(GX), looping(GL), 360(GS), older cameras(GOPR, GP) and Fusion lenses(GPFR, GF, GPBK, GB): https://community.gopro.com/t5/en/GoPro-Camera-File-Naming-Convention/ta-p/390220#")]
     InvalidEncoding(String),
 }
 
@@ -13,28 +13,94 @@ pub enum Encoding {
     Avc,
     #[display(fmt = "GX")]
     Hevc,
+    #[display(fmt = "GL")]
+    Loop,
+    /// The 360 movies of the MAX, GSccxxxx.360 holding the two lenses as separate video tracks.
+    #[display(fmt = "GS")]
+    Spherical,
+    /// Older cameras, up to HERO5, name the first chapter GOPRxxxx and the rest GP01xxxx.
+    #[display(fmt = "GP")]
+    Legacy,
+    /// The front lens of the Fusion, naming the first chapter GPFRxxxx and the rest GF01xxxx.
+    #[display(fmt = "GF")]
+    FusionFront,
+    /// The back lens of the Fusion, naming the first chapter GPBKxxxx and the rest GB01xxxx.
+    #[display(fmt = "GB")]
+    FusionBack,
 }
 
+/// Prefix of the first chapter of [Encoding::Legacy] movies.
+pub const LEGACY_FIRST_CHAPTER_PREFIX: &str = "GOPR";
+/// Prefix of the first chapter of [Encoding::FusionFront] movies.
+pub const FUSION_FRONT_FIRST_CHAPTER_PREFIX: &str = "GPFR";
+/// Prefix of the first chapter of [Encoding::FusionBack] movies.
+pub const FUSION_BACK_FIRST_CHAPTER_PREFIX: &str = "GPBK";
+
 impl Encoding {
     pub fn as_str(&self) -> &'static str {
         match self {
             Encoding::Avc => "GH",
             Encoding::Hevc => "GX",
+            Encoding::Loop => "GL",
+            Encoding::Spherical => "GS",
+            Encoding::Legacy => "GP",
+            Encoding::FusionFront => "GF",
+            Encoding::FusionBack => "GB",
+        }
+    }
+
+    /// Prefix of the first chapter when the camera names it differently from the rest.
+    pub fn first_chapter_prefix(&self) -> Option<&'static str> {
+        match self {
+            Encoding::Legacy => Some(LEGACY_FIRST_CHAPTER_PREFIX),
+            Encoding::FusionFront => Some(FUSION_FRONT_FIRST_CHAPTER_PREFIX),
+            Encoding::FusionBack => Some(FUSION_BACK_FIRST_CHAPTER_PREFIX),
+            _ => None,
         }
     }
+
+    /// One of the paired lenses of the Fusion.
+    pub fn is_fusion(&self) -> bool {
+        matches!(self, Encoding::FusionFront | Encoding::FusionBack)
+    }
 }
 
 impl TryFrom<&str> for Encoding {
     type Error = Error;
 
+    /// Matches the prefixes case-insensitively, tools renaming the files don't always keep the
+    /// uppercase names of the camera.
     fn try_from(name: &str) -> Result<Self, Self::Error> {
-        if name.starts_with(Encoding::Avc.as_str()) {
-            Ok(Encoding::Avc)
-        } else if name.starts_with(Encoding::Hevc.as_str()) {
-            Ok(Encoding::Hevc)
-        } else {
-            Err(Error::InvalidEncoding(name.into()))
+        let upper = name.to_ascii_uppercase();
+        // the first chapter prefixes start like the encodings, e.g. GPFR and GP
+        let first_chapter = [
+            Encoding::Legacy,
+            Encoding::FusionFront,
+            Encoding::FusionBack,
+        ]
+        .iter()
+        .find(|encoding| {
+            encoding
+                .first_chapter_prefix()
+                .map_or(false, |prefix| upper.starts_with(prefix))
+        });
+        if let Some(encoding) = first_chapter {
+            return Ok(*encoding);
         }
+
+        [
+            Encoding::Avc,
+            Encoding::Hevc,
+            Encoding::Loop,
+            Encoding::Spherical,
+            Encoding::Legacy,
+            Encoding::FusionFront,
+            Encoding::FusionBack,
+        ]
+        .iter()
+        .find(|encoding| upper.starts_with(encoding.as_str()))
+        .copied()
+        .ok_or_else(|| Error::InvalidEncoding(name.into()))
     }
 }
 
@@ -44,11 +110,27 @@ mod tests {
 
     #[test]
     fn encoding_try_from() {
-        let ok = vec!["GH", "GX"];
+        let ok = vec![
+            ("GH", Encoding::Avc),
+            ("GX", Encoding::Hevc),
+            ("GL", Encoding::Loop),
+            ("GS", Encoding::Spherical),
+            ("GP", Encoding::Legacy),
+            ("GOPR", Encoding::Legacy),
+            ("GF", Encoding::FusionFront),
+            ("GPFR", Encoding::FusionFront),
+            ("GB", Encoding::FusionBack),
+            ("GPBK", Encoding::FusionBack),
+            ("gh", Encoding::Avc),
+            ("Gx", Encoding::Hevc),
+            ("gopr", Encoding::Legacy),
+            ("gpBk", Encoding::FusionBack),
+            ("gs", Encoding::Spherical),
+        ];
         ok.into_iter()
-            .for_each(|i| assert!(Encoding::try_from(i).is_ok()));
+            .for_each(|(i, expected)| assert_eq!(expected, Encoding::try_from(i).unwrap()));
 
-        let non_ok = vec!["gh", "gh", "", "faasda"];
+        let non_ok = vec!["", "faasda", "GO", "go"];
         non_ok
             .into_iter()
             .for_each(|i| assert!(Encoding::try_from(i).is_err()));
@@ -58,5 +140,10 @@ mod tests {
     fn encoding_as_str() {
         assert_eq!("GH", Encoding::Avc.as_str());
         assert_eq!("GX", Encoding::Hevc.as_str());
+        assert_eq!("GL", Encoding::Loop.as_str());
+        assert_eq!("GS", Encoding::Spherical.as_str());
+        assert_eq!("GP", Encoding::Legacy.as_str());
+        assert_eq!("GF", Encoding::FusionFront.as_str());
+        assert_eq!("GB", Encoding::FusionBack.as_str());
     }
 }
@@ -0,0 +1,141 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::export::{escape_xml, file_url, MergedMovie};
+
+const FCPXML_VERSION: &str = "1.9";
+// Markers are instants, FCP requires them to have a non zero duration nevertheless
+const MARKER_DURATION: Duration = Duration::from_millis(1);
+
+// https://developer.apple.com/documentation/professional_video_applications/fcpxml_reference/story_elements/time
+fn time(duration: Duration) -> String {
+    match duration.as_millis() {
+        0 => "0s".into(),
+        millis => format!("{}/1000s", millis),
+    }
+}
+
+pub fn render(movie: &MergedMovie) -> String {
+    let name = escape_xml(&movie.name);
+    // events are how FCP organizes footage, group merged movies by their recording date
+    let event = movie
+        .chapters
+        .first()
+        .and_then(|chapter| chapter.timestamp.time)
+        .map(|time| humantime::format_rfc3339(time).to_string()[..10].to_string())
+        .unwrap_or_else(|| "gopro-merge".into());
+    let duration = time(movie.duration);
+
+    let mut markers = String::new();
+    movie.chapters.iter().for_each(|chapter| {
+        writeln!(
+            markers,
+            r#"              <marker start="{}" duration="{}" value="{}"/>"#,
+            time(chapter.start),
+            time(MARKER_DURATION),
+            escape_xml(&chapter.name),
+        )
+        .unwrap();
+    });
+    movie
+        .hilights
+        .iter()
+        .enumerate()
+        .for_each(|(index, hilight)| {
+            writeln!(
+                markers,
+                r#"              <marker start="{}" duration="{}" value="HiLight {}"/>"#,
+                time(*hilight),
+                time(MARKER_DURATION),
+                index + 1,
+            )
+            .unwrap();
+        });
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE fcpxml>
+<fcpxml version="{version}">
+  <resources>
+    <format id="r1" name="FFVideoFormatRateUndefined"/>
+    <asset id="r2" name="{name}" src="{src}" start="0s" duration="{duration}" hasVideo="1" hasAudio="1" format="r1"/>
+  </resources>
+  <library>
+    <event name="{event}">
+      <project name="{name}">
+        <sequence format="r1" duration="{duration}">
+          <spine>
+            <asset-clip ref="r2" name="{name}" offset="0s" start="0s" duration="{duration}">
+{markers}            </asset-clip>
+          </spine>
+        </sequence>
+      </project>
+    </event>
+  </library>
+</fcpxml>
+"#,
+        version = FCPXML_VERSION,
+        name = name,
+        event = event,
+        src = escape_xml(&file_url(&movie.path)),
+        duration = duration,
+        markers = markers,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::chapters::ChapterBoundary;
+    use crate::timestamp::{ChapterTimestamp, TimestampSource};
+
+    #[test]
+    fn test_time() {
+        assert_eq!("0s", time(Duration::default()));
+        assert_eq!("5458/1000s", time(Duration::from_micros(5458333)));
+    }
+
+    #[test]
+    fn test_render() {
+        let movie = MergedMovie {
+            name: "GH000084.mp4".into(),
+            path: "/movies/GH000084.mp4".into(),
+            duration: Duration::from_secs(10),
+            chapters: vec![
+                ChapterBoundary {
+                    name: "GH010084.mp4".into(),
+                    start: Duration::default(),
+                    duration: Duration::from_secs(6),
+                    timestamp: ChapterTimestamp {
+                        time: Some(humantime::parse_rfc3339("2021-11-14T23:15:25Z").unwrap()),
+                        source: TimestampSource::Container,
+                    },
+                },
+                ChapterBoundary {
+                    name: "GH020084.mp4".into(),
+                    start: Duration::from_secs(6),
+                    duration: Duration::from_secs(4),
+                    timestamp: ChapterTimestamp {
+                        time: None,
+                        source: TimestampSource::Unknown,
+                    },
+                },
+            ],
+            hilights: vec![Duration::from_millis(7500)],
+        };
+
+        let rendered = render(&movie);
+        assert!(rendered.contains(r#"<event name="2021-11-14">"#));
+        assert!(rendered.contains(
+            r#"<asset id="r2" name="GH000084.mp4" src="file:///movies/GH000084.mp4" start="0s" duration="10000/1000s""#
+        ));
+        assert!(
+            rendered.contains(r#"<marker start="0s" duration="1/1000s" value="GH010084.mp4"/>"#)
+        );
+        assert!(rendered
+            .contains(r#"<marker start="6000/1000s" duration="1/1000s" value="GH020084.mp4"/>"#));
+        assert!(rendered
+            .contains(r#"<marker start="7500/1000s" duration="1/1000s" value="HiLight 1"/>"#));
+    }
+}
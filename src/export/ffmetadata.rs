@@ -0,0 +1,70 @@
+use std::fmt::Write;
+
+use crate::export::MergedMovie;
+
+/// The chapters of the merged movie as an ffmpeg metadata file, muxed as the chapter markers
+/// players navigate by, numbered like "Chapter 1", "Chapter 2".
+pub fn render(movie: &MergedMovie) -> String {
+    let mut metadata = String::from(";FFMETADATA1\n");
+    movie
+        .chapters
+        .iter()
+        .enumerate()
+        .for_each(|(index, chapter)| {
+            write!(
+                metadata,
+                "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle=Chapter {}\n",
+                chapter.start.as_millis(),
+                chapter.start.saturating_add(chapter.duration).as_millis(),
+                index + 1,
+            )
+            .unwrap();
+        });
+
+    metadata
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::chapters::ChapterBoundary;
+    use crate::timestamp::{ChapterTimestamp, TimestampSource};
+
+    #[test]
+    fn test_render() {
+        let timestamp = ChapterTimestamp {
+            time: None,
+            source: TimestampSource::Unknown,
+        };
+        let movie = MergedMovie {
+            name: "GH000084.mp4".into(),
+            path: "/movies/GH000084.mp4".into(),
+            duration: Duration::from_millis(15_500),
+            chapters: vec![
+                ChapterBoundary {
+                    name: "GH010084.mp4".into(),
+                    start: Duration::default(),
+                    duration: Duration::from_secs(10),
+                    timestamp,
+                },
+                ChapterBoundary {
+                    name: "GH020084.mp4".into(),
+                    start: Duration::from_secs(10),
+                    duration: Duration::from_millis(5_500),
+                    timestamp,
+                },
+            ],
+            hilights: vec![],
+        };
+
+        assert_eq!(
+            ";FFMETADATA1\n\
+             [CHAPTER]\nTIMEBASE=1/1000\nSTART=0\nEND=10000\ntitle=Chapter 1\n\
+             [CHAPTER]\nTIMEBASE=1/1000\nSTART=10000\nEND=15500\ntitle=Chapter 2\n",
+            render(&movie)
+        );
+    }
+}
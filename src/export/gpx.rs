@@ -0,0 +1,82 @@
+use std::fmt::Write;
+
+use crate::export::escape_xml;
+use crate::telemetry::GpsFix;
+
+/// A GPX 1.1 track of the GPS fixes of every chapter in order, a segment per chapter so that
+/// trackers don't draw the gaps between them as straight lines.
+pub fn render(name: &str, chapters: &[Vec<GpsFix>]) -> String {
+    let mut gpx = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<gpx version=\"1.1\" creator=\"gopro-merge\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+    ));
+    writeln!(gpx, "  <trk>\n    <name>{}</name>", escape_xml(name)).unwrap();
+    for fixes in chapters.iter().filter(|fixes| !fixes.is_empty()) {
+        gpx.push_str("    <trkseg>\n");
+        for fix in fixes {
+            write!(
+                gpx,
+                "      <trkpt lat=\"{:.7}\" lon=\"{:.7}\"><ele>{:.3}</ele>",
+                fix.latitude, fix.longitude, fix.altitude
+            )
+            .unwrap();
+            if let Some(time) = fix.time {
+                write!(
+                    gpx,
+                    "<time>{}</time>",
+                    humantime::format_rfc3339_millis(time)
+                )
+                .unwrap();
+            }
+            gpx.push_str("</trkpt>\n");
+        }
+        gpx.push_str("    </trkseg>\n");
+    }
+    gpx.push_str("  </trk>\n</gpx>\n");
+
+    gpx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_render() {
+        let time = humantime::parse_rfc3339("2022-06-12T09:30:00Z").unwrap();
+        let fix = |latitude: f64, longitude: f64, time| GpsFix {
+            latitude,
+            longitude,
+            altitude: 450.5,
+            time,
+        };
+        let chapters = vec![
+            vec![
+                fix(47.4, 8.5, Some(time)),
+                fix(47.40001, 8.50002, Some(time + Duration::from_millis(500))),
+            ],
+            vec![],
+            vec![fix(47.5, 8.6, None)],
+        ];
+
+        assert_eq!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="gopro-merge" xmlns="http://www.topografix.com/GPX/1/1">
+  <trk>
+    <name>GH0084 &amp; friends</name>
+    <trkseg>
+      <trkpt lat="47.4000000" lon="8.5000000"><ele>450.500</ele><time>2022-06-12T09:30:00.000Z</time></trkpt>
+      <trkpt lat="47.4000100" lon="8.5000200"><ele>450.500</ele><time>2022-06-12T09:30:00.500Z</time></trkpt>
+    </trkseg>
+    <trkseg>
+      <trkpt lat="47.5000000" lon="8.6000000"><ele>450.500</ele></trkpt>
+    </trkseg>
+  </trk>
+</gpx>
+"#,
+            render("GH0084 & friends", &chapters)
+        );
+    }
+}
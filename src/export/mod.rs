@@ -0,0 +1,171 @@
+mod fcpxml;
+mod ffmetadata;
+mod gpx;
+mod srt;
+mod vtt;
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use derive_more::Display;
+use log::*;
+use thiserror::Error;
+
+use crate::chapters::ChapterBoundary;
+use crate::telemetry::GpsFix;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid export format {0}, supported formats are \"fcpxml\", \"srt\", \"vtt\"")]
+    InvalidFormat(String),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Display)]
+pub enum ExportFormat {
+    #[display(fmt = "fcpxml")]
+    FinalCutProXml,
+    #[display(fmt = "srt")]
+    Subtitles,
+    #[display(fmt = "vtt")]
+    WebVtt,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fcpxml" => Ok(ExportFormat::FinalCutProXml),
+            "srt" => Ok(ExportFormat::Subtitles),
+            "vtt" => Ok(ExportFormat::WebVtt),
+            _ => Err(Error::InvalidFormat(s.into())),
+        }
+    }
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::FinalCutProXml => "fcpxml",
+            ExportFormat::Subtitles => "srt",
+            ExportFormat::WebVtt => "vtt",
+        }
+    }
+
+    pub fn render(&self, movie: &MergedMovie) -> String {
+        match self {
+            ExportFormat::FinalCutProXml => fcpxml::render(movie),
+            ExportFormat::Subtitles => srt::render(movie),
+            ExportFormat::WebVtt => vtt::render(movie),
+        }
+    }
+}
+
+/// Everything known about a merged movie after a successful merge, fed to the exporters.
+#[derive(Debug, Clone)]
+pub struct MergedMovie {
+    pub name: String,
+    pub path: PathBuf,
+    pub duration: Duration,
+    pub chapters: Vec<ChapterBoundary>,
+    pub hilights: Vec<Duration>,
+}
+
+/// The ffmpeg metadata file with the chapter markers of the merged movie, from
+/// `--chapter-markers`.
+pub fn chapter_markers(movie: &MergedMovie) -> String {
+    ffmetadata::render(movie)
+}
+
+/// The GPX track named `name` of the GPS fixes of every chapter of a merged movie, from
+/// `--extract-gpx`.
+pub fn gpx_track(name: &str, chapters: &[Vec<GpsFix>]) -> String {
+    gpx::render(name, chapters)
+}
+
+/// Writes the export next to the merged movie, returning the path of the written file.
+pub fn export(format: ExportFormat, movie: &MergedMovie) -> io::Result<PathBuf> {
+    let path = movie.path.with_extension(format.extension());
+    info!("exporting {} to {}", movie.name, path.display());
+    fs::write(&path, format.render(movie))?;
+    Ok(path)
+}
+
+/// When the chapter was captured in UTC, e.g. "2021-11-14 23:15:25 UTC".
+fn capture_time(chapter: &ChapterBoundary) -> Option<String> {
+    chapter.timestamp.time.map(|time| {
+        humantime::format_rfc3339_seconds(time)
+            .to_string()
+            .replace('T', " ")
+            .replace('Z', " UTC")
+    })
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .chars()
+        .fold(String::with_capacity(value.len()), |mut acc, c| {
+            match c {
+                '&' => acc.push_str("&amp;"),
+                '<' => acc.push_str("&lt;"),
+                '>' => acc.push_str("&gt;"),
+                '"' => acc.push_str("&quot;"),
+                '\'' => acc.push_str("&apos;"),
+                c => acc.push(c),
+            };
+            acc
+        })
+}
+
+fn file_url(path: &std::path::Path) -> String {
+    let path = path.to_string_lossy();
+    let encoded = path.bytes().fold(String::new(), |mut acc, b| {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                acc.push(b as char)
+            }
+            b => acc.push_str(&format!("%{:02X}", b)),
+        };
+        acc
+    });
+
+    format!("file://{}", encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_format_from_str() {
+        assert_eq!(
+            ExportFormat::FinalCutProXml,
+            ExportFormat::from_str("fcpxml").unwrap()
+        );
+        assert_eq!(
+            ExportFormat::Subtitles,
+            ExportFormat::from_str("srt").unwrap()
+        );
+        assert_eq!(ExportFormat::WebVtt, ExportFormat::from_str("vtt").unwrap());
+        assert!(ExportFormat::from_str("edl").is_err());
+    }
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(
+            "a &amp; b &lt;c&gt; &quot;d&quot; &apos;e&apos;",
+            escape_xml("a & b <c> \"d\" 'e'")
+        );
+    }
+
+    #[test]
+    fn test_file_url() {
+        assert_eq!(
+            "file:///tmp/my%20movies/GH000084.mp4",
+            file_url(std::path::Path::new("/tmp/my movies/GH000084.mp4"))
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::export::{capture_time, MergedMovie};
+
+/// How long a chapter's cue stays on screen, shorter for shorter chapters.
+const CUE_DURATION: Duration = Duration::from_secs(5);
+
+fn time(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+pub fn render(movie: &MergedMovie) -> String {
+    let mut srt = String::new();
+    movie
+        .chapters
+        .iter()
+        .enumerate()
+        .for_each(|(index, chapter)| {
+            let captured = capture_time(chapter).unwrap_or_else(|| "unknown capture time".into());
+
+            write!(
+                srt,
+                "{}\n{} --> {}\n{}\n{}\n\n",
+                index + 1,
+                time(chapter.start),
+                time(
+                    chapter
+                        .start
+                        .saturating_add(CUE_DURATION.min(chapter.duration))
+                ),
+                chapter.name,
+                captured,
+            )
+            .unwrap();
+        });
+
+    srt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::chapters::ChapterBoundary;
+    use crate::timestamp::{ChapterTimestamp, TimestampSource};
+
+    #[test]
+    fn test_time() {
+        assert_eq!("00:00:00,000", time(Duration::default()));
+        assert_eq!("01:02:03,456", time(Duration::from_millis(3_723_456)));
+        assert_eq!("27:00:00,000", time(Duration::from_secs(27 * 60 * 60)));
+    }
+
+    #[test]
+    fn test_render() {
+        let movie = MergedMovie {
+            name: "GH000084.mp4".into(),
+            path: "/movies/GH000084.mp4".into(),
+            duration: Duration::from_secs(12),
+            chapters: vec![
+                ChapterBoundary {
+                    name: "GH010084.mp4".into(),
+                    start: Duration::default(),
+                    duration: Duration::from_secs(9),
+                    timestamp: ChapterTimestamp {
+                        time: Some(humantime::parse_rfc3339("2021-11-14T23:15:25Z").unwrap()),
+                        source: TimestampSource::Container,
+                    },
+                },
+                ChapterBoundary {
+                    name: "GH020084.mp4".into(),
+                    start: Duration::from_secs(9),
+                    duration: Duration::from_secs(3),
+                    timestamp: ChapterTimestamp {
+                        time: None,
+                        source: TimestampSource::Unknown,
+                    },
+                },
+            ],
+            hilights: vec![],
+        };
+
+        assert_eq!(
+            "1\n00:00:00,000 --> 00:00:05,000\nGH010084.mp4\n2021-11-14 23:15:25 UTC\n\n\
+             2\n00:00:09,000 --> 00:00:12,000\nGH020084.mp4\nunknown capture time\n\n",
+            render(&movie)
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::export::{capture_time, MergedMovie};
+
+fn time(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Cue text can't hold the markup characters, nor the "-->" separating cue times.
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// A WebVTT chapters track with a cue spanning every source chapter, labelled like
+/// "Chapter 1 (GH010084.mp4, 2021-11-14 23:15:25 UTC)", for the chapter menus of web players.
+pub fn render(movie: &MergedMovie) -> String {
+    let mut vtt = String::from("WEBVTT\n\n");
+    movie
+        .chapters
+        .iter()
+        .enumerate()
+        .for_each(|(index, chapter)| {
+            let label = match capture_time(chapter) {
+                Some(captured) => format!("{}, {}", chapter.name, captured),
+                None => chapter.name.clone(),
+            };
+
+            write!(
+                vtt,
+                "chapter-{}\n{} --> {}\nChapter {} ({})\n\n",
+                index + 1,
+                time(chapter.start),
+                time(chapter.start.saturating_add(chapter.duration)),
+                index + 1,
+                escape(&label),
+            )
+            .unwrap();
+        });
+
+    vtt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::chapters::ChapterBoundary;
+    use crate::timestamp::{ChapterTimestamp, TimestampSource};
+
+    #[test]
+    fn test_render() {
+        let movie = MergedMovie {
+            name: "GH000084.mp4".into(),
+            path: "/movies/GH000084.mp4".into(),
+            duration: Duration::from_millis(3_723_456),
+            chapters: vec![
+                ChapterBoundary {
+                    name: "GH010084.mp4".into(),
+                    start: Duration::default(),
+                    duration: Duration::from_secs(3_600),
+                    timestamp: ChapterTimestamp {
+                        time: Some(humantime::parse_rfc3339("2021-11-14T23:15:25Z").unwrap()),
+                        source: TimestampSource::Container,
+                    },
+                },
+                ChapterBoundary {
+                    name: "GH020084 <copy>.mp4".into(),
+                    start: Duration::from_secs(3_600),
+                    duration: Duration::from_millis(123_456),
+                    timestamp: ChapterTimestamp {
+                        time: None,
+                        source: TimestampSource::Unknown,
+                    },
+                },
+            ],
+            hilights: vec![],
+        };
+
+        assert_eq!(
+            "WEBVTT\n\n\
+             chapter-1\n00:00:00.000 --> 01:00:00.000\n\
+             Chapter 1 (GH010084.mp4, 2021-11-14 23:15:25 UTC)\n\n\
+             chapter-2\n01:00:00.000 --> 01:02:03.456\n\
+             Chapter 2 (GH020084 &lt;copy&gt;.mp4)\n\n",
+            render(&movie)
+        );
+    }
+}
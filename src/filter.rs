@@ -0,0 +1,219 @@
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::group::{MovieGroup, MovieGroups};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid file number range {0}, expected e.g. 1234 or 1250-1260")]
+    InvalidFileRange(String),
+}
+
+/// Inclusive range of file numbers, e.g. `1250-1260` or just `1234`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileRange {
+    start: usize,
+    end: usize,
+}
+
+impl FromStr for FileRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .map_err(|_| Error::InvalidFileRange(s.into()))
+        };
+
+        let (start, end) = match s.split_once('-') {
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            None => (parse(s)?, parse(s)?),
+        };
+        if start > end {
+            return Err(Error::InvalidFileRange(s.into()));
+        }
+
+        Ok(FileRange { start, end })
+    }
+}
+
+impl fmt::Display for FileRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+impl FileRange {
+    pub fn contains(&self, file: usize) -> bool {
+        self.start <= file && file <= self.end
+    }
+}
+
+/// Which of the found groups get merged, all of them unless restricted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupFilter {
+    /// Glob patterns with `*` and `?` matched against the merged movie or any chapter file name,
+    /// case insensitively.
+    pub patterns: Vec<String>,
+    pub files: Vec<FileRange>,
+}
+
+impl GroupFilter {
+    /// Whether the `group` matches any of the patterns and any of the file ranges, each of them
+    /// matching everything when there are none.
+    pub fn matches(&self, group: &MovieGroup) -> bool {
+        let matches_pattern = self.patterns.is_empty()
+            || self.patterns.iter().any(|pattern| {
+                std::iter::once(group.name())
+                    .chain(
+                        group
+                            .chapters
+                            .iter()
+                            .map(|chapter| group.chapter_file_name(chapter)),
+                    )
+                    .any(|name| glob_match(pattern, &name))
+            });
+        let matches_file = self.files.is_empty()
+            || group.fingerprint.file.numeric().map_or(false, |file| {
+                self.files.iter().any(|range| range.contains(file))
+            });
+
+        matches_pattern && matches_file
+    }
+
+    pub fn apply(&self, movies: MovieGroups) -> MovieGroups {
+        movies
+            .into_iter()
+            .filter(|group| self.matches(group))
+            .collect()
+    }
+}
+
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase().chars().collect::<Vec<_>>();
+    let name = name.to_ascii_lowercase().chars().collect::<Vec<_>>();
+
+    // the position after the last star and the name position it was tried at, backtracked to on
+    // a mismatch to let the star match one more character
+    let (mut p, mut n, mut star) = (0, 0, None);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use crate::encoding::Encoding;
+    use crate::identifier::Identifier;
+    use crate::movie::Fingerprint;
+
+    fn group(file: &str, chapters: &[&str]) -> MovieGroup {
+        MovieGroup {
+            fingerprint: Fingerprint {
+                encoding: Encoding::Avc,
+                file: Identifier::try_from(file).unwrap(),
+                extension: "MP4".into(),
+                scheme: Default::default(),
+            },
+            chapters: chapters
+                .iter()
+                .map(|chapter| Identifier::try_from(*chapter).unwrap())
+                .collect(),
+            directories: Default::default(),
+            file_names: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_file_range_from_str() {
+        assert_eq!(
+            FileRange {
+                start: 1234,
+                end: 1234
+            },
+            "1234".parse().unwrap()
+        );
+        assert_eq!(
+            FileRange {
+                start: 1250,
+                end: 1260
+            },
+            "1250-1260".parse().unwrap()
+        );
+        assert!("1260-1250".parse::<FileRange>().is_err());
+        assert!("GH011234".parse::<FileRange>().is_err());
+        assert!("".parse::<FileRange>().is_err());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("GH0112*", "GH011234.MP4"));
+        assert!(glob_match("gh0112*", "GH011234.MP4"));
+        assert!(glob_match("*.mp4", "GH011234.MP4"));
+        assert!(glob_match("GH??1234.MP4", "GH021234.MP4"));
+        assert!(glob_match("*12*4*", "GH011234.MP4"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("GH0112*", "GX011234.MP4"));
+        assert!(!glob_match("GH01123?.MP4", "GH011234.MOV"));
+        assert!(!glob_match("", "GH011234.MP4"));
+    }
+
+    #[test]
+    fn test_group_filter() {
+        let movies = vec![
+            group("1234", &["01", "02"]),
+            group("1250", &["01"]),
+            group("1261", &["01"]),
+        ];
+
+        assert_eq!(3, GroupFilter::default().apply(movies.clone()).len());
+
+        let by_chapter = GroupFilter {
+            patterns: vec!["GH0212*".into()],
+            ..Default::default()
+        };
+        assert_eq!(vec![movies[0].clone()], by_chapter.apply(movies.clone()));
+
+        let by_files = GroupFilter {
+            files: vec!["1234".parse().unwrap(), "1250-1260".parse().unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(movies[..2].to_vec(), by_files.apply(movies.clone()));
+
+        let both = GroupFilter {
+            patterns: vec!["GH00*".into()],
+            files: vec!["1250-1261".parse().unwrap()],
+        };
+        assert_eq!(movies[1..].to_vec(), both.apply(movies));
+    }
+}
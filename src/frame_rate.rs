@@ -0,0 +1,66 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// How far the average frame rate of a video stream may be off from its nominal one before the
+/// stream is taken for variable frame rate, the last frame alone shifting the average a little.
+const VARIABLE_TOLERANCE: f64 = 0.005;
+
+/// A frame rate as ffprobe prints it, e.g. `30000/1001`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRate {
+    pub num: u64,
+    pub den: u64,
+}
+
+impl FrameRate {
+    pub fn fps(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    /// Whether a stream of this nominal frame rate averaging `average` frames per second has
+    /// frames of varying durations, which some editors drift the audio against.
+    pub fn varies_from(&self, average: FrameRate) -> bool {
+        (self.fps() - average.fps()).abs() > self.fps() * VARIABLE_TOLERANCE
+    }
+}
+
+/// Formats as frames per second with up to two decimals, e.g. `29.97`.
+impl fmt::Display for FrameRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fps = format!("{:.2}", self.fps());
+        f.write_str(fps.trim_end_matches('0').trim_end_matches('.'))
+    }
+}
+
+/// Parses `<num>/<den>` or a whole number, ffprobe's `0/0` for unknown rates being invalid.
+impl FromStr for FrameRate {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (num, den) = s.split_once('/').unwrap_or((s, "1"));
+        match (num.trim().parse(), den.trim().parse()) {
+            (Ok(num), Ok(den)) if num > 0 && den > 0 => Ok(FrameRate { num, den }),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_rate() {
+        let ntsc: FrameRate = "60000/1001".parse().unwrap();
+        assert_eq!("59.94", ntsc.to_string());
+        assert_eq!("30", "30".parse::<FrameRate>().unwrap().to_string());
+        assert_eq!("25", "25/1".parse::<FrameRate>().unwrap().to_string());
+        assert!("0/0".parse::<FrameRate>().is_err());
+        assert!("N/A".parse::<FrameRate>().is_err());
+
+        assert!(!ntsc.varies_from(ntsc));
+        // a frame short over a minute of footage
+        assert!(!ntsc.varies_from("3595/60".parse().unwrap()));
+        assert!(ntsc.varies_from("14985000/300301".parse().unwrap()));
+    }
+}
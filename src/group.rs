@@ -1,13 +1,22 @@
+use std::collections::{BTreeMap, VecDeque};
 use std::convert::TryFrom;
-use std::io;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::{collections::HashMap, path::Path};
 
 use derive_more::Display;
 use log::*;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 
 use crate::identifier::Identifier;
 use crate::movie::{self, Fingerprint, Movie};
+use crate::mp4;
+use crate::proxy::ProxyMode;
+use crate::scheme::Schemes;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -16,6 +25,12 @@ pub enum Error {
 
     #[error(transparent)]
     IO(#[from] io::Error),
+
+    #[error("Invalid fusion mode {0}, one of \"separate\" | \"refuse\"")]
+    InvalidFusionMode(String),
+
+    #[error("{0} are GoPro Fusion front and back lens movies, merge each lens separately with --fusion separate")]
+    FusionRefused(String),
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -25,22 +40,84 @@ type Result<T> = std::result::Result<T, Error>;
 pub struct MovieGroup {
     pub fingerprint: Fingerprint,
     pub chapters: Vec<Identifier>,
+    /// Directories the chapters were found in, chapters without one are looked up in the
+    /// input directory.
+    pub directories: BTreeMap<Identifier, PathBuf>,
+    /// Names of the chapters on disk when they differ in case from
+    /// [Fingerprint::chapter_file_name], e.g. gh011234.MP4.
+    pub file_names: BTreeMap<Identifier, String>,
 }
 
 impl MovieGroup {
+    /// Full path of the chapter, falling back to `movies_path` for chapters without a known directory.
+    pub fn chapter_path(&self, chapter: &Identifier, movies_path: &Path) -> PathBuf {
+        self.directories
+            .get(chapter)
+            .map(PathBuf::as_path)
+            .unwrap_or(movies_path)
+            .join(
+                self.file_names
+                    .get(chapter)
+                    .cloned()
+                    .unwrap_or_else(|| self.chapter_file_name(chapter)),
+            )
+    }
+
     pub fn name(&self) -> String {
-        self.file_name("00")
+        self.fingerprint.to_string()
     }
 
     pub fn chapter_file_name(&self, chapter: &Identifier) -> String {
-        self.file_name(chapter.to_string().as_str())
+        self.fingerprint.chapter_file_name(chapter)
     }
 
-    fn file_name(&self, chapter: &str) -> String {
-        format!(
-            "{}{}{}.{}",
-            self.fingerprint.encoding, chapter, self.fingerprint.file, self.fingerprint.extension
-        )
+    /// The stable id of the group, the same across runs for the same chapters.
+    pub fn id(&self) -> GroupId {
+        let mut chapters = self
+            .chapters
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        chapters.sort();
+
+        // the extension is compared ignoring case when grouping, e.g. GH010084.MP4 and gh020084.mp4
+        let mut hash = Sha256::new();
+        // the GoPro groups keep the ids they had before other cameras' schemes
+        if !self.fingerprint.scheme.is_default() {
+            hash.update(format!("{}\n", self.fingerprint.scheme.name()).as_bytes());
+        }
+        hash.update(
+            format!(
+                "{}\n{}\n{}\n{}",
+                self.fingerprint.encoding,
+                self.fingerprint.file,
+                self.fingerprint.extension.to_string().to_ascii_lowercase(),
+                chapters.join(",")
+            )
+            .as_bytes(),
+        );
+        GroupId(format!("{:x}", hash.finalize())[..GroupId::LEN].into())
+    }
+}
+
+/// Deterministic id of a group, hashed from its encoding, file number, extension and chapters,
+/// for joining the plans, events and resume state of several runs without parsing names.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Display)]
+#[display(fmt = "{}", _0)]
+pub struct GroupId(String);
+
+impl GroupId {
+    /// Hex digits of the hash kept, 64 bits.
+    pub const LEN: usize = 16;
+
+    /// The id written as `s`, if it's one.
+    pub fn parse(s: &str) -> Option<Self> {
+        (s.len() == Self::LEN && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')))
+            .then(|| GroupId(s.into()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
     }
 }
 
@@ -52,45 +129,320 @@ impl PartialEq for MovieGroup {
 
 pub type MovieGroups = Vec<MovieGroup>;
 
+/// What to do with the paired front and back lens movies of a GoPro Fusion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum FusionMode {
+    /// Merge the chapters of each lens into its own movie, GF00xxxx and GB00xxxx sharing the
+    /// file number for stitching them afterwards.
+    #[display(fmt = "separate")]
+    Separate,
+    /// Fail the scan instead of merging them.
+    #[display(fmt = "refuse")]
+    Refuse,
+}
+
+impl Default for FusionMode {
+    fn default() -> Self {
+        FusionMode::Separate
+    }
+}
+
+impl FromStr for FusionMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "separate" => FusionMode::Separate,
+            "refuse" => FusionMode::Refuse,
+            _ => return Err(Error::InvalidFusionMode(s.into())),
+        })
+    }
+}
+
+/// Controls which directories are looked into for movies.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanOptions {
+    /// Descend into subdirectories, e.g. `DCIM/100GOPRO`, `DCIM/101GOPRO`.
+    pub recursive: bool,
+    /// How many levels of subdirectories to descend into when recursive, unlimited if `None`.
+    pub max_depth: Option<usize>,
+    /// What to do with the movies of a GoPro Fusion.
+    pub fusion: FusionMode,
+    /// Skips the .lrv and .thm proxies unless [ProxyMode::Off].
+    pub proxies: ProxyMode,
+    /// How the cameras name their movies, the GoPro names unless others are registered.
+    pub schemes: Schemes,
+}
+
 pub fn group_movies(path: &Path) -> Result<MovieGroups> {
-    let movies = collect_movies(path)?;
-    Ok(groups_from_movies(movies))
+    group_movies_with(path, &ScanOptions::default())
 }
 
-fn collect_movies(path: &Path) -> Result<impl Iterator<Item = Movie>> {
-    let files = path
-        .read_dir()?
-        .into_iter()
-        .map(|f| f.map_err(From::from))
-        .collect::<Result<Vec<_>>>()?;
+pub fn group_movies_with(path: &Path, options: &ScanOptions) -> Result<MovieGroups> {
+    group_movies_in(&[path.to_path_buf()], options)
+}
+
+/// Groups the movies of all the `paths` together, e.g. of several memory cards. A chapter found
+/// in more than one of them is taken from the first.
+pub fn group_movies_in(paths: &[PathBuf], options: &ScanOptions) -> Result<MovieGroups> {
+    scan_movies_in(paths, options).map(|(groups, _)| groups)
+}
+
+/// Groups the movies of all the `paths` like [group_movies_in], along with the files named like
+/// chapters that were skipped, for telling about them.
+pub fn scan_movies_in(
+    paths: &[PathBuf],
+    options: &ScanOptions,
+) -> Result<(MovieGroups, Vec<SkippedFile>)> {
+    let (mut movies, mut skipped) = (vec![], vec![]);
+    for path in paths {
+        movies.extend(collect_movies_with(path, options, &mut skipped)?);
+    }
+    let mut groups = groups_from_movies(movies.into_iter());
+    check_fusion_pairs(&groups, options.fusion)?;
+    groups.sort();
+    Ok((groups, skipped))
+}
+
+/// A file named like a chapter left out of the movies, e.g. a document renamed to .mp4.
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+#[display(fmt = "skipping {}, {}", "path.display()", reason)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    /// Why it was skipped, e.g. "it's not a movie file".
+    pub reason: String,
+}
 
-    let movies = files.into_iter().filter_map(|rec| {
-        let file_name = rec.file_name();
-        let name = file_name.to_str().unwrap();
-        debug!("trying to parse file with name {}", name);
-        let parsed = Movie::try_from(name).ok();
-        debug!("parsed file with name {}: {:?}", name, parsed);
-        parsed
+/// Refuses the movies of a GoPro Fusion with [FusionMode::Refuse], otherwise warns about lenses
+/// whose chapters don't pair up with the other lens.
+fn check_fusion_pairs(groups: &[MovieGroup], mode: FusionMode) -> Result<()> {
+    let mut fusion = groups
+        .iter()
+        .filter(|group| group.fingerprint.encoding.is_fusion())
+        .collect::<Vec<_>>();
+    if fusion.is_empty() {
+        return Ok(());
+    }
+    fusion.sort();
+
+    if mode == FusionMode::Refuse {
+        return Err(Error::FusionRefused(
+            fusion
+                .iter()
+                .map(|group| group.name())
+                .collect::<Vec<_>>()
+                .join(", "),
+        ));
+    }
+
+    for group in &fusion {
+        let paired = fusion.iter().any(|other| {
+            other.fingerprint.encoding != group.fingerprint.encoding
+                && other.fingerprint.file == group.fingerprint.file
+                && other.fingerprint.extension == group.fingerprint.extension
+                && other.chapters == group.chapters
+        });
+        if !paired {
+            warn!(
+                "{} doesn't pair up with the chapters of the other Fusion lens, merging it alone",
+                group
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Groups movie file names without looking at the file system, leaving out the names which
+/// aren't GoPro movies. The chapters have no known directory.
+pub fn group_file_names<'a>(names: impl IntoIterator<Item = &'a str>) -> MovieGroups {
+    let movies = names.into_iter().filter_map(|name| {
+        Movie::try_from(name)
+            .ok()
+            .map(|movie| (movie, PathBuf::new(), name.to_string()))
     });
+    let mut groups = groups_from_movies(movies);
+    groups
+        .iter_mut()
+        .for_each(|group| group.directories.clear());
+    groups.sort();
+    groups
+}
+
+/// Groups the chapters at `paths` as they are, without scanning their directories, e.g. the ones
+/// listed by a manifest. Fails for files not named like GoPro chapters.
+pub fn group_chapter_paths(paths: &[PathBuf]) -> Result<MovieGroups> {
+    let movies = paths
+        .iter()
+        .map(|path| {
+            let name = path
+                .file_name()
+                .and_then(OsStr::to_str)
+                .ok_or_else(|| movie::Error::InvalidFileName(path.display().to_string()))?;
+            let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            Ok((Movie::try_from(name)?, dir, name.to_string()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut groups = groups_from_movies(movies.into_iter());
+    groups.sort();
+    Ok(groups)
+}
+
+#[cfg(test)]
+fn collect_movies(path: &Path) -> Result<impl Iterator<Item = Movie>> {
+    collect_movies_with(path, &ScanOptions::default(), &mut vec![])
+        .map(|movies| movies.into_iter().map(|(movie, _, _)| movie))
+}
+
+/// Collects the movies with the directory they were found in and their name on disk, adding the
+/// files named like chapters which aren't movies to `skipped`.
+fn collect_movies_with(
+    path: &Path,
+    options: &ScanOptions,
+    skipped: &mut Vec<SkippedFile>,
+) -> Result<Vec<(Movie, PathBuf, String)>> {
+    let mut movies = vec![];
+    // breadth first, so that duplicated chapters are taken from the shallowest directory
+    let mut dirs = VecDeque::from(vec![(path.to_path_buf(), 0)]);
+
+    while let Some((dir, depth)) = dirs.pop_front() {
+        debug!("collecting movies in {} at depth {}", dir.display(), depth);
+        let mut files = dir
+            .read_dir()?
+            .map(|f| f.map_err(From::from))
+            .collect::<Result<Vec<_>>>()?;
+        // in the same order whatever order the file system lists them in, so that the same
+        // duplicate wins and repeated scans group the chapters the same way
+        files.sort_by_key(|file| file.file_name());
+
+        files.into_iter().try_for_each(|rec| -> Result<()> {
+            // symlinked directories are not followed to avoid cycles
+            if rec.file_type()?.is_dir() {
+                if options.recursive && options.max_depth.map_or(true, |max| depth < max) {
+                    dirs.push_back((rec.path(), depth + 1));
+                }
+                return Ok(());
+            }
+
+            let file_name = rec.file_name();
+            let name = file_name.to_str().unwrap();
+            if options.proxies.skips(name) {
+                debug!("skipping proxy {}", name);
+                return Ok(());
+            }
+            debug!("trying to parse file with name {}", name);
+            let parsed = options.schemes.parse(name).ok();
+            debug!("parsed file with name {}: {:?}", name, parsed);
+            if let Some(movie) = parsed {
+                match skip_non_movie(&rec.path()) {
+                    Some(file) => skipped.push(file),
+                    None => movies.push((movie, dir.clone(), name.to_string())),
+                }
+            }
+            Ok(())
+        })?;
+    }
 
     Ok(movies)
 }
 
-fn groups_from_movies(movies: impl Iterator<Item = Movie>) -> MovieGroups {
+/// The file at `path` as skipped, if it doesn't start like a movie.
+fn skip_non_movie(path: &Path) -> Option<SkippedFile> {
+    let reason =
+        match mp4::has_ftyp_signature(path).and_then(|ftyp| Ok(ftyp || has_ts_signature(path)?)) {
+            Ok(true) => return None,
+            Ok(false) => "it's not a movie file".to_string(),
+            Err(err) => format!("reading its signature: {}", err),
+        };
+    let skipped = SkippedFile {
+        path: path.to_path_buf(),
+        reason,
+    };
+    warn!("{}", skipped);
+    Some(skipped)
+}
+
+/// MPEG-TS packets are 188 bytes starting with this byte, 192 for the timestamped packets of
+/// .m2ts, which start with it after the 4 bytes of the timestamp.
+const TS_SYNC_BYTE: u8 = 0x47;
+const TS_PACKET_LEN: usize = 188;
+const M2TS_PACKET_LEN: usize = 192;
+
+/// Cheap check whether the file starts with two MPEG-TS packets, e.g. the chapters joined with
+/// `--no-temp-concat`, which have no `ftyp` box to tell them by.
+fn has_ts_signature(path: &Path) -> io::Result<bool> {
+    let mut header = Vec::with_capacity(M2TS_PACKET_LEN + 5);
+    fs::File::open(path)?
+        .take(M2TS_PACKET_LEN as u64 + 5)
+        .read_to_end(&mut header)?;
+    let synced = |offset: usize, packet_len: usize| {
+        header.get(offset) == Some(&TS_SYNC_BYTE)
+            && header.get(offset + packet_len) == Some(&TS_SYNC_BYTE)
+    };
+    Ok(synced(0, TS_PACKET_LEN) || synced(4, M2TS_PACKET_LEN))
+}
+
+fn same_size(path: &Path, other: &Path) -> bool {
+    match (fs::metadata(path), fs::metadata(other)) {
+        (Ok(metadata), Ok(other)) => metadata.len() == other.len(),
+        _ => false,
+    }
+}
+
+fn groups_from_movies(movies: impl Iterator<Item = (Movie, PathBuf, String)>) -> MovieGroups {
     movies
-        .fold(HashMap::new(), |mut acc, rec| {
+        .fold(HashMap::new(), |mut acc, (rec, dir, file_name)| {
             let group = acc
                 .entry(rec.fingerprint.clone())
                 .or_insert_with(|| MovieGroup {
                     fingerprint: rec.fingerprint.clone(),
                     chapters: vec![],
+                    directories: BTreeMap::new(),
+                    file_names: BTreeMap::new(),
                 });
-            group.chapters.push(rec.chapter);
+            match group.directories.get(&rec.chapter) {
+                // copies of the same chapter, e.g. on a card and its backup, have the same size
+                Some(existing)
+                    if same_size(
+                        &existing.join(&group.file_names[&rec.chapter]),
+                        &dir.join(&file_name),
+                    ) =>
+                {
+                    debug!(
+                        "skipping {} in {}, a copy of the one in {}",
+                        rec,
+                        dir.display(),
+                        existing.display()
+                    )
+                }
+                Some(existing) => warn!(
+                    "skipping {} in {}, it was already found in {} with a different size",
+                    rec,
+                    dir.display(),
+                    existing.display()
+                ),
+                None => {
+                    group.file_names.insert(rec.chapter.clone(), file_name);
+                    group.directories.insert(rec.chapter.clone(), dir);
+                    group.chapters.push(rec.chapter);
+                }
+            }
             acc
         })
         .drain()
         .map(|(_, mut v)| {
             v.chapters.sort();
+            // the merged movie takes the extension of the first chapter as it's named on disk
+            if let Some((_, extension)) = v
+                .chapters
+                .first()
+                .and_then(|first| v.file_names[first].rsplit_once('.'))
+            {
+                v.fingerprint.extension = extension.into();
+            }
+            let fingerprint = &v.fingerprint;
+            v.file_names
+                .retain(|chapter, name| *name != fingerprint.chapter_file_name(chapter));
             v
         })
         .collect::<MovieGroups>()
@@ -105,6 +457,8 @@ mod tests {
 
     use crate::encoding::Encoding;
 
+    const FTYP_HEADER: &[u8] = b"\0\0\0\x20ftypisom";
+
     #[derive(Debug)]
     struct Fs(PathBuf, Vec<PathBuf>);
 
@@ -136,7 +490,7 @@ mod tests {
                 .iter()
                 .map(|f| {
                     let p = tmp.join(f);
-                    fs::File::create(&p).unwrap();
+                    fs::write(&p, FTYP_HEADER).unwrap();
                     p
                 })
                 .collect();
@@ -155,6 +509,7 @@ mod tests {
                         encoding: Encoding::Avc,
                         file: Identifier::try_from("1234").unwrap(),
                         extension: "mp4".into(),
+                        scheme: Default::default(),
                     },
                     chapter: Identifier::try_from("01").unwrap(),
                 }],
@@ -167,6 +522,7 @@ mod tests {
                             encoding: Encoding::Avc,
                             file: Identifier::try_from("1234").unwrap(),
                             extension: "mp4".into(),
+                            scheme: Default::default(),
                         },
                         chapter: Identifier::try_from("01").unwrap(),
                     },
@@ -175,6 +531,7 @@ mod tests {
                             encoding: Encoding::Avc,
                             file: Identifier::try_from("1234").unwrap(),
                             extension: "mp4".into(),
+                            scheme: Default::default(),
                         },
                         chapter: Identifier::try_from("02").unwrap(),
                     },
@@ -195,6 +552,7 @@ mod tests {
                             encoding: Encoding::Avc,
                             file: Identifier::try_from("1234").unwrap(),
                             extension: "mp4".into(),
+                            scheme: Default::default(),
                         },
                         chapter: Identifier::try_from("01").unwrap(),
                     },
@@ -203,6 +561,7 @@ mod tests {
                             encoding: Encoding::Avc,
                             file: Identifier::try_from("1234").unwrap(),
                             extension: "mp4".into(),
+                            scheme: Default::default(),
                         },
                         chapter: Identifier::try_from("02").unwrap(),
                     },
@@ -216,6 +575,7 @@ mod tests {
                             encoding: Encoding::Avc,
                             file: Identifier::try_from("0001").unwrap(),
                             extension: "mp4".into(),
+                            scheme: Default::default(),
                         },
                         chapter: Identifier::try_from("AA").unwrap(),
                     },
@@ -224,6 +584,7 @@ mod tests {
                             encoding: Encoding::Avc,
                             file: Identifier::try_from("0002").unwrap(),
                             extension: "mp4".into(),
+                            scheme: Default::default(),
                         },
                         chapter: Identifier::try_from("AA").unwrap(),
                     },
@@ -237,6 +598,7 @@ mod tests {
                             encoding: Encoding::Avc,
                             file: Identifier::try_from("1234").unwrap(),
                             extension: "mp4".into(),
+                            scheme: Default::default(),
                         },
                         chapter: Identifier::try_from("01").unwrap(),
                     },
@@ -245,6 +607,7 @@ mod tests {
                             encoding: Encoding::Hevc,
                             file: Identifier::try_from("1234").unwrap(),
                             extension: "mp4".into(),
+                            scheme: Default::default(),
                         },
                         chapter: Identifier::try_from("01").unwrap(),
                     },
@@ -265,6 +628,107 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_collect_movies_skips_non_movies() {
+        let mut test = Test::<Movie>::new(vec!["GH011234.mp4"], vec![]);
+        test.setup_fs("test_collect_movies_skips_non_movies");
+
+        let fs = test.fs.as_ref().unwrap();
+        fs::write(fs.0.join("GH021234.mp4"), "not a movie").unwrap();
+        fs::write(fs.0.join("GH031234.mp4"), "").unwrap();
+
+        let movies = collect_movies(&fs.0).unwrap().collect::<Vec<_>>();
+        assert_eq!(1, movies.len());
+        assert_eq!("GH011234.mp4", movies[0].to_string());
+
+        let (groups, skipped) =
+            scan_movies_in(std::slice::from_ref(&fs.0), &ScanOptions::default()).unwrap();
+        assert_eq!(1, groups.len());
+        assert_eq!(
+            vec![
+                SkippedFile {
+                    path: fs.0.join("GH021234.mp4"),
+                    reason: "it's not a movie file".into(),
+                },
+                SkippedFile {
+                    path: fs.0.join("GH031234.mp4"),
+                    reason: "it's not a movie file".into(),
+                },
+            ],
+            skipped
+        );
+    }
+
+    #[test]
+    fn test_collect_movies_keeps_ts_chapters() {
+        let mut test = Test::<Movie>::new(vec![], vec![]);
+        test.setup_fs("test_collect_movies_keeps_ts_chapters");
+
+        let fs = test.fs.as_ref().unwrap();
+        let packets = |prefix: usize, packet_len: usize| {
+            let mut packets = vec![0u8; packet_len * 2];
+            packets[prefix] = 0x47;
+            packets[packet_len + prefix] = 0x47;
+            packets
+        };
+        fs::write(fs.0.join("GH011234.ts"), packets(0, 188)).unwrap();
+        fs::write(fs.0.join("GH021234.ts"), packets(0, 188)).unwrap();
+        fs::write(fs.0.join("GH011235.m2ts"), packets(4, 192)).unwrap();
+        // starts with the sync byte, but isn't followed by a packet
+        fs::write(fs.0.join("GH031234.ts"), "GoPro notes").unwrap();
+
+        let mut movies = collect_movies(&fs.0)
+            .unwrap()
+            .map(|movie| movie.to_string())
+            .collect::<Vec<_>>();
+        movies.sort();
+        assert_eq!(vec!["GH011234.ts", "GH011235.m2ts", "GH021234.ts"], movies);
+    }
+
+    #[test]
+    fn test_collect_movies_skips_proxies() {
+        let mut test = Test::<Movie>::new(vec!["GH011234.mp4", "GL011234.LRV"], vec![]);
+        test.setup_fs("test_collect_movies_skips_proxies");
+
+        let fs = test.fs.as_ref().unwrap();
+        let movies = collect_movies(&fs.0).unwrap().collect::<Vec<_>>();
+        assert_eq!(
+            vec!["GH011234.mp4"],
+            movies.iter().map(ToString::to_string).collect::<Vec<_>>()
+        );
+
+        let movies = collect_movies_with(
+            &fs.0,
+            &ScanOptions {
+                proxies: ProxyMode::Off,
+                ..Default::default()
+            },
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!(2, movies.len());
+    }
+
+    #[test]
+    fn test_group_movies_case_insensitive() {
+        let mut test = Test::<Movie>::new(vec!["GH011234.MP4", "gh021234.mp4"], vec![]);
+        test.setup_fs("test_group_movies_case_insensitive");
+
+        let fs = test.fs.as_ref().unwrap();
+        let groups = group_movies(&fs.0).unwrap();
+        assert_eq!(1, groups.len());
+        let group = &groups[0];
+        assert_eq!("GH001234.MP4", group.name());
+        assert_eq!(
+            vec![fs.0.join("GH011234.MP4"), fs.0.join("gh021234.mp4")],
+            group
+                .chapters
+                .iter()
+                .map(|chapter| group.chapter_path(chapter, &fs.0))
+                .collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn test_movies() {
         let tests = vec![
@@ -274,12 +738,15 @@ mod tests {
                     fingerprint: Fingerprint {
                         encoding: Encoding::Avc,
                         extension: "mp4".into(),
+                        scheme: Default::default(),
                         file: "1234".try_into().unwrap(),
                     },
                     chapters: vec![
                         Identifier::try_from("01").unwrap(),
                         Identifier::try_from("02").unwrap(),
                     ],
+                    directories: Default::default(),
+                    file_names: Default::default(),
                 }],
             ),
             Test::new(
@@ -294,20 +761,26 @@ mod tests {
                         fingerprint: Fingerprint {
                             encoding: Encoding::Avc,
                             extension: "mp4".into(),
+                            scheme: Default::default(),
                             file: "1234".try_into().unwrap(),
                         },
                         chapters: vec![
                             Identifier::try_from("01").unwrap(),
                             Identifier::try_from("02").unwrap(),
                         ],
+                        directories: Default::default(),
+                        file_names: Default::default(),
                     },
                     MovieGroup {
                         fingerprint: Fingerprint {
                             encoding: Encoding::Hevc,
                             extension: "flv".into(),
+                            scheme: Default::default(),
                             file: "1235".try_into().unwrap(),
                         },
                         chapters: vec![Identifier::try_from("01").unwrap()],
+                        directories: Default::default(),
+                        file_names: Default::default(),
                     },
                 ],
             ),
@@ -322,4 +795,258 @@ mod tests {
             assert_eq!(t.expected, result);
         });
     }
+
+    #[test]
+    fn test_group_legacy_movies() {
+        let mut test =
+            Test::<MovieGroup>::new(vec!["GP020034.MP4", "GOPR0034.MP4", "GP010034.MP4"], vec![]);
+        test.setup_fs("test_group_legacy_movies");
+
+        let fs = test.fs.as_ref().unwrap();
+        let groups = group_movies(&fs.0).unwrap();
+        assert_eq!(1, groups.len());
+
+        let group = &groups[0];
+        assert_eq!("GP000034.MP4", group.name());
+        let mut chapters = group.chapters.clone();
+        chapters.sort();
+        assert_eq!(
+            vec!["GOPR0034.MP4", "GP010034.MP4", "GP020034.MP4"],
+            chapters
+                .iter()
+                .map(|chapter| group.chapter_file_name(chapter))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_group_movies_recursive() {
+        let tmp = env::temp_dir().join("goprotest_group_test_group_movies_recursive");
+        let _ = fs::remove_dir_all(&tmp);
+        let nested = tmp.join("DCIM").join("100GOPRO");
+        let next = tmp.join("DCIM").join("101GOPRO");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(&next).unwrap();
+        fs::write(tmp.join("GH011234.mp4"), FTYP_HEADER).unwrap();
+        fs::write(nested.join("GH021234.mp4"), FTYP_HEADER).unwrap();
+        fs::write(next.join("GH031234.mp4"), FTYP_HEADER).unwrap();
+        fs::write(next.join("GH011234.mp4"), FTYP_HEADER).unwrap();
+
+        let flat = group_movies(&tmp).unwrap();
+        assert_eq!(1, flat[0].chapters.len());
+
+        let shallow = group_movies_with(
+            &tmp,
+            &ScanOptions {
+                recursive: true,
+                max_depth: Some(1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(1, shallow[0].chapters.len());
+
+        let groups = group_movies_with(
+            &tmp,
+            &ScanOptions {
+                recursive: true,
+                max_depth: None,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(1, groups.len());
+        let group = &groups[0];
+        let mut chapters = group.chapters.clone();
+        chapters.sort();
+        assert_eq!(
+            vec!["01", "02", "03"],
+            chapters.iter().map(|c| c.to_string()).collect::<Vec<_>>()
+        );
+        // the chapter from the shallowest directory wins over the duplicate
+        assert_eq!(
+            tmp.join("GH011234.mp4"),
+            group.chapter_path(&chapters[0], Path::new("/elsewhere"))
+        );
+        assert_eq!(
+            next.join("GH031234.mp4"),
+            group.chapter_path(&chapters[2], Path::new("/elsewhere"))
+        );
+
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+
+    #[test]
+    fn test_group_movies_in() {
+        let tmp = env::temp_dir().join("goprotest_group_test_group_movies_in");
+        let _ = fs::remove_dir_all(&tmp);
+        let (card, backup) = (tmp.join("card"), tmp.join("backup"));
+        fs::create_dir_all(&card).unwrap();
+        fs::create_dir_all(&backup).unwrap();
+        fs::write(card.join("GH011234.mp4"), FTYP_HEADER).unwrap();
+        fs::write(backup.join("GH011234.mp4"), FTYP_HEADER).unwrap();
+        fs::write(backup.join("GH021234.mp4"), FTYP_HEADER).unwrap();
+        fs::write(backup.join("GH011235.mp4"), FTYP_HEADER).unwrap();
+
+        let mut groups =
+            group_movies_in(&[card.clone(), backup.clone()], &ScanOptions::default()).unwrap();
+        groups.sort();
+        assert_eq!(2, groups.len());
+        let group = &groups[0];
+        assert_eq!("GH001234.mp4", group.name());
+        assert_eq!(
+            vec![card.join("GH011234.mp4"), backup.join("GH021234.mp4")],
+            group
+                .chapters
+                .iter()
+                .map(|chapter| group.chapter_path(chapter, &card))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            backup.join("GH011235.mp4"),
+            groups[1].chapter_path(&groups[1].chapters[0], &card)
+        );
+
+        // the first directory wins over a differing chapter too
+        fs::write(backup.join("GH011234.mp4"), [FTYP_HEADER, b"more"].concat()).unwrap();
+        let groups = group_movies_in(&[card.clone(), backup], &ScanOptions::default()).unwrap();
+        let group = groups
+            .iter()
+            .find(|group| group.chapters.len() == 2)
+            .unwrap();
+        assert_eq!(
+            card.join("GH011234.mp4"),
+            group.chapter_path(&group.chapters[0], &tmp)
+        );
+
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+
+    #[test]
+    fn test_group_movies_deterministic() {
+        let tmp = env::temp_dir().join("goprotest_group_test_group_movies_deterministic");
+        let _ = fs::remove_dir_all(&tmp);
+        // created in reverse so that file systems listing in creation order list them so
+        let dirs = ["102GOPRO", "101GOPRO", "100GOPRO"]
+            .iter()
+            .map(|dir| tmp.join("DCIM").join(dir))
+            .collect::<Vec<_>>();
+        for (index, dir) in dirs.iter().enumerate() {
+            fs::create_dir_all(dir).unwrap();
+            fs::write(dir.join(format!("GH01123{}.mp4", index)), FTYP_HEADER).unwrap();
+            // differing copies of the same chapter at the same depth
+            fs::write(
+                dir.join("GH029999.mp4"),
+                [FTYP_HEADER, &vec![0; index]].concat(),
+            )
+            .unwrap();
+        }
+        fs::write(dirs[0].join("gh019999.MP4"), FTYP_HEADER).unwrap();
+        fs::write(dirs[2].join("GH019999.mp4"), FTYP_HEADER).unwrap();
+
+        let options = ScanOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        let groups = group_movies_with(&tmp, &options).unwrap();
+        assert_eq!(
+            vec![
+                "GH001230.mp4",
+                "GH001231.mp4",
+                "GH001232.mp4",
+                "GH009999.mp4"
+            ],
+            groups.iter().map(|group| group.name()).collect::<Vec<_>>()
+        );
+        let group = &groups[3];
+        assert_eq!(
+            vec![dirs[2].join("GH019999.mp4"), dirs[2].join("GH029999.mp4")],
+            group
+                .chapters
+                .iter()
+                .map(|chapter| group.chapter_path(chapter, &tmp))
+                .collect::<Vec<_>>()
+        );
+        for _ in 0..3 {
+            assert_eq!(
+                format!("{:?}", groups),
+                format!("{:?}", group_movies_with(&tmp, &options).unwrap())
+            );
+        }
+
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+
+    #[test]
+    fn test_group_fusion_movies() {
+        let mut test = Test::<MovieGroup>::new(
+            vec![
+                "GPFR0039.MP4",
+                "GF010039.MP4",
+                "GPBK0039.MP4",
+                "GB010039.MP4",
+            ],
+            vec![],
+        );
+        test.setup_fs("test_group_fusion_movies");
+        let fs = test.fs.as_ref().unwrap();
+
+        let mut groups = group_movies(&fs.0).unwrap();
+        groups.sort();
+        assert_eq!(
+            vec!["GF000039.MP4", "GB000039.MP4"],
+            groups.iter().map(|group| group.name()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["GPBK0039.MP4", "GB010039.MP4"],
+            groups[1]
+                .chapters
+                .iter()
+                .map(|chapter| groups[1].chapter_file_name(chapter))
+                .collect::<Vec<_>>()
+        );
+
+        let refused = group_movies_with(
+            &fs.0,
+            &ScanOptions {
+                fusion: FusionMode::Refuse,
+                ..Default::default()
+            },
+        );
+        assert!(matches!(
+            refused,
+            Err(Error::FusionRefused(names)) if names == "GF000039.MP4, GB000039.MP4"
+        ));
+    }
+
+    #[test]
+    fn test_group_id() {
+        let groups = group_file_names(vec![
+            "GH010084.MP4",
+            "GH020084.MP4",
+            "gh030084.mp4",
+            "GH010085.MP4",
+        ]);
+        let id = groups[0].id();
+        assert_eq!(GroupId::LEN, id.as_str().len());
+        assert_eq!(Some(id.clone()), GroupId::parse(id.as_str()));
+
+        // the same chapters found in another order or case are the same group
+        let reordered = group_file_names(vec!["gh030084.MP4", "GH010084.mp4", "GH020084.MP4"]);
+        assert_eq!(id, reordered[0].id());
+
+        assert_ne!(id, groups[1].id());
+        let fewer = group_file_names(vec!["GH010084.MP4", "GH020084.MP4"]);
+        assert_ne!(id, fewer[0].id());
+
+        assert_eq!(None, GroupId::parse("GH000084.MP4"));
+        assert_eq!(None, GroupId::parse(&id.as_str().to_uppercase()));
+    }
+
+    #[test]
+    fn test_fusion_mode_from_str() {
+        assert_eq!(FusionMode::Separate, "separate".parse().unwrap());
+        assert_eq!(FusionMode::Refuse, "refuse".parse().unwrap());
+        assert!("stitch".parse::<FusionMode>().is_err());
+    }
 }
@@ -0,0 +1,601 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use derive_more::Display;
+use log::*;
+use parking_lot::Mutex;
+#[cfg(feature = "json-reporter")]
+use serde_json::json;
+use thiserror::Error;
+
+use crate::duration::FormattedDuration;
+use crate::group::MovieGroup;
+use crate::merge;
+use crate::mp4;
+use crate::progress::GroupStats;
+use crate::run::RunId;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Can't read the history {}, {1}", .0.display())]
+    Read(PathBuf, io::Error),
+
+    #[error("Invalid --history-report {0}, supported are \"day\", \"card\", \"camera\"")]
+    InvalidKey(String),
+
+    #[error(transparent)]
+    IO(#[from] io::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Name of the history file in the user data directory.
+const HISTORY_FILE_NAME: &str = "history";
+
+/// Camera models by the prefix of the firmware version they write into the `FIRM` box of their
+/// movies, e.g. HD9.01.01.60.00.
+const FIRMWARE_MODELS: [(&str, &str); 10] = [
+    ("HD5", "HERO5"),
+    ("HD6", "HERO6"),
+    ("HD7", "HERO7"),
+    ("HD8", "HERO8"),
+    ("HD9", "HERO9"),
+    ("H21", "HERO10"),
+    ("H22", "HERO11"),
+    ("H23", "HERO12"),
+    ("H19", "MAX"),
+    ("FS1", "Fusion"),
+];
+
+/// What the history keeps of the chapters of a movie, looked up before merging it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub name: String,
+    pub group_id: String,
+    pub chapters: usize,
+    /// The memory card the chapters were recorded on, the directory holding their DCIM directory,
+    /// or the directory of the first chapter when they were copied off it.
+    pub card: String,
+    /// The camera model going by the firmware of the first chapter, or the firmware itself for
+    /// models not known here, "unknown" for chapters without one.
+    pub camera: String,
+    /// When the first chapter was recorded, going by its modification time.
+    pub recorded: Option<SystemTime>,
+}
+
+impl Source {
+    /// The chapters of the `group`, looked up in `input` when they have no directory of their
+    /// own.
+    pub fn of(group: &MovieGroup, input: &Path) -> Self {
+        let first = group
+            .chapters
+            .first()
+            .map(|chapter| group.chapter_path(chapter, input));
+        Source {
+            name: group.name(),
+            group_id: group.id().to_string(),
+            chapters: group.chapters.len(),
+            card: first
+                .as_deref()
+                .map_or_else(|| input.display().to_string(), card_of),
+            camera: first
+                .as_deref()
+                .and_then(firmware)
+                .map_or_else(|| "unknown".to_string(), |firmware| camera(&firmware)),
+            recorded: first
+                .and_then(|path| fs::metadata(path).ok())
+                .and_then(|metadata| metadata.modified().ok()),
+        }
+    }
+}
+
+/// The directory holding the DCIM directory of the chapter at `path`, its own directory when it
+/// isn't in one.
+fn card_of(path: &Path) -> String {
+    let dir = path.parent().unwrap_or(path);
+    dir.ancestors()
+        .find(|dir| {
+            dir.file_name()
+                .map_or(false, |name| name.eq_ignore_ascii_case("DCIM"))
+        })
+        .and_then(Path::parent)
+        .unwrap_or(dir)
+        .display()
+        .to_string()
+}
+
+/// The firmware version in the `moov/udta/FIRM` box of the movie at `path`.
+fn firmware(path: &Path) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let header = mp4::find_box(&mut file, &[b"moov", b"udta", b"FIRM"]).ok()??;
+    let payload = mp4::read_payload(&mut file, &header).ok()?;
+    let firmware = String::from_utf8_lossy(&payload);
+    let firmware = firmware.trim_matches(char::from(0)).trim();
+    (!firmware.is_empty()).then(|| firmware.to_string())
+}
+
+/// The camera model of the `firmware`, e.g. HERO9 for HD9.01.01.60.00.
+fn camera(firmware: &str) -> String {
+    let prefix = firmware.split('.').next().unwrap_or(firmware);
+    FIRMWARE_MODELS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(prefix))
+        .map_or(prefix, |(_, model)| model)
+        .to_string()
+}
+
+/// How merging a movie ended in a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum Status {
+    #[display(fmt = "merged")]
+    Merged,
+    #[display(fmt = "skipped")]
+    Skipped,
+    #[display(fmt = "deferred")]
+    Deferred,
+    #[display(fmt = "interrupted")]
+    Interrupted,
+    #[display(fmt = "failed")]
+    Failed,
+}
+
+impl Status {
+    fn parse(status: &str) -> Option<Self> {
+        Some(match status {
+            "merged" => Status::Merged,
+            "skipped" => Status::Skipped,
+            "deferred" => Status::Deferred,
+            "interrupted" => Status::Interrupted,
+            "failed" => Status::Failed,
+            _ => return None,
+        })
+    }
+}
+
+/// How merging a movie ended in a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub run_id: String,
+    pub source: Source,
+    pub status: Status,
+    /// The merged duration, zero for the movies that weren't merged.
+    pub duration: Duration,
+    /// When merging it ended.
+    pub ended: SystemTime,
+}
+
+impl Entry {
+    fn new(
+        run_id: &RunId,
+        source: &Source,
+        merged: &std::result::Result<GroupStats, merge::Error>,
+    ) -> Self {
+        let (status, duration, ended) = match merged {
+            Ok(stats) => (
+                if stats.skipped {
+                    Status::Skipped
+                } else if stats.deferred {
+                    Status::Deferred
+                } else if stats.interrupted {
+                    Status::Interrupted
+                } else {
+                    Status::Merged
+                },
+                stats.duration,
+                stats.ended,
+            ),
+            Err(_) => (Status::Failed, Duration::ZERO, None),
+        };
+
+        Entry {
+            run_id: run_id.to_string(),
+            source: source.clone(),
+            status,
+            duration,
+            ended: ended.unwrap_or_else(SystemTime::now),
+        }
+    }
+
+    /// The line of the entry, its fields separated by tabs and the card last as it's a path.
+    fn line(&self) -> String {
+        let rfc3339 = |time: SystemTime| humantime::format_rfc3339_millis(time).to_string();
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            self.status,
+            self.run_id,
+            self.source.group_id,
+            self.source.name,
+            self.source.chapters,
+            self.duration.as_millis(),
+            self.source
+                .recorded
+                .map_or_else(|| "-".to_string(), rfc3339),
+            rfc3339(self.ended),
+            self.source.camera,
+            self.source.card,
+        )
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(10, '\t');
+        let mut field = || fields.next();
+        let status = Status::parse(field()?)?;
+        let (run_id, group_id, name) = (field()?, field()?, field()?);
+        let chapters = field()?.parse().ok()?;
+        let duration = Duration::from_millis(field()?.parse().ok()?);
+        let recorded = humantime::parse_rfc3339(field()?).ok();
+        let ended = humantime::parse_rfc3339(field()?).ok()?;
+        let (camera, card) = (field()?, field()?);
+
+        Some(Entry {
+            run_id: run_id.into(),
+            source: Source {
+                name: name.into(),
+                group_id: group_id.into(),
+                chapters,
+                card: card.into(),
+                camera: camera.into(),
+                recorded,
+            },
+            status,
+            duration,
+            ended,
+        })
+    }
+}
+
+/// The movies merged by every run, so that what earlier runs merged can be reported on with
+/// `--history-report`.
+///
+/// Every line of the file is an [Entry], appended as the movies of a run end.
+#[derive(Debug, Clone)]
+pub struct History {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+impl History {
+    /// The history in the user data directory, e.g. ~/.local/share/gopro-merge, if there's one.
+    pub fn default_path() -> Option<PathBuf> {
+        let data_dir = if cfg!(windows) {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else if cfg!(target_os = "macos") {
+            env::var_os("HOME")
+                .map(|home| Path::new(&home).join("Library").join("Application Support"))
+        } else {
+            env::var_os("XDG_DATA_HOME")
+                .filter(|dir| Path::new(dir).is_absolute())
+                .map(PathBuf::from)
+                .or_else(|| {
+                    env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("share"))
+                })
+        }?;
+
+        Some(data_dir.join("gopro-merge").join(HISTORY_FILE_NAME))
+    }
+
+    /// Opens the history at `path` for appending, creating it when there's none yet.
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(History {
+            path: path.to_path_buf(),
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Records how merging the movie of the `source` ended in the run `run_id`, warning when it
+    /// can't be written as the history is only ever reported on.
+    pub fn record(
+        &self,
+        run_id: &RunId,
+        source: &Source,
+        merged: &std::result::Result<GroupStats, merge::Error>,
+    ) {
+        // a single write per line so that lines of concurrent groups don't interleave
+        let line = Entry::new(run_id, source, merged).line();
+        if let Err(err) = self.file.lock().write_all(line.as_bytes()) {
+            warn!(
+                "not recording {} in the history {}: {}",
+                source.name,
+                self.path.display(),
+                err
+            );
+        }
+    }
+
+    /// The entries of the history at `path`, none when there's no history yet. Lines that can't
+    /// be read, e.g. cut short by a run killed while writing them, are skipped.
+    pub fn read(path: &Path) -> Result<Vec<Entry>> {
+        match fs::read_to_string(path) {
+            Ok(history) => Ok(history.lines().filter_map(Entry::parse).collect()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(err) => Err(Error::Read(path.to_path_buf(), err)),
+        }
+    }
+}
+
+/// What the movies of the history are aggregated by in a report, from `--history-report`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum ReportKey {
+    /// The day the movies were recorded on, in UTC.
+    #[display(fmt = "day")]
+    Day,
+    #[display(fmt = "card")]
+    Card,
+    #[display(fmt = "camera")]
+    Camera,
+}
+
+impl FromStr for ReportKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "day" => ReportKey::Day,
+            "card" => ReportKey::Card,
+            "camera" => ReportKey::Camera,
+            _ => return Err(Error::InvalidKey(s.into())),
+        })
+    }
+}
+
+impl ReportKey {
+    fn of(self, entry: &Entry) -> String {
+        match self {
+            ReportKey::Day => {
+                let day = entry.source.recorded.unwrap_or(entry.ended);
+                humantime::format_rfc3339(day).to_string()[..10].to_string()
+            }
+            ReportKey::Card => entry.source.card.clone(),
+            ReportKey::Camera => entry.source.camera.clone(),
+        }
+    }
+}
+
+/// The movies of the history recorded on the same day, card or camera.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Row {
+    pub key: String,
+    /// Every movie once, however many runs merged it.
+    pub movies: usize,
+    pub merged: usize,
+    /// Every failure, the same movie failing in several runs counting as many.
+    pub failed: usize,
+    /// The merged duration of the movies merged, each once.
+    pub footage: Duration,
+}
+
+impl fmt::Display for Row {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} movies, {} merged, {} failures, {} of footage",
+            self.key,
+            self.movies,
+            self.merged,
+            self.failed,
+            FormattedDuration(self.footage)
+        )
+    }
+}
+
+impl Row {
+    /// A single line of JSON, for the json reporter, the key named after what it is.
+    #[cfg(feature = "json-reporter")]
+    pub fn to_json(&self, by: ReportKey) -> String {
+        json!({
+            by.to_string(): self.key,
+            "movies": self.movies,
+            "merged": self.merged,
+            "failed": self.failed,
+            "footage": FormattedDuration(self.footage).to_string(),
+            "footage_ms": self.footage.as_millis() as u64,
+        })
+        .to_string()
+    }
+}
+
+/// The `entries` of the history aggregated `by` their day, card or camera, sorted by it, from
+/// `--history-report`. Movies merged by more than one run count once, with the
+/// duration they were last merged into.
+pub fn report(entries: &[Entry], by: ReportKey) -> Vec<Row> {
+    #[derive(Default)]
+    struct Movies<'a> {
+        all: BTreeSet<&'a str>,
+        merged: BTreeMap<&'a str, Duration>,
+        failed: usize,
+    }
+
+    let mut keys = BTreeMap::<String, Movies>::new();
+    for entry in entries {
+        let movies = keys.entry(by.of(entry)).or_default();
+        let id = entry.source.group_id.as_str();
+        movies.all.insert(id);
+        match entry.status {
+            Status::Merged => {
+                movies.merged.insert(id, entry.duration);
+            }
+            Status::Failed => movies.failed += 1,
+            _ => {}
+        }
+    }
+
+    keys.into_iter()
+        .map(|(key, movies)| Row {
+            key,
+            movies: movies.all.len(),
+            merged: movies.merged.len(),
+            failed: movies.failed,
+            footage: movies.merged.values().sum(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env::temp_dir;
+
+    use crate::merge::Error as MergeError;
+
+    fn source(name: &str, card: &str, camera: &str, day: u64) -> Source {
+        Source {
+            name: name.into(),
+            group_id: format!("id-{}", name),
+            chapters: 2,
+            card: card.into(),
+            camera: camera.into(),
+            recorded: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(day * 24 * 60 * 60)),
+        }
+    }
+
+    fn merged(duration: u64) -> std::result::Result<GroupStats, merge::Error> {
+        Ok(GroupStats {
+            duration: Duration::from_secs(duration),
+            skipped: false,
+            ended: Some(SystemTime::UNIX_EPOCH),
+            ..GroupStats::skipped(PathBuf::from("/movies"), Duration::default())
+        })
+    }
+
+    #[test]
+    fn test_card_of() {
+        assert_eq!(
+            "/media/card",
+            card_of(Path::new("/media/card/DCIM/100GOPRO/GH010084.MP4"))
+        );
+        assert_eq!(
+            "/movies/trip",
+            card_of(Path::new("/movies/trip/GH010084.MP4"))
+        );
+    }
+
+    #[test]
+    fn test_camera() {
+        assert_eq!("HERO9", camera("HD9.01.01.60.00"));
+        assert_eq!("HERO11", camera("H22.01.01.10.00"));
+        assert_eq!("HX1", camera("HX1.01.01.00.00"));
+    }
+
+    #[test]
+    fn test_history() {
+        let dir = temp_dir().join("goprotest_history");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("gopro-merge").join(HISTORY_FILE_NAME);
+        assert!(History::read(&path).unwrap().is_empty());
+
+        let run_id = RunId::generate();
+        let history = History::open(&path).unwrap();
+        let ski = source("GH000084.MP4", "/media/card", "HERO9", 18_000);
+        history.record(&run_id, &ski, &merged(90));
+        history.record(
+            &run_id,
+            &source("GH000085.MP4", "/media/card", "HERO9", 18_000),
+            &Err(MergeError::GoneAfterResume(
+                "/media/card/GH020085.MP4".into(),
+            )),
+        );
+        // killed while writing
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"merged\tcut-short")
+            .unwrap();
+
+        let entries = History::read(&path).unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(run_id.to_string(), entries[0].run_id);
+        assert_eq!(ski, entries[0].source);
+        assert_eq!(Status::Merged, entries[0].status);
+        assert_eq!(Duration::from_secs(90), entries[0].duration);
+        assert_eq!(Status::Failed, entries[1].status);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_report() {
+        let run_id = RunId::generate();
+        let entry = |source: Source, merged: std::result::Result<GroupStats, merge::Error>| {
+            Entry::new(&run_id, &source, &merged)
+        };
+        let failed = || {
+            Err(MergeError::GoneAfterResume(
+                "/media/card/GH020085.MP4".into(),
+            ))
+        };
+        let entries = vec![
+            entry(
+                source("GH000084.MP4", "/media/a", "HERO9", 18_000),
+                failed(),
+            ),
+            entry(
+                source("GH000084.MP4", "/media/a", "HERO9", 18_000),
+                merged(60),
+            ),
+            // merged again by a later run
+            entry(
+                source("GH000084.MP4", "/media/a", "HERO9", 18_000),
+                merged(90),
+            ),
+            entry(
+                source("GH000085.MP4", "/media/b", "HERO11", 18_000),
+                merged(30),
+            ),
+            entry(
+                source("GH000086.MP4", "/media/b", "HERO11", 18_001),
+                failed(),
+            ),
+        ];
+
+        assert_eq!(
+            vec![
+                Row {
+                    key: "2019-04-14".into(),
+                    movies: 2,
+                    merged: 2,
+                    failed: 1,
+                    footage: Duration::from_secs(120),
+                },
+                Row {
+                    key: "2019-04-15".into(),
+                    movies: 1,
+                    merged: 0,
+                    failed: 1,
+                    footage: Duration::ZERO,
+                },
+            ],
+            report(&entries, ReportKey::Day)
+        );
+        assert_eq!(
+            vec!["/media/a", "/media/b"],
+            report(&entries, ReportKey::Card)
+                .iter()
+                .map(|row| row.key.as_str())
+                .collect::<Vec<_>>()
+        );
+        let cameras = report(&entries, ReportKey::Camera);
+        assert_eq!(
+            "HERO11: 2 movies, 1 merged, 1 failures, 00:00:30 of footage",
+            cameras[0].to_string()
+        );
+        #[cfg(feature = "json-reporter")]
+        assert_eq!(
+            "{\"camera\":\"HERO11\",\"failed\":1,\"footage\":\"00:00:30\",\"footage_ms\":30000,\"merged\":1,\"movies\":2}",
+            cameras[0].to_json(ReportKey::Camera)
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+use thiserror::Error;
+
+use crate::activity::ACTIVITY_TAG;
+use crate::duration::FormattedDuration;
+use crate::merge::{
+    self, probe_media_info, Binaries, ByteSize, MediaInfo, StreamInfo, PROVENANCE_TAG,
+};
+use crate::movie::Movie;
+use crate::mp4::{find_truncation, Truncation};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Can't inspect {0}, {1}")]
+    Read(PathBuf, io::Error),
+
+    #[error(transparent)]
+    Probe(#[from] merge::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// What a chapter or merged movie is made of, from `--inspect`, so that answering what exactly
+/// is in a file doesn't need mediainfo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inspection {
+    pub path: PathBuf,
+    pub size: u64,
+    /// The chapter the file is named as, none for merged movies and files named otherwise.
+    pub chapter: Option<Movie>,
+    pub info: MediaInfo,
+    /// Why the file looks cut short, e.g. by the battery dying while recording.
+    pub truncation: Option<Truncation>,
+}
+
+impl Inspection {
+    /// Probes the file at `path` with ffprobe and reads its boxes for signs of truncation.
+    pub fn of(path: &Path, binaries: &Binaries) -> Result<Self> {
+        let read = |err| Error::Read(path.to_path_buf(), err);
+        let size = fs::metadata(path).map_err(read)?.len();
+        let truncation = find_truncation(path).map_err(read)?;
+        let chapter = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| Movie::try_from(name).ok());
+
+        Ok(Inspection {
+            path: path.to_path_buf(),
+            size,
+            chapter,
+            info: probe_media_info(path, binaries)?,
+            truncation,
+        })
+    }
+
+    /// A single line of JSON, for the json reporter.
+    pub fn to_json(&self) -> String {
+        let info = &self.info;
+        let streams = info
+            .streams
+            .iter()
+            .map(|stream| {
+                json!({
+                    "index": stream.index,
+                    "type": stream.codec_type,
+                    "codec": stream.codec_name,
+                    "profile": stream.profile,
+                    "tag": stream.codec_tag,
+                    "width": stream.width,
+                    "height": stream.height,
+                    "fps": stream.frame_rate.map(|rate| rate.fps()),
+                    "sample_rate": stream.sample_rate,
+                    "channels": stream.channels,
+                    "bit_rate": stream.bit_rate,
+                    "duration": stream.duration.map(|duration| duration.as_secs_f64()),
+                    "handler": stream.handler_name,
+                    "role": role(stream),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "path": self.path.display().to_string(),
+            "size": self.size,
+            "chapter": self.chapter.as_ref().map(|movie| json!({
+                "movie": movie.fingerprint.to_string(),
+                "chapter": movie.chapter.to_string(),
+            })),
+            "format": info.format,
+            "duration": info.duration.map(|duration| duration.as_secs_f64()),
+            "bit_rate": info.bit_rate,
+            "creation_time": info
+                .creation_time
+                .map(|time| humantime::format_rfc3339_seconds(time).to_string()),
+            "tags": info.tags,
+            "streams": streams,
+            "telemetry": info.telemetry(),
+            "truncation": self.truncation.as_ref().map(ToString::to_string),
+        })
+        .to_string()
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let info = &self.info;
+        writeln!(f, "{} ({})", self.path.display(), ByteSize(self.size))?;
+        if let Some(movie) = &self.chapter {
+            writeln!(f, "  chapter {} of {}", movie.chapter, movie.fingerprint)?;
+        }
+
+        let mut container = vec![info.format.clone().unwrap_or_else(|| "unknown".into())];
+        container.extend(
+            info.duration
+                .map(|duration| FormattedDuration(duration).to_string()),
+        );
+        container.extend(info.bit_rate.map(bit_rate));
+        container.extend(
+            info.creation_time
+                .map(|time| format!("recorded {}", humantime::format_rfc3339_seconds(time))),
+        );
+        writeln!(f, "  {}", container.join(", "))?;
+        if let Some(firmware) = info.tags.get("firmware") {
+            writeln!(f, "  firmware {}", firmware)?;
+        }
+        if let Some(sources) = info.tags.get(PROVENANCE_TAG) {
+            writeln!(f, "  merged by gopro-merge from {}", sources)?;
+        }
+        if let Some(activity) = info.tags.get(ACTIVITY_TAG) {
+            writeln!(f, "  activity {}", activity)?;
+        }
+
+        for stream in &info.streams {
+            writeln!(f, "  {}", DisplayStream(stream))?;
+        }
+        if info.telemetry().is_empty() {
+            writeln!(f, "  no GPMF telemetry")?;
+        }
+        if let Some(truncation) = &self.truncation {
+            writeln!(f, "  ! truncated, {}", truncation)?;
+        }
+
+        Ok(())
+    }
+}
+
+struct DisplayStream<'a>(&'a StreamInfo);
+
+/// e.g. `#0 video: h264 High (avc1), 1920x1080, 59.94 fps, 45.00 Mbit/s, GoPro AVC`.
+impl fmt::Display for DisplayStream<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stream = self.0;
+        let mut codec = [&stream.codec_name, &stream.profile]
+            .iter()
+            .filter_map(|part| part.as_deref())
+            .collect::<Vec<_>>()
+            .join(" ");
+        if let Some(tag) = &stream.codec_tag {
+            codec = format!("{} ({})", codec, tag).trim_start().to_string();
+        }
+
+        let mut parts = vec![if codec.is_empty() {
+            "unknown codec".into()
+        } else {
+            codec
+        }];
+        if let (Some(width), Some(height)) = (stream.width, stream.height) {
+            parts.push(format!("{}x{}", width, height));
+        }
+        parts.extend(stream.frame_rate.map(|rate| format!("{} fps", rate)));
+        parts.extend(stream.sample_rate.map(|rate| format!("{} Hz", rate)));
+        parts.extend(
+            stream
+                .channels
+                .map(|channels| format!("{} channels", channels)),
+        );
+        parts.extend(stream.bit_rate.map(bit_rate));
+        parts.extend(stream.handler_name.clone());
+        parts.extend(role(stream).map(String::from));
+
+        write!(
+            f,
+            "#{} {}: {}",
+            stream.index,
+            stream.codec_type.as_deref().unwrap_or("unknown"),
+            parts.join(", ")
+        )
+    }
+}
+
+/// What a GoPro stream is for, told by its codec tag.
+fn role(stream: &StreamInfo) -> Option<&'static str> {
+    match stream.codec_tag.as_deref()? {
+        "gpmd" => Some("GPMF telemetry"),
+        "tmcd" => Some("timecode"),
+        _ => None,
+    }
+}
+
+/// e.g. `45.00 Mbit/s`, or `189 kbit/s` for audio.
+fn bit_rate(bits: u64) -> String {
+    if bits >= 1_000_000 {
+        format!("{:.2} Mbit/s", bits as f64 / 1_000_000.0)
+    } else {
+        format!("{} kbit/s", bits / 1000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::frame_rate::FrameRate;
+
+    fn inspection() -> Inspection {
+        Inspection {
+            path: "/movies/GH010084.MP4".into(),
+            size: 3 << 29,
+            chapter: Movie::try_from("GH010084.MP4").ok(),
+            info: MediaInfo {
+                format: Some("mov,mp4,m4a,3gp,3g2,mj2".into()),
+                duration: Some(Duration::from_secs(531)),
+                bit_rate: Some(60_190_000),
+                creation_time: Some(humantime::parse_rfc3339("2021-11-14T23:15:25Z").unwrap()),
+                tags: [("firmware".to_string(), "HD9.01.01.60.00".to_string())]
+                    .into_iter()
+                    .collect(),
+                streams: vec![
+                    StreamInfo {
+                        index: 0,
+                        codec_type: Some("video".into()),
+                        codec_name: Some("h264".into()),
+                        profile: Some("High".into()),
+                        codec_tag: Some("avc1".into()),
+                        width: Some(1920),
+                        height: Some(1080),
+                        frame_rate: Some(FrameRate {
+                            num: 60000,
+                            den: 1001,
+                        }),
+                        bit_rate: Some(45_000_000),
+                        handler_name: Some("GoPro AVC".into()),
+                        ..Default::default()
+                    },
+                    StreamInfo {
+                        index: 1,
+                        codec_type: Some("audio".into()),
+                        codec_name: Some("aac".into()),
+                        sample_rate: Some(48000),
+                        channels: Some(2),
+                        bit_rate: Some(189_000),
+                        ..Default::default()
+                    },
+                    StreamInfo {
+                        index: 2,
+                        codec_type: Some("data".into()),
+                        codec_tag: Some("gpmd".into()),
+                        handler_name: Some("GoPro MET".into()),
+                        ..Default::default()
+                    },
+                ],
+            },
+            truncation: None,
+        }
+    }
+
+    #[test]
+    fn test_inspection_display() {
+        assert_eq!(
+            "/movies/GH010084.MP4 (1.50GiB)
+  chapter 01 of GH000084.MP4
+  mov,mp4,m4a,3gp,3g2,mj2, 00:08:51, 60.19 Mbit/s, recorded 2021-11-14T23:15:25Z
+  firmware HD9.01.01.60.00
+  #0 video: h264 High (avc1), 1920x1080, 59.94 fps, 45.00 Mbit/s, GoPro AVC
+  #1 audio: aac, 48000 Hz, 2 channels, 189 kbit/s
+  #2 data: (gpmd), GoPro MET, GPMF telemetry
+",
+            inspection().to_string()
+        );
+
+        let mut merged = inspection();
+        merged.chapter = None;
+        merged.info.streams.truncate(2);
+        merged.truncation = Some(Truncation::MissingIndex);
+        let display = merged.to_string();
+        assert!(!display.contains("chapter"));
+        assert!(display.ends_with("  no GPMF telemetry\n  ! truncated, it has no moov index\n"));
+    }
+
+    #[test]
+    fn test_inspection_to_json() {
+        let json: serde_json::Value = serde_json::from_str(&inspection().to_json()).unwrap();
+        assert_eq!("GH000084.MP4", json["chapter"]["movie"]);
+        assert_eq!("HD9.01.01.60.00", json["tags"]["firmware"]);
+        assert_eq!(json!([2]), json["telemetry"]);
+        assert_eq!("GPMF telemetry", json["streams"][2]["role"]);
+        assert_eq!(1920, json["streams"][0]["width"]);
+        assert!(json["truncation"].is_null());
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use derive_more::Display;
+use parking_lot::Mutex;
+use thiserror::Error;
+
+use crate::group::MovieGroup;
+use crate::merge::ErrorCode;
+use crate::progress::{calculate_percentage, GroupError, GroupStats};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum Error {
+    #[error("No movie {0} in this run")]
+    UnknownJob(String),
+
+    #[error("{0} is already {1}")]
+    Finished(String, JobState),
+}
+
+/// Where a group of the run is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum JobState {
+    #[display(fmt = "queued")]
+    Queued,
+    #[display(fmt = "running")]
+    Running,
+    #[display(fmt = "completed")]
+    Completed,
+    /// The output already existed, or an earlier run completed it.
+    #[display(fmt = "skipped")]
+    Skipped,
+    /// Left for a follow-up run by `--max-runtime`.
+    #[display(fmt = "deferred")]
+    Deferred,
+    #[display(fmt = "interrupted")]
+    Interrupted,
+    #[display(fmt = "cancelled")]
+    Cancelled,
+    #[display(fmt = "failed")]
+    Failed,
+}
+
+impl JobState {
+    pub fn finished(&self) -> bool {
+        !matches!(self, JobState::Queued | JobState::Running)
+    }
+}
+
+/// A group of the run as tracked for `--serve`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    pub name: String,
+    pub state: JobState,
+    /// Summed durations of the chapters, zero until they're probed.
+    pub len: Duration,
+    /// How much of the merged movie is written.
+    pub progress: Duration,
+    /// The merged movie, once finished.
+    pub output: Option<PathBuf>,
+    /// Why the group failed to merge.
+    pub error: Option<String>,
+    pub error_code: Option<ErrorCode>,
+    /// Cancelling was asked for, the merger stops the group at its next check.
+    pub cancelled: bool,
+}
+
+impl Job {
+    /// Returns `None` when the length is unknown, like the reporters do.
+    pub fn percentage(&self) -> Option<u64> {
+        match self.state {
+            JobState::Completed => Some(100),
+            _ => calculate_percentage(self.len, self.progress),
+        }
+    }
+}
+
+/// The groups of the run with their state, shared by the mergers updating it and the status
+/// server of `--serve` reading it and cancelling groups.
+#[derive(Debug, Clone, Default)]
+pub struct Jobs(Arc<Mutex<BTreeMap<String, Job>>>);
+
+impl Jobs {
+    /// Adds the `group` about to be merged, replacing an earlier job of the same name, e.g. of a
+    /// group --watch merges again.
+    pub fn queue(&self, group: &MovieGroup) {
+        let name = group.name();
+        self.0.lock().insert(
+            name.clone(),
+            Job {
+                name,
+                state: JobState::Queued,
+                len: Duration::default(),
+                progress: Duration::default(),
+                output: None,
+                error: None,
+                error_code: None,
+                cancelled: false,
+            },
+        );
+    }
+
+    pub fn start(&self, name: &str) {
+        self.update_job(name, |job| job.state = JobState::Running);
+    }
+
+    pub fn set_len(&self, name: &str, len: Duration) {
+        self.update_job(name, |job| job.len = len);
+    }
+
+    pub fn update(&self, name: &str, progress: Duration) {
+        self.update_job(name, |job| job.progress = progress);
+    }
+
+    pub fn finish(&self, name: &str, result: &Result<GroupStats, GroupError>) {
+        self.update_job(name, |job| match result {
+            Ok(stats) => {
+                job.state = if stats.skipped {
+                    JobState::Skipped
+                } else if stats.deferred {
+                    JobState::Deferred
+                } else if stats.interrupted && job.cancelled {
+                    JobState::Cancelled
+                } else if stats.interrupted {
+                    JobState::Interrupted
+                } else {
+                    JobState::Completed
+                };
+                job.output = Some(stats.output.clone());
+            }
+            Err(err) => {
+                job.state = JobState::Failed;
+                job.error = Some(err.message.clone());
+                job.error_code = Some(err.code);
+            }
+        });
+    }
+
+    /// Asks for the group `name` to stop, before it starts or while merging, its partial output
+    /// being removed like on Ctrl-C. Returns the job as it was cancelled.
+    pub fn cancel(&self, name: &str) -> Result<Job, Error> {
+        let mut jobs = self.0.lock();
+        let job = jobs
+            .get_mut(name)
+            .ok_or_else(|| Error::UnknownJob(name.into()))?;
+        if job.state.finished() {
+            return Err(Error::Finished(name.into(), job.state));
+        }
+
+        job.cancelled = true;
+        Ok(job.clone())
+    }
+
+    pub fn is_cancelled(&self, name: &str) -> bool {
+        self.0.lock().get(name).map_or(false, |job| job.cancelled)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Job> {
+        self.0.lock().get(name).cloned()
+    }
+
+    /// Every job, sorted by name.
+    pub fn list(&self) -> Vec<Job> {
+        self.0.lock().values().cloned().collect()
+    }
+
+    fn update_job(&self, name: &str, update: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.0.lock().get_mut(name) {
+            update(job);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::group::group_file_names;
+
+    #[test]
+    fn test_jobs() {
+        let jobs = Jobs::default();
+        for group in group_file_names(vec!["GH010084.mp4", "GH010085.mp4", "GH010086.mp4"]) {
+            jobs.queue(&group);
+        }
+        assert_eq!(
+            vec![JobState::Queued; 3],
+            jobs.list().iter().map(|job| job.state).collect::<Vec<_>>()
+        );
+
+        jobs.start("GH000084.mp4");
+        jobs.set_len("GH000084.mp4", Duration::from_secs(10));
+        jobs.update("GH000084.mp4", Duration::from_secs(4));
+        let job = jobs.get("GH000084.mp4").unwrap();
+        assert_eq!((JobState::Running, Some(40)), (job.state, job.percentage()));
+        assert_eq!(None, jobs.get("GH000085.mp4").unwrap().percentage());
+
+        jobs.finish(
+            "GH000084.mp4",
+            &Ok(GroupStats::skipped(
+                "/movies/GH000084.mp4".into(),
+                Duration::default(),
+            )),
+        );
+        assert_eq!(JobState::Skipped, jobs.get("GH000084.mp4").unwrap().state);
+        assert_eq!(
+            Err(Error::Finished("GH000084.mp4".into(), JobState::Skipped)),
+            jobs.cancel("GH000084.mp4")
+        );
+        assert_eq!(
+            Err(Error::UnknownJob("GH000099.mp4".into())),
+            jobs.cancel("GH000099.mp4")
+        );
+
+        assert!(!jobs.is_cancelled("GH000085.mp4"));
+        let cancelled = jobs.cancel("GH000085.mp4").unwrap();
+        assert!(cancelled.cancelled);
+        assert!(jobs.is_cancelled("GH000085.mp4"));
+        jobs.finish(
+            "GH000085.mp4",
+            &Ok(GroupStats::interrupted(
+                "/movies/GH000085.mp4".into(),
+                Duration::default(),
+            )),
+        );
+        assert_eq!(JobState::Cancelled, jobs.get("GH000085.mp4").unwrap().state);
+
+        jobs.finish(
+            "GH000086.mp4",
+            &Err(GroupError {
+                code: ErrorCode::ConvertFailed,
+                message: "ffmpeg failed".into(),
+                log: None,
+                started: None,
+                ended: None,
+            }),
+        );
+        let job = jobs.get("GH000086.mp4").unwrap();
+        assert_eq!(
+            (
+                JobState::Failed,
+                Some("ffmpeg failed".to_string()),
+                Some(ErrorCode::ConvertFailed)
+            ),
+            (job.state, job.error, job.error_code)
+        );
+    }
+}
@@ -0,0 +1,82 @@
+//! Merge GoPro chaptered movies into single files.
+//!
+//! The building blocks used by the `gopro-merge` binary, for embedding the merging into other
+//! tools without shelling out to the CLI:
+//!
+//! ```no_run
+//! # #[cfg(feature = "json-reporter")]
+//! # fn main() {
+//! use gopro_merge::{group_movies, JsonProgressReporter, MergeOptions, Processor, Reporter};
+//!
+//! let input = std::path::PathBuf::from("/media/DCIM/100GOPRO");
+//! let movies = group_movies(&input).unwrap();
+//! let options = MergeOptions::default();
+//! let reporter = JsonProgressReporter::new(options.run_id.clone());
+//! Processor::new(Box::new(reporter), input.clone(), input, movies, options)
+//!     .process()
+//!     .unwrap();
+//! # }
+//! # #[cfg(not(feature = "json-reporter"))]
+//! # fn main() {}
+//! ```
+//!
+//! Reporters and mergers are picked at runtime, the processor taking any `Box<dyn Reporter>` and
+//! merging with ffmpeg unless given another [merge::NewMerger] with `with_merger`.
+//!
+//! The reporters are behind the `progressbar` and `json-reporter` features, reading the GPS
+//! telemetry behind `telemetry` and the status server behind `server`, all of them on by default.
+//! Leaving out `progressbar` drops the terminal dependencies for embedded and NAS builds.
+//!
+//! Movies of cameras naming their chapters otherwise than GoPro are grouped by implementing
+//! [scheme::NamingScheme] and registering it in the [group::ScanOptions] schemes.
+
+pub mod activity;
+pub mod audit;
+pub mod chapters;
+pub mod confirm;
+pub mod duration;
+pub mod encoding;
+pub mod export;
+pub mod filter;
+pub mod frame_rate;
+pub mod group;
+pub mod history;
+pub mod identifier;
+pub mod inspect;
+pub mod jobs;
+pub mod locale;
+pub mod manifest;
+pub mod merge;
+pub mod models;
+pub mod movie;
+pub mod mp4;
+pub mod naming;
+pub mod paths;
+pub mod plan;
+pub mod processor;
+pub mod progress;
+pub mod proxy;
+pub mod report;
+pub mod resume;
+pub mod run;
+pub mod schedule;
+pub mod scheme;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod system;
+pub mod telemetry;
+pub mod timestamp;
+pub mod watch;
+pub mod wizard;
+pub mod workspace;
+
+pub use group::{group_movies, MovieGroup, MovieGroups};
+pub use merge::{
+    new_merger, Backend, FFmpegMerger, MP4BoxMerger, MergeOptions, Merger, NativeMerger, NewMerger,
+};
+pub use processor::Processor;
+#[cfg(feature = "progressbar")]
+pub use progress::ConsoleProgressBarReporter;
+#[cfg(feature = "json-reporter")]
+pub use progress::JsonProgressReporter;
+pub use progress::{GroupError, GroupStats, Progress, Reporter};
@@ -0,0 +1,482 @@
+use std::env;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use derive_more::Display;
+use log::*;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid --locale {0}, one of {1}")]
+    InvalidLocale(String, String),
+
+    #[error("Invalid date format {0}, {1}")]
+    InvalidDateFormat(String, String),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The language month and day names are rendered in, from `--locale` or the `LC_ALL`, `LC_TIME`
+/// and `LANG` environment variables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum Locale {
+    #[display(fmt = "en")]
+    English,
+    #[display(fmt = "bg")]
+    Bulgarian,
+    #[display(fmt = "de")]
+    German,
+    #[display(fmt = "es")]
+    Spanish,
+    #[display(fmt = "fr")]
+    French,
+    #[display(fmt = "it")]
+    Italian,
+    #[display(fmt = "nl")]
+    Dutch,
+    #[display(fmt = "pt")]
+    Portuguese,
+}
+
+const LOCALES: &[Locale] = &[
+    Locale::English,
+    Locale::Bulgarian,
+    Locale::German,
+    Locale::Spanish,
+    Locale::French,
+    Locale::Italian,
+    Locale::Dutch,
+    Locale::Portuguese,
+];
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale::English
+    }
+}
+
+impl FromStr for Locale {
+    type Err = Error;
+
+    /// Parses a language, e.g. `de`, or a POSIX locale, e.g. `de_DE.UTF-8`.
+    fn from_str(s: &str) -> Result<Self> {
+        let language = s
+            .split(|c| c == '_' || c == '-' || c == '.' || c == '@')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        if language == "c" || language == "posix" {
+            return Ok(Locale::English);
+        }
+
+        LOCALES
+            .iter()
+            .find(|locale| locale.to_string() == language)
+            .copied()
+            .ok_or_else(|| {
+                Error::InvalidLocale(
+                    s.into(),
+                    LOCALES
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(" | "),
+                )
+            })
+    }
+}
+
+impl Locale {
+    /// The locale of the environment, English when it's unset or of a language without
+    /// translations.
+    pub fn from_env() -> Self {
+        let locale = ["LC_ALL", "LC_TIME", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty());
+
+        match locale {
+            Some(locale) => locale.parse().unwrap_or_else(|err| {
+                debug!("rendering dates in English: {}", err);
+                Locale::English
+            }),
+            None => Locale::English,
+        }
+    }
+
+    fn months(self) -> [&'static str; 12] {
+        match self {
+            Locale::English => [
+                "January",
+                "February",
+                "March",
+                "April",
+                "May",
+                "June",
+                "July",
+                "August",
+                "September",
+                "October",
+                "November",
+                "December",
+            ],
+            Locale::Bulgarian => [
+                "януари",
+                "февруари",
+                "март",
+                "април",
+                "май",
+                "юни",
+                "юли",
+                "август",
+                "септември",
+                "октомври",
+                "ноември",
+                "декември",
+            ],
+            Locale::German => [
+                "Januar",
+                "Februar",
+                "März",
+                "April",
+                "Mai",
+                "Juni",
+                "Juli",
+                "August",
+                "September",
+                "Oktober",
+                "November",
+                "Dezember",
+            ],
+            Locale::Spanish => [
+                "enero",
+                "febrero",
+                "marzo",
+                "abril",
+                "mayo",
+                "junio",
+                "julio",
+                "agosto",
+                "septiembre",
+                "octubre",
+                "noviembre",
+                "diciembre",
+            ],
+            Locale::French => [
+                "janvier",
+                "février",
+                "mars",
+                "avril",
+                "mai",
+                "juin",
+                "juillet",
+                "août",
+                "septembre",
+                "octobre",
+                "novembre",
+                "décembre",
+            ],
+            Locale::Italian => [
+                "gennaio",
+                "febbraio",
+                "marzo",
+                "aprile",
+                "maggio",
+                "giugno",
+                "luglio",
+                "agosto",
+                "settembre",
+                "ottobre",
+                "novembre",
+                "dicembre",
+            ],
+            Locale::Dutch => [
+                "januari",
+                "februari",
+                "maart",
+                "april",
+                "mei",
+                "juni",
+                "juli",
+                "augustus",
+                "september",
+                "oktober",
+                "november",
+                "december",
+            ],
+            Locale::Portuguese => [
+                "janeiro",
+                "fevereiro",
+                "março",
+                "abril",
+                "maio",
+                "junho",
+                "julho",
+                "agosto",
+                "setembro",
+                "outubro",
+                "novembro",
+                "dezembro",
+            ],
+        }
+    }
+
+    fn short_months(self) -> [&'static str; 12] {
+        match self {
+            Locale::English => [
+                "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+            ],
+            Locale::Bulgarian => [
+                "яну", "фев", "мар", "апр", "май", "юни", "юли", "авг", "сеп", "окт", "ное", "дек",
+            ],
+            Locale::German => [
+                "Jan", "Feb", "Mär", "Apr", "Mai", "Jun", "Jul", "Aug", "Sep", "Okt", "Nov", "Dez",
+            ],
+            Locale::Spanish => [
+                "ene", "feb", "mar", "abr", "may", "jun", "jul", "ago", "sep", "oct", "nov", "dic",
+            ],
+            Locale::French => [
+                "janv.", "févr.", "mars", "avr.", "mai", "juin", "juil.", "août", "sept.", "oct.",
+                "nov.", "déc.",
+            ],
+            Locale::Italian => [
+                "gen", "feb", "mar", "apr", "mag", "giu", "lug", "ago", "set", "ott", "nov", "dic",
+            ],
+            Locale::Dutch => [
+                "jan", "feb", "mrt", "apr", "mei", "jun", "jul", "aug", "sep", "okt", "nov", "dec",
+            ],
+            Locale::Portuguese => [
+                "jan", "fev", "mar", "abr", "mai", "jun", "jul", "ago", "set", "out", "nov", "dez",
+            ],
+        }
+    }
+
+    /// Starting on Sunday.
+    fn weekdays(self) -> [&'static str; 7] {
+        match self {
+            Locale::English => [
+                "Sunday",
+                "Monday",
+                "Tuesday",
+                "Wednesday",
+                "Thursday",
+                "Friday",
+                "Saturday",
+            ],
+            Locale::Bulgarian => [
+                "неделя",
+                "понеделник",
+                "вторник",
+                "сряда",
+                "четвъртък",
+                "петък",
+                "събота",
+            ],
+            Locale::German => [
+                "Sonntag",
+                "Montag",
+                "Dienstag",
+                "Mittwoch",
+                "Donnerstag",
+                "Freitag",
+                "Samstag",
+            ],
+            Locale::Spanish => [
+                "domingo",
+                "lunes",
+                "martes",
+                "miércoles",
+                "jueves",
+                "viernes",
+                "sábado",
+            ],
+            Locale::French => [
+                "dimanche", "lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi",
+            ],
+            Locale::Italian => [
+                "domenica",
+                "lunedì",
+                "martedì",
+                "mercoledì",
+                "giovedì",
+                "venerdì",
+                "sabato",
+            ],
+            Locale::Dutch => [
+                "zondag",
+                "maandag",
+                "dinsdag",
+                "woensdag",
+                "donderdag",
+                "vrijdag",
+                "zaterdag",
+            ],
+            Locale::Portuguese => [
+                "domingo",
+                "segunda-feira",
+                "terça-feira",
+                "quarta-feira",
+                "quinta-feira",
+                "sexta-feira",
+                "sábado",
+            ],
+        }
+    }
+
+    fn short_weekdays(self) -> [&'static str; 7] {
+        match self {
+            Locale::English => ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"],
+            Locale::Bulgarian => ["нд", "пн", "вт", "ср", "чт", "пт", "сб"],
+            Locale::German => ["So", "Mo", "Di", "Mi", "Do", "Fr", "Sa"],
+            Locale::Spanish => ["dom", "lun", "mar", "mié", "jue", "vie", "sáb"],
+            Locale::French => ["dim.", "lun.", "mar.", "mer.", "jeu.", "ven.", "sam."],
+            Locale::Italian => ["dom", "lun", "mar", "mer", "gio", "ven", "sab"],
+            Locale::Dutch => ["zo", "ma", "di", "wo", "do", "vr", "za"],
+            Locale::Portuguese => ["dom", "seg", "ter", "qua", "qui", "sex", "sáb"],
+        }
+    }
+}
+
+/// The strftime-like specifiers of a [DateFormat].
+const SPECIFIERS: &str = "YymBbdeAaHMS%";
+
+/// A date rendered with strftime-like specifiers, e.g. `%d %B %Y` for 14 November 2021, the
+/// names being in the language of a [Locale].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DateFormat {
+    format: String,
+}
+
+impl FromStr for DateFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |reason: String| Error::InvalidDateFormat(s.into(), reason);
+        if s.is_empty() {
+            return Err(invalid("it's empty".into()));
+        }
+
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                continue;
+            }
+            match chars.next() {
+                Some(specifier) if SPECIFIERS.contains(specifier) => {}
+                specifier => {
+                    return Err(invalid(format!(
+                        "unknown specifier %{}, one of {}",
+                        specifier.map(String::from).unwrap_or_default(),
+                        SPECIFIERS
+                            .chars()
+                            .map(|specifier| format!("%{}", specifier))
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    )))
+                }
+            }
+        }
+
+        Ok(DateFormat { format: s.into() })
+    }
+}
+
+impl DateFormat {
+    /// The `time`, a wall clock written as if it were UTC, in the language of the `locale`.
+    pub fn render(&self, time: SystemTime, locale: Locale) -> String {
+        // e.g. 2021-11-14T23:15:25Z
+        let timestamp = humantime::format_rfc3339_seconds(time).to_string();
+        let month = timestamp[5..7].parse::<usize>().unwrap_or(1);
+        let days = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            / 86400;
+        // 1970-01-01 was a Thursday
+        let weekday = ((days + 4) % 7) as usize;
+
+        let mut rendered = String::new();
+        let mut chars = self.format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                rendered.push(c);
+                continue;
+            }
+            let value = match chars.next() {
+                Some('Y') => &timestamp[..4],
+                Some('y') => &timestamp[2..4],
+                Some('m') => &timestamp[5..7],
+                Some('B') => locale.months()[month - 1],
+                Some('b') => locale.short_months()[month - 1],
+                Some('d') => &timestamp[8..10],
+                Some('e') => timestamp[8..10].trim_start_matches('0'),
+                Some('A') => locale.weekdays()[weekday],
+                Some('a') => locale.short_weekdays()[weekday],
+                Some('H') => &timestamp[11..13],
+                Some('M') => &timestamp[14..16],
+                Some('S') => &timestamp[17..19],
+                // the specifiers are validated when parsed
+                _ => "%",
+            };
+            rendered.push_str(value);
+        }
+
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locale_from_str() {
+        assert_eq!(Locale::German, "de".parse().unwrap());
+        assert_eq!(Locale::German, "de_DE.UTF-8".parse().unwrap());
+        assert_eq!(Locale::Portuguese, "pt-BR".parse().unwrap());
+        assert_eq!(Locale::Bulgarian, "BG_bg".parse().unwrap());
+        assert_eq!(Locale::English, "C.UTF-8".parse().unwrap());
+        assert_eq!(Locale::English, "POSIX".parse().unwrap());
+        assert!("ja_JP.UTF-8".parse::<Locale>().is_err());
+        assert!("".parse::<Locale>().is_err());
+    }
+
+    #[test]
+    fn test_date_format_render() {
+        let time = humantime::parse_rfc3339("2021-11-04T23:15:25Z").unwrap();
+        let render = |format: &str, locale: Locale| {
+            format.parse::<DateFormat>().unwrap().render(time, locale)
+        };
+
+        assert_eq!(
+            "Thursday 04 November 2021",
+            render("%A %d %B %Y", Locale::English)
+        );
+        assert_eq!(
+            "Donnerstag, 4. November 2021",
+            render("%A, %e. %B %Y", Locale::German)
+        );
+        assert_eq!("jeu. 4 nov. 21", render("%a %e %b %y", Locale::French));
+        assert_eq!("4 ноември 2021", render("%e %B %Y", Locale::Bulgarian));
+        assert_eq!(
+            "2021-11-04 23:15:25 100%",
+            render("%Y-%m-%d %H:%M:%S 100%%", Locale::Spanish)
+        );
+        // the epoch
+        assert_eq!(
+            "jueves",
+            "%A".parse::<DateFormat>()
+                .unwrap()
+                .render(UNIX_EPOCH, Locale::Spanish)
+        );
+
+        for invalid in &["", "%", "%d %Q", "100%"] {
+            assert!(
+                invalid.parse::<DateFormat>().is_err(),
+                "{} was parsed",
+                invalid
+            );
+        }
+    }
+}
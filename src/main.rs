@@ -1,24 +1,65 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+#[cfg(feature = "json-reporter")]
+use std::io;
 use std::path::PathBuf;
+use std::process;
+use std::sync::Once;
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{env, path::Path, str::FromStr};
 
 use log::*;
 use structopt::StructOpt;
 
-use crate::group::group_movies;
-use crate::merge::FFmpegMerger;
-use crate::processor::Processor;
-use crate::progress::{ConsoleProgressBarReporter, JsonProgressReporter, Reporter};
-use derive_more::Display;
-
-mod encoding;
-mod group;
-mod identifier;
-mod merge;
-mod movie;
-mod processor;
-mod progress;
+use gopro_merge::activity::{self, Activities, ActivityMode, Region, RegionClassifier};
+use gopro_merge::audit::AuditHash;
+use gopro_merge::confirm::{confirm_overwrites, ConfirmPolicy};
+#[cfg(feature = "progressbar")]
+use gopro_merge::duration::FormattedDuration;
+use gopro_merge::export::ExportFormat;
+use gopro_merge::filter::{FileRange, GroupFilter};
+use gopro_merge::group::{scan_movies_in, FusionMode, MovieGroups, ScanOptions};
+use gopro_merge::history::{self, History, ReportKey};
+use gopro_merge::inspect::Inspection;
+use gopro_merge::jobs::Jobs;
+use gopro_merge::locale::Locale;
+use gopro_merge::manifest;
+use gopro_merge::merge::{
+    check_path_length, check_space, check_workspace_space, check_writable, ffprobe_available,
+    AudioFormat, Backend, Binaries, ByteSize, CommandRunner, ConcatMethod, Container, LogDir,
+    MergeOptions, OnExists, OutputContainer, ProbeCache, Retry, StreamMapping, VerifyMode,
+};
+use gopro_merge::models::{self, find_model};
+use gopro_merge::naming::{NameTemplate, OutputNames, TitleTemplate};
+use gopro_merge::paths::{PathMap, PathMapping};
+#[cfg(feature = "json-reporter")]
+use gopro_merge::plan;
+use gopro_merge::plan::{Plan, PlanFormat};
+use gopro_merge::processor::{self, Processor};
+#[cfg(feature = "json-reporter")]
+use gopro_merge::progress::JsonProgressReporter;
+use gopro_merge::progress::Reporter;
+#[cfg(feature = "progressbar")]
+use gopro_merge::progress::{ConsoleProgressBarReporter, GroupStats};
+use gopro_merge::proxy::ProxyMode;
+use gopro_merge::report::{self, RunReport};
+use gopro_merge::resume::ResumeState;
+use gopro_merge::run::{self, Deadline, Interrupt, RunId};
+use gopro_merge::schedule::{Priorities, Priority, Schedule};
+#[cfg(feature = "server")]
+use gopro_merge::server;
+use gopro_merge::system::{RealFs, SystemClock};
+use gopro_merge::timestamp::TimeZone;
+use gopro_merge::watch::{Watcher, POLL_INTERVAL};
+use gopro_merge::wizard;
+use gopro_merge::workspace::Workspace;
 
 type Error = Box<dyn std::error::Error + 'static>;
+
+/// Exit code of runs interrupted with Ctrl-C, 128 + SIGINT as shells report it.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
 type Result<T> = std::result::Result<T, Error>;
 
 #[derive(StructOpt, Debug, Default)]
@@ -32,29 +73,334 @@ struct Opt {
     #[structopt(parse(from_os_str))]
     output: Option<PathBuf>,
 
-    /// The amount of parallel movies to be merged. [default: amount of cores]
-    #[structopt(short, long)]
-    parallel: Option<usize>,
+    /// Read movies from several directories instead of <input>, e.g. two memory cards, merging the chapters found across them and taking a chapter found in more than one from the first. The only argument is then the output directory. Can be repeated.
+    #[structopt(short = "i", long = "input", number_of_values = 1, parse(from_os_str))]
+    inputs: Vec<PathBuf>,
+
+    /// Remap a base path written on another platform onto a local one, e.g. C:\footage=/mnt/footage when running a Windows invocation under WSL. Applies to every path argument and the chapters listed by --manifest, can be repeated.
+    #[structopt(long, number_of_values = 1)]
+    map_path: Vec<PathMapping>,
+
+    /// Sort the merged movies by the region most of their GPS telemetry is in, as "<name>=<latitude>,<longitude>,<latitude>,<longitude>" with the corners of its bounding box, e.g. "bike park=46.01,7.74,46.03,7.77". Can be repeated, the first region wins ties.
+    #[structopt(long, number_of_values = 1)]
+    region: Vec<Region>,
+
+    /// A file with a --region per line, empty lines and lines starting with # are skipped.
+    #[structopt(long, parse(from_os_str))]
+    regions_file: Option<PathBuf>,
+
+    /// Where the region of a merged movie ends up, one of "folder" (a subfolder of the output named after it) | "tag" (a gopro_merge_activity metadata tag).
+    #[structopt(long, default_value = "folder")]
+    activity: ActivityMode,
+
+    /// Name the merged movies after a template instead of their chapters, e.g. "{date}_{file}_{encoding}_merged.{ext}". Tokens are {name} (GH000084) | {encoding} (GH) | {file} (0084) | {ext} (MP4) | {date} (2021-11-14) | {time} (231525) | {chapters} (the count), the date and time being the ones the camera clock showed when recording the first chapter, from its modification time. The date can be formatted like {date:%d %B %Y} with %Y | %y | %m | %B (month name) | %b (short month name) | %d | %e (day without padding) | %A (weekday name) | %a (short weekday name) | %H | %M | %S | %%.
+    #[structopt(long)]
+    name_template: Option<NameTemplate>,
+
+    /// Embed a title into the merged movies for media centers to show, rendered from the tokens of --name-template, e.g. "GoPro {date:%A, %e %B %Y}". The title of the first chapter is kept otherwise.
+    #[structopt(long)]
+    title_template: Option<TitleTemplate>,
+
+    /// The language of the month and weekday names of --name-template and --title-template, one of "en" | "bg" | "de" | "es" | "fr" | "it" | "nl" | "pt", or a locale like de_DE.UTF-8. [default: from LC_ALL, LC_TIME or LANG]
+    #[structopt(long)]
+    locale: Option<Locale>,
+
+    /// Merge only the movies whose merged or chapter file names match the glob pattern, e.g. GH0112*, can be repeated.
+    #[structopt(long, number_of_values = 1)]
+    only: Vec<String>,
+
+    /// Merge only the movies with these file numbers, e.g. 1234,1250-1260.
+    #[structopt(long, use_delimiter = true)]
+    file: Vec<FileRange>,
+
+    /// Look for chapters in subdirectories too, e.g. DCIM/100GOPRO, DCIM/101GOPRO.
+    #[structopt(long)]
+    recursive: bool,
+
+    /// How many levels of subdirectories to look into, implies --recursive. [default: unlimited]
+    #[structopt(long)]
+    max_depth: Option<usize>,
+
+    /// Merge the chapters listed in this file, "-" for stdin, instead of scanning directories for them, so the tool copying the footage can tell exactly which files make up each movie. Either chapter paths one per line, grouped into movies by their names, or a JSON array of chapter paths and of {"chapters": [...]} objects with the chapters of a single movie. Relative paths are taken from the current directory, the only argument is then the output directory.
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["inputs", "watch", "recursive", "max-depth"])]
+    manifest: Option<PathBuf>,
+
+    /// What to do with the paired front and back lens movies of a GoPro Fusion, one of "separate" (merge each lens into its own movie) | "refuse".
+    #[structopt(long, default_value = "separate")]
+    fusion: FusionMode,
+
+    /// What to do with the low resolution proxies cameras record next to the movies, one of "warn" (skip the .lrv and .thm files, warn about chapters whose resolution or bit rate gives them away as proxies renamed to .mp4) | "auto" (leave those chapters out of their movies too, listing them in the report) | "off" (merge everything).
+    #[structopt(long, default_value = "warn")]
+    exclude_proxies: ProxyMode,
+
+    /// The amount of parallel movies to be merged, formerly --parallel. [default: amount of cores]
+    #[structopt(short = "p", long, alias = "parallel")]
+    merge_parallel: Option<usize>,
+
+    /// The amount of chapters to probe with ffprobe in parallel ahead of merging, those of a movie too, e.g. fewer for network storage that's slow to seek. [default: amount of cores]
+    #[structopt(long)]
+    probe_parallel: Option<usize>,
 
     /// The reporter to be used for progress one of "json" | "progressbar".
-    #[structopt(default_value = "progressbar", short, long)]
+    #[structopt(default_value, short, long)]
     reporter: OptReporter,
+
+    /// Print only a summary of the merged movies once they are done and the errors, without the progress bars or the progress events of the json reporter, e.g. for cron jobs.
+    #[structopt(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Log what is being done, -v for the warnings and info, -vv for the debug logs and -vvv for everything. RUST_LOG takes precedence when set. The json reporter adds an event for every ffmpeg, ffprobe and MP4Box process of the movies spawning and exiting.
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
+
+    /// Export a sidecar next to every merged movie based on the chapter boundaries, one of "fcpxml" (editor project with chapter and HiLight markers), "srt" (chapter subtitles), "vtt" (WebVTT chapters for the chapter menus of web players like Jellyfin).
+    #[structopt(short, long)]
+    export: Vec<ExportFormat>,
+
+    /// Mux a subtitles track showing the source chapter name and capture time at the start of each chapter.
+    #[structopt(long)]
+    subtitles: bool,
+
+    /// Mux a chapter marker at the start of every source chapter, shown by players as Chapter 1, Chapter 2 and so on.
+    #[structopt(long)]
+    chapter_markers: bool,
+
+    /// Re-encode the video of movies with variable frame rate chapters, which some editors drift the audio of, to their nominal constant frame rate. Much slower than merging and not lossless, such movies are only warned about otherwise.
+    #[structopt(long)]
+    cfr: bool,
+
+    /// Dissolve the video and crossfade the audio of every chapter into the next over this long, e.g. "250ms", masking the pops some cameras record at the chapter splits. Re-encodes the movies, much slower than merging and not lossless, and shortens them by the fades. Needs --drop-data-streams as the telemetry and timecode can't be faded.
+    #[structopt(long, parse(try_from_str = humantime::parse_duration))]
+    crossfade: Option<Duration>,
+
+    /// Drop the data streams (GoPro timecode and telemetry) which some hardware players choke on.
+    #[structopt(long)]
+    drop_data_streams: bool,
+
+    /// Keep the timecode track when dropping the data streams.
+    #[structopt(long)]
+    keep_timecode: bool,
+
+    /// Copy every stream of the chapters, e.g. the fdsc track without a codec, instead of only the video, audio, telemetry and timecode.
+    #[structopt(long)]
+    copy_unknown_streams: bool,
+
+    /// Join the chapters with ffmpeg's concat protocol instead of writing a list of them to the workspace, only works for MPEG-TS chapters.
+    #[structopt(long)]
+    no_temp_concat: bool,
+
+    /// The tool joining the chapters, one of "ffmpeg" | "mp4box" | "native". MP4Box -cat of GPAC writes no list of the chapters to the workspace and some find it better at keeping the GPMF telemetry, but it only joins them, without --subtitles, --chapter-markers, --verify or the other options needing ffmpeg. native joins MP4 chapters of the same codecs without any tool by appending their samples and rewriting their index, falling back to ffmpeg for groups it can't join or which need the options only ffmpeg does.
+    #[structopt(long, default_value = "ffmpeg")]
+    backend: Backend,
+
+    /// Probe every chapter with ffprobe again instead of reusing what earlier runs probed of it. The probes are cached in the user cache directory, e.g. ~/.cache/gopro-merge, by the path, size and modification time of the chapter.
+    #[structopt(long)]
+    no_probe_cache: bool,
+
+    /// Print the output files, their chapters and durations without merging anything, as a JSON document with the json reporter. The same chapters and options always print the same plan, so plans can be diffed between runs.
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// How --dry-run prints the plan with the json reporter, one of "json" (a single document once all the movies are planned) | "jsonl" (JSON Lines, a header record, a record per movie as soon as it's planned and a footer record, for archives too large to hold the plan of at once).
+    #[structopt(long, default_value = "json")]
+    plan_format: PlanFormat,
+
+    /// Hash every source chapter for the audit trail of the merged movies, reported with --dry-run and the json reporter, one of "sha256".
+    #[structopt(long)]
+    audit_hash: Option<AuditHash>,
+
+    /// Embed the list of source chapters, with their --audit-hash if any, into the merged movies as a metadata tag, or an attachment for mkv.
+    #[structopt(long)]
+    embed_sources: bool,
+
+    /// Write the merged movies in this container rather than the one of their chapters, one of "mp4" | "mkv" | "mov". Matroska takes chapters whose streams differ in ways the MP4 muxer rejects, it can't hold the telemetry though, pass --drop-data-streams with it.
+    #[structopt(long)]
+    container: Option<OutputContainer>,
+
+    /// What to do with merged movies that already exist, one of "skip" | "overwrite" | "rename" | "fail".
+    #[structopt(long, default_value = "overwrite")]
+    on_exists: OnExists,
+
+    /// Record the completed movies in a state file in the output directory, rerunning with --resume after an interruption skips them and removes the partial outputs of the unfinished ones.
+    #[structopt(long)]
+    resume: bool,
+
+    /// Keep merging the other movies when one fails, listing the failed ones at the end and exiting with an error.
+    #[structopt(long)]
+    keep_going: bool,
+
+    /// Keep the directory with the intermediate files of the run, e.g. the lists of chapters handed to ffmpeg, instead of removing it at the end, for debugging.
+    #[structopt(long)]
+    keep_temp: bool,
+
+    /// Keep the ffmpeg log of every movie in this directory instead of the temporary directory, named by when the run started and the movie, e.g. 20211114T231525Z_GH000084.MP4.log. Failed movies point to their log.
+    #[structopt(long, parse(from_os_str))]
+    log_dir: Option<PathBuf>,
+
+    /// The zone the clocks of the cameras were set to, "local", "UTC" or an offset like +02:00, for cameras whose clocks were set wrong, e.g. to UTC or to the time at home while travelling. The recording times the cameras write into the chapters and stamp their files with are read in it, all the chapters of a movie in the offset at its start so that movies spanning a daylight-saving changeover stay in one piece. [default: local, the zone of this machine]
+    #[structopt(long)]
+    timezone: Option<TimeZone>,
+
+    /// Keep running and merge the movies copied into the input as they come, once their chapters stop growing for --watch-settle, until Ctrl-C. A merged movie is merged again when chapters are added to it, and probed again when its chapters change between being probed ahead of merging and merging.
+    #[structopt(long)]
+    watch: bool,
+
+    /// How long the chapters of a movie must stay unchanged before --watch merges it, e.g. "2m" for slow card readers.
+    #[structopt(long, default_value = "30s", parse(try_from_str = humantime::parse_duration))]
+    watch_settle: Duration,
+
+    /// Serve the state of the merges over HTTP on this address, e.g. "0.0.0.0:8080" to check on a NAS from a browser. GET /jobs lists the movies with their state and percentage, GET /jobs/<name> shows one and POST /jobs/<name>/cancel stops it. There's no authentication, only serve on trusted networks. Pair with --watch to keep running.
+    #[structopt(long)]
+    serve: Option<String>,
+
+    /// The order movies start merging in, one of "name" | "longest-first" | "interleave" | "interleave:<large movies per wave>". Interleaving starts the largest movies left alongside the smallest ones so the quick ones' probing overlaps the long ones' copying.
+    #[structopt(long, default_value = "name")]
+    schedule: Schedule,
+
+    /// Merge the matching movies before all the others regardless of --schedule, one of "file:<file numbers>" (e.g. file:1250-1260) | "date:<YYYY-MM-DD>" (the date the camera clock showed when recording the first chapter) | "dir:<directory>" (chapters in it, relative to the input). Can be repeated.
+    #[structopt(long, number_of_values = 1)]
+    priority: Vec<Priority>,
+
+    /// Don't ask before destructive actions like overwriting existing merged movies, required to do them in non-interactive runs.
+    #[structopt(short = "y", long)]
+    assume_yes: bool,
+
+    /// How far a merged movie may be off from the summed durations of its chapters before it's considered truncated, e.g. "500ms".
+    #[structopt(long, default_value = "1s", parse(try_from_str = humantime::parse_duration))]
+    duration_tolerance: Duration,
+
+    /// Keep merged movies whose duration is off by more than --duration-tolerance, only warning about them.
+    #[structopt(long)]
+    allow_duration_mismatch: bool,
+
+    /// Kill ffmpeg and fail the movie when it makes no progress for this long, e.g. on a corrupt chapter or hung storage. "0" disables the watchdog.
+    #[structopt(long, default_value = "120s", parse(try_from_str = humantime::parse_duration))]
+    stall_timeout: Duration,
+
+    /// Retry reading chapters this many times before failing their movie, e.g. while an automounted network share comes up.
+    #[structopt(long, default_value = "2")]
+    read_retries: usize,
+
+    /// How long to wait before retrying to read chapters, doubling for every following retry up to 5 minutes, e.g. "15s" for slow network mounts.
+    #[structopt(long, default_value = "2s", parse(try_from_str = humantime::parse_duration))]
+    read_retry_delay: Duration,
+
+    /// Stop starting new movies after this long, e.g. "6h", the ones left are reported as deferred for a follow-up run with --on-exists skip.
+    #[structopt(long, parse(try_from_str = humantime::parse_duration))]
+    max_runtime: Option<Duration>,
+
+    /// Kill the merges still running at --max-runtime instead of letting them finish, removing their partial outputs.
+    #[structopt(long)]
+    hard_stop: bool,
+
+    /// Pause starting new movies while the output volume has less space free, e.g. "500M", resuming once space is freed, "0" disables pausing. Runs whose movies wouldn't fit on the volume with this much left free fail before merging.
+    #[structopt(long, default_value = "1GiB")]
+    min_free_space: ByteSize,
+
+    /// Fail movies still waiting for --min-free-space after this long rather than pausing them until space is freed, "0" fails them right away.
+    #[structopt(long, default_value = "30m", parse(try_from_str = humantime::parse_duration))]
+    space_wait: Duration,
+
+    /// Verify merged movies beyond their duration, "bitexact" hashes the video packets of the chapters and the merged movie to prove the merge lossless.
+    #[structopt(long)]
+    verify: Option<VerifyMode>,
+
+    /// Extract the GPMF telemetry track of the merged movies into a .bin file next to them, e.g. GH000084.bin, for telemetry tools like gopro-telemetry.
+    #[structopt(long)]
+    extract_telemetry: bool,
+
+    /// Write the GPS track of the merged movies, read from the GPMF telemetry of their chapters, into a .gpx file next to them, e.g. GH000084.gpx, for mapping and sport tracking apps. Works with --drop-data-streams too.
+    #[structopt(long)]
+    extract_gpx: bool,
+
+    /// Extract the audio of the merged movies into a file next to them, e.g. for narration or podcasts recorded on the camera, "aac" copying it as is into GH000084.m4a or "wav" decoding it into GH000084.wav. Movies without a sound track, e.g. timelapses, are only warned about.
+    #[structopt(long)]
+    extract_audio: Option<AudioFormat>,
+
+    /// Keep only the audio of the merged movies extracted with --extract-audio, removing the merged movies once everything else made from them is written.
+    #[structopt(long)]
+    audio_only: bool,
+
+    /// Generate a 540p proxy of the merged movies next to them for editing on slow machines, named like the proxies the cameras record, e.g. GH000084.LRV.
+    #[structopt(long)]
+    proxy: bool,
+
+    /// Also merge the low resolution proxies the cameras record next to the chapters, e.g. GL010084.LRV, into one next to the merged movies, e.g. GH000084.LRV, along with the thumbnail of the first chapter as GH000084.THM, keeping proxy workflows working after merging. Groups with chapters missing their proxy are only warned about.
+    #[structopt(long)]
+    merge_proxies: bool,
+
+    /// Merge, verify, extract the telemetry and generate a proxy of every movie in one go, reusing the probing of the chapters and reporting each movie once, the same as --verify bitexact --extract-telemetry --proxy.
+    #[structopt(long)]
+    pipeline: bool,
+
+    /// The ffmpeg binary to merge with, looked up in $PATH by default.
+    #[structopt(long, env = "GOPRO_MERGE_FFMPEG")]
+    ffmpeg_path: Option<PathBuf>,
+
+    /// The ffprobe binary to probe the chapters with, looked up in $PATH by default.
+    #[structopt(long, env = "GOPRO_MERGE_FFPROBE")]
+    ffprobe_path: Option<PathBuf>,
+
+    /// The MP4Box binary to merge with using --backend mp4box, looked up in $PATH by default.
+    #[structopt(long, env = "GOPRO_MERGE_MP4BOX")]
+    mp4box_path: Option<PathBuf>,
+
+    /// Run ffmpeg, ffprobe and MP4Box in a container instead of on the host, one of "docker:<image>" | "podman:<image>", e.g. "docker:jrottenberg/ffmpeg". The inputs, the output and the workspace are mounted into it at the same paths, --ffmpeg-path and the like being paths in the container. Not supported on Windows.
+    #[structopt(long, env = "GOPRO_MERGE_FFMPEG_VIA")]
+    ffmpeg_via: Option<Container>,
+
+    /// Print which file naming schemes of the camera model, e.g. HERO9 or Fusion, are merged and exit.
+    #[structopt(long)]
+    explain_model: Option<String>,
+
+    /// Print how many movies earlier runs merged or failed and how much footage they merged by the day the movies were recorded on, the card they were merged from or the camera that recorded them, one of "day" | "card" | "camera", and exit. Every run records its movies in a history in the user data directory, e.g. ~/.local/share/gopro-merge.
+    #[structopt(long)]
+    history_report: Option<ReportKey>,
+
+    /// Don't record the merged movies in the history of --history-report.
+    #[structopt(long)]
+    no_history: bool,
+
+    /// Print the streams, codecs, bit rates and GoPro specifics like the firmware and GPMF telemetry of a chapter or merged movie and exit, a JSON line per file with the json reporter. Can be repeated.
+    #[structopt(long, parse(from_os_str), number_of_values = 1)]
+    inspect: Vec<PathBuf>,
+
+    /// Compare two run reports saved from the json reporter, e.g. with --reporter json --quiet > run.json 2>&1, and exit, printing the movies added or removed, the new failures, the changed durations and the speed regressions, a JSON line per change with the json reporter.
+    #[structopt(long, parse(from_os_str), number_of_values = 2, value_names = &["before", "after"])]
+    diff_reports: Vec<PathBuf>,
 }
 
-#[derive(Debug, PartialEq, Eq, Display)]
+#[cfg(not(any(feature = "progressbar", feature = "json-reporter")))]
+compile_error!("gopro-merge needs a reporter, enable the progressbar or json-reporter feature");
+
+/// The reporters compiled in, with the progressbar and json-reporter features.
+#[derive(Debug, PartialEq, Eq)]
 enum OptReporter {
-    #[display(fmt = "json")]
+    #[cfg(feature = "json-reporter")]
     Json,
-    #[display(fmt = "progressbar")]
+    #[cfg(feature = "progressbar")]
     ProgressBar,
 }
 
+impl fmt::Display for OptReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "json-reporter")]
+            OptReporter::Json => write!(f, "json"),
+            #[cfg(feature = "progressbar")]
+            OptReporter::ProgressBar => write!(f, "progressbar"),
+        }
+    }
+}
+
 impl FromStr for OptReporter {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self> {
         Ok(match s {
+            #[cfg(feature = "json-reporter")]
             "json" => OptReporter::Json,
+            #[cfg(feature = "progressbar")]
             "progressbar" => OptReporter::ProgressBar,
             _ => Default::default(),
         })
@@ -62,66 +408,1044 @@ impl FromStr for OptReporter {
 }
 
 impl Default for OptReporter {
+    #[cfg(feature = "progressbar")]
     fn default() -> Self {
         OptReporter::ProgressBar
     }
+
+    #[cfg(not(feature = "progressbar"))]
+    fn default() -> Self {
+        OptReporter::Json
+    }
 }
 
+impl OptReporter {
+    /// The reporter of the run `run_id`, reporting only the outcome of the movies when `quiet` and
+    /// their child processes too when `verbose`.
+    fn reporter(&self, run_id: &RunId, quiet: bool, verbose: bool) -> Box<dyn Reporter> {
+        match self {
+            #[cfg(feature = "json-reporter")]
+            OptReporter::Json => reporter::<JsonProgressReporter>(run_id, quiet, verbose),
+            #[cfg(feature = "progressbar")]
+            OptReporter::ProgressBar => {
+                reporter::<ConsoleProgressBarReporter>(run_id, quiet, verbose)
+            }
+        }
+    }
+}
+
+fn reporter<R: Reporter>(run_id: &RunId, quiet: bool, verbose: bool) -> Box<dyn Reporter> {
+    let reporter = R::new(run_id.clone());
+    Box::new(match (quiet, verbose) {
+        (true, _) => reporter.quiet(),
+        (_, true) => reporter.verbose(),
+        _ => reporter,
+    })
+}
+
+/// Options contradicting each other, one of them failing or doing nothing because of the others.
+#[derive(Debug, PartialEq, Eq)]
+struct Conflict {
+    /// The options in conflict as they were given, e.g. `--verify bitexact`.
+    options: Vec<String>,
+    /// Why they conflict.
+    reason: &'static str,
+    /// How to resolve it.
+    fix: &'static str,
+}
+
+impl Conflict {
+    fn new<S: ToString>(
+        options: impl IntoIterator<Item = S>,
+        reason: &'static str,
+        fix: &'static str,
+    ) -> Self {
+        Conflict {
+            options: options
+                .into_iter()
+                .map(|option| option.to_string())
+                .collect(),
+            reason,
+            fix,
+        }
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}, {}",
+            self.options.join(" with "),
+            self.reason,
+            self.fix
+        )
+    }
+}
+
+/// The conflicts among the options of a run, failing it before anything is merged.
+#[derive(Debug)]
+struct Conflicts(Vec<Conflict>);
+
+impl fmt::Display for Conflicts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "conflicting options")?;
+        self.0
+            .iter()
+            .try_for_each(|conflict| write!(f, "\n  {}", conflict))
+    }
+}
+
+impl std::error::Error for Conflicts {}
+
 impl Opt {
     // Only the first calls of get_input and get_output produce expected results, not intended to be called twice
     fn get_input(&mut self, parent: &Path) -> Result<PathBuf> {
+        let paths = self.path_map();
         self.input
             .take()
             .map_or_else(
                 || parent.to_path_buf().canonicalize(),
-                |path| parent.join(path).canonicalize(),
+                |path| parent.join(paths.remap(&path)).canonicalize(),
             )
             .map_err(From::from)
     }
 
+    /// The directories of --input, or else the input argument. With --input and --manifest the
+    /// only argument is the output directory.
+    fn get_inputs(&mut self, parent: &Path) -> Result<Vec<PathBuf>> {
+        if self.inputs.is_empty() && self.manifest.is_none() {
+            return Ok(vec![self.get_input(parent)?]);
+        }
+        let flag = if self.manifest.is_some() {
+            "manifest"
+        } else {
+            "input"
+        };
+        if self.output.is_some() {
+            return Err(
+                format!("with --{} the only argument is the output directory", flag).into(),
+            );
+        }
+        self.output = self.input.take();
+        if self.manifest.is_some() {
+            // the chapters are listed with their directories, the current one stands in for the
+            // input, e.g. for --priority dir:
+            return Ok(vec![parent.canonicalize()?]);
+        }
+
+        let paths = self.path_map();
+        let mut inputs: Vec<PathBuf> = vec![];
+        for input in &self.inputs {
+            let input = parent.join(paths.remap(input)).canonicalize()?;
+            if !inputs.contains(&input) {
+                inputs.push(input);
+            }
+        }
+        Ok(inputs)
+    }
+
     fn get_output(&mut self, parent: &Path) -> Result<PathBuf> {
+        let paths = self.path_map();
         self.output.take().map_or_else(
             || self.get_input(parent),
-            |out| out.canonicalize().map_err(From::from),
+            |out| paths.remap(&out).canonicalize().map_err(From::from),
         )
     }
 
-    fn get_parallel(&self) -> usize {
-        self.parallel.unwrap_or_default()
+    fn group_filter(&self) -> GroupFilter {
+        GroupFilter {
+            patterns: self.only.clone(),
+            files: self.file.clone(),
+        }
+    }
+
+    fn activities(&self, movies: &MovieGroups, input: &Path) -> Result<Activities> {
+        let mut regions = self.region.clone();
+        if let Some(path) = &self.regions_file {
+            regions.extend(activity::read_regions(&self.path_map().remap(path))?);
+        }
+        if !regions.is_empty() && cfg!(not(feature = "telemetry")) {
+            return Err("--region reads the GPS telemetry, which this build is without".into());
+        }
+        if regions.is_empty() {
+            return Ok(Activities {
+                mode: self.activity,
+                ..Default::default()
+            });
+        }
+
+        Ok(Activities::classify(
+            movies,
+            input,
+            &RegionClassifier { regions },
+            self.activity,
+        ))
+    }
+
+    fn output_names(&self, movies: &MovieGroups, input: &Path) -> Result<OutputNames> {
+        let names = if self.name_template.is_none() && self.title_template.is_none() {
+            OutputNames::default()
+        } else {
+            OutputNames::render(
+                movies,
+                input,
+                self.name_template.as_ref(),
+                self.title_template.as_ref(),
+                self.locale.unwrap_or_else(Locale::from_env),
+                &RealFs,
+                &SystemClock,
+            )?
+        };
+
+        Ok(OutputNames {
+            container: self.container,
+            ..names
+        })
+    }
+
+    fn priorities(&self, movies: &MovieGroups, input: &Path) -> Priorities {
+        let priorities = self
+            .priority
+            .iter()
+            .map(|priority| match priority {
+                Priority::Dir(dir) => Priority::Dir(self.path_map().remap(dir)),
+                priority => priority.clone(),
+            })
+            .collect::<Vec<_>>();
+        Priorities::select(movies, input, &priorities, &RealFs, &SystemClock)
+    }
+
+    fn path_map(&self) -> PathMap {
+        PathMap(self.map_path.clone())
+    }
+
+    fn log_level(&self) -> LevelFilter {
+        match self.verbose {
+            0 => LevelFilter::Error,
+            1 => LevelFilter::Info,
+            2 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+
+    fn get_merge_parallel(&self) -> usize {
+        self.merge_parallel.unwrap_or_default()
+    }
+
+    fn scan_options(&self) -> ScanOptions {
+        ScanOptions {
+            recursive: self.recursive || self.max_depth.is_some(),
+            max_depth: self.max_depth,
+            fusion: self.fusion,
+            proxies: self.exclude_proxies,
+            ..Default::default()
+        }
+    }
+
+    fn binaries(&self) -> Binaries {
+        let default = Binaries::default();
+        Binaries {
+            ffmpeg: self
+                .ffmpeg_path
+                .as_deref()
+                .map_or(default.ffmpeg, |path| self.path_map().remap(path)),
+            ffprobe: self
+                .ffprobe_path
+                .as_deref()
+                .map_or(default.ffprobe, |path| self.path_map().remap(path)),
+            mp4box: self
+                .mp4box_path
+                .as_deref()
+                .map_or(default.mp4box, |path| self.path_map().remap(path)),
+            runner: self
+                .ffmpeg_via
+                .clone()
+                .map_or(default.runner, CommandRunner::Container),
+        }
+    }
+
+    /// The options given which only ffmpeg merges with, none of them work with `--backend mp4box`.
+    fn ffmpeg_only(&self) -> Vec<&'static str> {
+        [
+            (self.subtitles, "--subtitles"),
+            (self.chapter_markers, "--chapter-markers"),
+            (self.cfr, "--cfr"),
+            (self.crossfade.is_some(), "--crossfade"),
+            (self.drop_data_streams, "--drop-data-streams"),
+            (self.keep_timecode, "--keep-timecode"),
+            (self.copy_unknown_streams, "--copy-unknown-streams"),
+            (self.no_temp_concat, "--no-temp-concat"),
+            (self.embed_sources, "--embed-sources"),
+            (self.verify.is_some(), "--verify"),
+            (self.extract_telemetry, "--extract-telemetry"),
+            (self.proxy, "--proxy"),
+            (self.extract_audio.is_some(), "--extract-audio"),
+            (self.pipeline, "--pipeline"),
+            (
+                self.container
+                    .map_or(false, |container| container != OutputContainer::Mp4),
+                "--container",
+            ),
+        ]
+        .iter()
+        .filter(|(given, _)| *given)
+        .map(|(_, option)| *option)
+        .collect()
+    }
+
+    /// The combinations of the options that contradict each other, all of them at once so that
+    /// they can be fixed in one go.
+    fn conflicts(&self) -> Vec<Conflict> {
+        let mut conflicts = vec![];
+        let ffmpeg_only = self.ffmpeg_only();
+        if self.backend == Backend::MP4Box && !ffmpeg_only.is_empty() {
+            conflicts.push(Conflict::new(
+                std::iter::once("--backend mp4box").chain(ffmpeg_only),
+                "MP4Box only joins the chapters, the others need ffmpeg",
+                "merge with --backend ffmpeg or without them",
+            ));
+        }
+        let verify = match self.verify {
+            Some(verify) => Some(format!("--verify {}", verify)),
+            None => self.pipeline.then(|| "--pipeline".to_string()),
+        };
+        if let (true, Some(verify)) = (self.cfr, &verify) {
+            conflicts.push(Conflict::new(
+                vec!["--cfr", verify.as_str()],
+                "re-encoding the variable frame rate chapters makes their movies fail the bit exact verification",
+                "verify the movies without --cfr or convert them without verifying",
+            ));
+        }
+        if let Some(crossfade) = self.crossfade {
+            let crossfade = format!("--crossfade {}", humantime::format_duration(crossfade));
+            if let Some(verify) = &verify {
+                conflicts.push(Conflict::new(
+                    vec![crossfade.clone(), verify.clone()],
+                    "re-encoding the movies to crossfade their chapters makes them fail the bit exact verification",
+                    "verify the movies without --crossfade or crossfade them without verifying",
+                ));
+            }
+            if !self.drop_data_streams {
+                conflicts.push(Conflict::new(
+                    vec![crossfade],
+                    "the telemetry and timecode can't be faded into each other",
+                    "pass --drop-data-streams too or leave it out",
+                ));
+            }
+        }
+        let extract_telemetry = if self.extract_telemetry {
+            Some("--extract-telemetry")
+        } else {
+            self.pipeline.then(|| "--pipeline")
+        };
+        if let (true, Some(extract_telemetry)) = (self.drop_data_streams, extract_telemetry) {
+            conflicts.push(Conflict::new(
+                vec![extract_telemetry, "--drop-data-streams"],
+                "the telemetry is dropped from the merged movies before it could be extracted",
+                "keep the data streams or drop --extract-telemetry",
+            ));
+        }
+        if self.audio_only && self.extract_audio.is_none() {
+            conflicts.push(Conflict::new(
+                vec!["--audio-only"],
+                "nothing would be left of the merged movies",
+                "pass --extract-audio aac or --extract-audio wav too",
+            ));
+        }
+        if self.extract_gpx && cfg!(not(feature = "telemetry")) {
+            conflicts.push(Conflict::new(
+                vec!["--extract-gpx"],
+                "the GPS is read from the GPMF telemetry, which this build is without",
+                "build with the telemetry feature or leave it out",
+            ));
+        }
+        if self.merge_proxies {
+            let proxy = if self.proxy {
+                Some("--proxy")
+            } else {
+                self.pipeline.then(|| "--pipeline")
+            };
+            if let Some(proxy) = proxy {
+                conflicts.push(Conflict::new(
+                    vec![proxy, "--merge-proxies"],
+                    "both write the proxy next to the merged movies",
+                    "generate the proxies or merge the recorded ones, not both",
+                ));
+            }
+            if self.exclude_proxies == ProxyMode::Off {
+                conflicts.push(Conflict::new(
+                    vec!["--exclude-proxies off", "--merge-proxies"],
+                    "the proxies are merged as movies of their own",
+                    "leave out --exclude-proxies off",
+                ));
+            }
+        }
+        if self.keep_timecode && !self.drop_data_streams {
+            conflicts.push(Conflict::new(
+                vec!["--keep-timecode"],
+                "the timecode is kept anyways unless the data streams are dropped",
+                "pass --drop-data-streams too or leave it out",
+            ));
+        }
+        if self.container == Some(OutputContainer::Mkv) {
+            if !self.drop_data_streams {
+                conflicts.push(Conflict::new(
+                    vec!["--container mkv"],
+                    "Matroska can't hold the telemetry and timecode data streams",
+                    "pass --drop-data-streams too or merge into mp4",
+                ));
+            }
+            if self.keep_timecode {
+                conflicts.push(Conflict::new(
+                    vec!["--container mkv", "--keep-timecode"],
+                    "Matroska has no timecode track to keep the timecode in",
+                    "leave out --keep-timecode",
+                ));
+            }
+        }
+        if self.hard_stop && self.max_runtime.is_none() {
+            conflicts.push(Conflict::new(
+                vec!["--hard-stop"],
+                "there is no deadline to stop the merges at",
+                "pass a --max-runtime too or leave it out",
+            ));
+        }
+        if self.plan_format != PlanFormat::Json {
+            let plan_format = format!("--plan-format {}", self.plan_format);
+            if !self.dry_run {
+                conflicts.push(Conflict::new(
+                    vec![plan_format.clone()],
+                    "only --dry-run prints a plan",
+                    "pass --dry-run too or leave it out",
+                ));
+            }
+            #[cfg(feature = "progressbar")]
+            if self.reporter == OptReporter::ProgressBar {
+                conflicts.push(Conflict::new(
+                    vec![plan_format, "--reporter progressbar".to_string()],
+                    "the progressbar reporter prints the plan as text",
+                    "pass --reporter json",
+                ));
+            }
+        }
+        conflicts
+    }
+
+    /// The cache of the probes of earlier runs, none with --no-probe-cache or when it can't be
+    /// opened, probing everything again.
+    fn probe_cache(&self, probe: bool) -> Option<ProbeCache> {
+        if !probe || self.no_probe_cache {
+            return None;
+        }
+        let path = ProbeCache::default_path()?;
+        match ProbeCache::open(&path) {
+            Ok(cache) => Some(cache),
+            Err(err) => {
+                warn!(
+                    "probing without the cache, opening {} failed: {}",
+                    path.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// The history of the runs to record the merged movies in, none with --no-history or when it
+    /// can't be opened, merging without recording them.
+    fn history(&self) -> Option<History> {
+        if self.no_history {
+            return None;
+        }
+        let path = History::default_path()?;
+        match History::open(&path) {
+            Ok(history) => Some(history),
+            Err(err) => {
+                warn!(
+                    "not recording the run, opening the history {} failed: {}",
+                    path.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    fn merge_options(&self, run_id: RunId, workspace: PathBuf, probe: bool) -> MergeOptions {
+        MergeOptions {
+            run_id,
+            workspace,
+            exports: self.export.clone(),
+            subtitles: self.subtitles,
+            chapter_markers: self.chapter_markers,
+            probe,
+            streams: StreamMapping {
+                data: !self.drop_data_streams,
+                timecode: !self.drop_data_streams || self.keep_timecode,
+                copy_unknown: self.copy_unknown_streams,
+            },
+            concat: if self.no_temp_concat {
+                ConcatMethod::Protocol
+            } else {
+                ConcatMethod::Demuxer
+            },
+            audit_hash: self.audit_hash,
+            embed_sources: self.embed_sources,
+            on_exists: self.on_exists,
+            duration_tolerance: self.duration_tolerance,
+            allow_duration_mismatch: self.allow_duration_mismatch,
+            verify: self
+                .verify
+                .or_else(|| self.pipeline.then(|| VerifyMode::Bitexact)),
+            extract_telemetry: self.extract_telemetry || self.pipeline,
+            proxy: self.proxy || self.pipeline,
+            merge_proxies: self.merge_proxies,
+            extract_gpx: self.extract_gpx,
+            extract_audio: self.extract_audio,
+            audio_only: self.audio_only,
+            cfr: self.cfr,
+            crossfade: self.crossfade.filter(|crossfade| !crossfade.is_zero()),
+            min_free_space: self.min_free_space,
+            space_wait: self.space_wait,
+            stall_timeout: Some(self.stall_timeout).filter(|timeout| !timeout.is_zero()),
+            retry: Retry {
+                retries: self.read_retries,
+                delay: self.read_retry_delay,
+            },
+            probe_parallel: self.probe_parallel.unwrap_or_default(),
+            log_dir: self.log_dir.clone().map(LogDir::new),
+            timezone: self.timezone.unwrap_or_default(),
+            keep_going: self.keep_going,
+            schedule: self.schedule,
+            proxies: self.exclude_proxies,
+            binaries: self.binaries(),
+            // the runtime counts from when the options are made, right before merging starts
+            deadline: self
+                .max_runtime
+                .map(|max_runtime| Deadline::after(Instant::now(), max_runtime, self.hard_stop)),
+            ..Default::default()
+        }
     }
 }
 
-fn main() -> Result<()> {
-    color_backtrace::install();
-    env_logger::init();
+/// Reports the errors of the run with their messages rather than their debug representation,
+/// which would hide e.g. the install hints of the missing binaries.
+fn main() {
+    match run() {
+        Ok(Outcome::Merged) => {}
+        Ok(Outcome::Failed) => process::exit(1),
+        Ok(Outcome::Interrupted) => process::exit(INTERRUPTED_EXIT_CODE),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    }
+}
 
+fn run() -> Result<Outcome> {
+    color_backtrace::install();
+    let run_id = RunId::generate();
     let mut opt = Opt::from_args();
+    run::init_logger(&run_id, opt.log_level());
+    info!("starting run {}", run_id);
+
+    if let Some(name) = &opt.explain_model {
+        match find_model(name) {
+            Some(model) => print!("{}", model),
+            None => {
+                return Err(format!(
+                    "unknown camera model {}, one of {}",
+                    name,
+                    models::MODELS
+                        .iter()
+                        .map(|model| model.name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+                .into())
+            }
+        }
+        return Ok(Outcome::Merged);
+    }
+
+    if let Some(by) = opt.history_report {
+        report_history(&opt.reporter, by)?;
+        return Ok(Outcome::Merged);
+    }
+
+    if !opt.inspect.is_empty() {
+        let paths = opt
+            .inspect
+            .iter()
+            .map(|path| opt.path_map().remap(path))
+            .collect::<Vec<_>>();
+        let dirs = paths.iter().filter_map(|path| path.parent());
+        mount(&mut opt, dirs)?;
+        let binaries = opt.binaries();
+        for path in &paths {
+            let inspection = Inspection::of(path, &binaries)?;
+            match opt.reporter {
+                #[cfg(feature = "progressbar")]
+                OptReporter::ProgressBar => print!("{}", inspection),
+                #[cfg(feature = "json-reporter")]
+                OptReporter::Json => println!("{}", inspection.to_json()),
+            }
+        }
+        return Ok(Outcome::Merged);
+    }
+
+    if let [before, after] = &opt.diff_reports[..] {
+        let (before, after) = (
+            RunReport::read(&opt.path_map().remap(before))?,
+            RunReport::read(&opt.path_map().remap(after))?,
+        );
+        let changes = report::diff(&before, &after);
+        match opt.reporter {
+            #[cfg(feature = "progressbar")]
+            OptReporter::ProgressBar => {
+                changes.iter().for_each(|change| println!("{}", change));
+                if changes.is_empty() {
+                    println!(
+                        "no changes between the {} and {} movies of the runs",
+                        before.movies.len(),
+                        after.movies.len()
+                    );
+                }
+            }
+            #[cfg(feature = "json-reporter")]
+            OptReporter::Json => changes
+                .iter()
+                .for_each(|change| println!("{}", change.to_json())),
+        }
+        return Ok(Outcome::Merged);
+    }
+
+    // a bare run from a terminal is most likely someone new to the tool
+    if env::args_os().len() == 1 && ConfirmPolicy::detect(false) == ConfirmPolicy::Prompt {
+        let setup = wizard::run(&wizard::card_mounts(), &env::current_dir()?)?;
+        opt.input = Some(setup.input);
+        opt.output = Some(setup.output);
+        opt.recursive |= setup.recursive;
+    }
+
+    let conflicts = opt.conflicts();
+    if !conflicts.is_empty() {
+        return Err(Conflicts(conflicts).into());
+    }
 
     rayon::ThreadPoolBuilder::new()
-        .num_threads(opt.get_parallel())
+        .num_threads(opt.get_merge_parallel())
         .build_global()?;
 
     let wd = env::current_dir()?;
-    let input = opt.get_input(wd.as_path())?;
-    let output = opt.get_output(wd.as_path())?;
+    let inputs = opt.get_inputs(wd.as_path())?;
+    // stands in for the directories where a single one is expected, e.g. for --priority dir:
+    let input = inputs[0].clone();
+    let output = if opt.output.is_some() {
+        opt.get_output(wd.as_path())?
+    } else {
+        input.clone()
+    };
 
-    let movies = group_movies(&input)?;
+    let workspace = Workspace::path_of(&run_id);
+    let dirs = inputs.iter().chain([&output, &workspace]);
+    mount(&mut opt, dirs.map(PathBuf::as_path))?;
+    let binaries = opt.binaries();
+    let probe = ffprobe_available(&binaries);
+    if !probe {
+        warn!(
+            "{} not found, merging without probing",
+            binaries.ffprobe.display()
+        );
+        eprintln!(
+            "warning: {} was not found, merging without progress percentages and exports",
+            binaries.ffprobe.display()
+        );
+    }
+    // e.g. merging a locked memory card into itself, better told before probing anything
+    if !opt.dry_run {
+        check_writable(&inputs, &output, &RealFs)?;
+    }
+    let interrupt = Interrupt::default();
+    let jobs = Jobs::default();
+    if let Some(address) = &opt.serve {
+        serve(address, &jobs)?;
+    }
+    if opt.watch {
+        return watch(
+            &opt,
+            &run_id,
+            &inputs,
+            (&input, &output),
+            probe,
+            &interrupt,
+            &jobs,
+        );
+    }
+
+    let movies = match &opt.manifest {
+        Some(manifest) => manifest::read(&opt.path_map().remap(manifest), &wd, &opt.path_map())
+            .map_err(Error::from),
+        None => scan_movies_in(&inputs, &opt.scan_options())
+            .map(|(movies, skipped)| {
+                skipped
+                    .iter()
+                    .for_each(|file| eprintln!("warning: {}", file));
+                movies
+            })
+            .map_err(Error::from),
+    };
+    let movies = opt.group_filter().apply(movies?);
     debug!("collected movies: {:?}", movies);
+    merge(
+        &opt,
+        &run_id,
+        (&input, &output),
+        movies,
+        probe,
+        &interrupt,
+        &jobs,
+    )
+}
+
+/// Prints the report of the history of the runs kept in the user data directory, for
+/// --history-report.
+fn report_history(reporter: &OptReporter, by: ReportKey) -> Result<()> {
+    let path = History::default_path()
+        .ok_or("no user data directory to keep the history of the runs in")?;
+    let rows = history::report(&History::read(&path)?, by);
+    match reporter {
+        #[cfg(feature = "progressbar")]
+        OptReporter::ProgressBar => {
+            rows.iter().for_each(|row| println!("{}", row));
+            if rows.is_empty() {
+                println!("no movies merged yet in {}", path.display());
+            }
+        }
+        #[cfg(feature = "json-reporter")]
+        OptReporter::Json => rows.iter().for_each(|row| println!("{}", row.to_json(by))),
+    }
+    Ok(())
+}
+
+/// Mounts the directories at `paths` into the container of --ffmpeg-via, if any.
+fn mount<'a>(opt: &mut Opt, paths: impl IntoIterator<Item = &'a Path>) -> Result<()> {
+    if let Some(container) = opt.ffmpeg_via.take() {
+        opt.ffmpeg_via = Some(container.mounting(paths)?);
+    }
+    Ok(())
+}
+
+/// How the merges of a batch of movies ended.
+enum Outcome {
+    Merged,
+    /// Some of the movies failed to merge, the failures were reported.
+    Failed,
+    Interrupted,
+}
+
+/// Merges the `movies` of the `input` into the `output` directory, all the movies of the run or,
+/// with --watch, the ones settled since the last batch.
+fn merge(
+    opt: &Opt,
+    run_id: &RunId,
+    (input, output): (&Path, &Path),
+    movies: MovieGroups,
+    probe: bool,
+    interrupt: &Interrupt,
+    jobs: &Jobs,
+) -> Result<Outcome> {
+    let activities = opt.activities(&movies, input)?;
+    debug!("classified activities: {:?}", activities.groups);
+    let priorities = opt.priorities(&movies, input);
+    let output_names = opt.output_names(&movies, input)?;
+    // e.g. a deep output directory with a long --name-template, better told before merging any
+    for group in &movies {
+        let path = activities
+            .output_dir(group, output)
+            .join(output_names.of(group));
+        check_path_length(&path)?;
+    }
+    let probe_cache = opt.probe_cache(probe);
+
+    if opt.dry_run {
+        let options = MergeOptions {
+            activities,
+            names: output_names,
+            priorities,
+            probe_cache: probe_cache.clone(),
+            ..opt.merge_options(run_id.clone(), Workspace::plan_path(), probe)
+        };
+        match opt.reporter {
+            // printed as they're planned, like the json lines
+            #[cfg(feature = "progressbar")]
+            OptReporter::ProgressBar => {
+                for movie in Plan::movies(&movies, input, output, &options) {
+                    print!("{}", movie?);
+                }
+            }
+            #[cfg(feature = "json-reporter")]
+            OptReporter::Json => match opt.plan_format {
+                PlanFormat::Json => {
+                    println!("{}", Plan::new(&movies, input, output, &options)?.to_json())
+                }
+                PlanFormat::JsonLines => {
+                    let stdout = io::stdout();
+                    plan::write_json_lines(
+                        Plan::movies(&movies, input, output, &options),
+                        movies.len(),
+                        stdout.lock(),
+                    )?
+                }
+            },
+        }
+        return Ok(Outcome::Merged);
+    }
+
+    let resume = if opt.resume {
+        let resume = ResumeState::open(output)?;
+        for path in resume.remove_partial()? {
+            info!(
+                "removed partial output {} of an interrupted run",
+                path.display()
+            );
+        }
+        Some(resume)
+    } else {
+        None
+    };
+    // the outputs completed by an interrupted run are skipped, not overwritten
+    let (completed, movies): (MovieGroups, MovieGroups) = movies.into_iter().partition(|group| {
+        resume.as_ref().map_or(false, |resume| {
+            resume.completed_output(&group.name()).is_some()
+        })
+    });
+    let mut movies = match opt.on_exists {
+        OnExists::Overwrite => {
+            confirm_overwrites(&ConfirmPolicy::detect(opt.assume_yes), movies, |group| {
+                activities
+                    .output_dir(group, output)
+                    .join(output_names.of(group))
+            })?
+        }
+        _ => movies,
+    };
+    // e.g. a nearly full NAS share, better told now than by ffmpeg hours into the run
+    let merged = movies.iter().filter(|group| {
+        opt.on_exists != OnExists::Skip
+            || !activities
+                .output_dir(group, output)
+                .join(output_names.of(group))
+                .exists()
+    });
+    // failing just this batch, --watch goes on as space may be freed
+    if let Err(err) = check_space(merged, input, output, opt.min_free_space, &RealFs) {
+        eprintln!("error: {}", err);
+        return Ok(Outcome::Failed);
+    }
+    movies.extend(completed);
+    let names = movies.iter().map(|group| group.name()).collect::<Vec<_>>();
+    opt.backend.preflight(&opt.binaries())?;
+    handle_interrupts(interrupt)?;
+
+    let mut workspace = Workspace::create(run_id)?;
+    if opt.keep_temp {
+        workspace.keep();
+        eprintln!(
+            "keeping the intermediate files in {}",
+            workspace.path().display()
+        );
+    }
+    if let Some(log_dir) = &opt.log_dir {
+        if let Err(err) = fs::create_dir_all(log_dir) {
+            eprintln!(
+                "error: creating the log directory {}: {}",
+                log_dir.display(),
+                err
+            );
+            return Ok(Outcome::Failed);
+        }
+    }
+    // e.g. a small tmpfs, the merges would fail writing their lists of chapters
+    if let Err(err) = check_workspace_space(
+        &movies,
+        input,
+        workspace.path(),
+        rayon::current_num_threads(),
+        &RealFs,
+    ) {
+        eprintln!("error: {}", err);
+        return Ok(Outcome::Failed);
+    }
+    let options = MergeOptions {
+        resume: resume.clone(),
+        activities,
+        names: output_names,
+        priorities,
+        interrupt: interrupt.clone(),
+        jobs: jobs.clone(),
+        probe_cache,
+        history: opt.history(),
+        ..opt.merge_options(run_id.clone(), workspace.path().to_path_buf(), probe)
+    };
 
     debug!("starting processor with {} reporter", opt.reporter);
+    let (input, output) = (input.to_path_buf(), output.to_path_buf());
+    let reporter = opt.reporter.reporter(run_id, opt.quiet, opt.verbose > 0);
+    let processed = Processor::new(reporter, input, output, movies, options)
+        .with_merger(opt.backend.new_merger())
+        .process();
+    let stats = match processed {
+        Err(processor::Error::Failed(failures)) => {
+            eprintln!(
+                "error: {} of {} movies failed to merge",
+                failures.len(),
+                names.len()
+            );
+            for failure in failures {
+                eprintln!("  {}: {}", failure.group, failure.error);
+            }
+            return Ok(Outcome::Failed);
+        }
+        processed => processed?,
+    };
+
+    if interrupt.is_triggered() {
+        let completed = stats
+            .iter()
+            .filter(|stats| stats.completed())
+            .collect::<Vec<_>>();
+        eprintln!(
+            "interrupted, {} of {} movies completed",
+            completed.len(),
+            stats.len()
+        );
+        for stats in completed {
+            eprintln!("  {}", stats.output.display());
+        }
+        return Ok(Outcome::Interrupted);
+    }
+    // the json reporter reports the outcome of every movie already
     match opt.reporter {
-        OptReporter::ProgressBar => Processor::<
-            ConsoleProgressBarReporter,
-            FFmpegMerger<<ConsoleProgressBarReporter as Reporter>::Progress>,
-        >::new(input, output, movies)
-        .process(),
-        OptReporter::Json => Processor::<
-            JsonProgressReporter,
-            FFmpegMerger<<JsonProgressReporter as Reporter>::Progress>,
-        >::new(input, output, movies)
-        .process(),
+        #[cfg(feature = "progressbar")]
+        OptReporter::ProgressBar if opt.quiet => print!("{}", summary(&stats)),
+        _ => {}
+    }
+    if let Some(resume) = resume {
+        resume.remove_if_completed(names.iter().map(String::as_str))?;
+    }
+    workspace.finish();
+    Ok(Outcome::Merged)
+}
+
+/// What the progress bars would have shown of the movies, for --quiet runs.
+#[cfg(feature = "progressbar")]
+fn summary(stats: &[GroupStats]) -> String {
+    let completed = stats.iter().filter(|stats| stats.completed()).count();
+    let mut summary = format!("merged {} of {} movies\n", completed, stats.len());
+    for stats in stats {
+        let outcome = if stats.skipped {
+            "skipped, it already exists".to_string()
+        } else if stats.deferred {
+            "deferred to a follow-up run".to_string()
+        } else {
+            format!(
+                "{} in {}",
+                FormattedDuration(stats.duration),
+                FormattedDuration(stats.elapsed)
+            )
+        };
+        summary.push_str(&format!("  {} {}\n", stats.output.display(), outcome));
+    }
+    summary
+}
+
+/// Merges the movies of the `inputs` as their chapters settle, until interrupted. Failed movies
+/// are reported and watching goes on.
+fn watch(
+    opt: &Opt,
+    run_id: &RunId,
+    inputs: &[PathBuf],
+    dirs: (&Path, &Path),
+    probe: bool,
+    interrupt: &Interrupt,
+    jobs: &Jobs,
+) -> Result<Outcome> {
+    // Ctrl-C stops watching between the batches too
+    handle_interrupts(interrupt)?;
+    eprintln!(
+        "watching {} for new movies, press Ctrl-C to stop",
+        inputs
+            .iter()
+            .map(|input| input.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let mut watcher = Watcher::new(RealFs, SystemClock, opt.watch_settle);
+    // told once, not on every poll
+    let mut skipped_files = HashSet::new();
+    while !interrupt.is_triggered() {
+        let movies = match scan_movies_in(inputs, &opt.scan_options()) {
+            Ok((movies, skipped)) => {
+                skipped
+                    .into_iter()
+                    .filter(|file| skipped_files.insert(file.path.clone()))
+                    .for_each(|file| eprintln!("warning: {}", file));
+                opt.group_filter().apply(movies)
+            }
+            // e.g. a memory card unmounted while scanning, it may come back
+            Err(err) => {
+                warn!("scanning for new movies failed: {}", err);
+                vec![]
+            }
+        };
+        let movies = watcher.settled(movies, dirs.0);
+        if !movies.is_empty() {
+            debug!("collected settled movies: {:?}", movies);
+            if let Outcome::Interrupted = merge(opt, run_id, dirs, movies, probe, interrupt, jobs)?
+            {
+                return Ok(Outcome::Interrupted);
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
     }
-    .map_err(From::from)
+
+    // stopping to watch between the batches isn't an interrupted merge
+    Ok(Outcome::Merged)
+}
+
+/// Serves the state of the merges for --serve, in builds with the server feature.
+#[cfg(feature = "server")]
+fn serve(address: &str, jobs: &Jobs) -> Result<()> {
+    let address = server::serve(address, jobs.clone())?;
+    eprintln!("serving the state of the merges on http://{}/jobs", address);
+    Ok(())
+}
+
+#[cfg(not(feature = "server"))]
+fn serve(_: &str, _: &Jobs) -> Result<()> {
+    Err("--serve needs the HTTP status server, which this build is without".into())
+}
+
+/// Installed only once merging starts so that Ctrl-C still exits right away while scanning and
+/// prompting, and only once for all the batches of --watch.
+fn handle_interrupts(interrupt: &Interrupt) -> Result<()> {
+    static HANDLER: Once = Once::new();
+    let mut installed = Ok(());
+    HANDLER.call_once(|| {
+        let interrupt = interrupt.clone();
+        installed = ctrlc::set_handler(move || {
+            if interrupt.trigger() {
+                // a second Ctrl-C doesn't wait for the cleanup
+                process::exit(INTERRUPTED_EXIT_CODE);
+            }
+            eprintln!("interrupted, stopping the merges and removing their partial outputs, press Ctrl-C again to exit immediately");
+        });
+    });
+
+    installed.map_err(From::from)
 }
 
 #[cfg(test)]
@@ -173,23 +1497,280 @@ mod tests {
         assert_eq!(root, opt.get_output(root.as_path()).unwrap());
     }
 
+    #[test]
+    fn test_opt_inputs() {
+        let root = env::temp_dir().canonicalize().unwrap();
+        let mut opt = Opt {
+            input: Some("output".into()),
+            inputs: vec![".".into(), root.clone(), "..".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![root.clone(), root.parent().unwrap().to_path_buf()],
+            opt.get_inputs(&root).unwrap()
+        );
+        assert_eq!(None, opt.input);
+        assert_eq!(Some(PathBuf::from("output")), opt.output);
+
+        let mut opt = Opt {
+            input: Some("in".into()),
+            output: Some("out".into()),
+            inputs: vec![".".into()],
+            ..Default::default()
+        };
+        assert!(opt.get_inputs(&root).is_err());
+
+        let mut opt = Opt::default();
+        assert_eq!(vec![root.clone()], opt.get_inputs(&root).unwrap());
+
+        let mut opt = Opt {
+            input: Some("output".into()),
+            manifest: Some("-".into()),
+            ..Default::default()
+        };
+        assert_eq!(vec![root.clone()], opt.get_inputs(&root).unwrap());
+        assert_eq!(Some(PathBuf::from("output")), opt.output);
+
+        let mut opt = Opt {
+            input: Some("in".into()),
+            output: Some("out".into()),
+            manifest: Some("-".into()),
+            ..Default::default()
+        };
+        assert!(opt.get_inputs(&root).is_err());
+    }
+
     #[test]
     fn test_opt_parallel() {
         let mut opt = Opt {
-            parallel: Some(5),
+            merge_parallel: Some(5),
             ..Default::default()
         };
 
-        assert_eq!(5, opt.get_parallel());
+        assert_eq!(5, opt.get_merge_parallel());
+
+        opt.merge_parallel = Some(0);
+        assert_eq!(0, opt.get_merge_parallel());
+
+        opt.merge_parallel = None;
+        assert_eq!(0, opt.get_merge_parallel());
+
+        let opt = Opt::from_iter(&["gopro-merge", "--parallel", "3", "--probe-parallel", "1"]);
+        assert_eq!(3, opt.get_merge_parallel());
+        assert_eq!(
+            1,
+            opt.merge_options(RunId::generate(), "/tmp".into(), true)
+                .probe_parallel
+        );
+    }
+
+    #[test]
+    fn test_opt_pipeline() {
+        let options = Opt::from_iter(&["gopro-merge", "--pipeline"]).merge_options(
+            RunId::generate(),
+            "/tmp".into(),
+            true,
+        );
+        assert_eq!(Some(VerifyMode::Bitexact), options.verify);
+        assert!(options.extract_telemetry);
+        assert!(options.proxy);
+
+        let options = Opt::from_iter(&["gopro-merge", "--proxy"]).merge_options(
+            RunId::generate(),
+            "/tmp".into(),
+            true,
+        );
+        assert_eq!(None, options.verify);
+        assert!(!options.extract_telemetry);
+        assert!(options.proxy);
+    }
+
+    #[test]
+    fn test_opt_timezone() {
+        let options = Opt::default().merge_options(RunId::generate(), "/tmp".into(), true);
+        assert_eq!(TimeZone::Local, options.timezone);
+
+        let options = Opt::from_iter(&["gopro-merge", "--timezone", "+05:30"]).merge_options(
+            RunId::generate(),
+            "/tmp".into(),
+            true,
+        );
+        assert_eq!(TimeZone::Fixed(5 * 3600 + 30 * 60), options.timezone);
+    }
+
+    #[test]
+    fn test_opt_ffmpeg_only() {
+        let opt = Opt::from_iter(&["gopro-merge", "--backend", "mp4box", "--export", "srt"]);
+        assert_eq!(Backend::MP4Box, opt.backend);
+        assert!(opt.ffmpeg_only().is_empty());
+
+        let opt = Opt::from_iter(&["gopro-merge", "--subtitles", "--verify", "bitexact"]);
+        assert_eq!(vec!["--subtitles", "--verify"], opt.ffmpeg_only());
 
-        opt.parallel = Some(0);
-        assert_eq!(0, opt.get_parallel());
+        // native falls back to ffmpeg for them
+        let opt = Opt::from_iter(&["gopro-merge", "--backend", "native", "--subtitles"]);
+        assert_eq!(Backend::Native, opt.backend);
+        assert!(opt.conflicts().is_empty());
+    }
+
+    #[test]
+    fn test_opt_diff_reports() {
+        let opt = Opt::from_iter(&["gopro-merge", "--diff-reports", "before.json", "after.json"]);
+        assert_eq!(
+            vec![PathBuf::from("before.json"), PathBuf::from("after.json")],
+            opt.diff_reports
+        );
+        assert_eq!(None, opt.input);
+    }
+
+    #[test]
+    fn test_opt_conflicts() {
+        let cases: &[(&[&str], &[&[&str]])] = &[
+            (&[], &[]),
+            (&["--backend", "mp4box", "--export", "srt"], &[]),
+            (
+                &["--backend", "mp4box", "--subtitles", "--proxy"],
+                &[&["--backend mp4box", "--subtitles", "--proxy"]],
+            ),
+            (&["--cfr"], &[]),
+            (
+                &["--cfr", "--verify", "bitexact"],
+                &[&["--cfr", "--verify bitexact"]],
+            ),
+            (&["--cfr", "--pipeline"], &[&["--cfr", "--pipeline"]]),
+            (&["--crossfade", "250ms", "--drop-data-streams"], &[]),
+            (&["--crossfade", "250ms"], &[&["--crossfade 250ms"]]),
+            (
+                &[
+                    "--crossfade",
+                    "1s",
+                    "--drop-data-streams",
+                    "--verify",
+                    "bitexact",
+                ],
+                &[&["--crossfade 1s", "--verify bitexact"]],
+            ),
+            (
+                &["--extract-telemetry", "--drop-data-streams"],
+                &[&["--extract-telemetry", "--drop-data-streams"]],
+            ),
+            (
+                &["--pipeline", "--drop-data-streams", "--cfr"],
+                &[
+                    &["--cfr", "--pipeline"],
+                    &["--pipeline", "--drop-data-streams"],
+                ],
+            ),
+            (&["--merge-proxies"], &[]),
+            (
+                &["--pipeline", "--merge-proxies"],
+                &[&["--pipeline", "--merge-proxies"]],
+            ),
+            (
+                &["--exclude-proxies", "off", "--merge-proxies"],
+                &[&["--exclude-proxies off", "--merge-proxies"]],
+            ),
+            (&["--extract-audio", "aac", "--audio-only"], &[]),
+            (&["--audio-only"], &[&["--audio-only"]]),
+            (
+                &["--backend", "mp4box", "--extract-audio", "wav"],
+                &[&["--backend mp4box", "--extract-audio"]],
+            ),
+            (&["--drop-data-streams", "--keep-timecode"], &[]),
+            (&["--keep-timecode"], &[&["--keep-timecode"]]),
+            (&["--container", "mkv", "--drop-data-streams"], &[]),
+            (&["--container", "mov"], &[]),
+            (&["--container", "mkv"], &[&["--container mkv"]]),
+            (
+                &[
+                    "--container",
+                    "mkv",
+                    "--drop-data-streams",
+                    "--keep-timecode",
+                ],
+                &[&["--container mkv", "--keep-timecode"]],
+            ),
+            (&["--backend", "mp4box", "--container", "mp4"], &[]),
+            (
+                &["--backend", "mp4box", "--container", "mov"],
+                &[&["--backend mp4box", "--container"]],
+            ),
+            (&["--hard-stop", "--max-runtime", "6h"], &[]),
+            (&["--hard-stop"], &[&["--hard-stop"]]),
+            (
+                &["--dry-run", "--reporter", "json", "--plan-format", "jsonl"],
+                &[],
+            ),
+            (
+                &["--reporter", "json", "--plan-format", "jsonl"],
+                &[&["--plan-format jsonl"]],
+            ),
+        ];
+        for (args, expected) in cases {
+            let opt = Opt::from_iter(std::iter::once(&"gopro-merge").chain(args.iter()));
+            let conflicts = opt
+                .conflicts()
+                .into_iter()
+                .map(|conflict| conflict.options)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                expected
+                    .iter()
+                    .map(|options| options.iter().map(|option| option.to_string()).collect())
+                    .collect::<Vec<Vec<_>>>(),
+                conflicts,
+                "{:?}",
+                args
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "progressbar")]
+    fn test_opt_conflicts_progressbar() {
+        let opt = Opt::from_iter(&[
+            "gopro-merge",
+            "--dry-run",
+            "--reporter",
+            "progressbar",
+            "--plan-format",
+            "jsonl",
+        ]);
+        let conflict = Conflict::new(
+            vec!["--plan-format jsonl", "--reporter progressbar"],
+            "the progressbar reporter prints the plan as text",
+            "pass --reporter json",
+        );
+        assert_eq!(
+            "--plan-format jsonl with --reporter progressbar: the progressbar reporter prints the plan as text, pass --reporter json",
+            conflict.to_string()
+        );
+        assert_eq!(vec![conflict], opt.conflicts());
+    }
+
+    #[test]
+    fn test_opt_scan_options() {
+        let mut opt = Opt::default();
+        assert_eq!(ScanOptions::default(), opt.scan_options());
 
-        opt.parallel = None;
-        assert_eq!(0, opt.get_parallel());
+        opt.max_depth = Some(2);
+        assert_eq!(
+            ScanOptions {
+                recursive: true,
+                max_depth: Some(2),
+                fusion: FusionMode::Separate,
+                proxies: ProxyMode::Warn,
+                ..Default::default()
+            },
+            opt.scan_options()
+        );
+
+        opt.fusion = FusionMode::Refuse;
+        assert_eq!(FusionMode::Refuse, opt.scan_options().fusion);
     }
 
     #[test]
+    #[cfg(all(feature = "progressbar", feature = "json-reporter"))]
     fn test_opt_reporter() {
         let tests = vec![
             ("json", OptReporter::Json),
@@ -201,4 +1782,43 @@ mod tests {
             assert_eq!(expected, OptReporter::from_str(input).unwrap());
         })
     }
+
+    #[test]
+    fn test_opt_log_level() {
+        assert_eq!(LevelFilter::Error, Opt::default().log_level());
+        assert_eq!(
+            LevelFilter::Info,
+            Opt::from_iter(&["gopro-merge", "-v"]).log_level()
+        );
+        assert_eq!(
+            LevelFilter::Debug,
+            Opt::from_iter(&["gopro-merge", "-vv"]).log_level()
+        );
+        assert_eq!(
+            LevelFilter::Trace,
+            Opt::from_iter(&["gopro-merge", "-v", "-vvv"]).log_level()
+        );
+        assert!(Opt::from_iter_safe(&["gopro-merge", "-q", "-v"]).is_err());
+        assert!(Opt::from_iter(&["gopro-merge", "--quiet"]).quiet);
+    }
+
+    #[test]
+    #[cfg(feature = "progressbar")]
+    fn test_summary() {
+        let mut merged = GroupStats::skipped("/out/GH000084.MP4".into(), Duration::from_secs(42));
+        merged.skipped = false;
+        merged.duration = Duration::from_secs(751);
+        assert_eq!(
+            "merged 1 of 3 movies
+  /out/GH000084.MP4 00:12:31 in 00:00:42
+  /out/GH000085.MP4 skipped, it already exists
+  /out/GH000086.MP4 deferred to a follow-up run
+",
+            summary(&[
+                merged,
+                GroupStats::skipped("/out/GH000085.MP4".into(), Duration::ZERO),
+                GroupStats::deferred("/out/GH000086.MP4".into(), Duration::ZERO),
+            ])
+        );
+    }
 }
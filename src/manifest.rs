@@ -0,0 +1,269 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use log::*;
+use thiserror::Error;
+
+use crate::group::{self, group_chapter_paths, MovieGroups};
+use crate::paths::PathMap;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Group(#[from] group::Error),
+
+    #[error("Failed to read the manifest {0}: {1}")]
+    IO(PathBuf, io::Error),
+
+    #[error("The manifest lists no chapters")]
+    Empty,
+
+    #[error("Invalid manifest entry {0}, expected a chapter path or an object with a \"chapters\" list of paths")]
+    InvalidEntry(String),
+
+    #[error("Entry {0} of the manifest has chapters of several movies, {1}")]
+    MixedEntry(usize, String),
+
+    #[error("{0} is listed by more than one entry of the manifest")]
+    DuplicateMovie(String),
+
+    #[error("Invalid JSON manifest: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Reads the manifest at `path`, "-" for stdin, resolving relative chapter paths against `wd`.
+pub fn read(path: &Path, wd: &Path, paths: &PathMap) -> Result<MovieGroups> {
+    let mut manifest = String::new();
+    if path == Path::new("-") {
+        io::stdin().read_to_string(&mut manifest)
+    } else {
+        fs::File::open(path).and_then(|mut file| file.read_to_string(&mut manifest))
+    }
+    .map_err(|err| Error::IO(path.into(), err))?;
+
+    parse(&manifest, wd, paths)
+}
+
+/// The groups listed by `manifest`, telling exactly which chapter files make up each movie
+/// instead of scanning directories for them. Either:
+///
+/// * chapter paths one per line, grouped into movies by their names like scanning does, blank
+///   lines and `#` comments being skipped
+/// * a JSON array of chapter paths, grouped the same way, and of `{"chapters": [...]}` objects
+///   listing the chapters of a single movie
+///
+/// Chapter paths written on another platform are remapped by `paths` first.
+pub fn parse(manifest: &str, wd: &Path, paths: &PathMap) -> Result<MovieGroups> {
+    let chapter = |path: &str| wd.join(paths.remap(Path::new(path)));
+    let groups = if manifest.trim_start().starts_with(&['[', '{'][..]) {
+        parse_json(manifest, chapter)?
+    } else {
+        let paths = manifest
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(chapter)
+            .collect::<Vec<_>>();
+        group_chapter_paths(&paths)?
+    };
+
+    if groups.is_empty() {
+        return Err(Error::Empty);
+    }
+    debug!("the manifest lists {} movies", groups.len());
+    Ok(groups)
+}
+
+fn parse_json(manifest: &str, chapter: impl Fn(&str) -> PathBuf) -> Result<MovieGroups> {
+    use serde_json::Value;
+
+    let entries: Vec<Value> = serde_json::from_str(manifest)?;
+    let invalid = |entry: &Value| Error::InvalidEntry(entry.to_string());
+    let mut loose = vec![];
+    let mut groups = MovieGroups::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let chapters = match entry {
+            Value::String(path) => {
+                loose.push(chapter(path));
+                continue;
+            }
+            Value::Object(object) => object
+                .get("chapters")
+                .and_then(Value::as_array)
+                .ok_or_else(|| invalid(entry))?,
+            _ => return Err(invalid(entry)),
+        };
+        let paths = chapters
+            .iter()
+            .map(|path| path.as_str().map(&chapter))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid(entry))?;
+
+        let mut entry_groups = group_chapter_paths(&paths)?;
+        match entry_groups.len() {
+            0 => return Err(invalid(entry)),
+            1 => groups.append(&mut entry_groups),
+            _ => {
+                let names = entry_groups
+                    .iter()
+                    .map(|group| group.name())
+                    .collect::<Vec<_>>();
+                return Err(Error::MixedEntry(index + 1, names.join(", ")));
+            }
+        }
+    }
+    groups.append(&mut group_chapter_paths(&loose)?);
+
+    // loose chapters of a movie with an entry of its own would otherwise be merged twice
+    let mut names = BTreeSet::new();
+    if let Some(group) = groups.iter().find(|group| !names.insert(group.name())) {
+        return Err(Error::DuplicateMovie(group.name()));
+    }
+    groups.sort();
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::group::MovieGroup;
+
+    fn chapter_paths(groups: &[MovieGroup]) -> Vec<Vec<PathBuf>> {
+        groups
+            .iter()
+            .map(|group| {
+                group
+                    .chapters
+                    .iter()
+                    .map(|chapter| group.chapter_path(chapter, Path::new("/unused")))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_lines() {
+        let groups = parse(
+            "# copied from the card\n\
+             /card/GH020084.mp4\n\
+             \n\
+             GH010084.mp4\n  \
+             /backup/GH010085.MP4  \n",
+            Path::new("/wd"),
+            &PathMap::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            vec!["GH000084.mp4", "GH000085.MP4"],
+            groups.iter().map(|group| group.name()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                vec![
+                    PathBuf::from("/wd/GH010084.mp4"),
+                    PathBuf::from("/card/GH020084.mp4")
+                ],
+                vec![PathBuf::from("/backup/GH010085.MP4")],
+            ],
+            chapter_paths(&groups)
+        );
+
+        assert!(matches!(
+            parse("# nothing yet\n", Path::new("/wd"), &PathMap::default()),
+            Err(Error::Empty)
+        ));
+        assert!(matches!(
+            parse("/card/notes.txt\n", Path::new("/wd"), &PathMap::default()),
+            Err(Error::Group(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let groups = parse(
+            r#"[
+                {"chapters": ["/card/GH010084.mp4", "GH020084.mp4"]},
+                "/card/GH010085.mp4",
+                "/card/GH020085.mp4"
+            ]"#,
+            Path::new("/wd"),
+            &PathMap::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                vec![
+                    PathBuf::from("/card/GH010084.mp4"),
+                    PathBuf::from("/wd/GH020084.mp4")
+                ],
+                vec![
+                    PathBuf::from("/card/GH010085.mp4"),
+                    PathBuf::from("/card/GH020085.mp4")
+                ],
+            ],
+            chapter_paths(&groups)
+        );
+
+        assert!(matches!(
+            parse(
+                r#"[{"chapters": ["GH010084.mp4", "GH010085.mp4"]}]"#,
+                Path::new("/wd"), &PathMap::default()
+            ),
+            Err(Error::MixedEntry(1, names)) if names == "GH000084.mp4, GH000085.mp4"
+        ));
+        assert!(matches!(
+            parse(
+                r#"[{"chapters": ["GH010084.mp4"]}, "GH020084.mp4"]"#,
+                Path::new("/wd"), &PathMap::default()
+            ),
+            Err(Error::DuplicateMovie(name)) if name == "GH000084.mp4"
+        ));
+        assert!(matches!(
+            parse(
+                r#"[{"chapters": []}]"#,
+                Path::new("/wd"),
+                &PathMap::default()
+            ),
+            Err(Error::InvalidEntry(_))
+        ));
+        assert!(matches!(
+            parse(
+                r#"[{"files": ["GH010084.mp4"]}, 84]"#,
+                Path::new("/wd"),
+                &PathMap::default()
+            ),
+            Err(Error::InvalidEntry(_))
+        ));
+        assert!(matches!(
+            parse(
+                r#"{"chapters": ["GH010084.mp4"]}"#,
+                Path::new("/wd"),
+                &PathMap::default()
+            ),
+            Err(Error::Json(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_remapped() {
+        let paths = PathMap(vec![r"C:\footage=/mnt/footage".parse().unwrap()]);
+        let groups = parse(
+            "C:\\footage\\GH010084.mp4\nGH020084.mp4\n",
+            Path::new("/wd"),
+            &paths,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![vec![
+                PathBuf::from("/mnt/footage/GH010084.mp4"),
+                PathBuf::from("/wd/GH020084.mp4")
+            ]],
+            chapter_paths(&groups)
+        );
+    }
+}
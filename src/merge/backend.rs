@@ -0,0 +1,70 @@
+use std::str::FromStr;
+
+use derive_more::Display;
+use log::*;
+
+use crate::merge::merger::{new_merger, NewMerger};
+use crate::merge::{
+    mp4box_preflight, preflight, Binaries, Error, FFmpegMerger, MP4BoxMerger, NativeMerger, Result,
+};
+
+/// The tool joining the chapters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum Backend {
+    /// ffmpeg's concat demuxer or protocol.
+    #[display(fmt = "ffmpeg")]
+    FFmpeg,
+    /// MP4Box `-cat` of GPAC, which needs no list of the chapters.
+    #[display(fmt = "mp4box")]
+    MP4Box,
+    /// Rewriting the MP4 boxes of the chapters without any tool, falling back to ffmpeg for the
+    /// groups it can't join.
+    #[display(fmt = "native")]
+    Native,
+}
+
+impl Backend {
+    /// The merger of the groups.
+    pub fn new_merger(&self) -> NewMerger {
+        match self {
+            Backend::FFmpeg => new_merger::<FFmpegMerger>,
+            Backend::MP4Box => new_merger::<MP4BoxMerger>,
+            Backend::Native => new_merger::<NativeMerger>,
+        }
+    }
+
+    /// Checks upfront that the binaries the backend merges with are there.
+    pub fn preflight(&self, binaries: &Binaries) -> Result<()> {
+        match self {
+            Backend::FFmpeg => preflight(binaries),
+            Backend::MP4Box => mp4box_preflight(binaries),
+            Backend::Native => match preflight(binaries) {
+                // only the groups falling back to it need ffmpeg
+                Err(err @ (Error::BinaryNotFound(..) | Error::BinaryTooOld(..))) => {
+                    warn!("{}, groups that can't be joined natively will fail", err);
+                    Ok(())
+                }
+                result => result,
+            },
+        }
+    }
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::FFmpeg
+    }
+}
+
+impl FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "ffmpeg" => Backend::FFmpeg,
+            "mp4box" => Backend::MP4Box,
+            "native" => Backend::Native,
+            _ => return Err(Error::InvalidBackend(s.into())),
+        })
+    }
+}
@@ -1,7 +1,16 @@
-use std::process::ChildStdout;
+use std::ffi::OsString;
+use std::process::{Child, ChildStdout, ExitStatus};
+use std::time::Instant;
 
-pub use crate::merge::ffmpeg::{FFmpegCommand, FFmpegCommandKind};
+use log::*;
+use sha2::{Digest, Sha256};
+
+pub use crate::merge::ffmpeg::{
+    FFmpegCommand, FFmpegCommandKind, FFmpegDerive, FFmpegHash, FFmpegMerge,
+};
 use crate::merge::Result;
+use crate::progress::{ChildProcess, ProcessEvent, Progress};
+use crate::system::{Clock as _, SystemClock};
 
 pub trait Command
 where
@@ -9,7 +18,87 @@ where
 {
     fn spawn(self) -> Result<Self>;
 
-    fn stdout(&mut self) -> Result<&mut ChildStdout>;
+    /// Takes the stdout of the spawned command, it can only be taken once.
+    fn stdout(&mut self) -> Result<ChildStdout>;
+
+    fn kill(&mut self) -> Result<()>;
 
     fn wait_success(self) -> Result<()>;
 }
+
+/// Reports when the child process of a command is spawned and when it exits to the progress of
+/// its group, from the `report_to` of the commands.
+pub(crate) struct ProcessReport {
+    progress: Box<dyn Progress>,
+    program: String,
+    args_hash: String,
+    spawned: Option<(ChildProcess, Instant)>,
+}
+
+impl ProcessReport {
+    pub fn new(progress: &dyn Progress, program: &str, args: &[OsString]) -> Self {
+        ProcessReport {
+            progress: progress.clone_box(),
+            program: program.into(),
+            args_hash: args_hash(args),
+            spawned: None,
+        }
+    }
+
+    pub fn spawned(&mut self, child: &Child) {
+        let process = ChildProcess {
+            pid: child.id(),
+            program: self.program.clone(),
+            args_hash: self.args_hash.clone(),
+        };
+        debug!("spawned {} with pid {}", process.program, process.pid);
+        self.progress
+            .process(&ProcessEvent::Spawned(process.clone()));
+        self.spawned = Some((process, SystemClock.instant()));
+    }
+
+    pub fn exited(&mut self, status: ExitStatus) {
+        // reported once, MP4Box is polled for exiting before it's waited for
+        if let Some((process, spawned)) = self.spawned.take() {
+            debug!(
+                "{} with pid {} exited {}",
+                process.program, process.pid, status
+            );
+            self.progress.process(&ProcessEvent::Exited {
+                process,
+                elapsed: SystemClock.instant() - spawned,
+                code: status.code(),
+                success: status.success(),
+            });
+        }
+    }
+}
+
+/// The first 16 hex digits of the SHA-256 of the `args`, each ended by a NUL.
+fn args_hash(args: &[OsString]) -> String {
+    let mut hasher = Sha256::new();
+    for arg in args {
+        hasher.update(arg.to_string_lossy().as_bytes());
+        hasher.update([0]);
+    }
+    let mut hash = format!("{:x}", hasher.finalize());
+    hash.truncate(16);
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_args_hash() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+
+        let hash = args_hash(&args(&["-i", "/movies/GH010084.MP4"]));
+        assert_eq!(16, hash.len());
+        assert_eq!(hash, args_hash(&args(&["-i", "/movies/GH010084.MP4"])));
+        assert_ne!(hash, args_hash(&args(&["-i", "/movies/GH010085.MP4"])));
+        // the arguments are told apart rather than concatenated
+        assert_ne!(hash, args_hash(&args(&["-i/movies/GH010084.MP4"])));
+    }
+}
@@ -0,0 +1,112 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use derive_more::Display;
+
+use crate::merge::{Error, Result};
+
+/// Height of the generated proxies, the 540p GoPro cameras record their own proxies in.
+pub const PROXY_HEIGHT: u32 = 540;
+/// Height of the generated thumbnails, the 160x120 of the ones GoPro cameras record.
+pub const THUMBNAIL_HEIGHT: u32 = 120;
+
+/// The file the audio of the merged movies is extracted into, from `--extract-audio`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Display)]
+pub enum AudioFormat {
+    /// The AAC the cameras record, copied as is into an .m4a.
+    #[display(fmt = "aac")]
+    Aac,
+    /// Decoded into 16-bit PCM, for audio editors without AAC support.
+    #[display(fmt = "wav")]
+    Wav,
+}
+
+impl FromStr for AudioFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "aac" => Ok(AudioFormat::Aac),
+            "wav" => Ok(AudioFormat::Wav),
+            _ => Err(Error::InvalidAudioFormat(s.into())),
+        }
+    }
+}
+
+/// A file made from a merged movie after merging it, with `--extract-telemetry`, `--proxy`,
+/// `--merge-proxies`, `--extract-gpx`, `--extract-audio` or `--pipeline`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Display)]
+pub enum Derived {
+    /// The raw GPMF telemetry track, for telemetry tools like gopro-telemetry.
+    #[display(fmt = "telemetry")]
+    Telemetry,
+    /// A low resolution copy for editing on slow machines, named like the proxies the cameras
+    /// record.
+    #[display(fmt = "proxy")]
+    Proxy,
+    /// A still of the first frame, named like the thumbnails the cameras record.
+    #[display(fmt = "thumbnail")]
+    Thumbnail,
+    /// The GPS track of the chapters, for mapping and sport tracking apps.
+    #[display(fmt = "gpx")]
+    Gpx,
+    /// The audio track, e.g. narration or a podcast recorded on the camera.
+    #[display(fmt = "audio")]
+    Audio(AudioFormat),
+}
+
+impl Derived {
+    /// Where the file derived from the merged movie at `output` is written, next to it, e.g.
+    /// GH000084.bin, GH000084.LRV, GH000084.THM, GH000084.gpx and GH000084.m4a for GH000084.MP4.
+    pub fn path(&self, output: &Path) -> PathBuf {
+        output.with_extension(match self {
+            Derived::Telemetry => "bin",
+            Derived::Proxy => "LRV",
+            Derived::Thumbnail => "THM",
+            Derived::Gpx => "gpx",
+            Derived::Audio(AudioFormat::Aac) => "m4a",
+            Derived::Audio(AudioFormat::Wav) => "wav",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derived_path() {
+        let output = Path::new("/movies/GH000084.MP4");
+        assert_eq!(
+            PathBuf::from("/movies/GH000084.bin"),
+            Derived::Telemetry.path(output)
+        );
+        assert_eq!(
+            PathBuf::from("/movies/GH000084.LRV"),
+            Derived::Proxy.path(output)
+        );
+        assert_eq!(
+            PathBuf::from("/movies/GH000084.THM"),
+            Derived::Thumbnail.path(output)
+        );
+        assert_eq!(
+            PathBuf::from("/movies/GH000084.gpx"),
+            Derived::Gpx.path(output)
+        );
+        assert_eq!(
+            PathBuf::from("/movies/GH000084.m4a"),
+            Derived::Audio(AudioFormat::Aac).path(output)
+        );
+        assert_eq!(
+            PathBuf::from("/movies/GH000084.wav"),
+            Derived::Audio(AudioFormat::Wav).path(output)
+        );
+    }
+
+    #[test]
+    fn test_audio_format_from_str() {
+        assert_eq!(AudioFormat::Aac, "aac".parse().unwrap());
+        assert_eq!(AudioFormat::Wav, "wav".parse().unwrap());
+        assert!("mp3".parse::<AudioFormat>().is_err());
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::*;
+use parking_lot::Mutex;
+
+use crate::audit::AuditHash;
+use crate::merge::ffmpeg::parser::Probe;
+use crate::merge::Result;
+use crate::proxy::VideoStream;
+
+/// First line of the cache file, a cache of another version is started over, e.g. v1 holding the
+/// durations of the first streams rather than of the containers.
+const HEADER: &str = "# gopro-merge probe cache v2";
+
+/// Name of the cache file in the user cache directory.
+const CACHE_FILE_NAME: &str = "probes.tsv";
+
+/// What a cached probe is valid for, the file changing in size or modification time probes it
+/// again.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    path: PathBuf,
+    size: u64,
+    /// Nanoseconds since the unix epoch.
+    modified: u128,
+}
+
+impl CacheKey {
+    /// The key of the file at `path` as it is now, none when it can't be read or its path can't
+    /// be written to the cache.
+    pub fn of(path: &Path) -> Option<Self> {
+        let path_str = path.to_str()?;
+        if path_str.contains(|c: char| c == '\t' || c.is_control()) {
+            return None;
+        }
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+        Some(CacheKey {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            modified: modified.as_nanos(),
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// The ffprobe results of the chapters of earlier runs, so that reruns, dry runs and resumed runs
+/// don't probe hundreds of large files again.
+///
+/// Every line of the file is a probe appended by a run, the path, size and modification time of
+/// the file probed followed by what was read from it, separated by tabs. The last line of a file
+/// wins. The `--audit-hash` digests of the files are cached the same way, on lines starting with
+/// the name of the hash, so that merging after a dry run doesn't hash the chapters again.
+#[derive(Debug, Clone)]
+pub struct ProbeCache {
+    probes: Arc<Mutex<HashMap<CacheKey, Probe>>>,
+    digests: Arc<Mutex<HashMap<(AuditHash, CacheKey), String>>>,
+    file: Arc<Mutex<File>>,
+}
+
+impl ProbeCache {
+    /// The cache in the user cache directory, e.g. ~/.cache/gopro-merge, if there's one.
+    pub fn default_path() -> Option<PathBuf> {
+        let cache_dir = if cfg!(windows) {
+            env::var_os("LOCALAPPDATA").map(PathBuf::from)
+        } else if cfg!(target_os = "macos") {
+            env::var_os("HOME").map(|home| Path::new(&home).join("Library").join("Caches"))
+        } else {
+            env::var_os("XDG_CACHE_HOME")
+                .filter(|dir| Path::new(dir).is_absolute())
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        }?;
+
+        Some(cache_dir.join("gopro-merge").join(CACHE_FILE_NAME))
+    }
+
+    /// Opens the cache at `path`, creating it when there's none yet.
+    pub fn open(path: &Path) -> Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let (probes, digests) = match contents.lines().next() {
+            Some(HEADER) => parse(&contents),
+            header => {
+                if header.is_some() {
+                    info!(
+                        "starting over the probe cache {} of another version",
+                        path.display()
+                    );
+                }
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(path, format!("{}\n", HEADER))?;
+                Default::default()
+            }
+        };
+        debug!(
+            "opened the probe cache {} with {} chapters",
+            path.display(),
+            probes.len()
+        );
+        let file = OpenOptions::new().append(true).open(path)?;
+
+        Ok(ProbeCache {
+            probes: Arc::new(Mutex::new(probes)),
+            digests: Arc::new(Mutex::new(digests)),
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<Probe> {
+        self.probes.lock().get(key).cloned()
+    }
+
+    /// Records the `probe` of the file of `key`, warning when it can't be written as the cache
+    /// only ever saves time.
+    pub fn insert(&self, key: CacheKey, probe: Probe) {
+        let line = format_line(&key, &probe);
+        if let Err(err) = self.file.lock().write_all(line.as_bytes()) {
+            warn!("not caching the probe of {}: {}", key.path.display(), err);
+        }
+        self.probes.lock().insert(key, probe);
+    }
+
+    pub fn digest(&self, hash: AuditHash, key: &CacheKey) -> Option<String> {
+        self.digests.lock().get(&(hash, key.clone())).cloned()
+    }
+
+    /// Records the `digest` of the file of `key`, warning when it can't be written like
+    /// [ProbeCache::insert].
+    pub fn insert_digest(&self, hash: AuditHash, key: CacheKey, digest: String) {
+        let line = format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            hash,
+            key.path.display(),
+            key.size,
+            key.modified,
+            digest
+        );
+        if let Err(err) = self.file.lock().write_all(line.as_bytes()) {
+            warn!(
+                "not caching the {} of {}: {}",
+                hash,
+                key.path.display(),
+                err
+            );
+        }
+        self.digests.lock().insert((hash, key), digest);
+    }
+}
+
+fn format_line(key: &CacheKey, probe: &Probe) -> String {
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".into());
+    let video = probe.video.unwrap_or_default();
+    let fields = [
+        key.path.display().to_string(),
+        key.size.to_string(),
+        key.modified.to_string(),
+        probe.duration.as_micros().to_string(),
+        optional(probe.creation_time.map(|time| {
+            time.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros()
+                .to_string()
+        })),
+        optional((!probe.telemetry.is_empty()).then(|| {
+            probe
+                .telemetry
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        })),
+        optional(
+            probe
+                .video
+                .map(|video| format!("{}x{}", video.width, video.height)),
+        ),
+        optional(video.bit_rate.map(|bit_rate| bit_rate.to_string())),
+        optional(
+            video
+                .frame_rate
+                .map(|rate| format!("{}/{}", rate.num, rate.den)),
+        ),
+        optional(
+            video
+                .avg_frame_rate
+                .map(|rate| format!("{}/{}", rate.num, rate.den)),
+        ),
+    ];
+
+    format!("{}\n", fields.join("\t"))
+}
+
+/// The probes and the digests of the cache `contents`, skipping the lines that can't be read,
+/// e.g. one cut short by a run killed while writing it.
+fn parse(contents: &str) -> CacheContents {
+    let (mut probes, mut digests) = CacheContents::default();
+    for (index, line) in contents.lines().enumerate().skip(1) {
+        if let Some((hash, key, digest)) = parse_digest_line(line) {
+            digests.insert((hash, key), digest);
+        } else if let Some((key, probe)) = parse_line(line) {
+            probes.insert(key, probe);
+        } else {
+            debug!("skipping line {} of the probe cache: {}", index + 1, line);
+        }
+    }
+    (probes, digests)
+}
+
+type CacheContents = (
+    HashMap<CacheKey, Probe>,
+    HashMap<(AuditHash, CacheKey), String>,
+);
+
+fn parse_digest_line(line: &str) -> Option<(AuditHash, CacheKey, String)> {
+    let fields = line.split('\t').collect::<Vec<_>>();
+    match fields.as_slice() {
+        [hash, path, size, modified, digest] => Some((
+            hash.parse().ok()?,
+            CacheKey {
+                path: path.into(),
+                size: size.parse().ok()?,
+                modified: modified.parse().ok()?,
+            },
+            digest.to_string(),
+        )),
+        _ => None,
+    }
+}
+
+fn parse_line(line: &str) -> Option<(CacheKey, Probe)> {
+    let fields = line.split('\t').collect::<Vec<_>>();
+    let [path, size, modified, duration, creation_time, telemetry, size_video, bit_rate, frame_rate, avg_frame_rate] =
+        match fields.as_slice() {
+            [a, b, c, d, e, f, g, h, i, j] => [*a, *b, *c, *d, *e, *f, *g, *h, *i, *j],
+            _ => return None,
+        };
+    let optional = |value: &str| (value != "-").then(|| value.to_string());
+
+    let key = CacheKey {
+        path: path.into(),
+        size: size.parse().ok()?,
+        modified: modified.parse().ok()?,
+    };
+    let video = match optional(size_video) {
+        Some(size_video) => {
+            let (width, height) = size_video.split_once('x')?;
+            Some(VideoStream {
+                width: width.parse().ok()?,
+                height: height.parse().ok()?,
+                bit_rate: match optional(bit_rate) {
+                    Some(bit_rate) => Some(bit_rate.parse().ok()?),
+                    None => None,
+                },
+                frame_rate: optional(frame_rate).and_then(|rate| rate.parse().ok()),
+                avg_frame_rate: optional(avg_frame_rate).and_then(|rate| rate.parse().ok()),
+            })
+        }
+        None => None,
+    };
+    let probe = Probe {
+        duration: Duration::from_micros(duration.parse().ok()?),
+        creation_time: match optional(creation_time) {
+            Some(micros) => {
+                Some(SystemTime::UNIX_EPOCH + Duration::from_micros(micros.parse().ok()?))
+            }
+            None => None,
+        },
+        telemetry: match optional(telemetry) {
+            Some(indices) => indices
+                .split(',')
+                .map(|index| index.parse().ok())
+                .collect::<Option<_>>()?,
+            None => vec![],
+        },
+        video,
+    };
+
+    Some((key, probe))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::frame_rate::FrameRate;
+
+    fn probe() -> Probe {
+        Probe {
+            duration: Duration::from_micros(5_458_333),
+            creation_time: Some(humantime::parse_rfc3339("2021-11-14T23:15:25Z").unwrap()),
+            telemetry: vec![3],
+            video: Some(VideoStream {
+                width: 1920,
+                height: 1080,
+                bit_rate: Some(45_000_000),
+                frame_rate: Some(FrameRate {
+                    num: 60000,
+                    den: 1001,
+                }),
+                avg_frame_rate: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_format_parse_line() {
+        let key = CacheKey {
+            path: "/movies/GH010084.mp4".into(),
+            size: 1024,
+            modified: 1_636_931_725_000_000_001,
+        };
+        let line = format_line(&key, &probe());
+        assert_eq!(
+            "/movies/GH010084.mp4\t1024\t1636931725000000001\t5458333\t1636931725000000\t3\t1920x1080\t45000000\t60000/1001\t-\n",
+            line
+        );
+        assert_eq!(Some((key.clone(), probe())), parse_line(line.trim_end()));
+
+        let bare = Probe {
+            duration: Duration::from_secs(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            Some((key.clone(), bare.clone())),
+            parse_line(format_line(&key, &bare).trim_end())
+        );
+
+        assert_eq!(None, parse_line("/movies/GH010084.mp4\t1024\t1636931725"));
+        assert_eq!(
+            None,
+            parse_line("/movies/GH010084.mp4\t1024\t1\tnot a duration\t-\t-\t-\t-\t-\t-")
+        );
+    }
+
+    #[test]
+    fn test_probe_cache() {
+        let dir = env::temp_dir().join("goprotest_probe_cache");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let chapter = dir.join("GH010084.mp4");
+        fs::write(&chapter, b"chapter").unwrap();
+        let path = dir.join("cache").join(CACHE_FILE_NAME);
+
+        let cache = ProbeCache::open(&path).unwrap();
+        let key = CacheKey::of(&chapter).unwrap();
+        assert_eq!(None, cache.get(&key));
+        cache.insert(key.clone(), probe());
+        assert_eq!(Some(probe()), cache.get(&key));
+
+        // read back by the next run
+        let cache = ProbeCache::open(&path).unwrap();
+        assert_eq!(Some(probe()), cache.get(&key));
+
+        // the digests are read back along with the probes
+        cache.insert_digest(AuditHash::Sha256, key.clone(), "e3b0c442".into());
+        let cache = ProbeCache::open(&path).unwrap();
+        assert_eq!(Some(probe()), cache.get(&key));
+        assert_eq!(
+            Some("e3b0c442".into()),
+            cache.digest(AuditHash::Sha256, &key)
+        );
+
+        // a chapter copied over again is probed and hashed again
+        fs::write(&chapter, b"chapter, longer").unwrap();
+        assert_eq!(None, cache.get(&CacheKey::of(&chapter).unwrap()));
+        assert_eq!(
+            None,
+            cache.digest(AuditHash::Sha256, &CacheKey::of(&chapter).unwrap())
+        );
+        assert_eq!(None, CacheKey::of(&dir.join("GH020084.mp4")));
+
+        // a cache of another version, or cut short, is started over
+        fs::write(&path, "# gopro-merge probe cache v1\nwhatever\n").unwrap();
+        let cache = ProbeCache::open(&path).unwrap();
+        assert_eq!(None, cache.get(&key));
+        assert_eq!(format!("{}\n", HEADER), fs::read_to_string(&path).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,70 +1,854 @@
 use derive_more::Display;
 use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
     fs::OpenOptions,
-    path::PathBuf,
+    io::{self, Write},
+    path::{Path, PathBuf},
     process::{Child, ChildStdout, Command as Process, Stdio},
+    time::{Duration, SystemTime},
 };
 
 use log::*;
 
-use crate::merge::command::Command;
-use crate::merge::{Error, Result};
+use crate::frame_rate::FrameRate;
+use crate::merge::command::{Command, ProcessReport};
+use crate::merge::ffmpeg::{CommandRunner, RunningContainer};
+use crate::merge::{
+    AudioFormat, Derived, Error, Result, StreamMapping, MP4BOX_PROCESS_NAME, PROXY_HEIGHT,
+    THUMBNAIL_HEIGHT,
+};
+use crate::progress::Progress;
 
 const FFMPEG_PROCESS_NAME: &str = "ffmpeg";
 const FFPROBE_PROCESS_NAME: &str = "ffprobe";
 
+/// Where the ffmpeg, ffprobe and MP4Box binaries are, looked up in PATH unless configured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binaries {
+    pub ffmpeg: PathBuf,
+    pub ffprobe: PathBuf,
+    /// Only used with `--backend mp4box`.
+    pub mp4box: PathBuf,
+    /// Runs them, e.g. in a container of `--ffmpeg-via`, whose paths the binaries are then.
+    pub runner: CommandRunner,
+}
+
+impl Default for Binaries {
+    fn default() -> Self {
+        Binaries {
+            ffmpeg: FFMPEG_PROCESS_NAME.into(),
+            ffprobe: FFPROBE_PROCESS_NAME.into(),
+            mp4box: MP4BOX_PROCESS_NAME.into(),
+            runner: CommandRunner::Host,
+        }
+    }
+}
+
+/// Metadata key of the embedded source chapter list.
+pub const PROVENANCE_TAG: &str = "gopro_merge_sources";
+
+/// How the source chapter list travels inside the merged movie.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Provenance {
+    /// A [PROVENANCE_TAG] metadata tag, rendered as `key=value`.
+    Metadata(String),
+    /// A text file attached to the container.
+    Attachment(PathBuf),
+}
+
+impl Provenance {
+    /// Matroska carries attachments, other containers get a metadata tag.
+    pub fn for_output(output: &Path, sources: String, attachment: PathBuf) -> Self {
+        match output
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("mkv") => Provenance::Attachment(attachment),
+            _ => Provenance::Metadata(format!("{}={}", PROVENANCE_TAG, sources)),
+        }
+    }
+}
+
+/// How the chapters of a group are joined, from `--no-temp-concat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConcatMethod {
+    /// ffmpeg's concat demuxer, reading a list of the chapters written to the workspace. Works
+    /// for any container.
+    Demuxer,
+    /// ffmpeg's concat protocol, which needs no list but only joins formats that can be
+    /// concatenated byte by byte like MPEG-TS.
+    Protocol,
+}
+
+impl Default for ConcatMethod {
+    fn default() -> Self {
+        ConcatMethod::Demuxer
+    }
+}
+
+/// The `path` as ffmpeg is given it. Canonicalized paths are verbatim on Windows, e.g.
+/// `\\?\C:\movies`, which ffmpeg fails to open, so the prefix is dropped unless the path is too
+/// long to open without it. Absolute paths that long get the prefix instead, e.g. a deep output
+/// directory with a long --name-template, ffmpeg fails with "No such file or directory" otherwise.
+#[cfg(windows)]
+pub fn ffmpeg_path(path: &Path) -> Cow<'_, Path> {
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
+    use std::path::{Component, Prefix};
+
+    let (verbatim, replacement) = match path.components().next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::VerbatimDisk(..) => (r"\\?\", ""),
+            Prefix::VerbatimUNC(..) => (r"\\?\UNC\", r"\\"),
+            Prefix::Disk(..) | Prefix::UNC(..) => return verbatim_path(path),
+            _ => return Cow::Borrowed(path),
+        },
+        _ => return Cow::Borrowed(path),
+    };
+    let wide = replacement
+        .encode_utf16()
+        .chain(path.as_os_str().encode_wide().skip(verbatim.len()))
+        .collect::<Vec<_>>();
+    if wide.len() >= MAX_PATH {
+        return Cow::Borrowed(path);
+    }
+
+    Cow::Owned(OsString::from_wide(&wide).into())
+}
+
+/// Longest path Windows opens without the verbatim prefix.
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// The absolute `path` with the verbatim prefix if it's too long to open without it, e.g.
+/// `\\?\C:\movies` for `C:\movies`. Windows doesn't normalize verbatim paths, so ones with
+/// `..` are left as they are and the separators are made backslashes.
+#[cfg(windows)]
+fn verbatim_path(path: &Path) -> Cow<'_, Path> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::{Component, Prefix};
+
+    if path.as_os_str().encode_wide().count() < MAX_PATH {
+        return Cow::Borrowed(path);
+    }
+
+    let mut verbatim = OsString::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => match prefix.kind() {
+                Prefix::Disk(disk) => verbatim.push(format!(r"\\?\{}:", disk as char)),
+                Prefix::UNC(server, share) => {
+                    verbatim.push(r"\\?\UNC\");
+                    verbatim.push(server);
+                    verbatim.push(r"\");
+                    verbatim.push(share);
+                }
+                _ => return Cow::Borrowed(path),
+            },
+            Component::RootDir | Component::CurDir => {}
+            Component::Normal(name) => {
+                verbatim.push(r"\");
+                verbatim.push(name);
+            }
+            Component::ParentDir => return Cow::Borrowed(path),
+        }
+    }
+
+    Cow::Owned(verbatim.into())
+}
+
+/// The `path` as ffmpeg is given it.
+#[cfg(not(windows))]
+pub fn ffmpeg_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// What ffmpeg reads from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FFmpegInput {
+    File(PathBuf),
+    /// A concat demuxer list of chapters.
+    ConcatList(PathBuf),
+    /// Chapters joined with the concat protocol.
+    ConcatProtocol(Vec<PathBuf>),
+}
+
+impl FFmpegInput {
+    /// The arguments selecting the demuxer, before `-i`.
+    fn demuxer(&self) -> &'static [&'static str] {
+        match self {
+            FFmpegInput::ConcatList(..) => &["-f", "concat", "-safe", "0"],
+            FFmpegInput::File(..) | FFmpegInput::ConcatProtocol(..) => &[],
+        }
+    }
+
+    /// The url passed to `-i`.
+    pub fn url(&self) -> OsString {
+        match self {
+            FFmpegInput::File(path) | FFmpegInput::ConcatList(path) => {
+                ffmpeg_path(path).as_os_str().to_os_string()
+            }
+            FFmpegInput::ConcatProtocol(paths) => {
+                let mut url = OsString::from("concat:");
+                for (index, path) in paths.iter().enumerate() {
+                    if index > 0 {
+                        url.push("|");
+                    }
+                    url.push(ffmpeg_path(path).as_os_str());
+                }
+                url
+            }
+        }
+    }
+}
+
+/// Everything a single ffmpeg merge invocation is built from.
+pub struct FFmpegMerge {
+    /// The chapters, joined by the concat demuxer or protocol.
+    pub input: FFmpegInput,
+    pub output: PathBuf,
+    /// Subtitles file muxed as an additional track.
+    pub subtitles: Option<PathBuf>,
+    /// ffmpeg metadata file with the chapter markers, read after the subtitles.
+    pub chapters: Option<PathBuf>,
+    pub streams: StreamMapping,
+    /// Overwrite the output if it exists, ffmpeg fails instead otherwise.
+    pub overwrite: bool,
+    /// The source chapter list embedded into the output.
+    pub provenance: Option<Provenance>,
+    /// Indices of the GPMF telemetry streams of the chapters, tagged explicitly so that the muxer
+    /// doesn't drop them for an unknown codec tag. Only known when the chapters were probed.
+    pub telemetry: Vec<usize>,
+    /// An [ACTIVITY_TAG](crate::activity::ACTIVITY_TAG) metadata tag, rendered as `key=value`.
+    pub activity: Option<String>,
+    /// Recording time of the first chapter, written as the `creation_time` of the output as
+    /// chapters resolved from their mtime have none to copy.
+    pub creation_time: Option<SystemTime>,
+    /// The title of the merged movie from `--title-template`, the title of the first chapter is
+    /// kept otherwise.
+    pub title: Option<String>,
+    /// Re-encode the video to a constant frame rate instead of copying it, from `--cfr`.
+    pub cfr: Option<ConstantFrameRate>,
+    /// Fade the chapters into each other, read one by one instead of from the `input`, from
+    /// `--crossfade`.
+    pub crossfade: Option<Crossfade>,
+}
+
+/// The video encoding of a merge converting variable frame rate chapters to a constant rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConstantFrameRate {
+    /// The ffmpeg encoder, of the codec of the chapters, e.g. libx264.
+    pub encoder: &'static str,
+    /// The nominal frame rate of the chapters, frames being duplicated or dropped to match it.
+    pub frame_rate: FrameRate,
+}
+
+impl ConstantFrameRate {
+    /// Visually lossless, a re-encode of a whole movie being slow enough already.
+    const CRF: &'static str = "18";
+}
+
+/// The re-encoding of a merge dissolving the video and crossfading the audio of every chapter
+/// into the next one, masking the pops some cameras record at the chapter splits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crossfade {
+    pub chapters: Vec<PathBuf>,
+    /// Where the fade into every chapter after the first starts in the merged movie, each fade
+    /// shortening it by the `duration`.
+    pub offsets: Vec<Duration>,
+    pub duration: Duration,
+    /// The ffmpeg encoder of the video, of the codec of the chapters, e.g. libx264.
+    pub encoder: &'static str,
+    /// Whether the chapters have audio to crossfade, timelapses have none.
+    pub audio: bool,
+}
+
+impl Crossfade {
+    /// The `-filter_complex` graph joining the chapters into the `[v]` and `[a]` outputs.
+    pub fn filter_graph(&self) -> String {
+        let seconds = format!("{:.3}", self.duration.as_secs_f64());
+        let last = self.chapters.len().saturating_sub(1);
+        // the chapters faded so far, up to the one at `index`
+        let faded = |kind: &str, index: usize| match index {
+            0 => format!("{}0", kind),
+            _ if index == last => kind.to_string(),
+            _ => format!("{}faded{}", kind, index),
+        };
+
+        // xfade fails on inputs of different time bases, which chapters can have
+        let mut filters = (0..self.chapters.len())
+            .map(|index| format!("[{}:v]settb=AVTB[v{}]", index, index))
+            .collect::<Vec<_>>();
+        for (index, offset) in (1..=last).zip(&self.offsets) {
+            filters.push(format!(
+                "[{}][v{}]xfade=transition=fade:duration={}:offset={:.3}[{}]",
+                faded("v", index - 1),
+                index,
+                seconds,
+                offset.as_secs_f64(),
+                faded("v", index)
+            ));
+        }
+        if self.audio {
+            filters.extend((1..=last).map(|index| {
+                format!(
+                    "[{}][{}:a]acrossfade=d={}[{}]",
+                    match index {
+                        1 => "0:a".to_string(),
+                        _ => faded("a", index - 1),
+                    },
+                    index,
+                    seconds,
+                    faded("a", index)
+                )
+            }));
+        }
+        filters.join(";")
+    }
+}
+
+impl FFmpegMerge {
+    /// The whole command line, e.g. for previewing what would be executed.
+    pub fn command_line(&self, binaries: &Binaries) -> Vec<String> {
+        std::iter::once(binaries.ffmpeg.as_os_str().to_os_string())
+            .chain(self.args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    pub fn args(&self) -> Vec<OsString> {
+        let telemetry_maps = self
+            .telemetry
+            .iter()
+            .map(|index| format!("0:{}", index))
+            .collect::<Vec<_>>();
+        // -map 0 keeps the stream indices of the first chapter, the telemetry is the only data
+        // otherwise
+        let telemetry_tags = if self.streams.copy_unknown {
+            self.telemetry
+                .iter()
+                .map(|index| format!("-tag:{}", index))
+                .collect::<Vec<_>>()
+        } else if self.telemetry.is_empty() {
+            vec![]
+        } else {
+            vec!["-tag:d".to_string()]
+        };
+        // Matroska and the like hold neither the telemetry nor the timecode track, their muxers
+        // fail on the data streams and the options of the mp4 one
+        let quicktime = is_quicktime(&self.output);
+        let data = self.streams.data && quicktime;
+        let input = self.input.url();
+        let subtitles = self.subtitles.as_deref().map(ffmpeg_path);
+        let chapters = self.chapters.as_deref().map(ffmpeg_path);
+        let crossfaded = self
+            .crossfade
+            .iter()
+            .flat_map(|crossfade| &crossfade.chapters)
+            .map(|chapter| ffmpeg_path(chapter))
+            .collect::<Vec<_>>();
+        let filter_graph = self.crossfade.as_ref().map(Crossfade::filter_graph);
+        // the indices of the subtitles and the chapter markers inputs, after the chapters
+        let subtitles_input = crossfaded.len().max(1);
+        let chapters_input = (subtitles_input + subtitles.iter().count()).to_string();
+        let subtitles_input = subtitles_input.to_string();
+        let output = ffmpeg_path(&self.output);
+        // e.g. 2021-11-14T23:15:25.000000Z, as ffmpeg writes it
+        let creation_time = self
+            .creation_time
+            .map(|time| format!("creation_time={}", humantime::format_rfc3339_micros(time)));
+        let title = self.title.as_ref().map(|title| format!("title={}", title));
+
+        let cfr = self.cfr.map(|cfr| {
+            let frame_rate = format!("{}/{}", cfr.frame_rate.num, cfr.frame_rate.den);
+            (cfr.encoder, frame_rate)
+        });
+
+        let mut args: Vec<&OsStr> = vec![];
+        if self.streams.copy_unknown {
+            args.push(OsStr::new("-copy_unknown"));
+        }
+
+        let overwrite = OsStr::new(if self.overwrite { "-y" } else { "-n" });
+        if crossfaded.is_empty() {
+            args.extend(self.input.demuxer().iter().map(OsStr::new));
+            args.extend([overwrite, OsStr::new("-i"), input.as_os_str()]);
+        } else {
+            args.push(overwrite);
+        }
+        for chapter in &crossfaded {
+            args.extend([OsStr::new("-i"), chapter.as_os_str()]);
+        }
+
+        if let Some(subtitles) = &subtitles {
+            args.extend([OsStr::new("-i"), subtitles.as_os_str()]);
+        }
+        if let Some(chapters) = &chapters {
+            args.extend([OsStr::new("-i"), chapters.as_os_str()]);
+        }
+
+        if let Some(filter_graph) = &filter_graph {
+            // the data streams can't be faded, --crossfade is used with --drop-data-streams
+            args.extend(["-filter_complex", filter_graph.as_str(), "-map", "[v]"].map(OsStr::new));
+            if self
+                .crossfade
+                .as_ref()
+                .map_or(false, |crossfade| crossfade.audio)
+            {
+                args.extend(["-map", "[a]"].map(OsStr::new));
+            }
+        } else if self.streams.copy_unknown {
+            args.extend(["-map", "0"].map(OsStr::new));
+            if !data {
+                args.extend(["-map", "-0:d"].map(OsStr::new));
+            }
+        } else {
+            // the timecode and the codec-less fdsc and SOS tracks of the GoPros fail the muxer
+            args.extend(["-map", "0:v", "-map", "0:a?"].map(OsStr::new));
+            if data {
+                for map in &telemetry_maps {
+                    args.extend([OsStr::new("-map"), OsStr::new(map)]);
+                }
+            }
+        }
+        let timecode_copied = self.streams.copy_unknown && data;
+        if self.streams.timecode && quicktime && !timecode_copied && filter_graph.is_none() {
+            // the muxer recreates the timecode track from the timecode of the video stream
+            args.extend(["-write_tmcd", "on"].map(OsStr::new));
+        }
+
+        if self.subtitles.is_some() {
+            args.extend(
+                [
+                    "-map",
+                    subtitles_input.as_str(),
+                    "-c:s",
+                    subtitles_codec(&self.output),
+                ]
+                .map(OsStr::new),
+            );
+        }
+
+        args.extend(["-c", "copy"].map(OsStr::new));
+        if let Some((encoder, frame_rate)) = &cfr {
+            args.extend(
+                [
+                    "-c:v",
+                    encoder,
+                    "-crf",
+                    ConstantFrameRate::CRF,
+                    "-vsync",
+                    "cfr",
+                    "-r",
+                    frame_rate,
+                ]
+                .map(OsStr::new),
+            );
+            args.extend(hevc_tag(encoder).iter().map(OsStr::new));
+        }
+        if let Some(crossfade) = &self.crossfade {
+            if cfr.is_none() {
+                args.extend(
+                    ["-c:v", crossfade.encoder, "-crf", ConstantFrameRate::CRF].map(OsStr::new),
+                );
+                args.extend(hevc_tag(crossfade.encoder).iter().map(OsStr::new));
+            }
+            if crossfade.audio {
+                args.extend(["-c:a", "aac"].map(OsStr::new));
+            }
+        }
+        if data && filter_graph.is_none() {
+            for tag in &telemetry_tags {
+                args.extend([tag.as_str(), "gpmd"].map(OsStr::new));
+            }
+        }
+
+        let tags = match &self.provenance {
+            Some(Provenance::Metadata(tag)) => Some(tag),
+            _ => None,
+        }
+        .into_iter()
+        .chain(&self.activity)
+        .collect::<Vec<_>>();
+        if !tags.is_empty() && quicktime {
+            // custom keys are dropped by the mp4 muxer unless asked for
+            args.extend(["-movflags", "use_metadata_tags"].map(OsStr::new));
+        }
+        for tag in tags {
+            args.extend(["-metadata", tag.as_str()].map(OsStr::new));
+        }
+
+        let attachment = match &self.provenance {
+            Some(Provenance::Attachment(path)) => Some(ffmpeg_path(path)),
+            _ => None,
+        };
+        if let Some(attachment) = &attachment {
+            args.extend([
+                OsStr::new("-attach"),
+                attachment.as_os_str(),
+                OsStr::new("-metadata:s:t"),
+                OsStr::new("mimetype=text/plain"),
+            ]);
+        }
+
+        // the global metadata of the first chapter, its creation_time and the like
+        args.extend(["-map_metadata", "0"].map(OsStr::new));
+        if chapters.is_some() {
+            args.extend(["-map_chapters", chapters_input.as_str()].map(OsStr::new));
+        }
+        if let Some(creation_time) = &creation_time {
+            args.extend(["-metadata", creation_time.as_str()].map(OsStr::new));
+        }
+        if let Some(title) = &title {
+            args.extend(["-metadata", title.as_str()].map(OsStr::new));
+        }
+
+        if let Some(format) = output_format(&self.output) {
+            args.extend(["-f", format].map(OsStr::new));
+        }
+        args.push(output.as_os_str());
+        args.extend(["-loglevel", "error", "-progress", "pipe:1"].map(OsStr::new));
+
+        args.into_iter().map(OsStr::to_os_string).collect()
+    }
+}
+
+/// The muxer of outputs whose extension ffmpeg doesn't know, the .360 of the MAX being an MP4 with
+/// the two lenses of the equi-angular cubemap as separate video tracks.
+fn output_format(output: &Path) -> Option<&'static str> {
+    output
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .filter(|ext| ext.eq_ignore_ascii_case("360"))
+        .map(|_| "mp4")
+}
+
+/// QuickTime and the like only play HEVC tagged hvc1, re-encoded with the `encoder`.
+fn hevc_tag(encoder: &str) -> &'static [&'static str] {
+    match encoder {
+        "libx265" => &["-tag:v", "hvc1"],
+        _ => &[],
+    }
+}
+
+/// Hashes the video packets of `input` with the hash muxer, copying them the same way merging does.
+pub struct FFmpegHash {
+    /// The merged movie or its chapters, hashing the packets of all the chapters in order.
+    pub input: FFmpegInput,
+    /// Where the hash muxer writes the `SHA256=<hex>` line.
+    pub output: PathBuf,
+}
+
+impl FFmpegHash {
+    pub fn args(&self) -> Vec<OsString> {
+        let input = self.input.url();
+        let output = ffmpeg_path(&self.output);
+
+        let mut args = self
+            .input
+            .demuxer()
+            .iter()
+            .map(OsStr::new)
+            .collect::<Vec<_>>();
+        args.extend([OsStr::new("-i"), input.as_os_str()]);
+        args.extend(
+            [
+                "-map", "0:v", "-c", "copy", "-f", "hash", "-hash", "sha256", "-y",
+            ]
+            .map(OsStr::new),
+        );
+        args.push(output.as_os_str());
+        args.extend(["-loglevel", "error", "-progress", "pipe:1"].map(OsStr::new));
+
+        args.into_iter().map(OsStr::to_os_string).collect()
+    }
+}
+
+/// Makes the [Derived] file of a merged movie next to it.
+pub enum FFmpegDerive {
+    /// Copies out the GPMF telemetry stream at the `stream` index.
+    Telemetry {
+        input: PathBuf,
+        stream: usize,
+    },
+    Proxy {
+        input: PathBuf,
+    },
+    /// A still of the first frame, for merged movies whose first chapter has no thumbnail.
+    Thumbnail {
+        input: PathBuf,
+    },
+    /// The first audio stream, copied or decoded depending on the `format`.
+    Audio {
+        input: PathBuf,
+        format: AudioFormat,
+    },
+}
+
+impl FFmpegDerive {
+    pub fn derived(&self) -> Derived {
+        match self {
+            FFmpegDerive::Telemetry { .. } => Derived::Telemetry,
+            FFmpegDerive::Proxy { .. } => Derived::Proxy,
+            FFmpegDerive::Thumbnail { .. } => Derived::Thumbnail,
+            FFmpegDerive::Audio { format, .. } => Derived::Audio(*format),
+        }
+    }
+
+    /// The merged movie.
+    pub fn input(&self) -> &Path {
+        match self {
+            FFmpegDerive::Telemetry { input, .. }
+            | FFmpegDerive::Proxy { input }
+            | FFmpegDerive::Thumbnail { input }
+            | FFmpegDerive::Audio { input, .. } => input,
+        }
+    }
+
+    pub fn output(&self) -> PathBuf {
+        self.derived().path(self.input())
+    }
+
+    pub fn args(&self) -> Vec<OsString> {
+        let input = ffmpeg_path(self.input()).as_os_str().to_os_string();
+        let output = self.output();
+
+        let mut args = vec![OsString::from("-i"), input];
+        match self {
+            FFmpegDerive::Telemetry { stream, .. } => {
+                let stream = format!("0:{}", stream);
+                args.extend(
+                    ["-map", stream.as_str(), "-c", "copy", "-f", "rawvideo"].map(OsString::from),
+                );
+            }
+            // the audio is optional, e.g. for timelapses, and the extension unknown to ffmpeg
+            FFmpegDerive::Proxy { .. } => {
+                let scale = format!("scale=-2:{}", PROXY_HEIGHT);
+                args.extend(
+                    [
+                        "-map",
+                        "0:v:0",
+                        "-map",
+                        "0:a:0?",
+                        "-vf",
+                        scale.as_str(),
+                        "-c:v",
+                        "libx264",
+                        "-preset",
+                        "veryfast",
+                        "-crf",
+                        "28",
+                        "-c:a",
+                        "aac",
+                        "-b:a",
+                        "128k",
+                        "-f",
+                        "mp4",
+                    ]
+                    .map(OsString::from),
+                );
+            }
+            // the cameras record their thumbnails as JPEG whatever the extension
+            FFmpegDerive::Thumbnail { .. } => {
+                let scale = format!("scale=-2:{}", THUMBNAIL_HEIGHT);
+                args.extend(
+                    [
+                        "-map",
+                        "0:v:0",
+                        "-frames:v",
+                        "1",
+                        "-vf",
+                        scale.as_str(),
+                        "-c:v",
+                        "mjpeg",
+                        "-f",
+                        "image2",
+                    ]
+                    .map(OsString::from),
+                );
+            }
+            // m4a is the ipod muxer to ffmpeg
+            FFmpegDerive::Audio { format, .. } => {
+                let (codec, muxer) = match format {
+                    AudioFormat::Aac => ("copy", "ipod"),
+                    AudioFormat::Wav => ("pcm_s16le", "wav"),
+                };
+                args.extend(
+                    ["-map", "0:a:0", "-vn", "-c:a", codec, "-f", muxer].map(OsString::from),
+                );
+            }
+        }
+        args.push("-y".into());
+        args.push(ffmpeg_path(&output).as_os_str().to_os_string());
+        args.extend(["-loglevel", "error", "-progress", "pipe:1"].map(OsString::from));
+
+        args
+    }
+}
+
+// mp4 and mov only support the 3GPP timed text subtitles
+fn subtitles_codec(output: &Path) -> &'static str {
+    if is_quicktime(output) {
+        "mov_text"
+    } else {
+        "srt"
+    }
+}
+
+fn is_quicktime(output: &Path) -> bool {
+    matches!(
+        output
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase())
+            .as_deref(),
+        Some("mp4") | Some("mov") | Some("360")
+    )
+}
+
 #[derive(Display)]
 pub enum FFmpegCommandKind {
     #[display(fmt = "ffmpeg")]
-    FFmpeg(PathBuf, PathBuf, PathBuf),
+    FFmpeg(Box<FFmpegMerge>),
     #[display(fmt = "ffprobe")]
     FFprobe(PathBuf),
+    #[display(fmt = "ffmpeg hash")]
+    Hash(FFmpegHash),
+    #[display(fmt = "ffmpeg {}", "_0.derived()")]
+    Derive(FFmpegDerive),
 }
 
 impl FFmpegCommandKind {
-    fn args(&self) -> Vec<&str> {
+    fn args(&self) -> Vec<OsString> {
         match self {
-            FFmpegCommandKind::FFmpeg(input, output, _) => {
-                vec![
-                    "-f",
-                    "concat",
-                    "-safe",
-                    "0",
-                    "-y",
-                    "-i",
-                    input.as_os_str().to_str().unwrap(),
-                    "-c",
-                    "copy",
-                    output.as_os_str().to_str().unwrap(),
-                    "-loglevel",
-                    "error",
-                    "-progress",
-                    "pipe:1",
-                ]
-            }
+            FFmpegCommandKind::FFmpeg(merge) => merge.args(),
+            FFmpegCommandKind::Hash(hash) => hash.args(),
+            FFmpegCommandKind::Derive(derive) => derive.args(),
             FFmpegCommandKind::FFprobe(input) => {
-                vec![
-                    "-i",
-                    input.as_os_str().to_str().unwrap(),
-                    "-show_streams",
-                    "-loglevel",
-                    "error",
+                let input = ffmpeg_path(input);
+                [
+                    OsStr::new("-i"),
+                    input.as_os_str(),
+                    OsStr::new("-print_format"),
+                    OsStr::new("json"),
+                    OsStr::new("-show_format"),
+                    OsStr::new("-show_streams"),
+                    OsStr::new("-loglevel"),
+                    OsStr::new("error"),
                 ]
+                .iter()
+                .map(|arg| arg.to_os_string())
+                .collect()
             }
         }
     }
 
     fn process_name(&self) -> &'static str {
         match self {
-            FFmpegCommandKind::FFmpeg(..) => FFMPEG_PROCESS_NAME,
+            FFmpegCommandKind::FFmpeg(..)
+            | FFmpegCommandKind::Hash(..)
+            | FFmpegCommandKind::Derive(..) => FFMPEG_PROCESS_NAME,
             FFmpegCommandKind::FFprobe(..) => FFPROBE_PROCESS_NAME,
         }
     }
 
-    fn stderr_path(&self) -> Option<&PathBuf> {
+    fn program<'a>(&self, binaries: &'a Binaries) -> &'a Path {
         match self {
-            FFmpegCommandKind::FFmpeg(_, _, stderr) => Some(stderr),
-            FFmpegCommandKind::FFprobe(..) => None,
+            FFmpegCommandKind::FFmpeg(..)
+            | FFmpegCommandKind::Hash(..)
+            | FFmpegCommandKind::Derive(..) => &binaries.ffmpeg,
+            FFmpegCommandKind::FFprobe(..) => &binaries.ffprobe,
+        }
+    }
+}
+
+/// Every child is spawned through here so that it never inherits the stdio handles of the tool,
+/// stdout carries the json events and a child writing to it would corrupt the stream.
+pub(crate) fn isolated(program: impl AsRef<OsStr>) -> Process {
+    let mut process = Process::new(program);
+    process
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    process
+}
+
+/// Oldest ffmpeg release the commands are known to work with, as major and minor version.
+pub const MINIMUM_FFMPEG_VERSION: (u64, u64) = (4, 0);
+
+/// Checks upfront that ffmpeg exists and, like ffprobe if there is one, is at least
+/// [MINIMUM_FFMPEG_VERSION], instead of every group failing to spawn it.
+pub fn preflight(binaries: &Binaries) -> Result<()> {
+    binaries.runner.preflight()?;
+    check_version(binaries, &binaries.ffmpeg)?;
+    match check_version(binaries, &binaries.ffprobe) {
+        // merging works without probing
+        Err(Error::BinaryNotFound(..)) => Ok(()),
+        result => result,
+    }
+}
+
+fn check_version(binaries: &Binaries, binary: &Path) -> Result<()> {
+    let (mut process, _) = binaries.runner.command(binary);
+    let output = match process.arg("-version").output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(Error::BinaryNotFound(binary.to_path_buf()))
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match parse_version(&stdout) {
+        Some(version) if version < MINIMUM_FFMPEG_VERSION => Err(Error::BinaryTooOld(
+            binary.to_path_buf(),
+            format!("{}.{}", version.0, version.1),
+        )),
+        Some(version) => {
+            debug!("{} is version {:?}", binary.display(), version);
+            Ok(())
+        }
+        // e.g. the N-104000-g0123abcd of builds from the development branch, newer than any release
+        None => {
+            warn!(
+                "unknown version of {}: {:?}",
+                binary.display(),
+                stdout.lines().next()
+            );
+            Ok(())
+        }
+    }
+}
+
+/// The major and minor version of a `<ffmpeg|ffprobe> version 4.4.2-0ubuntu0.22.04.1 ...` line.
+fn parse_version(output: &str) -> Option<(u64, u64)> {
+    let version = output
+        .lines()
+        .next()?
+        .split_whitespace()
+        .skip_while(|word| *word != "version")
+        .nth(1)?;
+    let version = version.strip_prefix('n').unwrap_or(version);
+
+    let mut numbers = version.split(|c: char| !c.is_ascii_digit());
+    let major = numbers.next()?.parse().ok()?;
+    let minor = numbers
+        .next()
+        .and_then(|minor| minor.parse().ok())
+        .unwrap_or(0);
+    Some((major, minor))
+}
+
+/// Whether ffprobe can be spawned at all, without it durations can't be known upfront.
+pub fn ffprobe_available(binaries: &Binaries) -> bool {
+    let (mut process, _) = binaries.runner.command(&binaries.ffprobe);
+    match process.arg("-version").stdout(Stdio::null()).status() {
+        Ok(_) => true,
+        Err(err) => {
+            debug!("spawning {}: {}", binaries.ffprobe.display(), err);
+            err.kind() != io::ErrorKind::NotFound
         }
     }
 }
@@ -73,78 +857,1084 @@ pub struct FFmpegCommand {
     kind: FFmpegCommandKind,
     process: Process,
     child: Option<Child>,
+    /// Where the stderr of the command goes, to null unless logged.
+    log: Option<PathBuf>,
+    /// The container the command runs in with `--ffmpeg-via`.
+    container: Option<RunningContainer>,
+    report: Option<ProcessReport>,
 }
 
 impl FFmpegCommand {
-    pub fn new(kind: FFmpegCommandKind) -> Result<Self> {
+    pub fn new(kind: FFmpegCommandKind, binaries: &Binaries) -> Result<Self> {
         let args = kind.args();
 
         debug!(
             "Creating {} command with args {:?}",
-            kind.process_name(),
+            kind.program(binaries).display(),
             &args[..]
         );
 
-        let stderr = kind
-            .stderr_path()
-            .map(|path| {
-                info!("creating ffmpeg stderr file at {}", path.display());
-                OpenOptions::new().create(true).write(true).open(path)
-            })
-            .transpose()?
-            .map_or_else(Stdio::null, Stdio::from);
-
-        let mut process = Process::new(kind.process_name());
-        process.args(&args).stdout(Stdio::piped()).stderr(stderr);
+        let (mut process, container) = binaries.runner.command(kind.program(binaries));
+        process.args(&args);
 
         Ok(FFmpegCommand {
             kind,
             process,
             child: None,
+            log: None,
+            container,
+            report: None,
         })
     }
+
+    /// Appends the stderr of the command to the `log` of its group, after its command line, the
+    /// log holding every command run for the group in order.
+    pub fn log_to(mut self, log: &Path) -> Result<Self> {
+        info!("logging the {} stderr to {}", self.kind, log.display());
+        let mut file = OpenOptions::new().create(true).append(true).open(log)?;
+        writeln!(file, "{:?}", self.process)?;
+
+        self.process.stderr(file);
+        self.log = Some(log.to_path_buf());
+        Ok(self)
+    }
+
+    /// Reports the child process spawning and exiting to the `progress` of its group.
+    pub fn report_to(mut self, progress: &dyn Progress) -> Self {
+        let program = self.kind.process_name();
+        self.report = Some(ProcessReport::new(progress, program, &self.kind.args()));
+        self
+    }
 }
 
 impl Command for FFmpegCommand {
     fn spawn(mut self) -> Result<Self> {
-        self.child = Some(self.process.spawn()?);
+        let child = self.process.spawn()?;
+        if let Some(report) = &mut self.report {
+            report.spawned(&child);
+        }
+        self.child = Some(child);
         Ok(self)
     }
 
-    fn stdout(&mut self) -> Result<&mut ChildStdout> {
+    fn stdout(&mut self) -> Result<ChildStdout> {
         let stdout = self
             .child
             .as_mut()
             .ok_or_else(|| Error::CommandNotSpawned(self.kind.process_name().into()))?
             .stdout
-            .as_mut()
+            .take()
             .ok_or_else(|| Error::NoStdout(self.kind.process_name().into()))?;
 
         Ok(stdout)
     }
 
-    fn wait_success(self) -> Result<()> {
+    fn kill(&mut self) -> Result<()> {
+        let child = self
+            .child
+            .as_mut()
+            .ok_or_else(|| Error::CommandNotSpawned(self.kind.process_name().into()))?;
+        // killing the engine client leaves the container running
+        if let Some(container) = &self.container {
+            container.kill();
+        }
+        child.kill().map_err(From::from)
+    }
+
+    fn wait_success(mut self) -> Result<()> {
         let exit_status = self
             .child
             .ok_or_else(|| Error::CommandNotSpawned(self.kind.process_name().into()))?
             .wait()?;
+        if let Some(report) = &mut self.report {
+            report.exited(exit_status);
+        }
 
         if exit_status.success() {
-            Ok(())
-        } else {
-            Err(Error::FailedToConvert(
-                match &self.kind {
-                    kind @ FFmpegCommandKind::FFmpeg(input, _, _)
-                    | kind @ FFmpegCommandKind::FFprobe(input) => {
-                        format!(
-                            "{} {}",
-                            kind,
-                            input.as_os_str().to_str().unwrap().to_owned(),
-                        )
-                    }
-                },
-                exit_status,
-            ))
+            return Ok(());
+        }
+
+        let movie = match &self.kind {
+            kind @ FFmpegCommandKind::FFmpeg(merge) => {
+                format!("{} {}", kind, merge.input.url().to_string_lossy())
+            }
+            kind @ FFmpegCommandKind::Hash(FFmpegHash { input, .. }) => {
+                format!("{} {}", kind, input.url().to_string_lossy())
+            }
+            FFmpegCommandKind::FFprobe(input) => {
+                return Err(Error::FailedToProbe(input.clone(), exit_status, self.log))
+            }
+            kind @ FFmpegCommandKind::Derive(derive) => {
+                format!("{} {}", kind, derive.input().display())
+            }
+        };
+        Err(Error::FailedToConvert(movie, exit_status, self.log))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::merge::ffmpeg::MediaInfo;
+
+    #[test]
+    fn test_ffmpeg_merge_args() {
+        let mut merge = FFmpegMerge {
+            input: FFmpegInput::ConcatList("/tmp/.0084.txt".into()),
+            output: "/movies/GH000084.MP4".into(),
+            subtitles: None,
+            chapters: None,
+            streams: StreamMapping::default(),
+            overwrite: true,
+            provenance: None,
+            telemetry: vec![],
+            activity: None,
+            creation_time: None,
+            title: None,
+            cfr: None,
+            crossfade: None,
+        };
+
+        assert_eq!(
+            vec![
+                "-f",
+                "concat",
+                "-safe",
+                "0",
+                "-y",
+                "-i",
+                "/tmp/.0084.txt",
+                "-map",
+                "0:v",
+                "-map",
+                "0:a?",
+                "-write_tmcd",
+                "on",
+                "-c",
+                "copy",
+                "-map_metadata",
+                "0",
+                "/movies/GH000084.MP4",
+                "-loglevel",
+                "error",
+                "-progress",
+                "pipe:1"
+            ],
+            merge.args()
+        );
+
+        merge.subtitles = Some("/tmp/.0084.srt".into());
+        let args = merge.args();
+        assert_eq!(
+            &[
+                "-i",
+                "/tmp/.0084.srt",
+                "-map",
+                "0:v",
+                "-map",
+                "0:a?",
+                "-write_tmcd",
+                "on",
+                "-map",
+                "1",
+                "-c:s",
+                "mov_text"
+            ],
+            &args[7..19]
+        );
+
+        merge.subtitles = None;
+        merge.cfr = Some(ConstantFrameRate {
+            encoder: "libx265",
+            frame_rate: "60000/1001".parse().unwrap(),
+        });
+        let args = merge.args();
+        assert_eq!(
+            &[
+                "-c",
+                "copy",
+                "-c:v",
+                "libx265",
+                "-crf",
+                "18",
+                "-vsync",
+                "cfr",
+                "-r",
+                "60000/1001",
+                "-tag:v",
+                "hvc1"
+            ],
+            &args[13..25]
+        );
+    }
+
+    #[test]
+    fn test_ffmpeg_merge_crossfade_args() {
+        let merge = FFmpegMerge {
+            input: FFmpegInput::ConcatList("/tmp/.0084.txt".into()),
+            output: "/movies/GH000084.MP4".into(),
+            subtitles: Some("/tmp/.0084.srt".into()),
+            chapters: None,
+            streams: StreamMapping {
+                data: false,
+                timecode: false,
+                copy_unknown: false,
+            },
+            overwrite: true,
+            provenance: None,
+            telemetry: vec![2],
+            activity: None,
+            creation_time: None,
+            title: None,
+            cfr: None,
+            crossfade: Some(Crossfade {
+                chapters: vec![
+                    "/movies/GH010084.MP4".into(),
+                    "/movies/GH020084.MP4".into(),
+                    "/movies/GH030084.MP4".into(),
+                ],
+                offsets: vec![Duration::from_millis(59_500), Duration::from_secs(119)],
+                duration: Duration::from_millis(500),
+                encoder: "libx265",
+                audio: true,
+            }),
+        };
+
+        let graph = [
+            "[0:v]settb=AVTB[v0]",
+            "[1:v]settb=AVTB[v1]",
+            "[2:v]settb=AVTB[v2]",
+            "[v0][v1]xfade=transition=fade:duration=0.500:offset=59.500[vfaded1]",
+            "[vfaded1][v2]xfade=transition=fade:duration=0.500:offset=119.000[v]",
+            "[0:a][1:a]acrossfade=d=0.500[afaded1]",
+            "[afaded1][2:a]acrossfade=d=0.500[a]",
+        ]
+        .join(";");
+        assert_eq!(
+            vec![
+                "-y",
+                "-i",
+                "/movies/GH010084.MP4",
+                "-i",
+                "/movies/GH020084.MP4",
+                "-i",
+                "/movies/GH030084.MP4",
+                "-i",
+                "/tmp/.0084.srt",
+                "-filter_complex",
+                graph.as_str(),
+                "-map",
+                "[v]",
+                "-map",
+                "[a]",
+                "-map",
+                "3",
+                "-c:s",
+                "mov_text",
+                "-c",
+                "copy",
+                "-c:v",
+                "libx265",
+                "-crf",
+                "18",
+                "-tag:v",
+                "hvc1",
+                "-c:a",
+                "aac",
+                "-map_metadata",
+                "0",
+                "/movies/GH000084.MP4",
+                "-loglevel",
+                "error",
+                "-progress",
+                "pipe:1"
+            ],
+            merge.args()
+        );
+    }
+
+    #[test]
+    fn test_ffmpeg_merge_concat_protocol_args() {
+        let merge = FFmpegMerge {
+            input: FFmpegInput::ConcatProtocol(vec![
+                "/movies/GH010084.ts".into(),
+                "/movies/GH020084.ts".into(),
+            ]),
+            output: "/movies/GH000084.mp4".into(),
+            subtitles: None,
+            chapters: None,
+            streams: StreamMapping::default(),
+            overwrite: false,
+            provenance: None,
+            telemetry: vec![],
+            activity: None,
+            creation_time: None,
+            title: None,
+            cfr: None,
+            crossfade: None,
+        };
+
+        assert_eq!(
+            &[
+                "-n",
+                "-i",
+                "concat:/movies/GH010084.ts|/movies/GH020084.ts",
+                "-map",
+                "0:v",
+                "-map",
+                "0:a?"
+            ],
+            &merge.args()[..7]
+        );
+    }
+
+    #[test]
+    fn test_ffmpeg_merge_stream_mapping_args() {
+        let mut merge = FFmpegMerge {
+            input: FFmpegInput::ConcatList("/tmp/.0084.txt".into()),
+            output: "/movies/GH000084.MP4".into(),
+            subtitles: None,
+            chapters: None,
+            streams: StreamMapping {
+                data: false,
+                timecode: false,
+                copy_unknown: true,
+            },
+            overwrite: false,
+            provenance: None,
+            telemetry: vec![],
+            activity: None,
+            creation_time: None,
+            title: None,
+            cfr: None,
+            crossfade: None,
+        };
+
+        let args = merge.args();
+        assert_eq!("-copy_unknown", args[0]);
+        assert_eq!("-n", args[5]);
+        assert_eq!(&["-map", "0", "-map", "-0:d", "-c"], &args[8..13]);
+
+        merge.streams.timecode = true;
+        let args = merge.args();
+        assert_eq!(
+            &["-map", "0", "-map", "-0:d", "-write_tmcd", "on", "-c"],
+            &args[8..15]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_isolated_doesnt_inherit_stdio() {
+        let output = isolated("sh")
+            .args(["-c", "cat; echo out; echo err >&2"])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        // stdin is empty instead of waiting on the terminal, stdout is captured
+        assert_eq!(b"out\n", &output.stdout[..]);
+        assert!(output.stderr.is_empty());
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            Some((4, 4)),
+            parse_version("ffmpeg version 4.4.2-0ubuntu0.22.04.1 Copyright (c) 2000-2021 the FFmpeg developers\nbuilt with gcc 11\n")
+        );
+        assert_eq!(Some((6, 0)), parse_version("ffprobe version 6.0 Copyright"));
+        assert_eq!(
+            Some((5, 1)),
+            parse_version("ffmpeg version n5.1.2 Copyright")
+        );
+        assert_eq!(
+            Some((7, 0)),
+            parse_version("ffmpeg version 7-static https://johnvansickle.com")
+        );
+        assert_eq!(
+            None,
+            parse_version("ffmpeg version N-104000-g0123abcd Copyright")
+        );
+        assert_eq!(None, parse_version(""));
+    }
+
+    #[test]
+    fn test_preflight_missing_binaries() {
+        let binaries = Binaries {
+            ffmpeg: "/nonexistent/bin/ffmpeg".into(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            preflight(&binaries),
+            Err(Error::BinaryNotFound(path)) if path == binaries.ffmpeg
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_preflight_versions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join("goprotest_preflight_versions");
+        std::fs::create_dir_all(&dir).unwrap();
+        let fake = |name: &str, version: &str| {
+            let path = dir.join(name);
+            std::fs::write(
+                &path,
+                format!("#!/bin/sh\necho '{} version {} Copyright'\n", name, version),
+            )
+            .unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        };
+
+        let mut binaries = Binaries {
+            ffmpeg: fake("ffmpeg", "6.1.1"),
+            ffprobe: dir.join("missing-ffprobe"),
+            ..Default::default()
+        };
+        assert!(preflight(&binaries).is_ok());
+
+        binaries.ffprobe = fake("ffprobe", "3.4.8");
+        assert!(matches!(
+            preflight(&binaries),
+            Err(Error::BinaryTooOld(path, version)) if path == binaries.ffprobe && version == "3.4"
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ffmpeg_command_log_to() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join("goprotest_ffmpeg_command_log_to");
+        std::fs::create_dir_all(&dir).unwrap();
+        let binaries = Binaries {
+            ffmpeg: dir.join("ffmpeg"),
+            ..Default::default()
+        };
+        std::fs::write(
+            &binaries.ffmpeg,
+            "#!/bin/sh\necho 'moov atom not found' >&2\nexit 1\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&binaries.ffmpeg, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let log = dir.join("GH000084.MP4.log");
+        let _ = std::fs::remove_file(&log);
+
+        for pass in &["sources", "output"] {
+            let hash = FFmpegHash {
+                input: FFmpegInput::File("/movies/GH000084.MP4".into()),
+                output: dir.join(pass),
+            };
+            let err = FFmpegCommand::new(FFmpegCommandKind::Hash(hash), &binaries)
+                .and_then(|cmd| cmd.log_to(&log))
+                .and_then(Command::spawn)
+                .and_then(Command::wait_success)
+                .unwrap_err();
+            assert!(err
+                .to_string()
+                .ends_with(&format!("see the log {}", log.display())));
         }
+
+        // both commands are logged, each before its stderr
+        let logged = std::fs::read_to_string(&log).unwrap();
+        let lines = logged.lines().collect::<Vec<_>>();
+        assert_eq!(4, lines.len());
+        assert!(lines[0].contains("/movies/GH000084.MP4"));
+        assert_eq!("moov atom not found", lines[1]);
+        assert!(lines[2].contains("output"));
+        assert_eq!("moov atom not found", lines[3]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ffprobe_available_configured_path() {
+        let binaries = Binaries {
+            ffprobe: "/nonexistent/bin/ffprobe".into(),
+            ..Default::default()
+        };
+        assert!(!ffprobe_available(&binaries));
+    }
+
+    #[test]
+    fn test_ffmpeg_hash_args() {
+        let mut hash = FFmpegHash {
+            input: FFmpegInput::ConcatList("/tmp/.0084.txt".into()),
+            output: "/tmp/.0084.sources.sha256".into(),
+        };
+        assert_eq!(
+            vec![
+                "-f",
+                "concat",
+                "-safe",
+                "0",
+                "-i",
+                "/tmp/.0084.txt",
+                "-map",
+                "0:v",
+                "-c",
+                "copy",
+                "-f",
+                "hash",
+                "-hash",
+                "sha256",
+                "-y",
+                "/tmp/.0084.sources.sha256",
+                "-loglevel",
+                "error",
+                "-progress",
+                "pipe:1",
+            ],
+            hash.args()
+        );
+
+        hash.input = FFmpegInput::File("/movies/GH000084.MP4".into());
+        assert_eq!(&["-i", "/movies/GH000084.MP4"], &hash.args()[..2]);
+    }
+
+    #[test]
+    fn test_ffmpeg_derive_args() {
+        let telemetry = FFmpegDerive::Telemetry {
+            input: "/movies/GH000084.MP4".into(),
+            stream: 3,
+        };
+        assert_eq!(
+            vec![
+                "-i",
+                "/movies/GH000084.MP4",
+                "-map",
+                "0:3",
+                "-c",
+                "copy",
+                "-f",
+                "rawvideo",
+                "-y",
+                "/movies/GH000084.bin",
+                "-loglevel",
+                "error",
+                "-progress",
+                "pipe:1",
+            ],
+            telemetry.args()
+        );
+
+        let proxy = FFmpegDerive::Proxy {
+            input: "/movies/GH000084.MP4".into(),
+        };
+        let args = proxy.args();
+        assert_eq!(Derived::Proxy, proxy.derived());
+        assert!(args.windows(2).any(|args| args == ["-vf", "scale=-2:540"]));
+        assert!(args.windows(2).any(|args| args == ["-map", "0:a:0?"]));
+        assert!(args.windows(2).any(|args| args == ["-f", "mp4"]));
+        assert_eq!(
+            &["-y", "/movies/GH000084.LRV"],
+            &args[args.len() - 6..args.len() - 4]
+        );
+
+        let thumbnail = FFmpegDerive::Thumbnail {
+            input: "/movies/GH000084.MP4".into(),
+        };
+        let args = thumbnail.args();
+        assert_eq!(Derived::Thumbnail, thumbnail.derived());
+        assert!(args.windows(2).any(|args| args == ["-frames:v", "1"]));
+        assert!(args.windows(2).any(|args| args == ["-vf", "scale=-2:120"]));
+        assert_eq!(
+            &["-y", "/movies/GH000084.THM"],
+            &args[args.len() - 6..args.len() - 4]
+        );
+
+        let aac = FFmpegDerive::Audio {
+            input: "/movies/GH000084.MP4".into(),
+            format: AudioFormat::Aac,
+        };
+        let args = aac.args();
+        assert_eq!(Derived::Audio(AudioFormat::Aac), aac.derived());
+        assert!(args.windows(2).any(|args| args == ["-c:a", "copy"]));
+        assert!(args.windows(2).any(|args| args == ["-f", "ipod"]));
+        assert_eq!(
+            &["-y", "/movies/GH000084.m4a"],
+            &args[args.len() - 6..args.len() - 4]
+        );
+        let wav = FFmpegDerive::Audio {
+            input: "/movies/GH000084.MP4".into(),
+            format: AudioFormat::Wav,
+        };
+        let args = wav.args();
+        assert!(args.windows(2).any(|args| args == ["-c:a", "pcm_s16le"]));
+        assert_eq!(
+            &["-y", "/movies/GH000084.wav"],
+            &args[args.len() - 6..args.len() - 4]
+        );
+    }
+
+    #[test]
+    fn test_ffmpeg_merge_command_line() {
+        let merge = FFmpegMerge {
+            input: FFmpegInput::ConcatList("/tmp/.0084.txt".into()),
+            output: "/movies/GH000084.MP4".into(),
+            subtitles: None,
+            chapters: None,
+            streams: StreamMapping::default(),
+            overwrite: true,
+            provenance: None,
+            telemetry: vec![],
+            activity: None,
+            creation_time: None,
+            title: None,
+            cfr: None,
+            crossfade: None,
+        };
+
+        let command_line = merge.command_line(&Binaries::default());
+        assert_eq!("ffmpeg", command_line[0]);
+        assert_eq!(
+            merge
+                .args()
+                .iter()
+                .map(|arg| arg.to_string_lossy())
+                .collect::<Vec<_>>(),
+            &command_line[1..]
+        );
+
+        let binaries = Binaries {
+            ffmpeg: "/opt/ffmpeg/bin/ffmpeg".into(),
+            ..Default::default()
+        };
+        assert_eq!("/opt/ffmpeg/bin/ffmpeg", merge.command_line(&binaries)[0]);
+    }
+
+    #[test]
+    fn test_ffmpeg_merge_spherical_args() {
+        let merge = FFmpegMerge {
+            input: FFmpegInput::ConcatList("/tmp/.0038.txt".into()),
+            output: "/movies/GS000038.360".into(),
+            subtitles: None,
+            chapters: None,
+            streams: StreamMapping::default(),
+            overwrite: true,
+            provenance: None,
+            telemetry: vec![],
+            activity: None,
+            creation_time: None,
+            title: None,
+            cfr: None,
+            crossfade: None,
+        };
+
+        let args = merge.args();
+        // both lenses are copied along with the other streams
+        assert!(args.windows(2).any(|args| args == ["-map", "0:v"]));
+        assert!(!args.iter().any(|arg| arg == "0:v:0"));
+        assert_eq!(
+            &["-f", "mp4", "/movies/GS000038.360"],
+            &args[args.len() - 7..args.len() - 4]
+        );
+        assert_eq!(None, output_format(Path::new("/movies/GH000084.MP4")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ffmpeg_merge_non_unicode_args() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let output = PathBuf::from(OsStr::from_bytes(b"/movies/GH000084\xff.MP4"));
+        let merge = FFmpegMerge {
+            input: FFmpegInput::ConcatProtocol(vec![
+                OsStr::from_bytes(b"/movies/GH010084\xff.ts").into(),
+                "/movies/GH020084.ts".into(),
+            ]),
+            output: output.clone(),
+            subtitles: None,
+            chapters: None,
+            streams: StreamMapping::default(),
+            overwrite: true,
+            provenance: None,
+            telemetry: vec![],
+            activity: None,
+            creation_time: None,
+            title: None,
+            cfr: None,
+            crossfade: None,
+        };
+
+        let args = merge.args();
+        assert_eq!(
+            OsStr::from_bytes(b"concat:/movies/GH010084\xff.ts|/movies/GH020084.ts"),
+            args[2]
+        );
+        assert!(args.contains(&output.into_os_string()));
+        // lossy for the command line shown to users
+        assert!(merge
+            .command_line(&Binaries::default())
+            .contains(&"/movies/GH000084\u{FFFD}.MP4".to_string()));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_ffmpeg_path_strips_verbatim_prefix() {
+        assert_eq!(
+            Path::new(r"C:\movies\GH010084.MP4"),
+            ffmpeg_path(Path::new(r"\\?\C:\movies\GH010084.MP4"))
+        );
+        assert_eq!(
+            Path::new(r"\\nas\movies\GH010084.MP4"),
+            ffmpeg_path(Path::new(r"\\?\UNC\nas\movies\GH010084.MP4"))
+        );
+        assert_eq!(
+            Path::new(r"C:\movies\GH010084.MP4"),
+            ffmpeg_path(Path::new(r"C:\movies\GH010084.MP4"))
+        );
+
+        // too long to open without the prefix
+        let long = format!(r"\\?\C:\{}\GH010084.MP4", "a".repeat(300));
+        assert_eq!(Path::new(&long), ffmpeg_path(Path::new(&long)));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_ffmpeg_path_adds_verbatim_prefix_to_long_paths() {
+        let dir = "a".repeat(300);
+        assert_eq!(
+            PathBuf::from(format!(r"\\?\C:\{}\GH010084.MP4", dir)),
+            ffmpeg_path(Path::new(&format!(r"C:\{}/GH010084.MP4", dir)))
+        );
+        assert_eq!(
+            PathBuf::from(format!(r"\\?\UNC\nas\movies\{}\GH010084.MP4", dir)),
+            ffmpeg_path(Path::new(&format!(r"\\nas\movies\{}\GH010084.MP4", dir)))
+        );
+
+        // only too long ones, and not ones Windows would have to normalize
+        let parent = format!(r"C:\{}\..\GH010084.MP4", dir);
+        assert_eq!(Path::new(&parent), ffmpeg_path(Path::new(&parent)));
+        assert_eq!(
+            Path::new(r"C:\movies\GH010084.MP4"),
+            ffmpeg_path(Path::new(r"C:\movies\GH010084.MP4"))
+        );
+    }
+
+    #[test]
+    fn test_ffmpeg_merge_telemetry_args() {
+        let mut merge = FFmpegMerge {
+            input: FFmpegInput::ConcatList("/tmp/.0084.txt".into()),
+            output: "/movies/GH000084.MP4".into(),
+            subtitles: None,
+            chapters: None,
+            streams: StreamMapping::default(),
+            overwrite: true,
+            provenance: None,
+            telemetry: vec![3],
+            activity: None,
+            creation_time: None,
+            title: None,
+            cfr: None,
+            crossfade: None,
+        };
+        assert_eq!(
+            &[
+                "-c",
+                "copy",
+                "-tag:d",
+                "gpmd",
+                "-map_metadata",
+                "0",
+                "/movies/GH000084.MP4"
+            ],
+            &merge.args()[15..22]
+        );
+
+        assert_eq!(
+            &["-map", "0:v", "-map", "0:a?", "-map", "0:3"],
+            &merge.args()[7..13]
+        );
+
+        merge.streams.data = false;
+        assert!(!merge
+            .args()
+            .iter()
+            .any(|arg| arg.to_string_lossy().starts_with("-tag")));
+    }
+
+    /// The streams ffprobe shows for a HERO chapter, the timecode and the codec-less fdsc track
+    /// next to the telemetry.
+    const HERO_STREAMS: &[u8] = br#"{
+        "streams": [
+            { "index": 0, "codec_name": "h264", "codec_type": "video", "codec_tag_string": "avc1" },
+            { "index": 1, "codec_name": "aac", "codec_type": "audio", "codec_tag_string": "mp4a" },
+            { "index": 2, "codec_type": "data", "codec_tag_string": "tmcd" },
+            { "index": 3, "codec_name": "bin_data", "codec_type": "data", "codec_tag_string": "gpmd" },
+            { "index": 4, "codec_type": "data", "codec_tag_string": "fdsc" }
+        ],
+        "format": { "format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "5.472000" }
+    }"#;
+
+    #[test]
+    fn test_ffmpeg_merge_data_streams_args() {
+        let mut merge = FFmpegMerge {
+            input: FFmpegInput::ConcatList("/tmp/.0084.txt".into()),
+            output: "/movies/GH000084.MP4".into(),
+            subtitles: None,
+            chapters: None,
+            streams: StreamMapping::default(),
+            overwrite: true,
+            provenance: None,
+            telemetry: MediaInfo::from_json(HERO_STREAMS).unwrap().telemetry(),
+            activity: None,
+            creation_time: None,
+            title: None,
+            cfr: None,
+            crossfade: None,
+        };
+        let maps = |merge: &FFmpegMerge| {
+            merge
+                .args()
+                .windows(2)
+                .filter(|args| args[0] == "-map")
+                .map(|args| args[1].to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        // the timecode is written again from the video, the fdsc track left out
+        assert_eq!(vec!["0:v", "0:a?", "0:3"], maps(&merge));
+        let args = merge.args();
+        assert!(args.windows(2).any(|args| args == ["-write_tmcd", "on"]));
+        assert!(args.windows(2).any(|args| args == ["-tag:d", "gpmd"]));
+
+        merge.streams.copy_unknown = true;
+        assert_eq!(vec!["0"], maps(&merge));
+        let args = merge.args();
+        assert_eq!("-copy_unknown", args[0]);
+        assert!(args.windows(2).any(|args| args == ["-tag:3", "gpmd"]));
+        assert!(!args.iter().any(|arg| arg == "-write_tmcd"));
+
+        // Matroska holds neither the telemetry nor the timecode
+        merge.output = "/movies/GH000084.mkv".into();
+        merge.activity = Some("activity=ski".into());
+        assert_eq!(vec!["0", "-0:d"], maps(&merge));
+        merge.streams.copy_unknown = false;
+        assert_eq!(vec!["0:v", "0:a?"], maps(&merge));
+        let args = merge.args();
+        assert!(!args.iter().any(|arg| arg == "-write_tmcd"));
+        assert!(!args.iter().any(|arg| arg == "-movflags"));
+        assert!(!args
+            .iter()
+            .any(|arg| arg.to_string_lossy().starts_with("-tag")));
+        assert!(args
+            .windows(2)
+            .any(|args| args == ["-metadata", "activity=ski"]));
+    }
+
+    #[test]
+    fn test_ffmpeg_merge_provenance_args() {
+        let mut merge = FFmpegMerge {
+            input: FFmpegInput::ConcatList("/tmp/.0084.txt".into()),
+            output: "/movies/GH000084.MP4".into(),
+            subtitles: None,
+            chapters: None,
+            streams: StreamMapping::default(),
+            overwrite: true,
+            provenance: Some(Provenance::for_output(
+                Path::new("/movies/GH000084.MP4"),
+                "GH010084.MP4\n".into(),
+                "/tmp/.0084.sources.txt".into(),
+            )),
+            telemetry: vec![],
+            activity: None,
+            creation_time: None,
+            title: None,
+            cfr: None,
+            crossfade: None,
+        };
+        assert_eq!(
+            &[
+                "-c",
+                "copy",
+                "-movflags",
+                "use_metadata_tags",
+                "-metadata",
+                "gopro_merge_sources=GH010084.MP4\n",
+                "-map_metadata",
+                "0",
+                "/movies/GH000084.MP4"
+            ],
+            &merge.args()[13..22]
+        );
+
+        merge.output = "/movies/GH000084.mkv".into();
+        merge.provenance = Some(Provenance::for_output(
+            &merge.output,
+            "GH010084.MP4\n".into(),
+            "/tmp/.0084.sources.txt".into(),
+        ));
+        assert_eq!(
+            &[
+                "-c",
+                "copy",
+                "-attach",
+                "/tmp/.0084.sources.txt",
+                "-metadata:s:t",
+                "mimetype=text/plain",
+                "-map_metadata",
+                "0",
+                "/movies/GH000084.mkv"
+            ],
+            // without the -write_tmcd on
+            &merge.args()[11..20]
+        );
+    }
+
+    #[test]
+    fn test_ffmpeg_merge_activity_args() {
+        let mut merge = FFmpegMerge {
+            input: FFmpegInput::ConcatList("/tmp/.0084.txt".into()),
+            output: "/movies/GH000084.MP4".into(),
+            subtitles: None,
+            chapters: None,
+            streams: StreamMapping::default(),
+            overwrite: true,
+            provenance: Some(Provenance::for_output(
+                Path::new("/movies/GH000084.MP4"),
+                "GH010084.MP4\n".into(),
+                "/tmp/.0084.sources.txt".into(),
+            )),
+            telemetry: vec![],
+            activity: Some("gopro_merge_activity=bike park".into()),
+            creation_time: None,
+            title: None,
+            cfr: None,
+            crossfade: None,
+        };
+        assert_eq!(
+            &[
+                "-c",
+                "copy",
+                "-movflags",
+                "use_metadata_tags",
+                "-metadata",
+                "gopro_merge_sources=GH010084.MP4\n",
+                "-metadata",
+                "gopro_merge_activity=bike park",
+                "-map_metadata",
+                "0",
+                "/movies/GH000084.MP4"
+            ],
+            &merge.args()[13..24]
+        );
+
+        merge.output = "/movies/GH000084.mkv".into();
+        merge.provenance = None;
+        assert_eq!(
+            &[
+                "-c",
+                "copy",
+                "-metadata",
+                "gopro_merge_activity=bike park",
+                "-map_metadata",
+                "0",
+                "/movies/GH000084.mkv"
+            ],
+            &merge.args()[11..18]
+        );
+    }
+
+    #[test]
+    fn test_ffmpeg_merge_creation_time_args() {
+        let mut merge = FFmpegMerge {
+            input: FFmpegInput::ConcatProtocol(vec!["/movies/GH010084.ts".into()]),
+            output: "/movies/GH000084.mp4".into(),
+            subtitles: None,
+            chapters: None,
+            streams: StreamMapping::default(),
+            overwrite: true,
+            provenance: None,
+            telemetry: vec![],
+            activity: None,
+            creation_time: Some(humantime::parse_rfc3339("2021-11-14T23:15:25Z").unwrap()),
+            title: None,
+            cfr: None,
+            crossfade: None,
+        };
+        assert_eq!(
+            &[
+                "-map_metadata",
+                "0",
+                "-metadata",
+                "creation_time=2021-11-14T23:15:25.000000Z",
+                "/movies/GH000084.mp4"
+            ],
+            &merge.args()[11..16]
+        );
+
+        merge.creation_time = None;
+        assert_eq!(
+            &["-map_metadata", "0", "/movies/GH000084.mp4"],
+            &merge.args()[11..14]
+        );
+
+        merge.title = Some("GoPro 14 novembre 2021".into());
+        assert_eq!(
+            &[
+                "-map_metadata",
+                "0",
+                "-metadata",
+                "title=GoPro 14 novembre 2021",
+                "/movies/GH000084.mp4"
+            ],
+            &merge.args()[11..16]
+        );
+    }
+
+    #[test]
+    fn test_ffmpeg_merge_chapters_args() {
+        let mut merge = FFmpegMerge {
+            input: FFmpegInput::ConcatList("/tmp/.0084.txt".into()),
+            output: "/movies/GH000084.MP4".into(),
+            subtitles: None,
+            chapters: Some("/tmp/.0084.chapters.txt".into()),
+            streams: StreamMapping::default(),
+            overwrite: true,
+            provenance: None,
+            telemetry: vec![],
+            activity: None,
+            creation_time: None,
+            title: None,
+            cfr: None,
+            crossfade: None,
+        };
+        let args = merge.args();
+        assert_eq!(
+            &["-i", "/tmp/.0084.chapters.txt", "-map", "0:v"],
+            &args[7..11]
+        );
+        assert_eq!(
+            &[
+                "-map_metadata",
+                "0",
+                "-map_chapters",
+                "1",
+                "/movies/GH000084.MP4"
+            ],
+            &args[17..22]
+        );
+
+        merge.subtitles = Some("/tmp/.0084.srt".into());
+        let args = merge.args();
+        assert_eq!(
+            &["-i", "/tmp/.0084.srt", "-i", "/tmp/.0084.chapters.txt"],
+            &args[7..11]
+        );
+        assert_eq!(&["-map_chapters", "2"], &args[25..27]);
+    }
+
+    #[test]
+    fn test_subtitles_codec() {
+        assert_eq!("mov_text", subtitles_codec(Path::new("GH000084.MP4")));
+        assert_eq!("mov_text", subtitles_codec(Path::new("GH000084.mov")));
+        assert_eq!("srt", subtitles_codec(Path::new("GH000084.mkv")));
     }
 }
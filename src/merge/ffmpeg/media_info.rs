@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use log::*;
+use serde_json::Value;
+
+use crate::frame_rate::FrameRate;
+use crate::merge::Result;
+
+/// What ffprobe reads from a movie file with `-print_format json -show_format -show_streams`,
+/// for anything needing more than the durations, e.g. checking codecs or naming by resolution.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MediaInfo {
+    /// The container formats ffprobe took the file for, e.g. `mov,mp4,m4a,3gp,3g2,mj2`.
+    pub format: Option<String>,
+    /// Duration of the container, the longest of its streams.
+    pub duration: Option<Duration>,
+    /// Bits per second of the whole file.
+    pub bit_rate: Option<u64>,
+    pub creation_time: Option<SystemTime>,
+    /// The global metadata, e.g. the `firmware` of the camera or the tags of a merge.
+    pub tags: BTreeMap<String, String>,
+    pub streams: Vec<StreamInfo>,
+}
+
+/// A single stream of a [MediaInfo], the fields ffprobe doesn't know for its type being none.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub index: usize,
+    /// e.g. `video`, `audio` or `data`.
+    pub codec_type: Option<String>,
+    /// e.g. `h264` or `hevc`.
+    pub codec_name: Option<String>,
+    /// e.g. `High` or `Main 10`.
+    pub profile: Option<String>,
+    /// The fourcc of the stream, e.g. `gpmd` for the GoPro telemetry.
+    pub codec_tag: Option<String>,
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    /// Audio samples per second.
+    pub sample_rate: Option<u64>,
+    pub channels: Option<u64>,
+    /// The nominal frame rate, ffprobe's r_frame_rate.
+    pub frame_rate: Option<FrameRate>,
+    /// The frame rate averaged over the stream.
+    pub avg_frame_rate: Option<FrameRate>,
+    /// Bits per second, unknown for some containers.
+    pub bit_rate: Option<u64>,
+    pub duration: Option<Duration>,
+    /// Set by the camera, e.g. `GoPro AVC` or `GoPro MET`.
+    pub handler_name: Option<String>,
+    pub creation_time: Option<SystemTime>,
+}
+
+impl MediaInfo {
+    /// Parses the JSON ffprobe printed, leaving out what's missing from it or invalid.
+    pub fn from_json(json: &[u8]) -> Result<Self> {
+        let root: Value = serde_json::from_slice(json)?;
+        let format = &root["format"];
+
+        Ok(MediaInfo {
+            format: string(format, "format_name"),
+            duration: seconds(format, "duration"),
+            bit_rate: number(format, "bit_rate"),
+            creation_time: creation_time(format),
+            tags: format["tags"]
+                .as_object()
+                .map(|tags| {
+                    tags.iter()
+                        .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            streams: root["streams"]
+                .as_array()
+                .map(|streams| streams.iter().map(StreamInfo::from_json).collect())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// The first video stream, the one players show.
+    pub fn video(&self) -> Option<&StreamInfo> {
+        self.streams
+            .iter()
+            .find(|stream| stream.codec_type.as_deref() == Some("video"))
+    }
+
+    /// Indices of the GPMF telemetry streams, GPS, gyro and the like.
+    pub fn telemetry(&self) -> Vec<usize> {
+        self.streams
+            .iter()
+            .filter(|stream| stream.codec_tag.as_deref() == Some("gpmd"))
+            .map(|stream| stream.index)
+            .collect()
+    }
+}
+
+impl StreamInfo {
+    fn from_json(stream: &Value) -> Self {
+        StreamInfo {
+            index: number(stream, "index").unwrap_or_default() as usize,
+            codec_type: string(stream, "codec_type"),
+            codec_name: string(stream, "codec_name"),
+            profile: string(stream, "profile"),
+            codec_tag: string(stream, "codec_tag_string"),
+            width: number(stream, "width"),
+            height: number(stream, "height"),
+            sample_rate: number(stream, "sample_rate"),
+            channels: number(stream, "channels"),
+            // 0/0 when unknown
+            frame_rate: string(stream, "r_frame_rate").and_then(|rate| rate.parse().ok()),
+            avg_frame_rate: string(stream, "avg_frame_rate").and_then(|rate| rate.parse().ok()),
+            bit_rate: number(stream, "bit_rate"),
+            duration: seconds(stream, "duration"),
+            handler_name: string(&stream["tags"], "handler_name")
+                .map(|name| name.trim().to_string()),
+            creation_time: creation_time(stream),
+        }
+    }
+}
+
+fn string(value: &Value, key: &str) -> Option<String> {
+    value[key].as_str().map(str::to_string)
+}
+
+/// ffprobe prints some numbers as strings, e.g. `"bit_rate": "45000000"`, and "N/A" for unknown
+/// ones.
+fn number(value: &Value, key: &str) -> Option<u64> {
+    match &value[key] {
+        Value::Number(number) => number.as_u64(),
+        Value::String(number) => number.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Seconds printed like `5.458333`.
+fn seconds(value: &Value, key: &str) -> Option<Duration> {
+    let seconds = value[key].as_str()?;
+    let (whole, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+    let micros = format!("{:0<6}", fraction);
+
+    Some(
+        Duration::from_secs(whole.parse().ok()?)
+            + Duration::from_micros(micros.get(..6)?.parse().ok()?),
+    )
+}
+
+fn creation_time(value: &Value) -> Option<SystemTime> {
+    let time = value["tags"]["creation_time"].as_str()?;
+    humantime::parse_rfc3339_weak(time)
+        .map_err(|err| warn!("parsing creation_time {}: {}", time, err))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_media_info_from_json() {
+        let json = br#"{
+            "streams": [
+                {
+                    "index": 0,
+                    "codec_name": "h264",
+                    "codec_type": "video",
+                    "codec_tag_string": "avc1",
+                    "profile": "High",
+                    "width": 1920,
+                    "height": 1080,
+                    "r_frame_rate": "60000/1001",
+                    "avg_frame_rate": "60000/1001",
+                    "duration": "5.458333",
+                    "bit_rate": "45000000",
+                    "tags": {
+                        "creation_time": "2021-11-14T23:15:25.000000Z",
+                        "handler_name": "\tGoPro AVC  "
+                    }
+                },
+                {
+                    "index": 1,
+                    "codec_type": "data",
+                    "codec_tag_string": "gpmd",
+                    "r_frame_rate": "0/0",
+                    "duration": "5.5",
+                    "bit_rate": "N/A",
+                    "tags": {
+                        "handler_name": "\tGoPro MET  "
+                    }
+                },
+                {
+                    "index": 2,
+                    "codec_name": "aac",
+                    "codec_type": "audio",
+                    "sample_rate": "48000",
+                    "channels": 2
+                }
+            ],
+            "format": {
+                "format_name": "mov,mp4,m4a,3gp,3g2,mj2",
+                "duration": "5.472000",
+                "bit_rate": "45100000",
+                "tags": {
+                    "creation_time": "invalid",
+                    "firmware": "HD9.01.01.60.00"
+                }
+            }
+        }"#;
+
+        let info = MediaInfo::from_json(json).unwrap();
+        assert_eq!(Some("mov,mp4,m4a,3gp,3g2,mj2"), info.format.as_deref());
+        assert_eq!(Some(Duration::from_micros(5_472_000)), info.duration);
+        assert_eq!(Some(45_100_000), info.bit_rate);
+        assert_eq!(None, info.creation_time);
+        assert_eq!(
+            Some("HD9.01.01.60.00"),
+            info.tags.get("firmware").map(String::as_str)
+        );
+        assert_eq!(vec![1], info.telemetry());
+
+        let video = info.video().unwrap();
+        assert_eq!(
+            &StreamInfo {
+                index: 0,
+                codec_type: Some("video".into()),
+                codec_name: Some("h264".into()),
+                profile: Some("High".into()),
+                codec_tag: Some("avc1".into()),
+                width: Some(1920),
+                height: Some(1080),
+                sample_rate: None,
+                channels: None,
+                frame_rate: Some(FrameRate {
+                    num: 60000,
+                    den: 1001
+                }),
+                avg_frame_rate: Some(FrameRate {
+                    num: 60000,
+                    den: 1001
+                }),
+                bit_rate: Some(45_000_000),
+                duration: Some(Duration::from_micros(5_458_333)),
+                handler_name: Some("GoPro AVC".into()),
+                creation_time: Some(humantime::parse_rfc3339("2021-11-14T23:15:25Z").unwrap()),
+            },
+            video
+        );
+
+        let data = &info.streams[1];
+        assert_eq!(None, data.frame_rate);
+        assert_eq!(None, data.bit_rate);
+        assert_eq!(Some(Duration::from_millis(5_500)), data.duration);
+
+        let audio = &info.streams[2];
+        assert_eq!(Some(48000), audio.sample_rate);
+        assert_eq!(Some(2), audio.channels);
+
+        assert_eq!(MediaInfo::default(), MediaInfo::from_json(b"{}").unwrap());
+        assert!(MediaInfo::from_json(b"[STREAM]\nduration=1.0\n").is_err());
+    }
+}
@@ -1,89 +1,315 @@
+use std::collections::BTreeSet;
 use std::env::temp_dir;
-use std::fs;
-use std::io::Write;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use indicatif::HumanDuration;
+use crossbeam_channel::{unbounded, RecvTimeoutError};
 use log::*;
+use parking_lot::Mutex;
+use rayon::prelude::*;
 
-use crate::merge::command::{Command as _, FFmpegCommand, FFmpegCommandKind};
+use crate::audit::{self, SourceRecord};
+use crate::chapters::{chapter_boundaries, ChapterBoundary, ProbedChapter};
+use crate::confirm::DestructiveAction;
+use crate::duration::{self, FormattedDuration, HumanDuration};
+use crate::encoding::Encoding;
+use crate::export::{self, ExportFormat, MergedMovie};
+use crate::merge::command::{
+    Command as _, FFmpegCommand, FFmpegCommandKind, FFmpegDerive, FFmpegHash, FFmpegMerge,
+};
 use crate::merge::ffmpeg::parser::{
-    CommandStreamDurationParser as _, FFmpegDurationParser, FFprobeDurationParser,
+    CommandStreamDurationParser as _, FFmpegDurationParser, FFprobeParser, Probe,
+};
+use crate::merge::ffmpeg::{
+    ffmpeg_path, Binaries, CacheKey, ConcatMethod, ConstantFrameRate, Crossfade, FFmpegInput,
+    MediaInfo, ProbeCache, Provenance,
 };
-use crate::merge::Result;
-use crate::progress::Progress;
+use crate::merge::native::Concat;
+use crate::merge::{
+    parse_hash, resolve_output, wait_for_space, Derived, Error, MergeOptions, OnExists,
+    ResolvedOutput, Result, Retry, Verification, VerifyMode, VerifyPass, SPACE_POLL_INTERVAL,
+    VERY_LONG_DURATION,
+};
+use crate::mp4;
+use crate::progress::{GroupError, GroupStats, Progress, WarningKind};
+use crate::proxy;
+use crate::system::{Clock as _, Fs, RealFs, SuspendDetector, SystemClock};
+use crate::telemetry;
+use crate::timestamp::{self, TimeZone};
 use crate::{group::MovieGroup, merge::Merger};
 
-pub struct FFmpegMerger<P> {
-    progress: P,
+pub struct FFmpegMerger {
+    progress: Box<dyn Progress>,
     group: MovieGroup,
     movies_path: PathBuf,
     merged_output_path: PathBuf,
+    options: MergeOptions,
+    /// The chapters probed by [Merger::prepare], probed when merging otherwise.
+    probed: Option<Result<Vec<ProbedChapter>>>,
+    /// The chapters as they were when [Merger::prepare] probed them.
+    planned: Option<Vec<ChapterState>>,
 }
 
-impl<P> Merger for FFmpegMerger<P>
-where
-    P: Progress + Send + 'static,
-{
-    type Progress = P;
-
+impl Merger for FFmpegMerger {
     fn new(
-        progress: Self::Progress,
+        progress: Box<dyn Progress>,
         group: MovieGroup,
         movies_path: PathBuf,
         merged_output_path: PathBuf,
+        options: MergeOptions,
     ) -> Self {
         FFmpegMerger {
             progress,
             group,
             movies_path,
             merged_output_path,
+            options,
+            probed: None,
+            planned: None,
         }
     }
-    fn merge(self) -> Result<()> {
-        let progress = self.progress.clone();
-        let merge_result = self.merge_inner();
-        progress.finish(merge_result.as_ref().err().map(|e| format!("{}", e)));
-        merge_result
+
+    fn prepare(&mut self) {
+        let group = &self.group;
+        let options = &self.options;
+        let output = options
+            .activities
+            .output_dir(group, &self.merged_output_path)
+            .join(options.names.of(group));
+        // not probing groups about to be skipped, merging tells why
+        let skipped = options.stopping(group)
+            || options.resume.as_ref().map_or(false, |resume| {
+                resume.completed_output(&group.name()).is_some()
+            })
+            || (options.on_exists == OnExists::Skip && output.exists());
+        if !options.probe || skipped {
+            return;
+        }
+
+        let paths = group
+            .chapters
+            .iter()
+            .map(|chapter| group.chapter_path(chapter, &self.movies_path))
+            .collect::<Vec<_>>();
+        let report = self.progress.clone();
+        let progress = &mut self.progress;
+        debug!("probing {} ahead of merging", group);
+        // before probing, changes while probing are probed again too
+        self.planned = Some(chapter_states(&RealFs, &paths));
+        self.probed = Some(
+            preflight_chapters(group, &paths, options.retry).and_then(|_| {
+                probe_chapters(
+                    &paths,
+                    &options.binaries,
+                    options.probe_cache.as_ref(),
+                    options.timezone,
+                    Some(&report),
+                    |probed| progress.probing(probed, paths.len()),
+                )
+            }),
+        );
+        if let Some(Ok(probed)) = &self.probed {
+            let duration = duration::saturating_sum(probed.iter().map(|chapter| chapter.duration));
+            warn_very_long(progress, group, probed.len(), duration);
+        }
+    }
+
+    fn merge(self: Box<Self>) -> Result<GroupStats> {
+        let (progress, group) = (self.progress.clone(), self.group.clone());
+        let (merged_output_path, options) = (self.merged_output_path.clone(), self.options.clone());
+        report_merge(progress, &group, &merged_output_path, &options, || {
+            self.merge_inner()
+        })
     }
 }
 
-impl<P> FFmpegMerger<P>
-where
-    P: Progress + Send + 'static,
-{
-    fn merge_inner(self) -> Result<()> {
+impl FFmpegMerger {
+    fn merge_inner(self) -> Result<GroupStats> {
+        let started = SystemClock.instant();
         let Self {
             mut progress,
-            group,
+            mut group,
             movies_path,
             merged_output_path,
+            options,
+            probed,
+            planned,
         } = self;
+        // before leaving out proxies, the id is of the chapters found
+        let group_id = group.id();
+        let output = match start_merge(
+            &mut progress,
+            &group,
+            &merged_output_path,
+            &options,
+            started,
+        )? {
+            Start::Merge(output) => output,
+            Start::Done(stats) => return Ok(*stats),
+        };
 
-        let (ffmpeg_input_file, ffmpeg_input_file_path) =
-            init_ffmpeg_input_file(&group.fingerprint.file.to_string())?;
-
-        let movies_full_paths = group
+        let mut movies_full_paths = group
             .chapters
             .iter()
-            .map(|chapter| movies_path.join(&group.chapter_file_name(chapter)))
+            .map(|chapter| group.chapter_path(chapter, &movies_path))
             .collect::<Vec<_>>();
 
-        debug!(
-            "Writing movies to ffmpeg input file {}",
-            &ffmpeg_input_file_path.as_os_str().to_str().unwrap(),
-        );
-        write_movies_to_input_file(ffmpeg_input_file, &movies_full_paths)?;
+        let preflight_started = SystemClock.instant();
+        preflight_chapters(&group, &movies_full_paths, options.retry)?;
+        let preflight = SystemClock.instant() - preflight_started;
+        debug!("preflight of {} took {:?}", group, preflight);
 
-        debug!("Calculating total duration for group {}", group.name());
-        let duration = calculate_total_duration(&movies_full_paths)?;
+        // e.g. a chapter still being copied into a watched input when it was probed, or replaced
+        // while the group waited for its turn to merge
+        let replanned = planned
+            .map(|planned| changed_chapters(&RealFs, &movies_full_paths, &planned))
+            .unwrap_or_default();
+        let probed = if replanned.is_empty() {
+            probed
+        } else {
+            info!(
+                "{} changed since it was probed ahead of merging, probing it again: {}",
+                group,
+                replanned
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            None
+        };
+
+        // probing first as proxies are left out of everything that follows
+        let mut probed = match probed {
+            Some(probed) => Some(probed?),
+            None if options.probe => {
+                debug!("Calculating total duration for group {}", group.name());
+                let report = progress.clone();
+                Some(probe_chapters(
+                    &movies_full_paths,
+                    &options.binaries,
+                    options.probe_cache.as_ref(),
+                    options.timezone,
+                    Some(&report),
+                    |probed| progress.probing(probed, movies_full_paths.len()),
+                )?)
+            }
+            None => None,
+        };
+        let excluded = match &mut probed {
+            Some(probed) => proxy::exclude(
+                options.proxies,
+                &mut group,
+                &mut movies_full_paths,
+                probed,
+                |warning| progress.warning(WarningKind::ProxySuspect, &warning),
+            ),
+            None => vec![],
+        };
+        if let Some(probed) = &probed {
+            warn_empty_last_chapter(&group, &movies_full_paths, probed);
+        }
+        let variable_frame_rate = variable_frame_rate(&movies_full_paths, &probed);
+        warn_variable_frame_rate(&mut progress, &group, &variable_frame_rate, &options);
+        let input = merge_input(&group, &movies_full_paths, &options)?;
+
+        if let FFmpegInput::ConcatList(..) = &input {
+            let (mut ffmpeg_input_file, ffmpeg_input_file_path) =
+                init_ffmpeg_input_file(&options.workspace, &group.fingerprint.file.to_string())?;
+
+            debug!(
+                "Writing movies to ffmpeg input file {}",
+                ffmpeg_input_file_path.display(),
+            );
+            ffmpeg_input_file.write_all(concat_list(&movies_full_paths)?.as_bytes())?;
+        }
+
+        let sources = record_sources(&mut progress, &movies_full_paths, &options)?;
+
+        // the concat demuxer takes the streams of the first chapter
+        let telemetry = probed
+            .iter()
+            .flatten()
+            .next()
+            .map(|chapter| chapter.telemetry.clone())
+            .unwrap_or_default();
+
+        let merge = ffmpeg_merge(
+            &group,
+            &output,
+            input.clone(),
+            &options,
+            options.subtitles,
+            &sources,
+            probed.iter().flatten().next(),
+        );
+        let merge = FFmpegMerge {
+            cfr: constant_frame_rate(&group, &variable_frame_rate, &probed, &options),
+            crossfade: crossfade(&group, &movies_full_paths, &mut probed, &options),
+            ..merge
+        };
+        if let Some(Provenance::Attachment(path)) = &merge.provenance {
+            debug!("writing source chapters to {}", path.display());
+            fs::write(path, audit::provenance(&sources))?;
+        }
+        let duration =
+            duration::saturating_sum(probed.iter().flatten().map(|chapter| chapter.duration));
         debug!(
             "Total duration for group {} is {:?} ({})",
             group.name(),
             duration,
             HumanDuration(duration)
         );
+        let boundaries_needed =
+            !options.exports.is_empty() || options.subtitles || options.chapter_markers;
+        let movie = match &probed {
+            Some(probed) if boundaries_needed => Some(merged_movie(
+                &group,
+                &movies_full_paths,
+                output.path(),
+                probed,
+            )),
+            None if boundaries_needed => {
+                warn!(
+                    "skipping exports, subtitles and chapter markers of {}, chapter boundaries are unknown without probing",
+                    group.name()
+                );
+                None
+            }
+            _ => None,
+        };
+
+        let merge = match (&movie, &merge.subtitles) {
+            (Some(movie), Some(path)) => {
+                debug!("writing subtitles to {}", path.display());
+                fs::write(path, ExportFormat::Subtitles.render(movie))?;
+                merge
+            }
+            _ => FFmpegMerge {
+                subtitles: None,
+                ..merge
+            },
+        };
+        let merge = match (&movie, &merge.chapters) {
+            (Some(movie), Some(path)) => {
+                debug!("writing chapter markers to {}", path.display());
+                fs::write(path, export::chapter_markers(movie))?;
+                merge
+            }
+            _ => FFmpegMerge {
+                chapters: None,
+                ..merge
+            },
+        };
+        let subtitles_path = merge.subtitles.clone();
+        let chapters_path = merge.chapters.clone();
+        let attachment_path = match &merge.provenance {
+            Some(Provenance::Attachment(path)) => Some(path.clone()),
+            _ => None,
+        };
 
         debug!("converting {}", &group,);
         debug!(
@@ -92,21 +318,1039 @@ where
             HumanDuration(duration)
         );
         progress.set_len(duration);
-        convert(
-            progress.clone(),
-            &ffmpeg_input_file_path,
-            &merged_output_path,
-            &group,
-        )?;
+        options.jobs.set_len(&group.name(), duration);
+        // overwriting was confirmed before the run started
+        let actions = match &output {
+            ResolvedOutput::Overwrite(path) => vec![DestructiveAction::Overwrite(path.clone())],
+            _ => vec![],
+        };
+        // the directories that might get unmounted while the machine sleeps
+        let watched = movies_full_paths
+            .iter()
+            .map(PathBuf::as_path)
+            .chain(std::iter::once(output.path()))
+            .filter_map(Path::parent)
+            .map(Path::to_path_buf)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        if let Some(resume) = &options.resume {
+            resume.start(&group.name(), &group_id, output.path())?;
+        }
+        let converted = convert(progress.clone(), merge, &group, &watched, &options)?;
+
+        if let Some(subtitles_path) = subtitles_path {
+            fs::remove_file(subtitles_path)?;
+        }
+        if let Some(chapters_path) = chapters_path {
+            fs::remove_file(chapters_path)?;
+        }
+        if let Some(attachment_path) = attachment_path {
+            fs::remove_file(attachment_path)?;
+        }
+
+        let converted = match converted {
+            Some(converted) => converted,
+            None => {
+                let interrupted = options.stopping(&group);
+                warn!(
+                    "stopped merging {} {}, removing {}",
+                    group,
+                    if interrupted {
+                        "as the run was interrupted or it was cancelled"
+                    } else {
+                        "at the run deadline"
+                    },
+                    output.path().display()
+                );
+                remove_concat_list(&input)?;
+                remove_if_exists(output.path())?;
+                let (output, elapsed) =
+                    (output.path().to_path_buf(), SystemClock.instant() - started);
+                return Ok(if interrupted {
+                    GroupStats::interrupted(output, elapsed)
+                } else {
+                    GroupStats::deferred(output, elapsed)
+                });
+            }
+        };
+        let merged = if probed.is_some() {
+            debug!("verifying the duration of {}", output.path().display());
+            let merged = probe_output(output.path(), &options.binaries, Some(&progress))?;
+            if let Err(err) = check_duration(&group, duration, merged.duration, &options) {
+                // a truncated movie would be skipped as already merged by the next run
+                remove_concat_list(&input)?;
+                remove_if_exists(output.path())?;
+                return Err(err);
+            }
+            check_telemetry(&group, &telemetry, &merged.telemetry, &options)?;
+            Some(merged)
+        } else {
+            None
+        };
+
+        let verification = match options.verify {
+            Some(VerifyMode::Bitexact) => Some(verify_bitexact(
+                &progress,
+                &group,
+                &input,
+                output.path(),
+                &options,
+            )?),
+            None => None,
+        };
+        remove_concat_list(&input)?;
+        let recorded = match probed.iter().flatten().next() {
+            Some(chapter) => chapter.timestamp.time,
+            None => movies_full_paths.first().and_then(|path| {
+                let mut timestamps = [timestamp::resolve(None, path, &RealFs, &SystemClock)];
+                timestamp::align(&mut timestamps, options.timezone, &SystemClock);
+                timestamps[0].time
+            }),
+        };
+        timestamp::preserve(output.path(), recorded, &RealFs);
+
+        if let Some(movie) = movie {
+            options
+                .exports
+                .iter()
+                .try_for_each(|format| export::export(*format, &movie).map(|_| ()))?;
+        }
+        // the merged movie is kept when the files made from it fail, rerunning with --on-exists
+        // overwrite makes them again
+        let merged_path = output.path().to_path_buf();
+        let telemetry_track = match merged.as_ref().and_then(|merged| merged.telemetry.first()) {
+            _ if !options.extract_telemetry => None,
+            Some(stream) => Some(derive(
+                &progress,
+                &group,
+                FFmpegDerive::Telemetry {
+                    input: merged_path.clone(),
+                    stream: *stream,
+                },
+                &options,
+            )?),
+            None => {
+                warn!(
+                    "not extracting the telemetry of {}, {}",
+                    group,
+                    if merged.is_some() {
+                        "it has no GPMF telemetry track"
+                    } else {
+                        "its streams couldn't be probed"
+                    }
+                );
+                None
+            }
+        };
+        let proxy = if options.proxy {
+            Some(derive(
+                &progress,
+                &group,
+                FFmpegDerive::Proxy { input: merged_path },
+                &options,
+            )?)
+        } else if options.merge_proxies {
+            merge_recorded_proxies(
+                &progress,
+                &group,
+                &movies_full_paths,
+                &merged_path,
+                recorded,
+                &options,
+            )?
+        } else {
+            None
+        };
+        let gpx = if options.extract_gpx {
+            extract_gpx(
+                &progress,
+                &group,
+                &movies_full_paths,
+                output.path(),
+                recorded,
+            )?
+        } else {
+            None
+        };
+        let audio = match options.extract_audio {
+            Some(format) if has_sound_track(&movies_full_paths[0]) => Some(derive(
+                &progress,
+                &group,
+                FFmpegDerive::Audio {
+                    input: output.path().to_path_buf(),
+                    format,
+                },
+                &options,
+            )?),
+            Some(_) => {
+                warn!(
+                    "not extracting the audio of {}, it has no sound track{}",
+                    group,
+                    if options.audio_only {
+                        ", keeping the merged movie"
+                    } else {
+                        ""
+                    }
+                );
+                None
+            }
+            None => None,
+        };
+        for path in telemetry_track.iter().chain(&proxy).chain(&audio) {
+            timestamp::preserve(path, recorded, &RealFs);
+        }
+        // everything else made from the merged movie is made by now
+        let output = match &audio {
+            Some(audio) if options.audio_only => {
+                debug!("keeping only the audio {} of {}", audio.display(), group);
+                remove_if_exists(output.path())?;
+                audio.clone()
+            }
+            _ => output.path().to_path_buf(),
+        };
+
+        if let Some(resume) = &options.resume {
+            resume.complete(&group.name(), &group_id, &output)?;
+        }
+
+        Ok(GroupStats {
+            output,
+            duration: if duration.is_zero() {
+                converted
+            } else {
+                duration
+            },
+            elapsed: SystemClock.instant() - started,
+            preflight,
+            sources,
+            actions,
+            skipped: false,
+            deferred: false,
+            interrupted: false,
+            verification,
+            telemetry_track,
+            proxy,
+            gpx,
+            audio,
+            excluded,
+            variable_frame_rate,
+            replanned,
+            started: None,
+            ended: None,
+        })
+    }
+}
+
+/// Runs the `merge` of the `group`, reporting how it went to the `progress` and the jobs of the
+/// run, the part of merging shared by the mergers.
+pub(crate) fn report_merge(
+    progress: Box<dyn Progress>,
+    group: &MovieGroup,
+    merged_output_path: &Path,
+    options: &MergeOptions,
+    merge: impl FnOnce() -> Result<GroupStats>,
+) -> Result<GroupStats> {
+    let started = SystemClock.instant();
+    let started_at = SystemClock.now();
+    let log = stderr_log_path(options, group);
+    let (jobs, name) = (&options.jobs, group.name());
+    jobs.start(&name);
+    let output = options
+        .activities
+        .output_dir(group, merged_output_path)
+        .join(options.names.of(group));
+    let merge_result = match merge() {
+        // the child processes of the run get the signal too, failing whatever they were doing
+        Err(err) if options.interrupt.is_triggered() || jobs.is_cancelled(&name) => {
+            debug!("merging {} was interrupted: {}", output.display(), err);
+            Ok(GroupStats::interrupted(
+                output,
+                SystemClock.instant() - started,
+            ))
+        }
+        merge_result => merge_result,
+    };
+    let ended_at = SystemClock.now();
+    let merge_result = merge_result.map(|stats| GroupStats {
+        started: Some(started_at),
+        ended: Some(ended_at),
+        ..stats
+    });
+    let finished = match &merge_result {
+        Ok(stats) => Ok(stats.clone()),
+        Err(err) => Err(GroupError {
+            code: err.code(),
+            message: err.to_string(),
+            log: log.exists().then(|| log),
+            started: Some(started_at),
+            ended: Some(ended_at),
+        }),
+    };
+    jobs.finish(&name, &finished);
+    progress.finish(finished);
+    merge_result
+}
+
+/// Whether a group is merged, see [start_merge].
+pub(crate) enum Start {
+    /// Into the resolved output.
+    Merge(ResolvedOutput),
+    /// Not now, e.g. it was skipped or deferred.
+    Done(Box<GroupStats>),
+}
+
+/// What every merger checks before merging the `group`: the run being interrupted, its deadline,
+/// the outputs of a resumed run or already existing and the free space of the output directory.
+pub(crate) fn start_merge(
+    progress: &mut impl Progress,
+    group: &MovieGroup,
+    merged_output_path: &Path,
+    options: &MergeOptions,
+    started: Instant,
+) -> Result<Start> {
+    let output_dir = options.activities.output_dir(group, merged_output_path);
+    if options.stopping(group) {
+        info!(
+            "not starting {}, the run was interrupted or it was cancelled",
+            group
+        );
+        return Ok(Start::Done(Box::new(GroupStats::interrupted(
+            output_dir.join(options.names.of(group)),
+            SystemClock.instant() - started,
+        ))));
+    }
+    if options
+        .deadline
+        .map_or(false, |deadline| deadline.passed(started))
+    {
+        info!("deferring {}, the run deadline passed", group);
+        let output = output_dir.join(options.names.of(group));
+        return Ok(Start::Done(Box::new(GroupStats::deferred(
+            output,
+            SystemClock.instant() - started,
+        ))));
+    }
+
+    if let Some(path) = options
+        .resume
+        .as_ref()
+        .and_then(|resume| resume.completed_output(&group.name()))
+    {
+        info!(
+            "skipping {}, an earlier run completed it into {}",
+            group,
+            path.display()
+        );
+        return Ok(Start::Done(Box::new(GroupStats::skipped(
+            path,
+            SystemClock.instant() - started,
+        ))));
+    }
+
+    if output_dir != merged_output_path {
+        debug!("merging {} into {}", group, output_dir.display());
+        fs::create_dir_all(&output_dir)?;
+    }
+    let output = resolve_output(output_dir.join(options.names.of(group)), options.on_exists)?;
+    if let ResolvedOutput::Skip(path) = output {
+        info!("skipping {}, {} already exists", group, path.display());
+        return Ok(Start::Done(Box::new(GroupStats::skipped(
+            path,
+            SystemClock.instant() - started,
+        ))));
+    }
+    if !wait_for_space(
+        progress,
+        group,
+        &output_dir,
+        options,
+        &RealFs,
+        &SystemClock,
+        SPACE_POLL_INTERVAL,
+    )? {
+        if options.stopping(group) {
+            info!(
+                "not starting {}, the run was interrupted or it was cancelled",
+                group
+            );
+            return Ok(Start::Done(Box::new(GroupStats::interrupted(
+                output.path().to_path_buf(),
+                SystemClock.instant() - started,
+            ))));
+        }
+        info!(
+            "deferring {}, the run deadline passed waiting for space",
+            group
+        );
+        return Ok(Start::Done(Box::new(GroupStats::deferred(
+            output.path().to_path_buf(),
+            SystemClock.instant() - started,
+        ))));
+    }
+    Ok(Start::Merge(output))
+}
+
+/// The chapters at `paths` probed with a variable frame rate.
+fn variable_frame_rate(paths: &[PathBuf], probed: &Option<Vec<ProbedChapter>>) -> Vec<PathBuf> {
+    probed
+        .iter()
+        .flatten()
+        .zip(paths)
+        .filter(|(chapter, _)| {
+            chapter
+                .video
+                .map_or(false, |video| video.variable_frame_rate())
+        })
+        .map(|(_, path)| path.clone())
+        .collect()
+}
+
+/// Warns the `progress` of the `group` about its `variable_frame_rate` chapters, unless they're
+/// converted with `--cfr`, which the movie is reported with.
+fn warn_variable_frame_rate(
+    progress: &mut dyn Progress,
+    group: &MovieGroup,
+    variable_frame_rate: &[PathBuf],
+    options: &MergeOptions,
+) {
+    if variable_frame_rate.is_empty() {
+        return;
+    }
+    let warning = format!(
+        "{} has {} variable frame rate chapters, some editors drift its audio{}",
+        group,
+        variable_frame_rate.len(),
+        if options.cfr {
+            ", converting it to a constant frame rate"
+        } else {
+            ", pass --cfr to convert it to a constant frame rate"
+        }
+    );
+    warn!("{}", warning);
+    if !options.cfr {
+        progress.warning(WarningKind::VariableFrameRate, &warning);
+    }
+}
+
+/// How the video of the `group` is re-encoded to a constant frame rate with `--cfr`, when it has
+/// `variable_frame_rate` chapters, at the nominal rate of the first of them.
+fn constant_frame_rate(
+    group: &MovieGroup,
+    variable_frame_rate: &[PathBuf],
+    probed: &Option<Vec<ProbedChapter>>,
+    options: &MergeOptions,
+) -> Option<ConstantFrameRate> {
+    if !options.cfr || variable_frame_rate.is_empty() {
+        return None;
+    }
+    let frame_rate = probed
+        .iter()
+        .flatten()
+        .filter_map(|chapter| chapter.video)
+        .find(|video| video.variable_frame_rate())
+        .and_then(|video| video.frame_rate)?;
+
+    Some(ConstantFrameRate {
+        encoder: encoder(group),
+        frame_rate,
+    })
+}
+
+/// How the chapters of the `group` fade into each other with `--crossfade`, shortening their
+/// `probed` durations to the ones in the merged movie, where the fade into the next chapter cuts
+/// the end of every chapter but the last.
+fn crossfade(
+    group: &MovieGroup,
+    paths: &[PathBuf],
+    probed: &mut Option<Vec<ProbedChapter>>,
+    options: &MergeOptions,
+) -> Option<Crossfade> {
+    let duration = options.crossfade?;
+    let probed = match probed {
+        _ if paths.len() < 2 => return None,
+        Some(probed) => probed,
+        None => {
+            warn!(
+                "not crossfading {}, chapter boundaries are unknown without probing",
+                group
+            );
+            return None;
+        }
+    };
+    if probed.iter().any(|chapter| chapter.duration <= duration) {
+        warn!(
+            "not crossfading {}, some of its chapters are shorter than the {} fade",
+            group,
+            HumanDuration(duration)
+        );
+        return None;
+    }
+    let audio = has_sound_track(&paths[0]);
+
+    let last = probed.len() - 1;
+    for chapter in &mut probed[..last] {
+        chapter.duration -= duration;
+    }
+    let offsets = probed[..last]
+        .iter()
+        .scan(Duration::ZERO, |offset, chapter| {
+            *offset += chapter.duration;
+            Some(*offset)
+        })
+        .collect();
+    Some(Crossfade {
+        chapters: paths.to_vec(),
+        offsets,
+        duration,
+        encoder: encoder(group),
+        audio,
+    })
+}
+
+/// The ffmpeg encoder re-encoding the video of the `group`, of the codec of its chapters.
+fn encoder(group: &MovieGroup) -> &'static str {
+    match group.fingerprint.encoding {
+        // the MAX records its 360 movies in HEVC
+        Encoding::Hevc | Encoding::Spherical => "libx265",
+        _ => "libx264",
+    }
+}
+
+/// The size and modification time of a chapter, none where it couldn't be read.
+type ChapterState = (Option<u64>, Option<SystemTime>);
+
+fn chapter_states(fs: &impl Fs, paths: &[PathBuf]) -> Vec<ChapterState> {
+    paths
+        .iter()
+        .map(|path| (fs.len(path).ok(), fs.modified(path).ok()))
+        .collect()
+}
+
+/// The chapters at `paths` whose size or modification time isn't the `planned` one anymore.
+fn changed_chapters(fs: &impl Fs, paths: &[PathBuf], planned: &[ChapterState]) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .zip(chapter_states(fs, paths))
+        .zip(planned)
+        .filter(|((_, state), planned)| state != *planned)
+        .map(|((path, _), _)| path.clone())
+        .collect()
+}
+
+/// Reads the start of every chapter, triggering automounts and surfacing missing credentials of
+/// network filesystems before merging, retrying as mounts can take a moment to come up.
+pub(crate) fn preflight_chapters(
+    group: &MovieGroup,
+    paths: &[PathBuf],
+    retry: Retry,
+) -> Result<()> {
+    let mut retries = 0;
+    loop {
+        let unreadable = paths.iter().find_map(|path| {
+            let mut buf = [0; 4096];
+            File::open(path)
+                .and_then(|mut file| file.read(&mut buf))
+                .err()
+                .map(|err| (path, err))
+        });
 
-        fs::remove_file(ffmpeg_input_file_path)?;
+        match unreadable {
+            None => return check_truncation(paths),
+            Some((path, err)) if retries < retry.retries => {
+                retries += 1;
+                let delay = retry.delay(retries);
+                warn!(
+                    "chapter {} of {} isn't readable, retrying in {:?}: {}",
+                    path.display(),
+                    group,
+                    delay,
+                    err
+                );
+                thread::sleep(delay);
+            }
+            Some((path, err)) => return Err(Error::ChapterUnreadable(path.clone(), err)),
+        }
+    }
+}
 
+/// Fails for chapters which look cut short, most often the last one of a movie recorded until the
+/// battery died, as ffprobe and ffmpeg fail on them with little to go by.
+pub fn check_truncation(paths: &[PathBuf]) -> Result<()> {
+    for path in paths {
+        match mp4::find_truncation(path) {
+            Ok(Some(truncation)) => return Err(Error::TruncatedChapter(path.clone(), truncation)),
+            Ok(None) => {}
+            // left to probing and merging to fail on
+            Err(err) => debug!("looking for truncation of {}: {}", path.display(), err),
+        }
+    }
+    Ok(())
+}
+
+/// Warns about the last chapter being probed without a duration when the others have one, the
+/// index written by a camera whose battery died having no samples.
+fn warn_empty_last_chapter(group: &MovieGroup, paths: &[PathBuf], probed: &[ProbedChapter]) {
+    if let ([others @ .., last], Some(path)) = (probed, paths.last()) {
+        if last.duration.is_zero() && others.iter().any(|chapter| !chapter.duration.is_zero()) {
+            warn!(
+                "the last chapter {} of {} has no duration and appears truncated, e.g. by the battery dying while recording, leave it out with --manifest",
+                path.display(),
+                group
+            );
+        }
+    }
+}
+
+pub(crate) fn record_sources<P: Progress>(
+    progress: &mut P,
+    paths: &[PathBuf],
+    options: &MergeOptions,
+) -> Result<Vec<SourceRecord>> {
+    paths
+        .iter()
+        .map(|path| {
+            record_source(path, options, |hashed, size| progress.hashing(hashed, size))
+                .map_err(From::from)
+        })
+        .collect()
+}
+
+/// Records the source chapter at `path` for the audit trail, reusing the digest of an earlier
+/// run that hashed it unchanged, e.g. the dry run planning the merge.
+pub(crate) fn record_source(
+    path: &Path,
+    options: &MergeOptions,
+    progress: impl FnMut(u64, u64),
+) -> io::Result<SourceRecord> {
+    let (hash, cache) = match (options.audit_hash, &options.probe_cache) {
+        (Some(hash), Some(cache)) => (hash, cache),
+        _ => return audit::record(path, options.audit_hash, progress),
+    };
+    let key = CacheKey::of(path);
+    if let Some((key, digest)) = key
+        .as_ref()
+        .and_then(|key| Some((key, cache.digest(hash, key)?)))
+    {
+        debug!("reusing the {} of {}", hash, path.display());
+        return Ok(SourceRecord {
+            path: path.to_path_buf(),
+            size: key.size(),
+            hash: Some(digest),
+        });
+    }
+
+    let record = audit::record(path, Some(hash), progress)?;
+    // not for a chapter that changed while it was hashed
+    if let (Some(key), Some(digest)) = (key, &record.hash) {
+        if CacheKey::of(path).as_ref() == Some(&key) {
+            cache.insert_digest(hash, key, digest.clone());
+        }
+    }
+    Ok(record)
+}
+
+/// Why the merged movie of the `group` would be too long for some players to seek in, with how
+/// to split its `chapters` into movies shorter than [VERY_LONG_DURATION], if it would be.
+pub(crate) fn very_long_warning(
+    group: &MovieGroup,
+    chapters: usize,
+    duration: Duration,
+) -> Option<String> {
+    if duration <= VERY_LONG_DURATION {
+        return None;
+    }
+
+    let limit = VERY_LONG_DURATION.as_millis();
+    let parts = (duration.as_millis() + limit - 1) / limit;
+    let per_part = (chapters as u128 + parts - 1) / parts;
+    Some(format!(
+        "merged movie {} will be {}, longer than some players can seek in, consider listing its {} chapters in --manifest as {} movies of at most {} chapters each",
+        group.name(),
+        FormattedDuration(duration),
+        chapters,
+        parts,
+        per_part
+    ))
+}
+
+/// Warns the `progress` of the `group` about its merged movie being too long, as soon as it's
+/// probed.
+pub(crate) fn warn_very_long(
+    progress: &mut dyn Progress,
+    group: &MovieGroup,
+    chapters: usize,
+    duration: Duration,
+) {
+    if let Some(warning) = very_long_warning(group, chapters, duration) {
+        warn!("{}", warning);
+        progress.warning(WarningKind::VeryLong, &warning);
+    }
+}
+
+pub(crate) fn check_duration(
+    group: &MovieGroup,
+    expected: Duration,
+    merged: Duration,
+    options: &MergeOptions,
+) -> Result<()> {
+    let difference = if merged > expected {
+        merged - expected
+    } else {
+        expected - merged
+    };
+    if difference <= options.duration_tolerance {
+        return Ok(());
+    }
+
+    if options.allow_duration_mismatch {
+        warn!(
+            "merged movie {} is {} long but its chapters add up to {}",
+            group.name(),
+            FormattedDuration(merged),
+            FormattedDuration(expected)
+        );
         Ok(())
+    } else {
+        Err(Error::DurationMismatch(
+            group.name(),
+            FormattedDuration(merged),
+            FormattedDuration(expected),
+        ))
+    }
+}
+
+/// Hashes the video packets of the chapters, read through the concat `list`, and of the merged
+/// `output`, failing with [Error::NotBitExact] when they differ.
+fn verify_bitexact(
+    progress: &(impl Progress + Clone),
+    group: &MovieGroup,
+    sources: &FFmpegInput,
+    output: &Path,
+    options: &MergeOptions,
+) -> Result<Verification> {
+    let output = FFmpegInput::File(output.to_path_buf());
+    let verification = Verification {
+        sources: hash_video(progress, group, VerifyPass::Sources, sources, options)?,
+        output: hash_video(progress, group, VerifyPass::Output, &output, options)?,
+    };
+    debug!("verified {}: {:?}", group, verification);
+
+    if verification.sources == verification.output {
+        Ok(verification)
+    } else {
+        Err(Error::NotBitExact(
+            group.name(),
+            verification.output,
+            verification.sources,
+        ))
+    }
+}
+
+fn hash_video(
+    progress: &(impl Progress + Clone),
+    group: &MovieGroup,
+    pass: VerifyPass,
+    input: &FFmpegInput,
+    options: &MergeOptions,
+) -> Result<String> {
+    let hash_path = options
+        .workspace
+        .join(format!(".{}.{}.sha256", group.fingerprint.file, pass));
+    let hash = FFmpegHash {
+        input: input.clone(),
+        output: hash_path.clone(),
+    };
+
+    let mut progress = progress.clone();
+    let mut cmd = FFmpegCommand::new(FFmpegCommandKind::Hash(hash), &options.binaries)?
+        .log_to(&stderr_log_path(options, group))?
+        .report_to(&progress)
+        .spawn()?;
+    FFmpegDurationParser::new(cmd.stdout()?, |duration, _| {
+        progress.verifying(pass, duration)
+    })
+    .parse()?;
+    cmd.wait_success()?;
+
+    let contents = fs::read_to_string(&hash_path)?;
+    fs::remove_file(&hash_path)?;
+    parse_hash(&contents).ok_or_else(|| Error::NoHash(input.url().to_string_lossy().into_owned()))
+}
+
+/// Makes a file from the merged movie, removing what was written of it when ffmpeg fails.
+fn derive(
+    progress: &(impl Progress + Clone),
+    group: &MovieGroup,
+    derive: FFmpegDerive,
+    options: &MergeOptions,
+) -> Result<PathBuf> {
+    let (derived, path) = (derive.derived(), derive.output());
+    debug!("making the {} {}", derived, path.display());
+
+    let mut progress = progress.clone();
+    let made = FFmpegCommand::new(FFmpegCommandKind::Derive(derive), &options.binaries)?
+        .log_to(&stderr_log_path(options, group))?
+        .report_to(&progress)
+        .spawn()
+        .and_then(|mut cmd| {
+            FFmpegDurationParser::new(cmd.stdout()?, |duration, _| {
+                progress.deriving(derived, duration)
+            })
+            .parse()?;
+            cmd.wait_success()
+        });
+    if let Err(err) = made {
+        remove_if_exists(&path)?;
+        return Err(err);
+    }
+
+    Ok(path)
+}
+
+/// Whether the chapter at `path` has a sound track, taken to have one when its tracks can't be
+/// read.
+fn has_sound_track(path: &Path) -> bool {
+    mp4::has_sound_track(path).unwrap_or_else(|err| {
+        warn!("reading the tracks of {}: {}", path.display(), err);
+        true
+    })
+}
+
+/// Joins the proxies the camera recorded next to the chapters at `paths` into one next to the
+/// merged movie at `output`, with `--merge-proxies`, along with the thumbnail of the first chapter
+/// or one made from the merged movie when it has none. None when some of the chapters have no
+/// proxy or they can't be joined, which is only warned about.
+pub(crate) fn merge_recorded_proxies(
+    progress: &(impl Progress + Clone),
+    group: &MovieGroup,
+    paths: &[PathBuf],
+    output: &Path,
+    recorded: Option<SystemTime>,
+    options: &MergeOptions,
+) -> Result<Option<PathBuf>> {
+    let proxies = paths
+        .iter()
+        .map(|path| proxy::recorded_proxy(path, &RealFs).ok_or(path))
+        .collect::<std::result::Result<Vec<_>, _>>();
+    let concat = match proxies {
+        Ok(proxies) => Concat::plan(&proxies).map_err(|err| err.to_string()),
+        Err(path) => Err(format!("{} has none", path.display())),
+    };
+    let concat = match concat {
+        Ok(concat) => concat,
+        Err(reason) => {
+            warn!("not merging the proxies of {}, {}", group, reason);
+            return Ok(None);
+        }
+    };
+
+    let path = Derived::Proxy.path(output);
+    debug!("joining the proxies of {} into {}", group, path.display());
+    let mut progress = progress.clone();
+    let (duration, len, mut copied) = (concat.duration(), concat.data_len().max(1), 0);
+    let joined = concat.write(&path, |read| {
+        copied += read;
+        progress.deriving(Derived::Proxy, duration.mul_f64(copied as f64 / len as f64));
+        !options.stopping(group)
+    });
+    match joined {
+        Ok(true) => timestamp::preserve(&path, recorded, &RealFs),
+        Ok(false) => {
+            warn!("stopped merging the proxies of {}", group);
+            remove_if_exists(&path)?;
+            return Ok(None);
+        }
+        Err(err) => {
+            remove_if_exists(&path)?;
+            return Err(err);
+        }
+    }
+
+    let thumbnail = match paths
+        .first()
+        .and_then(|path| proxy::recorded_thumbnail(path, &RealFs))
+    {
+        Some(thumbnail) => {
+            let copy = Derived::Thumbnail.path(output);
+            debug!("copying {} to {}", thumbnail.display(), copy.display());
+            fs::copy(&thumbnail, &copy)?;
+            copy
+        }
+        None => derive(
+            &progress,
+            group,
+            FFmpegDerive::Thumbnail {
+                input: output.to_path_buf(),
+            },
+            options,
+        )?,
+    };
+    timestamp::preserve(&thumbnail, recorded, &RealFs);
+
+    Ok(Some(path))
+}
+
+/// Writes the GPS track read from the GPMF telemetry of the chapters at `paths` next to the merged
+/// movie at `output`, with `--extract-gpx`. The chapters are read rather than the merged movie so
+/// that it works with the data streams dropped too. None when they have no GPS fixes.
+pub(crate) fn extract_gpx(
+    progress: &(impl Progress + Clone),
+    group: &MovieGroup,
+    paths: &[PathBuf],
+    output: &Path,
+    recorded: Option<SystemTime>,
+) -> Result<Option<PathBuf>> {
+    let path = Derived::Gpx.path(output);
+    debug!(
+        "extracting the GPS track of {} to {}",
+        group,
+        path.display()
+    );
+    progress.clone().deriving(Derived::Gpx, Duration::ZERO);
+
+    let chapters = paths
+        .iter()
+        .map(|path| telemetry::read_gps(path))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    if chapters.iter().all(Vec::is_empty) {
+        warn!(
+            "not extracting the GPS track of {}, its chapters have no GPS fixes",
+            group
+        );
+        return Ok(None);
+    }
+    fs::write(&path, export::gpx_track(&group.name(), &chapters))?;
+    timestamp::preserve(&path, recorded, &RealFs);
+
+    Ok(Some(path))
+}
+
+/// Fails with [Error::TelemetryLost] when the merged movie has fewer GPMF telemetry streams than
+/// its chapters, unless the data streams were dropped on purpose.
+pub(crate) fn check_telemetry(
+    group: &MovieGroup,
+    chapters: &[usize],
+    merged: &[usize],
+    options: &MergeOptions,
+) -> Result<()> {
+    if !options.streams.data || merged.len() >= chapters.len() {
+        return Ok(());
+    }
+
+    Err(Error::TelemetryLost(
+        group.name(),
+        chapters.len(),
+        merged.len(),
+    ))
+}
+
+/// The log of the ffmpeg or MP4Box commands run for the `group`, in the `--log-dir` if any.
+pub(crate) fn stderr_log_path(options: &MergeOptions, group: &MovieGroup) -> PathBuf {
+    if let Some(log_dir) = &options.log_dir {
+        return log_dir.log_path(&group.name());
+    }
+    // the stderr logs are meant to outlive the workspace for debugging failed merges
+    temp_dir().join(format!(
+        ".ffmpeg_stderr_{}_{}.log",
+        options.run_id,
+        group.name()
+    ))
+}
+
+pub(crate) fn merged_movie(
+    group: &MovieGroup,
+    movies_full_paths: &[PathBuf],
+    output: &Path,
+    probed: &[ProbedChapter],
+) -> MergedMovie {
+    let chapters = chapter_boundaries(group, probed);
+    let hilights = read_hilights(movies_full_paths, &chapters);
+    MergedMovie {
+        name: group.name(),
+        path: output.to_path_buf(),
+        duration: duration::saturating_sum(probed.iter().map(|chapter| chapter.duration)),
+        chapters,
+        hilights,
     }
 }
 
-fn init_ffmpeg_input_file(filename: &str) -> Result<(impl Write, PathBuf)> {
-    let tmp_file_path = temp_dir().join(&format!(".{}.txt", filename));
+// HiLights are only used for exports so failing to read them shouldn't fail the whole merge
+fn read_hilights(paths: &[PathBuf], chapters: &[ChapterBoundary]) -> Vec<Duration> {
+    paths
+        .iter()
+        .zip(chapters)
+        .flat_map(|(path, chapter)| match mp4::read_hilights(path) {
+            Ok(hilights) => hilights
+                .into_iter()
+                .map(|hilight| chapter.start.saturating_add(hilight))
+                .collect(),
+            Err(err) => {
+                warn!("reading hilights of {}: {}", path.display(), err);
+                vec![]
+            }
+        })
+        .collect()
+}
+
+/// The ffmpeg invocation merging the `group` into `output`, muxing the subtitles when
+/// `subtitles` is set and embedding the `sources` when asked to. The streams and recording time
+/// of the `first` chapter are known when it was probed.
+pub fn ffmpeg_merge(
+    group: &MovieGroup,
+    output: &ResolvedOutput,
+    input: FFmpegInput,
+    options: &MergeOptions,
+    subtitles: bool,
+    sources: &[SourceRecord],
+    first: Option<&ProbedChapter>,
+) -> FFmpegMerge {
+    let file = group.fingerprint.file.to_string();
+    FFmpegMerge {
+        provenance: if options.embed_sources {
+            Some(Provenance::for_output(
+                output.path(),
+                audit::provenance(sources),
+                options.workspace.join(format!(".{}.sources.txt", file)),
+            ))
+        } else {
+            None
+        },
+        input,
+        output: output.path().to_path_buf(),
+        overwrite: matches!(output, ResolvedOutput::Overwrite(..)),
+        subtitles: if subtitles {
+            Some(options.workspace.join(format!(".{}.srt", file)))
+        } else {
+            None
+        },
+        // the chapter boundaries are known only when probing
+        chapters: if options.chapter_markers && options.probe {
+            Some(options.workspace.join(format!(".{}.chapters.txt", file)))
+        } else {
+            None
+        },
+        streams: options.streams,
+        telemetry: first
+            .map(|chapter| chapter.telemetry.clone())
+            .unwrap_or_default(),
+        activity: options.activities.tag(group),
+        // the wall clock of the camera, written like it writes the chapters
+        creation_time: first
+            .and_then(|chapter| chapter.timestamp.time)
+            .map(|time| options.timezone.wall_clock(time, &SystemClock)),
+        title: options.names.title(group),
+        cfr: None,
+        crossfade: None,
+    }
+}
+
+fn input_file_path(workspace: &Path, filename: &str) -> PathBuf {
+    workspace.join(format!(".{}.txt", filename))
+}
+
+fn init_ffmpeg_input_file(workspace: &Path, filename: &str) -> Result<(impl Write, PathBuf)> {
+    let tmp_file_path = input_file_path(workspace, filename);
     info!("Creating temporary ffmpeg file {}", tmp_file_path.display());
     let tmp_file = fs::OpenOptions::new()
         .create(true)
@@ -117,57 +1361,332 @@ fn init_ffmpeg_input_file(filename: &str) -> Result<(impl Write, PathBuf)> {
     Ok((tmp_file, tmp_file_path))
 }
 
-fn write_movies_to_input_file(mut input_file: impl Write, movies_paths: &[PathBuf]) -> Result<()> {
-    movies_paths.iter().try_for_each(|path| {
-        write!(
-            input_file,
-            "file '{}'\r\n",
-            path.as_os_str().to_str().unwrap()
-        )
-        .map_err(From::from)
-    })
+/// The concat demuxer list of the chapter `paths`, failing with [Error::UnsupportedChapterPath]
+/// instead of listing a path ffmpeg would read back differently. The list is read line by line,
+/// so no control characters can be quoted in it, nor can paths that aren't unicode. Paths are
+/// listed the way they're passed to ffmpeg, without the verbatim prefix on Windows.
+pub fn concat_list(paths: &[PathBuf]) -> Result<String> {
+    paths
+        .iter()
+        .map(|path| match ffmpeg_path(path).to_str() {
+            Some(s) if !s.chars().any(char::is_control) => {
+                Ok(format!("file '{}'\r\n", s.replace('\'', r"'\''")))
+            }
+            _ => Err(Error::UnsupportedChapterPath(path.clone())),
+        })
+        .collect()
 }
 
+/// How ffmpeg reads the chapter `paths` of the `group` with the concat method of the `options`,
+/// failing upfront when it couldn't read them back.
+pub fn merge_input(
+    group: &MovieGroup,
+    paths: &[PathBuf],
+    options: &MergeOptions,
+) -> Result<FFmpegInput> {
+    match options.concat {
+        ConcatMethod::Demuxer => {
+            concat_list(paths)?;
+            Ok(FFmpegInput::ConcatList(input_file_path(
+                &options.workspace,
+                &group.fingerprint.file.to_string(),
+            )))
+        }
+        ConcatMethod::Protocol => paths
+            .iter()
+            .map(|path| match path.to_str() {
+                _ if !is_transport_stream(path) => {
+                    Err(Error::ConcatProtocolUnsupported(path.clone()))
+                }
+                // the protocol separates the chapters with |
+                Some(s) if !s.contains('|') && !s.chars().any(char::is_control) => Ok(path.clone()),
+                _ => Err(Error::UnsupportedChapterPath(path.clone())),
+            })
+            .collect::<Result<_>>()
+            .map(FFmpegInput::ConcatProtocol),
+    }
+}
+
+fn is_transport_stream(path: &Path) -> bool {
+    matches!(
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase())
+            .as_deref(),
+        Some("ts") | Some("mts") | Some("m2ts")
+    )
+}
+
+fn remove_concat_list(input: &FFmpegInput) -> Result<()> {
+    if let FFmpegInput::ConcatList(list) = input {
+        fs::remove_file(list)?;
+    }
+    Ok(())
+}
+
+/// Why a running merge was killed.
+enum Stop {
+    Deadline,
+    Interrupted,
+    /// The directory disappeared while the machine was suspended.
+    Gone(PathBuf),
+    /// ffmpeg made no progress for this long.
+    Stalled(Duration),
+}
+
+/// Merges the chapters, `None` when ffmpeg was killed at a hard stopping deadline or as the run
+/// was interrupted. Fails with
+/// [Error::GoneAfterResume] when any of the `watched` directories is gone after the machine
+/// resumes from sleep, e.g. an unmounted card or network share, and with [Error::Stalled] when
+/// ffmpeg makes no progress for the `stall_timeout` of the options.
 fn convert(
     mut progress: impl Progress,
-    input_file_path: &Path,
-    output_path: &Path,
+    merge: FFmpegMerge,
     group: &MovieGroup,
-) -> Result<()> {
+    watched: &[PathBuf],
+    options: &MergeOptions,
+) -> Result<Option<Duration>> {
     // https://trac.ffmpeg.org/wiki/Concatenate
-    let output_file_path = output_path.join(&group.name());
+    let output_lock = options.output_locks.get(&merge.output);
+    let _output_guard = output_lock.lock();
 
-    let mut cmd = FFmpegCommand::new(FFmpegCommandKind::FFmpeg(
-        input_file_path.into(),
-        output_file_path,
-        temp_dir().join(&format!(".ffmpeg_stderr_{}.log", group.name())),
-    ))?
+    let log = stderr_log_path(options, group);
+    let mut cmd = FFmpegCommand::new(
+        FFmpegCommandKind::FFmpeg(Box::new(merge)),
+        &options.binaries,
+    )?
+    .log_to(&log)?
+    .report_to(&progress)
     .spawn()?;
 
-    FFmpegDurationParser::new(cmd.stdout()?, |duration| {
+    let mut converted = Duration::default();
+    let mut stopped = None;
+    let mut suspensions = SuspendDetector::new(SystemClock);
+    let stdout = cmd.stdout()?;
+    // parsed on its own thread, a hung ffmpeg would block the watchdog reading its output
+    let (sender, receiver) = unbounded();
+    let parser = thread::spawn(move || {
+        FFmpegDurationParser::new(stdout, |duration, throughput| {
+            let _ = sender.send((duration, throughput));
+        })
+        .parse()
+    });
+    let mut advanced = SystemClock.instant();
+    loop {
+        let received = match (options.stall_timeout, &stopped) {
+            (Some(timeout), None) => {
+                receiver.recv_timeout(timeout.saturating_sub(SystemClock.instant() - advanced))
+            }
+            _ => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        let (duration, throughput) = match received {
+            Ok(received) => received,
+            Err(RecvTimeoutError::Timeout) => {
+                let timeout = SystemClock.instant() - advanced;
+                warn!(
+                    "ffmpeg made no progress merging {} for {}, killing it",
+                    group,
+                    HumanDuration(timeout)
+                );
+                match cmd.kill() {
+                    Ok(()) => stopped = Some(Stop::Stalled(timeout)),
+                    Err(err) => {
+                        warn!("killing the merge of {}: {}", group, err);
+                        advanced = SystemClock.instant();
+                    }
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        if duration > converted {
+            advanced = SystemClock.instant();
+        }
+        converted = duration;
         debug!(
             "updating progress for {} to {}",
             &group,
             HumanDuration(duration)
         );
-        progress.update(duration);
-    })
-    .parse()?;
+        progress.update(duration, throughput);
+        options.jobs.update(&group.name(), duration);
+        if stopped.is_some() {
+            continue;
+        }
+
+        // progress is reported twice a second which is precise enough for both checks
+        let stop = match suspensions.check() {
+            Some(slept) => {
+                warn!(
+                    "the system was suspended for {} while merging {}",
+                    HumanDuration(slept),
+                    group
+                );
+                watched
+                    .iter()
+                    .find(|dir| !dir.exists())
+                    .map(|dir| Stop::Gone(dir.clone()))
+            }
+            None => None,
+        };
+        let stop = stop.or_else(|| {
+            if options.stopping(group) {
+                return Some(Stop::Interrupted);
+            }
+            options
+                .deadline
+                .filter(|deadline| deadline.stops(SystemClock.instant()))
+                .map(|_| Stop::Deadline)
+        });
+        if let Some(stop) = stop {
+            match cmd.kill() {
+                Ok(()) => stopped = Some(stop),
+                Err(err) => warn!("killing the merge of {}: {}", group, err),
+            }
+        }
+    }
+    parser.join().expect("parsing the ffmpeg progress")?;
     debug!("progress finish {}", &group);
 
-    cmd.wait_success()
+    match stopped {
+        Some(stop) => {
+            // the exit status of a killed ffmpeg is a failure
+            let _ = cmd.wait_success();
+            match stop {
+                Stop::Deadline | Stop::Interrupted => Ok(None),
+                Stop::Gone(dir) => Err(Error::GoneAfterResume(dir)),
+                Stop::Stalled(timeout) => Err(Error::Stalled(
+                    group.name(),
+                    HumanDuration(timeout),
+                    Some(log),
+                )),
+            }
+        }
+        None => match cmd.wait_success() {
+            // ffmpeg got the interrupt itself and quit before reporting progress again
+            Err(_) if options.interrupt.is_triggered() => Ok(None),
+            result => result.map(|_| Some(converted)),
+        },
+    }
+}
+
+pub(crate) fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
 }
 
-fn calculate_total_duration(paths: &[PathBuf]) -> Result<Duration> {
-    paths
+/// Probes the duration and capture time of every chapter with ffprobe, reporting how many
+/// chapters were probed so far to `probed` and the ffprobe processes to `report`. The capture times are read in the `zone`, the
+/// `paths` being the chapters of a single recording. Chapters unchanged since they were cached in `cache`
+/// aren't probed again.
+///
+/// ffprobe reads a single input, probing the concat list of the group in one go would only tell
+/// the duration of the whole group, not the duration, creation time and streams of every chapter
+/// the chapter boundaries and timestamps need. The chapters are probed in parallel on the rayon
+/// pool of the caller instead, e.g. the one of `--probe-parallel`, as starting ffprobe takes about
+/// as long as probing a chapter.
+pub fn probe_chapters(
+    paths: &[PathBuf],
+    binaries: &Binaries,
+    cache: Option<&ProbeCache>,
+    zone: TimeZone,
+    report: Option<&dyn Progress>,
+    mut probed: impl FnMut(usize) + Send,
+) -> Result<Vec<ProbedChapter>> {
+    probed(0);
+    let probed = Mutex::new((0, probed));
+    // a progress per chapter, they're only sent to the threads of the pool
+    let reports = paths
         .iter()
-        .map(|path| {
-            let kind = FFmpegCommandKind::FFprobe(path.into());
-            let mut cmd = FFmpegCommand::new(kind)?.spawn()?;
-            let duration = FFprobeDurationParser::new(cmd.stdout()?).parse()?;
-            cmd.wait_success().map(|_| duration)
+        .map(|_| report.map(|progress| progress.clone_box()))
+        .collect::<Vec<_>>();
+    paths
+        .par_iter()
+        .zip(reports)
+        .map(|(path, report)| {
+            // keyed before probing, a chapter still being copied is probed again next time
+            let key = cache.and_then(|_| CacheKey::of(path));
+            let cached = cache
+                .zip(key.as_ref())
+                .and_then(|(cache, key)| cache.get(key));
+            let probe = match cached {
+                Some(probe) => {
+                    trace!("using the cached probe of {}", path.display());
+                    probe
+                }
+                None => {
+                    let probe = probe_output(path, binaries, report.as_deref())?;
+                    if let Some((cache, key)) = cache.zip(key) {
+                        cache.insert(key, probe.clone());
+                    }
+                    probe
+                }
+            };
+            let (count, probed) = &mut *probed.lock();
+            *count += 1;
+            probed(*count);
+
+            Ok(ProbedChapter {
+                duration: probe.duration,
+                telemetry: probe.telemetry,
+                timestamp: timestamp::resolve(probe.creation_time, path, &RealFs, &SystemClock),
+                video: probe.video,
+            })
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(|mut chapters| {
+            let mut timestamps = chapters
+                .iter()
+                .map(|chapter| chapter.timestamp)
+                .collect::<Vec<_>>();
+            timestamp::align(&mut timestamps, zone, &SystemClock);
+            for (chapter, timestamp) in chapters.iter_mut().zip(timestamps) {
+                chapter.timestamp = timestamp;
+            }
+            chapters
         })
-        .sum()
+}
+
+/// Probes the movie at `path`, reporting the ffprobe process to `report` if any.
+pub(crate) fn probe_output(
+    path: &Path,
+    binaries: &Binaries,
+    report: Option<&dyn Progress>,
+) -> Result<Probe> {
+    probe(path, binaries, report).map(|info| Probe::from(&info))
+}
+
+/// Everything ffprobe reads from the movie file at `path`, its streams and their codecs.
+pub fn probe_media_info(path: &Path, binaries: &Binaries) -> Result<MediaInfo> {
+    probe(path, binaries, None)
+}
+
+fn probe(path: &Path, binaries: &Binaries, report: Option<&dyn Progress>) -> Result<MediaInfo> {
+    let kind = FFmpegCommandKind::FFprobe(path.into());
+    let cmd = FFmpegCommand::new(kind, binaries)?;
+    let mut cmd = match report {
+        Some(progress) => cmd.report_to(progress),
+        None => cmd,
+    }
+    .spawn()?;
+    let info = FFprobeParser::new(cmd.stdout()?).parse_media_info()?;
+    cmd.wait_success().map(|_| info)
+}
+
+#[cfg(test)]
+fn calculate_total_duration(paths: &[PathBuf]) -> Result<Duration> {
+    probe_chapters(
+        paths,
+        &Binaries::default(),
+        None,
+        Default::default(),
+        None,
+        |_| {},
+    )
+    .map(|probed| probed.iter().map(|chapter| chapter.duration).sum())
 }
 
 #[cfg(test)]
@@ -182,6 +1701,18 @@ mod tests {
     };
     use std::{io::Read, sync::Arc};
 
+    use crate::audit::AuditHash;
+    use crate::merge::new_merger;
+    use crate::progress::Throughput;
+    use crate::system::mock::MemoryFs;
+    #[cfg(feature = "json-reporter")]
+    use crate::{
+        progress::JsonProgress,
+        resume::ResumeState,
+        run::{Deadline, Interrupt, RunId},
+        system::mock::SharedBuffer,
+    };
+
     lazy_static::lazy_static! {
         static ref TEST_FILES_PATHS: Vec<PathBuf> =
             vec!["tests/GH010084.mp4", "tests/GH020084.mp4"]
@@ -205,7 +1736,7 @@ mod tests {
 
     #[test]
     fn test_ffmpeg_tmp_file() {
-        let (mut f, p) = init_ffmpeg_input_file("filename").unwrap();
+        let (mut f, p) = init_ffmpeg_input_file(&temp_dir(), "filename").unwrap();
         assert!(p.exists());
         assert_eq!(p.file_name().unwrap().to_str().unwrap(), ".filename.txt");
 
@@ -218,7 +1749,7 @@ mod tests {
 
         assert_eq!(contents, "test");
 
-        let (_, p) = init_ffmpeg_input_file("filename").unwrap();
+        let (_, p) = init_ffmpeg_input_file(&temp_dir(), "filename").unwrap();
         assert!(p.exists());
         assert_eq!(p.file_name().unwrap().to_str().unwrap(), ".filename.txt");
         let mut contents = String::new();
@@ -230,6 +1761,670 @@ mod tests {
         assert_eq!(contents, "");
     }
 
+    /// Reads back the paths of a concat list the way ffmpeg's concat demuxer does.
+    fn parse_concat_list(list: &str) -> Vec<PathBuf> {
+        list.split("\r\n")
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let quoted = line.strip_prefix("file ").unwrap();
+                let (mut path, mut chars, mut in_quotes) = (String::new(), quoted.chars(), false);
+                while let Some(c) = chars.next() {
+                    match (c, in_quotes) {
+                        ('\'', _) => in_quotes = !in_quotes,
+                        ('\\', false) => path.push(chars.next().unwrap()),
+                        (c, _) => path.push(c),
+                    }
+                }
+                assert!(!in_quotes, "unterminated quote in {:?}", line);
+                path.into()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_concat_list_round_trip() {
+        let paths: Vec<PathBuf> = vec![
+            "/movies/GH010084.mp4".into(),
+            "/movies/it's here/GH020084.mp4".into(),
+            "/movies/''/GH030084.mp4".into(),
+            "/movies/back\\slash \"quoted\"/GH040084.mp4".into(),
+            "/movies/ünïcödé ☀/GH050084.mp4".into(),
+            " /movies/ leading and trailing /GH060084.mp4 ".into(),
+        ];
+
+        let list = concat_list(&paths).unwrap();
+        assert_eq!(paths.len(), list.lines().count());
+        assert_eq!(paths, parse_concat_list(&list));
+    }
+
+    #[test]
+    fn test_concat_list_unsupported_paths() {
+        for path in &[
+            "/movies/new\nline/GH010084.mp4",
+            "/movies/carriage\rreturn/GH010084.mp4",
+            "/movies/tab\t/GH010084.mp4",
+            "/movies/nul\0/GH010084.mp4",
+            "/movies/escape\x1b[0m/GH010084.mp4",
+        ] {
+            let paths = vec!["/movies/GH010084.mp4".into(), PathBuf::from(path)];
+            assert!(
+                matches!(
+                    concat_list(&paths),
+                    Err(Error::UnsupportedChapterPath(unsupported)) if unsupported == Path::new(path)
+                ),
+                "{:?} was listed",
+                path
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_concat_list_non_unicode_path() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = PathBuf::from(OsStr::from_bytes(b"/movies/\xff/GH010084.mp4"));
+        assert!(matches!(
+            concat_list(&[path]),
+            Err(Error::UnsupportedChapterPath(..))
+        ));
+    }
+
+    #[test]
+    fn test_merge_input() {
+        let group = crate::group::group_file_names(vec!["GH010084.ts"])[0].clone();
+        let paths = vec![
+            PathBuf::from("/movies/GH010084.ts"),
+            PathBuf::from("/movies/GH020084.TS"),
+        ];
+        let mut options = MergeOptions {
+            workspace: "/workspace".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            FFmpegInput::ConcatList("/workspace/.0084.txt".into()),
+            merge_input(&group, &paths, &options).unwrap()
+        );
+
+        options.concat = ConcatMethod::Protocol;
+        assert_eq!(
+            FFmpegInput::ConcatProtocol(paths.clone()),
+            merge_input(&group, &paths, &options).unwrap()
+        );
+        assert!(matches!(
+            merge_input(&group, &[PathBuf::from("/movies/GH010084.mp4")], &options),
+            Err(Error::ConcatProtocolUnsupported(..))
+        ));
+        assert!(matches!(
+            merge_input(
+                &group,
+                &[PathBuf::from("/movies/a|b/GH010084.ts")],
+                &options
+            ),
+            Err(Error::UnsupportedChapterPath(..))
+        ));
+    }
+
+    #[test]
+    fn test_check_duration() {
+        let movies_path = std::fs::canonicalize(PathBuf::from("./tests")).unwrap();
+        let group = crate::group::group_movies(&movies_path).unwrap()[0].clone();
+        let mut options = MergeOptions::default();
+
+        assert!(check_duration(&group, *TOTAL_DURATION, *TOTAL_DURATION_ENCODED, &options).is_ok());
+        assert!(check_duration(&group, *TOTAL_DURATION_ENCODED, *TOTAL_DURATION, &options).is_ok());
+        assert!(matches!(
+            check_duration(&group, *TOTAL_DURATION, *SINGLE_FILE_DURATION, &options),
+            Err(Error::DurationMismatch(..))
+        ));
+
+        options.duration_tolerance = Duration::from_secs(6);
+        assert!(check_duration(&group, *TOTAL_DURATION, *SINGLE_FILE_DURATION, &options).is_ok());
+
+        options.duration_tolerance = Duration::default();
+        options.allow_duration_mismatch = true;
+        assert!(check_duration(&group, *TOTAL_DURATION, *SINGLE_FILE_DURATION, &options).is_ok());
+    }
+
+    #[test]
+    fn test_preflight_chapters() {
+        let movies_path = std::fs::canonicalize(PathBuf::from("./tests")).unwrap();
+        let group = crate::group::group_movies(&movies_path).unwrap()[0].clone();
+
+        let retry = Retry {
+            retries: 2,
+            delay: Duration::default(),
+        };
+        assert!(preflight_chapters(&group, &TEST_FILES_PATHS, retry).is_ok());
+
+        let missing = movies_path.join("GH030084.mp4");
+        let paths = [TEST_FILES_PATHS[0].clone(), missing.clone()];
+        assert!(matches!(
+            preflight_chapters(&group, &paths, retry),
+            Err(Error::ChapterUnreadable(path, _)) if path == missing
+        ));
+        assert!(matches!(
+            preflight_chapters(&group, &paths, Retry { retries: 0, ..retry }),
+            Err(Error::ChapterUnreadable(path, _)) if path == missing
+        ));
+
+        // recorded until the battery died, without the index written at the end
+        let dir = temp_dir().join("goprotest_preflight_truncated");
+        std::fs::create_dir_all(&dir).unwrap();
+        let truncated = dir.join("GH020084.mp4");
+        std::fs::write(&truncated, b"\0\0\0\x0cftypmp41\0\0\0\x10mdat\x01\x02").unwrap();
+        let paths = [TEST_FILES_PATHS[0].clone(), truncated.clone()];
+        assert!(matches!(
+            preflight_chapters(&group, &paths, retry),
+            Err(Error::TruncatedChapter(path, crate::mp4::Truncation::CutShort(_))) if path == truncated
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_record_source_reuses_digest() {
+        let dir = temp_dir().join("goprotest_record_source");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let chapter = dir.join("GH010084.mp4");
+        std::fs::write(&chapter, b"abc").unwrap();
+        let options = MergeOptions {
+            audit_hash: Some(AuditHash::Sha256),
+            probe_cache: Some(ProbeCache::open(&dir.join("probes.tsv")).unwrap()),
+            ..Default::default()
+        };
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+        let mut hashed = false;
+        let record = record_source(&chapter, &options, |_, _| hashed = true).unwrap();
+        assert_eq!(
+            (Some(sha256.into()), 3, true),
+            (record.hash, record.size, hashed)
+        );
+
+        // the digest of the dry run is taken for the merge
+        let mut hashed = false;
+        let record = record_source(&chapter, &options, |_, _| hashed = true).unwrap();
+        assert_eq!(
+            (Some(sha256.into()), 3, false),
+            (record.hash, record.size, hashed)
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_changed_chapters() {
+        let fs = MemoryFs::default();
+        let paths = vec![
+            PathBuf::from("/movies/GH010084.mp4"),
+            PathBuf::from("/movies/GH020084.mp4"),
+        ];
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_636_931_725);
+        for path in &paths {
+            fs.write(path, b"chapter").unwrap();
+            fs.set_modified(path, modified);
+        }
+        let planned = chapter_states(&fs, &paths);
+        assert!(changed_chapters(&fs, &paths, &planned).is_empty());
+
+        // still being copied when it was probed
+        fs.write(&paths[1], b"chapter 2").unwrap();
+        fs.set_modified(&paths[1], modified);
+        assert_eq!(
+            vec![paths[1].clone()],
+            changed_chapters(&fs, &paths, &planned)
+        );
+
+        // replaced by a chapter of the same size
+        fs.write(&paths[1], b"chapter").unwrap();
+        fs.set_modified(&paths[1], modified + Duration::from_secs(60));
+        assert_eq!(
+            vec![paths[1].clone()],
+            changed_chapters(&fs, &paths, &planned)
+        );
+
+        fs.remove_file(&paths[0]).unwrap();
+        assert_eq!(paths, changed_chapters(&fs, &paths, &planned));
+    }
+
+    #[test]
+    fn test_constant_frame_rate() {
+        use crate::proxy::VideoStream;
+        use crate::timestamp::{ChapterTimestamp, TimestampSource};
+
+        let group = crate::group::group_file_names(vec!["GX010084.mp4", "GX020084.mp4"])
+            .pop()
+            .unwrap();
+        let paths = [
+            PathBuf::from("/movies/GX010084.mp4"),
+            PathBuf::from("/movies/GX020084.mp4"),
+        ];
+        let chapter = |avg_frame_rate: &str| ProbedChapter {
+            duration: Duration::from_secs(60),
+            telemetry: vec![],
+            timestamp: ChapterTimestamp {
+                time: None,
+                source: TimestampSource::Unknown,
+            },
+            video: Some(VideoStream {
+                frame_rate: "60000/1001".parse().ok(),
+                avg_frame_rate: avg_frame_rate.parse().ok(),
+                ..Default::default()
+            }),
+        };
+        let probed = Some(vec![chapter("60000/1001"), chapter("14985000/300301")]);
+        let mut options = MergeOptions::default();
+
+        let variable = variable_frame_rate(&paths, &probed);
+        assert_eq!(vec![paths[1].clone()], variable);
+        assert_eq!(
+            None,
+            constant_frame_rate(&group, &variable, &probed, &options)
+        );
+
+        options.cfr = true;
+        assert_eq!(
+            Some(ConstantFrameRate {
+                encoder: "libx265",
+                frame_rate: "60000/1001".parse().unwrap(),
+            }),
+            constant_frame_rate(&group, &variable, &probed, &options)
+        );
+        assert_eq!(None, constant_frame_rate(&group, &[], &probed, &options));
+        assert!(variable_frame_rate(&paths, &None).is_empty());
+    }
+
+    #[test]
+    fn test_crossfade() {
+        use crate::timestamp::{ChapterTimestamp, TimestampSource};
+
+        let group = crate::group::group_file_names(vec!["GH010084.ts", "GH020084.ts"])
+            .pop()
+            .unwrap();
+        let paths = [
+            PathBuf::from("/movies/GH010084.ts"),
+            PathBuf::from("/movies/GH020084.ts"),
+            PathBuf::from("/movies/GH030084.ts"),
+        ];
+        let chapter = |secs: u64| ProbedChapter {
+            duration: Duration::from_secs(secs),
+            telemetry: vec![],
+            timestamp: ChapterTimestamp {
+                time: None,
+                source: TimestampSource::Unknown,
+            },
+            video: None,
+        };
+        let mut probed = Some(vec![chapter(60), chapter(60), chapter(30)]);
+        let mut options = MergeOptions::default();
+        assert_eq!(None, crossfade(&group, &paths, &mut probed, &options));
+
+        options.crossfade = Some(Duration::from_millis(500));
+        assert_eq!(None, crossfade(&group, &paths, &mut None, &options));
+        assert_eq!(
+            Some(Crossfade {
+                chapters: paths.to_vec(),
+                offsets: vec![Duration::from_millis(59_500), Duration::from_secs(119)],
+                duration: Duration::from_millis(500),
+                encoder: "libx264",
+                // the chapters are taken to have audio when their tracks can't be read
+                audio: true,
+            }),
+            crossfade(&group, &paths, &mut probed, &options)
+        );
+        let durations = probed
+            .iter()
+            .flatten()
+            .map(|chapter| chapter.duration.as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![59_500, 59_500, 30_000], durations);
+
+        options.crossfade = Some(Duration::from_secs(30));
+        let mut probed = Some(vec![chapter(60), chapter(60), chapter(30)]);
+        assert_eq!(None, crossfade(&group, &paths, &mut probed, &options));
+    }
+
+    #[test]
+    fn test_check_telemetry() {
+        let movies_path = std::fs::canonicalize(PathBuf::from("./tests")).unwrap();
+        let group = crate::group::group_movies(&movies_path).unwrap()[0].clone();
+        let mut options = MergeOptions::default();
+
+        assert!(check_telemetry(&group, &[], &[], &options).is_ok());
+        assert!(check_telemetry(&group, &[3], &[3], &options).is_ok());
+        assert!(matches!(
+            check_telemetry(&group, &[3], &[], &options),
+            Err(Error::TelemetryLost(_, 1, 0))
+        ));
+
+        options.streams.data = false;
+        assert!(check_telemetry(&group, &[3], &[], &options).is_ok());
+    }
+
+    #[test]
+    fn test_very_long_warning() {
+        let movies_path = std::fs::canonicalize(PathBuf::from("./tests")).unwrap();
+        let group = crate::group::group_movies(&movies_path).unwrap()[0].clone();
+        let day = Duration::from_secs(24 * 60 * 60);
+
+        assert_eq!(None, very_long_warning(&group, 3, VERY_LONG_DURATION));
+        let warning = very_long_warning(&group, 75, day).unwrap();
+        assert!(
+            warning.ends_with("as 2 movies of at most 38 chapters each"),
+            "{}",
+            warning
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[cfg(feature = "json-reporter")]
+    fn test_json_stream_with_noisy_child() {
+        use crate::merge::ffmpeg::command::isolated;
+
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut progress = JsonProgress::new(
+            RunId::generate(),
+            &crate::group::group_file_names(vec!["GH010084.mp4", "GH020084.mp4"])[0],
+            0,
+            1,
+            out.clone(),
+            err.clone(),
+        );
+        progress.set_len(Duration::from_secs(2));
+
+        // a converter printing all sorts of unexpected output on both streams
+        let mut child = isolated("sh")
+            .args([
+                "-c",
+                "printf '{\"broken\\n\\377\\376\\nout_time=00:00:01.000000\\n'; \
+                 echo '{\"progress\": 1}' >&2; \
+                 printf 'frame=1\\nout_time=00:00:02.000000\\nprogress=end\\n'",
+            ])
+            .spawn()
+            .unwrap();
+        FFmpegDurationParser::new(child.stdout.take().unwrap(), |duration, throughput| {
+            progress.update(duration, throughput)
+        })
+        .parse()
+        .unwrap();
+        assert!(child.wait().unwrap().success());
+        progress.finish(Ok(GroupStats {
+            output: "/tmp/GH000084.mp4".into(),
+            duration: Duration::from_secs(2),
+            elapsed: Duration::from_secs(1),
+            preflight: Duration::default(),
+            sources: vec![],
+            actions: vec![],
+            skipped: false,
+            deferred: false,
+            interrupted: false,
+            verification: None,
+            telemetry_track: None,
+            proxy: None,
+            gpx: None,
+            audio: None,
+            excluded: vec![],
+            variable_frame_rate: vec![],
+            replanned: vec![],
+            started: None,
+            ended: None,
+        }));
+
+        let lines = out.lines();
+        assert_eq!(3, lines.len());
+        assert_eq!(50, lines[0]["progress_percentage"]);
+        assert_eq!(100, lines[1]["progress_percentage"]);
+        assert_eq!("/tmp/GH000084.mp4", lines[2]["output"]);
+        assert!(err.lines().is_empty());
+    }
+
+    /// The directory of the test chapters and their group.
+    fn test_group() -> (PathBuf, MovieGroup) {
+        let movies_path = std::fs::canonicalize(PathBuf::from("./tests")).unwrap();
+        let group = crate::group::group_movies(&movies_path).unwrap()[0].clone();
+        (movies_path, group)
+    }
+
+    /// Merges the `group` of the chapters in `input` into `output`, returning the json events
+    /// reported along with the result.
+    #[cfg(feature = "json-reporter")]
+    fn json_merge(
+        group: &MovieGroup,
+        input: &Path,
+        output: &Path,
+        options: MergeOptions,
+    ) -> (Result<GroupStats>, SharedBuffer) {
+        let out = SharedBuffer::default();
+        let progress = JsonProgress::new(
+            RunId::generate(),
+            group,
+            0,
+            1,
+            out.clone(),
+            SharedBuffer::default(),
+        );
+        let merged = new_merger::<FFmpegMerger>(
+            Box::new(progress),
+            group.clone(),
+            input.to_path_buf(),
+            output.to_path_buf(),
+            options,
+        )
+        .merge();
+        (merged, out)
+    }
+
+    #[test]
+    #[cfg(feature = "json-reporter")]
+    fn test_merger_skips_existing_output() {
+        let output = temp_dir().join("goprotest_merger_skips_existing_output");
+        std::fs::create_dir_all(&output).unwrap();
+        let (movies_path, group) = test_group();
+        std::fs::write(output.join(group.name()), "merged before").unwrap();
+
+        let options = MergeOptions {
+            on_exists: OnExists::Skip,
+            ..Default::default()
+        };
+        let (stats, out) = json_merge(&group, &movies_path, &output, options);
+        let stats = stats.unwrap();
+
+        assert!(stats.skipped);
+        assert_eq!(output.join(group.name()), stats.output);
+        assert!(stats.started.unwrap() <= stats.ended.unwrap());
+        assert_eq!(true, out.lines()[0]["skipped"]);
+        assert!(out.lines()[0]["started"].is_string());
+
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "json-reporter")]
+    fn test_merger_read_only_input() {
+        let dir = temp_dir().join("goprotest_merger_read_only_input");
+        let _ = std::fs::remove_dir_all(&dir);
+        let (input, output, workspace) = (dir.join("card"), dir.join("out"), dir.join("work"));
+        for path in [&input, &output, &workspace] {
+            std::fs::create_dir_all(path).unwrap();
+        }
+        for path in TEST_FILES_PATHS.iter() {
+            std::fs::copy(path, input.join(path.file_name().unwrap())).unwrap();
+        }
+        let listing = || {
+            let mut entries = std::fs::read_dir(&input)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect::<Vec<_>>();
+            entries.sort();
+            entries
+        };
+        let before = listing();
+        let writable = std::fs::metadata(&input).unwrap().permissions();
+        let mut read_only = writable.clone();
+        read_only.set_readonly(true);
+        std::fs::set_permissions(&input, read_only).unwrap();
+
+        let group = crate::group::group_movies(&input).unwrap()[0].clone();
+        let options = MergeOptions {
+            workspace: workspace.clone(),
+            exports: vec![ExportFormat::Subtitles, ExportFormat::WebVtt],
+            subtitles: true,
+            chapter_markers: true,
+            embed_sources: true,
+            ..Default::default()
+        };
+        let (merged, _) = json_merge(&group, &input, &output, options);
+
+        // every intermediate file went to the workspace and is gone, even running as root
+        assert_eq!(before, listing());
+        assert_eq!(0, std::fs::read_dir(&workspace).unwrap().count());
+        assert_eq!(output.join(group.name()), merged.unwrap().output);
+
+        std::fs::set_permissions(&input, writable).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "json-reporter")]
+    fn test_merger_skips_resumed_group() {
+        let output = temp_dir().join("goprotest_merger_skips_resumed_group");
+        let _ = std::fs::remove_dir_all(&output);
+        std::fs::create_dir_all(&output).unwrap();
+        let (movies_path, group) = test_group();
+        let merged = output.join(group.name());
+        std::fs::write(&merged, "merged before").unwrap();
+        let resume = ResumeState::open(&output).unwrap();
+        resume
+            .complete(&group.name(), &group.id(), &merged)
+            .unwrap();
+
+        // overwriting would merge it again if it weren't for the resume state
+        let options = MergeOptions {
+            resume: Some(resume),
+            ..Default::default()
+        };
+        let (stats, out) = json_merge(&group, &movies_path, &output, options);
+        let stats = stats.unwrap();
+
+        assert!(stats.skipped);
+        assert_eq!(merged, stats.output);
+        assert_eq!(true, out.lines()[0]["skipped"]);
+        assert_eq!("merged before", std::fs::read_to_string(&merged).unwrap());
+
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "json-reporter")]
+    fn test_merger_defers_after_deadline() {
+        let output = temp_dir().join("goprotest_merger_defers_after_deadline");
+        let (movies_path, group) = test_group();
+
+        let deadline = Deadline::after(SystemClock.instant(), Duration::default(), false);
+        let options = MergeOptions {
+            deadline: Some(deadline),
+            ..Default::default()
+        };
+        let (stats, out) = json_merge(&group, &movies_path, &output, options);
+        let stats = stats.unwrap();
+
+        assert!(stats.deferred);
+        assert_eq!(output.join(group.name()), stats.output);
+        assert_eq!(true, out.lines()[0]["deferred"]);
+        assert!(!output.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[cfg(feature = "json-reporter")]
+    fn test_convert_kills_stalled_ffmpeg() {
+        use std::os::unix::fs::PermissionsExt;
+
+        use crate::merge::StreamMapping;
+
+        let dir = temp_dir().join("goprotest_convert_kills_stalled_ffmpeg");
+        std::fs::create_dir_all(&dir).unwrap();
+        // reports a second of progress then hangs, exec so that killing it closes its stdout
+        let ffmpeg = dir.join("ffmpeg");
+        std::fs::write(
+            &ffmpeg,
+            "#!/bin/sh\nprintf 'out_time=00:00:01.000000\\nprogress=continue\\n'\nexec sleep 30\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&ffmpeg, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let group = crate::group::group_file_names(vec!["GH010084.mp4"])[0].clone();
+        let out = SharedBuffer::default();
+        let progress = JsonProgress::new(
+            RunId::generate(),
+            &group,
+            0,
+            1,
+            out.clone(),
+            SharedBuffer::default(),
+        );
+        let merge = FFmpegMerge {
+            input: FFmpegInput::ConcatList(dir.join(".0084.txt")),
+            output: dir.join(group.name()),
+            subtitles: None,
+            chapters: None,
+            streams: StreamMapping::default(),
+            overwrite: true,
+            provenance: None,
+            telemetry: vec![],
+            activity: None,
+            creation_time: None,
+            title: None,
+            cfr: None,
+            crossfade: None,
+        };
+        let options = MergeOptions {
+            stall_timeout: Some(Duration::from_millis(500)),
+            binaries: Binaries {
+                ffmpeg,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let started = SystemClock.instant();
+        let result = convert(progress, merge, &group, &[], &options);
+        assert!(
+            matches!(&result, Err(Error::Stalled(name, _, _)) if name == "GH000084.mp4"),
+            "{:?}",
+            result
+        );
+        assert!(SystemClock.instant() - started < Duration::from_secs(10));
+        assert_eq!(1, out.lines().len());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "json-reporter")]
+    fn test_merger_interrupted_before_start() {
+        let output = temp_dir().join("goprotest_merger_interrupted_before_start");
+        let (movies_path, group) = test_group();
+
+        let interrupt = Interrupt::default();
+        interrupt.trigger();
+        let options = MergeOptions {
+            interrupt,
+            ..Default::default()
+        };
+        let (stats, out) = json_merge(&group, &movies_path, &output, options);
+        let stats = stats.unwrap();
+
+        assert!(stats.interrupted);
+        assert!(!stats.completed());
+        assert_eq!(output.join(group.name()), stats.output);
+        assert_eq!(true, out.lines()[0]["interrupted"]);
+        assert!(!output.exists());
+    }
+
     #[test]
     fn test_calculate_total_duration() {
         let duration = calculate_total_duration(&TEST_FILES_PATHS).unwrap();
@@ -246,9 +2441,21 @@ mod tests {
         impl Progress for MockProgress {
             fn set_len(&mut self, _: Duration) {}
 
-            fn update(&mut self, _: Duration) {}
+            fn update(&mut self, _: Duration, _: Throughput) {}
+
+            fn probing(&mut self, _: usize, _: usize) {}
+
+            fn hashing(&mut self, _: u64, _: u64) {}
+
+            fn verifying(&mut self, _: VerifyPass, _: Duration) {}
+
+            fn deriving(&mut self, _: Derived, _: Duration) {}
 
-            fn finish(&self, _: Option<String>) {
+            fn low_space(&mut self, _: &Path, _: u64, _: u64) {}
+
+            fn warning(&mut self, _: WarningKind, _: &str) {}
+
+            fn finish(&self, _: std::result::Result<GroupStats, GroupError>) {
                 self.finish_called.store(true, Ordering::Relaxed);
             }
         }
@@ -259,9 +2466,14 @@ mod tests {
         let merged_file_name = tmp_path.join("GH000084.mp4");
 
         let progress = MockProgress::default();
-        let movies_path = std::fs::canonicalize(PathBuf::from("./tests")).unwrap();
-        let group = crate::group::group_movies(&movies_path).unwrap()[0].clone();
-        let merger = FFmpegMerger::new(progress.clone(), group, movies_path, tmp_path);
+        let (movies_path, group) = test_group();
+        let merger = new_merger::<FFmpegMerger>(
+            Box::new(progress.clone()),
+            group,
+            movies_path,
+            tmp_path,
+            MergeOptions::default(),
+        );
         merger.merge().unwrap();
 
         let duration = calculate_total_duration(&[merged_file_name]).unwrap();
@@ -1,7 +1,13 @@
+mod cache;
 mod command;
+mod media_info;
 mod merger;
 mod parser;
+mod runner;
 
+pub use cache::*;
 pub use command::*;
+pub use media_info::*;
 pub use merger::*;
 pub use parser::*;
+pub use runner::*;
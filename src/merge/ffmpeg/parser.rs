@@ -1,9 +1,12 @@
 use std::io::{BufRead, BufReader, Read};
-use std::ops::Add;
 use std::str::Split;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
+use crate::duration;
+use crate::merge::ffmpeg::MediaInfo;
 use crate::merge::Result;
+use crate::progress::Throughput;
+use crate::proxy::VideoStream;
 
 use log::*;
 
@@ -27,34 +30,64 @@ pub trait CommandStreamDurationParser<T: Read, V: Default> {
     fn parse(&mut self) -> Result<V>;
 }
 
-pub struct FFprobeDurationParser<T: Read> {
-    stream: Option<T>,
+/// Information about a single movie file read from the ffprobe output.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Probe {
+    pub duration: Duration,
+    pub creation_time: Option<SystemTime>,
+    /// Indices of the GPMF telemetry streams, GPS, gyro and the like.
+    pub telemetry: Vec<usize>,
+    /// The first video stream, to tell low resolution proxies apart.
+    pub video: Option<VideoStream>,
 }
 
-impl<T: Read> CommandStreamDurationParser<T, Duration> for FFprobeDurationParser<T> {
-    fn parse(&mut self) -> Result<Duration> {
-        let duration = parse_command_stream(self.stream.take().unwrap(), |name, value| {
-            if name != "duration" {
-                return None;
-            }
-
-            let mut split = CharToU64Iter(value.split('.'));
-            let seconds = Duration::from_secs(split.next_default());
-            let micros = Duration::from_micros(split.next_default());
-
-            Some(seconds.add(micros))
-        })?;
+pub struct FFprobeParser<T: Read> {
+    stream: Option<T>,
+}
 
-        Ok(duration)
+impl<T: Read> CommandStreamDurationParser<T, Probe> for FFprobeParser<T> {
+    fn parse(&mut self) -> Result<Probe> {
+        Ok(Probe::from(&self.parse_media_info()?))
     }
 }
 
-impl<T: Read> FFprobeDurationParser<T> {
+impl<T: Read> FFprobeParser<T> {
     pub fn new(stream: T) -> Self {
         Self {
             stream: Some(stream),
         }
     }
+
+    /// Everything ffprobe printed about the file, see [Probe] for the parts merging needs.
+    pub fn parse_media_info(&mut self) -> Result<MediaInfo> {
+        let mut json = vec![];
+        self.stream.take().unwrap().read_to_end(&mut json)?;
+        trace!("ffprobe output {}", String::from_utf8_lossy(&json));
+        MediaInfo::from_json(&json)
+    }
+}
+
+impl From<&MediaInfo> for Probe {
+    fn from(info: &MediaInfo) -> Self {
+        Probe {
+            // some containers only know the durations of their streams
+            duration: info
+                .duration
+                .or_else(|| info.streams.iter().find_map(|stream| stream.duration))
+                .unwrap_or_default(),
+            creation_time: info
+                .creation_time
+                .or_else(|| info.streams.iter().find_map(|stream| stream.creation_time)),
+            telemetry: info.telemetry(),
+            video: info.video().map(|video| VideoStream {
+                width: video.width.unwrap_or_default(),
+                height: video.height.unwrap_or_default(),
+                bit_rate: video.bit_rate,
+                frame_rate: video.frame_rate,
+                avg_frame_rate: video.avg_frame_rate,
+            }),
+        }
+    }
 }
 
 pub struct FFmpegDurationParser<T: Read, P> {
@@ -62,24 +95,46 @@ pub struct FFmpegDurationParser<T: Read, P> {
     cb: P,
 }
 
-impl<T: Read, P: FnMut(Duration)> CommandStreamDurationParser<T, ()>
+impl<T: Read, P: FnMut(Duration, Throughput)> CommandStreamDurationParser<T, ()>
     for FFmpegDurationParser<T, P>
 {
+    /// Reports every block of the progress once its `progress=` line ends it, the throughput
+    /// following the time within the block.
     fn parse(&mut self) -> Result<()> {
-        parse_command_stream(self.stream.take().unwrap(), |name, value| match name {
-            "out_time" => {
-                let duration = self.parse_timestamp_match(value);
-                (self.cb)(duration);
-                None
-            }
-            _ => None,
+        let mut pending = None;
+        let mut throughput = Throughput::default();
+        parse_command_stream::<()>(self.stream.take().unwrap(), |name, value| {
+            match name {
+                "out_time" => {
+                    // of a block cut short without its progress line
+                    if let Some(duration) = pending.replace(self.parse_timestamp_match(value)) {
+                        (self.cb)(duration, throughput);
+                    }
+                }
+                // N/A until ffmpeg merged a little, padded to align, e.g. "speed= 1.9x"
+                "speed" => throughput.speed = value.trim().trim_end_matches('x').parse().ok(),
+                "bitrate" => {
+                    throughput.bitrate = value.trim().trim_end_matches("kbits/s").parse().ok()
+                }
+                "fps" => throughput.fps = value.trim().parse().ok(),
+                "progress" => {
+                    if let Some(duration) = pending.take() {
+                        (self.cb)(duration, throughput);
+                    }
+                }
+                _ => {}
+            };
+            None
         })?;
+        if let Some(duration) = pending {
+            (self.cb)(duration, throughput);
+        }
 
         Ok(())
     }
 }
 
-impl<T: Read, P: FnMut(Duration)> FFmpegDurationParser<T, P> {
+impl<T: Read, P: FnMut(Duration, Throughput)> FFmpegDurationParser<T, P> {
     pub fn new(stream: T, cb: P) -> Self {
         Self {
             stream: stream.into(),
@@ -91,12 +146,12 @@ impl<T: Read, P: FnMut(Duration)> FFmpegDurationParser<T, P> {
         let mut micros_split = input.split('.');
         let mut secs_split = CharToU64Iter(micros_split.next().unwrap_or("0:0:0").split(':'));
 
-        let hours = Duration::from_secs(secs_split.next_default() * 60 * 60);
-        let minutes = Duration::from_secs(secs_split.next_default() * 60);
-        let seconds = Duration::from_secs(secs_split.next_default());
-        let micros = Duration::from_micros(CharToU64Iter(micros_split).next_default());
-
-        [hours, minutes, seconds, micros].iter().sum()
+        duration::from_clock(
+            secs_split.next_default(),
+            secs_split.next_default(),
+            secs_split.next_default(),
+            CharToU64Iter(micros_split).next_default(),
+        )
     }
 }
 
@@ -105,9 +160,12 @@ fn parse_command_stream<V: Default>(
     mut parse: impl FnMut(&str, &str) -> Option<V>,
 ) -> Result<V> {
     let stdout_reader = BufReader::new(stream);
-    let mut lines = stdout_reader.lines();
+    // unexpected output, e.g. non utf-8 lines, is skipped instead of ending the parsing early
+    // which would leave the child blocked on a full pipe
+    let mut lines = stdout_reader.split(b'\n');
 
     while let Some(Ok(line)) = lines.next() {
+        let line = String::from_utf8_lossy(&line);
         trace!("get_duration_from_command_stream line {}", &line);
 
         let mut split = line.split('=');
@@ -128,6 +186,9 @@ mod tests {
     use super::*;
 
     use std::fmt::Write;
+    use std::ops::Add;
+
+    use crate::frame_rate::FrameRate;
 
     #[test]
     fn test_ffmpeg_parse_duration() {
@@ -170,11 +231,21 @@ mod tests {
             ),
             ("00:00:00.000", Duration::default()),
             ("000:0000:0.000000", Duration::default()),
+            // multi day timelapses
+            (
+                "50:13:44.000",
+                Duration::from_secs(50 * 60 * 60 + 13 * 60 + 44),
+            ),
+            // saturates instead of overflowing
+            (
+                "18446744073709551615:00:00.0",
+                Duration::from_secs(u64::MAX),
+            ),
         ]
         .into_iter()
         .for_each(|(input, expected)| {
             let s = String::new();
-            let parser = FFmpegDurationParser::new(s.as_bytes(), |_| {});
+            let parser = FFmpegDurationParser::new(s.as_bytes(), |_, _| {});
 
             let result = parser.parse_timestamp_match(input);
             assert_eq!(expected, result);
@@ -214,7 +285,7 @@ mod tests {
         .into_iter()
         .for_each(|(stream, expected)| {
             let mut total_duration = Duration::default();
-            let mut parser = FFmpegDurationParser::new(stream.as_bytes(), |duration| {
+            let mut parser = FFmpegDurationParser::new(stream.as_bytes(), |duration, _| {
                 total_duration = total_duration.add(duration);
             });
 
@@ -225,35 +296,138 @@ mod tests {
     }
 
     #[test]
-    fn test_ffprobe_duration_parse_stream() {
-        fn stream_data(v: &'static str) -> String {
-            let mut d = String::new();
-            writeln!(d, "duration={}", v).unwrap();
-            writeln!(d, "other_key_name={}", v).unwrap();
-            d
-        }
-
-        [
-            (stream_data("5.0"), Duration::from_secs(5)),
-            (
-                stream_data("99.10"),
-                Duration::from_secs(99).add(Duration::from_micros(10)),
-            ),
-            (
-                stream_data("100.10000"),
-                Duration::from_secs(100).add(Duration::from_micros(10000)),
-            ),
-            (stream_data("0000.0000"), Duration::default()),
-            (stream_data("1111."), Duration::from_secs(1111)),
-            (stream_data(".1"), Duration::from_micros(1)),
-        ]
-        .into_iter()
-        .for_each(|(input, expected)| {
-            let result = FFprobeDurationParser::new(input.as_bytes())
-                .parse()
-                .unwrap();
+    fn test_ffmpeg_parse_duration_stream_with_noise() {
+        let stream =
+            b"{\"garbage\n\xff\xfe=\x80\nout_time=00:00:01.000000\n\nout_time=00:00:02.000000\n";
+
+        let mut durations = vec![];
+        FFmpegDurationParser::new(&stream[..], |duration, _| durations.push(duration))
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            vec![Duration::from_secs(1), Duration::from_secs(2)],
+            durations
+        );
+    }
 
-            assert_eq!(expected, result);
+    #[test]
+    fn test_ffmpeg_parse_throughput() {
+        let stream = "frame=0\nfps=0.00\nbitrate=N/A\nout_time=00:00:00.000000\nspeed=N/A\nprogress=continue\n\
+                      frame=570\nfps=113.85\nbitrate=11645.2kbits/s\nout_time=00:00:09.500000\nspeed=1.9x\nprogress=continue\n\
+                      frame=  585\nfps= 98.5\nbitrate= 837.6kbits/s\nout_time=00:00:09.750000\nspeed= 2.1x\nprogress=continue\n\
+                      frame=600\nfps=114.02\nout_time=00:00:10.000000\n";
+
+        let mut updates = vec![];
+        FFmpegDurationParser::new(stream.as_bytes(), |duration, throughput| {
+            updates.push((duration, throughput))
         })
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                (
+                    Duration::default(),
+                    Throughput {
+                        speed: None,
+                        bitrate: None,
+                        fps: Some(0.0)
+                    }
+                ),
+                (
+                    Duration::from_millis(9_500),
+                    Throughput {
+                        speed: Some(1.9),
+                        bitrate: Some(11645.2),
+                        fps: Some(113.85)
+                    }
+                ),
+                (
+                    Duration::from_millis(9_750),
+                    Throughput {
+                        speed: Some(2.1),
+                        bitrate: Some(837.6),
+                        fps: Some(98.5)
+                    }
+                ),
+                // ffmpeg killed before ending the block, the speed is of the block before
+                (
+                    Duration::from_secs(10),
+                    Throughput {
+                        speed: Some(2.1),
+                        bitrate: Some(837.6),
+                        fps: Some(114.02)
+                    }
+                ),
+            ],
+            updates
+        );
+    }
+
+    #[test]
+    fn test_ffprobe_parse_stream() {
+        let json = r#"{
+            "streams": [
+                {
+                    "index": 0,
+                    "codec_type": "audio",
+                    "bit_rate": "189000",
+                    "duration": "5.458333",
+                    "tags": {"creation_time": "2021-11-14T23:15:25.000000Z"}
+                },
+                {
+                    "index": 1,
+                    "codec_type": "video",
+                    "width": 848,
+                    "height": 480,
+                    "bit_rate": "3014000",
+                    "r_frame_rate": "60000/1001",
+                    "avg_frame_rate": "0/0"
+                },
+                {"index": 2, "codec_type": "video", "width": 1920, "height": 1080},
+                {"index": 3, "codec_type": "data", "codec_tag_string": "gpmd"}
+            ],
+            "format": {}
+        }"#;
+        let result = FFprobeParser::new(json.as_bytes()).parse().unwrap();
+        assert_eq!(
+            Probe {
+                duration: Duration::from_micros(5_458_333),
+                creation_time: Some(humantime::parse_rfc3339("2021-11-14T23:15:25Z").unwrap()),
+                telemetry: vec![3],
+                video: Some(VideoStream {
+                    width: 848,
+                    height: 480,
+                    bit_rate: Some(3_014_000),
+                    frame_rate: Some(FrameRate {
+                        num: 60000,
+                        den: 1001
+                    }),
+                    avg_frame_rate: None,
+                }),
+            },
+            result
+        );
+
+        // the container knows better than its streams
+        let json = r#"{
+            "streams": [{"index": 0, "codec_type": "audio", "duration": "1.000000"}],
+            "format": {
+                "duration": "2.500000",
+                "tags": {"creation_time": "2000-01-01T00:00:00.000000Z"}
+            }
+        }"#;
+        let result = FFprobeParser::new(json.as_bytes()).parse().unwrap();
+        assert_eq!(Duration::from_millis(2_500), result.duration);
+        assert_eq!(
+            Some(humantime::parse_rfc3339("2000-01-01T00:00:00Z").unwrap()),
+            result.creation_time
+        );
+        assert_eq!(None, result.video);
+
+        assert!(FFprobeParser::new("duration=1.0\n".as_bytes())
+            .parse()
+            .is_err());
     }
 }
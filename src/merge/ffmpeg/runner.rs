@@ -0,0 +1,342 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command as Process, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use derive_more::Display;
+use log::*;
+
+use crate::merge::ffmpeg::isolated;
+use crate::merge::{Error, Result};
+
+/// Numbers the containers started by the run, naming them apart.
+static CONTAINERS: AtomicUsize = AtomicUsize::new(0);
+
+/// How the ffmpeg, ffprobe and MP4Box commands are run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandRunner {
+    /// Directly on the host.
+    Host,
+    /// In a container, from `--ffmpeg-via`.
+    Container(Container),
+}
+
+impl Default for CommandRunner {
+    fn default() -> Self {
+        CommandRunner::Host
+    }
+}
+
+impl CommandRunner {
+    /// The process running the `program`, its arguments still to be added. Commands run in a
+    /// container come with it, to be killed with them.
+    pub fn command(&self, program: &Path) -> (Process, Option<RunningContainer>) {
+        match self {
+            CommandRunner::Host => (isolated(program), None),
+            CommandRunner::Container(container) => {
+                let running = RunningContainer {
+                    engine: container.engine,
+                    name: format!(
+                        "gopro-merge-{}-{}",
+                        process::id(),
+                        CONTAINERS.fetch_add(1, Ordering::Relaxed)
+                    ),
+                };
+                let mut process = isolated(container.engine.to_string());
+                process.args(container.run_args(program, &running.name));
+                (process, Some(running))
+            }
+        }
+    }
+
+    /// Checks upfront that the container engine, if any, exists.
+    pub fn preflight(&self) -> Result<()> {
+        let container = match self {
+            CommandRunner::Host => return Ok(()),
+            CommandRunner::Container(container) => container,
+        };
+        match isolated(container.engine.to_string())
+            .arg("--version")
+            .stdout(Stdio::null())
+            .status()
+        {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Err(Error::ContainerEngineNotFound(container.engine))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// The engine running the containers of `--ffmpeg-via`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum Engine {
+    #[display(fmt = "docker")]
+    Docker,
+    #[display(fmt = "podman")]
+    Podman,
+}
+
+/// Runs the commands in a container of the `image`, e.g. `docker:jrottenberg/ffmpeg`, with the
+/// `mounts` bind mounted at the same paths as on the host so that the paths passed to the
+/// commands, and the ones in the concat lists, need no translating.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    pub engine: Engine,
+    pub image: String,
+    pub mounts: Vec<PathBuf>,
+}
+
+impl Container {
+    /// Mounts the directories at `paths` too, failing for paths that can't be passed to
+    /// `--volume`, e.g. Windows paths.
+    pub fn mounting<'a>(mut self, paths: impl IntoIterator<Item = &'a Path>) -> Result<Self> {
+        let paths: Vec<PathBuf> = self
+            .mounts
+            .iter()
+            .cloned()
+            .chain(paths.into_iter().map(Path::to_path_buf))
+            .collect();
+        self.mounts = mounts(paths.iter().map(PathBuf::as_path));
+        match self
+            .mounts
+            .iter()
+            .find(|path| !path.is_absolute() || path.to_string_lossy().contains(':'))
+        {
+            Some(path) => Err(Error::UnmountablePath(path.clone())),
+            None => Ok(self),
+        }
+    }
+
+    /// The arguments of the engine running the `program` of the image in the container `name`,
+    /// followed by the arguments of the program.
+    fn run_args(&self, program: &Path, name: &str) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![
+            "run".into(),
+            "--rm".into(),
+            "--name".into(),
+            name.into(),
+            "--network".into(),
+            "none".into(),
+        ];
+        args.extend(self.user_args());
+        // the entrypoint of the image is often ffmpeg itself, ffprobe wouldn't run otherwise
+        args.push("--entrypoint".into());
+        args.push(program.into());
+        // not created on the host by the engine when missing, e.g. the workspace of a dry run
+        for mount in self.mounts.iter().filter(|mount| mount.exists()) {
+            let mut volume = mount.as_os_str().to_os_string();
+            volume.push(":");
+            volume.push(mount);
+            args.push("--volume".into());
+            args.push(volume);
+        }
+        args.push(self.image.as_str().into());
+        args
+    }
+
+    /// Runs the commands as the user of the run, the outputs being owned by it rather than by
+    /// the root of the container.
+    #[cfg(unix)]
+    fn user_args(&self) -> Vec<OsString> {
+        match self.engine {
+            // rootless podman maps the user of the run to root in the container
+            Engine::Podman => vec!["--userns".into(), "keep-id".into()],
+            Engine::Docker => {
+                let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+                vec!["--user".into(), format!("{}:{}", uid, gid).into()]
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn user_args(&self) -> Vec<OsString> {
+        vec![]
+    }
+}
+
+impl FromStr for Container {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (engine, image) = match s.split_once(':') {
+            Some(("docker", image)) => (Engine::Docker, image),
+            Some(("podman", image)) => (Engine::Podman, image),
+            _ => return Err(Error::InvalidFFmpegVia(s.into())),
+        };
+        if image.is_empty() {
+            return Err(Error::InvalidFFmpegVia(s.into()));
+        }
+        Ok(Container {
+            engine,
+            image: image.into(),
+            mounts: vec![],
+        })
+    }
+}
+
+/// A container started for a command. Killing the engine client leaves the container running,
+/// it's killed through the engine instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunningContainer {
+    engine: Engine,
+    name: String,
+}
+
+impl RunningContainer {
+    pub fn kill(&self) {
+        debug!("killing the container {}", self.name);
+        let killed = isolated(self.engine.to_string())
+            .arg("kill")
+            .arg(&self.name)
+            .stdout(Stdio::null())
+            .status();
+        match killed {
+            Ok(status) if status.success() => {}
+            // e.g. the command exited and its container is gone already
+            Ok(status) => debug!("killing the container {}: {}", self.name, status),
+            Err(err) => warn!("killing the container {}: {}", self.name, err),
+        }
+    }
+}
+
+/// The directories to mount for the `paths`, leaving out the ones within another.
+pub fn mounts<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Vec<PathBuf> {
+    let mut paths = paths.into_iter().map(Path::to_path_buf).collect::<Vec<_>>();
+    // by components, a directory comes before everything within it
+    paths.sort();
+    let mut mounts: Vec<PathBuf> = vec![];
+    for path in paths {
+        if !mounts.iter().any(|mount| path.starts_with(mount)) {
+            mounts.push(path);
+        }
+    }
+    mounts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_container_from_str() {
+        assert_eq!(
+            Container {
+                engine: Engine::Docker,
+                image: "jrottenberg/ffmpeg:6.1-alpine".into(),
+                mounts: vec![],
+            },
+            "docker:jrottenberg/ffmpeg:6.1-alpine".parse().unwrap()
+        );
+        assert_eq!(
+            Engine::Podman,
+            "podman:docker.io/linuxserver/ffmpeg"
+                .parse::<Container>()
+                .unwrap()
+                .engine
+        );
+        for invalid in ["jrottenberg/ffmpeg", "docker:", "lxc:ffmpeg"] {
+            assert!(
+                matches!(
+                    invalid.parse::<Container>(),
+                    Err(Error::InvalidFFmpegVia(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_mounts() {
+        let paths = [
+            Path::new("/media/card/DCIM/100GOPRO"),
+            Path::new("/media/card/DCIM"),
+            Path::new("/tmp/.gopro-merge-run"),
+            Path::new("/media/card/DCIM/101GOPRO"),
+            Path::new("/media/card-2"),
+            Path::new("/tmp/.gopro-merge-run"),
+        ];
+        // sorted by components, card comes before card-2
+        assert_eq!(
+            vec![
+                PathBuf::from("/media/card/DCIM"),
+                PathBuf::from("/media/card-2"),
+                PathBuf::from("/tmp/.gopro-merge-run"),
+            ],
+            mounts(paths)
+        );
+    }
+
+    #[test]
+    fn test_container_mounting() {
+        let container = "docker:jrottenberg/ffmpeg".parse::<Container>().unwrap();
+        let container = container
+            .mounting(vec![Path::new("/movies/100GOPRO")])
+            .unwrap()
+            .mounting(vec![Path::new("/movies"), Path::new("/merged")])
+            .unwrap();
+        assert_eq!(
+            vec![PathBuf::from("/merged"), PathBuf::from("/movies")],
+            container.mounts
+        );
+
+        assert!(container
+            .clone()
+            .mounting(vec![Path::new("/movies/a:b")])
+            .is_ok());
+        assert!(matches!(
+            container.clone().mounting(vec![Path::new("/media/a:b")]),
+            Err(Error::UnmountablePath(path)) if path == Path::new("/media/a:b")
+        ));
+        assert!(matches!(
+            container.mounting(vec![Path::new("movies")]),
+            Err(Error::UnmountablePath(_))
+        ));
+    }
+
+    #[test]
+    fn test_container_run_args() {
+        let dir = std::env::temp_dir().join("goprotest_container_run_args");
+        std::fs::create_dir_all(&dir).unwrap();
+        let container = Container {
+            engine: Engine::Podman,
+            image: "linuxserver/ffmpeg".into(),
+            mounts: vec![dir.clone(), "/nonexistent/goprotest".into()],
+        };
+
+        let args = container.run_args(Path::new("ffprobe"), "gopro-merge-1-0");
+        let mut volume = dir.as_os_str().to_os_string();
+        volume.push(":");
+        volume.push(&dir);
+        let expected: Vec<OsString> = vec![
+            "run".into(),
+            "--rm".into(),
+            "--name".into(),
+            "gopro-merge-1-0".into(),
+            "--network".into(),
+            "none".into(),
+            "--userns".into(),
+            "keep-id".into(),
+            "--entrypoint".into(),
+            "ffprobe".into(),
+            "--volume".into(),
+            volume,
+            "linuxserver/ffmpeg".into(),
+        ];
+        #[cfg(unix)]
+        assert_eq!(expected, args);
+        #[cfg(not(unix))]
+        assert_eq!(
+            expected
+                .into_iter()
+                .filter(|arg| arg != "--userns" && arg != "keep-id")
+                .collect::<Vec<_>>(),
+            args
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::*;
+use parking_lot::Mutex;
+
+/// Mutexes keyed by the canonical output path, so that no matter how the outputs were planned
+/// two mergers never write the same file at the same time.
+#[derive(Debug, Clone, Default)]
+pub struct OutputLocks {
+    locks: Arc<Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>>,
+}
+
+impl OutputLocks {
+    /// Returns the lock for `path`, the output file itself doesn't need to exist.
+    pub fn get(&self, path: &Path) -> Arc<Mutex<()>> {
+        let key = canonical_output_path(path);
+        debug!("acquiring output lock for {}", key.display());
+        self.locks.lock().entry(key).or_default().clone()
+    }
+}
+
+fn canonical_output_path(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => parent
+            .canonicalize()
+            .map(|parent| parent.join(name))
+            .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_output_locks_same_file() {
+        let locks = OutputLocks::default();
+        let tmp = env::temp_dir();
+        let a = locks.get(&tmp.join("GH000084.mp4"));
+        let b = locks.get(&tmp.join(".").join("GH000084.mp4"));
+        let c = locks.get(&tmp.join("GH000085.mp4"));
+
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &c));
+    }
+
+    #[test]
+    fn test_output_locks_serialize() {
+        let locks = OutputLocks::default();
+        let running = Arc::new(AtomicUsize::new(0));
+        let path = env::temp_dir().join("GH000084.mp4");
+
+        let handles = (0..4)
+            .map(|_| {
+                let (locks, running, path) = (locks.clone(), running.clone(), path.clone());
+                thread::spawn(move || {
+                    let lock = locks.get(&path);
+                    let _guard = lock.lock();
+                    assert_eq!(0, running.fetch_add(1, Ordering::SeqCst));
+                    thread::sleep(Duration::from_millis(5));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .for_each(|handle| handle.join().unwrap());
+    }
+}
@@ -1,17 +1,44 @@
 use std::path::PathBuf;
 
 use crate::group::MovieGroup;
-use crate::merge::Result;
-use crate::progress::Progress;
-
-pub trait Merger: Sized + Send + 'static {
-    type Progress: Progress;
+use crate::merge::{MergeOptions, Result};
+use crate::progress::{GroupStats, Progress};
 
+/// Merges a single group, used as a `Box<dyn Merger>` created by a [NewMerger] so that the
+/// merger can be picked at runtime.
+pub trait Merger: Send + 'static {
     fn new(
-        progress: Self::Progress,
+        progress: Box<dyn Progress>,
         group: MovieGroup,
         movies_path: PathBuf,
         merged_output_path: PathBuf,
-    ) -> Self;
-    fn merge(self) -> Result<()>;
+        options: MergeOptions,
+    ) -> Self
+    where
+        Self: Sized;
+    /// Work done ahead of merging on the pool of `--probe-parallel`, e.g. probing the chapters,
+    /// failures are kept for [Merger::merge] to report.
+    fn prepare(&mut self) {}
+    fn merge(self: Box<Self>) -> Result<GroupStats>;
+}
+
+/// Creates the merger of a group, e.g. `new_merger::<FFmpegMerger>`.
+pub type NewMerger =
+    fn(Box<dyn Progress>, MovieGroup, PathBuf, PathBuf, MergeOptions) -> Box<dyn Merger>;
+
+/// The [NewMerger] of the merger `M`.
+pub fn new_merger<M: Merger>(
+    progress: Box<dyn Progress>,
+    group: MovieGroup,
+    movies_path: PathBuf,
+    merged_output_path: PathBuf,
+    options: MergeOptions,
+) -> Box<dyn Merger> {
+    Box::new(M::new(
+        progress,
+        group,
+        movies_path,
+        merged_output_path,
+        options,
+    ))
 }
@@ -1,20 +1,46 @@
+mod backend;
 mod command;
+mod derive;
 mod ffmpeg;
+mod lock;
 pub mod merger;
+mod mp4box;
+mod native;
+mod options;
+mod output;
+mod space;
+mod verify;
 
-use std::io;
+use std::io::{self, ErrorKind};
 use std::num::ParseIntError;
+use std::path::PathBuf;
 use std::process::ExitStatus;
 
+use derive_more::Display;
+
+use crate::duration::{FormattedDuration, HumanDuration};
+
+pub use backend::*;
+pub use derive::*;
 pub use ffmpeg::*;
+pub use lock::*;
 pub use merger::*;
+pub use mp4box::*;
+pub use native::*;
+pub use options::*;
+pub use output::*;
+pub use space::*;
+pub use verify::*;
 
 type Result<T> = std::result::Result<T, Error>;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("Failed to convert movie {0}, exit status {1}")]
-    FailedToConvert(String, ExitStatus),
+    #[error("Failed to convert movie {0}, exit status {1}{}", see_log(.2))]
+    FailedToConvert(String, ExitStatus, Option<PathBuf>),
+
+    #[error("Failed to probe chapter {}, exit status {1}{}", .0.display(), see_log(.2))]
+    FailedToProbe(PathBuf, ExitStatus, Option<PathBuf>),
 
     #[error("Parsing ffmpeg output line {0}")]
     ParseInt(#[from] ParseIntError),
@@ -22,9 +48,228 @@ pub enum Error {
     #[error(transparent)]
     IO(#[from] io::Error),
 
+    #[error("Invalid ffprobe output: {0}")]
+    FFprobeOutput(#[from] serde_json::Error),
+
     #[error("Cannot get stdout stream for command {0}")]
     NoStdout(String),
 
     #[error("Command not spawned {0}")]
     CommandNotSpawned(String),
+
+    #[error("Merged movie {0} is {1} long but its chapters add up to {2}, the merge is likely truncated and was removed, pass --allow-duration-mismatch to keep it anyways")]
+    DurationMismatch(String, FormattedDuration, FormattedDuration),
+
+    #[error(
+        "Merged movie {0} isn't bit exact, its video packets hash to {1} but its chapters to {2}"
+    )]
+    NotBitExact(String, String, String),
+
+    #[error("ffmpeg wrote no hash for {0} while verifying the merge")]
+    NoHash(String),
+
+    #[error("Invalid --verify {0}, supported is \"bitexact\"")]
+    InvalidVerify(String),
+
+    #[error("{} is gone after the system resumed from sleep, was it unmounted?", .0.display())]
+    GoneAfterResume(PathBuf),
+
+    #[error("ffmpeg made no progress merging {0} for {1} and was killed, is one of its chapters corrupt? Pass a longer --stall-timeout for slow storage{}", see_log(.2))]
+    Stalled(String, HumanDuration, Option<PathBuf>),
+
+    #[error("{} was not found, install ffmpeg from https://ffmpeg.org/download.html (e.g. `brew install ffmpeg`, `apt install ffmpeg` or `winget install ffmpeg`) or pass its location with --ffmpeg-path and --ffprobe-path", .0.display())]
+    BinaryNotFound(PathBuf),
+
+    #[error("{} is version {}, at least {}.{} is required, install a newer ffmpeg from https://ffmpeg.org/download.html", .0.display(), .1, MINIMUM_FFMPEG_VERSION.0, MINIMUM_FFMPEG_VERSION.1)]
+    BinaryTooOld(PathBuf, String),
+
+    #[error("{} was not found, install GPAC from https://gpac.io/downloads/ (e.g. `brew install gpac` or `apt install gpac`) or pass its location with --mp4box-path", .0.display())]
+    MP4BoxNotFound(PathBuf),
+
+    #[error("Invalid --backend {0}, supported are \"ffmpeg\", \"mp4box\", \"native\"")]
+    InvalidBackend(String),
+
+    #[error("Invalid --ffmpeg-via {0}, supported are \"docker:<image>\", \"podman:<image>\"")]
+    InvalidFFmpegVia(String),
+
+    #[error("{} can't be mounted into the container of --ffmpeg-via at the same path, only absolute paths without colons can, e.g. no Windows paths", .0.display())]
+    UnmountablePath(PathBuf),
+
+    #[error(
+        "{0} was not found, install it or pass the container engine there is with --ffmpeg-via"
+    )]
+    ContainerEngineNotFound(Engine),
+
+    #[error("Merged movie {0} has {2} GPMF telemetry streams but its chapters have {1}, the GPS and sensor data was lost, pass --drop-data-streams to merge without it")]
+    TelemetryLost(String, usize, usize),
+
+    #[error("Chapter {} isn't readable, is its filesystem mounted and are its credentials valid? {1}", .0.display())]
+    ChapterUnreadable(PathBuf, io::Error),
+
+    #[error("Chapter {} appears truncated, {1}, as when the battery dies while recording. Leave it out by listing the other chapters with --manifest, or repair it first with a tool like untrunc", .0.display())]
+    TruncatedChapter(PathBuf, crate::mp4::Truncation),
+
+    #[error("Merged movie {} already exists, pass --on-exists to skip, overwrite or rename it", .0.display())]
+    OutputExists(PathBuf),
+
+    #[error("{} is read-only, e.g. a locked memory card, pass a writable directory to merge into", .0.display())]
+    ReadOnlyOutput(PathBuf),
+
+    #[error("Merged movie {} can't be written, {1}. Merge into a shallower directory or shorten --name-template", .0.display())]
+    OutputPathTooLong(PathBuf, String),
+
+    #[error(
+        "Invalid --on-exists {0}, supported are \"skip\", \"overwrite\", \"rename\", \"fail\""
+    )]
+    InvalidOnExists(String),
+
+    #[error("Invalid --container {0}, supported are \"mp4\", \"mkv\", \"mov\"")]
+    InvalidContainer(String),
+
+    #[error("{} has {1} free, not enough to merge the movies and keep the {2} of --min-free-space free: {3}. Free up space, merge into another directory or fewer movies with --only", .0.display())]
+    InsufficientSpace(PathBuf, ByteSize, ByteSize, String),
+
+    #[error("The workspace {} has {1} free, not enough for the {2} the movies merged at once stage in it: {3}. Point TMPDIR, or TMP on Windows, to a larger volume or merge fewer movies at once with --merge-parallel", .0.display())]
+    InsufficientWorkspaceSpace(PathBuf, ByteSize, ByteSize, String),
+
+    #[error("Invalid --extract-audio {0}, supported are \"aac\", \"wav\"")]
+    InvalidAudioFormat(String),
+
+    #[error("Invalid size {0}, expected bytes or e.g. 500M, 10GiB")]
+    InvalidByteSize(String),
+
+    #[error(transparent)]
+    Resume(#[from] crate::resume::Error),
+
+    #[error("Chapter {0:?} can't be passed to ffmpeg's concat demuxer or protocol, rename it without newlines, | or other control characters")]
+    UnsupportedChapterPath(PathBuf),
+
+    #[error("Chapter {} can't be joined without ffmpeg, {1}", .0.display())]
+    NativeUnsupported(PathBuf, String),
+
+    #[error("Chapter {} can't be joined with --no-temp-concat, ffmpeg's concat protocol only joins MPEG-TS chapters", .0.display())]
+    ConcatProtocolUnsupported(PathBuf),
+}
+
+/// Points to the ffmpeg or MP4Box `log` of a failed group, if it was logged.
+fn see_log(log: &Option<PathBuf>) -> String {
+    log.as_ref()
+        .map(|log| format!(", see the log {}", log.display()))
+        .unwrap_or_default()
+}
+
+/// What kind of failure failed a group, for tools wrapping the json reporter to branch on and
+/// translate instead of parsing the messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum ErrorCode {
+    /// ffprobe failed on a chapter or wrote output that couldn't be read.
+    #[display(fmt = "probe_failed")]
+    ProbeFailed,
+    /// ffmpeg failed merging, verifying or deriving from the movie.
+    #[display(fmt = "convert_failed")]
+    ConvertFailed,
+    /// ffmpeg made no progress for --stall-timeout.
+    #[display(fmt = "stalled")]
+    Stalled,
+    /// A chapter is gone, or a directory of the movie after the system resumed from sleep.
+    #[display(fmt = "missing_chapter")]
+    MissingChapter,
+    #[display(fmt = "unreadable_chapter")]
+    UnreadableChapter,
+    #[display(fmt = "truncated_chapter")]
+    TruncatedChapter,
+    /// A chapter ffmpeg can't be passed or join the way it was asked to.
+    #[display(fmt = "unsupported_chapter")]
+    UnsupportedChapter,
+    #[display(fmt = "output_exists")]
+    OutputExists,
+    #[display(fmt = "read_only_output")]
+    ReadOnlyOutput,
+    #[display(fmt = "insufficient_space")]
+    InsufficientSpace,
+    /// ffmpeg or ffprobe is missing or too old, or the container engine of --ffmpeg-via.
+    #[display(fmt = "ffmpeg_unavailable")]
+    FFmpegUnavailable,
+    /// MP4Box is missing with --backend mp4box.
+    #[display(fmt = "mp4box_unavailable")]
+    MP4BoxUnavailable,
+    /// The merged movie failed a check, e.g. its duration, --verify or its telemetry.
+    #[display(fmt = "verification_failed")]
+    VerificationFailed,
+    #[display(fmt = "invalid_option")]
+    InvalidOption,
+    #[display(fmt = "io")]
+    Io,
+    /// Anything else, e.g. ffmpeg output that couldn't be parsed.
+    #[display(fmt = "internal")]
+    Internal,
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::FailedToProbe(..) | Error::FFprobeOutput(..) => ErrorCode::ProbeFailed,
+            Error::FailedToConvert(..) | Error::NoHash(..) => ErrorCode::ConvertFailed,
+            Error::Stalled(..) => ErrorCode::Stalled,
+            Error::GoneAfterResume(..) => ErrorCode::MissingChapter,
+            Error::ChapterUnreadable(_, err) if err.kind() == ErrorKind::NotFound => {
+                ErrorCode::MissingChapter
+            }
+            Error::ChapterUnreadable(..) => ErrorCode::UnreadableChapter,
+            Error::TruncatedChapter(..) => ErrorCode::TruncatedChapter,
+            Error::UnsupportedChapterPath(..)
+            | Error::ConcatProtocolUnsupported(..)
+            | Error::NativeUnsupported(..) => ErrorCode::UnsupportedChapter,
+            Error::OutputExists(..) => ErrorCode::OutputExists,
+            Error::ReadOnlyOutput(..) => ErrorCode::ReadOnlyOutput,
+            Error::InsufficientSpace(..) | Error::InsufficientWorkspaceSpace(..) => {
+                ErrorCode::InsufficientSpace
+            }
+            Error::BinaryNotFound(..)
+            | Error::BinaryTooOld(..)
+            | Error::ContainerEngineNotFound(..) => ErrorCode::FFmpegUnavailable,
+            Error::MP4BoxNotFound(..) => ErrorCode::MP4BoxUnavailable,
+            Error::DurationMismatch(..) | Error::NotBitExact(..) | Error::TelemetryLost(..) => {
+                ErrorCode::VerificationFailed
+            }
+            Error::InvalidVerify(..)
+            | Error::InvalidOnExists(..)
+            | Error::InvalidContainer(..)
+            | Error::InvalidByteSize(..)
+            | Error::InvalidAudioFormat(..)
+            | Error::InvalidBackend(..)
+            | Error::InvalidFFmpegVia(..)
+            | Error::UnmountablePath(..)
+            | Error::OutputPathTooLong(..) => ErrorCode::InvalidOption,
+            Error::IO(..) | Error::Resume(..) => ErrorCode::Io,
+            Error::ParseInt(..) | Error::NoStdout(..) | Error::CommandNotSpawned(..) => {
+                ErrorCode::Internal
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_code() {
+        let missing = io::Error::from(ErrorKind::NotFound);
+        assert_eq!(
+            ErrorCode::MissingChapter,
+            Error::ChapterUnreadable("/movies/GH010084.MP4".into(), missing).code()
+        );
+        let denied = io::Error::from(ErrorKind::PermissionDenied);
+        assert_eq!(
+            ErrorCode::UnreadableChapter,
+            Error::ChapterUnreadable("/movies/GH010084.MP4".into(), denied).code()
+        );
+        assert_eq!(
+            "verification_failed",
+            Error::NotBitExact("GH000084.MP4".into(), "a".into(), "b".into())
+                .code()
+                .to_string()
+        );
+    }
 }
@@ -0,0 +1,515 @@
+use std::ffi::OsString;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command as Process, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use log::*;
+
+use crate::confirm::DestructiveAction;
+use crate::duration::{self, HumanDuration};
+use crate::export;
+use crate::group::MovieGroup;
+use crate::merge::command::{Command, ProcessReport};
+use crate::merge::ffmpeg::{
+    check_duration, check_telemetry, extract_gpx, ffmpeg_path, merge_recorded_proxies,
+    merged_movie, preflight_chapters, probe_chapters, probe_output, record_sources,
+    remove_if_exists, report_merge, start_merge, stderr_log_path, warn_very_long, Binaries,
+    RunningContainer, Start,
+};
+use crate::merge::{Error, MergeOptions, Merger, ResolvedOutput, Result};
+use crate::progress::{GroupStats, Progress, Throughput, WarningKind};
+use crate::proxy;
+use crate::system::{Clock as _, RealFs, SystemClock};
+use crate::timestamp;
+
+pub(crate) const MP4BOX_PROCESS_NAME: &str = "MP4Box";
+
+/// How often a running MP4Box is checked for having exited and the run for stopping, MP4Box
+/// reports no progress which could be checked on instead.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Checks upfront that MP4Box exists, instead of every group failing to spawn it.
+pub fn mp4box_preflight(binaries: &Binaries) -> Result<()> {
+    binaries.runner.preflight()?;
+    let (mut process, _) = binaries.runner.command(&binaries.mp4box);
+    match process.arg("-version").stdout(Stdio::null()).status() {
+        Ok(_) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            Err(Error::MP4BoxNotFound(binaries.mp4box.clone()))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// `MP4Box -add <first> -cat <second> ... -new <output>`, importing every track of the first
+/// chapter, GPMF telemetry included, and appending the samples of the others to them.
+#[derive(Debug, Clone)]
+pub struct MP4BoxCat {
+    pub inputs: Vec<PathBuf>,
+    pub output: PathBuf,
+    /// Where MP4Box keeps its temporary files, the workspace of the run.
+    pub tmp: PathBuf,
+}
+
+impl MP4BoxCat {
+    pub fn args(&self) -> Vec<OsString> {
+        let mut args = vec![];
+        for (i, input) in self.inputs.iter().enumerate() {
+            args.push(if i == 0 { "-add" } else { "-cat" }.into());
+            args.push(ffmpeg_path(input).as_os_str().to_os_string());
+        }
+        args.push("-tmp".into());
+        args.push(ffmpeg_path(&self.tmp).as_os_str().to_os_string());
+        // writing a new file rather than adding the chapters to an existing output
+        args.push("-new".into());
+        args.push(ffmpeg_path(&self.output).as_os_str().to_os_string());
+        args
+    }
+}
+
+pub struct MP4BoxCommand {
+    cat: MP4BoxCat,
+    process: Process,
+    child: Option<Child>,
+    /// Where the stderr of the command goes, to null unless logged.
+    log: Option<PathBuf>,
+    /// The container the command runs in with `--ffmpeg-via`.
+    container: Option<RunningContainer>,
+    report: Option<ProcessReport>,
+}
+
+impl MP4BoxCommand {
+    pub fn new(cat: MP4BoxCat, binaries: &Binaries) -> Self {
+        let args = cat.args();
+        debug!(
+            "Creating {} command with args {:?}",
+            binaries.mp4box.display(),
+            &args[..]
+        );
+
+        let (mut process, container) = binaries.runner.command(&binaries.mp4box);
+        process.args(&args);
+
+        MP4BoxCommand {
+            cat,
+            process,
+            child: None,
+            log: None,
+            container,
+            report: None,
+        }
+    }
+
+    /// Appends the stderr of the command to the `log` of its group, after its command line.
+    pub fn log_to(mut self, log: &Path) -> Result<Self> {
+        info!("logging the MP4Box stderr to {}", log.display());
+        let mut file = OpenOptions::new().create(true).append(true).open(log)?;
+        writeln!(file, "{:?}", self.process)?;
+
+        self.process.stderr(file);
+        self.log = Some(log.to_path_buf());
+        Ok(self)
+    }
+
+    /// Reports the child process spawning and exiting to the `progress` of its group.
+    pub fn report_to(mut self, progress: &dyn Progress) -> Self {
+        let report = ProcessReport::new(progress, MP4BOX_PROCESS_NAME, &self.cat.args());
+        self.report = Some(report);
+        self
+    }
+
+    /// Whether the spawned command exited, without waiting for it.
+    pub fn exited(&mut self) -> Result<bool> {
+        let status = self
+            .child
+            .as_mut()
+            .ok_or_else(|| Error::CommandNotSpawned(MP4BOX_PROCESS_NAME.into()))?
+            .try_wait()?;
+        if let (Some(status), Some(report)) = (status, &mut self.report) {
+            report.exited(status);
+        }
+        Ok(status.is_some())
+    }
+}
+
+impl Command for MP4BoxCommand {
+    fn spawn(mut self) -> Result<Self> {
+        let child = self.process.spawn()?;
+        if let Some(report) = &mut self.report {
+            report.spawned(&child);
+        }
+        self.child = Some(child);
+        Ok(self)
+    }
+
+    fn stdout(&mut self) -> Result<ChildStdout> {
+        self.child
+            .as_mut()
+            .ok_or_else(|| Error::CommandNotSpawned(MP4BOX_PROCESS_NAME.into()))?
+            .stdout
+            .take()
+            .ok_or_else(|| Error::NoStdout(MP4BOX_PROCESS_NAME.into()))
+    }
+
+    fn kill(&mut self) -> Result<()> {
+        let child = self
+            .child
+            .as_mut()
+            .ok_or_else(|| Error::CommandNotSpawned(MP4BOX_PROCESS_NAME.into()))?;
+        if let Some(container) = &self.container {
+            container.kill();
+        }
+        child.kill().map_err(From::from)
+    }
+
+    fn wait_success(mut self) -> Result<()> {
+        let exit_status = self
+            .child
+            .ok_or_else(|| Error::CommandNotSpawned(MP4BOX_PROCESS_NAME.into()))?
+            .wait()?;
+        if let Some(report) = &mut self.report {
+            report.exited(exit_status);
+        }
+
+        if exit_status.success() {
+            return Ok(());
+        }
+        let movie = self
+            .cat
+            .inputs
+            .first()
+            .map(|input| format!("{} {}", MP4BOX_PROCESS_NAME, input.display()))
+            .unwrap_or_else(|| MP4BOX_PROCESS_NAME.into());
+        Err(Error::FailedToConvert(movie, exit_status, self.log))
+    }
+}
+
+/// Merges with MP4Box `-cat` instead of ffmpeg's concat demuxer, writing no list of the chapters
+/// to the workspace. ffprobe still probes the chapters and the merged movie when available, for
+/// the progress, the exports and checking the duration and telemetry of the merge.
+pub struct MP4BoxMerger {
+    progress: Box<dyn Progress>,
+    group: MovieGroup,
+    movies_path: PathBuf,
+    merged_output_path: PathBuf,
+    options: MergeOptions,
+}
+
+impl Merger for MP4BoxMerger {
+    fn new(
+        progress: Box<dyn Progress>,
+        group: MovieGroup,
+        movies_path: PathBuf,
+        merged_output_path: PathBuf,
+        options: MergeOptions,
+    ) -> Self {
+        MP4BoxMerger {
+            progress,
+            group,
+            movies_path,
+            merged_output_path,
+            options,
+        }
+    }
+
+    fn merge(self: Box<Self>) -> Result<GroupStats> {
+        let (progress, group) = (self.progress.clone(), self.group.clone());
+        let (merged_output_path, options) = (self.merged_output_path.clone(), self.options.clone());
+        report_merge(progress, &group, &merged_output_path, &options, || {
+            self.merge_inner()
+        })
+    }
+}
+
+impl MP4BoxMerger {
+    fn merge_inner(self) -> Result<GroupStats> {
+        let started = SystemClock.instant();
+        let Self {
+            mut progress,
+            mut group,
+            movies_path,
+            merged_output_path,
+            options,
+        } = self;
+        // before leaving out proxies, the id is of the chapters found
+        let group_id = group.id();
+        let output = match start_merge(
+            &mut progress,
+            &group,
+            &merged_output_path,
+            &options,
+            started,
+        )? {
+            Start::Merge(output) => output,
+            Start::Done(stats) => return Ok(*stats),
+        };
+
+        let mut movies_full_paths = group
+            .chapters
+            .iter()
+            .map(|chapter| group.chapter_path(chapter, &movies_path))
+            .collect::<Vec<_>>();
+        let preflight_started = SystemClock.instant();
+        preflight_chapters(&group, &movies_full_paths, options.retry)?;
+        let preflight = SystemClock.instant() - preflight_started;
+        debug!("preflight of {} took {:?}", group, preflight);
+
+        let mut probed = if options.probe {
+            let report = progress.clone();
+            Some(probe_chapters(
+                &movies_full_paths,
+                &options.binaries,
+                options.probe_cache.as_ref(),
+                options.timezone,
+                Some(&report),
+                |probed| progress.probing(probed, movies_full_paths.len()),
+            )?)
+        } else {
+            None
+        };
+        let excluded = match &mut probed {
+            Some(probed) => proxy::exclude(
+                options.proxies,
+                &mut group,
+                &mut movies_full_paths,
+                probed,
+                |warning| progress.warning(WarningKind::ProxySuspect, &warning),
+            ),
+            None => vec![],
+        };
+        let sources = record_sources(&mut progress, &movies_full_paths, &options)?;
+        // MP4Box takes the tracks of the first chapter
+        let telemetry = probed
+            .iter()
+            .flatten()
+            .next()
+            .map(|chapter| chapter.telemetry.clone())
+            .unwrap_or_default();
+        let duration =
+            duration::saturating_sum(probed.iter().flatten().map(|chapter| chapter.duration));
+        warn_very_long(&mut progress, &group, movies_full_paths.len(), duration);
+
+        let movie = match &probed {
+            _ if options.exports.is_empty() => None,
+            Some(probed) => Some(merged_movie(
+                &group,
+                &movies_full_paths,
+                output.path(),
+                probed,
+            )),
+            None => {
+                warn!(
+                    "skipping the exports of {}, chapter boundaries are unknown without probing",
+                    group.name()
+                );
+                None
+            }
+        };
+
+        debug!("joining {} with MP4Box", group);
+        progress.set_len(duration);
+        options.jobs.set_len(&group.name(), duration);
+        // overwriting was confirmed before the run started
+        let actions = match &output {
+            ResolvedOutput::Overwrite(path) => vec![DestructiveAction::Overwrite(path.clone())],
+            _ => vec![],
+        };
+        if let Some(resume) = &options.resume {
+            resume.start(&group.name(), &group_id, output.path())?;
+        }
+        let cat = MP4BoxCat {
+            inputs: movies_full_paths.clone(),
+            output: output.path().to_path_buf(),
+            tmp: options.workspace.clone(),
+        };
+        if !join(&progress, cat, &group, &options)? {
+            let interrupted = options.stopping(&group);
+            warn!(
+                "stopped merging {} {}, removing {}",
+                group,
+                if interrupted {
+                    "as the run was interrupted or it was cancelled"
+                } else {
+                    "at the run deadline"
+                },
+                output.path().display()
+            );
+            remove_if_exists(output.path())?;
+            let (output, elapsed) = (output.path().to_path_buf(), SystemClock.instant() - started);
+            return Ok(if interrupted {
+                GroupStats::interrupted(output, elapsed)
+            } else {
+                GroupStats::deferred(output, elapsed)
+            });
+        }
+        progress.update(duration, Throughput::default());
+        options.jobs.update(&group.name(), duration);
+
+        if probed.is_some() {
+            debug!("verifying the duration of {}", output.path().display());
+            let merged = probe_output(output.path(), &options.binaries, Some(&progress))?;
+            if let Err(err) = check_duration(&group, duration, merged.duration, &options) {
+                // a truncated movie would be skipped as already merged by the next run
+                remove_if_exists(output.path())?;
+                return Err(err);
+            }
+            check_telemetry(&group, &telemetry, &merged.telemetry, &options)?;
+        }
+        let recorded = match probed.iter().flatten().next() {
+            Some(chapter) => chapter.timestamp.time,
+            None => movies_full_paths.first().and_then(|path| {
+                let mut timestamps = [timestamp::resolve(None, path, &RealFs, &SystemClock)];
+                timestamp::align(&mut timestamps, options.timezone, &SystemClock);
+                timestamps[0].time
+            }),
+        };
+        timestamp::preserve(output.path(), recorded, &RealFs);
+
+        if let Some(movie) = movie {
+            options
+                .exports
+                .iter()
+                .try_for_each(|format| export::export(*format, &movie).map(|_| ()))?;
+        }
+        let proxy = if options.merge_proxies {
+            merge_recorded_proxies(
+                &progress,
+                &group,
+                &movies_full_paths,
+                output.path(),
+                recorded,
+                &options,
+            )?
+        } else {
+            None
+        };
+        let gpx = if options.extract_gpx {
+            extract_gpx(
+                &progress,
+                &group,
+                &movies_full_paths,
+                output.path(),
+                recorded,
+            )?
+        } else {
+            None
+        };
+        if let Some(resume) = &options.resume {
+            resume.complete(&group.name(), &group_id, output.path())?;
+        }
+
+        Ok(GroupStats {
+            output: output.path().to_path_buf(),
+            duration,
+            elapsed: SystemClock.instant() - started,
+            preflight,
+            sources,
+            actions,
+            skipped: false,
+            deferred: false,
+            interrupted: false,
+            verification: None,
+            telemetry_track: None,
+            proxy,
+            gpx,
+            audio: None,
+            excluded,
+            variable_frame_rate: vec![],
+            replanned: vec![],
+            started: None,
+            ended: None,
+        })
+    }
+}
+
+/// Joins the chapters with MP4Box, `false` when it was killed at a hard stopping deadline or as
+/// the run was interrupted.
+fn join(
+    progress: &dyn Progress,
+    cat: MP4BoxCat,
+    group: &MovieGroup,
+    options: &MergeOptions,
+) -> Result<bool> {
+    let output_lock = options.output_locks.get(&cat.output);
+    let _output_guard = output_lock.lock();
+
+    let log = stderr_log_path(options, group);
+    let mut cmd = MP4BoxCommand::new(cat, &options.binaries)
+        .log_to(&log)?
+        .report_to(progress)
+        .spawn()?;
+    let started = SystemClock.instant();
+    while !cmd.exited()? {
+        let stop = options.stopping(group)
+            || options
+                .deadline
+                .map_or(false, |deadline| deadline.stops(SystemClock.instant()));
+        if stop {
+            cmd.kill()?;
+            // the exit status of a killed MP4Box is a failure
+            let _ = cmd.wait_success();
+            return Ok(false);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    debug!(
+        "MP4Box joined {} in {}",
+        group,
+        HumanDuration(SystemClock.instant() - started)
+    );
+
+    match cmd.wait_success() {
+        // MP4Box got the interrupt itself and quit
+        Err(_) if options.interrupt.is_triggered() => Ok(false),
+        result => result.map(|_| true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mp4box_cat_args() {
+        let cat = MP4BoxCat {
+            inputs: vec![
+                "/movies/GH010084.MP4".into(),
+                "/movies/GH020084.MP4".into(),
+                "/movies/GH030084.MP4".into(),
+            ],
+            output: "/merged/GH000084.MP4".into(),
+            tmp: "/tmp/gopro-merge".into(),
+        };
+        let args = [
+            "-add",
+            "/movies/GH010084.MP4",
+            "-cat",
+            "/movies/GH020084.MP4",
+            "-cat",
+            "/movies/GH030084.MP4",
+            "-tmp",
+            "/tmp/gopro-merge",
+            "-new",
+            "/merged/GH000084.MP4",
+        ];
+        assert_eq!(
+            args.iter().map(OsString::from).collect::<Vec<_>>(),
+            cat.args()
+        );
+    }
+
+    #[test]
+    fn test_mp4box_preflight_not_found() {
+        let binaries = Binaries {
+            mp4box: "/nonexistent/MP4Box".into(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            mp4box_preflight(&binaries),
+            Err(Error::MP4BoxNotFound(path)) if path == binaries.mp4box
+        ));
+    }
+}
@@ -0,0 +1,892 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::merge::{Error, Result};
+use crate::mp4::{self, BoxType};
+
+/// Boxes holding nothing but other boxes, among the ones rewritten when joining.
+const CONTAINERS: [&BoxType; 7] = [
+    b"moov", b"trak", b"mdia", b"minf", b"stbl", b"edts", b"dinf",
+];
+
+/// Sample table boxes rebuilt from the tables of all the chapters.
+const REBUILT: [&BoxType; 8] = [
+    b"stts", b"ctts", b"stss", b"stsz", b"stsc", b"stco", b"co64", b"cslg",
+];
+
+/// Sample table boxes describing the samples of a single chapter which are left out rather than
+/// rebuilt, players do without them.
+const LEFT_OUT: [&BoxType; 7] = [
+    b"sdtp", b"sbgp", b"sgpd", b"subs", b"stps", b"padb", b"stdp",
+];
+
+/// How many bytes of sample data are copied at once, and how often stopping is checked.
+const COPY_CHUNK: usize = 8 << 20;
+
+/// Why chapters can't be joined natively, attached to the chapter by [Error::NativeUnsupported].
+type Check<T> = std::result::Result<T, String>;
+
+/// A box read into memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Atom {
+    kind: BoxType,
+    content: Content,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Content {
+    Data(Vec<u8>),
+    Children(Vec<Atom>),
+}
+
+impl Atom {
+    fn data(kind: &BoxType, data: Vec<u8>) -> Self {
+        Atom {
+            kind: *kind,
+            content: Content::Data(data),
+        }
+    }
+
+    /// Reads the boxes of the `payload` of a box, the children of the [CONTAINERS] too.
+    fn parse_all(payload: &[u8]) -> io::Result<Vec<Atom>> {
+        let mut cursor = Cursor::new(payload);
+        mp4::children(&mut cursor, 0, payload.len() as u64)?
+            .into_iter()
+            .map(|header| {
+                let data = &payload[header.payload_offset() as usize..header.end() as usize];
+                Ok(Atom {
+                    kind: header.kind,
+                    content: if CONTAINERS.contains(&&header.kind) {
+                        Content::Children(Atom::parse_all(data)?)
+                    } else {
+                        Content::Data(data.to_vec())
+                    },
+                })
+            })
+            .collect()
+    }
+
+    fn children(&self) -> &[Atom] {
+        match &self.content {
+            Content::Children(children) => children,
+            Content::Data(_) => &[],
+        }
+    }
+
+    fn payload(&self) -> &[u8] {
+        match &self.content {
+            Content::Data(data) => data,
+            Content::Children(_) => &[],
+        }
+    }
+
+    /// The box at `path` below this one, e.g. `[b"mdia", b"mdhd"]` of a `trak`.
+    fn find(&self, path: &[&BoxType]) -> Option<&Atom> {
+        path.iter().try_fold(self, |atom, kind| {
+            atom.children().iter().find(|child| &child.kind == *kind)
+        })
+    }
+
+    fn find_mut(&mut self, path: &[&BoxType]) -> Option<&mut Atom> {
+        let mut atom = self;
+        for kind in path {
+            atom = match &mut atom.content {
+                Content::Children(children) => {
+                    children.iter_mut().find(|child| &child.kind == *kind)?
+                }
+                Content::Data(_) => return None,
+            };
+        }
+        Some(atom)
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut payload = vec![];
+        match &self.content {
+            Content::Data(data) => payload.extend_from_slice(data),
+            Content::Children(children) => {
+                children.iter().for_each(|child| child.write(&mut payload))
+            }
+        }
+        out.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+        out.extend_from_slice(&self.kind);
+        out.extend_from_slice(&payload);
+    }
+}
+
+/// Reads the big endian fields of a box payload one after the other.
+struct Fields<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Fields<'a> {
+    /// The fields after the version and flags of a full box.
+    fn full_box(data: &'a [u8]) -> Self {
+        Fields { data, at: 4 }
+    }
+
+    fn take(&mut self, len: usize) -> Check<&'a [u8]> {
+        let field = self
+            .data
+            .get(self.at..self.at + len)
+            .ok_or_else(|| "a box of it is cut short".to_string())?;
+        self.at += len;
+        Ok(field)
+    }
+
+    fn u32(&mut self) -> Check<u32> {
+        let field = self.take(4)?;
+        Ok(u32::from_be_bytes([field[0], field[1], field[2], field[3]]))
+    }
+
+    fn u64(&mut self) -> Check<u64> {
+        let mut field = [0; 8];
+        field.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(field))
+    }
+
+    /// The entries of a table, preceded by their count.
+    fn entries<T>(&mut self, mut entry: impl FnMut(&mut Self) -> Check<T>) -> Check<Vec<T>> {
+        let count = self.u32()?;
+        (0..count).map(|_| entry(self)).collect()
+    }
+}
+
+/// A full box of version 0 without flags.
+fn full_box(kind: &BoxType, fields: impl IntoIterator<Item = u32>) -> Atom {
+    full_box_versioned(kind, 0, fields)
+}
+
+fn full_box_versioned(kind: &BoxType, version: u8, fields: impl IntoIterator<Item = u32>) -> Atom {
+    let mut data = vec![version, 0, 0, 0];
+    fields
+        .into_iter()
+        .for_each(|field| data.extend_from_slice(&field.to_be_bytes()));
+    Atom::data(kind, data)
+}
+
+/// The sample table of a track, see ISO/IEC 14496-12 8.6 and 8.7.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct SampleTable {
+    /// `stts`, sample counts and their durations.
+    times: Vec<(u32, u32)>,
+    /// `ctts`, its version and sample counts with their composition offsets.
+    composition: Option<(u8, Vec<(u32, u32)>)>,
+    /// `stss`, the numbers of the keyframes counting from one, all samples are when missing.
+    sync: Option<Vec<u32>>,
+    /// `stsz`.
+    sizes: Vec<u32>,
+    /// `stsc`, the first chunk of runs of chunks with the same samples per chunk and description.
+    chunks: Vec<(u32, u32, u32)>,
+    /// `stco` or `co64`.
+    offsets: Vec<u64>,
+}
+
+impl SampleTable {
+    /// Parses the tables of the `stbl` box of a track whose samples are in `data_len` bytes of
+    /// sample data.
+    fn parse(stbl: &Atom, data_len: u64) -> Check<Self> {
+        let table = |kind: &BoxType| {
+            stbl.find(&[kind])
+                .map(|atom| Fields::full_box(atom.payload()))
+        };
+        if stbl.find(&[b"stz2"]).is_some() {
+            return Err("it has compact sample sizes".into());
+        }
+
+        let times = match table(b"stts") {
+            Some(mut fields) => fields.entries(|fields| Ok((fields.u32()?, fields.u32()?)))?,
+            None => return Err("a track of it has no sample durations".into()),
+        };
+        let composition = match stbl.find(&[b"ctts"]) {
+            Some(ctts) => {
+                let version = ctts.payload().first().copied().unwrap_or_default();
+                let entries = Fields::full_box(ctts.payload())
+                    .entries(|fields| Ok((fields.u32()?, fields.u32()?)))?;
+                Some((version, entries))
+            }
+            None => None,
+        };
+        let sync = table(b"stss")
+            .map(|mut fields| fields.entries(Fields::u32))
+            .transpose()?;
+        let sizes = match table(b"stsz") {
+            Some(mut fields) => match fields.u32()? {
+                0 => fields.entries(Fields::u32)?,
+                size => match fields.u32()? {
+                    // not backed by a box of that size, only by the sample data they'd take
+                    count if u64::from(size) * u64::from(count) > data_len => {
+                        return Err("a track of it has more samples than its mdat box".into())
+                    }
+                    count => vec![size; count as usize],
+                },
+            },
+            None => return Err("a track of it has no sample sizes".into()),
+        };
+        let chunks = match table(b"stsc") {
+            Some(mut fields) => {
+                fields.entries(|fields| Ok((fields.u32()?, fields.u32()?, fields.u32()?)))?
+            }
+            None => return Err("a track of it has no chunks".into()),
+        };
+        let offsets = match (table(b"stco"), table(b"co64")) {
+            (Some(mut fields), _) => fields.entries(|fields| fields.u32().map(u64::from))?,
+            (None, Some(mut fields)) => fields.entries(Fields::u64)?,
+            (None, None) => return Err("a track of it has no chunk offsets".into()),
+        };
+
+        let samples = times.iter().map(|(count, _)| *count as u64).sum::<u64>();
+        if samples != sizes.len() as u64 {
+            return Err("a track of it has more or fewer sample durations than sizes".into());
+        }
+        Ok(SampleTable {
+            times,
+            composition,
+            sync,
+            sizes,
+            chunks,
+            offsets,
+        })
+    }
+
+    /// Appends the samples of the `other` table, its chunks `shift`ed by the sample data before.
+    fn append(&mut self, other: &SampleTable, shift: u64) -> Check<()> {
+        let (samples, chunks) = (self.sizes.len() as u32, self.offsets.len() as u32);
+        self.composition = match (self.composition.take(), &other.composition) {
+            (None, None) => None,
+            (Some((version, mut offsets)), Some((other_version, other_offsets)))
+                if version == *other_version =>
+            {
+                offsets.extend_from_slice(other_offsets);
+                Some((version, offsets))
+            }
+            _ => {
+                return Err("its frames are reordered unlike the ones of the first chapter".into())
+            }
+        };
+        self.sync = match (self.sync.take(), &other.sync) {
+            (None, None) => None,
+            (sync, other_sync) => {
+                let mut sync = sync.unwrap_or_else(|| (1..=samples).collect());
+                match other_sync {
+                    Some(other_sync) => sync.extend(other_sync.iter().map(|n| n + samples)),
+                    None => sync.extend(samples + 1..=samples + other.sizes.len() as u32),
+                }
+                Some(sync)
+            }
+        };
+        self.times.extend_from_slice(&other.times);
+        self.sizes.extend_from_slice(&other.sizes);
+        self.chunks.extend(
+            other
+                .chunks
+                .iter()
+                .map(|(first, samples, description)| (first + chunks, *samples, *description)),
+        );
+        self.offsets
+            .extend(other.offsets.iter().map(|offset| offset + shift));
+        Ok(())
+    }
+
+    /// The sample table boxes, the chunk offsets `shift`ed to where the sample data is written.
+    fn atoms(&self, shift: u64) -> Vec<Atom> {
+        let mut atoms = vec![full_box(
+            b"stts",
+            std::iter::once(self.times.len() as u32).chain(
+                self.times
+                    .iter()
+                    .flat_map(|(count, delta)| [*count, *delta]),
+            ),
+        )];
+        if let Some((version, offsets)) = &self.composition {
+            atoms.push(full_box_versioned(
+                b"ctts",
+                *version,
+                std::iter::once(offsets.len() as u32)
+                    .chain(offsets.iter().flat_map(|(count, offset)| [*count, *offset])),
+            ));
+        }
+        if let Some(sync) = &self.sync {
+            atoms.push(full_box(
+                b"stss",
+                std::iter::once(sync.len() as u32).chain(sync.iter().copied()),
+            ));
+        }
+        atoms.push(full_box(
+            b"stsc",
+            std::iter::once(self.chunks.len() as u32).chain(
+                self.chunks
+                    .iter()
+                    .flat_map(|(first, samples, description)| [*first, *samples, *description]),
+            ),
+        ));
+        let count = self.sizes.len() as u32;
+        atoms.push(match self.sizes.first() {
+            Some(size) if self.sizes.iter().all(|other| other == size) => {
+                full_box(b"stsz", [*size, count])
+            }
+            _ => full_box(
+                b"stsz",
+                [0, count].into_iter().chain(self.sizes.iter().copied()),
+            ),
+        });
+        let offsets = self.offsets.iter().map(|offset| offset + shift);
+        let last = self.offsets.iter().max().map_or(0, |max| max + shift);
+        atoms.push(if last > u32::MAX as u64 {
+            full_box(
+                b"co64",
+                std::iter::once(self.offsets.len() as u32)
+                    .chain(offsets.flat_map(|offset| [(offset >> 32) as u32, offset as u32])),
+            )
+        } else {
+            full_box(
+                b"stco",
+                std::iter::once(self.offsets.len() as u32)
+                    .chain(offsets.map(|offset| offset as u32)),
+            )
+        });
+        atoms
+    }
+}
+
+/// A track of a chapter, what joining needs of it.
+#[derive(Debug, Clone)]
+struct Track {
+    handler: Vec<u8>,
+    timescale: u32,
+    /// In the timescale of the movie.
+    duration: u64,
+    media_duration: u64,
+    /// The sample descriptions, the codec and its parameters.
+    descriptions: Vec<u8>,
+    samples: SampleTable,
+}
+
+impl Track {
+    fn parse(trak: &Atom, data_len: u64) -> Check<Self> {
+        let payload = |path: &[&BoxType]| {
+            trak.find(path)
+                .map(Atom::payload)
+                .ok_or_else(|| "a track of it is incomplete".to_string())
+        };
+        if let Some(elst) = trak.find(&[b"edts", b"elst"]) {
+            if !is_plain_edit(elst.payload())? {
+                return Err("a track of it has an edit list".into());
+            }
+        }
+        let mdhd = payload(&[b"mdia", b"mdhd"])?;
+        let stbl = trak
+            .find(&[b"mdia", b"minf", b"stbl"])
+            .ok_or_else(|| "a track of it has no sample table".to_string())?;
+        Ok(Track {
+            handler: payload(&[b"mdia", b"hdlr"])?
+                .get(8..12)
+                .unwrap_or_default()
+                .to_vec(),
+            timescale: timescale(mdhd)?,
+            duration: duration(payload(&[b"tkhd"])?, 20, 28)?,
+            media_duration: duration(mdhd, 16, 24)?,
+            descriptions: payload(&[b"mdia", b"minf", b"stbl", b"stsd"])?.to_vec(),
+            samples: SampleTable::parse(stbl, data_len)?,
+        })
+    }
+}
+
+/// Whether the edit list presents the media as is, from its start, as GoPro cameras write it.
+fn is_plain_edit(elst: &[u8]) -> Check<bool> {
+    let mut fields = Fields::full_box(elst);
+    let count = fields.u32()?;
+    // the media time follows the duration of the first edit
+    let media_time = if elst.first() == Some(&1) {
+        fields.take(8)?;
+        fields.u64()?
+    } else {
+        fields.take(4)?;
+        fields.u32()? as u64
+    };
+    Ok(count == 1 && media_time == 0)
+}
+
+/// The timescale of a `mvhd` or `mdhd` box.
+fn timescale(payload: &[u8]) -> Check<u32> {
+    let mut fields = Fields::full_box(payload);
+    // after the creation and modification times
+    fields.take(if payload.first() == Some(&1) { 16 } else { 8 })?;
+    fields.u32()
+}
+
+/// The duration of a `mvhd`, `tkhd` or `mdhd` box, found at `v0` or `v1` depending on its
+/// version.
+fn duration(payload: &[u8], v0: usize, v1: usize) -> Check<u64> {
+    match payload.first() {
+        Some(1) => Fields {
+            data: payload,
+            at: v1,
+        }
+        .u64(),
+        _ => Fields {
+            data: payload,
+            at: v0,
+        }
+        .u32()
+        .map(u64::from),
+    }
+}
+
+fn set_duration(payload: &mut [u8], v0: usize, v1: usize, duration: u64) -> Check<()> {
+    match payload.first() {
+        Some(1) => payload[v1..v1 + 8].copy_from_slice(&duration.to_be_bytes()),
+        _ if duration > u32::MAX as u64 => {
+            return Err("the merged movie would be too long for its 32 bit durations".into())
+        }
+        _ => payload[v0..v0 + 4].copy_from_slice(&(duration as u32).to_be_bytes()),
+    }
+    Ok(())
+}
+
+/// A chapter read for joining, its `moov` index and where its sample data is.
+#[derive(Debug, Clone)]
+struct Chapter {
+    ftyp: Vec<u8>,
+    moov: Atom,
+    /// The chunk offsets relative to the sample data.
+    tracks: Vec<Track>,
+    /// The offset and length of the `mdat` payload, the sample data.
+    data: (u64, u64),
+}
+
+impl Chapter {
+    fn read(path: &Path) -> Result<Self> {
+        let unsupported = |reason: String| Error::NativeUnsupported(path.to_path_buf(), reason);
+        let mut reader = BufReader::new(File::open(path)?);
+        let end = reader.seek(SeekFrom::End(0))?;
+        let boxes =
+            mp4::children(&mut reader, 0, end).map_err(|err| unsupported(err.to_string()))?;
+        let find = |kind: &BoxType| {
+            boxes
+                .iter()
+                .filter(|header| &header.kind == kind)
+                .copied()
+                .collect::<Vec<_>>()
+        };
+
+        if !find(b"moof").is_empty() {
+            return Err(unsupported("it's fragmented".into()));
+        }
+        let mdat = match find(b"mdat").as_slice() {
+            [mdat] => *mdat,
+            mdats => return Err(unsupported(format!("it has {} mdat boxes", mdats.len()))),
+        };
+        let (ftyp, moov) = match (find(b"ftyp").first(), find(b"moov").first()) {
+            (Some(ftyp), Some(moov)) => (
+                mp4::read_payload(&mut reader, ftyp)?,
+                mp4::read_payload(&mut reader, moov)?,
+            ),
+            _ => return Err(unsupported("it has no ftyp or moov box".into())),
+        };
+        let moov = Atom {
+            kind: *b"moov",
+            content: Content::Children(Atom::parse_all(&moov)?),
+        };
+        let mut tracks = moov
+            .children()
+            .iter()
+            .filter(|child| &child.kind == b"trak")
+            .map(|trak| Track::parse(trak, mdat.payload_len()))
+            .collect::<Check<Vec<_>>>()
+            .map_err(unsupported)?;
+
+        let data = (mdat.payload_offset(), mdat.payload_len());
+        let outside = tracks
+            .iter()
+            .flat_map(|track| &track.samples.offsets)
+            .any(|offset| *offset < data.0 || *offset >= data.0 + data.1);
+        if outside {
+            return Err(unsupported("it has samples outside of its mdat box".into()));
+        }
+        for track in &mut tracks {
+            track
+                .samples
+                .offsets
+                .iter_mut()
+                .for_each(|offset| *offset -= data.0);
+        }
+        Ok(Chapter {
+            ftyp,
+            moov,
+            tracks,
+            data,
+        })
+    }
+}
+
+/// Joins MP4 chapters of the same codecs without ffmpeg by appending their sample data and
+/// rewriting the sample tables of the first chapter to index all of it.
+#[derive(Debug, Clone)]
+pub struct Concat {
+    paths: Vec<PathBuf>,
+    first: Chapter,
+    /// Where the sample data of every chapter is, see [Chapter::data].
+    data: Vec<(u64, u64)>,
+    /// The tracks of the merged movie, the chunk offsets relative to its sample data.
+    tracks: Vec<Track>,
+    /// The summed durations of the chapters, in the timescale of the movie.
+    movie_duration: u64,
+    movie_timescale: u32,
+}
+
+impl Concat {
+    /// Reads the chapters at `paths`, failing with [Error::NativeUnsupported] for chapters which
+    /// can't be joined natively, e.g. ones with other codecs or tracks than the first chapter.
+    pub fn plan(paths: &[PathBuf]) -> Result<Self> {
+        let chapters = paths
+            .iter()
+            .map(|path| Chapter::read(path))
+            .collect::<Result<Vec<_>>>()?;
+        let first = match chapters.first() {
+            Some(first) => first.clone(),
+            None => {
+                return Err(Error::NativeUnsupported(
+                    PathBuf::new(),
+                    "no chapters".into(),
+                ))
+            }
+        };
+        let movie = |chapter: &Chapter| -> Check<(u32, u64)> {
+            let mvhd = chapter.moov.find(&[b"mvhd"]).map(Atom::payload);
+            let mvhd = mvhd.ok_or_else(|| "it has no movie header".to_string())?;
+            Ok((timescale(mvhd)?, duration(mvhd, 16, 24)?))
+        };
+
+        let (movie_timescale, _) =
+            movie(&first).map_err(|reason| Error::NativeUnsupported(paths[0].clone(), reason))?;
+        let mut tracks = first.tracks.clone();
+        let (mut movie_duration, mut written) = (0, 0);
+        for (i, (chapter, path)) in chapters.iter().zip(paths).enumerate() {
+            let unsupported = |reason: String| Error::NativeUnsupported(path.clone(), reason);
+            let (chapter_timescale, chapter_duration) = movie(chapter).map_err(unsupported)?;
+            let same_tracks = chapter_timescale == movie_timescale
+                && chapter.tracks.len() == tracks.len()
+                && chapter.tracks.iter().zip(&tracks).all(|(track, first)| {
+                    track.handler == first.handler
+                        && track.timescale == first.timescale
+                        && track.descriptions == first.descriptions
+                });
+            if !same_tracks {
+                return Err(unsupported(
+                    "its tracks or codecs differ from the ones of the first chapter".into(),
+                ));
+            }
+            movie_duration += chapter_duration;
+            if i > 0 {
+                for (merged, track) in tracks.iter_mut().zip(&chapter.tracks) {
+                    merged.duration += track.duration;
+                    merged.media_duration += track.media_duration;
+                    merged
+                        .samples
+                        .append(&track.samples, written)
+                        .map_err(unsupported)?;
+                }
+            }
+            written += chapter.data.1;
+        }
+
+        Ok(Concat {
+            paths: paths.to_vec(),
+            data: chapters.iter().map(|chapter| chapter.data).collect(),
+            first,
+            tracks,
+            movie_duration,
+            movie_timescale,
+        })
+    }
+
+    /// The summed durations of the chapters.
+    pub fn duration(&self) -> Duration {
+        match self.movie_timescale {
+            0 => Duration::ZERO,
+            timescale => Duration::from_secs_f64(self.movie_duration as f64 / timescale as f64),
+        }
+    }
+
+    /// The bytes of sample data copied from the chapters.
+    pub fn data_len(&self) -> u64 {
+        self.data.iter().map(|(_, len)| len).sum()
+    }
+
+    /// The `moov` of the merged movie, its sample data starting at `data_offset`.
+    fn moov(&self, data_offset: u64) -> Check<Atom> {
+        let mut moov = self.first.moov.clone();
+        let mvhd = moov.find_mut(&[b"mvhd"]).and_then(payload_mut);
+        set_duration(
+            mvhd.ok_or("it has no movie header")?,
+            16,
+            24,
+            self.movie_duration,
+        )?;
+
+        let traks = match &mut moov.content {
+            Content::Children(children) => children.iter_mut(),
+            Content::Data(_) => return Err("it has no movie header".into()),
+        };
+        let traks = traks.filter(|child| &child.kind == b"trak");
+        for (trak, track) in traks.zip(&self.tracks) {
+            if let Content::Children(children) = &mut trak.content {
+                // plain edit lists only, the media of the merged movie is presented as is too
+                children.retain(|child| &child.kind != b"edts");
+            }
+            let tkhd = trak.find_mut(&[b"tkhd"]).and_then(payload_mut);
+            set_duration(
+                tkhd.ok_or("a track of it is incomplete")?,
+                20,
+                28,
+                track.duration,
+            )?;
+            let mdhd = trak.find_mut(&[b"mdia", b"mdhd"]).and_then(payload_mut);
+            set_duration(
+                mdhd.ok_or("a track of it is incomplete")?,
+                16,
+                24,
+                track.media_duration,
+            )?;
+
+            let stbl = trak.find_mut(&[b"mdia", b"minf", b"stbl"]);
+            if let Some(Atom {
+                content: Content::Children(children),
+                ..
+            }) = stbl
+            {
+                children.retain(|child| {
+                    !REBUILT.contains(&&child.kind) && !LEFT_OUT.contains(&&child.kind)
+                });
+                children.extend(track.samples.atoms(data_offset));
+            }
+        }
+        Ok(moov)
+    }
+
+    /// Writes the merged movie to `output`: the `ftyp` of the first chapter, the sample data of
+    /// all the chapters and the rewritten `moov`. `copied` is told of every chunk of sample data
+    /// copied, the copy stops when it returns `false` and so does this, returning `false`.
+    pub fn write(&self, output: &Path, mut copied: impl FnMut(u64) -> bool) -> Result<bool> {
+        let mut head = vec![];
+        Atom::data(b"ftyp", self.first.ftyp.clone()).write(&mut head);
+        let len = self.data_len();
+        if len + 8 > u32::MAX as u64 {
+            head.extend_from_slice(&1u32.to_be_bytes());
+            head.extend_from_slice(b"mdat");
+            head.extend_from_slice(&(len + 16).to_be_bytes());
+        } else {
+            head.extend_from_slice(&(len as u32 + 8).to_be_bytes());
+            head.extend_from_slice(b"mdat");
+        }
+        let moov = self
+            .moov(head.len() as u64)
+            .map_err(|reason| Error::NativeUnsupported(self.paths[0].clone(), reason))?;
+
+        let mut writer = BufWriter::new(File::create(output)?);
+        writer.write_all(&head)?;
+        let mut buffer = vec![0; COPY_CHUNK];
+        for (path, (offset, len)) in self.paths.iter().zip(&self.data) {
+            let mut reader = File::open(path)?;
+            reader.seek(SeekFrom::Start(*offset))?;
+            let mut reader = reader.take(*len);
+            loop {
+                let read = reader.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                writer.write_all(&buffer[..read])?;
+                if !copied(read as u64) {
+                    return Ok(false);
+                }
+            }
+            if reader.limit() > 0 {
+                let cut = io::Error::new(io::ErrorKind::UnexpectedEof, "chapter cut short");
+                return Err(Error::ChapterUnreadable(path.clone(), cut));
+            }
+        }
+        let mut tail = vec![];
+        moov.write(&mut tail);
+        writer.write_all(&tail)?;
+        writer.flush()?;
+        Ok(true)
+    }
+}
+
+fn payload_mut(atom: &mut Atom) -> Option<&mut [u8]> {
+    match &mut atom.content {
+        Content::Data(data) => Some(data),
+        Content::Children(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{env, fs};
+
+    fn container(kind: &BoxType, children: Vec<Atom>) -> Atom {
+        Atom {
+            kind: *kind,
+            content: Content::Children(children),
+        }
+    }
+
+    /// A movie of a single video track with `samples` of 100ms each, in a single chunk.
+    fn movie(samples: &[&[u8]], sync: Option<Vec<u32>>, codec: &[u8]) -> Vec<u8> {
+        let (count, duration) = (samples.len() as u32, samples.len() as u32 * 100);
+        let mut stbl = vec![
+            Atom::data(b"stsd", codec.to_vec()),
+            full_box(b"stts", [1, count, 100]),
+            full_box(b"sdtp", [0]),
+        ];
+        if let Some(sync) = sync {
+            stbl.push(full_box(
+                b"stss",
+                std::iter::once(sync.len() as u32).chain(sync),
+            ));
+        }
+        stbl.push(full_box(
+            b"stsz",
+            [0, count]
+                .into_iter()
+                .chain(samples.iter().map(|sample| sample.len() as u32)),
+        ));
+        stbl.push(full_box(b"stsc", [1, 1, count, 1]));
+        // after the ftyp and the mdat header
+        stbl.push(full_box(b"stco", [1, 28]));
+
+        let trak = container(
+            b"trak",
+            vec![
+                full_box(b"tkhd", [0, 0, 1, 0, duration]),
+                container(b"edts", vec![full_box(b"elst", [1, duration, 0, 0x10000])]),
+                container(
+                    b"mdia",
+                    vec![
+                        full_box(b"mdhd", [0, 0, 1000, duration, 0]),
+                        full_box(b"hdlr", [0, u32::from_be_bytes(*b"vide"), 0, 0, 0]),
+                        container(b"minf", vec![container(b"stbl", stbl)]),
+                    ],
+                ),
+            ],
+        );
+        let moov = container(
+            b"moov",
+            vec![full_box(b"mvhd", [0, 0, 1000, duration]), trak],
+        );
+
+        let mut data = vec![];
+        Atom::data(b"ftyp", b"mp41\0\0\0\0mp41".to_vec()).write(&mut data);
+        Atom::data(b"mdat", samples.concat()).write(&mut data);
+        moov.write(&mut data);
+        data
+    }
+
+    #[test]
+    fn test_concat() {
+        let dir = env::temp_dir().join("goprotest_native_concat");
+        fs::create_dir_all(&dir).unwrap();
+        let paths = vec![dir.join("GH010084.MP4"), dir.join("GH020084.MP4")];
+        fs::write(&paths[0], movie(&[b"ab", b"cde"], Some(vec![1]), b"avc1")).unwrap();
+        fs::write(&paths[1], movie(&[b"fghi"], None, b"avc1")).unwrap();
+
+        let concat = Concat::plan(&paths).unwrap();
+        assert_eq!(Duration::from_millis(300), concat.duration());
+        assert_eq!(9, concat.data_len());
+        let output = dir.join("GH000084.MP4");
+        let mut copied = 0;
+        assert!(concat
+            .write(&output, |read| {
+                copied += read;
+                true
+            })
+            .unwrap());
+        assert_eq!(9, copied);
+
+        let merged = fs::read(&output).unwrap();
+        let boxes = mp4::children(&mut Cursor::new(&merged), 0, merged.len() as u64).unwrap();
+        let kinds = boxes.iter().map(|header| &header.kind).collect::<Vec<_>>();
+        assert_eq!(vec![b"ftyp", b"mdat", b"moov"], kinds);
+        assert_eq!(
+            b"abcdefghi",
+            &merged[boxes[1].payload_offset() as usize..boxes[1].end() as usize]
+        );
+
+        let moov = &merged[boxes[2].payload_offset() as usize..boxes[2].end() as usize];
+        let moov = container(b"moov", Atom::parse_all(moov).unwrap());
+        let trak = moov.find(&[b"trak"]).unwrap();
+        let track = Track::parse(trak, boxes[1].payload_len()).unwrap();
+        assert_eq!(
+            SampleTable {
+                times: vec![(2, 100), (1, 100)],
+                composition: None,
+                sync: Some(vec![1, 3]),
+                sizes: vec![2, 3, 4],
+                chunks: vec![(1, 2, 1), (2, 1, 1)],
+                offsets: vec![28, 33],
+            },
+            track.samples
+        );
+        assert_eq!((300, 300), (track.duration, track.media_duration));
+        assert!(trak.find(&[b"edts"]).is_none());
+        assert!(trak.find(&[b"mdia", b"minf", b"stbl", b"sdtp"]).is_none());
+        let mvhd = moov.find(&[b"mvhd"]).unwrap().payload();
+        assert_eq!(Ok(300), duration(mvhd, 16, 24));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_concat_unsupported() {
+        let dir = env::temp_dir().join("goprotest_native_concat_unsupported");
+        fs::create_dir_all(&dir).unwrap();
+        let paths = vec![dir.join("GH010084.MP4"), dir.join("GH020084.MP4")];
+        fs::write(&paths[0], movie(&[b"ab"], None, b"avc1")).unwrap();
+        fs::write(&paths[1], movie(&[b"cd"], None, b"hvc1")).unwrap();
+
+        assert!(matches!(
+            Concat::plan(&paths),
+            Err(Error::NativeUnsupported(path, _)) if path == paths[1]
+        ));
+
+        let mut edited = movie(&[b"cd"], None, b"avc1");
+        // a media time of 1 in the edit list
+        let elst = edited.windows(4).position(|kind| kind == b"elst").unwrap();
+        edited[elst + 19] = 1;
+        fs::write(&paths[1], edited).unwrap();
+        assert!(matches!(
+            Concat::plan(&paths),
+            Err(Error::NativeUnsupported(path, _)) if path == paths[1]
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sample_table_uniform_sizes() {
+        let stbl = |count| {
+            container(
+                b"stbl",
+                vec![
+                    full_box(b"stts", [1, count, 100]),
+                    full_box(b"stsz", [1024, count]),
+                    full_box(b"stsc", [1, 1, count, 1]),
+                    full_box(b"stco", [1, 0]),
+                ],
+            )
+        };
+        assert_eq!(
+            Ok(vec![1024; 4]),
+            SampleTable::parse(&stbl(4), 4096).map(|table| table.sizes)
+        );
+        // samples of the same size aren't allocated for more than the sample data holds
+        assert!(SampleTable::parse(&stbl(5), 4096).is_err());
+        assert!(SampleTable::parse(&stbl(u32::MAX), 4096).is_err());
+    }
+}
@@ -0,0 +1,310 @@
+use std::path::PathBuf;
+
+use log::*;
+
+use crate::confirm::DestructiveAction;
+use crate::duration::HumanDuration;
+use crate::group::MovieGroup;
+use crate::merge::ffmpeg::{
+    extract_gpx, merge_recorded_proxies, preflight_chapters, record_sources, remove_if_exists,
+    report_merge, start_merge, Start,
+};
+use crate::merge::native::Concat;
+use crate::merge::{FFmpegMerger, MergeOptions, Merger, OutputContainer, ResolvedOutput, Result};
+use crate::progress::{GroupStats, Progress, Throughput};
+use crate::proxy::ProxyMode;
+use crate::system::{Clock as _, RealFs, SystemClock};
+use crate::timestamp;
+
+/// Joins MP4 chapters of the same codecs without ffmpeg, see [Concat]. Groups it can't join, or
+/// which are asked for something only ffmpeg does, e.g. subtitles, are merged by [FFmpegMerger]
+/// instead.
+pub struct NativeMerger {
+    progress: Box<dyn Progress>,
+    group: MovieGroup,
+    movies_path: PathBuf,
+    merged_output_path: PathBuf,
+    options: MergeOptions,
+}
+
+impl Merger for NativeMerger {
+    fn new(
+        progress: Box<dyn Progress>,
+        group: MovieGroup,
+        movies_path: PathBuf,
+        merged_output_path: PathBuf,
+        options: MergeOptions,
+    ) -> Self {
+        NativeMerger {
+            progress,
+            group,
+            movies_path,
+            merged_output_path,
+            options,
+        }
+    }
+
+    fn merge(self: Box<Self>) -> Result<GroupStats> {
+        let paths = self
+            .group
+            .chapters
+            .iter()
+            .map(|chapter| self.group.chapter_path(chapter, &self.movies_path))
+            .collect::<Vec<_>>();
+        let concat = match ffmpeg_needed(&self.group, &self.options) {
+            Some(option) => Err(format!("{} needs ffmpeg", option)),
+            None => Concat::plan(&paths).map_err(|err| err.to_string()),
+        };
+        let concat = match concat {
+            Ok(concat) => concat,
+            Err(reason) => {
+                info!("merging {} with ffmpeg: {}", self.group, reason);
+                let NativeMerger {
+                    progress,
+                    group,
+                    movies_path,
+                    merged_output_path,
+                    options,
+                } = *self;
+                let merger =
+                    FFmpegMerger::new(progress, group, movies_path, merged_output_path, options);
+                return Box::new(merger).merge();
+            }
+        };
+
+        let (progress, group) = (self.progress.clone(), self.group.clone());
+        let (merged_output_path, options) = (self.merged_output_path.clone(), self.options.clone());
+        report_merge(progress, &group, &merged_output_path, &options, || {
+            self.merge_inner(paths, concat)
+        })
+    }
+}
+
+impl NativeMerger {
+    fn merge_inner(self, paths: Vec<PathBuf>, concat: Concat) -> Result<GroupStats> {
+        let started = SystemClock.instant();
+        let Self {
+            mut progress,
+            group,
+            merged_output_path,
+            options,
+            ..
+        } = self;
+        let output = match start_merge(
+            &mut progress,
+            &group,
+            &merged_output_path,
+            &options,
+            started,
+        )? {
+            Start::Merge(output) => output,
+            Start::Done(stats) => return Ok(*stats),
+        };
+
+        let preflight_started = SystemClock.instant();
+        preflight_chapters(&group, &paths, options.retry)?;
+        let preflight = SystemClock.instant() - preflight_started;
+        debug!("preflight of {} took {:?}", group, preflight);
+        let sources = record_sources(&mut progress, &paths, &options)?;
+
+        let duration = concat.duration();
+        debug!("joining {} natively", group);
+        progress.set_len(duration);
+        options.jobs.set_len(&group.name(), duration);
+        // overwriting was confirmed before the run started
+        let actions = match &output {
+            ResolvedOutput::Overwrite(path) => vec![DestructiveAction::Overwrite(path.clone())],
+            _ => vec![],
+        };
+        if let Some(resume) = &options.resume {
+            resume.start(&group.name(), &group.id(), output.path())?;
+        }
+
+        let joined = {
+            let output_lock = options.output_locks.get(output.path());
+            let _output_guard = output_lock.lock();
+            let (len, mut copied) = (concat.data_len().max(1), 0);
+            concat.write(output.path(), |read| {
+                copied += read;
+                // the sample data is copied as is, its bytes are as good a measure as its duration
+                let merged = duration.mul_f64(copied as f64 / len as f64);
+                let elapsed = (SystemClock.instant() - started).as_secs_f64();
+                progress.update(
+                    merged,
+                    Throughput {
+                        speed: Some(merged.as_secs_f64() / elapsed.max(f64::EPSILON)),
+                        ..Default::default()
+                    },
+                );
+                options.jobs.update(&group.name(), merged);
+                !options.stopping(&group)
+                    && !options
+                        .deadline
+                        .map_or(false, |deadline| deadline.stops(SystemClock.instant()))
+            })
+        };
+        let joined = match joined {
+            Ok(joined) => joined,
+            Err(err) => {
+                remove_if_exists(output.path())?;
+                return Err(err);
+            }
+        };
+        if !joined {
+            let interrupted = options.stopping(&group);
+            warn!(
+                "stopped merging {} {}, removing {}",
+                group,
+                if interrupted {
+                    "as the run was interrupted or it was cancelled"
+                } else {
+                    "at the run deadline"
+                },
+                output.path().display()
+            );
+            remove_if_exists(output.path())?;
+            let (output, elapsed) = (output.path().to_path_buf(), SystemClock.instant() - started);
+            return Ok(if interrupted {
+                GroupStats::interrupted(output, elapsed)
+            } else {
+                GroupStats::deferred(output, elapsed)
+            });
+        }
+        debug!(
+            "joined {} natively in {}",
+            group,
+            HumanDuration(SystemClock.instant() - started)
+        );
+
+        let recorded = paths.first().and_then(|path| {
+            let mut timestamps = [timestamp::resolve(None, path, &RealFs, &SystemClock)];
+            timestamp::align(&mut timestamps, options.timezone, &SystemClock);
+            timestamps[0].time
+        });
+        timestamp::preserve(output.path(), recorded, &RealFs);
+        let proxy = if options.merge_proxies {
+            merge_recorded_proxies(&progress, &group, &paths, output.path(), recorded, &options)?
+        } else {
+            None
+        };
+        let gpx = if options.extract_gpx {
+            extract_gpx(&progress, &group, &paths, output.path(), recorded)?
+        } else {
+            None
+        };
+        if let Some(resume) = &options.resume {
+            resume.complete(&group.name(), &group.id(), output.path())?;
+        }
+
+        Ok(GroupStats {
+            output: output.path().to_path_buf(),
+            duration,
+            elapsed: SystemClock.instant() - started,
+            preflight,
+            sources,
+            actions,
+            skipped: false,
+            deferred: false,
+            interrupted: false,
+            verification: None,
+            telemetry_track: None,
+            proxy,
+            gpx,
+            audio: None,
+            excluded: vec![],
+            variable_frame_rate: vec![],
+            replanned: vec![],
+            started: None,
+            ended: None,
+        })
+    }
+}
+
+/// The option the `group` is merged with ffmpeg for rather than natively, one asking for more
+/// than copying the streams of its chapters. The ones needing the chapters probed are among
+/// them, the native merge probes nothing.
+pub fn ffmpeg_needed(group: &MovieGroup, options: &MergeOptions) -> Option<&'static str> {
+    // every stream is copied, unknown ones included
+    let needed = [
+        (options.subtitles, "--subtitles"),
+        (options.chapter_markers, "--chapter-markers"),
+        (options.cfr, "--cfr"),
+        (options.crossfade.is_some(), "--crossfade"),
+        (!options.streams.data, "--drop-data-streams"),
+        (options.embed_sources, "--embed-sources"),
+        (options.verify.is_some(), "--verify"),
+        (options.extract_telemetry, "--extract-telemetry"),
+        (options.proxy, "--proxy"),
+        (options.extract_audio.is_some(), "--extract-audio"),
+        (!options.exports.is_empty(), "--export"),
+        (options.proxies == ProxyMode::Auto, "--exclude-proxies auto"),
+        (options.names.title(group).is_some(), "--title-template"),
+        (
+            options
+                .names
+                .container
+                .map_or(false, |container| container != OutputContainer::Mp4),
+            "--container",
+        ),
+        (options.activities.tag(group).is_some(), "--activity tag"),
+    ];
+    needed
+        .iter()
+        .find(|(needed, _)| *needed)
+        .map(|(_, option)| *option)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::merge::{StreamMapping, VerifyMode};
+    use crate::naming::OutputNames;
+
+    #[test]
+    fn test_ffmpeg_needed() {
+        let group = crate::group::group_file_names(vec!["GH010084.mp4", "GH020084.mp4"])[0].clone();
+        assert_eq!(None, ffmpeg_needed(&group, &MergeOptions::default()));
+
+        let options = MergeOptions {
+            // unknown streams are copied natively too
+            streams: StreamMapping {
+                copy_unknown: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(None, ffmpeg_needed(&group, &options));
+
+        let options = MergeOptions {
+            verify: Some(VerifyMode::Bitexact),
+            ..Default::default()
+        };
+        assert_eq!(Some("--verify"), ffmpeg_needed(&group, &options));
+        let options = MergeOptions {
+            streams: StreamMapping {
+                data: false,
+                timecode: true,
+                copy_unknown: false,
+            },
+            ..Default::default()
+        };
+        assert_eq!(Some("--drop-data-streams"), ffmpeg_needed(&group, &options));
+
+        let container = |container| MergeOptions {
+            names: OutputNames {
+                container: Some(container),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            None,
+            ffmpeg_needed(&group, &container(OutputContainer::Mp4))
+        );
+        assert_eq!(
+            Some("--container"),
+            ffmpeg_needed(&group, &container(OutputContainer::Mkv))
+        );
+    }
+}
@@ -0,0 +1,5 @@
+mod concat;
+mod merger;
+
+pub use concat::*;
+pub use merger::*;
@@ -0,0 +1,312 @@
+use std::env;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::activity::Activities;
+use crate::audit::AuditHash;
+use crate::export::ExportFormat;
+use crate::group::MovieGroup;
+use crate::history::History;
+use crate::jobs::Jobs;
+use crate::merge::{
+    AudioFormat, Binaries, ByteSize, ConcatMethod, OnExists, OutputLocks, ProbeCache, VerifyMode,
+    MIN_FREE_SPACE, SPACE_WAIT,
+};
+use crate::naming::OutputNames;
+use crate::proxy::ProxyMode;
+use crate::resume::ResumeState;
+use crate::run::{Deadline, Interrupt, RunId};
+use crate::schedule::{Priorities, Schedule};
+use crate::timestamp::TimeZone;
+
+/// Longest duration representable by a 32 bit MP4 duration in the 90kHz timescale used for video
+/// tracks, around 13 hours. Muxers switch to 64 bit durations beyond it which many players and
+/// editors refuse to seek in.
+pub const VERY_LONG_DURATION: Duration = Duration::from_secs(u32::MAX as u64 / 90_000);
+
+/// How far the duration of a merged movie may be off from the summed durations of its chapters,
+/// remuxing shifts every chapter boundary by up to a frame or an audio packet.
+pub const DURATION_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// How long ffmpeg may go without its merged duration advancing before it's taken for hung, it
+/// reports progress twice a second while merging.
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// The longest wait between two attempts at reading the chapters, unless the first one is longer.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// How reading the chapters of a group is retried before failing it, e.g. while automounted
+/// network shares come up. The same for every backend, they all read the chapters up front.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    /// Attempts after the first one, none failing the group right away.
+    pub retries: usize,
+    /// The wait before the first retry, doubling for every following one.
+    pub delay: Duration,
+}
+
+impl Retry {
+    /// The wait before the `retry`th retry, counting from one.
+    pub fn delay(&self, retry: usize) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1) as u32);
+        self.delay
+            .saturating_mul(factor)
+            .min(MAX_RETRY_DELAY.max(self.delay))
+    }
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            retries: 2,
+            delay: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Which streams of the chapters end up in the merged movie, by default the video, the audio and
+/// the GoPro telemetry (GPMF) with the timecode (tmcd) written again from the video, the data
+/// tracks which some hardware players choke on. The other data tracks, e.g. fdsc, have no codec
+/// the muxer could write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamMapping {
+    /// Keep the data streams, telemetry and timecode.
+    pub data: bool,
+    /// Keep the timecode track even when the other data streams are dropped.
+    pub timecode: bool,
+    /// Copy every stream as it is, those of a type unknown to ffmpeg too.
+    pub copy_unknown: bool,
+}
+
+impl Default for StreamMapping {
+    fn default() -> Self {
+        StreamMapping {
+            data: true,
+            timecode: true,
+            copy_unknown: false,
+        }
+    }
+}
+
+/// Where the ffmpeg logs of the groups are kept, from `--log-dir`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogDir {
+    pub path: PathBuf,
+    /// When the run started, stamping its logs apart from the ones of earlier runs.
+    pub started: SystemTime,
+}
+
+impl LogDir {
+    pub fn new(path: PathBuf) -> Self {
+        LogDir {
+            path,
+            started: SystemTime::now(),
+        }
+    }
+
+    /// The log of the group named `name`, e.g. 20211114T231525Z_GH000084.MP4.log.
+    pub fn log_path(&self, name: &str) -> PathBuf {
+        let started = humantime::format_rfc3339_seconds(self.started)
+            .to_string()
+            .replace(|c: char| c == '-' || c == ':', "");
+        self.path.join(format!("{}_{}.log", started, name))
+    }
+}
+
+/// Settings shared by all the mergers of a run.
+#[derive(Debug, Clone)]
+pub struct MergeOptions {
+    pub run_id: RunId,
+    /// Directory for the intermediate files of the run.
+    pub workspace: PathBuf,
+    /// Sidecar files to export next to every merged movie.
+    pub exports: Vec<ExportFormat>,
+    /// Mux a subtitles track naming the source chapter at the start of each of them.
+    pub subtitles: bool,
+    /// Mux chapter markers at the start of every source chapter, from `--chapter-markers`.
+    pub chapter_markers: bool,
+    /// Whether chapters are probed for their durations before merging, disabled when ffprobe
+    /// isn't available in which case progress is reported with an unknown length.
+    pub probe: bool,
+    /// Streams to keep from the chapters.
+    pub streams: StreamMapping,
+    /// How the chapters are joined, the protocol writes no list to the workspace.
+    pub concat: ConcatMethod,
+    /// Hash every source chapter for the audit trail of the merged movies, only their sizes
+    /// are recorded otherwise.
+    pub audit_hash: Option<AuditHash>,
+    /// Embed the source chapter list, with their hashes if any, into the merged movies.
+    pub embed_sources: bool,
+    /// What to do with merged movies that already exist.
+    pub on_exists: OnExists,
+    /// How far the merged movie may be off from the summed durations of its chapters, it's
+    /// probed after merging when the chapters were.
+    pub duration_tolerance: Duration,
+    /// Keep merged movies off by more than `duration_tolerance`, only warning about them.
+    pub allow_duration_mismatch: bool,
+    /// Verification of the merged movies beyond their duration.
+    pub verify: Option<VerifyMode>,
+    /// Extract the GPMF telemetry track of the merged movies next to them, from
+    /// `--extract-telemetry`.
+    pub extract_telemetry: bool,
+    /// Generate a low resolution proxy of the merged movies next to them, from `--proxy`.
+    pub proxy: bool,
+    /// Join the proxies the camera recorded next to the chapters into one next to the merged
+    /// movies, along with a thumbnail, from `--merge-proxies`.
+    pub merge_proxies: bool,
+    /// Write the GPS track of the chapters next to the merged movies, from `--extract-gpx`.
+    pub extract_gpx: bool,
+    /// Extract the audio of the merged movies next to them, from `--extract-audio`.
+    pub extract_audio: Option<AudioFormat>,
+    /// Remove the merged movies once their audio is extracted, from `--audio-only`.
+    pub audio_only: bool,
+    /// Kill ffmpeg and fail the group when its merged duration doesn't advance for this long,
+    /// from `--stall-timeout`.
+    pub stall_timeout: Option<Duration>,
+    /// How reading the chapters is retried, from `--read-retries` and `--read-retry-delay`.
+    pub retry: Retry,
+    /// Re-encode the video of groups with variable frame rate chapters to a constant frame rate
+    /// instead of copying it, from `--cfr`.
+    pub cfr: bool,
+    /// Dissolve the video and crossfade the audio of the chapters into each other over this long,
+    /// re-encoding both, from `--crossfade`.
+    pub crossfade: Option<Duration>,
+    /// The ffmpeg and ffprobe binaries to run.
+    pub binaries: Binaries,
+    /// Probes of earlier runs, none with `--no-probe-cache`.
+    pub probe_cache: Option<ProbeCache>,
+    /// New groups wait for the output volume to have this much space free.
+    pub min_free_space: ByteSize,
+    /// How long new groups wait for that space before failing, from `--space-wait`.
+    pub space_wait: Duration,
+    /// No groups are started after it, from `--max-runtime`.
+    pub deadline: Option<Deadline>,
+    /// Set on Ctrl-C, stops the in-flight merges and the groups not started yet.
+    pub interrupt: Interrupt,
+    /// The state of the groups served by `--serve`, cancelling one stops it like Ctrl-C does.
+    pub jobs: Jobs,
+    /// Chapters probed at once ahead of merging, from `--probe-parallel`, 0 for one per core. How
+    /// many groups are merged at once is the size of the global rayon pool.
+    pub probe_parallel: usize,
+    /// Keep merging the other groups when one fails instead of stopping the run, from
+    /// `--keep-going`.
+    pub keep_going: bool,
+    /// The order the groups start merging in, from `--schedule`.
+    pub schedule: Schedule,
+    /// Groups merged before all the others, from `--priority`.
+    pub priorities: Priorities,
+    /// Shared by all the mergers of the run to serialize writes to the same output file.
+    pub output_locks: OutputLocks,
+    /// Records which groups started and completed for resuming an interrupted run, from
+    /// `--resume`.
+    pub resume: Option<ResumeState>,
+    /// Records how every group ended for `gopro-merge history report`, none with `--no-history`.
+    pub history: Option<History>,
+    /// Activities of the groups classified by their GPS, from `--region`.
+    pub activities: Activities,
+    /// Names of the merged movies, from `--name-template`.
+    pub names: OutputNames,
+    /// Whether chapters looking like low resolution proxies are left out of their groups, from
+    /// `--exclude-proxies`.
+    pub proxies: ProxyMode,
+    /// Keep the ffmpeg log of every group there instead of the temporary directory, from
+    /// `--log-dir`.
+    pub log_dir: Option<LogDir>,
+    /// The zone the recording times of the chapters are read in, from `--timezone`.
+    pub timezone: TimeZone,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        MergeOptions {
+            run_id: RunId::generate(),
+            workspace: env::temp_dir(),
+            exports: vec![],
+            subtitles: false,
+            chapter_markers: false,
+            probe: true,
+            streams: StreamMapping::default(),
+            concat: ConcatMethod::default(),
+            audit_hash: None,
+            embed_sources: false,
+            on_exists: OnExists::default(),
+            duration_tolerance: DURATION_TOLERANCE,
+            allow_duration_mismatch: false,
+            verify: None,
+            extract_telemetry: false,
+            proxy: false,
+            merge_proxies: false,
+            extract_gpx: false,
+            extract_audio: None,
+            audio_only: false,
+            stall_timeout: Some(STALL_TIMEOUT),
+            retry: Retry::default(),
+            cfr: false,
+            crossfade: None,
+            binaries: Binaries::default(),
+            min_free_space: MIN_FREE_SPACE,
+            space_wait: SPACE_WAIT,
+            deadline: None,
+            interrupt: Interrupt::default(),
+            jobs: Jobs::default(),
+            probe_parallel: 0,
+            probe_cache: None,
+            keep_going: false,
+            schedule: Schedule::default(),
+            priorities: Priorities::default(),
+            output_locks: OutputLocks::default(),
+            resume: None,
+            history: None,
+            activities: Activities::default(),
+            names: OutputNames::default(),
+            proxies: ProxyMode::default(),
+            log_dir: None,
+            timezone: TimeZone::default(),
+        }
+    }
+}
+
+impl MergeOptions {
+    /// Whether to stop merging the `group`, the run being interrupted or the group cancelled.
+    pub fn stopping(&self, group: &MovieGroup) -> bool {
+        self.interrupt.is_triggered() || self.jobs.is_cancelled(&group.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let retry = Retry {
+            retries: 20,
+            delay: Duration::from_secs(2),
+        };
+        assert_eq!(
+            vec![2, 4, 8, 16],
+            (1..=4)
+                .map(|retry_number| retry.delay(retry_number).as_secs())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(MAX_RETRY_DELAY, retry.delay(20));
+
+        let retry = Retry {
+            retries: 2,
+            delay: Duration::from_secs(600),
+        };
+        assert_eq!(Duration::from_secs(600), retry.delay(2));
+    }
+
+    #[test]
+    fn test_log_dir_log_path() {
+        let log_dir = LogDir {
+            path: "/logs".into(),
+            started: humantime::parse_rfc3339("2021-11-14T23:15:25.5Z").unwrap(),
+        };
+        assert_eq!(
+            PathBuf::from("/logs/20211114T231525Z_GH000084.MP4.log"),
+            log_dir.log_path("GH000084.MP4")
+        );
+    }
+}
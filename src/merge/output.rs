@@ -0,0 +1,321 @@
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use derive_more::Display;
+use log::*;
+
+use crate::merge::ffmpeg::ffmpeg_path;
+use crate::merge::{Error, Result};
+use crate::system::Fs;
+
+/// What to do when the merged movie already exists in the output directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum OnExists {
+    #[display(fmt = "skip")]
+    Skip,
+    #[display(fmt = "overwrite")]
+    Overwrite,
+    /// Merge into the first free `<name>_<n>.<ext>` next to it.
+    #[display(fmt = "rename")]
+    Rename,
+    #[display(fmt = "fail")]
+    Fail,
+}
+
+impl Default for OnExists {
+    fn default() -> Self {
+        OnExists::Overwrite
+    }
+}
+
+impl FromStr for OnExists {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "skip" => OnExists::Skip,
+            "overwrite" => OnExists::Overwrite,
+            "rename" => OnExists::Rename,
+            "fail" => OnExists::Fail,
+            _ => return Err(Error::InvalidOnExists(s.into())),
+        })
+    }
+}
+
+/// The container the merged movies are written in with --container, none keeping the one of
+/// their chapters. Matroska takes chapters whose streams differ in ways the MP4 muxer rejects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum OutputContainer {
+    #[display(fmt = "mp4")]
+    Mp4,
+    #[display(fmt = "mkv")]
+    Mkv,
+    #[display(fmt = "mov")]
+    Mov,
+}
+
+impl FromStr for OutputContainer {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "mp4" => OutputContainer::Mp4,
+            "mkv" => OutputContainer::Mkv,
+            "mov" => OutputContainer::Mov,
+            _ => return Err(Error::InvalidContainer(s.into())),
+        })
+    }
+}
+
+impl OutputContainer {
+    /// The merged movie `name` with the extension of the container, kept as is when it already
+    /// has it in any case, e.g. GH010084.MP4.
+    pub fn rename(self, name: &str) -> String {
+        let path = Path::new(name);
+        let ext = self.to_string();
+        match path.extension().and_then(OsStr::to_str) {
+            Some(current) if current.eq_ignore_ascii_case(&ext) => name.into(),
+            _ => path.with_extension(ext).to_string_lossy().into_owned(),
+        }
+    }
+}
+
+/// Where a merged movie ends up after applying [OnExists].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolvedOutput {
+    New(PathBuf),
+    Overwrite(PathBuf),
+    Renamed(PathBuf),
+    Skip(PathBuf),
+}
+
+impl ResolvedOutput {
+    pub fn path(&self) -> &Path {
+        match self {
+            ResolvedOutput::New(path)
+            | ResolvedOutput::Overwrite(path)
+            | ResolvedOutput::Renamed(path)
+            | ResolvedOutput::Skip(path) => path,
+        }
+    }
+}
+
+/// Applies `on_exists` to the merged movie `path`, failing with [Error::OutputExists] for
+/// [OnExists::Fail].
+pub fn resolve_output(path: PathBuf, on_exists: OnExists) -> Result<ResolvedOutput> {
+    if !path.exists() {
+        return Ok(ResolvedOutput::New(path));
+    }
+
+    Ok(match on_exists {
+        OnExists::Skip => ResolvedOutput::Skip(path),
+        OnExists::Overwrite => ResolvedOutput::Overwrite(path),
+        OnExists::Rename => ResolvedOutput::Renamed(free_path(&path)),
+        OnExists::Fail => return Err(Error::OutputExists(path)),
+    })
+}
+
+/// Fails with [Error::ReadOnlyOutput] when the merged movies can't be written to `output`, e.g.
+/// the memory card it defaults to being the input. Read-only `inputs` are fine, only the merged
+/// movies, their sidecars and the --resume state are written outside of the workspace.
+pub fn check_writable(inputs: &[PathBuf], output: &Path, fs: &impl Fs) -> Result<()> {
+    for input in inputs {
+        match fs.read_only(input) {
+            Ok(true) => info!(
+                "{} is read-only, only reading the chapters from it",
+                input.display()
+            ),
+            Ok(false) => {}
+            Err(err) => debug!("not checking if {} is read-only: {}", input.display(), err),
+        }
+    }
+
+    match fs.read_only(output) {
+        Ok(true) => Err(Error::ReadOnlyOutput(output.to_path_buf())),
+        Ok(false) => Ok(()),
+        // merging fails on its own if it really isn't writable
+        Err(err) => {
+            warn!("not checking if {} is read-only: {}", output.display(), err);
+            Ok(())
+        }
+    }
+}
+
+/// Longest file name the filesystems of the platform take, in [path_len] units.
+const MAX_NAME: usize = 255;
+
+/// Longest path the platform opens, with the verbatim prefix on Windows.
+#[cfg(windows)]
+const MAX_PATH: usize = 32_767;
+#[cfg(not(windows))]
+const MAX_PATH: usize = 4095;
+
+#[cfg(windows)]
+const PATH_UNITS: &str = "characters";
+#[cfg(not(windows))]
+const PATH_UNITS: &str = "bytes";
+
+/// Fails with [Error::OutputPathTooLong] when the merged movie `path` can't be written even as
+/// it's given to ffmpeg, i.e. verbatim on Windows when it's longer than `MAX_PATH`.
+pub fn check_path_length(path: &Path) -> Result<()> {
+    let too_long = |reason: String| Err(Error::OutputPathTooLong(path.to_path_buf(), reason));
+    for component in path.components() {
+        if let Component::Normal(name) = component {
+            if path_len(name) > MAX_NAME {
+                return too_long(format!(
+                    "{} is {} {} long, at most {} are allowed",
+                    name.to_string_lossy(),
+                    path_len(name),
+                    PATH_UNITS,
+                    MAX_NAME
+                ));
+            }
+        }
+    }
+
+    let len = path_len(ffmpeg_path(path).as_os_str());
+    if len > MAX_PATH {
+        return too_long(format!(
+            "its path is {} {} long, at most {} are allowed",
+            len, PATH_UNITS, MAX_PATH
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn path_len(path: &OsStr) -> usize {
+    use std::os::windows::ffi::OsStrExt;
+
+    path.encode_wide().count()
+}
+
+#[cfg(not(windows))]
+fn path_len(path: &OsStr) -> usize {
+    path.len()
+}
+
+fn free_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+
+    (1..)
+        .map(|n| path.with_file_name(format!("{}_{}{}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+
+    use crate::system::mock::MemoryFs;
+
+    #[test]
+    fn test_on_exists_from_str() {
+        assert_eq!(OnExists::Skip, "skip".parse().unwrap());
+        assert_eq!(OnExists::Overwrite, "overwrite".parse().unwrap());
+        assert_eq!(OnExists::Rename, "rename".parse().unwrap());
+        assert_eq!(OnExists::Fail, "fail".parse().unwrap());
+        assert!("ask".parse::<OnExists>().is_err());
+    }
+
+    #[test]
+    fn test_output_container() {
+        assert_eq!(OutputContainer::Mkv, "mkv".parse().unwrap());
+        assert!(matches!(
+            "avi".parse::<OutputContainer>(),
+            Err(Error::InvalidContainer(_))
+        ));
+
+        assert_eq!("GH010084.mkv", OutputContainer::Mkv.rename("GH010084.MP4"));
+        assert_eq!("GH010084.MP4", OutputContainer::Mp4.rename("GH010084.MP4"));
+        assert_eq!("GS010084.mov", OutputContainer::Mov.rename("GS010084.360"));
+        assert_eq!(
+            "2021-11-14 Ski.day.mp4",
+            OutputContainer::Mp4.rename("2021-11-14 Ski.day.mov")
+        );
+    }
+
+    #[test]
+    fn test_resolve_output() {
+        let dir = env::temp_dir().join("goprotest_resolve_output");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("GH000084.MP4");
+
+        assert_eq!(
+            ResolvedOutput::New(path.clone()),
+            resolve_output(path.clone(), OnExists::Fail).unwrap()
+        );
+
+        fs::write(&path, "merged").unwrap();
+        fs::write(dir.join("GH000084_1.MP4"), "merged again").unwrap();
+        assert_eq!(
+            ResolvedOutput::Skip(path.clone()),
+            resolve_output(path.clone(), OnExists::Skip).unwrap()
+        );
+        assert_eq!(
+            ResolvedOutput::Overwrite(path.clone()),
+            resolve_output(path.clone(), OnExists::Overwrite).unwrap()
+        );
+        assert_eq!(
+            ResolvedOutput::Renamed(dir.join("GH000084_2.MP4")),
+            resolve_output(path.clone(), OnExists::Rename).unwrap()
+        );
+        assert!(matches!(
+            resolve_output(path, OnExists::Fail),
+            Err(Error::OutputExists(..))
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_writable() {
+        let fs = MemoryFs::default();
+        let (card, output) = (Path::new("/media/GOPRO/DCIM"), Path::new("/movies"));
+        fs.set_read_only(Path::new("/media/GOPRO"));
+
+        assert!(check_writable(&[card.to_path_buf()], output, &fs).is_ok());
+        assert!(matches!(
+            check_writable(&[card.to_path_buf()], card, &fs),
+            Err(Error::ReadOnlyOutput(path)) if path == card
+        ));
+
+        // unknown is taken for writable
+        fs.fail(output);
+        assert!(check_writable(&[], output, &fs).is_ok());
+    }
+
+    #[test]
+    fn test_check_path_length() {
+        let dir = Path::new("/movies/2021/11");
+        assert!(check_path_length(&dir.join("GH000084.MP4")).is_ok());
+        assert!(check_path_length(&dir.join(format!("{}.MP4", "a".repeat(251)))).is_ok());
+
+        let name = format!("{}.MP4", "a".repeat(252));
+        assert!(matches!(
+            check_path_length(&dir.join(&name)),
+            Err(Error::OutputPathTooLong(path, reason))
+                if path == dir.join(&name) && reason.starts_with(&name)
+        ));
+        let deep =
+            (0..=MAX_PATH / 100).fold(dir.to_path_buf(), |path, _| path.join("a".repeat(99)));
+        assert!(matches!(
+            check_path_length(&deep.join("GH000084.MP4")),
+            Err(Error::OutputPathTooLong(_, reason)) if reason.starts_with("its path is")
+        ));
+    }
+}
@@ -0,0 +1,573 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use log::*;
+
+use crate::group::MovieGroup;
+use crate::merge::{Error, MergeOptions, Result};
+use crate::progress::Progress;
+use crate::system::{Clock, Fs};
+
+/// Free space the output volume needs for starting new groups, enough for ffmpeg to write its
+/// trailer and the sidecars of the in-flight merges.
+pub const MIN_FREE_SPACE: ByteSize = ByteSize(1024 * 1024 * 1024);
+
+/// What a group stages in the workspace per chapter besides its path, room to spare for the
+/// lines of the subtitles, the chapter markers and the list of sources.
+const STAGED_BYTES_PER_CHAPTER: u64 = 512;
+
+/// Binary prefixes of [ByteSize], the largest fitting a u64.
+const BINARY_PREFIXES: [&str; 6] = ["Ki", "Mi", "Gi", "Ti", "Pi", "Ei"];
+
+/// How often the free space is checked again while new groups are paused.
+pub const SPACE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long a paused group waits for space to be freed before failing, so that a full volume
+/// doesn't hold up the workers of the run until its deadline, if any.
+pub const SPACE_WAIT: Duration = Duration::from_secs(30 * 60);
+
+/// An amount of bytes, parsed from e.g. `500M`, `10GiB` or a plain number of bytes with binary
+/// units either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+/// Formats with two decimals in the largest binary unit, e.g. `1.50GiB`, or as plain bytes below
+/// a KiB, e.g. `512B`.
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut amount = self.0 as f64;
+        let mut unit = None;
+        for prefix in BINARY_PREFIXES {
+            if amount < 1024.0 {
+                break;
+            }
+            amount /= 1024.0;
+            unit = Some(prefix);
+        }
+
+        match unit {
+            Some(prefix) => write!(f, "{:.2}{}B", amount, prefix),
+            None => write!(f, "{}B", self.0),
+        }
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidByteSize(s.into());
+        let trimmed = s.trim();
+        let split = trimmed
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(trimmed.len());
+        let (number, unit) = trimmed.split_at(split);
+        let number = number.parse::<u64>().map_err(|_| invalid())?;
+
+        let shift = match unit.trim().to_ascii_uppercase().as_str() {
+            "" | "B" => 0,
+            "K" | "KB" | "KIB" => 10,
+            "M" | "MB" | "MIB" => 20,
+            "G" | "GB" | "GIB" => 30,
+            "T" | "TB" | "TIB" => 40,
+            _ => return Err(invalid()),
+        };
+        number
+            .checked_mul(1 << shift)
+            .map(ByteSize)
+            .ok_or_else(invalid)
+    }
+}
+
+/// Waits for the volume of `output` to have `options.min_free_space` available before the
+/// `group` starts, so that merges running out of space fail one at a time rather than together.
+/// Warns through `progress` when pausing and checks again every `poll` until space is freed,
+/// failing with [Error::InsufficientSpace] when none is within `options.space_wait`. Returns
+/// `false` when the run deadline passed, the run was interrupted or the group cancelled while
+/// waiting.
+pub fn wait_for_space(
+    progress: &mut impl Progress,
+    group: &MovieGroup,
+    output: &Path,
+    options: &MergeOptions,
+    fs: &impl Fs,
+    clock: &impl Clock,
+    poll: Duration,
+) -> Result<bool> {
+    let started = clock.instant();
+    let mut paused = false;
+    loop {
+        let available = match fs.available_space(output) {
+            Ok(available) => available,
+            Err(err) => {
+                warn!(
+                    "not checking the free space of {} for {}: {}",
+                    output.display(),
+                    group,
+                    err
+                );
+                return Ok(true);
+            }
+        };
+        if available >= options.min_free_space.0 {
+            if paused {
+                info!(
+                    "resuming {}, {} has {} free",
+                    group,
+                    output.display(),
+                    ByteSize(available)
+                );
+            }
+            return Ok(true);
+        }
+
+        if !paused {
+            warn!(
+                "pausing {}, {} has {} free, below the {} of --min-free-space",
+                group,
+                output.display(),
+                ByteSize(available),
+                options.min_free_space
+            );
+            progress.low_space(output, available, options.min_free_space.0);
+            paused = true;
+        }
+        if options.stopping(group)
+            || options
+                .deadline
+                .map_or(false, |deadline| deadline.passed(clock.instant()))
+        {
+            return Ok(false);
+        }
+        if clock.instant() - started >= options.space_wait {
+            return Err(Error::InsufficientSpace(
+                output.to_path_buf(),
+                ByteSize(available),
+                options.min_free_space,
+                format!(
+                    "no space was freed for {} within the {} of --space-wait",
+                    group,
+                    humantime::format_duration(options.space_wait)
+                ),
+            ));
+        }
+        thread::sleep(poll);
+    }
+}
+
+/// Checks up front that the volume of `output` fits the merged movies of `groups` while keeping
+/// `margin` free, failing with the groups that wouldn't fit rather than ffmpeg failing cryptically
+/// hours into the run. Merged movies are about as large as their chapters, which are looked up
+/// in `input` when they have no directory of their own.
+pub fn check_space<'a>(
+    groups: impl IntoIterator<Item = &'a MovieGroup>,
+    input: &Path,
+    output: &Path,
+    margin: ByteSize,
+    fs: &impl Fs,
+) -> Result<()> {
+    let available = match fs.available_space(output) {
+        Ok(available) => available,
+        Err(err) => {
+            warn!(
+                "not checking the free space of {} up front: {}",
+                output.display(),
+                err
+            );
+            return Ok(());
+        }
+    };
+
+    let mut left = available.saturating_sub(margin.0);
+    let mut short = vec![];
+    for group in groups {
+        let needed = group
+            .chapters
+            .iter()
+            .map(|chapter| {
+                let path = group.chapter_path(chapter, input);
+                // unreadable chapters fail their group when merging, with a better error
+                fs.len(&path).unwrap_or_else(|err| {
+                    debug!("not sizing {}: {}", path.display(), err);
+                    0
+                })
+            })
+            .sum::<u64>();
+        match left.checked_sub(needed) {
+            Some(rest) => left = rest,
+            None => short.push(format!(
+                "{} needs {} with {} left",
+                group,
+                ByteSize(needed),
+                ByteSize(left)
+            )),
+        }
+    }
+
+    if short.is_empty() {
+        return Ok(());
+    }
+    Err(Error::InsufficientSpace(
+        output.into(),
+        ByteSize(available),
+        margin,
+        short.join(", "),
+    ))
+}
+
+/// The most the `group` stages in the workspace while merging, the list of its chapters for the
+/// concat demuxer, the subtitles, chapter markers and list of sources, each naming the chapters.
+pub fn staged_size(group: &MovieGroup, input: &Path) -> u64 {
+    group
+        .chapters
+        .iter()
+        .map(|chapter| {
+            let path = group.chapter_path(chapter, input);
+            // quoted into the concat list and listed again with the sources
+            2 * path.as_os_str().len() as u64 + STAGED_BYTES_PER_CHAPTER
+        })
+        .sum()
+}
+
+/// Checks up front that the volume of the `workspace`, e.g. a small tmpfs, fits what the largest
+/// `parallel` of the `groups` stage in it at once, rather than their merges failing with an
+/// opaque IO error as it fills up. Every merge removes its own intermediate files.
+pub fn check_workspace_space<'a>(
+    groups: impl IntoIterator<Item = &'a MovieGroup>,
+    input: &Path,
+    workspace: &Path,
+    parallel: usize,
+    fs: &impl Fs,
+) -> Result<()> {
+    let available = match fs.available_space(workspace) {
+        Ok(available) => available,
+        Err(err) => {
+            warn!(
+                "not checking the free space of the workspace {} up front: {}",
+                workspace.display(),
+                err
+            );
+            return Ok(());
+        }
+    };
+
+    let mut staged = groups
+        .into_iter()
+        .map(|group| (staged_size(group, input), group.name()))
+        .collect::<Vec<_>>();
+    // the largest first
+    staged.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    staged.truncate(parallel.max(1));
+    let needed = staged.iter().map(|(size, _)| size).sum::<u64>();
+    debug!(
+        "the workspace {} needs {} of its {} free",
+        workspace.display(),
+        ByteSize(needed),
+        ByteSize(available)
+    );
+
+    if needed <= available {
+        return Ok(());
+    }
+    Err(Error::InsufficientWorkspaceSpace(
+        workspace.into(),
+        ByteSize(available),
+        ByteSize(needed),
+        staged
+            .into_iter()
+            .map(|(size, name)| format!("{} {}", name, ByteSize(size)))
+            .collect::<Vec<_>>()
+            .join(", "),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    use crate::group::group_file_names;
+    use crate::identifier::Identifier;
+    use crate::merge::{Derived, VerifyPass};
+    use crate::movie::Fingerprint;
+    use crate::progress::{GroupError, GroupStats, Throughput, WarningKind};
+    use crate::run::Deadline;
+    use crate::system::mock::{MemoryFs, MockClock};
+
+    /// Frees up space on the volume when warned about it.
+    #[derive(Clone)]
+    struct FreeingProgress {
+        fs: MemoryFs,
+        warnings: Arc<AtomicUsize>,
+    }
+
+    impl Progress for FreeingProgress {
+        fn set_len(&mut self, _: Duration) {}
+
+        fn update(&mut self, _: Duration, _: Throughput) {}
+
+        fn probing(&mut self, _: usize, _: usize) {}
+
+        fn hashing(&mut self, _: u64, _: u64) {}
+
+        fn verifying(&mut self, _: VerifyPass, _: Duration) {}
+
+        fn deriving(&mut self, _: Derived, _: Duration) {}
+
+        fn low_space(&mut self, _: &Path, available: u64, needed: u64) {
+            assert!(available < needed);
+            self.warnings.fetch_add(1, Ordering::SeqCst);
+            self.fs.set_available_space(needed);
+        }
+
+        fn warning(&mut self, _: WarningKind, _: &str) {}
+
+        fn finish(&self, _: std::result::Result<GroupStats, GroupError>) {}
+    }
+
+    fn group() -> MovieGroup {
+        MovieGroup {
+            fingerprint: Fingerprint {
+                encoding: crate::encoding::Encoding::Avc,
+                file: Identifier::try_from("0084").unwrap(),
+                extension: "mp4".into(),
+                scheme: Default::default(),
+            },
+            chapters: vec![Identifier::try_from("01").unwrap()],
+            directories: Default::default(),
+            file_names: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_byte_size_from_str() {
+        assert_eq!(ByteSize(0), "0".parse().unwrap());
+        assert_eq!(ByteSize(512), "512".parse().unwrap());
+        assert_eq!(ByteSize(512), "512B".parse().unwrap());
+        assert_eq!(ByteSize(500 * 1024 * 1024), "500M".parse().unwrap());
+        assert_eq!(ByteSize(10 << 30), "10GiB".parse().unwrap());
+        assert_eq!(ByteSize(10 << 30), " 10 gb ".parse().unwrap());
+        assert_eq!(ByteSize(2 << 40), "2T".parse().unwrap());
+
+        for invalid in &["", "G", "1.5G", "10X", "-1", "99999999999T"] {
+            assert!(
+                invalid.parse::<ByteSize>().is_err(),
+                "{} was parsed",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_byte_size_display() {
+        assert_eq!("0B", ByteSize(0).to_string());
+        assert_eq!("1023B", ByteSize(1023).to_string());
+        assert_eq!("1.00KiB", ByteSize(1024).to_string());
+        assert_eq!("1.50GiB", ByteSize(3 << 29).to_string());
+        assert_eq!("16.00EiB", ByteSize(u64::MAX).to_string());
+    }
+
+    #[test]
+    fn test_wait_for_space() {
+        let fs = MemoryFs::default();
+        let clock = MockClock::new(SystemTime::now());
+        let output = PathBuf::from("/movies");
+        let mut progress = FreeingProgress {
+            fs: fs.clone(),
+            warnings: Default::default(),
+        };
+        let options = MergeOptions {
+            min_free_space: ByteSize(1024),
+            ..Default::default()
+        };
+
+        fs.set_available_space(1024);
+        assert!(wait_for_space(
+            &mut progress,
+            &group(),
+            &output,
+            &options,
+            &fs,
+            &clock,
+            Duration::default()
+        )
+        .unwrap());
+        assert_eq!(0, progress.warnings.load(Ordering::SeqCst));
+
+        fs.set_available_space(10);
+        assert!(wait_for_space(
+            &mut progress,
+            &group(),
+            &output,
+            &options,
+            &fs,
+            &clock,
+            Duration::default()
+        )
+        .unwrap());
+        assert_eq!(1, progress.warnings.load(Ordering::SeqCst));
+
+        // unknown free space doesn't hold up the run
+        fs.set_available_space(10);
+        fs.fail(&output);
+        assert!(wait_for_space(
+            &mut progress,
+            &group(),
+            &output,
+            &options,
+            &fs,
+            &clock,
+            Duration::default()
+        )
+        .unwrap());
+        assert_eq!(1, progress.warnings.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_check_space() {
+        let fs = MemoryFs::default();
+        let input = Path::new("/card");
+        let output = Path::new("/movies");
+        let groups = group_file_names(vec![
+            "GH010084.mp4",
+            "GH020084.mp4",
+            "GH010085.mp4",
+            "GH010086.mp4",
+        ]);
+        fs.write(&input.join("GH010084.mp4"), &[0; 300]).unwrap();
+        fs.write(&input.join("GH020084.mp4"), &[0; 300]).unwrap();
+        fs.write(&input.join("GH010085.mp4"), &[0; 500]).unwrap();
+        // GH010086.mp4 is gone, its group fails when merging rather than here
+
+        fs.set_available_space(1200);
+        check_space(&groups, input, output, ByteSize(100), &fs).unwrap();
+
+        fs.set_available_space(1000);
+        match check_space(&groups, input, output, ByteSize(100), &fs) {
+            Err(Error::InsufficientSpace(path, available, margin, short)) => {
+                assert_eq!(
+                    (output, ByteSize(1000), ByteSize(100)),
+                    (path.as_path(), available, margin)
+                );
+                assert_eq!("GH000085.mp4 needs 500B with 300B left", short);
+            }
+            result => panic!("expected the space to be short, got {:?}", result),
+        }
+        check_space(&groups[..1], input, output, ByteSize(100), &fs).unwrap();
+
+        // unknown free space doesn't hold up the run
+        fs.fail(output);
+        check_space(&groups, input, output, ByteSize(100), &fs).unwrap();
+    }
+
+    #[test]
+    fn test_check_workspace_space() {
+        let fs = MemoryFs::default();
+        let (input, workspace) = (Path::new("/card"), Path::new("/tmp/.gopro-merge"));
+        let groups = group_file_names(vec![
+            "GH010084.mp4",
+            "GH020084.mp4",
+            "GH030084.mp4",
+            "GH010085.mp4",
+            "GH010086.mp4",
+        ]);
+        // /card/GH010084.mp4 is 18 bytes
+        assert_eq!(
+            3 * (2 * 18 + STAGED_BYTES_PER_CHAPTER),
+            staged_size(&groups[0], input)
+        );
+        assert_eq!(
+            2 * 18 + STAGED_BYTES_PER_CHAPTER,
+            staged_size(&groups[1], input)
+        );
+
+        fs.set_available_space(5 * 548);
+        check_workspace_space(&groups, input, workspace, 2, &fs).unwrap();
+        // all of them at once
+        check_workspace_space(&groups, input, workspace, 3, &fs).unwrap();
+
+        fs.set_available_space(4 * 548 - 1);
+        match check_workspace_space(&groups, input, workspace, 2, &fs) {
+            Err(Error::InsufficientWorkspaceSpace(path, available, needed, largest)) => {
+                assert_eq!(
+                    (workspace, ByteSize(4 * 548 - 1), ByteSize(4 * 548)),
+                    (path.as_path(), available, needed)
+                );
+                assert_eq!("GH000084.mp4 1.61KiB, GH000085.mp4 548B", largest);
+            }
+            result => panic!("expected the workspace to be short, got {:?}", result),
+        }
+        check_workspace_space(&groups, input, workspace, 1, &fs).unwrap();
+
+        // unknown free space doesn't hold up the run
+        fs.fail(workspace);
+        check_workspace_space(&groups, input, workspace, 2, &fs).unwrap();
+    }
+
+    #[test]
+    fn test_wait_for_space_deadline() {
+        let fs = MemoryFs::default();
+        let clock = MockClock::new(SystemTime::now());
+        fs.set_available_space(10);
+        let options = MergeOptions {
+            min_free_space: ByteSize(1024),
+            deadline: Some(Deadline::after(clock.instant(), Duration::default(), false)),
+            ..Default::default()
+        };
+        // nothing frees up space
+        let mut progress = FreeingProgress {
+            fs: MemoryFs::default(),
+            warnings: Default::default(),
+        };
+
+        assert!(!wait_for_space(
+            &mut progress,
+            &group(),
+            Path::new("/movies"),
+            &options,
+            &fs,
+            &clock,
+            Duration::default()
+        )
+        .unwrap());
+        assert_eq!(1, progress.warnings.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_wait_for_space_gives_up() {
+        let fs = MemoryFs::default();
+        let clock = MockClock::new(SystemTime::now());
+        fs.set_available_space(10);
+        let options = MergeOptions {
+            min_free_space: ByteSize(1024),
+            space_wait: Duration::default(),
+            ..Default::default()
+        };
+        // nothing frees up space
+        let mut progress = FreeingProgress {
+            fs: MemoryFs::default(),
+            warnings: Default::default(),
+        };
+
+        assert!(matches!(
+            wait_for_space(
+                &mut progress,
+                &group(),
+                Path::new("/movies"),
+                &options,
+                &fs,
+                &clock,
+                Duration::default()
+            ),
+            Err(Error::InsufficientSpace(_, ByteSize(10), ByteSize(1024), _))
+        ));
+        assert_eq!(1, progress.warnings.load(Ordering::SeqCst));
+    }
+}
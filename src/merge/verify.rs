@@ -0,0 +1,73 @@
+use std::str::FromStr;
+
+use derive_more::Display;
+
+use crate::merge::{Error, Result};
+
+/// How merged movies are verified on top of comparing their duration with their chapters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum VerifyMode {
+    /// Hash the video packets of the chapters and of the merged movie, which stream copying
+    /// keeps byte for byte identical.
+    #[display(fmt = "bitexact")]
+    Bitexact,
+}
+
+impl FromStr for VerifyMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bitexact" => Ok(VerifyMode::Bitexact),
+            _ => Err(Error::InvalidVerify(s.into())),
+        }
+    }
+}
+
+/// Which side of the merge a verification pass hashes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum VerifyPass {
+    #[display(fmt = "sources")]
+    Sources,
+    #[display(fmt = "output")]
+    Output,
+}
+
+/// The video packet hashes proving a merge lossless, equal for a verified merge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    pub sources: String,
+    pub output: String,
+}
+
+/// The hex digest of the `SHA256=<hex>` line written by the ffmpeg hash muxer.
+pub(crate) fn parse_hash(contents: &str) -> Option<String> {
+    contents
+        .trim()
+        .split_once('=')
+        .filter(|(algorithm, _)| algorithm.eq_ignore_ascii_case("sha256"))
+        .map(|(_, hash)| hash.to_ascii_lowercase())
+        .filter(|hash| !hash.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_mode_from_str() {
+        assert_eq!(VerifyMode::Bitexact, "bitexact".parse().unwrap());
+        assert!("framemd5".parse::<VerifyMode>().is_err());
+    }
+
+    #[test]
+    fn test_parse_hash() {
+        assert_eq!(
+            Some("e3b0c44298fc1c149afbf4c8996fb924"),
+            parse_hash("SHA256=E3B0C44298FC1C149AFBF4C8996FB924\n").as_deref()
+        );
+        assert_eq!(None, parse_hash("MD5=d41d8cd98f00b204e9800998ecf8427e\n"));
+        assert_eq!(None, parse_hash("SHA256=\n"));
+        assert_eq!(None, parse_hash(""));
+    }
+}
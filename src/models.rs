@@ -0,0 +1,216 @@
+use std::fmt;
+
+/// How a camera names one kind of its movies, `cc` being the chapter and `xxxx` the file number
+/// in the patterns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NamingScheme {
+    pub pattern: &'static str,
+    pub description: &'static str,
+    /// Chapters of a single recording as the camera names them.
+    pub samples: &'static [&'static str],
+    /// What the samples are merged into, nothing when the scheme isn't supported.
+    pub merged: &'static [&'static str],
+}
+
+impl NamingScheme {
+    pub fn supported(&self) -> bool {
+        !self.merged.is_empty()
+    }
+}
+
+/// A GoPro camera model and the naming schemes of its movies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CameraModel {
+    pub name: &'static str,
+    pub schemes: &'static [NamingScheme],
+}
+
+// https://community.gopro.com/t5/en/GoPro-Camera-File-Naming-Convention/ta-p/390220#
+const LEGACY: NamingScheme = NamingScheme {
+    pattern: "GOPRxxxx.MP4, GPccxxxx.MP4",
+    description: "movies of older cameras, the first chapter being GOPRxxxx",
+    samples: &["GOPR0034.MP4", "GP010034.MP4", "GP020034.MP4"],
+    merged: &["GP000034.MP4"],
+};
+
+const AVC: NamingScheme = NamingScheme {
+    pattern: "GHccxxxx.MP4",
+    description: "AVC (H.264) movies",
+    samples: &["GH010035.MP4", "GH020035.MP4", "GH030035.MP4"],
+    merged: &["GH000035.MP4"],
+};
+
+const HEVC: NamingScheme = NamingScheme {
+    pattern: "GXccxxxx.MP4",
+    description: "HEVC (H.265) movies",
+    samples: &["GX010036.MP4", "GX020036.MP4"],
+    merged: &["GX000036.MP4"],
+};
+
+const LOOPING: NamingScheme = NamingScheme {
+    pattern: "GHccxxxx.MP4, GXccxxxx.MP4",
+    description: "looping movies, lettered chapters AA-ZZ",
+    samples: &["GHAA0037.MP4", "GHAB0037.MP4"],
+    merged: &["GH000037.MP4"],
+};
+
+const SPHERICAL: NamingScheme = NamingScheme {
+    pattern: "GSccxxxx.360",
+    description: "360 movies, both lenses kept as separate video tracks",
+    samples: &["GS010038.360", "GS020038.360"],
+    merged: &["GS000038.360"],
+};
+
+const FUSION: NamingScheme = NamingScheme {
+    pattern: "GPFRxxxx.MP4, GFccxxxx.MP4, GPBKxxxx.MP4, GBccxxxx.MP4",
+    description: "front and back lens movies, merged separately per lens",
+    samples: &[
+        "GPFR0039.MP4",
+        "GF010039.MP4",
+        "GPBK0039.MP4",
+        "GB010039.MP4",
+    ],
+    merged: &["GF000039.MP4", "GB000039.MP4"],
+};
+
+const HERO6_AND_NEWER: &[NamingScheme] = &[AVC, HEVC, LOOPING];
+
+/// The documented camera models, oldest first.
+pub const MODELS: &[CameraModel] = &[
+    CameraModel {
+        name: "HERO5",
+        schemes: &[LEGACY],
+    },
+    CameraModel {
+        name: "HERO6",
+        schemes: HERO6_AND_NEWER,
+    },
+    CameraModel {
+        name: "HERO7",
+        schemes: HERO6_AND_NEWER,
+    },
+    CameraModel {
+        name: "HERO8",
+        schemes: HERO6_AND_NEWER,
+    },
+    CameraModel {
+        name: "HERO9",
+        schemes: HERO6_AND_NEWER,
+    },
+    CameraModel {
+        name: "HERO10",
+        schemes: HERO6_AND_NEWER,
+    },
+    CameraModel {
+        name: "HERO11",
+        schemes: HERO6_AND_NEWER,
+    },
+    CameraModel {
+        name: "HERO12",
+        schemes: HERO6_AND_NEWER,
+    },
+    CameraModel {
+        name: "MAX",
+        schemes: &[AVC, HEVC, SPHERICAL],
+    },
+    CameraModel {
+        name: "Fusion",
+        schemes: &[FUSION],
+    },
+];
+
+/// Looks up a model by its name, ignoring case, spaces and a "Black" edition suffix, e.g.
+/// "hero9 black".
+pub fn find_model(name: &str) -> Option<&'static CameraModel> {
+    let normalize = |name: &str| {
+        name.chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_ascii_lowercase()
+    };
+    let name = normalize(name);
+    let name = name.strip_suffix("black").unwrap_or(&name);
+
+    MODELS.iter().find(|model| normalize(model.name) == name)
+}
+
+impl fmt::Display for CameraModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.name)?;
+        for scheme in self.schemes {
+            writeln!(f, "  {} - {}", scheme.pattern, scheme.description)?;
+            if scheme.supported() {
+                writeln!(
+                    f,
+                    "    merged: {} -> {}",
+                    scheme.samples.join(" "),
+                    scheme.merged.join(" ")
+                )?;
+            } else {
+                writeln!(f, "    not supported: {}", scheme.samples.join(" "))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::group::group_file_names;
+
+    #[test]
+    fn test_models_golden() {
+        for model in MODELS {
+            for scheme in model.schemes {
+                let groups = group_file_names(scheme.samples.iter().copied());
+                assert_eq!(
+                    scheme.merged,
+                    groups.iter().map(|group| group.name()).collect::<Vec<_>>(),
+                    "{} {}",
+                    model.name,
+                    scheme.pattern
+                );
+
+                if scheme.supported() {
+                    let chapters = groups
+                        .iter()
+                        .flat_map(|group| {
+                            group
+                                .chapters
+                                .iter()
+                                .map(move |chapter| group.chapter_file_name(chapter))
+                        })
+                        .collect::<Vec<_>>();
+                    assert_eq!(scheme.samples, chapters, "{}", model.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_model() {
+        assert_eq!(Some("HERO9"), find_model("HERO9").map(|model| model.name));
+        assert_eq!(
+            Some("HERO9"),
+            find_model("hero9 black").map(|model| model.name)
+        );
+        assert_eq!(
+            Some("HERO12"),
+            find_model("Hero 12").map(|model| model.name)
+        );
+        assert_eq!(Some("Fusion"), find_model("fusion").map(|model| model.name));
+        assert_eq!(None, find_model("HERO"));
+        assert_eq!(None, find_model(""));
+    }
+
+    #[test]
+    fn test_display_model() {
+        assert_eq!(
+            "MAX\n  GHccxxxx.MP4 - AVC (H.264) movies\n    merged: GH010035.MP4 GH020035.MP4 GH030035.MP4 -> GH000035.MP4\n  GXccxxxx.MP4 - HEVC (H.265) movies\n    merged: GX010036.MP4 GX020036.MP4 -> GX000036.MP4\n  GSccxxxx.360 - 360 movies, both lenses kept as separate video tracks\n    merged: GS010038.360 GS020038.360 -> GS000038.360\n",
+            find_model("max").unwrap().to_string()
+        );
+    }
+}
@@ -1,7 +1,11 @@
+use std::cmp::Ordering;
 use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 
 use crate::encoding::{self, Encoding};
 use crate::identifier::{self, Identifier};
+use crate::scheme::Scheme;
 
 use derive_more::Display;
 use thiserror::Error;
@@ -17,6 +21,11 @@ pub enum Error {
     #[error("Invalid movie chapter number 0. Non loop file numbers should be numeric in the range of 0001-9999")]
     InvalidMovieChapterNumberZero,
 
+    #[error(
+        "Invalid chapter of file {0}. Older cameras and the Fusion number their chapters 01-99"
+    )]
+    InvalidLegacyChapter(String),
+
     #[error(transparent)]
     Identifier(#[from] identifier::Error),
 
@@ -24,27 +33,80 @@ pub enum Error {
     Encoding(#[from] encoding::Error),
 }
 
-#[derive(Debug, Eq, PartialOrd, PartialEq, Ord, Hash, Clone, Display)]
-#[display(fmt = "{}00{}.{}", "encoding", "file", "extension")]
+/// Extension of a movie file, compared ignoring case so that GH011234.MP4 and GH021234.mp4 group
+/// together while the merged movie keeps the case of its chapters.
+#[derive(Debug, Clone, Display)]
+pub struct Extension(String);
+
+impl From<&str> for Extension {
+    fn from(extension: &str) -> Self {
+        Extension(extension.into())
+    }
+}
+
+impl PartialEq for Extension {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+}
+
+impl Eq for Extension {}
+
+impl Hash for Extension {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_ascii_lowercase().hash(state)
+    }
+}
+
+impl PartialOrd for Extension {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Extension {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .to_ascii_lowercase()
+            .cmp(&other.0.to_ascii_lowercase())
+    }
+}
+
+/// The movie the chapters belong to, displayed as the name of the merged movie.
+#[derive(Debug, Eq, PartialOrd, PartialEq, Ord, Hash, Clone)]
 pub struct Fingerprint {
     pub encoding: Encoding,
     pub file: Identifier,
-    pub extension: String,
+    pub extension: Extension,
+    /// Names the chapters and the merged movie, the GoPro names unless another camera's.
+    pub scheme: Scheme,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Display)]
-#[display(
-    fmt = "{}{}{}.{}",
-    "fingerprint.encoding",
-    chapter,
-    "fingerprint.file",
-    "fingerprint.extension"
-)]
+impl Fingerprint {
+    /// File name of the chapter of the movie as its scheme names it.
+    pub fn chapter_file_name(&self, chapter: &Identifier) -> String {
+        self.scheme.chapter_file_name(self, chapter)
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.scheme.movie_file_name(self))
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord)]
 pub struct Movie {
     pub fingerprint: Fingerprint,
     pub chapter: Identifier,
 }
 
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.fingerprint.chapter_file_name(&self.chapter))
+    }
+}
+
 impl<'a> TryFrom<&'a str> for Movie {
     type Error = Error;
 
@@ -55,26 +117,43 @@ impl<'a> TryFrom<&'a str> for Movie {
         let invalid_file_name_error = |name: &'a str| || Error::InvalidFileName(name.into());
         let ext = iter.next().ok_or_else(invalid_file_name_error(name))?;
         let name = iter.next().ok_or_else(invalid_file_name_error(name))?;
-        if name.len() != 8 {
+        if name.len() != 8 || !name.is_ascii() {
             return Err(Error::InvalidFileName(name.into()));
         }
+        // SD cards name the movies GH011234.MP4 while some tools lowercase them, the names are
+        // normalized to uppercase and the extensions compared ignoring case so they group together
+        let name = &name.to_ascii_uppercase();
 
-        let encoding = Encoding::try_from(name)?;
+        let encoding = Encoding::try_from(name.as_str())?;
         let file = Identifier::try_from(&name[4..])?;
         if let Ok(0) = file.numeric() {
             return Err(Error::InvalidMovieFileNumberZero);
         }
 
-        let chapter = Identifier::try_from(&name[2..4])?;
-        if let Ok(0) = chapter.numeric() {
-            return Err(Error::InvalidMovieChapterNumberZero);
-        }
+        let first_chapter = encoding
+            .first_chapter_prefix()
+            .map_or(false, |prefix| name.starts_with(prefix));
+        let chapter = if first_chapter {
+            Identifier::try_from("00")?
+        } else {
+            let chapter = Identifier::try_from(&name[2..4])?;
+            match chapter.numeric() {
+                Ok(0) => return Err(Error::InvalidMovieChapterNumberZero),
+                // looping chapters are lettered only on newer cameras
+                Err(_) if encoding.first_chapter_prefix().is_some() => {
+                    return Err(Error::InvalidLegacyChapter(name.clone()))
+                }
+                _ => {}
+            }
+            chapter
+        };
 
         let movie = Movie {
             fingerprint: Fingerprint {
                 encoding,
                 file,
                 extension: ext.into(),
+                scheme: Scheme::default(),
             },
             chapter,
         };
@@ -97,6 +176,7 @@ mod tests {
                         encoding: Encoding::Avc,
                         file: Identifier::try_from("0034").unwrap(),
                         extension: "mp4".into(),
+                        scheme: Default::default(),
                     },
                     chapter: Identifier::try_from("01").unwrap(),
                 },
@@ -108,6 +188,7 @@ mod tests {
                         encoding: Encoding::Hevc,
                         file: Identifier::try_from("1134").unwrap(),
                         extension: "flv".into(),
+                        scheme: Default::default(),
                     },
                     chapter: Identifier::try_from("11").unwrap(),
                 },
@@ -119,10 +200,71 @@ mod tests {
                         encoding: Encoding::Avc,
                         file: Identifier::try_from("0001").unwrap(),
                         extension: "mp4".into(),
+                        scheme: Default::default(),
                     },
                     chapter: Identifier::try_from("AA").unwrap(),
                 },
             ),
+            (
+                "GOPR0034.MP4",
+                Movie {
+                    fingerprint: Fingerprint {
+                        encoding: Encoding::Legacy,
+                        file: Identifier::try_from("0034").unwrap(),
+                        extension: "MP4".into(),
+                        scheme: Default::default(),
+                    },
+                    chapter: Identifier::try_from("00").unwrap(),
+                },
+            ),
+            (
+                "GP010034.MP4",
+                Movie {
+                    fingerprint: Fingerprint {
+                        encoding: Encoding::Legacy,
+                        file: Identifier::try_from("0034").unwrap(),
+                        extension: "MP4".into(),
+                        scheme: Default::default(),
+                    },
+                    chapter: Identifier::try_from("01").unwrap(),
+                },
+            ),
+            (
+                "GPFR0034.MP4",
+                Movie {
+                    fingerprint: Fingerprint {
+                        encoding: Encoding::FusionFront,
+                        file: Identifier::try_from("0034").unwrap(),
+                        extension: "MP4".into(),
+                        scheme: Default::default(),
+                    },
+                    chapter: Identifier::try_from("00").unwrap(),
+                },
+            ),
+            (
+                "GB010034.MP4",
+                Movie {
+                    fingerprint: Fingerprint {
+                        encoding: Encoding::FusionBack,
+                        file: Identifier::try_from("0034").unwrap(),
+                        extension: "MP4".into(),
+                        scheme: Default::default(),
+                    },
+                    chapter: Identifier::try_from("01").unwrap(),
+                },
+            ),
+            (
+                "GL020034.mp4",
+                Movie {
+                    fingerprint: Fingerprint {
+                        encoding: Encoding::Loop,
+                        file: Identifier::try_from("0034").unwrap(),
+                        extension: "mp4".into(),
+                        scheme: Default::default(),
+                    },
+                    chapter: Identifier::try_from("02").unwrap(),
+                },
+            ),
         ];
         ok_input.into_iter().for_each(|(input, expected)| {
             let parsed = Movie::try_from(input).unwrap();
@@ -131,6 +273,30 @@ mod tests {
         });
     }
 
+    #[test]
+    fn movie_try_from_case_insensitive() {
+        let expected = Movie::try_from("GH011234.mp4").unwrap();
+        vec!["GH011234.MP4", "gh011234.mp4", "gH011234.Mp4"]
+            .into_iter()
+            .for_each(|input| {
+                let parsed = Movie::try_from(input).unwrap();
+                assert_eq!(expected, parsed, "{}", input);
+                assert_eq!(
+                    format!("GH011234.{}", &input[9..]),
+                    parsed.to_string(),
+                    "{}",
+                    input
+                );
+            });
+
+        let looping = Movie::try_from("ghaa0001.mp4").unwrap();
+        assert_eq!(Movie::try_from("GHAA0001.MP4").unwrap(), looping);
+        assert_eq!(
+            Movie::try_from("GOPR0034.MP4").unwrap(),
+            Movie::try_from("gopr0034.mp4").unwrap()
+        );
+    }
+
     #[test]
     fn movie_try_from_err() {
         let not_ok_input = vec![
@@ -145,6 +311,13 @@ mod tests {
             "GX000000.mp4",
             "GH010000.mp4",
             "GH000001.mp4",
+            "GP000034.mp4",
+            "GOPR0000.mp4",
+            "GPXX0034.MP4",
+            "GFAA0034.MP4",
+            "GF000034.MP4",
+            "gy111134.flv",
+            "GHé0001.mp4",
         ];
         not_ok_input.into_iter().for_each(|input| {
             assert!(Movie::try_from(input).is_err(), "{} isn't error", input,);
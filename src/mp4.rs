@@ -0,0 +1,369 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+pub type BoxType = [u8; 4];
+
+const HEADER_LEN: u64 = 8;
+const LARGE_HEADER_LEN: u64 = 16;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct BoxHeader {
+    pub kind: BoxType,
+    pub offset: u64,
+    pub size: u64,
+    pub header_len: u64,
+}
+
+impl BoxHeader {
+    pub fn payload_offset(&self) -> u64 {
+        self.offset + self.header_len
+    }
+
+    pub fn payload_len(&self) -> u64 {
+        self.size - self.header_len
+    }
+
+    pub fn end(&self) -> u64 {
+        self.offset + self.size
+    }
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// Reads the box header at `offset`, `end` being the end of the enclosing box or the file.
+pub fn read_header<R: Read + Seek>(
+    r: &mut R,
+    offset: u64,
+    end: u64,
+) -> io::Result<Option<BoxHeader>> {
+    if offset.checked_add(HEADER_LEN).map_or(true, |e| e > end) {
+        return Ok(None);
+    }
+
+    r.seek(SeekFrom::Start(offset))?;
+    let size = read_u32(r)? as u64;
+    let mut kind = [0u8; 4];
+    r.read_exact(&mut kind)?;
+
+    let (size, header_len) = match size {
+        0 => (end - offset, HEADER_LEN),
+        1 => (read_u64(r)?, LARGE_HEADER_LEN),
+        size => (size, HEADER_LEN),
+    };
+
+    if size < header_len || offset.checked_add(size).map_or(true, |e| e > end) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "invalid size {} of box {} at offset {}",
+                size,
+                String::from_utf8_lossy(&kind),
+                offset
+            ),
+        ));
+    }
+
+    Ok(Some(BoxHeader {
+        kind,
+        offset,
+        size,
+        header_len,
+    }))
+}
+
+/// Returns the headers of all direct children between `offset` and `end`.
+pub fn children<R: Read + Seek>(r: &mut R, offset: u64, end: u64) -> io::Result<Vec<BoxHeader>> {
+    let mut headers = vec![];
+    let mut offset = offset;
+    while let Some(header) = read_header(r, offset, end)? {
+        offset = header.end();
+        headers.push(header);
+    }
+
+    Ok(headers)
+}
+
+/// Finds the box at `path`, e.g. `[b"moov", b"udta"]`, starting from the top level of the stream.
+pub fn find_box<R: Read + Seek>(r: &mut R, path: &[&BoxType]) -> io::Result<Option<BoxHeader>> {
+    let end = r.seek(SeekFrom::End(0))?;
+    find_box_in(r, 0, end, path)
+}
+
+/// Finds the box at `path` among the boxes between `offset` and `end`, e.g. the children of a
+/// `trak` box.
+pub fn find_box_in<R: Read + Seek>(
+    r: &mut R,
+    mut offset: u64,
+    mut end: u64,
+    path: &[&BoxType],
+) -> io::Result<Option<BoxHeader>> {
+    let mut found = None;
+
+    for kind in path {
+        found = children(r, offset, end)?
+            .into_iter()
+            .find(|header| &header.kind == *kind);
+
+        match found {
+            Some(header) => {
+                offset = header.payload_offset();
+                end = header.end();
+            }
+            None => return Ok(None),
+        }
+    }
+
+    Ok(found)
+}
+
+/// Reads the payload of the box at `path`, see [find_box].
+pub fn read_box<R: Read + Seek>(r: &mut R, path: &[&BoxType]) -> io::Result<Option<Vec<u8>>> {
+    find_box(r, path)?
+        .map(|header| read_payload(r, &header))
+        .transpose()
+}
+
+/// Reads the payload of the box of the `header`.
+pub fn read_payload<R: Read + Seek>(r: &mut R, header: &BoxHeader) -> io::Result<Vec<u8>> {
+    r.seek(SeekFrom::Start(header.payload_offset()))?;
+    let mut payload = vec![0u8; header.payload_len() as usize];
+    r.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+/// Cheap check whether the file starts with an ISO base media `ftyp` box, which every GoPro
+/// movie does, so renamed non movie files can be skipped without spawning ffprobe for them.
+pub fn has_ftyp_signature(path: &Path) -> io::Result<bool> {
+    let mut header = [0u8; HEADER_LEN as usize];
+    let mut file = File::open(path)?;
+    match file.read_exact(&mut header) {
+        Ok(_) => Ok(&header[4..] == b"ftyp"),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Why a movie looks cut short, the signature of the battery dying while recording: the camera
+/// writes the samples first and the `moov` index of them only when the recording stops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Truncation {
+    /// The file ends without the index, players and ffmpeg can't read it.
+    MissingIndex,
+    /// The file ends within a box, the message telling which.
+    CutShort(String),
+}
+
+impl fmt::Display for Truncation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Truncation::MissingIndex => write!(f, "it has no moov index"),
+            Truncation::CutShort(box_error) => write!(f, "it ends early, {}", box_error),
+        }
+    }
+}
+
+/// Looks at the top level boxes of the movie at `path` for signs of it being truncated, none for
+/// files which aren't ISO base media, e.g. MPEG-TS chapters.
+pub fn find_truncation(path: &Path) -> io::Result<Option<Truncation>> {
+    if !has_ftyp_signature(path)? {
+        return Ok(None);
+    }
+    truncation(&mut BufReader::new(File::open(path)?))
+}
+
+fn truncation<R: Read + Seek>(r: &mut R) -> io::Result<Option<Truncation>> {
+    let end = r.seek(SeekFrom::End(0))?;
+    match children(r, 0, end) {
+        Ok(boxes) if boxes.iter().any(|header| &header.kind == b"moov") => Ok(None),
+        Ok(_) => Ok(Some(Truncation::MissingIndex)),
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            Ok(Some(Truncation::CutShort(err.to_string())))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Whether the movie at `path` has a sound track, e.g. not for timelapses. Files which aren't ISO
+/// base media, e.g. MPEG-TS chapters, are taken to have one.
+pub fn has_sound_track(path: &Path) -> io::Result<bool> {
+    if !has_ftyp_signature(path)? {
+        return Ok(true);
+    }
+    sound_track(&mut BufReader::new(File::open(path)?))
+}
+
+fn sound_track<R: Read + Seek>(r: &mut R) -> io::Result<bool> {
+    let moov = match find_box(r, &[b"moov"])? {
+        Some(moov) => moov,
+        None => return Ok(false),
+    };
+    let traks = children(r, moov.payload_offset(), moov.end())?
+        .into_iter()
+        .filter(|header| &header.kind == b"trak");
+    for trak in traks {
+        let hdlr = find_box_in(r, trak.payload_offset(), trak.end(), &[b"mdia", b"hdlr"])?;
+        if let Some(hdlr) = hdlr {
+            // after the version, flags and a reserved field
+            if read_payload(r, &hdlr)?.get(8..12) == Some(&b"soun"[..]) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Reads the HiLight tags stored by the camera in the `moov/udta/HMMT` box
+/// as offsets from the beginning of the file.
+pub fn read_hilights(path: &Path) -> io::Result<Vec<Duration>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let payload = match read_box(&mut reader, &[b"moov", b"udta", b"HMMT"])? {
+        Some(payload) => payload,
+        None => return Ok(vec![]),
+    };
+
+    Ok(parse_hilights(&payload))
+}
+
+fn parse_hilights(payload: &[u8]) -> Vec<Duration> {
+    let mut values = payload
+        .chunks_exact(4)
+        .map(|chunk| u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+
+    let count = values.next().unwrap_or_default() as usize;
+    values
+        .take(count)
+        .map(|millis| Duration::from_millis(millis as u64))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn mp4_box(kind: &BoxType, payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn test_sound_track() {
+        let trak = |handler: &BoxType| {
+            let hdlr = mp4_box(b"hdlr", &[&[0; 8][..], handler, &[0; 12]].concat());
+            mp4_box(b"trak", &mp4_box(b"mdia", &hdlr))
+        };
+        let movie = |traks: Vec<Vec<u8>>| {
+            let moov = mp4_box(b"moov", &traks.concat());
+            Cursor::new([mp4_box(b"ftyp", b"mp41"), moov].concat())
+        };
+
+        assert!(sound_track(&mut movie(vec![trak(b"vide"), trak(b"soun")])).unwrap());
+        assert!(!sound_track(&mut movie(vec![trak(b"vide"), trak(b"meta")])).unwrap());
+        assert!(!sound_track(&mut movie(vec![])).unwrap());
+    }
+
+    #[test]
+    fn test_find_box() {
+        let hmmt = mp4_box(b"HMMT", &[0, 0, 0, 2, 0, 0, 3, 232, 0, 0, 7, 208]);
+        let udta = mp4_box(b"udta", &hmmt);
+        let moov = mp4_box(b"moov", &[mp4_box(b"mvhd", &[0; 4]), udta].concat());
+        let file = [mp4_box(b"ftyp", b"mp41"), mp4_box(b"mdat", &[1; 16]), moov].concat();
+
+        let mut cursor = Cursor::new(file);
+        let payload = read_box(&mut cursor, &[b"moov", b"udta", b"HMMT"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            vec![Duration::from_secs(1), Duration::from_secs(2)],
+            parse_hilights(&payload)
+        );
+
+        assert!(read_box(&mut cursor, &[b"moov", b"trak"])
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            b"mp41".to_vec(),
+            read_box(&mut cursor, &[b"ftyp"]).unwrap().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_find_box_invalid_size() {
+        let mut file = mp4_box(b"moov", &[0; 8]);
+        file[3] = 200;
+        assert!(find_box(&mut Cursor::new(file), &[b"moov"]).is_err());
+
+        // a large size overflowing the offset of the box
+        let mut file = mp4_box(b"free", &[]);
+        file.extend([&1u32.to_be_bytes()[..], b"mdat", &u64::MAX.to_be_bytes()].concat());
+        assert!(find_box(&mut Cursor::new(file), &[b"mdat"]).is_err());
+    }
+
+    #[test]
+    fn test_truncation() {
+        let ftyp = mp4_box(b"ftyp", b"mp41");
+        let mdat = mp4_box(b"mdat", &[1; 16]);
+        let moov = mp4_box(b"moov", &mp4_box(b"mvhd", &[0; 4]));
+        let truncation_of = |file: Vec<u8>| truncation(&mut Cursor::new(file)).unwrap();
+
+        assert_eq!(None, truncation_of([&ftyp[..], &mdat, &moov].concat()));
+        assert_eq!(
+            Some(Truncation::MissingIndex),
+            truncation_of([&ftyp[..], &mdat].concat())
+        );
+        // the battery died while the samples were written
+        let cut = [&ftyp, &mdat[..12]].concat();
+        assert_eq!(
+            Some(Truncation::CutShort(
+                "invalid size 24 of box mdat at offset 12".into()
+            )),
+            truncation_of(cut)
+        );
+        assert_eq!(
+            "it ends early, invalid size 24 of box mdat at offset 12",
+            Truncation::CutShort("invalid size 24 of box mdat at offset 12".into()).to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_hilights() {
+        assert!(parse_hilights(&[]).is_empty());
+        assert!(parse_hilights(&[0, 0, 0, 0]).is_empty());
+        // count larger than the actual amount of tags
+        assert_eq!(
+            vec![Duration::from_millis(10)],
+            parse_hilights(&[0, 0, 0, 5, 0, 0, 0, 10])
+        );
+    }
+
+    #[test]
+    fn test_has_ftyp_signature() {
+        assert!(has_ftyp_signature(Path::new("tests/GH010084.mp4")).unwrap());
+        assert!(!has_ftyp_signature(Path::new("Cargo.toml")).unwrap());
+        assert!(!has_ftyp_signature(Path::new("LICENSE")).unwrap());
+        assert!(has_ftyp_signature(Path::new("tests/missing.mp4")).is_err());
+    }
+
+    #[test]
+    fn test_read_hilights_without_tags() {
+        assert!(read_hilights(Path::new("tests/GH010084.mp4"))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,513 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use log::*;
+use thiserror::Error;
+
+use crate::group::MovieGroup;
+use crate::locale::{DateFormat, Locale};
+use crate::merge::OutputContainer;
+use crate::system::{Clock, Fs};
+use crate::timestamp;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid --name-template {0}, {1}")]
+    InvalidNameTemplate(String, String),
+
+    #[error("Invalid --title-template {0}, {1}")]
+    InvalidTitleTemplate(String, String),
+
+    #[error("--name-template names both {1} and {2} as {0}, add {{file}} to tell them apart")]
+    DuplicateName(String, String, String),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Rendered for the date and time of groups whose recording time is unknown.
+const UNKNOWN: &str = "unknown";
+
+/// A value of the merged movie name, as `{token}` in a [NameTemplate].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    /// The default name without its extension, e.g. GH000084.
+    Name,
+    /// The encoding prefix of the chapters, e.g. GH.
+    Encoding,
+    /// The file number, e.g. 0084.
+    File,
+    /// The extension of the chapters as found on disk, e.g. MP4.
+    Ext,
+    /// The recording date of the first chapter as the camera clock showed it, e.g. 2021-11-14,
+    /// or formatted like `{date:%d %B %Y}`.
+    Date,
+    /// The recording time of the first chapter as the camera clock showed it, e.g. 231525.
+    Time,
+    /// How many chapters are merged.
+    Chapters,
+}
+
+const TOKENS: &[(&str, Token)] = &[
+    ("name", Token::Name),
+    ("encoding", Token::Encoding),
+    ("file", Token::File),
+    ("ext", Token::Ext),
+    ("date", Token::Date),
+    ("time", Token::Time),
+    ("chapters", Token::Chapters),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Token(Token),
+    /// A `{date:FORMAT}` token.
+    FormattedDate(DateFormat),
+}
+
+/// The segments of a template, failing with the reason it's invalid.
+fn parse_segments(s: &str) -> std::result::Result<Vec<Segment>, String> {
+    let mut segments = vec![];
+    let mut rest = s;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            segments.push(Segment::Literal(rest[..start].into()));
+        }
+        let end = rest[start..].find('}').ok_or("a { isn't closed")?;
+        let (name, format) = match rest[start + 1..start + end].split_once(':') {
+            Some((name, format)) => (name, Some(format)),
+            None => (&rest[start + 1..start + end], None),
+        };
+        let token = TOKENS
+            .iter()
+            .find(|(token, _)| *token == name)
+            .map(|(_, token)| *token)
+            .ok_or_else(|| {
+                format!(
+                    "unknown token {{{}}}, one of {}",
+                    name,
+                    TOKENS
+                        .iter()
+                        .map(|(token, _)| format!("{{{}}}", token))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                )
+            })?;
+        segments.push(match (token, format) {
+            (Token::Date, Some(format)) => {
+                Segment::FormattedDate(format.parse().map_err(|err| format!("{}", err))?)
+            }
+            (_, Some(_)) => {
+                return Err(format!(
+                    "only {{date}} takes a format, e.g. {{date:%d %B %Y}}, not {{{}}}",
+                    name
+                ))
+            }
+            (token, None) => Segment::Token(token),
+        });
+        rest = &rest[start + end + 1..];
+    }
+    if rest.contains('}') {
+        return Err("a } isn't opened".into());
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest.into()));
+    }
+
+    Ok(segments)
+}
+
+/// Renders the `segments` for the `group` whose first chapter was recorded at `time`.
+fn render_segments(
+    segments: &[Segment],
+    group: &MovieGroup,
+    time: Option<SystemTime>,
+    locale: Locale,
+) -> String {
+    // e.g. 2021-11-14T23:15:25Z
+    let timestamp = time.map(|time| humantime::format_rfc3339_seconds(time).to_string());
+    let fingerprint = &group.fingerprint;
+
+    segments
+        .iter()
+        .map(|segment| match segment {
+            Segment::Literal(literal) => literal.clone(),
+            Segment::Token(Token::Name) => {
+                format!("{}00{}", fingerprint.encoding, fingerprint.file)
+            }
+            Segment::Token(Token::Encoding) => fingerprint.encoding.to_string(),
+            Segment::Token(Token::File) => fingerprint.file.to_string(),
+            Segment::Token(Token::Ext) => fingerprint.extension.to_string(),
+            Segment::Token(Token::Date) => timestamp
+                .as_ref()
+                .map_or(UNKNOWN.into(), |timestamp| timestamp[..10].into()),
+            Segment::Token(Token::Time) => timestamp.as_ref().map_or(UNKNOWN.into(), |timestamp| {
+                timestamp[11..19].replace(':', "")
+            }),
+            Segment::Token(Token::Chapters) => group.chapters.len().to_string(),
+            Segment::FormattedDate(format) => {
+                time.map_or(UNKNOWN.into(), |time| format.render(time, locale))
+            }
+        })
+        .collect()
+}
+
+fn uses_time(segments: &[Segment]) -> bool {
+    segments.iter().any(|segment| {
+        matches!(
+            segment,
+            Segment::Token(Token::Date) | Segment::Token(Token::Time) | Segment::FormattedDate(_)
+        )
+    })
+}
+
+/// How merged movies are named, e.g. `{date}_{file}_{encoding}_merged.{ext}`, from
+/// `--name-template`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl fmt::Display for NameTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidNameTemplate(s.into(), reason.into());
+        if s.trim().is_empty() {
+            return Err(invalid("it's empty"));
+        }
+        if s.contains(|c: char| c == '/' || c == '\\' || c.is_control()) {
+            return Err(invalid("names can't contain path separators"));
+        }
+
+        let segments = parse_segments(s).map_err(|reason| invalid(&reason))?;
+
+        Ok(NameTemplate {
+            template: s.into(),
+            segments,
+        })
+    }
+}
+
+impl NameTemplate {
+    /// The name of the merged movie of the `group` whose first chapter was recorded at `time`,
+    /// with the month and day names of `{date:FORMAT}` in the language of the `locale`.
+    pub fn render(&self, group: &MovieGroup, time: Option<SystemTime>, locale: Locale) -> String {
+        render_segments(&self.segments, group, time, locale)
+    }
+}
+
+/// The title embedded into the metadata of the merged movies, e.g. `GoPro {date:%d %B %Y}`, for
+/// media centers to show instead of the file name, from `--title-template`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TitleTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl fmt::Display for TitleTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+impl FromStr for TitleTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidTitleTemplate(s.into(), reason.into());
+        if s.trim().is_empty() {
+            return Err(invalid("it's empty"));
+        }
+        if s.contains(char::is_control) {
+            return Err(invalid("titles can't contain control characters"));
+        }
+
+        Ok(TitleTemplate {
+            template: s.into(),
+            segments: parse_segments(s).map_err(|reason| invalid(&reason))?,
+        })
+    }
+}
+
+impl TitleTemplate {
+    /// The title of the merged movie of the `group` whose first chapter was recorded at `time`.
+    pub fn render(&self, group: &MovieGroup, time: Option<SystemTime>, locale: Locale) -> String {
+        render_segments(&self.segments, group, time, locale)
+    }
+}
+
+/// The names of the merged movies of a run, keyed by group name. Groups without one are named
+/// after their chapters, e.g. GH000084.MP4.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputNames {
+    pub groups: BTreeMap<String, String>,
+    /// The titles embedded into the merged movies, keyed by group name, none keeping the title of
+    /// the first chapter.
+    pub titles: BTreeMap<String, String>,
+    /// The --container the merged movies are renamed to, none keeping the extension of their
+    /// chapters.
+    pub container: Option<OutputContainer>,
+}
+
+impl OutputNames {
+    /// Names and titles the `groups` found in `input` with the `template` and `title`, failing
+    /// when two of them would be merged into the same file.
+    pub fn render(
+        groups: &[MovieGroup],
+        input: &Path,
+        template: Option<&NameTemplate>,
+        title: Option<&TitleTemplate>,
+        locale: Locale,
+        fs: &impl Fs,
+        clock: &impl Clock,
+    ) -> Result<Self> {
+        let uses_time = template.map_or(false, |template| uses_time(&template.segments))
+            || title.map_or(false, |title| uses_time(&title.segments));
+
+        let mut names = OutputNames::default();
+        let mut named = BTreeMap::<String, String>::new();
+        for group in groups {
+            // the container creation time is known only after probing, the name is needed before
+            let time = match group.chapters.first() {
+                Some(chapter) if uses_time => {
+                    timestamp::wall_clock(&group.chapter_path(chapter, input), fs, clock)
+                }
+                _ => None,
+            };
+
+            if let Some(template) = template {
+                let name = template.render(group, time, locale);
+                debug!("naming {} {}", group, name);
+                if let Some(other) = named.insert(name.to_lowercase(), group.name()) {
+                    return Err(Error::DuplicateName(name, other, group.name()));
+                }
+                names.groups.insert(group.name(), name);
+            }
+            if let Some(title) = title {
+                let title = title.render(group, time, locale);
+                debug!("titling {} {}", group, title);
+                names.titles.insert(group.name(), title);
+            }
+        }
+
+        Ok(names)
+    }
+
+    /// The file name of the merged movie of the `group`.
+    pub fn of(&self, group: &MovieGroup) -> String {
+        let name = self
+            .groups
+            .get(&group.name())
+            .cloned()
+            .unwrap_or_else(|| group.name());
+        match self.container {
+            Some(container) => container.rename(&name),
+            None => name,
+        }
+    }
+
+    /// The title of the merged movie of the `group`, if there's a --title-template.
+    pub fn title(&self, group: &MovieGroup) -> Option<String> {
+        self.titles.get(&group.name()).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::group::group_file_names;
+    use crate::system::mock::{MemoryFs, MockClock};
+
+    fn time(rfc3339: &str) -> SystemTime {
+        humantime::parse_rfc3339(rfc3339).unwrap()
+    }
+
+    #[test]
+    fn test_name_template_from_str() {
+        let template = "{date}_{file}_{encoding}_merged.{ext}"
+            .parse::<NameTemplate>()
+            .unwrap();
+        assert_eq!(
+            "{date}_{file}_{encoding}_merged.{ext}",
+            template.to_string()
+        );
+        assert_eq!(
+            vec![
+                Segment::Token(Token::Date),
+                Segment::Literal("_".into()),
+                Segment::Token(Token::File),
+                Segment::Literal("_".into()),
+                Segment::Token(Token::Encoding),
+                Segment::Literal("_merged.".into()),
+                Segment::Token(Token::Ext),
+            ],
+            template.segments
+        );
+
+        for invalid in &[
+            "",
+            "{date",
+            "date}.mp4",
+            "{day}.mp4",
+            "{}.mp4",
+            "rides/{file}.mp4",
+            r"rides\{file}.mp4",
+            "{date:%Q}.mp4",
+            "{file:%d}.mp4",
+        ] {
+            assert!(
+                invalid.parse::<NameTemplate>().is_err(),
+                "{} was parsed",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_name_template_render() {
+        let groups = group_file_names(vec!["GH010084.MP4", "GH020084.MP4", "GOPR0034.mp4"]);
+        let recorded = Some(time("2021-11-14T23:15:25Z"));
+        let render = |template: &str, group: &MovieGroup, time: Option<SystemTime>| {
+            template
+                .parse::<NameTemplate>()
+                .unwrap()
+                .render(group, time, Locale::English)
+        };
+
+        assert_eq!(
+            "2021-11-14_0084_GH_merged.MP4",
+            render(
+                "{date}_{file}_{encoding}_merged.{ext}",
+                &groups[0],
+                recorded
+            )
+        );
+        assert_eq!(
+            "GH000084 231525 2 chapters.MP4",
+            render(
+                "{name} {time} {chapters} chapters.{ext}",
+                &groups[0],
+                recorded
+            )
+        );
+        assert_eq!(
+            "unknown_GP000034.mp4",
+            render("{date}_{name}.{ext}", &groups[1], None)
+        );
+        // the default name
+        assert_eq!(
+            groups[1].name(),
+            render("{encoding}00{file}.{ext}", &groups[1], None)
+        );
+        assert_eq!(
+            "14 November 2021 0084.MP4",
+            render("{date:%e %B %Y} {file}.{ext}", &groups[0], recorded)
+        );
+        assert_eq!(
+            "unknown 0034.mp4",
+            render("{date:%e %B %Y} {file}.{ext}", &groups[1], None)
+        );
+    }
+
+    #[test]
+    fn test_title_template() {
+        let groups = group_file_names(vec!["GH010084.MP4", "GH020084.MP4"]);
+        let title = "GoPro {date:%A, %e. %B %Y}, {chapters} chapters/{name}"
+            .parse::<TitleTemplate>()
+            .unwrap();
+        assert_eq!(
+            "GoPro Sonntag, 14. November 2021, 2 chapters/GH000084",
+            title.render(
+                &groups[0],
+                Some(time("2021-11-14T23:15:25Z")),
+                Locale::German
+            )
+        );
+
+        for invalid in &["", " ", "{date", "{day}", "{date:%Q}", "GoPro\n{date}"] {
+            assert!(
+                invalid.parse::<TitleTemplate>().is_err(),
+                "{} was parsed",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_output_names_render() {
+        let input = PathBuf::from("/movies");
+        let groups = group_file_names(vec!["GH010084.MP4", "GH010085.MP4"]);
+        let fs = MemoryFs::default();
+        let clock = MockClock::new(time("2022-01-01T00:00:00Z"));
+        fs.set_modified(&input.join("GH010084.MP4"), time("2021-11-14T23:15:25Z"));
+        fs.set_modified(&input.join("GH010085.MP4"), time("2021-11-15T08:00:00Z"));
+
+        let template = "{date}_{file}.{ext}".parse().unwrap();
+        let names = OutputNames::render(
+            &groups,
+            &input,
+            Some(&template),
+            None,
+            Locale::English,
+            &fs,
+            &clock,
+        )
+        .unwrap();
+        assert_eq!("2021-11-14_0084.MP4", names.of(&groups[0]));
+        assert_eq!("2021-11-15_0085.MP4", names.of(&groups[1]));
+        assert_eq!(
+            "GH000086.MP4",
+            names.of(&group_file_names(vec!["GH010086.MP4"])[0])
+        );
+        assert_eq!("GH000084.MP4", OutputNames::default().of(&groups[0]));
+        assert_eq!(None, names.title(&groups[0]));
+        let names = OutputNames {
+            container: Some(OutputContainer::Mkv),
+            ..names
+        };
+        assert_eq!("2021-11-14_0084.mkv", names.of(&groups[0]));
+
+        let title = "{date:%e %B %Y}".parse().unwrap();
+        let names = OutputNames::render(
+            &groups,
+            &input,
+            None,
+            Some(&title),
+            Locale::French,
+            &fs,
+            &clock,
+        )
+        .unwrap();
+        assert_eq!("GH000085.MP4", names.of(&groups[1]));
+        assert_eq!(Some("15 novembre 2021".into()), names.title(&groups[1]));
+
+        fs.set_modified(&input.join("GH010085.MP4"), time("2021-11-14T08:00:00Z"));
+        let template = "{date}.{ext}".parse().unwrap();
+        assert!(matches!(
+            OutputNames::render(
+                &groups,
+                &input,
+                Some(&template),
+                None,
+                Locale::English,
+                &fs,
+                &clock
+            ),
+            Err(Error::DuplicateName(name, ..)) if name == "2021-11-14.MP4"
+        ));
+    }
+}
@@ -0,0 +1,155 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid path mapping {0}, expected <from>=<to>, e.g. C:\\footage=/mnt/footage")]
+    InvalidMapping(String),
+}
+
+/// Remaps the `from` base path, written on another platform, onto the local `to` path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathMapping {
+    from: String,
+    to: PathBuf,
+}
+
+impl FromStr for PathMapping {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((from, to)) if !from.is_empty() && !to.is_empty() => Ok(PathMapping {
+                from: normalize(from),
+                to: to.into(),
+            }),
+            _ => Err(Error::InvalidMapping(s.into())),
+        }
+    }
+}
+
+/// The path mappings of a run, the first one matching a path wins.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathMap(pub Vec<PathMapping>);
+
+impl PathMap {
+    /// Remaps `path` if it's under any of the mapped base paths, with either separator and only
+    /// the drive letters compared case insensitively, otherwise it's returned as is.
+    pub fn remap(&self, path: &Path) -> PathBuf {
+        let normalized = normalize(&path.to_string_lossy());
+        self.0
+            .iter()
+            .find_map(|mapping| {
+                let rest = strip_prefix_ignore_drive_case(&normalized, &mapping.from)?;
+                if !rest.is_empty() && !rest.starts_with('/') && !mapping.from.ends_with('/') {
+                    return None;
+                }
+
+                Some(
+                    rest.split('/')
+                        .filter(|component| !component.is_empty())
+                        .fold(mapping.to.clone(), |path, component| path.join(component)),
+                )
+            })
+            .unwrap_or_else(|| path.to_path_buf())
+    }
+}
+
+fn normalize(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    match path.trim_end_matches('/') {
+        // the root itself, e.g. / or C:/
+        "" => "/".into(),
+        trimmed if trimmed.len() == 2 && trimmed.ends_with(':') => format!("{}/", trimmed),
+        trimmed => trimmed.into(),
+    }
+}
+
+/// Strips `prefix` off `path` with the case of their `X:` drive letters folded, Windows writing
+/// them either way, the rest of the path being case sensitive on the local side.
+fn strip_prefix_ignore_drive_case<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let drive =
+        |path: &str| matches!(path.as_bytes(), [letter, b':', ..] if letter.is_ascii_alphabetic());
+    if drive(path) && drive(prefix) {
+        if !path[..1].eq_ignore_ascii_case(&prefix[..1]) {
+            return None;
+        }
+        return path[2..].strip_prefix(&prefix[2..]);
+    }
+    path.strip_prefix(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(mappings: &[&str]) -> PathMap {
+        PathMap(mappings.iter().map(|m| m.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn test_path_mapping_from_str() {
+        assert_eq!(
+            PathMapping {
+                from: "C:/footage".into(),
+                to: "/mnt/footage".into(),
+            },
+            r"C:\footage\=/mnt/footage".parse().unwrap()
+        );
+        assert!("C:\\footage".parse::<PathMapping>().is_err());
+        assert!("=/mnt/footage".parse::<PathMapping>().is_err());
+        assert!("C:\\footage=".parse::<PathMapping>().is_err());
+    }
+
+    #[test]
+    fn test_path_map_remap() {
+        let paths = map(&[r"C:\footage=/mnt/footage", "D:=/mnt/d"]);
+
+        assert_eq!(
+            PathBuf::from("/mnt/footage/2021/GH010084.MP4"),
+            paths.remap(Path::new(r"C:\footage\2021\GH010084.MP4"))
+        );
+        assert_eq!(
+            PathBuf::from("/mnt/footage/2021"),
+            paths.remap(Path::new("c:/footage/2021/"))
+        );
+        // only the drive letter is case insensitive
+        assert_eq!(
+            PathBuf::from("c:/Footage/2021/"),
+            paths.remap(Path::new("c:/Footage/2021/"))
+        );
+        assert_eq!(
+            PathBuf::from("/mnt/footage"),
+            paths.remap(Path::new(r"C:\footage"))
+        );
+        assert_eq!(
+            PathBuf::from("/mnt/d/DCIM"),
+            paths.remap(Path::new(r"D:\DCIM"))
+        );
+
+        // only whole components match
+        assert_eq!(
+            PathBuf::from(r"C:\footage2\GH010084.MP4"),
+            paths.remap(Path::new(r"C:\footage2\GH010084.MP4"))
+        );
+        assert_eq!(
+            PathBuf::from("/home/footage"),
+            paths.remap(Path::new("/home/footage"))
+        );
+    }
+
+    #[test]
+    fn test_path_map_first_match_wins() {
+        let paths = map(&["/mnt/footage/raw=/data/raw", "/mnt/footage=/data"]);
+        assert_eq!(
+            PathBuf::from("/data/raw/GH010084.MP4"),
+            paths.remap(Path::new("/mnt/footage/raw/GH010084.MP4"))
+        );
+        assert_eq!(
+            PathBuf::from("/data/merged"),
+            paths.remap(Path::new("/mnt/footage/merged"))
+        );
+    }
+}
@@ -0,0 +1,748 @@
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use derive_more::Display;
+use log::*;
+use serde_json::json;
+use thiserror::Error;
+
+use crate::audit::SourceRecord;
+use crate::confirm::DestructiveAction;
+use crate::duration::{self, FormattedDuration};
+use crate::group::{GroupId, MovieGroup, MovieGroups};
+use crate::merge::{
+    self, check_truncation, ffmpeg_merge, merge_input, probe_chapters, record_source,
+    resolve_output, very_long_warning, ByteSize, MergeOptions, ResolvedOutput,
+};
+use crate::proxy::{self, ExcludedChapter};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid --plan-format {0}, one of \"json\" | \"jsonl\"")]
+    InvalidPlanFormat(String),
+}
+
+/// Version of the records of `--plan-format jsonl`, in their header.
+pub const JSON_LINES_VERSION: u32 = 1;
+
+/// How `--dry-run` prints the plan with the json reporter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum PlanFormat {
+    /// A single JSON document with all the movies, printed once all of them are planned.
+    #[display(fmt = "json")]
+    Json,
+    /// JSON Lines, a header record, a record per movie as soon as it's planned and a footer
+    /// record, for archives too large to hold the plan of at once.
+    #[display(fmt = "jsonl")]
+    JsonLines,
+}
+
+impl Default for PlanFormat {
+    fn default() -> Self {
+        PlanFormat::Json
+    }
+}
+
+impl FromStr for PlanFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "json" => PlanFormat::Json,
+            "jsonl" => PlanFormat::JsonLines,
+            _ => return Err(Error::InvalidPlanFormat(s.into())),
+        })
+    }
+}
+
+/// What a run would merge, without merging anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    pub movies: Vec<PlannedMovie>,
+}
+
+/// A single merged movie of the plan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedMovie {
+    pub group_id: GroupId,
+    pub output: PathBuf,
+    pub chapters: Vec<PlannedChapter>,
+    /// What merging the movie would destroy, e.g. an already existing output.
+    pub actions: Vec<DestructiveAction>,
+    /// The ffmpeg command line merging the movie, its intermediate files like the concat list are
+    /// only written when merging.
+    pub ffmpeg: Vec<String>,
+    /// Not going to be merged as the output already exists.
+    pub skipped: bool,
+    /// Merged before the movies without priority, from `--priority`.
+    pub priority: bool,
+    /// Chapters left out as low resolution proxies, from `--exclude-proxies auto`.
+    pub excluded: Vec<ExcludedChapter>,
+    /// What merging would warn about without failing the movie, e.g. it being longer than some
+    /// players can seek in.
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedChapter {
+    pub source: SourceRecord,
+    /// Unknown when the chapters weren't probed.
+    pub duration: Option<Duration>,
+}
+
+impl Plan {
+    /// Plans merging the `movies` found in `input` into `output` with the `options` of the run,
+    /// probing and hashing the chapters the same way merging would.
+    pub fn new(
+        movies: &MovieGroups,
+        input: &Path,
+        output: &Path,
+        options: &MergeOptions,
+    ) -> Result<Self, merge::Error> {
+        let movies = Plan::movies(movies, input, output, options).collect::<Result<_, _>>()?;
+        Ok(Plan { movies })
+    }
+
+    /// Plans the `movies` one at a time as they're iterated, in the order they'd be merged in, so
+    /// that plans of large archives don't have to be held at once.
+    pub fn movies<'a>(
+        movies: &MovieGroups,
+        input: &'a Path,
+        output: &'a Path,
+        options: &'a MergeOptions,
+    ) -> impl Iterator<Item = Result<PlannedMovie, merge::Error>> + 'a {
+        let mut movies = movies.clone();
+        options.priorities.sort(&mut movies);
+        movies
+            .into_iter()
+            .map(move |group| plan_movie(group, input, output, options))
+    }
+}
+
+fn plan_movie(
+    mut group: MovieGroup,
+    input: &Path,
+    output: &Path,
+    options: &MergeOptions,
+) -> Result<PlannedMovie, merge::Error> {
+    // before leaving out proxies, the id is of the chapters found
+    let group_id = group.id();
+    let mut paths = group
+        .chapters
+        .iter()
+        .map(|chapter| group.chapter_path(chapter, input))
+        .collect::<Vec<_>>();
+    // fail before probing or hashing anything if ffmpeg couldn't read the chapters
+    let mut ffmpeg_input = merge_input(&group, &paths, options)?;
+    check_truncation(&paths)?;
+    let mut probed = if options.probe {
+        probe_chapters(
+            &paths,
+            &options.binaries,
+            options.probe_cache.as_ref(),
+            options.timezone,
+            None,
+            |probed| debug!("probed {} / {} chapters of {}", probed, paths.len(), group),
+        )?
+    } else {
+        vec![]
+    };
+    let mut warnings = vec![];
+    let excluded = proxy::exclude(
+        options.proxies,
+        &mut group,
+        &mut paths,
+        &mut probed,
+        |warning| warnings.push(warning),
+    );
+    if !excluded.is_empty() {
+        ffmpeg_input = merge_input(&group, &paths, options)?;
+    }
+    if options.probe {
+        let duration = duration::saturating_sum(probed.iter().map(|chapter| chapter.duration));
+        warnings.extend(very_long_warning(&group, probed.len(), duration));
+    }
+    let durations = if options.probe {
+        probed
+            .iter()
+            .map(|chapter| Some(chapter.duration))
+            .collect()
+    } else {
+        vec![None; paths.len()]
+    };
+
+    let sources = paths
+        .iter()
+        .map(|path| {
+            record_source(path, options, |hashed, size| {
+                info!(
+                    "hashing {}: {} / {}",
+                    path.display(),
+                    ByteSize(hashed),
+                    ByteSize(size)
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let resolved = resolve_output(
+        options
+            .activities
+            .output_dir(&group, output)
+            .join(options.names.of(&group)),
+        options.on_exists,
+    )?;
+    // subtitles need the chapter boundaries, known only when probing
+    let ffmpeg = ffmpeg_merge(
+        &group,
+        &resolved,
+        ffmpeg_input,
+        options,
+        options.subtitles && options.probe,
+        &sources,
+        probed.first(),
+    )
+    .command_line(&options.binaries);
+    let actions = match &resolved {
+        ResolvedOutput::Overwrite(path) => {
+            vec![DestructiveAction::Overwrite(path.clone())]
+        }
+        _ => vec![],
+    };
+    let skipped = matches!(resolved, ResolvedOutput::Skip(..));
+    let output = resolved.path().to_path_buf();
+
+    Ok(PlannedMovie {
+        group_id,
+        output,
+        actions,
+        ffmpeg,
+        skipped,
+        priority: options.priorities.contains(&group),
+        excluded,
+        warnings,
+        chapters: sources
+            .into_iter()
+            .zip(durations)
+            .map(|(source, duration)| PlannedChapter { source, duration })
+            .collect(),
+    })
+}
+
+impl Plan {
+    /// The plan as a JSON document, the same byte for byte for the same chapters and options so
+    /// that plans can be diffed between runs: movies in the order they're merged in, keys sorted
+    /// and paths normalized.
+    pub fn to_json(&self) -> String {
+        let movies = self.movies.iter().map(movie_json).collect::<Vec<_>>();
+
+        // serde_json's maps are sorted by key
+        serde_json::to_string_pretty(&json!({ "movies": movies })).unwrap()
+    }
+}
+
+/// Writes the plan of `movies_len` movies as JSON Lines to `out` as they're planned, a header
+/// record, a record per movie with the keys of the movies of [`Plan::to_json`] and a footer
+/// record. The footer is left out when planning fails, telling consumers the plan is incomplete.
+pub fn write_json_lines<W: Write>(
+    movies: impl Iterator<Item = Result<PlannedMovie, merge::Error>>,
+    movies_len: usize,
+    mut out: W,
+) -> Result<(), merge::Error> {
+    let header = json!({
+        "type": "header",
+        "version": JSON_LINES_VERSION,
+        "movies_len": movies_len,
+    });
+    writeln!(out, "{}", header)?;
+
+    let (mut planned, mut skipped) = (0, 0);
+    let mut duration = Some(Duration::ZERO);
+    for movie in movies {
+        let movie = movie?;
+        let mut record = movie_json(&movie);
+        record["type"] = json!("movie");
+        writeln!(out, "{}", record)?;
+        // the consumer is reading along
+        out.flush()?;
+
+        planned += 1;
+        if movie.skipped {
+            skipped += 1;
+        } else {
+            duration = duration
+                .zip(movie.duration())
+                .map(|(sum, movie)| sum + movie);
+        }
+    }
+
+    let footer = json!({
+        "type": "footer",
+        "movies_len": planned,
+        "skipped": skipped,
+        "duration": duration.map(|duration| FormattedDuration(duration).to_string()),
+    });
+    writeln!(out, "{}", footer)?;
+    out.flush()?;
+    Ok(())
+}
+
+// serde_json's maps are sorted by key
+fn movie_json(movie: &PlannedMovie) -> serde_json::Value {
+    json!({
+        "group_id": movie.group_id.as_str(),
+        "output": normalize(&movie.output),
+        "duration": movie.duration().map(|duration| FormattedDuration(duration).to_string()),
+        "skipped": movie.skipped,
+        "priority": movie.priority,
+        "destructive_actions": movie.actions.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        "ffmpeg": movie.ffmpeg,
+        "chapters": movie.chapters.iter().map(|chapter| json!({
+            "path": normalize(&chapter.source.path),
+            "size": chapter.source.size,
+            "hash": chapter.source.hash,
+            "duration": chapter.duration.map(|duration| FormattedDuration(duration).to_string()),
+        })).collect::<Vec<_>>(),
+        "excluded": movie.excluded.iter().map(|chapter| json!({
+            "path": normalize(&chapter.path),
+            "reason": chapter.reason,
+        })).collect::<Vec<_>>(),
+        "warnings": movie.warnings,
+    })
+}
+
+/// `path` without the `.` components and trailing separators it may have been joined with, e.g.
+/// from a manifest listing `./GH010084.mp4`.
+fn normalize(path: &Path) -> String {
+    path.components().collect::<PathBuf>().display().to_string()
+}
+
+impl PlannedMovie {
+    /// Total duration of the merged movie, unknown if the duration of any chapter is.
+    pub fn duration(&self) -> Option<Duration> {
+        duration::checked_sum(self.chapters.iter().map(|chapter| chapter.duration))
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.movies
+            .iter()
+            .try_for_each(|movie| write!(f, "{}", movie))
+    }
+}
+
+impl fmt::Display for PlannedMovie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.skipped {
+            return writeln!(
+                f,
+                "{} [{}] (skipped, already exists)",
+                self.output.display(),
+                self.group_id
+            );
+        }
+
+        writeln!(
+            f,
+            "{} [{}] ({}{})",
+            self.output.display(),
+            self.group_id,
+            DisplayDuration(self.duration()),
+            if self.priority { ", priority" } else { "" }
+        )?;
+        for action in &self.actions {
+            writeln!(f, "  ! will {}", action)?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "  ! {}", warning)?;
+        }
+        writeln!(
+            f,
+            "  $ {}",
+            self.ffmpeg
+                .iter()
+                .map(|arg| shell_quote(arg))
+                .collect::<Vec<_>>()
+                .join(" ")
+        )?;
+        for chapter in &self.chapters {
+            write!(
+                f,
+                "  {} ({}, {}",
+                chapter.source.path.display(),
+                DisplayDuration(chapter.duration),
+                ByteSize(chapter.source.size)
+            )?;
+            match &chapter.source.hash {
+                Some(hash) => writeln!(f, ", {})", hash)?,
+                None => writeln!(f, ")")?,
+            }
+        }
+        for chapter in &self.excluded {
+            writeln!(
+                f,
+                "  - excluded {} ({})",
+                chapter.path.display(),
+                chapter.reason
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// quoted for copying the command into a POSIX shell
+fn shell_quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:=,+@%".contains(c));
+    if safe {
+        arg.into()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+struct DisplayDuration(Option<Duration>);
+
+impl fmt::Display for DisplayDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(duration) => write!(f, "{}", FormattedDuration(duration)),
+            None => write!(f, "unknown duration"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+    use std::env;
+    use std::fs;
+
+    use crate::audit::AuditHash;
+    use crate::encoding::Encoding;
+    use crate::group::MovieGroup;
+    use crate::identifier::Identifier;
+    use crate::movie::Fingerprint;
+
+    #[test]
+    fn test_plan_without_probing() {
+        let input = env::temp_dir().join("goprotest_plan_without_probing");
+        fs::create_dir_all(&input).unwrap();
+        fs::write(input.join("GH010084.mp4"), b"abc").unwrap();
+        fs::write(input.join("GH020084.mp4"), b"").unwrap();
+
+        let group = MovieGroup {
+            fingerprint: Fingerprint {
+                encoding: Encoding::Avc,
+                file: Identifier::try_from("0084").unwrap(),
+                extension: "mp4".into(),
+                scheme: Default::default(),
+            },
+            chapters: vec![
+                Identifier::try_from("01").unwrap(),
+                Identifier::try_from("02").unwrap(),
+            ],
+            directories: Default::default(),
+            file_names: Default::default(),
+        };
+
+        let options = MergeOptions {
+            workspace: "/workspace".into(),
+            probe: false,
+            audit_hash: Some(AuditHash::Sha256),
+            ..Default::default()
+        };
+        let group_id = group.id();
+        let plan = Plan::new(&vec![group], &input, Path::new("/out"), &options).unwrap();
+        assert_eq!(1, plan.movies.len());
+        assert_eq!(group_id, plan.movies[0].group_id);
+        assert_eq!(Path::new("/out/GH000084.mp4"), plan.movies[0].output);
+        assert_eq!(None, plan.movies[0].duration());
+        assert_eq!(
+            &[
+                "-i",
+                "/workspace/.0084.txt",
+                "-map",
+                "0:v",
+                "-map",
+                "0:a?",
+                "-write_tmcd",
+                "on",
+                "-c",
+                "copy",
+                "-map_metadata",
+                "0",
+                "/out/GH000084.mp4"
+            ],
+            &plan.movies[0].ffmpeg[6..19]
+        );
+        assert_eq!(
+            format!(
+                "/out/GH000084.mp4 [{}] (unknown duration)\n  $ {}\n  {} (unknown duration, 3B, {})\n  {} (unknown duration, 0B, {})\n",
+                group_id,
+                plan.movies[0]
+                    .ffmpeg
+                    .iter()
+                    .map(|arg| shell_quote(arg))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                input.join("GH010084.mp4").display(),
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+                input.join("GH020084.mp4").display(),
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            plan.to_string()
+        );
+
+        fs::remove_dir_all(&input).unwrap();
+    }
+
+    #[test]
+    fn test_plan_to_json() {
+        let input = env::temp_dir().join("goprotest_plan_to_json");
+        let _ = fs::remove_dir_all(&input);
+        fs::create_dir_all(&input).unwrap();
+        for name in ["GH020085.mp4", "GH010084.mp4", "GH010085.mp4"] {
+            fs::write(input.join(name), b"\0\0\0\x0cftypisom\0\0\0\x08moov").unwrap();
+        }
+
+        let options = MergeOptions {
+            workspace: "/workspace".into(),
+            probe: false,
+            ..Default::default()
+        };
+        let plan = |input: &Path| {
+            let movies = crate::group::group_movies(input).unwrap();
+            Plan::new(&movies, input, Path::new("/out/./"), &options)
+                .unwrap()
+                .to_json()
+        };
+        let json = plan(&input);
+        for _ in 0..3 {
+            assert_eq!(json, plan(&input));
+        }
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let movies = value["movies"].as_array().unwrap();
+        assert_eq!(
+            vec!["/out/GH000084.mp4", "/out/GH000085.mp4"],
+            movies
+                .iter()
+                .map(|movie| movie["output"].as_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            input.join("GH020085.mp4").display().to_string(),
+            movies[1]["chapters"][1]["path"]
+        );
+        assert_eq!(serde_json::Value::Null, movies[1]["duration"]);
+        let keys = json
+            .lines()
+            .filter(|line| line.starts_with("      \""))
+            .map(|line| line.trim().split('"').nth(1).unwrap())
+            .take(9)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "chapters",
+                "destructive_actions",
+                "duration",
+                "excluded",
+                "ffmpeg",
+                "group_id",
+                "output",
+                "priority",
+                "skipped"
+            ],
+            keys
+        );
+
+        fs::remove_dir_all(&input).unwrap();
+    }
+
+    #[test]
+    fn test_write_json_lines() {
+        let movie = |output: &str, skipped| PlannedMovie {
+            group_id: GroupId::parse("0123456789abcdef").unwrap(),
+            output: output.into(),
+            actions: vec![],
+            ffmpeg: vec!["ffmpeg".into()],
+            skipped,
+            priority: false,
+            excluded: vec![],
+            warnings: vec![],
+            chapters: vec![PlannedChapter {
+                source: SourceRecord {
+                    path: "/in/GH010084.mp4".into(),
+                    size: 3,
+                    hash: None,
+                },
+                duration: Some(Duration::from_secs(90)),
+            }],
+        };
+        let write = |movies: Vec<Result<PlannedMovie, merge::Error>>| {
+            let mut out = vec![];
+            let result = write_json_lines(movies.into_iter(), 2, &mut out);
+            let lines = String::from_utf8(out)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .collect::<Vec<_>>();
+            (result, lines)
+        };
+
+        let (result, lines) = write(vec![
+            Ok(movie("/out/GH000084.mp4", false)),
+            Ok(movie("/out/GH000085.mp4", true)),
+        ]);
+        assert!(result.is_ok());
+        assert_eq!(4, lines.len());
+        assert_eq!(
+            json!({ "type": "header", "version": JSON_LINES_VERSION, "movies_len": 2 }),
+            lines[0]
+        );
+        assert_eq!("movie", lines[1]["type"]);
+        assert_eq!("/out/GH000084.mp4", lines[1]["output"]);
+        assert_eq!("/in/GH010084.mp4", lines[1]["chapters"][0]["path"]);
+        assert_eq!("00:01:30", lines[1]["duration"]);
+        assert_eq!(true, lines[2]["skipped"]);
+        assert_eq!(
+            json!({ "type": "footer", "movies_len": 2, "skipped": 1, "duration": "00:01:30" }),
+            lines[3]
+        );
+
+        // no footer for consumers to tell the plan is incomplete
+        let (result, lines) = write(vec![
+            Ok(movie("/out/GH000084.mp4", false)),
+            Err(merge::Error::OutputExists("/out/GH000085.mp4".into())),
+        ]);
+        assert!(matches!(result, Err(merge::Error::OutputExists(..))));
+        assert_eq!(2, lines.len());
+        assert_eq!("movie", lines[1]["type"]);
+    }
+
+    #[test]
+    fn test_plan_format_from_str() {
+        assert_eq!(PlanFormat::Json, "json".parse().unwrap());
+        assert_eq!(PlanFormat::JsonLines, "jsonl".parse().unwrap());
+        assert!("ndjson".parse::<PlanFormat>().is_err());
+    }
+
+    #[test]
+    fn test_plan_unsupported_chapter_path() {
+        let input = env::temp_dir().join("goprotest_plan\nunsupported");
+        fs::create_dir_all(&input).unwrap();
+        fs::write(input.join("GH010084.mp4"), b"\0\0\0\x20ftypisom").unwrap();
+        let movies = crate::group::group_movies(&input).unwrap();
+
+        let options = MergeOptions {
+            probe: false,
+            ..Default::default()
+        };
+        assert!(matches!(
+            Plan::new(&movies, &input, Path::new("/out"), &options),
+            Err(merge::Error::UnsupportedChapterPath(path)) if path == input.join("GH010084.mp4")
+        ));
+
+        fs::remove_dir_all(&input).unwrap();
+    }
+
+    #[test]
+    fn test_plan_priority() {
+        let plan = Plan {
+            movies: vec![PlannedMovie {
+                group_id: GroupId::parse("0123456789abcdef").unwrap(),
+                output: "/out/GH000084.mp4".into(),
+                actions: vec![],
+                ffmpeg: vec!["ffmpeg".into()],
+                skipped: false,
+                priority: true,
+                excluded: vec![],
+                warnings: vec![],
+                chapters: vec![],
+            }],
+        };
+        assert_eq!(
+            "/out/GH000084.mp4 [0123456789abcdef] (00:00:00, priority)\n  $ ffmpeg\n",
+            plan.to_string()
+        );
+    }
+
+    #[test]
+    fn test_plan_excluded() {
+        let plan = Plan {
+            movies: vec![PlannedMovie {
+                group_id: GroupId::parse("0123456789abcdef").unwrap(),
+                output: "/out/GH000084.mp4".into(),
+                actions: vec![],
+                ffmpeg: vec!["ffmpeg".into()],
+                skipped: false,
+                priority: false,
+                excluded: vec![ExcludedChapter {
+                    path: "/in/GH020084.mp4".into(),
+                    reason: "848x480 at 3.0 Mb/s against 1920x1080 at 45.0 Mb/s".into(),
+                }],
+                warnings: vec![],
+                chapters: vec![],
+            }],
+        };
+        assert_eq!(
+            "/out/GH000084.mp4 [0123456789abcdef] (00:00:00)\n  $ ffmpeg\n  - excluded /in/GH020084.mp4 (848x480 at 3.0 Mb/s against 1920x1080 at 45.0 Mb/s)\n",
+            plan.to_string()
+        );
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!("-c:s", shell_quote("-c:s"));
+        assert_eq!("/tmp/.0084.txt", shell_quote("/tmp/.0084.txt"));
+        assert_eq!("'/my movies/a.mp4'", shell_quote("/my movies/a.mp4"));
+        assert_eq!(r"'it'\''s'", shell_quote("it's"));
+        assert_eq!("''", shell_quote(""));
+    }
+
+    #[test]
+    fn test_planned_movie_duration() {
+        let mut movie = PlannedMovie {
+            group_id: GroupId::parse("0123456789abcdef").unwrap(),
+            output: "/out/GH000084.mp4".into(),
+            actions: vec![],
+            ffmpeg: vec![],
+            skipped: false,
+            priority: false,
+            excluded: vec![],
+            warnings: vec![],
+            chapters: vec![
+                PlannedChapter {
+                    source: SourceRecord {
+                        path: "/in/GH010084.mp4".into(),
+                        size: 0,
+                        hash: None,
+                    },
+                    duration: Some(Duration::from_secs(60)),
+                },
+                PlannedChapter {
+                    source: SourceRecord {
+                        path: "/in/GH020084.mp4".into(),
+                        size: 0,
+                        hash: None,
+                    },
+                    duration: Some(Duration::from_secs(30)),
+                },
+            ],
+        };
+        assert_eq!(Some(Duration::from_secs(90)), movie.duration());
+
+        movie.chapters[1].duration = None;
+        assert_eq!(None, movie.duration());
+    }
+}
@@ -1,11 +1,14 @@
+use std::io;
 use std::path::PathBuf;
 use std::thread;
-use std::{io, marker::PhantomData};
 
-use crate::merge::{self, Merger};
-use crate::progress::{self, Reporter};
-use crate::{group::MovieGroups, progress::Progress};
+use crate::group::MovieGroups;
+use crate::history::Source;
+use crate::merge::{self, new_merger, FFmpegMerger, MergeOptions, NewMerger};
+use crate::progress::{self, GroupStats, Reporter};
+use crate::schedule;
 
+use crossbeam_channel::unbounded;
 use log::*;
 use rayon::prelude::*;
 
@@ -21,71 +24,408 @@ pub enum Error {
 
     #[error(transparent)]
     IO(#[from] io::Error),
+
+    #[error("Failed to start the probing threads: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+
+    #[error("Failed to merge {}", failed_groups(.0))]
+    Failed(Vec<Failure>),
 }
 
-pub struct Processor<R, M> {
+/// A group that failed to merge while the run kept going.
+#[derive(Debug)]
+pub struct Failure {
+    pub group: String,
+    pub error: merge::Error,
+}
+
+fn failed_groups(failures: &[Failure]) -> String {
+    failures
+        .iter()
+        .map(|failure| failure.group.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Stats of the groups in the order they were sorted in, from the order they finished in.
+fn sorted(mut stats: Vec<(usize, GroupStats)>) -> Vec<GroupStats> {
+    stats.sort_by_key(|(index, _)| *index);
+    stats.into_iter().map(|(_, stats)| stats).collect()
+}
+
+pub struct Processor {
     input: Option<PathBuf>,
     output: Option<PathBuf>,
     movies: Option<MovieGroups>,
+    options: MergeOptions,
 
-    _reporter: PhantomData<R>,
-    _merger: PhantomData<M>,
+    reporter: Box<dyn Reporter>,
+    new_merger: NewMerger,
 }
 
-impl<R, M> Processor<R, M>
-where
-    R: Reporter,
-    R::Progress: Progress,
-    M: Merger<Progress = R::Progress>,
-{
-    pub fn new(input: PathBuf, output: PathBuf, movies: MovieGroups) -> Self {
+impl Processor {
+    /// Merges the `movies` of `input` into `output` with ffmpeg, reporting to the `reporter`.
+    pub fn new(
+        reporter: Box<dyn Reporter>,
+        input: PathBuf,
+        output: PathBuf,
+        movies: MovieGroups,
+        options: MergeOptions,
+    ) -> Self {
         Self {
             input: Some(input),
             output: Some(output),
             movies: Some(movies),
+            options,
 
-            _reporter: Default::default(),
-            _merger: Default::default(),
+            reporter,
+            new_merger: new_merger::<FFmpegMerger>,
         }
     }
 
-    pub fn process(mut self) -> Result<()> {
-        let reporter = R::new();
+    /// Merges with the mergers `new_merger` creates instead of ffmpeg.
+    pub fn with_merger(mut self, new_merger: NewMerger) -> Self {
+        self.new_merger = new_merger;
+        self
+    }
 
+    /// Merges every group, returning their stats in the order the groups were sorted in, the
+    /// prioritized ones first. Stops at the first group failing unless `keep_going` is set in the
+    /// options, in which case all the groups are merged and the failed ones returned as
+    /// [Error::Failed].
+    pub fn process(mut self) -> Result<Vec<GroupStats>> {
+        let reporter = self.reporter;
         let movies = {
             let mut m = self.movies.take().unwrap();
-            m.sort();
+            self.options.priorities.sort(&mut m);
             m
         };
         let movies_len = movies.len();
         let input = self.input.take().unwrap();
         let output = self.output.take().unwrap();
 
-        let mergers = movies
+        let sizes = movies
+            .iter()
+            .map(|movie| schedule::group_size(movie, &input))
+            .collect::<Vec<_>>();
+        let prioritized = movies
+            .iter()
+            .map(|movie| self.options.priorities.contains(movie))
+            .collect::<Vec<_>>();
+        let history = self.options.history.clone().map(|history| {
+            let sources = movies
+                .iter()
+                .map(|movie| Source::of(movie, &input))
+                .collect::<Vec<_>>();
+            (history, sources)
+        });
+        let run_id = self.options.run_id.clone();
+        let mut mergers = movies
             .into_iter()
             .enumerate()
             .map(|(index, movie)| {
                 debug!("adding movie {} {:?}", index, movie);
-                M::new(
+                self.options.jobs.queue(&movie);
+                let name = movie.name();
+                let merger = (self.new_merger)(
                     reporter.add(&movie, index, movies_len),
                     movie,
                     input.clone(),
                     output.clone(),
-                )
+                    self.options.clone(),
+                );
+                Some((index, name, merger))
             })
             .collect::<Vec<_>>();
+        // the progress keeps the sorted order, only the order the groups start in changes
+        let schedule = self.options.schedule;
+        let mut order = schedule.order(&sizes, rayon::current_num_threads());
+        // stable, the prioritized groups start first in the order of the schedule
+        order.sort_by_key(|index| !prioritized[*index]);
+        let mergers = order
+            .into_iter()
+            .filter_map(|index| mergers[index].take())
+            .collect::<Vec<_>>();
+        debug!("scheduled {} groups by {}", mergers.len(), schedule);
+
+        // probing runs ahead on a pool of its own, handing the groups over to the global pool for
+        // merging as they're probed, so ffprobe and ffmpeg can be limited separately
+        let probe_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.options.probe_parallel)
+            .build()?;
+        let (prepared, probed) = unbounded();
+        thread::spawn(move || {
+            probe_pool.install(|| {
+                // bridged to probe the groups in the scheduled order, indexed iterators split it
+                mergers.into_iter().par_bridge().for_each_with(
+                    prepared,
+                    |prepared, (index, group, mut merger)| {
+                        merger.prepare();
+                        // gone when the run stopped at a failure
+                        let _ = prepared.send((index, group, merger));
+                    },
+                )
+            })
+        });
 
+        let keep_going = self.options.keep_going;
         let worker = thread::spawn(move || {
-            mergers
-                .into_par_iter()
-                .try_for_each(|merger| merger.merge())
-                .map_err(From::from)
+            // bridged to start the groups in the order they're probed in
+            let merged = probed
+                .into_iter()
+                .par_bridge()
+                .map(|(index, group, merger)| {
+                    let merged = merger.merge();
+                    if let Some((history, sources)) = &history {
+                        history.record(&run_id, &sources[index], &merged);
+                    }
+                    merged
+                        .map(|stats| {
+                            debug!(
+                                "merged {} ({:?}) in {:?}",
+                                stats.output.display(),
+                                stats.duration,
+                                stats.elapsed
+                            );
+                            (index, stats)
+                        })
+                        .map_err(|error| Failure { group, error })
+                });
+            if !keep_going {
+                return merged
+                    .collect::<std::result::Result<Vec<_>, _>>()
+                    .map(sorted)
+                    .map_err(|failure| Error::Merge(failure.error));
+            }
+
+            let (mut stats, mut failures) = (vec![], vec![]);
+            for result in merged.collect::<Vec<_>>() {
+                match result {
+                    Ok(merged) => stats.push(merged),
+                    Err(failure) => {
+                        warn!("{} failed to merge: {}", failure.group, failure.error);
+                        failures.push(failure)
+                    }
+                }
+            }
+            if failures.is_empty() {
+                Ok(sorted(stats))
+            } else {
+                Err(Error::Failed(failures))
+            }
         });
 
         let reporter = thread::spawn(move || reporter.wait().map_err(Error::from));
 
-        [worker, reporter]
-            .into_iter()
-            .try_for_each(|handle| handle.join().unwrap())
+        let stats = worker.join().unwrap();
+        // the groups left over after a failure never finish, waiting for their progress would hang
+        if let Err(Error::Merge(..)) = stats {
+            return stats;
+        }
+        reporter.join().unwrap()?;
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+    use std::time::Duration;
+
+    use crate::group::{group_file_names, MovieGroup};
+    use crate::merge::{Derived, Merger, VerifyPass};
+    use crate::progress::{GroupError, Progress, Throughput, WarningKind};
+    use crate::run::RunId;
+    use crate::schedule::{Priorities, Schedule};
+
+    #[derive(Clone)]
+    struct NoopProgress;
+
+    impl Progress for NoopProgress {
+        fn update(&mut self, _: Duration, _: Throughput) {}
+
+        fn set_len(&mut self, _: Duration) {}
+
+        fn probing(&mut self, _: usize, _: usize) {}
+
+        fn hashing(&mut self, _: u64, _: u64) {}
+
+        fn verifying(&mut self, _: VerifyPass, _: Duration) {}
+
+        fn deriving(&mut self, _: Derived, _: Duration) {}
+
+        fn low_space(&mut self, _: &Path, _: u64, _: u64) {}
+
+        fn warning(&mut self, _: WarningKind, _: &str) {}
+
+        fn finish(&self, _: std::result::Result<GroupStats, GroupError>) {}
+    }
+
+    #[derive(Clone)]
+    struct NoopReporter;
+
+    impl Reporter for NoopReporter {
+        fn new(_: RunId) -> Self {
+            NoopReporter
+        }
+
+        fn add(&self, _: &MovieGroup, _: usize, _: usize) -> Box<dyn Progress> {
+            Box::new(NoopProgress)
+        }
+
+        fn wait(&self) -> std::result::Result<(), progress::Error> {
+            Ok(())
+        }
+    }
+
+    /// Fails merging the groups of file 0085, and the ones it didn't prepare.
+    struct FailingMerger {
+        group: MovieGroup,
+        output: PathBuf,
+        prepared: bool,
+    }
+
+    impl Merger for FailingMerger {
+        fn new(
+            _: Box<dyn Progress>,
+            group: MovieGroup,
+            _: PathBuf,
+            output: PathBuf,
+            _: MergeOptions,
+        ) -> Self {
+            FailingMerger {
+                group,
+                output,
+                prepared: false,
+            }
+        }
+
+        fn prepare(&mut self) {
+            self.prepared = true;
+        }
+
+        fn merge(self: Box<Self>) -> std::result::Result<GroupStats, merge::Error> {
+            assert!(self.prepared, "{} was merged unprepared", self.group);
+            if self.group.fingerprint.file.to_string() == "0085" {
+                return Err(merge::Error::NoHash(self.group.name()));
+            }
+            Ok(GroupStats::skipped(
+                self.output.join(self.group.name()),
+                Duration::default(),
+            ))
+        }
+    }
+
+    fn process_files(files: Vec<&str>, options: MergeOptions) -> Result<Vec<GroupStats>> {
+        Processor::new(
+            Box::new(NoopReporter),
+            "/movies".into(),
+            "/merged".into(),
+            group_file_names(files),
+            options,
+        )
+        .with_merger(new_merger::<FailingMerger>)
+        .process()
+    }
+
+    fn process(keep_going: bool) -> Result<Vec<GroupStats>> {
+        process_files(
+            vec!["GH010084.mp4", "GH010085.mp4", "GH010086.mp4"],
+            MergeOptions {
+                keep_going,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_process_stops_at_failure() {
+        assert!(matches!(
+            process(false),
+            Err(Error::Merge(merge::Error::NoHash(group))) if group == "GH000085.mp4"
+        ));
+    }
+
+    #[test]
+    fn test_process_keep_going() {
+        match process(true) {
+            Err(Error::Failed(failures)) => {
+                assert_eq!(1, failures.len());
+                assert_eq!("GH000085.mp4", failures[0].group);
+                assert!(matches!(failures[0].error, merge::Error::NoHash(..)));
+            }
+            other => panic!("expected the failed groups, got {:?}", other),
+        }
+        assert_eq!(
+            "Failed to merge GH000085.mp4",
+            Error::Failed(vec![Failure {
+                group: "GH000085.mp4".into(),
+                error: merge::Error::NoHash("GH000085.mp4".into()),
+            }])
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn test_process_schedule_keeps_order() {
+        let stats = process_files(
+            vec![
+                "GH010087.mp4",
+                "GH010084.mp4",
+                "GH010086.mp4",
+                "GH010088.mp4",
+            ],
+            MergeOptions {
+                schedule: Schedule::Interleave(None),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                "/merged/GH000084.mp4",
+                "/merged/GH000086.mp4",
+                "/merged/GH000087.mp4",
+                "/merged/GH000088.mp4"
+            ],
+            stats
+                .iter()
+                .map(|stats| stats.output.to_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_process_priorities_first() {
+        let stats = process_files(
+            vec![
+                "GH010084.mp4",
+                "GH010086.mp4",
+                "GH010087.mp4",
+                "GH010088.mp4",
+            ],
+            MergeOptions {
+                schedule: Schedule::LongestFirst,
+                priorities: Priorities {
+                    groups: vec!["GH000087.mp4".to_string()].into_iter().collect(),
+                },
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                "/merged/GH000087.mp4",
+                "/merged/GH000084.mp4",
+                "/merged/GH000086.mp4",
+                "/merged/GH000088.mp4"
+            ],
+            stats
+                .iter()
+                .map(|stats| stats.output.to_str().unwrap())
+                .collect::<Vec<_>>()
+        );
     }
 }
@@ -0,0 +1,834 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crossbeam_channel::{bounded, Receiver, Sender};
+use parking_lot::Mutex;
+use serde_json::json;
+
+use super::{
+    calculate_percentage, estimate_remaining, Error, GroupError, GroupStats, ProcessEvent,
+    Progress, ProgressDuration, Reporter, Result, RunProgress, Throughput, WarningKind,
+};
+use crate::duration::FormattedDuration;
+use crate::group::{GroupId, MovieGroup};
+use crate::merge::{Derived, VerifyPass};
+use crate::run::RunId;
+
+#[derive(Clone)]
+pub struct JsonProgressReporter {
+    run_id: RunId,
+    started: SystemTime,
+    progresses: Arc<Mutex<Vec<JsonProgress>>>,
+    run: Arc<Mutex<RunProgress>>,
+    quiet: bool,
+    verbose: bool,
+    out_stream: JsonProgressStream,
+}
+
+impl Reporter for JsonProgressReporter {
+    fn new(run_id: RunId) -> Self {
+        JsonProgressReporter::with_out_stream(run_id, io::stdout())
+    }
+
+    /// Prints only the done and error events of the movies and the run summary.
+    fn quiet(mut self) -> Self {
+        self.quiet = true;
+        self
+    }
+
+    /// Prints the process events of the child processes of the movies too.
+    fn verbose(mut self) -> Self {
+        self.verbose = true;
+        self
+    }
+
+    fn add(&self, group: &MovieGroup, index: usize, movies_len: usize) -> Box<dyn Progress> {
+        let mut p = JsonProgress::new(
+            self.run_id.clone(),
+            group,
+            index,
+            movies_len,
+            io::stdout(),
+            io::stderr(),
+        )
+        .with_run(self.run.clone());
+        p.quiet = self.quiet;
+        p.verbose = self.verbose;
+        self.progresses.lock().push(p.clone());
+        Box::new(p)
+    }
+
+    /// Waits for every group to finish, then prints the run summary.
+    fn wait(&self) -> Result<()> {
+        let progresses = self.progresses.lock();
+        progresses
+            .iter()
+            .try_for_each(|p| p.chan.1.recv().map_err(Error::from))?;
+
+        let ended = SystemTime::now();
+        let json_data = json!({
+            "run_id": self.run_id.as_str(),
+            "movies_len": progresses.len(),
+            "run_started": rfc3339(Some(self.started)),
+            "run_ended": rfc3339(Some(ended)),
+            "run_elapsed_ms": elapsed_ms(Some(self.started), Some(ended)),
+        });
+        self.out_stream
+            .lock()
+            .write_all(format!("{}\n", json_data).as_bytes())?;
+        Ok(())
+    }
+}
+
+impl JsonProgressReporter {
+    pub(crate) fn with_out_stream<T: Write + Sync + Send + 'static>(
+        run_id: RunId,
+        out_stream: T,
+    ) -> Self {
+        JsonProgressReporter {
+            run_id,
+            started: SystemTime::now(),
+            progresses: Arc::new(Mutex::new(vec![])),
+            run: Arc::new(Mutex::new(RunProgress::default())),
+            quiet: false,
+            verbose: false,
+            out_stream: Arc::new(Mutex::new(out_stream)),
+        }
+    }
+}
+
+/// Wall clock times of the json events, RFC3339 in UTC with milliseconds so that they sort and
+/// correlate with other logs, e.g. "2021-11-14T23:15:25.120Z".
+fn rfc3339(time: Option<SystemTime>) -> Option<String> {
+    time.map(|time| humantime::format_rfc3339_millis(time).to_string())
+}
+
+fn elapsed_ms(started: Option<SystemTime>, ended: Option<SystemTime>) -> Option<u64> {
+    let elapsed = ended?.duration_since(started?).unwrap_or_default();
+    Some(elapsed.as_millis() as u64)
+}
+
+type JsonProgressStream = Arc<Mutex<dyn Write + Sync + Send>>;
+
+#[derive(Clone)]
+pub struct JsonProgress {
+    len: ProgressDuration,
+
+    run_id: RunId,
+    name: String,
+    group_id: GroupId,
+    chapters: usize,
+    index: usize,
+    movies_len: usize,
+    /// Shared by the movies of the run, for the time left merging all of them.
+    run: Arc<Mutex<RunProgress>>,
+    /// Leaves out the progress events, from `--quiet`.
+    quiet: bool,
+    /// Adds the process events, from `--verbose`.
+    verbose: bool,
+
+    chan: (Sender<()>, Receiver<()>),
+
+    out_stream: JsonProgressStream,
+    err_out_stream: JsonProgressStream,
+}
+
+impl Progress for JsonProgress {
+    fn set_len(&mut self, len: Duration) {
+        *self.len.write() = len;
+        self.run.lock().set_len(self.index, len);
+    }
+
+    fn update(&mut self, progress: Duration, throughput: Throughput) {
+        self.run
+            .lock()
+            .update(self.index, progress, throughput.speed);
+        if !self.quiet {
+            self.print(progress, throughput);
+        }
+    }
+
+    fn probing(&mut self, probed: usize, _: usize) {
+        if !self.quiet {
+            self.print_probing(probed);
+        }
+    }
+
+    fn hashing(&mut self, hashed: u64, size: u64) {
+        if !self.quiet {
+            self.print_hashing(hashed, size);
+        }
+    }
+
+    fn verifying(&mut self, pass: VerifyPass, progress: Duration) {
+        if !self.quiet {
+            self.print_verifying(pass, progress);
+        }
+    }
+
+    fn deriving(&mut self, derived: Derived, progress: Duration) {
+        if !self.quiet {
+            self.print_deriving(derived, progress);
+        }
+    }
+
+    fn low_space(&mut self, output: &Path, available: u64, needed: u64) {
+        self.print_low_space(output, available, needed);
+    }
+
+    fn warning(&mut self, kind: WarningKind, message: &str) {
+        self.print_warning(kind, message);
+    }
+
+    fn process(&mut self, event: &ProcessEvent) {
+        if self.verbose {
+            self.print_process(event);
+        }
+    }
+
+    fn finish(&self, result: std::result::Result<GroupStats, GroupError>) {
+        self.run.lock().finish(self.index);
+        match result {
+            Ok(stats) => self.print_done(stats),
+            Err(err) => self.print_err(err),
+        }
+
+        self.chan.0.send(()).unwrap();
+    }
+}
+
+impl JsonProgress {
+    pub(crate) fn new<T: Write + Sync + Send + 'static, E: Write + Sync + Send + 'static>(
+        run_id: RunId,
+        group: &MovieGroup,
+        index: usize,
+        movies_len: usize,
+        out_stream: T,
+        err_out_stream: E,
+    ) -> Self {
+        let run = Arc::new(Mutex::new(RunProgress::default()));
+        run.lock().add(index);
+
+        JsonProgress {
+            len: ProgressDuration::new(),
+            run_id,
+            name: group.name(),
+            group_id: group.id(),
+            chapters: group.chapters.len(),
+            index,
+            movies_len,
+            run,
+            quiet: false,
+            verbose: false,
+            chan: bounded(1),
+            out_stream: Arc::new(Mutex::new(out_stream)),
+            err_out_stream: Arc::new(Mutex::new(err_out_stream)),
+        }
+    }
+
+    /// Shares the progress of the run with the other movies of the `run`.
+    pub(crate) fn with_run(mut self, run: Arc<Mutex<RunProgress>>) -> Self {
+        run.lock().add(self.index);
+        self.run = run;
+        self
+    }
+
+    fn print_done(&self, stats: GroupStats) {
+        let json_data = json!({
+            "run_id": self.run_id.as_str(),
+            "name": self.name,
+            "group_id": self.group_id.as_str(),
+            "chapters": self.chapters,
+            "index": self.index,
+            "len": FormattedDuration(*self.len.read()).to_string(),
+            "movies_len": self.movies_len,
+            "output": stats.output,
+            "duration": FormattedDuration(stats.duration).to_string(),
+            "elapsed": FormattedDuration(stats.elapsed).to_string(),
+            "started": rfc3339(stats.started),
+            "ended": rfc3339(stats.ended),
+            "elapsed_ms": elapsed_ms(stats.started, stats.ended),
+            "preflight_ms": stats.preflight.as_millis() as u64,
+            "sources": stats.sources.iter().map(|source| json!({
+                "path": source.path,
+                "size": source.size,
+                "hash": source.hash,
+            })).collect::<Vec<_>>(),
+            "skipped": stats.skipped,
+            "deferred": stats.deferred,
+            "interrupted": stats.interrupted,
+            "verification": stats.verification.map(|verification| json!({
+                "sources_hash": verification.sources,
+                "output_hash": verification.output,
+            })),
+            "telemetry_track": stats.telemetry_track,
+            "proxy": stats.proxy,
+            "gpx": stats.gpx,
+            "audio": stats.audio,
+            "excluded": stats.excluded.iter().map(|chapter| json!({
+                "path": chapter.path,
+                "reason": chapter.reason,
+            })).collect::<Vec<_>>(),
+            "variable_frame_rate": stats.variable_frame_rate,
+            "replanned": stats.replanned,
+            "destructive_actions": stats.actions.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        });
+
+        self.write_out(json_data);
+    }
+
+    fn print_err(&self, err: GroupError) {
+        let json_data = json!({
+            "run_id": self.run_id.as_str(),
+            "name": self.name,
+            "group_id": self.group_id.as_str(),
+            "chapters": self.chapters,
+            "index": self.index,
+            "len": FormattedDuration(*self.len.read()).to_string(),
+            "movies_len": self.movies_len,
+            "error_code": err.code.to_string(),
+            "detail": err.message,
+            // the free text of before the error codes, for the tools still reading it
+            "err": err.message,
+            "log": err.log,
+            "started": rfc3339(err.started),
+            "ended": rfc3339(err.ended),
+            "elapsed_ms": elapsed_ms(err.started, err.ended),
+        });
+
+        // This stream is usually going to be stderr, unless in tests
+        // so it's generally fine to panic if we can't print to stdout anyways
+        self.err_out_stream
+            .lock()
+            .write_all(format!("{}\n", json_data).as_bytes())
+            .expect("writing json progress to err stream");
+    }
+
+    fn print(&self, progress: Duration, throughput: Throughput) {
+        let len = *self.len.read();
+        let remaining = estimate_remaining(len, progress, throughput.speed);
+        let (finished, (run_merged, run_len), run_remaining) = {
+            let run = self.run.lock();
+            (run.finished(), run.merged(), run.remaining())
+        };
+        let json_data = json!({
+            "run_id": self.run_id.as_str(),
+            "name": self.name,
+            "group_id": self.group_id.as_str(),
+            "chapters": self.chapters,
+            "index": self.index,
+            "len": FormattedDuration(len).to_string(),
+            "movies_len": self.movies_len,
+            "movies_finished": finished,
+            "progress_time": FormattedDuration(progress).to_string(),
+            "progress_percentage": calculate_percentage(len, progress),
+            "speed": throughput.speed,
+            "bitrate_kbps": throughput.bitrate,
+            "fps": throughput.fps,
+            "eta_ms": remaining.map(|remaining| remaining.as_millis() as u64),
+            "run_progress_percentage": calculate_percentage(run_len, run_merged),
+            "run_eta_ms": run_remaining.map(|remaining| remaining.as_millis() as u64),
+        });
+
+        self.write_out(json_data);
+    }
+
+    fn print_probing(&self, probed: usize) {
+        let json_data = json!({
+            "run_id": self.run_id.as_str(),
+            "name": self.name,
+            "group_id": self.group_id.as_str(),
+            "chapters": self.chapters,
+            "index": self.index,
+            "movies_len": self.movies_len,
+            "probed_chapters": probed,
+        });
+
+        self.write_out(json_data);
+    }
+
+    fn print_hashing(&self, hashed: u64, size: u64) {
+        let json_data = json!({
+            "run_id": self.run_id.as_str(),
+            "name": self.name,
+            "group_id": self.group_id.as_str(),
+            "chapters": self.chapters,
+            "index": self.index,
+            "movies_len": self.movies_len,
+            "hashed_bytes": hashed,
+            "total_bytes": size,
+        });
+
+        self.write_out(json_data);
+    }
+
+    fn print_verifying(&self, pass: VerifyPass, progress: Duration) {
+        let len = *self.len.read();
+        let json_data = json!({
+            "run_id": self.run_id.as_str(),
+            "name": self.name,
+            "group_id": self.group_id.as_str(),
+            "chapters": self.chapters,
+            "index": self.index,
+            "len": FormattedDuration(len).to_string(),
+            "movies_len": self.movies_len,
+            "verify_pass": pass.to_string(),
+            "verify_time": FormattedDuration(progress).to_string(),
+            "verify_percentage": calculate_percentage(len, progress),
+        });
+
+        self.write_out(json_data);
+    }
+
+    fn print_deriving(&self, derived: Derived, progress: Duration) {
+        let len = *self.len.read();
+        let json_data = json!({
+            "run_id": self.run_id.as_str(),
+            "name": self.name,
+            "group_id": self.group_id.as_str(),
+            "chapters": self.chapters,
+            "index": self.index,
+            "len": FormattedDuration(len).to_string(),
+            "movies_len": self.movies_len,
+            "derive": derived.to_string(),
+            "derive_time": FormattedDuration(progress).to_string(),
+            "derive_percentage": calculate_percentage(len, progress),
+        });
+
+        self.write_out(json_data);
+    }
+
+    fn print_low_space(&self, output: &Path, available: u64, needed: u64) {
+        let json_data = json!({
+            "run_id": self.run_id.as_str(),
+            "name": self.name,
+            "group_id": self.group_id.as_str(),
+            "chapters": self.chapters,
+            "index": self.index,
+            "movies_len": self.movies_len,
+            "warning": "low_space",
+            "output_dir": output,
+            "available_bytes": available,
+            "needed_bytes": needed,
+        });
+
+        self.write_out(json_data);
+    }
+
+    fn print_warning(&self, kind: WarningKind, message: &str) {
+        let json_data = json!({
+            "run_id": self.run_id.as_str(),
+            "name": self.name,
+            "group_id": self.group_id.as_str(),
+            "chapters": self.chapters,
+            "index": self.index,
+            "movies_len": self.movies_len,
+            "warning": kind.to_string(),
+            "message": message,
+        });
+
+        self.write_out(json_data);
+    }
+
+    fn print_process(&self, event: &ProcessEvent) {
+        let (process, exited) = match event {
+            ProcessEvent::Spawned(process) => (process, None),
+            ProcessEvent::Exited {
+                process,
+                elapsed,
+                code,
+                success,
+            } => (process, Some((elapsed, code, success))),
+        };
+        let mut json_data = json!({
+            "run_id": self.run_id.as_str(),
+            "name": self.name,
+            "group_id": self.group_id.as_str(),
+            "chapters": self.chapters,
+            "index": self.index,
+            "movies_len": self.movies_len,
+            "process": if exited.is_some() { "exited" } else { "spawned" },
+            "pid": process.pid,
+            "program": process.program,
+            "args_hash": process.args_hash,
+            "time": rfc3339(Some(SystemTime::now())),
+        });
+        if let Some((elapsed, code, success)) = exited {
+            json_data["elapsed_ms"] = json!(elapsed.as_millis() as u64);
+            json_data["exit_code"] = json!(code);
+            json_data["success"] = json!(success);
+        }
+
+        self.write_out(json_data);
+    }
+
+    fn write_out(&self, json_data: serde_json::Value) {
+        // This stream is usually going to be stdout, unless in tests
+        // so it's generally fine to panic if we can't print to stdout anyways
+        self.out_stream
+            .lock()
+            .write_all(format!("{}\n", json_data).as_bytes())
+            .expect("writing json progress to out stream");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::audit::SourceRecord;
+    use crate::confirm::DestructiveAction;
+    use crate::group::group_file_names;
+    use crate::merge::{ErrorCode, Verification};
+    use crate::progress::ChildProcess;
+    use crate::proxy::ExcludedChapter;
+    use crate::system::mock::SharedBuffer;
+
+    fn json_progress(out: &SharedBuffer, err: &SharedBuffer) -> JsonProgress {
+        JsonProgress::new(
+            RunId::generate(),
+            &group_file_names(vec!["GH010084.mp4", "GH020084.mp4"])[0],
+            0,
+            1,
+            out.clone(),
+            err.clone(),
+        )
+    }
+
+    #[test]
+    fn test_json_progress_update() {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut progress = json_progress(&out, &err);
+
+        progress.update(Duration::from_secs(1), Throughput::default());
+        progress.set_len(Duration::from_secs(4));
+        progress.update(
+            Duration::from_secs(2),
+            Throughput {
+                speed: Some(1.9),
+                bitrate: Some(11645.2),
+                fps: Some(113.85),
+            },
+        );
+        progress.finish(Err(GroupError {
+            code: ErrorCode::Stalled,
+            message: "failed".into(),
+            log: Some("/tmp/ffmpeg.log".into()),
+            started: Some(humantime::parse_rfc3339("2021-11-14T23:15:25Z").unwrap()),
+            ended: None,
+        }));
+
+        let lines = out.lines();
+        assert_eq!(2, lines.len());
+        assert_eq!(serde_json::Value::Null, lines[0]["progress_percentage"]);
+        assert_eq!(50, lines[1]["progress_percentage"]);
+        assert_eq!(serde_json::Value::Null, lines[0]["speed"]);
+        assert_eq!(1.9, lines[1]["speed"]);
+        assert_eq!(11645.2, lines[1]["bitrate_kbps"]);
+        assert_eq!(113.85, lines[1]["fps"]);
+        assert_eq!(serde_json::Value::Null, lines[0]["eta_ms"]);
+        // 2 seconds left at 1.9x
+        assert_eq!(1052, lines[1]["eta_ms"]);
+        assert_eq!(1052, lines[1]["run_eta_ms"]);
+        assert_eq!(0, lines[1]["movies_finished"]);
+        assert_eq!(progress.run_id.as_str(), lines[1]["run_id"]);
+
+        let lines = err.lines();
+        assert_eq!(1, lines.len());
+        assert_eq!("stalled", lines[0]["error_code"]);
+        assert_eq!("failed", lines[0]["detail"]);
+        assert_eq!("failed", lines[0]["err"]);
+        assert_eq!("/tmp/ffmpeg.log", lines[0]["log"]);
+        assert_eq!("2021-11-14T23:15:25.000Z", lines[0]["started"]);
+        assert_eq!(serde_json::Value::Null, lines[0]["ended"]);
+        assert_eq!(serde_json::Value::Null, lines[0]["elapsed_ms"]);
+    }
+
+    #[test]
+    fn test_json_progress_run_eta() {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let run = Arc::new(Mutex::new(RunProgress::default()));
+        let mut first = json_progress(&out, &err).with_run(run.clone());
+        let mut second = JsonProgress::new(
+            RunId::generate(),
+            &group_file_names(vec!["GH010085.mp4"])[0],
+            1,
+            2,
+            out.clone(),
+            err.clone(),
+        )
+        .with_run(run);
+        let at = |speed| Throughput {
+            speed: Some(speed),
+            ..Default::default()
+        };
+
+        first.set_len(Duration::from_secs(40));
+        second.set_len(Duration::from_secs(20));
+        first.update(Duration::from_secs(10), at(2.0));
+        second.update(Duration::from_secs(10), at(2.0));
+        first.finish(Ok(GroupStats::skipped(
+            "/tmp/GH000084.mp4".into(),
+            Duration::ZERO,
+        )));
+        second.update(Duration::from_secs(12), at(2.0));
+
+        let lines = out.lines();
+        assert_eq!(15_000, lines[0]["eta_ms"]);
+        // 30 and 20 seconds left at the 2x of the only movie merging so far
+        assert_eq!(25_000, lines[0]["run_eta_ms"]);
+        assert_eq!(5_000, lines[1]["eta_ms"]);
+        // 30 and 10 seconds left at 4x
+        assert_eq!(10_000, lines[1]["run_eta_ms"]);
+        assert_eq!(1, lines[3]["movies_finished"]);
+        // 40 of the first movie skipped and 12 of the second merged
+        assert_eq!(87, lines[3]["run_progress_percentage"]);
+        assert_eq!(4_000, lines[3]["run_eta_ms"]);
+    }
+
+    #[test]
+    fn test_json_progress_quiet() {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut progress = json_progress(&out, &err);
+        progress.quiet = true;
+
+        progress.probing(1, 2);
+        progress.set_len(Duration::from_secs(4));
+        progress.update(Duration::from_secs(2), Throughput::default());
+        progress.finish(Ok(GroupStats::skipped(
+            "/tmp/GH000084.mp4".into(),
+            Duration::ZERO,
+        )));
+
+        let lines = out.lines();
+        assert_eq!(1, lines.len());
+        assert_eq!(true, lines[0]["skipped"]);
+        assert!(err.lines().is_empty());
+    }
+
+    #[test]
+    fn test_json_progress_probing() {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut progress = json_progress(&out, &err);
+
+        progress.probing(0, 2);
+        progress.probing(2, 2);
+
+        let lines = out.lines();
+        assert_eq!(2, lines.len());
+        assert_eq!(0, lines[0]["probed_chapters"]);
+        assert_eq!(2, lines[1]["probed_chapters"]);
+        assert_eq!(2, lines[1]["chapters"]);
+        assert!(err.lines().is_empty());
+    }
+
+    #[test]
+    fn test_json_progress_verifying() {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut progress = json_progress(&out, &err);
+
+        progress.set_len(Duration::from_secs(4));
+        progress.verifying(VerifyPass::Sources, Duration::from_secs(1));
+        progress.verifying(VerifyPass::Output, Duration::from_secs(4));
+
+        let lines = out.lines();
+        assert_eq!(2, lines.len());
+        assert_eq!("sources", lines[0]["verify_pass"]);
+        assert_eq!(25, lines[0]["verify_percentage"]);
+        assert_eq!("output", lines[1]["verify_pass"]);
+        assert_eq!("00:00:04", lines[1]["verify_time"]);
+        assert!(err.lines().is_empty());
+    }
+
+    #[test]
+    fn test_json_progress_deriving() {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut progress = json_progress(&out, &err);
+
+        progress.set_len(Duration::from_secs(4));
+        progress.deriving(Derived::Telemetry, Duration::from_secs(4));
+        progress.deriving(Derived::Proxy, Duration::from_secs(1));
+
+        let lines = out.lines();
+        assert_eq!(2, lines.len());
+        assert_eq!("telemetry", lines[0]["derive"]);
+        assert_eq!(100, lines[0]["derive_percentage"]);
+        assert_eq!("proxy", lines[1]["derive"]);
+        assert_eq!("00:00:01", lines[1]["derive_time"]);
+        assert!(err.lines().is_empty());
+    }
+
+    #[test]
+    fn test_json_progress_low_space() {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut progress = json_progress(&out, &err);
+
+        progress.low_space(Path::new("/movies"), 512, 1024);
+
+        let lines = out.lines();
+        assert_eq!(1, lines.len());
+        assert_eq!("low_space", lines[0]["warning"]);
+        assert_eq!("/movies", lines[0]["output_dir"]);
+        assert_eq!(512, lines[0]["available_bytes"]);
+        assert_eq!(1024, lines[0]["needed_bytes"]);
+        assert!(err.lines().is_empty());
+    }
+
+    #[test]
+    fn test_json_progress_warning() {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut progress = json_progress(&out, &err);
+        progress.quiet = true;
+
+        progress.warning(
+            WarningKind::VeryLong,
+            "merged movie GH000084 will be 14:00:00",
+        );
+
+        let lines = out.lines();
+        assert_eq!(1, lines.len());
+        assert_eq!("very_long", lines[0]["warning"]);
+        assert_eq!(
+            "merged movie GH000084 will be 14:00:00",
+            lines[0]["message"]
+        );
+        assert!(err.lines().is_empty());
+    }
+
+    #[test]
+    fn test_json_progress_process() {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut progress = json_progress(&out, &err);
+        let process = ChildProcess {
+            pid: 4242,
+            program: "ffmpeg".into(),
+            args_hash: "0123456789abcdef".into(),
+        };
+
+        // only reported when verbose
+        progress.process(&ProcessEvent::Spawned(process.clone()));
+        assert!(out.lines().is_empty());
+
+        progress.verbose = true;
+        progress.process(&ProcessEvent::Spawned(process.clone()));
+        progress.process(&ProcessEvent::Exited {
+            process,
+            elapsed: Duration::from_millis(1500),
+            code: Some(1),
+            success: false,
+        });
+
+        let lines = out.lines();
+        assert_eq!(2, lines.len());
+        assert_eq!("spawned", lines[0]["process"]);
+        assert_eq!(4242, lines[0]["pid"]);
+        assert_eq!("ffmpeg", lines[0]["program"]);
+        assert_eq!("0123456789abcdef", lines[0]["args_hash"]);
+        assert_eq!(serde_json::Value::Null, lines[0]["exit_code"]);
+        assert_eq!("exited", lines[1]["process"]);
+        assert_eq!(4242, lines[1]["pid"]);
+        assert_eq!(1500, lines[1]["elapsed_ms"]);
+        assert_eq!(1, lines[1]["exit_code"]);
+        assert_eq!(false, lines[1]["success"]);
+        assert!(err.lines().is_empty());
+    }
+
+    #[test]
+    fn test_json_progress_finish_ok() {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let progress = json_progress(&out, &err);
+
+        progress.finish(Ok(GroupStats {
+            output: "/tmp/GH000084.mp4".into(),
+            duration: Duration::from_secs(10),
+            elapsed: Duration::from_secs(2),
+            preflight: Duration::from_millis(120),
+            sources: vec![SourceRecord {
+                path: "/movies/GH010084.mp4".into(),
+                size: 1024,
+                hash: None,
+            }],
+            actions: vec![DestructiveAction::Overwrite("/tmp/GH000084.mp4".into())],
+            skipped: false,
+            deferred: false,
+            interrupted: false,
+            verification: Some(Verification {
+                sources: "abc".into(),
+                output: "abc".into(),
+            }),
+            telemetry_track: None,
+            proxy: Some("/tmp/GH000084.LRV".into()),
+            gpx: Some("/tmp/GH000084.gpx".into()),
+            audio: Some("/tmp/GH000084.m4a".into()),
+            excluded: vec![ExcludedChapter {
+                path: "/movies/GL020084.mp4".into(),
+                reason: "848x480 against 1920x1080".into(),
+            }],
+            variable_frame_rate: vec!["/movies/GH010084.mp4".into()],
+            replanned: vec!["/movies/GH020084.mp4".into()],
+            started: Some(humantime::parse_rfc3339("2021-11-14T23:15:25Z").unwrap()),
+            ended: Some(humantime::parse_rfc3339("2021-11-14T23:15:27.250Z").unwrap()),
+        }));
+
+        let lines = out.lines();
+        assert_eq!(1, lines.len());
+        assert_eq!("/tmp/GH000084.mp4", lines[0]["output"]);
+        assert_eq!(
+            group_file_names(vec!["GH010084.mp4", "GH020084.mp4"])[0]
+                .id()
+                .as_str(),
+            lines[0]["group_id"]
+        );
+        assert_eq!("00:00:10", lines[0]["duration"]);
+        assert_eq!("00:00:02", lines[0]["elapsed"]);
+        assert_eq!(120, lines[0]["preflight_ms"]);
+        assert_eq!("2021-11-14T23:15:25.000Z", lines[0]["started"]);
+        assert_eq!("2021-11-14T23:15:27.250Z", lines[0]["ended"]);
+        assert_eq!(2250, lines[0]["elapsed_ms"]);
+        assert_eq!("/movies/GH010084.mp4", lines[0]["sources"][0]["path"]);
+        assert_eq!(1024, lines[0]["sources"][0]["size"]);
+        assert_eq!(serde_json::Value::Null, lines[0]["sources"][0]["hash"]);
+        assert_eq!(false, lines[0]["deferred"]);
+        assert_eq!(false, lines[0]["interrupted"]);
+        assert_eq!("abc", lines[0]["verification"]["output_hash"]);
+        assert_eq!(serde_json::Value::Null, lines[0]["telemetry_track"]);
+        assert_eq!("/tmp/GH000084.LRV", lines[0]["proxy"]);
+        assert_eq!("/tmp/GH000084.gpx", lines[0]["gpx"]);
+        assert_eq!("/tmp/GH000084.m4a", lines[0]["audio"]);
+        assert_eq!("/movies/GL020084.mp4", lines[0]["excluded"][0]["path"]);
+        assert_eq!("/movies/GH010084.mp4", lines[0]["variable_frame_rate"][0]);
+        assert_eq!("/movies/GH020084.mp4", lines[0]["replanned"][0]);
+        assert_eq!(
+            "848x480 against 1920x1080",
+            lines[0]["excluded"][0]["reason"]
+        );
+        assert_eq!(
+            "overwrite /tmp/GH000084.mp4",
+            lines[0]["destructive_actions"][0]
+        );
+        assert_eq!(false, lines[0]["skipped"]);
+        assert!(err.lines().is_empty());
+    }
+
+    #[test]
+    fn test_json_reporter_summary() {
+        let out = SharedBuffer::default();
+        let reporter = JsonProgressReporter::with_out_stream(RunId::generate(), out.clone());
+
+        reporter.wait().unwrap();
+
+        let lines = out.lines();
+        assert_eq!(1, lines.len());
+        assert_eq!(reporter.run_id.as_str(), lines[0]["run_id"]);
+        assert_eq!(0, lines[0]["movies_len"]);
+        let started = lines[0]["run_started"].as_str().unwrap();
+        let ended = lines[0]["run_ended"].as_str().unwrap();
+        assert!(started.ends_with('Z'), "{}", started);
+        assert!(
+            humantime::parse_rfc3339(started).unwrap() <= humantime::parse_rfc3339(ended).unwrap()
+        );
+        assert!(lines[0]["run_elapsed_ms"].is_u64());
+    }
+}
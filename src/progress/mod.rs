@@ -0,0 +1,571 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use derive_more::Display;
+use parking_lot::RwLock;
+use thiserror::Error;
+
+use crate::audit::SourceRecord;
+use crate::confirm::DestructiveAction;
+use crate::duration;
+use crate::group::MovieGroup;
+use crate::merge::{Derived, ErrorCode, Verification, VerifyPass};
+use crate::proxy::ExcludedChapter;
+use crate::run::RunId;
+
+#[cfg(feature = "json-reporter")]
+mod json;
+#[cfg(feature = "progressbar")]
+mod terminal;
+
+#[cfg(feature = "json-reporter")]
+pub use json::{JsonProgress, JsonProgressReporter};
+#[cfg(feature = "progressbar")]
+pub use terminal::{ConsoleProgressBarReporter, TerminalProgressBar};
+
+#[derive(Clone, Debug)]
+struct ProgressDuration(Arc<RwLock<Duration>>);
+
+impl ProgressDuration {
+    fn new() -> Self {
+        ProgressDuration(Arc::new(RwLock::new(Duration::default())))
+    }
+}
+
+impl std::ops::Deref for ProgressDuration {
+    type Target = Arc<RwLock<Duration>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Recv(#[from] crossbeam_channel::RecvError),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Reports the progress of the movies of a run, used as a `Box<dyn Reporter>` so that the
+/// reporter can be picked at runtime, e.g. from `--reporter`.
+pub trait Reporter: Send + 'static {
+    fn new(run_id: RunId) -> Self
+    where
+        Self: Sized;
+
+    /// Reports only the outcome of the movies, e.g. for cron jobs, from `--quiet`.
+    fn quiet(self) -> Self
+    where
+        Self: Sized,
+    {
+        self
+    }
+
+    /// Reports the child processes of the movies too, from `--verbose`.
+    fn verbose(self) -> Self
+    where
+        Self: Sized,
+    {
+        self
+    }
+
+    fn add(&self, group: &MovieGroup, index: usize, movies_len: usize) -> Box<dyn Progress>;
+
+    fn wait(&self) -> Result<()>;
+}
+
+/// Outcome of a successfully merged group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupStats {
+    pub output: PathBuf,
+    /// Duration of the merged movie.
+    pub duration: Duration,
+    /// Wall time it took to merge the group.
+    pub elapsed: Duration,
+    /// Part of `elapsed` spent making sure the chapters are readable, e.g. waiting on automounts.
+    pub preflight: Duration,
+    /// The source chapters as they were when merging started.
+    pub sources: Vec<SourceRecord>,
+    /// The confirmed destructive actions done while merging.
+    pub actions: Vec<DestructiveAction>,
+    /// Not merged as the output already existed.
+    pub skipped: bool,
+    /// Not merged as the run reached its deadline, left for a follow-up run.
+    pub deferred: bool,
+    /// Not merged as the run was interrupted, its partial output removed.
+    pub interrupted: bool,
+    /// The hashes proving the merge lossless, when verified with `--verify bitexact`.
+    pub verification: Option<Verification>,
+    /// The GPMF telemetry track extracted from the merged movie, with `--extract-telemetry`.
+    pub telemetry_track: Option<PathBuf>,
+    /// The low resolution proxy generated from the merged movie with `--proxy`, or joined from the
+    /// recorded ones with `--merge-proxies`.
+    pub proxy: Option<PathBuf>,
+    /// The GPS track of the chapters, with `--extract-gpx`.
+    pub gpx: Option<PathBuf>,
+    /// The audio extracted from the merged movie with `--extract-audio`, also the `output` with
+    /// `--audio-only` as the merged movie is removed.
+    pub audio: Option<PathBuf>,
+    /// Chapters left out as low resolution proxies, with `--exclude-proxies auto`.
+    pub excluded: Vec<ExcludedChapter>,
+    /// Chapters with a variable frame rate, converted to a constant one with `--cfr`.
+    pub variable_frame_rate: Vec<PathBuf>,
+    /// Chapters that changed between being probed ahead of merging and merging, e.g. still being
+    /// copied into a watched input, probed again before merging.
+    pub replanned: Vec<PathBuf>,
+    /// Wall clock time the merger started and finished the group, stamped by the merger.
+    pub started: Option<SystemTime>,
+    pub ended: Option<SystemTime>,
+}
+
+impl GroupStats {
+    /// A group whose `output` already exists and was left alone.
+    pub fn skipped(output: PathBuf, elapsed: Duration) -> Self {
+        GroupStats {
+            output,
+            duration: Duration::default(),
+            elapsed,
+            preflight: Duration::default(),
+            sources: vec![],
+            actions: vec![],
+            skipped: true,
+            deferred: false,
+            interrupted: false,
+            verification: None,
+            telemetry_track: None,
+            proxy: None,
+            gpx: None,
+            audio: None,
+            excluded: vec![],
+            variable_frame_rate: vec![],
+            replanned: vec![],
+            started: None,
+            ended: None,
+        }
+    }
+
+    /// A group the run had no time left for, to be merged into `output` by a follow-up run.
+    pub fn deferred(output: PathBuf, elapsed: Duration) -> Self {
+        GroupStats {
+            output,
+            duration: Duration::default(),
+            elapsed,
+            preflight: Duration::default(),
+            sources: vec![],
+            actions: vec![],
+            skipped: false,
+            deferred: true,
+            interrupted: false,
+            verification: None,
+            telemetry_track: None,
+            proxy: None,
+            gpx: None,
+            audio: None,
+            excluded: vec![],
+            variable_frame_rate: vec![],
+            replanned: vec![],
+            started: None,
+            ended: None,
+        }
+    }
+
+    /// A group that wasn't merged into `output` as the run was interrupted.
+    pub fn interrupted(output: PathBuf, elapsed: Duration) -> Self {
+        GroupStats {
+            output,
+            duration: Duration::default(),
+            elapsed,
+            preflight: Duration::default(),
+            sources: vec![],
+            actions: vec![],
+            skipped: false,
+            deferred: false,
+            interrupted: true,
+            verification: None,
+            telemetry_track: None,
+            proxy: None,
+            gpx: None,
+            audio: None,
+            excluded: vec![],
+            variable_frame_rate: vec![],
+            replanned: vec![],
+            started: None,
+            ended: None,
+        }
+    }
+
+    /// Whether the group was merged by this run.
+    pub fn completed(&self) -> bool {
+        !(self.skipped || self.deferred || self.interrupted)
+    }
+}
+
+/// Details of a group that failed to merge.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{message}")]
+pub struct GroupError {
+    pub code: ErrorCode,
+    pub message: String,
+    /// The converter log of the group, if it got as far as running the converter.
+    pub log: Option<PathBuf>,
+    /// Wall clock time the merger started the group and gave up on it.
+    pub started: Option<SystemTime>,
+    pub ended: Option<SystemTime>,
+}
+
+/// How fast ffmpeg is merging, from the `speed=`, `bitrate=` and `fps=` of its progress, unknown
+/// until it merged a little.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Throughput {
+    /// Seconds of the movie merged per second, e.g. 1.9.
+    pub speed: Option<f64>,
+    /// Kilobits per second of the merged movie.
+    pub bitrate: Option<f64>,
+    /// Frames merged per second.
+    pub fps: Option<f64>,
+}
+
+/// What a [Progress::warning] is about, for the json reporter to tell them apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum WarningKind {
+    /// The merged movie is longer than some players can seek in.
+    #[display(fmt = "very_long")]
+    VeryLong,
+    /// A chapter looks like a low resolution proxy renamed to .mp4, with `--exclude-proxies warn`.
+    #[display(fmt = "proxy_suspect")]
+    ProxySuspect,
+    /// Chapters have a variable frame rate, which some editors drift the audio of, without `--cfr`.
+    #[display(fmt = "variable_frame_rate")]
+    VariableFrameRate,
+}
+
+/// A child process run for a movie, ffmpeg, ffprobe or MP4Box.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChildProcess {
+    pub pid: u32,
+    /// The name of the process, e.g. ffmpeg.
+    pub program: String,
+    /// Hash of the arguments, equal for the same command without giving away the paths in it.
+    pub args_hash: String,
+}
+
+/// The lifetime of a [ChildProcess], for supervisors to correlate the metrics of the processes of
+/// the system with the progress of the run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessEvent {
+    Spawned(ChildProcess),
+    /// The `code` is none when the process was killed by a signal.
+    Exited {
+        process: ChildProcess,
+        elapsed: Duration,
+        code: Option<i32>,
+        success: bool,
+    },
+}
+
+/// The progress of a single movie. Cloned for the threads reading the output of ffmpeg, handles
+/// of the same movie reporting to the same place.
+pub trait Progress: CloneProgress + Send + 'static {
+    fn update(&mut self, progress: Duration, throughput: Throughput);
+    fn set_len(&mut self, len: Duration);
+    /// Progress of probing the chapters for their durations, before merging starts.
+    fn probing(&mut self, probed: usize, chapters: usize);
+    /// Progress of hashing a source chapter for the audit trail, before merging starts.
+    fn hashing(&mut self, hashed: u64, size: u64);
+    /// Progress of a verification pass hashing the video packets, after merging.
+    fn verifying(&mut self, pass: VerifyPass, progress: Duration);
+    /// Progress of making a file from the merged movie, after merging.
+    fn deriving(&mut self, derived: Derived, progress: Duration);
+    /// Warning that the group is paused until the volume of `output` has `needed` bytes free.
+    fn low_space(&mut self, output: &Path, available: u64, needed: u64);
+    /// Warning about the group which doesn't stop it from merging, told by every reporter as the
+    /// log only shows errors by default.
+    fn warning(&mut self, kind: WarningKind, message: &str);
+    /// A child process of the group was spawned or exited, only reported by verbose reporters.
+    fn process(&mut self, _event: &ProcessEvent) {}
+    fn finish(&self, result: std::result::Result<GroupStats, GroupError>);
+}
+
+/// Clones a `Box<dyn Progress>`, implemented for every progress that's `Clone`.
+pub trait CloneProgress {
+    fn clone_box(&self) -> Box<dyn Progress>;
+}
+
+impl<P: Progress + Clone> CloneProgress for P {
+    fn clone_box(&self) -> Box<dyn Progress> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Progress> {
+    fn clone(&self) -> Self {
+        (**self).clone_box()
+    }
+}
+
+impl Progress for Box<dyn Progress> {
+    fn update(&mut self, progress: Duration, throughput: Throughput) {
+        (**self).update(progress, throughput)
+    }
+
+    fn set_len(&mut self, len: Duration) {
+        (**self).set_len(len)
+    }
+
+    fn probing(&mut self, probed: usize, chapters: usize) {
+        (**self).probing(probed, chapters)
+    }
+
+    fn hashing(&mut self, hashed: u64, size: u64) {
+        (**self).hashing(hashed, size)
+    }
+
+    fn verifying(&mut self, pass: VerifyPass, progress: Duration) {
+        (**self).verifying(pass, progress)
+    }
+
+    fn deriving(&mut self, derived: Derived, progress: Duration) {
+        (**self).deriving(derived, progress)
+    }
+
+    fn low_space(&mut self, output: &Path, available: u64, needed: u64) {
+        (**self).low_space(output, available, needed)
+    }
+
+    fn warning(&mut self, kind: WarningKind, message: &str) {
+        (**self).warning(kind, message)
+    }
+
+    fn process(&mut self, event: &ProcessEvent) {
+        (**self).process(event)
+    }
+
+    fn finish(&self, result: std::result::Result<GroupStats, GroupError>) {
+        (**self).finish(result)
+    }
+}
+
+/// Returns `None` when the length is unknown, e.g. when durations couldn't be probed. At most
+/// 100, ffmpeg reports a little past the probed length.
+pub(crate) fn calculate_percentage(len: Duration, progress: Duration) -> Option<u64> {
+    if len.is_zero() {
+        return None;
+    }
+
+    // in nanoseconds, f64 seconds lose precision for movies lasting days
+    let (len, progress) = (len.as_nanos(), progress.as_nanos().min(len.as_nanos()));
+    Some(((progress * 200 + len) / (len * 2)) as u64)
+}
+
+/// The time left merging the rest of a movie of `len` at ffmpeg's `speed`, none while either is
+/// unknown.
+pub(crate) fn estimate_remaining(
+    len: Duration,
+    progress: Duration,
+    speed: Option<f64>,
+) -> Option<Duration> {
+    let speed = speed.filter(|speed| *speed > 0.0)?;
+    if len.is_zero() {
+        return None;
+    }
+
+    // from_secs_f64 panics past Duration::MAX, e.g. on a corrupt chapter's nonsensical duration
+    let secs = len.saturating_sub(progress).as_secs_f64() / speed;
+    Some(if secs < Duration::MAX.as_secs_f64() {
+        Duration::from_secs_f64(secs)
+    } else {
+        Duration::MAX
+    })
+}
+
+/// How far every group of a run got, for estimating the time left merging all of them.
+#[derive(Debug, Default)]
+pub(crate) struct RunProgress {
+    groups: BTreeMap<usize, GroupProgress>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct GroupProgress {
+    len: Duration,
+    progress: Duration,
+    speed: Option<f64>,
+    finished: bool,
+}
+
+impl RunProgress {
+    pub(crate) fn add(&mut self, index: usize) {
+        self.groups.insert(index, GroupProgress::default());
+    }
+
+    pub(crate) fn set_len(&mut self, index: usize, len: Duration) {
+        self.groups.entry(index).or_default().len = len;
+    }
+
+    pub(crate) fn update(&mut self, index: usize, progress: Duration, speed: Option<f64>) {
+        let group = self.groups.entry(index).or_default();
+        group.progress = progress;
+        group.speed = speed;
+    }
+
+    /// Marks the group finished, returning whether it was the last one.
+    pub(crate) fn finish(&mut self, index: usize) -> bool {
+        self.groups.entry(index).or_default().finished = true;
+        self.groups.values().all(|group| group.finished)
+    }
+
+    /// How many of the groups finished, merged or not.
+    pub(crate) fn finished(&self) -> usize {
+        self.groups.values().filter(|group| group.finished).count()
+    }
+
+    /// How much of the movies of the run is merged, the finished ones in whole, and their total
+    /// length as far as it's known.
+    pub(crate) fn merged(&self) -> (Duration, Duration) {
+        let merged = self.groups.values().map(|group| {
+            if group.finished {
+                group.len
+            } else {
+                group.progress.min(group.len)
+            }
+        });
+        let len = self.groups.values().map(|group| group.len);
+
+        (
+            duration::saturating_sum(merged),
+            duration::saturating_sum(len),
+        )
+    }
+
+    /// The time left merging the rest of the groups at the combined speed of the ones merging
+    /// now, none until every group left was probed and one of them reported its speed.
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        let left = self.groups.values().filter(|group| !group.finished);
+        if left.clone().any(|group| group.len.is_zero()) {
+            return None;
+        }
+        let speed = left
+            .clone()
+            .filter_map(|group| group.speed)
+            .filter(|speed| *speed > 0.0)
+            .sum::<f64>();
+        let movie_left =
+            duration::saturating_sum(left.map(|group| group.len.saturating_sub(group.progress)));
+
+        estimate_remaining(movie_left, Duration::ZERO, Some(speed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calculate_percentage() {
+        fn test_case(len: u64, progress: u64, expected: u64) -> (Duration, Duration, u64) {
+            (
+                Duration::from_secs(len),
+                Duration::from_secs(progress),
+                expected,
+            )
+        }
+
+        let tests = vec![
+            test_case(9, 3, 33),
+            test_case(10, 3, 30),
+            test_case(10, 5, 50),
+            test_case(100, 5, 5),
+            test_case(33, 10, 30),
+        ];
+
+        tests.into_iter().for_each(|(len, progress, expected)| {
+            let result = calculate_percentage(len, progress);
+            assert_eq!(result, Some(expected));
+        });
+
+        assert_eq!(
+            None,
+            calculate_percentage(Duration::default(), Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn test_estimate_remaining() {
+        let minutes = |minutes: u64| Duration::from_secs(minutes * 60);
+
+        assert_eq!(
+            Some(minutes(3)),
+            estimate_remaining(minutes(10), minutes(4), Some(2.0))
+        );
+        assert_eq!(
+            Some(Duration::ZERO),
+            estimate_remaining(minutes(10), minutes(11), Some(2.0))
+        );
+        assert_eq!(None, estimate_remaining(minutes(10), minutes(4), None));
+        assert_eq!(None, estimate_remaining(minutes(10), minutes(4), Some(0.0)));
+        assert_eq!(
+            None,
+            estimate_remaining(Duration::ZERO, minutes(4), Some(2.0))
+        );
+    }
+
+    #[test]
+    fn test_run_progress_remaining() {
+        let minutes = |minutes: u64| Duration::from_secs(minutes * 60);
+        let mut run = RunProgress::default();
+        (0..3).for_each(|index| run.add(index));
+        assert_eq!(None, run.remaining());
+
+        run.set_len(0, minutes(10));
+        run.set_len(1, minutes(20));
+        // not probed yet
+        assert_eq!(None, run.remaining());
+        run.set_len(2, minutes(30));
+        // no speed yet
+        assert_eq!(None, run.remaining());
+
+        run.update(0, minutes(4), Some(2.0));
+        run.update(1, minutes(2), Some(1.0));
+        // 6 + 18 + 30 minutes left at 3x
+        assert_eq!(Some(minutes(18)), run.remaining());
+        assert_eq!((minutes(6), minutes(60)), run.merged());
+
+        assert!(!run.finish(0));
+        assert_eq!(Some(minutes(48)), run.remaining());
+        assert_eq!((minutes(12), minutes(60)), run.merged());
+        assert_eq!(1, run.finished());
+        assert!(!run.finish(1));
+        assert!(run.finish(2));
+        assert_eq!(3, run.finished());
+    }
+
+    #[test]
+    fn test_calculate_percentage_extremes() {
+        let day = Duration::from_secs(24 * 60 * 60);
+
+        assert_eq!(Some(50), calculate_percentage(30 * day, 15 * day));
+        assert_eq!(
+            Some(99),
+            calculate_percentage(30 * day, 30 * day * 99 / 100)
+        );
+        assert_eq!(
+            Some(100),
+            calculate_percentage(day, day + Duration::from_secs(1))
+        );
+        assert_eq!(
+            Some(50),
+            calculate_percentage(Duration::MAX, Duration::MAX / 2)
+        );
+        assert_eq!(
+            Some(100),
+            calculate_percentage(Duration::from_nanos(1), Duration::MAX)
+        );
+    }
+}
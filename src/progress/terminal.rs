@@ -0,0 +1,308 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use console::{style, Term};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use parking_lot::Mutex;
+
+use super::{
+    calculate_percentage, estimate_remaining, GroupError, GroupStats, Progress, ProgressDuration,
+    Reporter, Result, RunProgress, Throughput, WarningKind,
+};
+use crate::duration::FormattedDuration;
+use crate::group::MovieGroup;
+use crate::merge::{ByteSize, Derived, VerifyPass};
+use crate::run::RunId;
+
+#[derive(Clone)]
+pub struct ConsoleProgressBarReporter {
+    multi: Arc<MultiProgress>,
+    /// The bar above the movies with the progress of the whole run, added with the first movie as
+    /// a run without any would wait for it forever.
+    header: Arc<Mutex<Option<ProgressBar>>>,
+    run: Arc<Mutex<RunProgress>>,
+    /// Nothing is drawn, e.g. with stderr redirected to a file, so warnings are printed instead.
+    hidden: bool,
+}
+
+impl Reporter for ConsoleProgressBarReporter {
+    fn new(_: RunId) -> Self {
+        ConsoleProgressBarReporter {
+            multi: Arc::new(MultiProgress::new()),
+            header: Arc::new(Mutex::new(None)),
+            run: Arc::new(Mutex::new(RunProgress::default())),
+            hidden: !Term::stderr().is_term(),
+        }
+    }
+
+    /// Draws nothing, the outcome of the movies is left to the caller to print.
+    fn quiet(self) -> Self {
+        self.multi.set_draw_target(ProgressDrawTarget::hidden());
+        ConsoleProgressBarReporter {
+            hidden: true,
+            ..self
+        }
+    }
+
+    fn add(&self, group: &MovieGroup, index: usize, movies_len: usize) -> Box<dyn Progress> {
+        let header = self
+            .header
+            .lock()
+            .get_or_insert_with(|| {
+                let header = self.multi.add(
+                    ProgressBar::new(100).with_style(
+                        ProgressStyle::default_bar()
+                            .template("🎬 {prefix}  {bar:70.green/blue}  {msg}"),
+                    ),
+                );
+                update_header(&header, &RunProgress::default(), movies_len);
+                header
+            })
+            .clone();
+        self.run.lock().add(index);
+
+        let pb = self.multi.add(
+            ProgressBar::new(100)
+                .with_style(
+                    ProgressStyle::default_bar().template("📹 {prefix}  {bar:70.cyan/blue}  {msg}"),
+                )
+                .with_prefix(format!(
+                    "{} {}",
+                    style(format!("{:<9}", format!("[{}/{}]", index + 1, movies_len))).bold(),
+                    style(format!(
+                        "{} ({} chapters)",
+                        group.name(),
+                        group.chapters.len()
+                    ))
+                    .bold()
+                    .dim()
+                )),
+        );
+        Box::new(TerminalProgressBar {
+            pb,
+            len: ProgressDuration::new(),
+            progress: ProgressDuration::new(),
+            index,
+            movies_len,
+            header,
+            run: self.run.clone(),
+            hidden: self.hidden,
+        })
+    }
+
+    fn wait(&self) -> Result<()> {
+        self.multi.join().map_err(From::from)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TerminalProgressBar {
+    pb: ProgressBar,
+    len: ProgressDuration,
+    progress: ProgressDuration,
+    index: usize,
+    movies_len: usize,
+    header: ProgressBar,
+    run: Arc<Mutex<RunProgress>>,
+    hidden: bool,
+}
+
+impl Progress for TerminalProgressBar {
+    fn set_len(&mut self, len: Duration) {
+        *self.len.write() = len;
+        self.run.lock().set_len(self.index, len);
+    }
+
+    fn update(&mut self, progress: Duration, throughput: Throughput) {
+        let len = *self.len.read();
+        // 0x while ffmpeg is still opening the chapters
+        let speed = match throughput.speed.filter(|speed| *speed > 0.0) {
+            Some(speed) => format!(" at {:.1}x speed", speed),
+            None => String::new(),
+        };
+        let speed = match estimate_remaining(len, progress, throughput.speed) {
+            Some(remaining) => format!("{}, {} left", speed, FormattedDuration(remaining)),
+            None => speed,
+        };
+        match calculate_percentage(len, progress) {
+            Some(percentage) => {
+                self.pb.set_position(percentage);
+                self.pb.set_message(self.message_styled(format!(
+                    "🕒 {} / {}{}",
+                    FormattedDuration(progress),
+                    FormattedDuration(len),
+                    speed
+                )));
+            }
+            None => self.pb.set_message(self.message_styled(format!(
+                "🕒 {} / ?{}",
+                FormattedDuration(progress),
+                speed
+            ))),
+        }
+        *self.progress.write() = progress;
+
+        let mut run = self.run.lock();
+        run.update(self.index, progress, throughput.speed);
+        update_header(&self.header, &run, self.movies_len);
+    }
+
+    fn probing(&mut self, probed: usize, chapters: usize) {
+        self.pb.set_message(
+            self.message_styled(format!("🔎 probing {} / {} chapters", probed, chapters)),
+        );
+    }
+
+    fn hashing(&mut self, hashed: u64, size: u64) {
+        self.pb.set_message(self.message_styled(format!(
+            "🔒 hashing {} / {}",
+            ByteSize(hashed),
+            ByteSize(size)
+        )));
+    }
+
+    fn verifying(&mut self, pass: VerifyPass, progress: Duration) {
+        self.pb.set_message(self.message_styled(format!(
+            "🔍 verifying {} {} / {}",
+            pass,
+            FormattedDuration(progress),
+            FormattedDuration(*self.len.read())
+        )));
+    }
+
+    fn deriving(&mut self, derived: Derived, progress: Duration) {
+        let action = match derived {
+            Derived::Telemetry => "extracting the telemetry",
+            Derived::Proxy => "generating the proxy",
+            Derived::Thumbnail => "generating the thumbnail",
+            Derived::Gpx => "extracting the GPS track",
+            Derived::Audio(..) => "extracting the audio",
+        };
+        self.pb.set_message(self.message_styled(format!(
+            "🎞  {} {} / {}",
+            action,
+            FormattedDuration(progress),
+            FormattedDuration(*self.len.read())
+        )));
+    }
+
+    fn low_space(&mut self, output: &Path, available: u64, needed: u64) {
+        self.pb.set_message(
+            style(format!(
+                "⚠️  paused, {} has {} free of the {} needed",
+                output.display(),
+                ByteSize(available),
+                ByteSize(needed)
+            ))
+            .yellow()
+            .bold()
+            .to_string(),
+        );
+    }
+
+    fn warning(&mut self, _: WarningKind, message: &str) {
+        // printed above the bars, which print nothing while hidden
+        if self.hidden {
+            eprintln!("warning: {}", message);
+        } else {
+            self.pb
+                .println(style(format!("⚠️  {}", message)).yellow().to_string());
+        }
+    }
+
+    fn finish(&self, result: std::result::Result<GroupStats, GroupError>) {
+        let message = match result {
+            Ok(stats) if stats.interrupted => self.message_styled("⏹  interrupted".to_string()),
+            Ok(stats) if stats.deferred => {
+                self.message_styled("⏸  deferred, --max-runtime reached".to_string())
+            }
+            Ok(stats) if stats.skipped => {
+                self.message_styled(format!("⏭  skipped, {} exists", stats.output.display()))
+            }
+            Ok(stats) => self.message_styled(format!(
+                "✅ {} in {}{}{}{}{}{}",
+                FormattedDuration(stats.duration),
+                FormattedDuration(stats.elapsed),
+                if stats.verification.is_some() {
+                    ", bit exact"
+                } else {
+                    ""
+                },
+                match stats.excluded.len() {
+                    0 => String::new(),
+                    1 => ", excluded 1 proxy".to_string(),
+                    excluded => format!(", excluded {} proxies", excluded),
+                },
+                match stats.variable_frame_rate.len() {
+                    0 => String::new(),
+                    1 => ", 1 variable frame rate chapter".to_string(),
+                    chapters => format!(", {} variable frame rate chapters", chapters),
+                },
+                match stats.replanned.len() {
+                    0 => String::new(),
+                    1 => ", probed 1 changed chapter again".to_string(),
+                    chapters => format!(", probed {} changed chapters again", chapters),
+                },
+                stats
+                    .actions
+                    .iter()
+                    .map(|action| format!(", {}", action))
+                    .collect::<String>()
+            )),
+            Err(err) => self.message_styled(match err.log {
+                Some(log) => format!("❌ {} (log: {})", err.message, log.display()),
+                None => format!("❌ {}", err.message),
+            }),
+        };
+
+        self.pb.finish_with_message(message);
+
+        let mut run = self.run.lock();
+        let last = run.finish(self.index);
+        update_header(&self.header, &run, self.movies_len);
+        if last {
+            self.header.finish();
+        }
+    }
+}
+
+/// Shows the progress of the whole `run` on its `header`, e.g. `[2/5] all movies ... 🕒 00:12:00
+/// / 01:00:00, 00:12:30 left`, the time left being unknown until every movie left was probed and
+/// ffmpeg reported its speed.
+fn update_header(header: &ProgressBar, run: &RunProgress, movies_len: usize) {
+    let (merged, len) = run.merged();
+    header.set_prefix(format!(
+        "{} {}",
+        style(format!(
+            "{:<9}",
+            format!("[{}/{}]", run.finished(), movies_len)
+        ))
+        .bold(),
+        style("all movies").bold().dim()
+    ));
+    if let Some(percentage) = calculate_percentage(len, merged) {
+        header.set_position(percentage);
+    }
+
+    let remaining = match run.remaining() {
+        Some(remaining) => format!(", {} left", FormattedDuration(remaining)),
+        None => String::new(),
+    };
+    header.set_message(
+        style(format!(
+            "🕒 {} / {}{}",
+            FormattedDuration(merged),
+            FormattedDuration(len),
+            remaining
+        ))
+        .bold()
+        .to_string(),
+    );
+}
+
+impl TerminalProgressBar {
+    fn message_styled(&self, msg: String) -> String {
+        style(msg).bold().to_string()
+    }
+}
@@ -0,0 +1,417 @@
+use std::cmp::Reverse;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use derive_more::Display;
+use log::*;
+use thiserror::Error;
+
+use crate::chapters::ProbedChapter;
+use crate::frame_rate::FrameRate;
+use crate::group::MovieGroup;
+use crate::system::Fs;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid --exclude-proxies {0}, one of \"warn\" | \"auto\" | \"off\"")]
+    InvalidProxyMode(String),
+}
+
+/// Extensions of the low resolution proxies and thumbnails GoPro cameras record next to the
+/// movies, e.g. GL010084.LRV and GH010084.THM.
+pub const PROXY_EXTENSIONS: &[&str] = &["lrv", "thm"];
+
+/// A chapter with a quarter of the pixels of the largest chapter of its group, or less, looks
+/// like a proxy, the LRV of a 1080p movie being 848x480.
+const PIXELS_RATIO: u64 = 4;
+/// Likewise for an eighth of the bit rate, proxies being recorded at a few Mb/s.
+const BIT_RATE_RATIO: u64 = 8;
+
+/// What to do with the low resolution proxies of the movies, from `--exclude-proxies`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum ProxyMode {
+    /// Skip the .lrv and .thm files and warn about chapters looking like proxies renamed to .mp4.
+    #[display(fmt = "warn")]
+    Warn,
+    /// Exclude the chapters looking like proxies from their groups too.
+    #[display(fmt = "auto")]
+    Auto,
+    /// Merge everything, .lrv files included.
+    #[display(fmt = "off")]
+    Off,
+}
+
+impl Default for ProxyMode {
+    fn default() -> Self {
+        ProxyMode::Warn
+    }
+}
+
+impl FromStr for ProxyMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "warn" => ProxyMode::Warn,
+            "auto" => ProxyMode::Auto,
+            "off" => ProxyMode::Off,
+            _ => return Err(Error::InvalidProxyMode(s.into())),
+        })
+    }
+}
+
+impl ProxyMode {
+    /// Whether the file `name` is skipped while scanning for its proxy extension.
+    pub fn skips(&self, name: &str) -> bool {
+        *self != ProxyMode::Off
+            && name.rsplit_once('.').map_or(false, |(_, extension)| {
+                PROXY_EXTENSIONS
+                    .iter()
+                    .any(|proxy| extension.eq_ignore_ascii_case(proxy))
+            })
+    }
+}
+
+/// The low resolution proxy the camera recorded next to the chapter at `path`, if it did, e.g.
+/// GL010084.LRV for GH010084.MP4 and GX010084.MP4, or GOPR0084.LRV for GOPR0084.MP4.
+pub fn recorded_proxy(path: &Path, fs: &impl Fs) -> Option<PathBuf> {
+    let stem = path.file_stem()?.to_str()?;
+    // HERO6 and later name the proxies GL whatever the encoding of the movie, older cameras like
+    // the movie
+    let renamed = ["GH", "GX"]
+        .iter()
+        .find(|prefix| {
+            stem.get(..2)
+                .map_or(false, |start| start.eq_ignore_ascii_case(prefix))
+        })
+        .map(|_| {
+            let prefix = if stem.starts_with('g') { "gl" } else { "GL" };
+            format!("{}{}", prefix, &stem[2..])
+        });
+    renamed
+        .iter()
+        .map(String::as_str)
+        .chain(std::iter::once(stem))
+        .find_map(|stem| sidecar(path, stem, "lrv", fs))
+}
+
+/// The thumbnail the camera recorded next to the chapter at `path`, if it did, e.g.
+/// GH010084.THM for GH010084.MP4.
+pub fn recorded_thumbnail(path: &Path, fs: &impl Fs) -> Option<PathBuf> {
+    sidecar(path, path.file_stem()?.to_str()?, "thm", fs)
+}
+
+/// The file named `stem` with the `extension` in upper or lower case next to `path`.
+fn sidecar(path: &Path, stem: &str, extension: &str, fs: &impl Fs) -> Option<PathBuf> {
+    [
+        extension.to_ascii_uppercase(),
+        extension.to_ascii_lowercase(),
+    ]
+    .iter()
+    .map(|extension| path.with_file_name(format!("{}.{}", stem, extension)))
+    .find(|sidecar| fs.len(sidecar).is_ok())
+}
+
+/// The first video stream of a chapter as probed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct VideoStream {
+    pub width: u64,
+    pub height: u64,
+    /// Bits per second, unknown for some containers.
+    pub bit_rate: Option<u64>,
+    /// The nominal frame rate, ffprobe's r_frame_rate.
+    pub frame_rate: Option<FrameRate>,
+    /// The frame rate averaged over the stream, off from the nominal one for variable frame rate.
+    pub avg_frame_rate: Option<FrameRate>,
+}
+
+impl fmt::Display for VideoStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)?;
+        if let Some(bit_rate) = self.bit_rate {
+            write!(f, " at {:.1} Mb/s", bit_rate as f64 / 1_000_000.0)?;
+        }
+        Ok(())
+    }
+}
+
+impl VideoStream {
+    fn pixels(&self) -> u64 {
+        self.width.saturating_mul(self.height)
+    }
+
+    /// Whether the frames have varying durations, e.g. in some low light modes.
+    pub fn variable_frame_rate(&self) -> bool {
+        match (self.frame_rate, self.avg_frame_rate) {
+            (Some(nominal), Some(average)) => nominal.varies_from(average),
+            _ => false,
+        }
+    }
+}
+
+/// A chapter left out of its group for looking like a proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExcludedChapter {
+    pub path: PathBuf,
+    /// Why it looks like one, e.g. "848x480 at 3.0 Mb/s against 1920x1080 at 45.0 Mb/s".
+    pub reason: String,
+}
+
+/// Indices of the `videos` looking like proxies of the others, with the reason why.
+pub fn suspects(videos: &[Option<VideoStream>]) -> Vec<(usize, String)> {
+    // zero sized streams are the ones ffprobe couldn't tell the size of
+    let known = || videos.iter().flatten().filter(|video| video.pixels() > 0);
+    // the first of the largest ones, max_by_key would pick the last
+    let largest = match known().min_by_key(|video| Reverse(video.pixels())) {
+        Some(largest) => *largest,
+        None => return vec![],
+    };
+    let max_bit_rate = known().filter_map(|video| video.bit_rate).max();
+
+    videos
+        .iter()
+        .enumerate()
+        .filter_map(|(index, video)| {
+            let video = video.filter(|video| video.pixels() > 0)?;
+            let low_resolution = video.pixels().saturating_mul(PIXELS_RATIO) <= largest.pixels();
+            let low_bit_rate = match (video.bit_rate, max_bit_rate) {
+                (Some(bit_rate), Some(max)) if bit_rate > 0 => {
+                    bit_rate.saturating_mul(BIT_RATE_RATIO) <= max
+                }
+                _ => false,
+            };
+            (low_resolution || low_bit_rate)
+                .then(|| (index, format!("{} against {}", video, largest)))
+        })
+        .collect()
+}
+
+/// Warns about the chapters at `paths` of the `group` looking like proxies and, with
+/// [ProxyMode::Auto], removes them from the `group`, the `paths` and the `probed` chapters. With
+/// [ProxyMode::Warn] the warnings are handed to `warn` too, for telling about them.
+pub fn exclude(
+    mode: ProxyMode,
+    group: &mut MovieGroup,
+    paths: &mut Vec<PathBuf>,
+    probed: &mut Vec<ProbedChapter>,
+    mut warn: impl FnMut(String),
+) -> Vec<ExcludedChapter> {
+    if mode == ProxyMode::Off {
+        return vec![];
+    }
+
+    let suspects = suspects(
+        &probed
+            .iter()
+            .map(|chapter| chapter.video)
+            .collect::<Vec<_>>(),
+    );
+    for (index, reason) in &suspects {
+        let warning = format!(
+            "chapter {} of {} looks like a low resolution proxy, {}{}",
+            paths[*index].display(),
+            group,
+            reason,
+            if mode == ProxyMode::Auto {
+                ", excluding it"
+            } else {
+                ", pass --exclude-proxies auto to leave it out"
+            }
+        );
+        warn!("{}", warning);
+        // the excluded ones are reported with the movie
+        if mode != ProxyMode::Auto {
+            warn(warning);
+        }
+    }
+    if mode != ProxyMode::Auto {
+        return vec![];
+    }
+
+    // backwards so that the indices stay valid
+    suspects
+        .into_iter()
+        .rev()
+        .map(|(index, reason)| {
+            group.chapters.remove(index);
+            probed.remove(index);
+            ExcludedChapter {
+                path: paths.remove(index),
+                reason,
+            }
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::group::group_file_names;
+    use crate::system::mock::MemoryFs;
+    use crate::timestamp::{ChapterTimestamp, TimestampSource};
+
+    fn video(width: u64, height: u64, mbps: u64) -> Option<VideoStream> {
+        Some(VideoStream {
+            width,
+            height,
+            bit_rate: Some(mbps * 1_000_000),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_proxy_mode() {
+        for mode in &[ProxyMode::Warn, ProxyMode::Auto, ProxyMode::Off] {
+            assert_eq!(*mode, mode.to_string().parse().unwrap());
+        }
+        assert!("on".parse::<ProxyMode>().is_err());
+
+        assert!(ProxyMode::Warn.skips("GL010084.LRV"));
+        assert!(ProxyMode::Auto.skips("gh010084.thm"));
+        assert!(!ProxyMode::Warn.skips("GH010084.MP4"));
+        assert!(!ProxyMode::Warn.skips("LRV"));
+        assert!(!ProxyMode::Off.skips("GL010084.LRV"));
+    }
+
+    #[test]
+    fn test_recorded_proxy() {
+        let fs = MemoryFs::default();
+        for file in &[
+            "/movies/GL010084.LRV",
+            "/movies/GH010084.THM",
+            "/movies/gl020084.lrv",
+            "/movies/GOPR0085.LRV",
+        ] {
+            fs.write(Path::new(file), b"").unwrap();
+        }
+
+        let proxy = |path: &str| recorded_proxy(Path::new(path), &fs);
+        assert_eq!(
+            Some("/movies/GL010084.LRV".into()),
+            proxy("/movies/GH010084.MP4")
+        );
+        assert_eq!(
+            Some("/movies/GL010084.LRV".into()),
+            proxy("/movies/GX010084.MP4")
+        );
+        assert_eq!(
+            Some("/movies/gl020084.lrv".into()),
+            proxy("/movies/gh020084.mp4")
+        );
+        assert_eq!(
+            Some("/movies/GOPR0085.LRV".into()),
+            proxy("/movies/GOPR0085.MP4")
+        );
+        assert_eq!(None, proxy("/movies/GH030084.MP4"));
+
+        let thumbnail = |path: &str| recorded_thumbnail(Path::new(path), &fs);
+        assert_eq!(
+            Some("/movies/GH010084.THM".into()),
+            thumbnail("/movies/GH010084.MP4")
+        );
+        assert_eq!(None, thumbnail("/movies/GH020084.MP4"));
+    }
+
+    #[test]
+    fn test_suspects() {
+        assert!(suspects(&[]).is_empty());
+        assert!(suspects(&[None, None]).is_empty());
+        assert!(suspects(&[Some(VideoStream::default()), video(1920, 1080, 45)]).is_empty());
+        assert!(suspects(&[video(1920, 1080, 45), video(1920, 1080, 30), None]).is_empty());
+
+        assert_eq!(
+            vec![(
+                1,
+                "848x480 at 3.0 Mb/s against 1920x1080 at 45.0 Mb/s".to_string()
+            )],
+            suspects(&[
+                video(1920, 1080, 45),
+                video(848, 480, 3),
+                video(1920, 1080, 44)
+            ])
+        );
+        // renamed proxies of the same resolution give themselves away by their bit rate
+        assert_eq!(
+            vec![0],
+            suspects(&[video(1920, 1080, 4), video(1920, 1080, 60)])
+                .into_iter()
+                .map(|(index, _)| index)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_exclude() {
+        let chapter = |video| ProbedChapter {
+            duration: Duration::from_secs(60),
+            telemetry: vec![],
+            timestamp: ChapterTimestamp {
+                time: None,
+                source: TimestampSource::Unknown,
+            },
+            video,
+        };
+        let group =
+            group_file_names(vec!["GH010084.mp4", "GH020084.mp4", "GH030084.mp4"]).remove(0);
+        let paths = vec![
+            PathBuf::from("/movies/GH010084.mp4"),
+            PathBuf::from("/movies/GH020084.mp4"),
+            PathBuf::from("/movies/GH030084.mp4"),
+        ];
+        let probed = vec![
+            chapter(video(3840, 2160, 100)),
+            chapter(video(848, 480, 3)),
+            chapter(video(3840, 2160, 100)),
+        ];
+
+        let (mut warned, mut warned_paths, mut warned_probed) =
+            (group.clone(), paths.clone(), probed.clone());
+        let mut warnings = vec![];
+        assert!(exclude(
+            ProxyMode::Warn,
+            &mut warned,
+            &mut warned_paths,
+            &mut warned_probed,
+            |warning| warnings.push(warning)
+        )
+        .is_empty());
+        assert_eq!((group.clone(), paths.clone()), (warned, warned_paths));
+        assert_eq!(
+            vec!["chapter /movies/GH020084.mp4 of GH000084.mp4 looks like a low resolution proxy, 848x480 at 3.0 Mb/s against 3840x2160 at 100.0 Mb/s, pass --exclude-proxies auto to leave it out"],
+            warnings
+        );
+
+        let (mut excluded_group, mut excluded_paths, mut excluded_probed) =
+            (group.clone(), paths.clone(), probed.clone());
+        assert_eq!(
+            vec![ExcludedChapter {
+                path: "/movies/GH020084.mp4".into(),
+                reason: "848x480 at 3.0 Mb/s against 3840x2160 at 100.0 Mb/s".into(),
+            }],
+            exclude(
+                ProxyMode::Auto,
+                &mut excluded_group,
+                &mut excluded_paths,
+                &mut excluded_probed,
+                |warning| panic!("excluded proxies are reported with the movie: {}", warning)
+            )
+        );
+        assert_eq!(
+            vec!["GH010084.mp4", "GH030084.mp4"],
+            excluded_group
+                .chapters
+                .iter()
+                .map(|chapter| excluded_group.chapter_file_name(chapter))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec![paths[0].clone(), paths[2].clone()], excluded_paths);
+        assert_eq!(vec![probed[0].clone(), probed[2].clone()], excluded_probed);
+    }
+}
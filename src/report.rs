@@ -0,0 +1,470 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use derive_more::Display;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::duration::{self, FormattedDuration};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Can't read the run report {0}, {1}")]
+    Read(PathBuf, io::Error),
+
+    #[error("{0} has no movies, expected the output of a run with the json reporter")]
+    NoMovies(PathBuf),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// How much slower a movie may merge in the later run before it's reported, the speed of the
+/// same merge varying by a few percent between runs.
+const SPEED_REGRESSION: f64 = 0.8;
+/// How far the merged durations of a movie may be apart between the runs, ffmpeg versions
+/// differing by a frame or so at the ends of the streams.
+const DURATION_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// How a movie ended in a run.
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+pub enum Status {
+    #[display(fmt = "merged")]
+    Merged,
+    #[display(fmt = "skipped")]
+    Skipped,
+    #[display(fmt = "deferred")]
+    Deferred,
+    #[display(fmt = "interrupted")]
+    Interrupted,
+    /// With the error code of the failure, e.g. "ffmpeg_failed".
+    #[display(fmt = "failed with {}", _0)]
+    Failed(String),
+}
+
+/// A movie as a run report has it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportedMovie {
+    pub name: String,
+    pub status: Status,
+    /// The merged duration, zero for the movies that weren't merged.
+    pub duration: Duration,
+    pub elapsed: Option<Duration>,
+}
+
+impl ReportedMovie {
+    /// How many times faster than realtime the movie merged, none unless it merged.
+    fn speed(&self) -> Option<f64> {
+        let elapsed = self.elapsed?.as_secs_f64();
+        (self.status == Status::Merged && elapsed > 0.0)
+            .then(|| self.duration.as_secs_f64() / elapsed)
+    }
+}
+
+/// How every movie of a run ended, read back from the output of the json reporter, e.g. saved
+/// with `gopro-merge --reporter json --quiet > run.json 2>&1`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunReport {
+    /// By their group id, the same across runs for the same chapters.
+    pub movies: BTreeMap<String, ReportedMovie>,
+}
+
+impl RunReport {
+    /// Reads the report at `path`, failing for files without a single movie in them.
+    pub fn read(path: &Path) -> Result<Self> {
+        let report =
+            fs::read_to_string(path).map_err(|err| Error::Read(path.to_path_buf(), err))?;
+        let report = Self::parse(&report);
+        if report.movies.is_empty() {
+            return Err(Error::NoMovies(path.to_path_buf()));
+        }
+
+        Ok(report)
+    }
+
+    /// Reads the done and error events of the movies, skipping the progress events and whatever
+    /// else was saved along with them, e.g. log lines. The last event of a movie merged more
+    /// than once, e.g. with --watch, wins.
+    pub fn parse(report: &str) -> Self {
+        let movies = report
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+            .filter_map(|event| {
+                let group_id = event["group_id"].as_str()?.to_string();
+                Some((group_id, reported_movie(&event)?))
+            })
+            .collect();
+
+        RunReport { movies }
+    }
+}
+
+fn reported_movie(event: &Value) -> Option<ReportedMovie> {
+    let flag = |key: &str| event[key].as_bool().unwrap_or(false);
+    let status = match event["error_code"].as_str() {
+        Some(code) => Status::Failed(code.to_string()),
+        // only the done events list them
+        None if event.get("destructive_actions").is_none() => return None,
+        None if flag("skipped") => Status::Skipped,
+        None if flag("deferred") => Status::Deferred,
+        None if flag("interrupted") => Status::Interrupted,
+        None => Status::Merged,
+    };
+
+    Some(ReportedMovie {
+        name: event["name"].as_str().unwrap_or_default().to_string(),
+        status,
+        duration: event["duration"]
+            .as_str()
+            .and_then(parse_clock)
+            .unwrap_or_default(),
+        elapsed: event["elapsed_ms"]
+            .as_u64()
+            .map(Duration::from_millis)
+            .or_else(|| event["elapsed"].as_str().and_then(parse_clock)),
+    })
+}
+
+/// Parses a duration formatted by [FormattedDuration], e.g. `02:13:44` or `1d 02:13:44`.
+fn parse_clock(clock: &str) -> Option<Duration> {
+    let (days, clock) = match clock.split_once("d ") {
+        Some((days, clock)) => (days.parse::<u64>().ok()?, clock),
+        None => (0, clock),
+    };
+    let parts = clock
+        .split(':')
+        .map(|part| part.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match parts[..] {
+        [hours, minutes, seconds] => Some(duration::from_clock(
+            days.saturating_mul(24).saturating_add(hours),
+            minutes,
+            seconds,
+            0,
+        )),
+        _ => None,
+    }
+}
+
+/// A difference of a movie between two runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// Only in the later run.
+    Added { name: String, status: Status },
+    /// Only in the earlier run.
+    Removed { name: String, status: Status },
+    /// Ended differently, e.g. failing where it merged before.
+    Status {
+        name: String,
+        before: Status,
+        after: Status,
+    },
+    /// Merged into a movie of a different duration.
+    Duration {
+        name: String,
+        before: Duration,
+        after: Duration,
+    },
+    /// Merged slower, in times realtime.
+    Slower {
+        name: String,
+        before: f64,
+        after: f64,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { name, status } => write!(f, "+ {} added, {}", name, status),
+            Change::Removed { name, status } => write!(f, "- {} removed, was {}", name, status),
+            Change::Status {
+                name,
+                before,
+                after,
+            } => write!(f, "! {} {} instead of {}", name, after, before),
+            Change::Duration {
+                name,
+                before,
+                after,
+            } => write!(
+                f,
+                "~ {} lasts {} instead of {}",
+                name,
+                FormattedDuration(*after),
+                FormattedDuration(*before)
+            ),
+            Change::Slower {
+                name,
+                before,
+                after,
+            } => write!(
+                f,
+                "~ {} merged at {:.1}x realtime instead of {:.1}x",
+                name, after, before
+            ),
+        }
+    }
+}
+
+impl Change {
+    /// A single line of JSON, for the json reporter.
+    pub fn to_json(&self) -> String {
+        let change = match self {
+            Change::Added { name, status } => json!({
+                "change": "added",
+                "name": name,
+                "status": status.to_string(),
+            }),
+            Change::Removed { name, status } => json!({
+                "change": "removed",
+                "name": name,
+                "status": status.to_string(),
+            }),
+            Change::Status {
+                name,
+                before,
+                after,
+            } => json!({
+                "change": "status",
+                "name": name,
+                "before": before.to_string(),
+                "after": after.to_string(),
+            }),
+            Change::Duration {
+                name,
+                before,
+                after,
+            } => json!({
+                "change": "duration",
+                "name": name,
+                "before": FormattedDuration(*before).to_string(),
+                "after": FormattedDuration(*after).to_string(),
+            }),
+            Change::Slower {
+                name,
+                before,
+                after,
+            } => json!({
+                "change": "slower",
+                "name": name,
+                "before_speed": before,
+                "after_speed": after,
+            }),
+        };
+
+        change.to_string()
+    }
+}
+
+/// What changed for the movies of the `before` run in the `after` one, from `--diff-reports`,
+/// by their group ids. Changes in duration and speed are only looked at for movies merged in
+/// both runs, and only beyond the variance between runs of the same merges.
+pub fn diff(before: &RunReport, after: &RunReport) -> Vec<Change> {
+    let ids = before
+        .movies
+        .keys()
+        .chain(after.movies.keys())
+        .collect::<BTreeSet<_>>();
+    let mut changes = vec![];
+    for id in ids {
+        let (earlier, later) = match (before.movies.get(id), after.movies.get(id)) {
+            (Some(earlier), Some(later)) => (earlier, later),
+            (None, Some(later)) => {
+                changes.push(Change::Added {
+                    name: later.name.clone(),
+                    status: later.status.clone(),
+                });
+                continue;
+            }
+            (Some(earlier), None) => {
+                changes.push(Change::Removed {
+                    name: earlier.name.clone(),
+                    status: earlier.status.clone(),
+                });
+                continue;
+            }
+            (None, None) => continue,
+        };
+        let name = later.name.clone();
+
+        if earlier.status != later.status {
+            changes.push(Change::Status {
+                name,
+                before: earlier.status.clone(),
+                after: later.status.clone(),
+            });
+            continue;
+        }
+        let apart = if earlier.duration > later.duration {
+            earlier.duration - later.duration
+        } else {
+            later.duration - earlier.duration
+        };
+        if later.status == Status::Merged && apart > DURATION_TOLERANCE {
+            changes.push(Change::Duration {
+                name: name.clone(),
+                before: earlier.duration,
+                after: later.duration,
+            });
+        }
+        if let (Some(before), Some(after)) = (earlier.speed(), later.speed()) {
+            if after < before * SPEED_REGRESSION {
+                changes.push(Change::Slower {
+                    name,
+                    before,
+                    after,
+                });
+            }
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn done(id: &str, duration: &str, elapsed_ms: u64) -> String {
+        json!({
+            "group_id": id,
+            "name": format!("GH{}", id),
+            "duration": duration,
+            "elapsed": "00:00:01",
+            "elapsed_ms": elapsed_ms,
+            "skipped": false,
+            "destructive_actions": [],
+        })
+        .to_string()
+    }
+
+    fn failed(id: &str, code: &str) -> String {
+        json!({
+            "group_id": id,
+            "name": format!("GH{}", id),
+            "error_code": code,
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_parse_clock() {
+        assert_eq!(Some(Duration::from_secs(8024)), parse_clock("02:13:44"));
+        assert_eq!(
+            Some(Duration::from_secs(86400 + 8024)),
+            parse_clock("1d 02:13:44")
+        );
+        assert_eq!(None, parse_clock("02:13"));
+        assert_eq!(None, parse_clock("2h"));
+    }
+
+    #[test]
+    fn test_parse() {
+        let progress = json!({
+            "group_id": "0084",
+            "progress_time": "00:00:05",
+        })
+        .to_string();
+        let report = [
+            progress,
+            "[2021-11-14T23:15:25Z WARN gopro_merge] a log line".to_string(),
+            done("0084", "00:10:00", 60_000),
+            failed("0085", "ffmpeg_failed"),
+        ]
+        .join("\n");
+
+        let report = RunReport::parse(&report);
+        assert_eq!(2, report.movies.len());
+        assert_eq!(
+            ReportedMovie {
+                name: "GH0084".into(),
+                status: Status::Merged,
+                duration: Duration::from_secs(600),
+                elapsed: Some(Duration::from_secs(60)),
+            },
+            report.movies["0084"]
+        );
+        assert_eq!(Some(10.0), report.movies["0084"].speed());
+        assert_eq!(
+            Status::Failed("ffmpeg_failed".into()),
+            report.movies["0085"].status
+        );
+        assert_eq!(None, report.movies["0085"].speed());
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = RunReport::parse(
+            &[
+                done("0084", "00:10:00", 60_000),
+                done("0085", "00:10:00", 60_000),
+                done("0086", "00:10:00", 60_000),
+                done("0087", "00:10:00", 60_000),
+            ]
+            .join("\n"),
+        );
+        let after = RunReport::parse(
+            &[
+                // within the variance between runs
+                done("0084", "00:10:01", 70_000),
+                done("0085", "00:09:50", 60_000),
+                done("0086", "00:10:00", 120_000),
+                failed("0088", "telemetry_lost"),
+            ]
+            .join("\n"),
+        );
+        assert!(diff(&before, &before).is_empty());
+
+        let changes = diff(&before, &after);
+        assert_eq!(
+            vec![
+                Change::Duration {
+                    name: "GH0085".into(),
+                    before: Duration::from_secs(600),
+                    after: Duration::from_secs(590),
+                },
+                Change::Slower {
+                    name: "GH0086".into(),
+                    before: 10.0,
+                    after: 5.0,
+                },
+                Change::Removed {
+                    name: "GH0087".into(),
+                    status: Status::Merged,
+                },
+                Change::Added {
+                    name: "GH0088".into(),
+                    status: Status::Failed("telemetry_lost".into()),
+                },
+            ],
+            changes
+        );
+        assert_eq!(
+            "~ GH0086 merged at 5.0x realtime instead of 10.0x",
+            changes[1].to_string()
+        );
+
+        let after = RunReport::parse(&failed("0084", "ffmpeg_failed"));
+        let changes = diff(&before, &after);
+        assert_eq!(
+            Change::Status {
+                name: "GH0084".into(),
+                before: Status::Merged,
+                after: Status::Failed("ffmpeg_failed".into()),
+            },
+            changes[0]
+        );
+        assert_eq!(
+            "! GH0084 failed with ffmpeg_failed instead of merged",
+            changes[0].to_string()
+        );
+        assert_eq!(
+            "status",
+            serde_json::from_str::<Value>(&changes[0].to_json()).unwrap()["change"]
+        );
+    }
+}
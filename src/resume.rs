@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::*;
+use parking_lot::Mutex;
+use thiserror::Error;
+
+use crate::group::GroupId;
+
+/// Name of the state file `--resume` keeps in the output directory.
+pub const STATE_FILE_NAME: &str = ".gopro-merge-state";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid line {0} of the resume state file {}: {2}, remove it to start over", .1.display())]
+    InvalidLine(usize, PathBuf, String),
+
+    #[error(transparent)]
+    IO(#[from] io::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// How far an earlier run got with merging a group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum GroupState {
+    /// The group was being merged into the output, which is partial unless it completed.
+    Started(PathBuf),
+    Completed(PathBuf),
+}
+
+/// Per group completion of the runs merging into an output directory, appended to as the groups
+/// start and complete so that a run killed mid-merge can be resumed where it stopped.
+///
+/// Every line of the file is `started` or `completed`, the group name, its id and its output
+/// separated by tabs, the last line of a group telling how far it got. Lines written before ids
+/// were recorded have none.
+#[derive(Debug, Clone)]
+pub struct ResumeState {
+    path: PathBuf,
+    groups: Arc<Mutex<BTreeMap<String, GroupState>>>,
+    file: Arc<Mutex<File>>,
+}
+
+impl ResumeState {
+    /// Opens the state file of the `output` directory, creating it when there's none yet.
+    pub fn open(output: &Path) -> Result<Self> {
+        let path = output.join(STATE_FILE_NAME);
+        let groups = match fs::read_to_string(&path) {
+            Ok(contents) => parse(&path, &contents)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+        debug!(
+            "resuming from {} with {} groups",
+            path.display(),
+            groups.len()
+        );
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        Ok(ResumeState {
+            path,
+            groups: Arc::new(Mutex::new(groups)),
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The output of the `group` when an earlier run completed it and it's still there.
+    pub fn completed_output(&self, group: &str) -> Option<PathBuf> {
+        match self.groups.lock().get(group) {
+            Some(GroupState::Completed(output)) if output.exists() => Some(output.clone()),
+            _ => None,
+        }
+    }
+
+    /// Removes the partial outputs of the groups an earlier run started but didn't complete,
+    /// returning the removed paths.
+    pub fn remove_partial(&self) -> Result<Vec<PathBuf>> {
+        let mut removed = vec![];
+        for state in self.groups.lock().values() {
+            if let GroupState::Started(output) = state {
+                match fs::remove_file(output) {
+                    Ok(()) => removed.push(output.clone()),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Records the `group` with the `id` starting to be merged into `output`.
+    pub fn start(&self, group: &str, id: &GroupId, output: &Path) -> Result<()> {
+        self.record(group, id, GroupState::Started(output.to_path_buf()))
+    }
+
+    /// Records the `group` with the `id` being completely merged into `output`.
+    pub fn complete(&self, group: &str, id: &GroupId, output: &Path) -> Result<()> {
+        self.record(group, id, GroupState::Completed(output.to_path_buf()))
+    }
+
+    /// Removes the state file once every one of the `groups` is completed, nothing is left to
+    /// resume then.
+    pub fn remove_if_completed<'a>(
+        &self,
+        mut groups: impl Iterator<Item = &'a str>,
+    ) -> Result<bool> {
+        let completed = {
+            let states = self.groups.lock();
+            groups.all(|group| matches!(states.get(group), Some(GroupState::Completed(..))))
+        };
+        if completed {
+            debug!("removing resume state {}", self.path.display());
+            fs::remove_file(&self.path)?;
+        }
+
+        Ok(completed)
+    }
+
+    fn record(&self, group: &str, id: &GroupId, state: GroupState) -> Result<()> {
+        let (status, output) = match &state {
+            GroupState::Started(output) => ("started", output),
+            GroupState::Completed(output) => ("completed", output),
+        };
+        // a single write per line so that lines of concurrent groups don't interleave
+        let line = format!("{}\t{}\t{}\t{}\n", status, group, id, output.display());
+        self.file.lock().write_all(line.as_bytes())?;
+        self.groups.lock().insert(group.into(), state);
+        Ok(())
+    }
+}
+
+fn parse(path: &Path, contents: &str) -> Result<BTreeMap<String, GroupState>> {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(index, line)| {
+            parse_line(line)
+                .ok_or_else(|| Error::InvalidLine(index + 1, path.to_path_buf(), line.into()))
+        })
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<(String, GroupState)> {
+    let mut fields = line.splitn(3, '\t');
+    let (status, group, rest) = (fields.next()?, fields.next()?, fields.next()?);
+    // the id is informational, the group is looked up by name
+    let output = match rest.split_once('\t') {
+        Some((id, output)) if GroupId::parse(id).is_some() => output,
+        _ => rest,
+    };
+    let state = match status {
+        "started" => GroupState::Started(output.into()),
+        "completed" => GroupState::Completed(output.into()),
+        _ => return None,
+    };
+
+    Some((group.into(), state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    use crate::group::group_file_names;
+
+    #[test]
+    fn test_parse() {
+        let path = Path::new("/movies").join(STATE_FILE_NAME);
+        let groups = parse(
+            &path,
+            "started\tGH000084.mp4\t/movies/GH000084.mp4\n\
+             started\tGH000085.mp4\t9f86d081884c7d65\t/movies/GH000085.mp4\n\
+             completed\tGH000084.mp4\t0123456789abcdef\t/movies/GH000084.mp4\n",
+        )
+        .unwrap();
+        assert_eq!(
+            Some(&GroupState::Completed("/movies/GH000084.mp4".into())),
+            groups.get("GH000084.mp4")
+        );
+        assert_eq!(
+            Some(&GroupState::Started("/movies/GH000085.mp4".into())),
+            groups.get("GH000085.mp4")
+        );
+
+        assert!(matches!(
+            parse(
+                &path,
+                "started\tGH000084.mp4\t/movies/GH000084.mp4\nmerged\tGH000085.mp4\t/movies/GH000085.mp4"
+            ),
+            Err(Error::InvalidLine(2, ..))
+        ));
+        assert!(matches!(
+            parse(&path, "started GH000084.mp4"),
+            Err(Error::InvalidLine(1, ..))
+        ));
+    }
+
+    #[test]
+    fn test_resume_state() {
+        let output = env::temp_dir().join("goprotest_resume_state");
+        let _ = fs::remove_dir_all(&output);
+        fs::create_dir_all(&output).unwrap();
+        let (completed, partial) = (output.join("GH000084.mp4"), output.join("GH000085.mp4"));
+
+        let groups = group_file_names(vec!["GH010084.mp4", "GH010085.mp4"]);
+        let (id, other_id) = (groups[0].id(), groups[1].id());
+
+        let state = ResumeState::open(&output).unwrap();
+        assert!(state.path().exists());
+        state.start("GH000084.mp4", &id, &completed).unwrap();
+        fs::write(&completed, "merged").unwrap();
+        state.complete("GH000084.mp4", &id, &completed).unwrap();
+        state.start("GH000085.mp4", &other_id, &partial).unwrap();
+        fs::write(&partial, "interrupted").unwrap();
+        drop(state);
+        assert!(fs::read_to_string(output.join(STATE_FILE_NAME))
+            .unwrap()
+            .starts_with(&format!(
+                "started\tGH000084.mp4\t{}\t{}\n",
+                id,
+                completed.display()
+            )));
+
+        let state = ResumeState::open(&output).unwrap();
+        assert_eq!(
+            Some(completed.clone()),
+            state.completed_output("GH000084.mp4")
+        );
+        assert_eq!(None, state.completed_output("GH000085.mp4"));
+        assert_eq!(vec![partial.clone()], state.remove_partial().unwrap());
+        assert!(!partial.exists());
+        assert!(completed.exists());
+
+        let groups = ["GH000084.mp4", "GH000085.mp4"];
+        assert!(!state.remove_if_completed(groups.iter().copied()).unwrap());
+        state.complete("GH000085.mp4", &other_id, &partial).unwrap();
+        assert!(state.remove_if_completed(groups.iter().copied()).unwrap());
+        assert!(!state.path().exists());
+
+        // a completed output removed since has to be merged again
+        fs::remove_file(&completed).unwrap();
+        let state = ResumeState::open(&output).unwrap();
+        state.complete("GH000084.mp4", &id, &completed).unwrap();
+        assert_eq!(None, state.completed_output("GH000084.mp4"));
+
+        fs::remove_dir_all(&output).unwrap();
+    }
+}
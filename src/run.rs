@@ -0,0 +1,181 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use derive_more::Display;
+use log::LevelFilter;
+
+/// Unique id of a single run, attached to logs, events and temporary files so that
+/// artifacts of several runs can be told apart once aggregated.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Display)]
+#[display(fmt = "{}", _0)]
+pub struct RunId(String);
+
+impl RunId {
+    /// Generates a random (version 4) UUID.
+    pub fn generate() -> Self {
+        let bytes = [random_u64().to_be_bytes(), random_u64().to_be_bytes()].concat();
+        RunId(format_uuid_v4(&bytes))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for RunId {
+    fn default() -> Self {
+        RunId::generate()
+    }
+}
+
+/// When a run stops starting new groups, the ones left over are deferred to a follow-up run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline {
+    pub at: Instant,
+    /// Kill the in-flight merges at the deadline instead of letting them finish.
+    pub hard_stop: bool,
+}
+
+impl Deadline {
+    pub fn after(started: Instant, max_runtime: Duration, hard_stop: bool) -> Self {
+        Deadline {
+            at: started + max_runtime,
+            hard_stop,
+        }
+    }
+
+    pub fn passed(&self, now: Instant) -> bool {
+        now >= self.at
+    }
+
+    /// Whether in-flight merges have to be killed by `now`.
+    pub fn stops(&self, now: Instant) -> bool {
+        self.hard_stop && self.passed(now)
+    }
+}
+
+/// Set when the run is interrupted, e.g. with Ctrl-C. The in-flight merges are killed and no new
+/// groups are started once it is.
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt(Arc<AtomicBool>);
+
+impl Interrupt {
+    /// Interrupts the run, returning whether it already was.
+    pub fn trigger(&self) -> bool {
+        self.0.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// RandomState is seeded from the OS once per thread and its keys change with every instance,
+// which is enough entropy for telling runs apart without pulling in a rng
+fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.write_u32(process::id());
+    hasher.finish()
+}
+
+fn format_uuid_v4(bytes: &[u8]) -> String {
+    let mut bytes = bytes.to_vec();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Initializes the logger at `level`, from `--verbose`, or from `RUST_LOG` when it's set,
+/// attaching the run id to every line.
+pub fn init_logger(run_id: &RunId, level: LevelFilter) {
+    let run_id = run_id.clone();
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_env(env_logger::Env::default())
+        .format(move |buf, record| {
+            writeln!(
+                buf,
+                "[{} {} {} run={}] {}",
+                buf.timestamp(),
+                buf.default_styled_level(record.level()),
+                record.target(),
+                run_id,
+                record.args()
+            )
+        })
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_uuid_v4() {
+        assert_eq!(
+            "00000000-0000-4000-8000-000000000000",
+            format_uuid_v4(&[0; 16])
+        );
+        assert_eq!(
+            "ffffffff-ffff-4fff-bfff-ffffffffffff",
+            format_uuid_v4(&[0xff; 16])
+        );
+    }
+
+    #[test]
+    fn test_deadline() {
+        let started = Instant::now();
+        let hour = Duration::from_secs(60 * 60);
+
+        let soft = Deadline::after(started, hour, false);
+        assert!(!soft.passed(started));
+        assert!(soft.passed(started + hour));
+        assert!(!soft.stops(started + hour * 2));
+
+        let hard = Deadline::after(started, hour, true);
+        assert!(!hard.stops(started + hour / 2));
+        assert!(hard.stops(started + hour));
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let interrupt = Interrupt::default();
+        let shared = interrupt.clone();
+        assert!(!interrupt.is_triggered());
+
+        assert!(!shared.trigger());
+        assert!(interrupt.is_triggered());
+        assert!(interrupt.trigger());
+    }
+
+    #[test]
+    fn test_run_id_generate() {
+        let id = RunId::generate();
+        assert_eq!(36, id.as_str().len());
+        assert_eq!(Some('4'), id.as_str().chars().nth(14));
+        assert_ne!(id, RunId::generate());
+    }
+}
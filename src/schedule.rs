@@ -0,0 +1,513 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use log::*;
+use thiserror::Error;
+
+use crate::filter::FileRange;
+use crate::group::{MovieGroup, MovieGroups};
+use crate::system::{Clock, Fs};
+use crate::timestamp;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid --schedule {0}, one of \"name\" | \"longest-first\" | \"interleave\" | \"interleave:<large groups per wave>\"")]
+    InvalidSchedule(String),
+
+    #[error("Invalid --priority {0}, one of \"file:<file numbers>\" | \"date:<YYYY-MM-DD>\" | \"dir:<directory>\"")]
+    InvalidPriority(String),
+}
+
+/// The order in which the groups start merging, the ones started first being merged in parallel
+/// with each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// By name, the order the groups are listed in.
+    Name,
+    /// The largest groups first, so that the last ones to start are quick to merge.
+    LongestFirst,
+    /// Every wave of parallel merges mixes the largest groups left with the smallest ones, which
+    /// probe and hash while the large ones keep the disks busy copying. Takes how many of the
+    /// largest groups start per wave, half of the wave by default.
+    Interleave(Option<usize>),
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule::Name
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Name => write!(f, "name"),
+            Schedule::LongestFirst => write!(f, "longest-first"),
+            Schedule::Interleave(None) => write!(f, "interleave"),
+            Schedule::Interleave(Some(large)) => write!(f, "interleave:{}", large),
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidSchedule(s.into());
+        Ok(match s.split_once(':') {
+            None if s == "name" => Schedule::Name,
+            None if s == "longest-first" => Schedule::LongestFirst,
+            None if s == "interleave" => Schedule::Interleave(None),
+            Some(("interleave", large)) => match large.parse() {
+                Ok(large) if large > 0 => Schedule::Interleave(Some(large)),
+                _ => return Err(invalid()),
+            },
+            _ => return Err(invalid()),
+        })
+    }
+}
+
+impl Schedule {
+    /// The order in which groups of the `sizes` start merging on `workers` parallel workers, as
+    /// indices into `sizes`.
+    pub fn order(&self, sizes: &[u64], workers: usize) -> Vec<usize> {
+        let mut largest_first = (0..sizes.len()).collect::<Vec<_>>();
+        // stable, groups of the same size stay ordered by name
+        largest_first.sort_by_key(|index| std::cmp::Reverse(sizes[*index]));
+
+        match *self {
+            Schedule::Name => (0..sizes.len()).collect(),
+            Schedule::LongestFirst => largest_first,
+            Schedule::Interleave(large) => {
+                let workers = workers.max(1);
+                let large = large.unwrap_or((workers + 1) / 2).clamp(1, workers);
+                interleave(&largest_first, workers, large)
+            }
+        }
+    }
+}
+
+/// Takes `large` groups from the front and fills the rest of every wave of `workers` from the
+/// back of `largest_first`.
+fn interleave(largest_first: &[usize], workers: usize, large: usize) -> Vec<usize> {
+    let mut remaining = largest_first.iter().copied();
+    let mut order = Vec::with_capacity(largest_first.len());
+    while order.len() < largest_first.len() {
+        order.extend(remaining.by_ref().take(large));
+        order.extend(remaining.by_ref().rev().take(workers - large));
+    }
+
+    order
+}
+
+/// Groups merged before all the others regardless of the [Schedule], from `--priority`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Priority {
+    /// Groups with these file numbers, e.g. `file:1234` or `file:1250-1260`.
+    File(FileRange),
+    /// Groups whose first chapter was recorded on the date, as the camera clock showed it, e.g.
+    /// `date:2021-11-14`.
+    Date(String),
+    /// Groups with chapters in the directory or its subdirectories, relative to the input
+    /// directory, e.g. `dir:DCIM/101GOPRO`.
+    Dir(PathBuf),
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Priority::File(range) => write!(f, "file:{}", range),
+            Priority::Date(date) => write!(f, "date:{}", date),
+            Priority::Dir(dir) => write!(f, "dir:{}", dir.display()),
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidPriority(s.into());
+        Ok(match s.split_once(':').ok_or_else(invalid)? {
+            ("file", range) => Priority::File(range.parse().map_err(|_| invalid())?),
+            ("date", date) => {
+                humantime::parse_rfc3339(&format!("{}T00:00:00Z", date)).map_err(|_| invalid())?;
+                Priority::Date(date.into())
+            }
+            ("dir", dir) if !dir.is_empty() => Priority::Dir(dir.into()),
+            _ => return Err(invalid()),
+        })
+    }
+}
+
+impl Priority {
+    fn matches(&self, group: &MovieGroup, input: &Path, recorded: Option<&str>) -> bool {
+        match self {
+            Priority::File(range) => group
+                .fingerprint
+                .file
+                .numeric()
+                .map_or(false, |file| range.contains(file)),
+            Priority::Date(date) => recorded == Some(date.as_str()),
+            Priority::Dir(dir) => group.chapters.iter().any(|chapter| {
+                group
+                    .chapter_path(chapter, input)
+                    .parent()
+                    .map_or(false, |parent| parent.starts_with(input.join(dir)))
+            }),
+        }
+    }
+}
+
+/// The groups of a run given priority, keyed by group name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Priorities {
+    pub groups: BTreeSet<String>,
+}
+
+impl Priorities {
+    /// The `groups` found in `input` matching any of the `priorities`.
+    pub fn select(
+        groups: &[MovieGroup],
+        input: &Path,
+        priorities: &[Priority],
+        fs: &impl Fs,
+        clock: &impl Clock,
+    ) -> Self {
+        let by_date = priorities
+            .iter()
+            .any(|priority| matches!(priority, Priority::Date(..)));
+        let groups = groups
+            .iter()
+            .filter(|group| {
+                let recorded = match group.chapters.first() {
+                    Some(chapter) if by_date => {
+                        recorded_date(&group.chapter_path(chapter, input), fs, clock)
+                    }
+                    _ => None,
+                };
+                priorities
+                    .iter()
+                    .any(|priority| priority.matches(group, input, recorded.as_deref()))
+            })
+            .map(|group| group.name())
+            .collect::<BTreeSet<_>>();
+        debug!("prioritized {:?}", groups);
+
+        Priorities { groups }
+    }
+
+    pub fn contains(&self, group: &MovieGroup) -> bool {
+        self.groups.contains(&group.name())
+    }
+
+    /// Sorts the `groups` by name, the prioritized ones first.
+    pub fn sort(&self, groups: &mut MovieGroups) {
+        groups.sort();
+        // stable, each part stays sorted by name
+        groups.sort_by_key(|group| !self.contains(group));
+    }
+}
+
+/// The date the camera clock showed when the chapter at `path` was recorded, e.g. 2021-11-14.
+fn recorded_date(path: &Path, fs: &impl Fs, clock: &impl Clock) -> Option<String> {
+    let time = timestamp::wall_clock(path, fs, clock)?;
+    Some(humantime::format_rfc3339_seconds(time).to_string()[..10].to_string())
+}
+
+/// Bytes of the chapters of the `group` found in `input`, the merge copying them being most of
+/// the work. Chapters that can't be read count as empty.
+pub fn group_size(group: &MovieGroup, input: &Path) -> u64 {
+    group
+        .chapters
+        .iter()
+        .filter_map(|chapter| fs::metadata(group.chapter_path(chapter, input)).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A merge in the simulation, `latency` seconds of probing and hashing which leave the disk
+    /// idle followed by copying `bytes` megabytes.
+    #[derive(Debug, Clone, Copy)]
+    struct Job {
+        latency: f64,
+        bytes: f64,
+    }
+
+    /// How long merging the `jobs` in the `order` takes on `workers` workers, the copying ones
+    /// sharing the disk `bandwidth` while each copies at most `cap` megabytes a second.
+    fn makespan(jobs: &[Job], order: &[usize], workers: usize, bandwidth: f64, cap: f64) -> f64 {
+        const EPSILON: f64 = 1e-9;
+        let mut queue = order.iter().map(|index| jobs[*index]);
+        let mut running = vec![];
+        let mut elapsed = 0.0;
+        running.extend(queue.by_ref().take(workers));
+
+        while !running.is_empty() {
+            let copying = running
+                .iter()
+                .filter(|job: &&Job| job.latency <= EPSILON)
+                .count();
+            let rate = cap.min(bandwidth / copying.max(1) as f64);
+            let step = running
+                .iter()
+                .map(|job| {
+                    if job.latency > EPSILON {
+                        job.latency
+                    } else {
+                        job.bytes / rate
+                    }
+                })
+                .fold(f64::INFINITY, f64::min);
+
+            for job in &mut running {
+                if job.latency > EPSILON {
+                    job.latency -= step;
+                } else {
+                    job.bytes -= rate * step;
+                }
+            }
+            elapsed += step;
+            running.retain(|job| job.latency > EPSILON || job.bytes > EPSILON);
+            let free = workers - running.len();
+            running.extend(queue.by_ref().take(free));
+        }
+
+        elapsed
+    }
+
+    fn jobs(groups: &[(usize, f64, f64)]) -> Vec<Job> {
+        groups
+            .iter()
+            .flat_map(|(count, latency, bytes)| {
+                std::iter::repeat(Job {
+                    latency: *latency,
+                    bytes: *bytes,
+                })
+                .take(*count)
+            })
+            .collect()
+    }
+
+    fn simulate(schedule: Schedule, jobs: &[Job], workers: usize) -> f64 {
+        let sizes = jobs.iter().map(|job| job.bytes as u64).collect::<Vec<_>>();
+        // two disks worth of bandwidth, a single merge saturating one
+        makespan(
+            jobs,
+            &schedule.order(&sizes, workers),
+            workers,
+            200.0,
+            100.0,
+        )
+    }
+
+    #[test]
+    fn test_schedule_from_str() {
+        for schedule in &[
+            Schedule::Name,
+            Schedule::LongestFirst,
+            Schedule::Interleave(None),
+            Schedule::Interleave(Some(3)),
+        ] {
+            assert_eq!(*schedule, schedule.to_string().parse().unwrap());
+        }
+
+        for invalid in &[
+            "",
+            "shortest-first",
+            "interleave:",
+            "interleave:0",
+            "name:1",
+        ] {
+            assert!(
+                invalid.parse::<Schedule>().is_err(),
+                "{} was parsed",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_schedule_order() {
+        let sizes = [5, 1, 8, 3, 8, 2, 6];
+        assert_eq!(vec![0, 1, 2, 3, 4, 5, 6], Schedule::Name.order(&sizes, 2));
+        assert_eq!(
+            vec![2, 4, 6, 0, 3, 5, 1],
+            Schedule::LongestFirst.order(&sizes, 2)
+        );
+        assert_eq!(
+            vec![2, 1, 4, 5, 6, 3, 0],
+            Schedule::Interleave(None).order(&sizes, 2)
+        );
+        assert_eq!(
+            vec![2, 4, 1, 6, 0, 5, 3],
+            Schedule::Interleave(Some(2)).order(&sizes, 3)
+        );
+        // a wave can't have more large groups than workers
+        assert_eq!(
+            Schedule::LongestFirst.order(&sizes, 2),
+            Schedule::Interleave(Some(5)).order(&sizes, 2)
+        );
+        assert_eq!(
+            Schedule::LongestFirst.order(&sizes, 1),
+            Schedule::Interleave(None).order(&sizes, 0)
+        );
+        assert!(Schedule::Interleave(None).order(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn test_interleave_makespan() {
+        // a day of recording, a few long movies and lots of short clips
+        let day = jobs(&[(4, 10.0, 8000.0), (12, 8.0, 200.0)]);
+        let longest_first = simulate(Schedule::LongestFirst, &day, 4);
+        let interleaved = simulate(Schedule::Interleave(None), &day, 4);
+        assert!(
+            interleaved < longest_first - 5.0,
+            "interleaved {} longest first {}",
+            interleaved,
+            longest_first
+        );
+
+        // long movies of different lengths with a tail of short and medium ones
+        let trip = jobs(&[
+            (1, 12.0, 12000.0),
+            (1, 12.0, 10000.0),
+            (1, 10.0, 8000.0),
+            (1, 10.0, 6000.0),
+            (10, 8.0, 300.0),
+            (4, 9.0, 1000.0),
+        ]);
+        let longest_first = simulate(Schedule::LongestFirst, &trip, 4);
+        let interleaved = simulate(Schedule::Interleave(None), &trip, 4);
+        assert!(
+            interleaved < longest_first,
+            "interleaved {} longest first {}",
+            interleaved,
+            longest_first
+        );
+
+        // nothing to mix when all the movies are alike
+        let even = jobs(&[(8, 5.0, 4000.0)]);
+        assert_eq!(
+            simulate(Schedule::LongestFirst, &even, 4),
+            simulate(Schedule::Interleave(None), &even, 4)
+        );
+    }
+
+    #[test]
+    fn test_priority_from_str() {
+        for priority in &[
+            "file:1234",
+            "file:1250-1260",
+            "date:2021-11-14",
+            "dir:/movies/101GOPRO",
+        ] {
+            assert_eq!(*priority, priority.parse::<Priority>().unwrap().to_string());
+        }
+
+        for invalid in &[
+            "",
+            "1234",
+            "file:",
+            "file:1260-1250",
+            "date:2021-13-01",
+            "date:yesterday",
+            "dir:",
+            "name:GH010084.mp4",
+        ] {
+            assert!(
+                invalid.parse::<Priority>().is_err(),
+                "{} was parsed",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_priorities() {
+        use crate::group::group_file_names;
+        use crate::system::mock::{MemoryFs, MockClock};
+
+        let input = Path::new("/movies");
+        let time = |rfc3339| humantime::parse_rfc3339(rfc3339).unwrap();
+        let fs = MemoryFs::default();
+        let clock = MockClock::new(time("2022-01-01T00:00:00Z"));
+        fs.set_modified(&input.join("GH010085.mp4"), time("2021-11-14T23:15:25Z"));
+        fs.set_modified(&input.join("GH010086.mp4"), time("2021-11-15T08:00:00Z"));
+        let mut groups = group_file_names(vec![
+            "GH010084.mp4",
+            "GH010085.mp4",
+            "GH010086.mp4",
+            "GH010087.mp4",
+            "GH010088.mp4",
+        ]);
+        let select = |priorities: &[&str]| {
+            let priorities = priorities
+                .iter()
+                .map(|priority| priority.parse().unwrap())
+                .collect::<Vec<_>>();
+            Priorities::select(&groups, input, &priorities, &fs, &clock)
+                .groups
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+
+        assert!(select(&[]).is_empty());
+        assert_eq!(vec!["GH000085.mp4"], select(&["date:2021-11-14"]));
+        assert_eq!(
+            vec!["GH000085.mp4", "GH000087.mp4", "GH000088.mp4"],
+            select(&["date:2021-11-14", "file:87-88"])
+        );
+        assert_eq!(5, select(&["dir:/movies"]).len());
+        assert_eq!(5, select(&["dir:."]).len());
+        assert!(select(&["dir:/movies/101GOPRO"]).is_empty());
+
+        let priorities = Priorities {
+            groups: vec!["GH000086.mp4".to_string(), "GH000088.mp4".to_string()]
+                .into_iter()
+                .collect(),
+        };
+        groups.reverse();
+        priorities.sort(&mut groups);
+        assert_eq!(
+            vec![
+                "GH000086.mp4",
+                "GH000088.mp4",
+                "GH000084.mp4",
+                "GH000085.mp4",
+                "GH000087.mp4"
+            ],
+            groups.iter().map(|group| group.name()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_group_size() {
+        let input = std::fs::canonicalize("./tests").unwrap();
+        let group = &crate::group::group_movies(&input).unwrap()[0];
+        let expected = group
+            .chapters
+            .iter()
+            .map(|chapter| {
+                fs::metadata(group.chapter_path(chapter, &input))
+                    .unwrap()
+                    .len()
+            })
+            .sum::<u64>();
+
+        assert!(expected > 0);
+        assert_eq!(expected, group_size(group, &input));
+        // the chapters are looked up in the input only when their directories aren't known
+        let mut moved = group.clone();
+        moved.directories.clear();
+        assert_eq!(expected, group_size(&moved, &input));
+        assert_eq!(0, group_size(&moved, Path::new("/nonexistent")));
+    }
+}
@@ -0,0 +1,243 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use crate::identifier::Identifier;
+use crate::movie::{self, Fingerprint, Movie};
+
+/// How a camera names the chapters of its movies, telling apart the chapters of one recording
+/// from the other files and naming the merged movie.
+///
+/// The GoPro names are [GoPro], the default. Library users add the names of other cameras with
+/// [Schemes::register]; the movies a scheme parses are described by a [Fingerprint] whose encoding
+/// is the codec of the movie, its file the 4 characters numbering the recording and its chapter
+/// the 2 characters numbering the chapter, which the scheme maps its own names onto.
+pub trait NamingScheme: fmt::Debug + Send + Sync {
+    /// Tells the schemes apart, e.g. `gopro`.
+    fn name(&self) -> &'static str;
+
+    /// The chapter named `file_name`, failing for files the scheme doesn't name.
+    fn parse(&self, file_name: &str) -> Result<Movie, movie::Error>;
+
+    /// The name of the `chapter` of the movie as the camera names it.
+    fn chapter_file_name(&self, fingerprint: &Fingerprint, chapter: &Identifier) -> String;
+
+    /// The name of the merged movie.
+    fn movie_file_name(&self, fingerprint: &Fingerprint) -> String;
+}
+
+/// The GoPro names, e.g. GH011234.MP4:
+/// https://community.gopro.com/t5/en/GoPro-Camera-File-Naming-Convention/ta-p/390220#
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GoPro;
+
+impl NamingScheme for GoPro {
+    fn name(&self) -> &'static str {
+        "gopro"
+    }
+
+    fn parse(&self, file_name: &str) -> Result<Movie, movie::Error> {
+        Movie::try_from(file_name)
+    }
+
+    /// The first chapter of [Encoding::Legacy](crate::encoding::Encoding::Legacy) and Fusion
+    /// movies being chapter 00.
+    fn chapter_file_name(&self, fingerprint: &Fingerprint, chapter: &Identifier) -> String {
+        match (
+            fingerprint.encoding.first_chapter_prefix(),
+            chapter.numeric(),
+        ) {
+            (Some(prefix), Ok(0)) => {
+                format!("{}{}.{}", prefix, fingerprint.file, fingerprint.extension)
+            }
+            _ => format!(
+                "{}{}{}.{}",
+                fingerprint.encoding, chapter, fingerprint.file, fingerprint.extension
+            ),
+        }
+    }
+
+    fn movie_file_name(&self, fingerprint: &Fingerprint) -> String {
+        format!(
+            "{}00{}.{}",
+            fingerprint.encoding, fingerprint.file, fingerprint.extension
+        )
+    }
+}
+
+/// The scheme a movie is named by, compared by its name so that the fingerprints of the same
+/// scheme group together.
+#[derive(Clone)]
+pub struct Scheme(Arc<dyn NamingScheme>);
+
+impl Scheme {
+    pub fn new(scheme: impl NamingScheme + 'static) -> Self {
+        Scheme(Arc::new(scheme))
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.name() == GoPro.name()
+    }
+}
+
+impl std::ops::Deref for Scheme {
+    type Target = dyn NamingScheme;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl Default for Scheme {
+    fn default() -> Self {
+        Scheme::new(GoPro)
+    }
+}
+
+impl fmt::Debug for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl PartialEq for Scheme {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl Eq for Scheme {}
+
+impl Hash for Scheme {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name().hash(state)
+    }
+}
+
+impl PartialOrd for Scheme {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheme {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.name().cmp(other.name())
+    }
+}
+
+/// The schemes the movies are looked up by, in order, the GoPro names first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schemes(Vec<Scheme>);
+
+impl Default for Schemes {
+    fn default() -> Self {
+        Schemes(vec![Scheme::default()])
+    }
+}
+
+impl Schemes {
+    /// Adds the names of another camera, replacing a scheme of the same name.
+    pub fn register(&mut self, scheme: impl NamingScheme + 'static) {
+        let scheme = Scheme::new(scheme);
+        match self.0.iter_mut().find(|existing| **existing == scheme) {
+            Some(existing) => *existing = scheme,
+            None => self.0.push(scheme),
+        }
+    }
+
+    /// The chapter named `file_name` by the first scheme naming it, the error of the first
+    /// scheme otherwise.
+    pub fn parse(&self, file_name: &str) -> Result<Movie, movie::Error> {
+        let mut first_err = None;
+        for scheme in &self.0 {
+            match scheme.parse(file_name) {
+                Ok(movie) => return Ok(movie),
+                Err(err) => {
+                    first_err.get_or_insert(err);
+                }
+            }
+        }
+        Err(first_err.unwrap_or_else(|| movie::Error::InvalidFileName(file_name.into())))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Scheme> {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::encoding::Encoding;
+
+    /// Names like CAM_0084_02.MP4, the second chapter of the 84th recording.
+    #[derive(Debug)]
+    struct Numbered;
+
+    impl NamingScheme for Numbered {
+        fn name(&self) -> &'static str {
+            "numbered"
+        }
+
+        fn parse(&self, file_name: &str) -> Result<Movie, movie::Error> {
+            let invalid = || movie::Error::InvalidFileName(file_name.into());
+            let (name, extension) = file_name.rsplit_once('.').ok_or_else(invalid)?;
+            let mut parts = name.split('_');
+            if parts.next() != Some("CAM") {
+                return Err(invalid());
+            }
+            let file = parts.next().ok_or_else(invalid)?;
+            let chapter = parts.next().ok_or_else(invalid)?;
+            Ok(Movie {
+                fingerprint: Fingerprint {
+                    encoding: Encoding::Avc,
+                    file: Identifier::try_from(file)?,
+                    extension: extension.into(),
+                    scheme: Scheme::new(Numbered),
+                },
+                chapter: Identifier::try_from(chapter)?,
+            })
+        }
+
+        fn chapter_file_name(&self, fingerprint: &Fingerprint, chapter: &Identifier) -> String {
+            format!(
+                "CAM_{}_{}.{}",
+                fingerprint.file, chapter, fingerprint.extension
+            )
+        }
+
+        fn movie_file_name(&self, fingerprint: &Fingerprint) -> String {
+            format!("CAM_{}.{}", fingerprint.file, fingerprint.extension)
+        }
+    }
+
+    #[test]
+    fn test_schemes_parse() {
+        let mut schemes = Schemes::default();
+        assert!(schemes.parse("CAM_0084_02.MP4").is_err());
+
+        schemes.register(Numbered);
+        schemes.register(Numbered);
+        assert_eq!(2, schemes.iter().count());
+
+        let movie = schemes.parse("CAM_0084_02.MP4").unwrap();
+        assert_eq!("numbered", movie.fingerprint.scheme.name());
+        assert_eq!("CAM_0084_02.MP4", movie.to_string());
+        assert_eq!("CAM_0084.MP4", movie.fingerprint.to_string());
+
+        let gopro = schemes.parse("GH020084.MP4").unwrap();
+        assert!(gopro.fingerprint.scheme.is_default());
+        assert_eq!("GH000084.MP4", gopro.fingerprint.to_string());
+        // the same numbers named by another scheme are another movie
+        assert_ne!(movie.fingerprint, gopro.fingerprint);
+
+        assert!(matches!(
+            schemes.parse("picture.png"),
+            Err(movie::Error::InvalidFileName(_))
+        ));
+    }
+}
@@ -0,0 +1,236 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use log::*;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::jobs::{self, Job, Jobs};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to serve on {0}: {1}")]
+    Bind(String, io::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Clients sending their request slower than this are dropped, so they can't pile up threads.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves the `jobs` of the run over HTTP on `address`, e.g. "0.0.0.0:8080", from a background
+/// thread for as long as the process lives. Returns the address bound, for port 0 the one picked.
+///
+/// * `GET /jobs` lists the movies with their state and percentage
+/// * `GET /jobs/<name>` shows a single one
+/// * `POST /jobs/<name>/cancel` stops it, removing its partial output
+pub fn serve(address: &str, jobs: Jobs) -> Result<SocketAddr> {
+    let bind = |err| Error::Bind(address.into(), err);
+    let listener = TcpListener::bind(address).map_err(bind)?;
+    let local = listener.local_addr().map_err(bind)?;
+    info!("serving the state of the run on {}", local);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("accepting a status connection: {}", err);
+                    continue;
+                }
+            };
+            let jobs = jobs.clone();
+            thread::spawn(move || {
+                if let Err(err) = handle(&stream, &jobs) {
+                    debug!("answering {:?}: {}", stream.peer_addr(), err);
+                }
+            });
+        }
+    });
+
+    Ok(local)
+}
+
+fn handle(stream: &TcpStream, jobs: &Jobs) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // none of the endpoints take a body, the headers are only read past
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let (status, body) = respond(&request, jobs);
+    debug!("answering {} with {}", request.trim(), status);
+    let body = format!("{}\n", body);
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// The status line and body answering the HTTP `request` line, e.g. "GET /jobs HTTP/1.1".
+fn respond(request: &str, jobs: &Jobs) -> (&'static str, Value) {
+    let mut parts = request.split_whitespace();
+    let (method, path) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+    // the query string of a browser refreshing the page is ignored
+    let path = path.split('?').next().unwrap_or_default();
+    let segments = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+
+    match (method, segments.as_slice()) {
+        ("GET", []) | ("GET", ["jobs"]) => (
+            "200 OK",
+            Value::Array(jobs.list().iter().map(job_json).collect()),
+        ),
+        ("GET", ["jobs", name]) => match jobs.get(name) {
+            Some(job) => ("200 OK", job_json(&job)),
+            None => not_found(&jobs::Error::UnknownJob(name.to_string())),
+        },
+        ("POST", ["jobs", name, "cancel"]) => match jobs.cancel(name) {
+            Ok(job) => {
+                info!("cancelling {} as asked over HTTP", name);
+                ("202 Accepted", job_json(&job))
+            }
+            Err(err @ jobs::Error::UnknownJob(_)) => not_found(&err),
+            Err(err) => ("409 Conflict", json!({ "error": err.to_string() })),
+        },
+        ("GET", _) | ("POST", _) => ("404 Not Found", json!({ "error": "No such endpoint" })),
+        _ => (
+            "405 Method Not Allowed",
+            json!({ "error": format!("Unsupported method {}", method) }),
+        ),
+    }
+}
+
+fn not_found(err: &jobs::Error) -> (&'static str, Value) {
+    ("404 Not Found", json!({ "error": err.to_string() }))
+}
+
+fn job_json(job: &Job) -> Value {
+    json!({
+        "name": job.name,
+        "state": job.state.to_string(),
+        "percentage": job.percentage(),
+        "duration_ms": job.len.as_millis() as u64,
+        "progress_ms": job.progress.as_millis() as u64,
+        "output": job.output.as_ref().map(|output| output.display().to_string()),
+        "error": job.error,
+        "error_code": job.error_code.map(|code| code.to_string()),
+        "cancelled": job.cancelled,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    use crate::group::group_file_names;
+
+    fn jobs() -> Jobs {
+        let jobs = Jobs::default();
+        for group in group_file_names(vec!["GH010084.mp4", "GH010085.mp4"]) {
+            jobs.queue(&group);
+        }
+        jobs.start("GH000084.mp4");
+        jobs.set_len("GH000084.mp4", Duration::from_secs(10));
+        jobs.update("GH000084.mp4", Duration::from_secs(5));
+        jobs
+    }
+
+    #[test]
+    fn test_respond() {
+        let jobs = jobs();
+
+        let (status, body) = respond("GET /jobs HTTP/1.1\r\n", &jobs);
+        assert_eq!("200 OK", status);
+        assert_eq!(
+            json!([
+                {
+                    "name": "GH000084.mp4",
+                    "state": "running",
+                    "percentage": 50,
+                    "duration_ms": 10000,
+                    "progress_ms": 5000,
+                    "output": null,
+                    "error": null,
+                    "error_code": null,
+                    "cancelled": false,
+                },
+                {
+                    "name": "GH000085.mp4",
+                    "state": "queued",
+                    "percentage": null,
+                    "duration_ms": 0,
+                    "progress_ms": 0,
+                    "output": null,
+                    "error": null,
+                    "error_code": null,
+                    "cancelled": false,
+                },
+            ]),
+            body
+        );
+        assert_eq!(body, respond("GET /?refresh=1 HTTP/1.1", &jobs).1);
+        assert_eq!(
+            "GH000085.mp4",
+            respond("GET /jobs/GH000085.mp4 HTTP/1.1", &jobs).1["name"]
+        );
+
+        let (status, body) = respond("POST /jobs/GH000085.mp4/cancel HTTP/1.1", &jobs);
+        assert_eq!("202 Accepted", status);
+        assert_eq!(true, body["cancelled"]);
+        assert!(jobs.is_cancelled("GH000085.mp4"));
+
+        assert_eq!(
+            "404 Not Found",
+            respond("GET /jobs/GH000099.mp4 HTTP/1.1", &jobs).0
+        );
+        assert_eq!(
+            "404 Not Found",
+            respond("POST /jobs/GH000099.mp4/cancel HTTP/1.1", &jobs).0
+        );
+        assert_eq!("404 Not Found", respond("GET /movies HTTP/1.1", &jobs).0);
+        assert_eq!(
+            "405 Method Not Allowed",
+            respond("DELETE /jobs/GH000085.mp4 HTTP/1.1", &jobs).0
+        );
+        assert_eq!("405 Method Not Allowed", respond("", &jobs).0);
+    }
+
+    #[test]
+    fn test_serve() {
+        let address = serve("127.0.0.1:0", jobs()).unwrap();
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"GET /jobs/GH000084.mp4 HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        let job: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(50, job["percentage"]);
+    }
+}
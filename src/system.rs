@@ -0,0 +1,649 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Source of time, abstracted so that tests can simulate time passing without sleeping.
+pub trait Clock: Clone + Send + Sync + 'static {
+    fn now(&self) -> SystemTime;
+    fn instant(&self) -> Instant;
+    /// Seconds the local time of the machine is ahead of UTC at `time`, following the
+    /// daylight-saving rules of its zone.
+    fn utc_offset(&self, time: SystemTime) -> i32;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn utc_offset(&self, time: SystemTime) -> i32 {
+        utc_offset(time)
+    }
+}
+
+/// Wall clock jumps up to it are taken for adjustments, e.g. NTP corrections, rather than sleep.
+const SUSPEND_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// Notices the machine having been suspended by the wall clock running ahead of the monotonic
+/// clock, which stops while asleep on linux and macos.
+#[derive(Debug, Clone)]
+pub struct SuspendDetector<C: Clock> {
+    clock: C,
+    last: (SystemTime, Instant),
+}
+
+impl<C: Clock> SuspendDetector<C> {
+    pub fn new(clock: C) -> Self {
+        let last = (clock.now(), clock.instant());
+        SuspendDetector { clock, last }
+    }
+
+    /// For how long the machine was suspended since the last check, if it was.
+    pub fn check(&mut self) -> Option<Duration> {
+        let now = (self.clock.now(), self.clock.instant());
+        let wall = now.0.duration_since(self.last.0).unwrap_or_default();
+        let monotonic = now.1 - self.last.1;
+        self.last = now;
+
+        wall.checked_sub(monotonic)
+            .filter(|slept| *slept >= SUSPEND_THRESHOLD)
+    }
+}
+
+/// The filesystem operations done outside of the converters, abstracted so that tests can
+/// simulate failures without touching the real disk.
+pub trait Fs: Clone + Send + Sync + 'static {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+    /// The files and directories directly in the directory `path`, sorted.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+    fn modified(&self, path: &Path) -> io::Result<SystemTime>;
+    /// Size of the file `path` in bytes.
+    fn len(&self, path: &Path) -> io::Result<u64>;
+    /// Sets the modification and access times of `path`, and its creation time on Windows.
+    fn set_times(&self, path: &Path, time: SystemTime) -> io::Result<()>;
+    /// Bytes available to unprivileged users on the volume of `path`.
+    fn available_space(&self, path: &Path) -> io::Result<u64>;
+    /// Whether nothing can be written to `path`, e.g. a locked memory card or a DVD.
+    fn read_only(&self, path: &Path) -> io::Result<bool>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl Fs for RealFs {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+        Ok(entries)
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        fs::metadata(path)?.modified()
+    }
+
+    fn len(&self, path: &Path) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    fn set_times(&self, path: &Path, time: SystemTime) -> io::Result<()> {
+        set_times(path, time)
+    }
+
+    fn available_space(&self, path: &Path) -> io::Result<u64> {
+        available_space(path)
+    }
+
+    fn read_only(&self, path: &Path) -> io::Result<bool> {
+        read_only(path)
+    }
+}
+
+#[cfg(unix)]
+// the widths of the tm fields differ between platforms
+#[allow(clippy::unnecessary_cast)]
+fn utc_offset(time: SystemTime) -> i32 {
+    use std::time::UNIX_EPOCH;
+
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => since_epoch.as_secs() as libc::time_t,
+        Err(_) => return 0,
+    };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        return 0;
+    }
+
+    tm.tm_gmtoff as i32
+}
+
+#[cfg(windows)]
+fn utc_offset(time: SystemTime) -> i32 {
+    use std::ptr;
+
+    use winapi::shared::minwindef::FILETIME;
+    use winapi::um::minwinbase::SYSTEMTIME;
+    use winapi::um::timezoneapi::{
+        FileTimeToSystemTime, SystemTimeToFileTime, SystemTimeToTzSpecificLocalTime,
+    };
+
+    let utc = match filetime(time) {
+        Ok(utc) => utc,
+        Err(_) => return 0,
+    };
+    let mut utc_system: SYSTEMTIME = unsafe { std::mem::zeroed() };
+    let mut local_system: SYSTEMTIME = unsafe { std::mem::zeroed() };
+    let mut local: FILETIME = unsafe { std::mem::zeroed() };
+    let ok = unsafe {
+        FileTimeToSystemTime(&utc, &mut utc_system) != 0
+            && SystemTimeToTzSpecificLocalTime(ptr::null(), &utc_system, &mut local_system) != 0
+            && SystemTimeToFileTime(&local_system, &mut local) != 0
+    };
+    if !ok {
+        return 0;
+    }
+
+    let intervals = |filetime: &FILETIME| {
+        (u64::from(filetime.dwHighDateTime) << 32 | u64::from(filetime.dwLowDateTime)) as i64
+    };
+    ((intervals(&local) - intervals(&utc)) / 10_000_000) as i32
+}
+
+#[cfg(not(any(unix, windows)))]
+fn utc_offset(_: SystemTime) -> i32 {
+    0
+}
+
+#[cfg(unix)]
+// the widths of the timeval fields differ between platforms
+#[allow(clippy::unnecessary_cast)]
+fn set_times(path: &Path, time: SystemTime) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::time::UNIX_EPOCH;
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let since_epoch = time
+        .duration_since(UNIX_EPOCH)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let timeval = libc::timeval {
+        tv_sec: since_epoch.as_secs() as libc::time_t,
+        tv_usec: since_epoch.subsec_micros() as libc::suseconds_t,
+    };
+    if unsafe { libc::utimes(path.as_ptr(), [timeval, timeval].as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn set_times(path: &Path, time: SystemTime) -> io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+
+    use winapi::um::fileapi::SetFileTime;
+
+    let filetime = filetime(time)?;
+    let file = fs::OpenOptions::new().write(true).open(path)?;
+    let ok = unsafe { SetFileTime(file.as_raw_handle() as _, &filetime, &filetime, &filetime) };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn filetime(time: SystemTime) -> io::Result<winapi::shared::minwindef::FILETIME> {
+    // FILETIME counts 100ns intervals since 1601-01-01
+    const EPOCH_DIFFERENCE_SECS: u64 = 11_644_473_600;
+
+    let since_epoch = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let intervals = (since_epoch.as_secs() + EPOCH_DIFFERENCE_SECS) * 10_000_000
+        + u64::from(since_epoch.subsec_nanos() / 100);
+    Ok(winapi::shared::minwindef::FILETIME {
+        dwLowDateTime: intervals as u32,
+        dwHighDateTime: (intervals >> 32) as u32,
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+fn set_times(_: &Path, _: SystemTime) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "setting file times isn't supported on this platform",
+    ))
+}
+
+#[cfg(unix)]
+fn statvfs(path: &Path) -> io::Result<libc::statvfs> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(stat)
+}
+
+#[cfg(unix)]
+// the widths of the statvfs fields differ between platforms
+#[allow(clippy::unnecessary_cast)]
+fn available_space(path: &Path) -> io::Result<u64> {
+    let stat = statvfs(path)?;
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(unix)]
+// the widths of the statvfs fields differ between platforms
+#[allow(clippy::unnecessary_cast)]
+fn read_only(path: &Path) -> io::Result<bool> {
+    // a volume mounted read-only, or a directory without write permissions on a writable one
+    Ok(statvfs(path)?.f_flag as u64 & libc::ST_RDONLY as u64 != 0
+        || fs::metadata(path)?.permissions().readonly())
+}
+
+#[cfg(not(unix))]
+fn read_only(path: &Path) -> io::Result<bool> {
+    Ok(fs::metadata(path)?.permissions().readonly())
+}
+
+#[cfg(windows)]
+fn available_space(path: &Path) -> io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+
+    use winapi::um::fileapi::GetDiskFreeSpaceExW;
+    use winapi::um::winnt::ULARGE_INTEGER;
+
+    let path = path
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect::<Vec<_>>();
+    let mut available: ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            path.as_ptr(),
+            &mut available,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(unsafe { *available.QuadPart() })
+}
+
+#[cfg(not(any(unix, windows)))]
+fn available_space(_: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "querying the available space isn't supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+
+    use std::collections::{HashMap, HashSet};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use parking_lot::Mutex;
+
+    #[derive(Debug, Clone)]
+    pub struct MockClock {
+        base: (SystemTime, Instant),
+        elapsed: Arc<Mutex<Duration>>,
+        suspended: Arc<Mutex<Duration>>,
+        /// The offsets of the local time and from when on they apply, in UTC until the first.
+        utc_offsets: Arc<Mutex<Vec<(SystemTime, i32)>>>,
+    }
+
+    impl MockClock {
+        pub fn new(now: SystemTime) -> Self {
+            MockClock {
+                base: (now, Instant::now()),
+                elapsed: Default::default(),
+                suspended: Default::default(),
+                utc_offsets: Default::default(),
+            }
+        }
+
+        /// The local time is `offset` seconds ahead of UTC from `from` on, e.g. at a
+        /// daylight-saving changeover.
+        pub fn set_utc_offset(&self, from: SystemTime, offset: i32) {
+            let mut utc_offsets = self.utc_offsets.lock();
+            utc_offsets.push((from, offset));
+            utc_offsets.sort();
+        }
+
+        pub fn advance(&self, duration: Duration) {
+            *self.elapsed.lock() += duration;
+        }
+
+        /// Advances only the wall clock, like a suspended machine does.
+        pub fn suspend(&self, duration: Duration) {
+            *self.suspended.lock() += duration;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> SystemTime {
+            self.base.0 + *self.elapsed.lock() + *self.suspended.lock()
+        }
+
+        fn instant(&self) -> Instant {
+            self.base.1 + *self.elapsed.lock()
+        }
+
+        fn utc_offset(&self, time: SystemTime) -> i32 {
+            self.utc_offsets
+                .lock()
+                .iter()
+                .rev()
+                .find(|(from, _)| *from <= time)
+                .map_or(0, |(_, offset)| *offset)
+        }
+    }
+
+    /// Cloneable in memory stream, e.g. standing in for the stdout of the json reporter.
+    #[derive(Clone, Default)]
+    pub struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        /// Every line parsed as json, panics if any of them isn't.
+        pub fn lines(&self) -> Vec<serde_json::Value> {
+            String::from_utf8(self.0.lock().clone())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct MemoryFsState {
+        dirs: HashSet<PathBuf>,
+        files: HashMap<PathBuf, (Vec<u8>, SystemTime)>,
+        failing: HashSet<PathBuf>,
+        available_space: Option<u64>,
+        read_only: HashSet<PathBuf>,
+    }
+
+    /// In memory filesystem, operations on paths marked with [MemoryFs::fail] return errors.
+    #[derive(Debug, Clone, Default)]
+    pub struct MemoryFs {
+        state: Arc<Mutex<MemoryFsState>>,
+    }
+
+    impl MemoryFs {
+        pub fn fail(&self, path: &Path) {
+            self.state.lock().failing.insert(path.to_path_buf());
+        }
+
+        /// Bytes available on every volume, unlimited unless set.
+        pub fn set_available_space(&self, available: u64) {
+            self.state.lock().available_space = Some(available);
+        }
+
+        /// Makes `path` and everything under it read-only.
+        pub fn set_read_only(&self, path: &Path) {
+            self.state.lock().read_only.insert(path.to_path_buf());
+        }
+
+        pub fn set_modified(&self, path: &Path, modified: SystemTime) {
+            self.state
+                .lock()
+                .files
+                .entry(path.to_path_buf())
+                .or_insert_with(|| (vec![], modified))
+                .1 = modified;
+        }
+
+        pub fn remove_file(&self, path: &Path) -> io::Result<()> {
+            self.check(path)?;
+            self.state
+                .lock()
+                .files
+                .remove(path)
+                .map(|_| ())
+                .ok_or_else(|| Self::not_found(path))
+        }
+
+        pub fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            self.check(path)?;
+            self.state
+                .lock()
+                .files
+                .insert(path.to_path_buf(), (contents.to_vec(), SystemTime::now()));
+            Ok(())
+        }
+
+        pub fn exists(&self, path: &Path) -> bool {
+            let state = self.state.lock();
+            state.dirs.contains(path) || state.files.contains_key(path)
+        }
+
+        fn check(&self, path: &Path) -> io::Result<()> {
+            if self.state.lock().failing.contains(path) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("simulated failure for {}", path.display()),
+                ));
+            }
+
+            Ok(())
+        }
+
+        fn not_found(path: &Path) -> io::Error {
+            io::Error::new(io::ErrorKind::NotFound, path.display().to_string())
+        }
+    }
+
+    impl Fs for MemoryFs {
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            self.check(path)?;
+            self.state.lock().dirs.insert(path.to_path_buf());
+            Ok(())
+        }
+
+        fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+            self.check(path)?;
+            let mut state = self.state.lock();
+            if !state.dirs.remove(path) {
+                return Err(Self::not_found(path));
+            }
+            state.files.retain(|file, _| !file.starts_with(path));
+            state.dirs.retain(|dir| !dir.starts_with(path));
+            Ok(())
+        }
+
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+            self.check(path)?;
+            let state = self.state.lock();
+            if !state.dirs.contains(path) {
+                return Err(Self::not_found(path));
+            }
+            let mut entries = state
+                .dirs
+                .iter()
+                .chain(state.files.keys())
+                .filter(|entry| entry.parent() == Some(path))
+                .cloned()
+                .collect::<Vec<_>>();
+            entries.sort();
+            Ok(entries)
+        }
+
+        fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+            self.check(path)?;
+            self.state
+                .lock()
+                .files
+                .get(path)
+                .map(|(_, modified)| *modified)
+                .ok_or_else(|| Self::not_found(path))
+        }
+
+        fn len(&self, path: &Path) -> io::Result<u64> {
+            self.check(path)?;
+            self.state
+                .lock()
+                .files
+                .get(path)
+                .map(|(contents, _)| contents.len() as u64)
+                .ok_or_else(|| Self::not_found(path))
+        }
+
+        fn set_times(&self, path: &Path, time: SystemTime) -> io::Result<()> {
+            self.check(path)?;
+            self.state
+                .lock()
+                .files
+                .get_mut(path)
+                .map(|(_, modified)| *modified = time)
+                .ok_or_else(|| Self::not_found(path))
+        }
+
+        fn available_space(&self, path: &Path) -> io::Result<u64> {
+            self.check(path)?;
+            Ok(self.state.lock().available_space.unwrap_or(u64::MAX))
+        }
+
+        fn read_only(&self, path: &Path) -> io::Result<bool> {
+            self.check(path)?;
+            Ok(self
+                .state
+                .lock()
+                .read_only
+                .iter()
+                .any(|read_only| path.starts_with(read_only)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::*;
+    use super::*;
+
+    #[test]
+    fn test_suspend_detector() {
+        let clock = MockClock::new(SystemTime::now());
+        let mut detector = SuspendDetector::new(clock.clone());
+        assert_eq!(None, detector.check());
+
+        clock.advance(Duration::from_secs(60 * 60));
+        assert_eq!(None, detector.check());
+
+        // clock corrections aren't sleep
+        clock.suspend(Duration::from_secs(5));
+        assert_eq!(None, detector.check());
+
+        clock.advance(Duration::from_secs(1));
+        clock.suspend(Duration::from_secs(8 * 60 * 60));
+        assert_eq!(Some(Duration::from_secs(8 * 60 * 60)), detector.check());
+        assert_eq!(None, detector.check());
+    }
+
+    #[test]
+    fn test_mock_clock() {
+        let now = SystemTime::now();
+        let clock = MockClock::new(now);
+        let instant = clock.instant();
+
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(now + Duration::from_secs(3600), clock.now());
+        assert_eq!(Duration::from_secs(3600), clock.instant() - instant);
+    }
+
+    #[test]
+    fn test_real_fs_available_space() {
+        assert!(RealFs.available_space(&std::env::temp_dir()).unwrap() > 0);
+        assert!(RealFs
+            .available_space(Path::new("/gopro-merge/nonexistent"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_real_fs_set_times() {
+        let path = std::env::temp_dir().join("goprotest_real_fs_set_times.mp4");
+        fs::write(&path, b"").unwrap();
+        let time = humantime::parse_rfc3339("2021-11-14T23:15:25Z").unwrap();
+
+        RealFs.set_times(&path, time).unwrap();
+        assert_eq!(time, RealFs.modified(&path).unwrap());
+        assert!(RealFs
+            .set_times(Path::new("/gopro-merge/nonexistent"), time)
+            .is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_memory_fs() {
+        let fs = MemoryFs::default();
+        let dir = Path::new("/workspace");
+        let file = dir.join("list.txt");
+
+        fs.create_dir_all(dir).unwrap();
+        fs.write(&file, b"file 'a'").unwrap();
+        assert!(fs.exists(&file));
+        assert_eq!(vec![file.clone()], fs.read_dir(dir).unwrap());
+
+        fs.remove_dir_all(dir).unwrap();
+        assert!(!fs.exists(&file));
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            fs.remove_file(&file).unwrap_err().kind()
+        );
+
+        fs.fail(dir);
+        assert_eq!(
+            io::ErrorKind::PermissionDenied,
+            fs.create_dir_all(dir).unwrap_err().kind()
+        );
+    }
+}
@@ -0,0 +1,515 @@
+// without the feature only the parser tests use it
+#![cfg_attr(not(feature = "telemetry"), allow(dead_code, unused_imports))]
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::mp4::{self, BoxType};
+
+/// Sample entry format of the GPMF telemetry track.
+const GPMD: &BoxType = b"gpmd";
+
+const STRM: &[u8; 4] = b"STRM";
+const SCAL: &[u8; 4] = b"SCAL";
+const GPSF: &[u8; 4] = b"GPSF";
+const GPS5: &[u8; 4] = b"GPS5";
+const GPSU: &[u8; 4] = b"GPSU";
+
+/// How long the fixes of a GPS5 entry span, the cameras writing a telemetry sample a second.
+const GPS_SAMPLE_DURATION: Duration = Duration::from_secs(1);
+
+/// A position recorded by the GPS of the camera, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GpsFix {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Meters above the WGS84 ellipsoid.
+    pub altitude: f64,
+    /// When it was recorded according to the GPS, unknown for cameras without GPSU entries.
+    pub time: Option<SystemTime>,
+}
+
+/// Reads the GPS fixes from the GPMF telemetry track of the movie at `path`, empty for movies
+/// without one, e.g. recorded with the GPS off or by cameras without it.
+#[cfg(feature = "telemetry")]
+pub fn read_gps(path: &Path) -> io::Result<Vec<GpsFix>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let samples = match telemetry_samples(&mut reader)? {
+        Some(samples) => samples,
+        None => return Ok(vec![]),
+    };
+
+    let mut fixes = vec![];
+    for (offset, size) in samples {
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut sample = vec![0u8; size as usize];
+        reader.read_exact(&mut sample)?;
+        fixes.extend(parse_gps(&sample));
+    }
+
+    Ok(fixes)
+}
+
+/// Built without the telemetry feature there is no GPMF parser to read the GPS fixes with.
+#[cfg(not(feature = "telemetry"))]
+pub fn read_gps(_path: &Path) -> io::Result<Vec<GpsFix>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "built without the telemetry feature",
+    ))
+}
+
+/// Offsets and sizes of the samples of the `gpmd` track, `None` when there's no such track.
+fn telemetry_samples<R: Read + Seek>(r: &mut R) -> io::Result<Option<Vec<(u64, u64)>>> {
+    // the sample tables are untrusted, no sample can be larger than the file
+    let len = r.seek(SeekFrom::End(0))?;
+    let moov = match mp4::find_box(r, &[b"moov"])? {
+        Some(moov) => moov,
+        None => return Ok(None),
+    };
+
+    let traks = mp4::children(r, moov.payload_offset(), moov.end())?
+        .into_iter()
+        .filter(|header| &header.kind == b"trak");
+    for trak in traks {
+        let stbl = match mp4::find_box_in(
+            r,
+            trak.payload_offset(),
+            trak.end(),
+            &[b"mdia", b"minf", b"stbl"],
+        )? {
+            Some(stbl) => stbl,
+            None => continue,
+        };
+        let mut table = |kind: &BoxType| -> io::Result<Option<Vec<u8>>> {
+            mp4::find_box_in(r, stbl.payload_offset(), stbl.end(), &[kind])?
+                .map(|header| mp4::read_payload(r, &header))
+                .transpose()
+        };
+
+        if table(b"stsd")?.as_deref().and_then(sample_format) != Some(*GPMD) {
+            continue;
+        }
+        let sizes = table(b"stsz")?
+            .as_deref()
+            .and_then(|stsz| parse_stsz(stsz, len));
+        let chunks = match table(b"stco")? {
+            Some(stco) => parse_chunk_offsets(&stco, 4),
+            None => table(b"co64")?.and_then(|co64| parse_chunk_offsets(&co64, 8)),
+        };
+        let stsc = table(b"stsc")?.as_deref().and_then(parse_stsc);
+
+        return match (sizes, chunks, stsc) {
+            (Some(sizes), Some(chunks), Some(stsc)) => {
+                sample_locations(&chunks, &stsc, &sizes, len)
+            }
+            _ => None,
+        }
+        .map(Some)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid sample tables of the gpmd track",
+            )
+        });
+    }
+
+    Ok(None)
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn be_u64(data: &[u8], offset: usize) -> Option<u64> {
+    data.get(offset..offset + 8)
+        .map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// Format of the first sample entry of a `stsd` box.
+fn sample_format(stsd: &[u8]) -> Option<BoxType> {
+    stsd.get(12..16).map(|format| format.try_into().unwrap())
+}
+
+/// The sample sizes of a `stsz` box, `None` when its samples of the same size don't fit in the
+/// `len` of the file.
+fn parse_stsz(stsz: &[u8], len: u64) -> Option<Vec<u64>> {
+    let (size, count) = (be_u32(stsz, 4)?, be_u32(stsz, 8)? as usize);
+    if size != 0 {
+        if u64::from(size) * count as u64 > len {
+            return None;
+        }
+        return Some(vec![u64::from(size); count]);
+    }
+
+    (0..count)
+        .map(|index| be_u32(stsz, 12 + index * 4).map(u64::from))
+        .collect()
+}
+
+/// The chunk offsets of a `stco` or `co64` box, `width` being the size of an offset.
+fn parse_chunk_offsets(table: &[u8], width: usize) -> Option<Vec<u64>> {
+    let count = be_u32(table, 4)? as usize;
+    (0..count)
+        .map(|index| {
+            let offset = 8 + index * width;
+            match width {
+                4 => be_u32(table, offset).map(u64::from),
+                _ => be_u64(table, offset),
+            }
+        })
+        .collect()
+}
+
+/// The `(first chunk, samples per chunk)` runs of a `stsc` box.
+fn parse_stsc(stsc: &[u8]) -> Option<Vec<(u32, u32)>> {
+    let count = be_u32(stsc, 4)? as usize;
+    (0..count)
+        .map(|index| {
+            let offset = 8 + index * 12;
+            Some((be_u32(stsc, offset)?, be_u32(stsc, offset + 4)?))
+        })
+        .collect()
+}
+
+/// The `(offset, size)` of the samples, `None` when any of them ends past the `len` of the file.
+fn sample_locations(
+    chunks: &[u64],
+    stsc: &[(u32, u32)],
+    sizes: &[u64],
+    len: u64,
+) -> Option<Vec<(u64, u64)>> {
+    let mut sizes = sizes.iter();
+    let mut locations = vec![];
+    for (index, chunk) in chunks.iter().enumerate() {
+        // chunks are numbered from 1
+        let samples = stsc
+            .iter()
+            .take_while(|(first, _)| *first as usize <= index + 1)
+            .last()
+            .map_or(0, |(_, samples)| *samples);
+
+        let mut offset = *chunk;
+        for size in sizes.by_ref().take(samples as usize) {
+            locations.push((offset, *size));
+            offset = offset.checked_add(*size).filter(|end| *end <= len)?;
+        }
+    }
+
+    Some(locations)
+}
+
+/// A GPMF key-length-value entry, nested entries have the type 0.
+struct Klv<'a> {
+    key: [u8; 4],
+    kind: u8,
+    size: usize,
+    data: &'a [u8],
+}
+
+fn klvs(mut data: &[u8]) -> impl Iterator<Item = Klv<'_>> {
+    std::iter::from_fn(move || {
+        let header = data.get(..8)?;
+        let (size, repeat) = (
+            header[5] as usize,
+            u16::from_be_bytes([header[6], header[7]]) as usize,
+        );
+        let len = size * repeat;
+        let payload = data.get(8..8 + len)?;
+        let klv = Klv {
+            key: header[..4].try_into().unwrap(),
+            kind: header[4],
+            size,
+            data: payload,
+        };
+        // the payloads are padded to 32 bits
+        data = data.get(8 + (len + 3) / 4 * 4..).unwrap_or_default();
+        Some(klv)
+    })
+}
+
+impl Klv<'_> {
+    /// The numeric values of the entry, empty for types that aren't numbers.
+    fn values(&self) -> Vec<f64> {
+        let width = match self.kind {
+            b'b' | b'B' => 1,
+            b's' | b'S' => 2,
+            b'l' | b'L' | b'f' => 4,
+            b'd' => 8,
+            _ => return vec![],
+        };
+        self.data
+            .chunks_exact(width)
+            .map(|bytes| match self.kind {
+                b'b' => bytes[0] as i8 as f64,
+                b'B' => bytes[0] as f64,
+                b's' => i16::from_be_bytes(bytes.try_into().unwrap()) as f64,
+                b'S' => u16::from_be_bytes(bytes.try_into().unwrap()) as f64,
+                b'l' => i32::from_be_bytes(bytes.try_into().unwrap()) as f64,
+                b'L' => u32::from_be_bytes(bytes.try_into().unwrap()) as f64,
+                b'f' => f32::from_be_bytes(bytes.try_into().unwrap()) as f64,
+                _ => f64::from_be_bytes(bytes.try_into().unwrap()),
+            })
+            .collect()
+    }
+}
+
+/// The GPS fixes of a GPMF telemetry sample, leaving out the ones without a 2D or 3D lock.
+fn parse_gps(sample: &[u8]) -> Vec<GpsFix> {
+    let mut fixes = vec![];
+    for klv in klvs(sample).filter(|klv| klv.kind == 0) {
+        if &klv.key == STRM {
+            fixes.extend(parse_gps_stream(klv.data));
+        } else {
+            fixes.extend(parse_gps(klv.data));
+        }
+    }
+
+    fixes
+}
+
+fn parse_gps_stream(stream: &[u8]) -> Vec<GpsFix> {
+    let (mut scale, mut locked, mut rows, mut time) = (vec![], true, vec![], None);
+    for klv in klvs(stream) {
+        match &klv.key {
+            SCAL => scale = klv.values(),
+            GPSF => locked = klv.values().first().map_or(true, |fix| *fix >= 2.0),
+            GPSU => time = parse_gps_time(klv.data),
+            // latitude, longitude, altitude, 2D and 3D speed
+            GPS5 if klv.size == 20 => rows = klv.values(),
+            _ => {}
+        }
+    }
+    if !locked {
+        return vec![];
+    }
+
+    let scale = |index: usize| {
+        scale
+            .get(index)
+            .or_else(|| scale.first())
+            .copied()
+            .filter(|scale| *scale != 0.0)
+            .unwrap_or(1.0)
+    };
+    // the fixes are spread evenly over the sample, GPSU being the time of the first one
+    let spacing = GPS_SAMPLE_DURATION / (rows.len() as u32 / 5).max(1);
+    rows.chunks_exact(5)
+        .enumerate()
+        .map(|(index, row)| GpsFix {
+            latitude: row[0] / scale(0),
+            longitude: row[1] / scale(1),
+            altitude: row[2] / scale(2),
+            time: time.map(|time| time + spacing * index as u32),
+        })
+        .collect()
+}
+
+/// The UTC time of a GPSU entry, e.g. "220612093000.000" for 2022-06-12 09:30:00.
+fn parse_gps_time(data: &[u8]) -> Option<SystemTime> {
+    let time = std::str::from_utf8(data.get(..16)?).ok()?;
+    if !time.bytes().enumerate().all(|(index, c)| match index {
+        12 => c == b'.',
+        _ => c.is_ascii_digit(),
+    }) {
+        return None;
+    }
+    humantime::parse_rfc3339(&format!(
+        "20{}-{}-{}T{}:{}:{}Z",
+        &time[..2],
+        &time[2..4],
+        &time[4..6],
+        &time[6..8],
+        &time[8..10],
+        &time[10..]
+    ))
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn mp4_box(kind: &BoxType, payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn klv(key: &[u8; 4], kind: u8, size: u8, repeat: u16, data: &[u8]) -> Vec<u8> {
+        let mut klv = key.to_vec();
+        klv.extend([kind, size]);
+        klv.extend(repeat.to_be_bytes());
+        klv.extend_from_slice(data);
+        klv.resize(8 + (data.len() + 3) / 4 * 4, 0);
+        klv
+    }
+
+    fn gps_sample(fix: u32, rows: &[[i32; 5]]) -> Vec<u8> {
+        let scal = [10_000_000i32, 10_000_000, 1000, 1000, 100]
+            .iter()
+            .flat_map(|scale| scale.to_be_bytes())
+            .collect::<Vec<_>>();
+        let gps5 = rows
+            .iter()
+            .flatten()
+            .flat_map(|value| value.to_be_bytes())
+            .collect::<Vec<_>>();
+        let strm = [
+            klv(b"STNM", b'c', 3, 1, b"GPS"),
+            klv(GPSF, b'L', 4, 1, &fix.to_be_bytes()),
+            klv(GPSU, b'U', 16, 1, b"220612093000.000"),
+            klv(SCAL, b'l', 4, 5, &scal),
+            klv(GPS5, b'l', 20, rows.len() as u16, &gps5),
+        ]
+        .concat();
+        let strm = klv(STRM, 0, 1, strm.len() as u16, &strm);
+        klv(b"DEVC", 0, 1, strm.len() as u16, &strm)
+    }
+
+    #[test]
+    fn test_parse_gps() {
+        let sample = gps_sample(
+            3,
+            &[
+                [474_000_000, 85_000_000, 450_000, 0, 0],
+                [474_000_100, 85_000_200, 450_000, 0, 0],
+            ],
+        );
+        let time = humantime::parse_rfc3339("2022-06-12T09:30:00Z").unwrap();
+        assert_eq!(
+            vec![
+                GpsFix {
+                    latitude: 47.4,
+                    longitude: 8.5,
+                    altitude: 450.0,
+                    time: Some(time),
+                },
+                GpsFix {
+                    latitude: 47.40001,
+                    longitude: 8.50002,
+                    altitude: 450.0,
+                    time: Some(time + Duration::from_millis(500)),
+                },
+            ],
+            parse_gps(&sample)
+        );
+
+        // no lock
+        assert!(parse_gps(&gps_sample(0, &[[474_000_000, 85_000_000, 0, 0, 0]])).is_empty());
+        // truncated samples are read as far as they go
+        assert!(parse_gps(&sample[..20]).is_empty());
+        assert!(parse_gps(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_parse_gps_time() {
+        assert_eq!(
+            humantime::parse_rfc3339("2022-06-12T09:30:05.250Z").ok(),
+            parse_gps_time(b"220612093005.250")
+        );
+        assert_eq!(None, parse_gps_time(b"220612093005"));
+        assert_eq!(None, parse_gps_time(b"2206120930:5.250"));
+        assert_eq!(None, parse_gps_time(b"221312093005.250"));
+    }
+
+    #[test]
+    fn test_sample_locations() {
+        // two chunks of two samples and a last chunk of one
+        assert_eq!(
+            Some(vec![(100, 10), (110, 20), (500, 30), (530, 40), (900, 50)]),
+            sample_locations(
+                &[100, 500, 900],
+                &[(1, 2), (3, 1)],
+                &[10, 20, 30, 40, 50],
+                1000
+            )
+        );
+        assert_eq!(Some(vec![]), sample_locations(&[100], &[], &[10], 1000));
+        assert_eq!(None, sample_locations(&[100], &[(1, 1)], &[10], 105));
+        assert_eq!(
+            None,
+            sample_locations(&[u64::MAX - 5], &[(1, 1)], &[10], u64::MAX)
+        );
+    }
+
+    #[test]
+    fn test_parse_stsz() {
+        let stsz = |entries: &[u32]| {
+            std::iter::once(0u32)
+                .chain(entries.iter().copied())
+                .flat_map(|value| value.to_be_bytes())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(Some(vec![10, 20]), parse_stsz(&stsz(&[0, 2, 10, 20]), 1000));
+        assert_eq!(Some(vec![10; 3]), parse_stsz(&stsz(&[10, 3]), 1000));
+        assert_eq!(None, parse_stsz(&stsz(&[0, 3, 10, 20]), 1000));
+        // a huge count of samples of the same size isn't allocated
+        assert_eq!(None, parse_stsz(&stsz(&[1024, u32::MAX]), 1000));
+    }
+
+    #[test]
+    fn test_telemetry_samples() {
+        let sample = gps_sample(3, &[[474_000_000, 85_000_000, 0, 0, 0]]);
+        let mdat = mp4_box(b"mdat", &sample);
+        let ftyp = mp4_box(b"ftyp", b"mp41");
+        let sample_offset = (ftyp.len() + 8) as u32;
+
+        let table = |kind: &BoxType, entries: &[u32]| {
+            let payload = std::iter::once(0u32)
+                .chain(entries.iter().copied())
+                .flat_map(|value| value.to_be_bytes())
+                .collect::<Vec<_>>();
+            mp4_box(kind, &payload)
+        };
+        let stsd = [
+            0u32.to_be_bytes().to_vec(),
+            1u32.to_be_bytes().to_vec(),
+            mp4_box(GPMD, &[0; 8]),
+        ]
+        .concat();
+        let stbl = [
+            mp4_box(b"stsd", &stsd),
+            table(b"stsz", &[0, 1, sample.len() as u32]),
+            table(b"stsc", &[1, 1, 1, 1]),
+            table(b"stco", &[1, sample_offset]),
+        ]
+        .concat();
+        let trak = mp4_box(
+            b"trak",
+            &mp4_box(b"mdia", &mp4_box(b"minf", &mp4_box(b"stbl", &stbl))),
+        );
+        let video = mp4_box(b"trak", &mp4_box(b"tkhd", &[0; 4]));
+        let moov = mp4_box(b"moov", &[video, trak].concat());
+        let file = [ftyp, mdat, moov].concat();
+
+        let mut cursor = Cursor::new(file.clone());
+        let samples = telemetry_samples(&mut cursor).unwrap().unwrap();
+        assert_eq!(vec![(sample_offset as u64, sample.len() as u64)], samples);
+
+        let (offset, size) = (samples[0].0 as usize, samples[0].1 as usize);
+        assert_eq!(
+            vec![GpsFix {
+                latitude: 47.4,
+                longitude: 8.5,
+                altitude: 0.0,
+                time: humantime::parse_rfc3339("2022-06-12T09:30:00Z").ok(),
+            }],
+            parse_gps(&file[offset..offset + size])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "telemetry")]
+    fn test_read_gps_without_telemetry() {
+        assert!(read_gps(Path::new("tests/GH010084.mp4")).is_ok());
+        assert!(read_gps(Path::new("tests/missing.mp4")).is_err());
+    }
+}
@@ -0,0 +1,539 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use derive_more::Display;
+use log::*;
+use thiserror::Error;
+
+use crate::system::{Clock, Fs};
+
+// GoPro cameras didn't record video before 2005, anything earlier is an unset clock or a
+// filesystem defaulting to the FAT/exFAT epoch
+const EARLIEST_PLAUSIBLE_SECS: u64 = 1_104_537_600;
+// Cards written by cameras with a drifted clock can be slightly in the future
+const FUTURE_TOLERANCE: Duration = Duration::from_secs(24 * 60 * 60);
+// The furthest any zone is ahead of or behind UTC, e.g. the +14:00 of Kiribati
+const MAX_UTC_OFFSET_SECS: i32 = 14 * 60 * 60;
+// Daylight-saving changeovers are a year apart at least, a day before or after a time is in the
+// offset before or after the one around it
+const CHANGEOVER_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid --timezone {0}, expected \"local\", \"UTC\" or an offset from UTC like +02:00 or -0530")]
+    InvalidTimeZone(String),
+}
+
+/// The zone the clocks of the cameras were set to, from `--timezone`. GoPro cameras write their
+/// wall clock into the chapters as if it were UTC, and stamp the files with it which the machine
+/// reads in its own zone, so both are read back into instants in the zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeZone {
+    /// The zone of the machine, with its daylight-saving rules.
+    Local,
+    /// Seconds ahead of UTC all year, e.g. for a camera set to UTC or to the time at home while
+    /// travelling.
+    Fixed(i32),
+}
+
+impl Default for TimeZone {
+    fn default() -> Self {
+        TimeZone::Local
+    }
+}
+
+impl fmt::Display for TimeZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeZone::Local => write!(f, "local"),
+            TimeZone::Fixed(offset) => {
+                let sign = if *offset < 0 { '-' } else { '+' };
+                let minutes = offset.unsigned_abs() / 60;
+                write!(f, "{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+            }
+        }
+    }
+}
+
+impl FromStr for TimeZone {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidTimeZone(s.into());
+        match s.to_ascii_lowercase().as_str() {
+            "local" => return Ok(TimeZone::Local),
+            "utc" | "z" => return Ok(TimeZone::Fixed(0)),
+            _ => {}
+        }
+
+        let (sign, offset) = match (s.strip_prefix('+'), s.strip_prefix('-')) {
+            (Some(offset), _) => (1, offset),
+            (_, Some(offset)) => (-1, offset),
+            _ => return Err(invalid()),
+        };
+        // e.g. 02:00, 0530 or 2
+        let (hours, minutes) = match offset.split_once(':') {
+            Some(parts) => parts,
+            None if offset.len() == 4 && offset.is_ascii() => offset.split_at(2),
+            None => (offset, "0"),
+        };
+        let number = |part: &str| match part.parse::<i32>() {
+            Ok(number) if part.len() <= 2 && part.chars().all(|c| c.is_ascii_digit()) => Ok(number),
+            _ => Err(invalid()),
+        };
+        let (hours, minutes) = (number(hours)?, number(minutes)?);
+        let offset = hours * 60 * 60 + minutes * 60;
+        if minutes >= 60 || offset > MAX_UTC_OFFSET_SECS {
+            return Err(invalid());
+        }
+
+        Ok(TimeZone::Fixed(sign * offset))
+    }
+}
+
+impl TimeZone {
+    /// Seconds the clocks of the zone are ahead of UTC at `time`.
+    pub fn offset(&self, time: SystemTime, clock: &impl Clock) -> i32 {
+        match self {
+            TimeZone::Local => clock.utc_offset(time),
+            TimeZone::Fixed(offset) => *offset,
+        }
+    }
+
+    /// What the clocks of the zone showed at `time`, the way the cameras write it, as if it were
+    /// UTC.
+    pub fn wall_clock(&self, time: SystemTime, clock: &impl Clock) -> SystemTime {
+        shift(time, self.offset(time, clock))
+    }
+
+    /// The instant the clocks of the zone showed `wall_clock` at.
+    pub fn instant(&self, wall_clock: SystemTime, clock: &impl Clock) -> SystemTime {
+        shift(wall_clock, -self.offset_showing(wall_clock, clock))
+    }
+
+    /// The offset of the zone when its clocks showed `wall_clock`. The times showing twice as the
+    /// clocks go back are taken before the changeover, the ones skipped as they go forward in the
+    /// offset before it.
+    fn offset_showing(&self, wall_clock: SystemTime, clock: &impl Clock) -> i32 {
+        let before = self.offset(
+            wall_clock
+                .checked_sub(CHANGEOVER_WINDOW)
+                .unwrap_or(wall_clock),
+            clock,
+        );
+        let after = self.offset(wall_clock + CHANGEOVER_WINDOW, clock);
+        [before, after]
+            .iter()
+            .copied()
+            .find(|offset| self.offset(shift(wall_clock, -offset), clock) == *offset)
+            .unwrap_or(before)
+    }
+}
+
+fn shift(time: SystemTime, secs: i32) -> SystemTime {
+    let by = Duration::from_secs(u64::from(secs.unsigned_abs()));
+    if secs < 0 {
+        time.checked_sub(by).unwrap_or(UNIX_EPOCH)
+    } else {
+        time + by
+    }
+}
+
+/// Where the recording time of a chapter was taken from, most trustworthy first.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Display)]
+pub enum TimestampSource {
+    #[display(fmt = "container")]
+    Container,
+    #[display(fmt = "mtime")]
+    Modified,
+    #[display(fmt = "unknown")]
+    Unknown,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ChapterTimestamp {
+    pub time: Option<SystemTime>,
+    pub source: TimestampSource,
+}
+
+/// Resolves the recording time of a chapter preferring the container `creation_time` over the
+/// filesystem mtime, since exFAT cards often report epoch or future mtimes.
+pub fn resolve(
+    creation_time: Option<SystemTime>,
+    path: &Path,
+    fs: &impl Fs,
+    clock: &impl Clock,
+) -> ChapterTimestamp {
+    let modified = fs
+        .modified(path)
+        .map_err(|err| debug!("reading mtime of {}: {}", path.display(), err))
+        .ok();
+
+    let timestamp = resolve_with(creation_time, modified, clock.now());
+    debug!(
+        "resolved timestamp of {} to {:?} from {}",
+        path.display(),
+        timestamp.time.map(humantime::format_rfc3339),
+        timestamp.source
+    );
+    timestamp
+}
+
+/// What the clock of the camera showed when the chapter at `path` was recorded, from its file time
+/// read in the zone of the machine, for the names of movies not probed yet. Whatever the zone of
+/// the camera, it's the wall clock the recording times of its chapters are read from.
+pub fn wall_clock(path: &Path, fs: &impl Fs, clock: &impl Clock) -> Option<SystemTime> {
+    resolve(None, path, fs, clock)
+        .time
+        .map(|time| TimeZone::Local.wall_clock(time, clock))
+}
+
+fn resolve_with(
+    creation_time: Option<SystemTime>,
+    modified: Option<SystemTime>,
+    now: SystemTime,
+) -> ChapterTimestamp {
+    [
+        (creation_time, TimestampSource::Container),
+        (modified, TimestampSource::Modified),
+    ]
+    .into_iter()
+    .find_map(|(time, source)| match time {
+        Some(time) if is_plausible(time, now) => Some(ChapterTimestamp {
+            time: Some(time),
+            source,
+        }),
+        _ => None,
+    })
+    .unwrap_or(ChapterTimestamp {
+        time: None,
+        source: TimestampSource::Unknown,
+    })
+}
+
+fn is_plausible(time: SystemTime, now: SystemTime) -> bool {
+    time >= UNIX_EPOCH + Duration::from_secs(EARLIEST_PLAUSIBLE_SECS)
+        && time <= now + FUTURE_TOLERANCE
+}
+
+/// Turns the recording times of the chapters of a single recording, as [resolve]d, into instants
+/// read in the `zone`. Cameras don't change their clocks in the middle of a recording, so all of
+/// them are read in the offset of the zone at the first chapter: a recording spanning a
+/// daylight-saving changeover keeps its chapters back to back instead of an hour apart, however
+/// each of their times was resolved.
+pub fn align(timestamps: &mut [ChapterTimestamp], zone: TimeZone, clock: &impl Clock) {
+    // what the camera showed, the machine reads the times of the files in its own zone
+    let wall_clocks = timestamps
+        .iter()
+        .map(|timestamp| match timestamp.source {
+            TimestampSource::Container => timestamp.time,
+            _ => timestamp
+                .time
+                .map(|time| TimeZone::Local.wall_clock(time, clock)),
+        })
+        .collect::<Vec<_>>();
+    let offset = match wall_clocks.iter().flatten().next() {
+        Some(first) => zone.offset_showing(*first, clock),
+        None => return,
+    };
+    debug!("reading the recording times in {} at {}s", zone, offset);
+
+    for (timestamp, wall_clock) in timestamps.iter_mut().zip(wall_clocks) {
+        timestamp.time = wall_clock.map(|wall_clock| shift(wall_clock, -offset));
+    }
+}
+
+/// Stamps the merged movie at `output` with the recording time of its first chapter, merged
+/// movies sorting as "now" in media libraries otherwise. Failing to is only worth a warning.
+pub fn preserve(output: &Path, recorded: Option<SystemTime>, fs: &impl Fs) {
+    let recorded = match recorded {
+        Some(recorded) => recorded,
+        None => {
+            debug!(
+                "leaving the times of {} alone, its recording time is unknown",
+                output.display()
+            );
+            return;
+        }
+    };
+
+    match fs.set_times(output, recorded) {
+        Ok(()) => debug!(
+            "set the times of {} to {}",
+            output.display(),
+            humantime::format_rfc3339(recorded)
+        ),
+        Err(err) => warn!(
+            "setting the times of {} to its recording time: {}",
+            output.display(),
+            err
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::system::mock::{MemoryFs, MockClock};
+
+    fn time(rfc3339: &str) -> SystemTime {
+        humantime::parse_rfc3339(rfc3339).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_with() {
+        let now = time("2021-11-15T00:00:00Z");
+        let container = time("2021-11-14T23:15:25Z");
+        let modified = time("2021-11-14T23:20:00Z");
+
+        let tests = vec![
+            (
+                Some(container),
+                Some(modified),
+                Some(container),
+                TimestampSource::Container,
+            ),
+            (
+                None,
+                Some(modified),
+                Some(modified),
+                TimestampSource::Modified,
+            ),
+            // unset camera clock
+            (
+                Some(time("1970-01-01T00:00:00Z")),
+                Some(modified),
+                Some(modified),
+                TimestampSource::Modified,
+            ),
+            // exFAT epoch mtime
+            (
+                Some(container),
+                Some(time("1980-01-01T00:00:00Z")),
+                Some(container),
+                TimestampSource::Container,
+            ),
+            (
+                None,
+                Some(time("2030-01-01T00:00:00Z")),
+                None,
+                TimestampSource::Unknown,
+            ),
+            (None, None, None, TimestampSource::Unknown),
+        ];
+
+        tests.into_iter().for_each(
+            |(creation_time, modified, expected_time, expected_source)| {
+                assert_eq!(
+                    ChapterTimestamp {
+                        time: expected_time,
+                        source: expected_source,
+                    },
+                    resolve_with(creation_time, modified, now)
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let fs = MemoryFs::default();
+        let clock = MockClock::new(time("2021-11-15T00:00:00Z"));
+        let path = Path::new("GH010084.mp4");
+        let modified = time("2021-11-14T23:20:00Z");
+        fs.set_modified(path, modified);
+
+        assert_eq!(
+            ChapterTimestamp {
+                time: Some(modified),
+                source: TimestampSource::Modified,
+            },
+            resolve(None, path, &fs, &clock)
+        );
+
+        // the camera clock was a bit ahead when the card was written
+        clock.advance(Duration::from_secs(30 * 24 * 60 * 60));
+        fs.set_modified(path, time("2021-12-15T01:00:00Z"));
+        assert_eq!(
+            TimestampSource::Modified,
+            resolve(None, path, &fs, &clock).source
+        );
+
+        fs.fail(path);
+        assert_eq!(
+            TimestampSource::Unknown,
+            resolve(None, path, &fs, &clock).source
+        );
+    }
+
+    /// A machine in Berlin in 2021, summer time ending on October 31 and starting on March 28.
+    fn berlin() -> MockClock {
+        let clock = MockClock::new(time("2021-11-15T00:00:00Z"));
+        clock.set_utc_offset(time("2020-10-25T01:00:00Z"), 3600);
+        clock.set_utc_offset(time("2021-03-28T01:00:00Z"), 7200);
+        clock.set_utc_offset(time("2021-10-31T01:00:00Z"), 3600);
+        clock
+    }
+
+    #[test]
+    fn test_time_zone_from_str() {
+        let tests = vec![
+            ("local", TimeZone::Local),
+            ("UTC", TimeZone::Fixed(0)),
+            ("Z", TimeZone::Fixed(0)),
+            ("+02:00", TimeZone::Fixed(7200)),
+            ("-0530", TimeZone::Fixed(-19800)),
+            ("+2", TimeZone::Fixed(7200)),
+            ("+14:00", TimeZone::Fixed(50400)),
+        ];
+        tests.into_iter().for_each(|(s, expected)| {
+            assert_eq!(expected, s.parse::<TimeZone>().unwrap(), "{}", s);
+        });
+
+        for s in &[
+            "",
+            "+",
+            "02:00",
+            "+15:00",
+            "+02:60",
+            "+2:3:4",
+            "+a€",
+            "Europe/Berlin",
+        ] {
+            assert!(s.parse::<TimeZone>().is_err(), "{}", s);
+        }
+
+        assert_eq!("-05:30", TimeZone::Fixed(-19800).to_string());
+        assert_eq!("+00:00", TimeZone::Fixed(0).to_string());
+        assert_eq!("local", TimeZone::Local.to_string());
+    }
+
+    #[test]
+    fn test_time_zone_instant() {
+        let clock = berlin();
+        let local = TimeZone::Local;
+
+        let tests = vec![
+            ("2021-07-01T12:00:00Z", "2021-07-01T10:00:00Z"),
+            ("2021-12-01T12:00:00Z", "2021-12-01T11:00:00Z"),
+            // shown twice as the clocks went back, taken before the changeover
+            ("2021-10-31T02:30:00Z", "2021-10-31T00:30:00Z"),
+            ("2021-10-31T03:00:00Z", "2021-10-31T02:00:00Z"),
+            // skipped as the clocks went forward, taken before the changeover
+            ("2021-03-28T02:30:00Z", "2021-03-28T01:30:00Z"),
+            ("2021-03-28T03:00:00Z", "2021-03-28T01:00:00Z"),
+        ];
+        tests.into_iter().for_each(|(wall_clock, expected)| {
+            assert_eq!(
+                time(expected),
+                local.instant(time(wall_clock), &clock),
+                "{}",
+                wall_clock
+            );
+        });
+
+        let instant = time("2021-10-31T00:30:00Z");
+        assert_eq!(
+            time("2021-10-31T02:30:00Z"),
+            local.wall_clock(instant, &clock)
+        );
+        assert_eq!(
+            time("2021-10-31T02:30:00Z"),
+            local.wall_clock(instant + Duration::from_secs(3600), &clock)
+        );
+
+        // fixed offsets ignore the rules of the machine
+        let fixed = TimeZone::Fixed(-5 * 3600);
+        assert_eq!(
+            time("2021-10-31T07:30:00Z"),
+            fixed.instant(time("2021-10-31T02:30:00Z"), &clock)
+        );
+    }
+
+    #[test]
+    fn test_align_across_changeovers() {
+        let clock = berlin();
+        let chapter = |source, rfc3339| ChapterTimestamp {
+            time: Some(time(rfc3339)),
+            source,
+        };
+
+        // a ride from 02:40 to 03:30 in summer time, the camera keeping its clock while the
+        // machine goes back an hour at 03:00: the first chapters are read from the containers,
+        // the last one only from its file time which the machine read in winter time
+        let mut timestamps = vec![
+            chapter(TimestampSource::Container, "2021-10-31T02:40:00Z"),
+            chapter(TimestampSource::Container, "2021-10-31T03:00:00Z"),
+            chapter(TimestampSource::Modified, "2021-10-31T02:20:00Z"),
+            ChapterTimestamp {
+                time: None,
+                source: TimestampSource::Unknown,
+            },
+        ];
+        align(&mut timestamps, TimeZone::Local, &clock);
+        assert_eq!(
+            vec![
+                Some(time("2021-10-31T00:40:00Z")),
+                Some(time("2021-10-31T01:00:00Z")),
+                Some(time("2021-10-31T01:20:00Z")),
+                None,
+            ],
+            timestamps
+                .iter()
+                .map(|timestamp| timestamp.time)
+                .collect::<Vec<_>>()
+        );
+        // the sources are kept
+        assert_eq!(TimestampSource::Modified, timestamps[2].source);
+
+        // from 01:50 in winter time, across the hour skipped at 02:00
+        let mut timestamps = vec![
+            chapter(TimestampSource::Container, "2021-03-28T01:50:00Z"),
+            chapter(TimestampSource::Container, "2021-03-28T02:10:00Z"),
+            chapter(TimestampSource::Container, "2021-03-28T02:30:00Z"),
+        ];
+        align(&mut timestamps, TimeZone::Local, &clock);
+        assert_eq!(
+            vec![
+                Some(time("2021-03-28T00:50:00Z")),
+                Some(time("2021-03-28T01:10:00Z")),
+                Some(time("2021-03-28T01:30:00Z")),
+            ],
+            timestamps
+                .iter()
+                .map(|timestamp| timestamp.time)
+                .collect::<Vec<_>>()
+        );
+
+        // a camera left on UTC, the file times read in the zone of the machine
+        let mut timestamps = vec![
+            chapter(TimestampSource::Container, "2021-07-01T10:00:00Z"),
+            chapter(TimestampSource::Modified, "2021-07-01T08:20:00Z"),
+        ];
+        align(&mut timestamps, TimeZone::Fixed(0), &clock);
+        assert_eq!(Some(time("2021-07-01T10:00:00Z")), timestamps[0].time);
+        assert_eq!(Some(time("2021-07-01T10:20:00Z")), timestamps[1].time);
+    }
+
+    #[test]
+    fn test_preserve() {
+        let fs = MemoryFs::default();
+        let output = Path::new("/movies/GH000084.mp4");
+        let merged = time("2022-01-01T00:00:00Z");
+        let recorded = time("2021-11-14T23:15:25Z");
+        fs.set_modified(output, merged);
+
+        preserve(output, None, &fs);
+        assert_eq!(merged, fs.modified(output).unwrap());
+
+        preserve(output, Some(recorded), &fs);
+        assert_eq!(recorded, fs.modified(output).unwrap());
+
+        // doesn't fail the merge
+        fs.fail(output);
+        preserve(output, Some(merged), &fs);
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::group::{MovieGroup, MovieGroups};
+use crate::system::{Clock, Fs};
+
+/// How often --watch scans the inputs for new chapters.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The chapters of a group with their sizes, as last seen.
+type Snapshot = Vec<(PathBuf, u64)>;
+
+/// Tells the groups still being copied into the watched inputs from the settled ones, whose
+/// chapters have stopped changing for a while.
+#[derive(Debug)]
+pub struct Watcher<F: Fs, C: Clock> {
+    fs: F,
+    clock: C,
+    settle: Duration,
+    /// The groups waiting to settle, with since when they're unchanged.
+    pending: HashMap<String, (Snapshot, Instant)>,
+    /// The groups handed out for merging, with their chapters at the time.
+    merged: HashMap<String, Snapshot>,
+}
+
+impl<F: Fs, C: Clock> Watcher<F, C> {
+    pub fn new(fs: F, clock: C, settle: Duration) -> Self {
+        Watcher {
+            fs,
+            clock,
+            settle,
+            pending: Default::default(),
+            merged: Default::default(),
+        }
+    }
+
+    /// The `groups` scanned from `input` whose chapters, and their sizes, haven't changed for the
+    /// settle duration. Each group is handed out once, and again only when its chapters change.
+    pub fn settled(&mut self, groups: MovieGroups, input: &Path) -> MovieGroups {
+        let now = self.clock.instant();
+        let mut settled = vec![];
+        for group in groups {
+            let name = group.name();
+            let snapshot = match self.snapshot(&group, input) {
+                Ok(snapshot) => snapshot,
+                Err(err) => {
+                    // e.g. a chapter removed or renamed into place while scanning
+                    debug!("waiting for {} to settle, {}", group, err);
+                    self.pending.remove(&name);
+                    continue;
+                }
+            };
+            if self.merged.get(&name) == Some(&snapshot) {
+                continue;
+            }
+
+            let since = match self.pending.get(&name) {
+                Some((pending, since)) if *pending == snapshot => *since,
+                _ => now,
+            };
+            if now.duration_since(since) < self.settle {
+                self.pending.insert(name, (snapshot, since));
+                continue;
+            }
+            info!("{} settled, merging it", group);
+            self.pending.remove(&name);
+            self.merged.insert(name, snapshot);
+            settled.push(group);
+        }
+
+        settled
+    }
+
+    fn snapshot(&self, group: &MovieGroup, input: &Path) -> io::Result<Snapshot> {
+        group
+            .chapters
+            .iter()
+            .map(|chapter| {
+                let path = group.chapter_path(chapter, input);
+                let len = self.fs.len(&path)?;
+                Ok((path, len))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::SystemTime;
+
+    use crate::group::group_file_names;
+    use crate::system::mock::{MemoryFs, MockClock};
+
+    #[test]
+    fn test_settled() {
+        let fs = MemoryFs::default();
+        let clock = MockClock::new(SystemTime::now());
+        let input = Path::new("/movies");
+        let mut watcher = Watcher::new(fs.clone(), clock.clone(), Duration::from_secs(30));
+        let settled = |watcher: &mut Watcher<_, _>, names: Vec<&str>| {
+            watcher
+                .settled(group_file_names(names), input)
+                .iter()
+                .map(|group| group.name())
+                .collect::<Vec<_>>()
+        };
+
+        fs.write(&input.join("GH010084.mp4"), b"chapter").unwrap();
+        fs.write(&input.join("GH010085.mp4"), b"chapter").unwrap();
+        assert!(settled(&mut watcher, vec!["GH010084.mp4", "GH010085.mp4"]).is_empty());
+
+        // the second group is still growing
+        clock.advance(Duration::from_secs(20));
+        fs.write(&input.join("GH010085.mp4"), b"chapter 1").unwrap();
+        assert!(settled(&mut watcher, vec!["GH010084.mp4", "GH010085.mp4"]).is_empty());
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(
+            vec!["GH000084.mp4"],
+            settled(&mut watcher, vec!["GH010084.mp4", "GH010085.mp4"])
+        );
+
+        // a chapter missing while scanning restarts the wait
+        clock.advance(Duration::from_secs(20));
+        fs.remove_file(&input.join("GH010085.mp4")).unwrap();
+        assert!(settled(&mut watcher, vec!["GH010084.mp4", "GH010085.mp4"]).is_empty());
+        clock.advance(Duration::from_secs(10));
+        fs.write(&input.join("GH010085.mp4"), b"chapter 1").unwrap();
+        assert!(settled(&mut watcher, vec!["GH010084.mp4", "GH010085.mp4"]).is_empty());
+        clock.advance(Duration::from_secs(20));
+        assert!(settled(&mut watcher, vec!["GH010084.mp4", "GH010085.mp4"]).is_empty());
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(
+            vec!["GH000085.mp4"],
+            settled(&mut watcher, vec!["GH010084.mp4", "GH010085.mp4"])
+        );
+
+        // merged groups come back only with new chapters
+        fs.write(&input.join("GH020084.mp4"), b"chapter").unwrap();
+        assert!(settled(&mut watcher, vec!["GH010084.mp4", "GH020084.mp4"]).is_empty());
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(
+            vec!["GH000084.mp4"],
+            settled(&mut watcher, vec!["GH010084.mp4", "GH020084.mp4"])
+        );
+
+        let mut watcher = Watcher::new(fs, clock, Duration::default());
+        assert_eq!(
+            vec!["GH000084.mp4"],
+            settled(&mut watcher, vec!["GH010084.mp4"])
+        );
+    }
+}
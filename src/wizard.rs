@@ -0,0 +1,236 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use log::*;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Setup cancelled, pass the input and output directories to skip it")]
+    Cancelled,
+
+    #[error(transparent)]
+    IO(#[from] io::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The folder cameras record into at the root of their memory cards.
+const CARD_FOLDER: &str = "DCIM";
+
+/// Where to merge from and to, as picked by the first run setup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setup {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Set for memory cards, whose chapters are spread across DCIM/100GOPRO, DCIM/101GOPRO.
+    pub recursive: bool,
+}
+
+/// The mounted memory cards, the directories holding a DCIM folder among the usual mount points.
+pub fn card_mounts() -> Vec<PathBuf> {
+    card_mounts_in(&mount_points())
+}
+
+fn card_mounts_in(mount_points: &[PathBuf]) -> Vec<PathBuf> {
+    let mut cards = mount_points
+        .iter()
+        .filter(|mount| mount.join(CARD_FOLDER).is_dir())
+        .cloned()
+        .collect::<Vec<_>>();
+    cards.sort();
+    cards.dedup();
+    cards
+}
+
+#[cfg(windows)]
+fn mount_points() -> Vec<PathBuf> {
+    // A: to C: being floppies and the system drive
+    (b'D'..=b'Z')
+        .map(|letter| PathBuf::from(format!("{}:\\", letter as char)))
+        .collect()
+}
+
+#[cfg(not(windows))]
+fn mount_points() -> Vec<PathBuf> {
+    let mut roots = vec![PathBuf::from("/Volumes"), PathBuf::from("/mnt")];
+    if let Ok(user) = std::env::var("USER") {
+        roots.push(Path::new("/media").join(&user));
+        roots.push(Path::new("/run/media").join(&user));
+    }
+    roots.push(PathBuf::from("/media"));
+
+    roots
+        .iter()
+        .filter_map(|root| fs::read_dir(root).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect()
+}
+
+/// Asks on the terminal where the movies are, offering the `cards`, and where to merge them to,
+/// suggesting the `wd`. The output directory is created when missing.
+pub fn run(cards: &[PathBuf], wd: &Path) -> Result<Setup> {
+    let setup = run_with(cards, wd, io::stdin().lock(), io::stderr())?;
+    fs::create_dir_all(&setup.output)?;
+    info!(
+        "set up merging {} into {}",
+        setup.input.display(),
+        setup.output.display()
+    );
+
+    Ok(setup)
+}
+
+fn run_with(
+    cards: &[PathBuf],
+    wd: &Path,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> Result<Setup> {
+    writeln!(
+        output,
+        "No directories were given, let's set up the merge. Pass them as arguments to skip this."
+    )?;
+    writeln!(output, "Where are the movies?")?;
+    let choices = cards
+        .iter()
+        .map(|card| (card.clone(), "memory card"))
+        .chain(Some((wd.to_path_buf(), "current directory")))
+        .collect::<Vec<_>>();
+    for (index, (path, kind)) in choices.iter().enumerate() {
+        writeln!(output, "  {}) {} ({})", index + 1, path.display(), kind)?;
+    }
+
+    let answer = ask(&mut input, &mut output, "Pick one or type a path [1]: ")?;
+    let source = match answer.parse::<usize>() {
+        Ok(choice) if (1..=choices.len()).contains(&choice) => choices[choice - 1].0.clone(),
+        _ if answer.is_empty() => choices[0].0.clone(),
+        _ => wd.join(answer),
+    };
+    let card = source.join(CARD_FOLDER);
+    let (source, recursive) = if card.is_dir() {
+        (card, true)
+    } else {
+        (source, false)
+    };
+
+    // the current directory is suggested over the card, which merging onto would fill up
+    let answer = ask(
+        &mut input,
+        &mut output,
+        &format!("Where should the merged movies go? [{}]: ", wd.display()),
+    )?;
+    let destination = if answer.is_empty() {
+        wd.to_path_buf()
+    } else {
+        wd.join(answer)
+    };
+
+    let answer = ask(
+        &mut input,
+        &mut output,
+        &format!(
+            "Merge the movies of {} into {}? [Y/n] ",
+            source.display(),
+            destination.display()
+        ),
+    )?;
+    if matches!(answer.to_ascii_lowercase().as_str(), "n" | "no") {
+        return Err(Error::Cancelled);
+    }
+
+    Ok(Setup {
+        input: source,
+        output: destination,
+        recursive,
+    })
+}
+
+fn ask(input: &mut impl BufRead, output: &mut impl Write, question: &str) -> Result<String> {
+    write!(output, "{}", question)?;
+    output.flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::slice;
+
+    fn setup(cards: &[PathBuf], wd: &Path, answers: &str) -> (Result<Setup>, String) {
+        let mut prompt = vec![];
+        let result = run_with(cards, wd, answers.as_bytes(), &mut prompt);
+        (result, String::from_utf8(prompt).unwrap())
+    }
+
+    #[test]
+    fn test_card_mounts_in() {
+        let mounts = env::temp_dir().join("goprotest_card_mounts_in");
+        let _ = fs::remove_dir_all(&mounts);
+        fs::create_dir_all(mounts.join("GOPRO").join("DCIM").join("100GOPRO")).unwrap();
+        fs::create_dir_all(mounts.join("BACKUP")).unwrap();
+        fs::create_dir_all(mounts.join("FILE").join("DCIM.txt")).unwrap();
+
+        assert_eq!(
+            vec![mounts.join("GOPRO")],
+            card_mounts_in(&[
+                mounts.join("GOPRO"),
+                mounts.join("BACKUP"),
+                mounts.join("FILE"),
+                mounts.join("MISSING"),
+                mounts.join("GOPRO"),
+            ])
+        );
+
+        fs::remove_dir_all(&mounts).unwrap();
+    }
+
+    #[test]
+    fn test_run_with() {
+        let wd = env::temp_dir().join("goprotest_wizard_run_with");
+        let _ = fs::remove_dir_all(&wd);
+        let card = wd.join("GOPRO");
+        fs::create_dir_all(card.join("DCIM").join("100GOPRO")).unwrap();
+
+        let (result, prompt) = setup(slice::from_ref(&card), &wd, "\n\n\n");
+        assert_eq!(
+            Setup {
+                input: card.join("DCIM"),
+                output: wd.clone(),
+                recursive: true,
+            },
+            result.unwrap()
+        );
+        assert!(prompt.contains(&format!("  1) {} (memory card)\n", card.display())));
+        assert!(prompt.contains(&format!("  2) {} (current directory)\n", wd.display())));
+
+        let (result, _) = setup(slice::from_ref(&card), &wd, "2\nmerged\ny\n");
+        assert_eq!(
+            Setup {
+                input: wd.clone(),
+                output: wd.join("merged"),
+                recursive: false,
+            },
+            result.unwrap()
+        );
+
+        // a number out of range is a directory name
+        let (result, _) = setup(&[], &wd, "2\n\n\n");
+        assert_eq!(wd.join("2"), result.unwrap().input);
+
+        assert!(matches!(
+            setup(&[card], &wd, "1\n\nn\n").0,
+            Err(Error::Cancelled)
+        ));
+
+        fs::remove_dir_all(&wd).unwrap();
+    }
+}
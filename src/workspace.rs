@@ -0,0 +1,177 @@
+use std::env;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::*;
+
+use crate::run::RunId;
+use crate::system::{Fs, RealFs};
+
+/// Per run directory for intermediate files, removed together with its contents when dropped
+/// unless kept.
+#[derive(Debug)]
+pub struct Workspace<F: Fs = RealFs> {
+    path: PathBuf,
+    fs: F,
+    keep: bool,
+}
+
+impl Workspace {
+    pub fn create(run_id: &RunId) -> io::Result<Self> {
+        Self::create_in(RealFs, &env::temp_dir(), run_id)
+    }
+
+    /// Where [Workspace::create] creates the workspace of the run, e.g. to mount it into the
+    /// container of `--ffmpeg-via` beforehand.
+    pub fn path_of(run_id: &RunId) -> PathBuf {
+        Self::path_in(&env::temp_dir(), run_id)
+    }
+
+    /// The workspace shown by plans, without creating it. Not the one of the run, so that plans
+    /// of the same chapters are the same whatever run planned them.
+    pub fn plan_path() -> PathBuf {
+        Self::path_in(&env::temp_dir(), "plan")
+    }
+
+    fn path_in(parent: &Path, name: impl fmt::Display) -> PathBuf {
+        parent.join(format!(".gopro-merge-{}", name))
+    }
+}
+
+impl<F: Fs> Workspace<F> {
+    pub fn create_in(fs: F, parent: &Path, run_id: &RunId) -> io::Result<Self> {
+        let path = Workspace::path_in(parent, run_id);
+        info!("creating workspace {}", path.display());
+        fs.create_dir_all(&path)?;
+        Ok(Workspace {
+            path,
+            fs,
+            keep: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leaves the workspace with its contents behind for debugging, from `--keep-temp`.
+    pub fn keep(&mut self) {
+        self.keep = true;
+    }
+
+    /// Ends a run whose merges all went through, each of which removes its own intermediate files.
+    /// Whatever is still in the workspace leaked, e.g. from a new feature forgetting to clean
+    /// up, and is warned about before removing the workspace. Returns the leaked paths, none when
+    /// kept.
+    pub fn finish(self) -> Vec<PathBuf> {
+        if self.keep {
+            return vec![];
+        }
+
+        let leaked = match self.fs.read_dir(&self.path) {
+            Ok(leaked) => leaked,
+            Err(err) => {
+                warn!("auditing workspace {}: {}", self.path.display(), err);
+                vec![]
+            }
+        };
+        for path in &leaked {
+            warn!("{} leaked into the workspace of the run", path.display());
+        }
+
+        let (path, fs) = (self.path.clone(), self.fs.clone());
+        drop(self);
+        if fs.read_dir(&path).is_ok() {
+            warn!("workspace {} is left behind", path.display());
+        }
+
+        leaked
+    }
+}
+
+impl<F: Fs> Drop for Workspace<F> {
+    fn drop(&mut self) {
+        if self.keep {
+            info!("keeping workspace {}", self.path.display());
+            return;
+        }
+
+        debug!("removing workspace {}", self.path.display());
+        if let Err(err) = self.fs.remove_dir_all(&self.path) {
+            warn!("removing workspace {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::system::mock::MemoryFs;
+
+    #[test]
+    fn test_workspace_lifecycle() {
+        let run_id = RunId::generate();
+        let workspace = Workspace::create(&run_id).unwrap();
+        let path = workspace.path().to_path_buf();
+
+        assert!(path.is_dir());
+        assert!(path
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .contains(run_id.as_str()));
+
+        fs::write(path.join("file.txt"), "contents").unwrap();
+        drop(workspace);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_workspace_failures() {
+        let fs = MemoryFs::default();
+        let run_id = RunId::generate();
+        let parent = Path::new("/readonly");
+        let path = parent.join(format!(".gopro-merge-{}", run_id));
+
+        fs.fail(&path);
+        assert!(Workspace::create_in(fs.clone(), parent, &run_id).is_err());
+
+        let fs = MemoryFs::default();
+        let workspace = Workspace::create_in(fs.clone(), parent, &run_id).unwrap();
+        fs.write(&path.join("list.txt"), b"file 'a'").unwrap();
+        assert!(fs.exists(&path));
+
+        // failing to clean up only warns
+        fs.fail(&path);
+        drop(workspace);
+        assert!(fs.exists(&path));
+    }
+
+    #[test]
+    fn test_workspace_finish() {
+        let fs = MemoryFs::default();
+        let run_id = RunId::generate();
+        let parent = Path::new("/tmp");
+        let path = parent.join(format!(".gopro-merge-{}", run_id));
+
+        let workspace = Workspace::create_in(fs.clone(), parent, &run_id).unwrap();
+        assert!(workspace.finish().is_empty());
+        assert!(!fs.exists(&path));
+
+        let workspace = Workspace::create_in(fs.clone(), parent, &run_id).unwrap();
+        fs.write(&path.join(".0084.txt"), b"file 'a'").unwrap();
+        assert_eq!(vec![path.join(".0084.txt")], workspace.finish());
+        assert!(!fs.exists(&path));
+
+        let mut workspace = Workspace::create_in(fs.clone(), parent, &run_id).unwrap();
+        fs.write(&path.join(".0084.srt"), b"1").unwrap();
+        workspace.keep();
+        assert!(workspace.finish().is_empty());
+        assert!(fs.exists(&path.join(".0084.srt")));
+    }
+}